@@ -0,0 +1,22 @@
+use std::sync::Arc;
+
+use slog::debug;
+
+use replicante_agent::actions::ACTIONS;
+use replicante_agent::AgentContext;
+
+use super::agent::KafkaZoo;
+
+mod topics;
+
+pub use self::topics::TopicAlterPartitions;
+pub use self::topics::TopicCreate;
+pub use self::topics::TopicDelete;
+
+/// Register Kafka specific actions.
+pub fn register(zoo: Arc<KafkaZoo>, context: &AgentContext) {
+    debug!(context.logger, "Registering Kafka topic actions");
+    ACTIONS::register(TopicCreate::new(Arc::clone(&zoo), context.clone()));
+    ACTIONS::register(TopicDelete::new(Arc::clone(&zoo), context.clone()));
+    ACTIONS::register(TopicAlterPartitions::new(zoo, context.clone()));
+}
@@ -0,0 +1,422 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use opentracingrust::Span;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::actions::utils::validate_action_args;
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionDescriptor;
+use replicante_agent::actions::ActionRecordView;
+use replicante_agent::actions::ActionState;
+use replicante_agent::actions::ActionValidity;
+use replicante_agent::actions::ActionValidityError;
+use replicante_agent::spans::child_span;
+use replicante_agent::spans::ChildSpan;
+use replicante_agent::AgentContext;
+use replicante_agent::ErrorKind as BaseKind;
+use replicante_agent::Result;
+use replicante_agent::ResultExt;
+use replicante_agent::Transaction;
+
+use crate::agent::KafkaZoo;
+use crate::agent::TopicAssignment;
+use crate::error::ErrorKind;
+
+/// Longest topic name accepted by Kafka.
+const MAX_TOPIC_NAME: usize = 249;
+
+/// Arguments of the topic create action.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct CreateArgs {
+    /// Topic configuration overrides.
+    #[serde(default)]
+    config: BTreeMap<String, String>,
+
+    /// Report the changes that would be made without applying them.
+    #[serde(default)]
+    dry_run: bool,
+
+    /// Number of partitions of the topic.
+    partitions: u32,
+
+    /// Number of replicas for each partition.
+    replication_factor: u16,
+
+    /// Name of the topic to create.
+    topic: String,
+}
+
+impl CreateArgs {
+    fn validate(&self) -> ActionValidity {
+        validate_topic(&self.topic)?;
+        validate_partitions(self.partitions)?;
+        if self.replication_factor == 0 {
+            let error = "replication_factor must be at least 1".to_string();
+            return Err(ActionValidityError::InvalidArgs(error));
+        }
+        Ok(())
+    }
+}
+
+/// Arguments of the topic delete action.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct DeleteArgs {
+    /// Report the changes that would be made without applying them.
+    #[serde(default)]
+    dry_run: bool,
+
+    /// Name of the topic to delete.
+    topic: String,
+}
+
+/// Arguments of the topic partitions alter action.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct AlterPartitionsArgs {
+    /// Report the changes that would be made without applying them.
+    #[serde(default)]
+    dry_run: bool,
+
+    /// New total number of partitions of the topic.
+    partitions: u32,
+
+    /// Name of the topic to add partitions to.
+    topic: String,
+}
+
+/// Create a topic, assigning partition replicas to the brokers in the cluster.
+pub struct TopicCreate {
+    context: AgentContext,
+    zoo: Arc<KafkaZoo>,
+}
+
+impl TopicCreate {
+    pub fn new(zoo: Arc<KafkaZoo>, context: AgentContext) -> TopicCreate {
+        TopicCreate { context, zoo }
+    }
+}
+
+impl Action for TopicCreate {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "kafka.apache.org/topic.create".into(),
+            description: "Create a topic with the given partitions and replication factor".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args: CreateArgs = decode_args(record)?;
+        let mut op_span = action_span(&self.context, "topicCreate", &span);
+        if self
+            .zoo
+            .topic_assignment(&args.topic, &mut op_span)?
+            .is_some()
+        {
+            let error = format!("topic {} already exists", args.topic);
+            return Err(ErrorKind::TopicAdmin(error).into());
+        }
+        let brokers = self.zoo.brokers(&mut op_span)?;
+        let partitions = assign_replicas(
+            &brokers,
+            0,
+            args.partitions as i32,
+            usize::from(args.replication_factor),
+        )?;
+        if !args.dry_run {
+            self.zoo
+                .create_topic(&args.topic, &partitions, &args.config, &mut op_span)?;
+        }
+        let payload = json!({
+            "after": {
+                "config": args.config,
+                "partitions": partitions,
+            },
+            "before": null,
+            "dry_run": args.dry_run,
+            "topic": args.topic,
+        });
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        let args: CreateArgs = validate_action_args(args.clone())?;
+        args.validate()
+    }
+}
+
+/// Mark a topic for deletion by the brokers.
+pub struct TopicDelete {
+    context: AgentContext,
+    zoo: Arc<KafkaZoo>,
+}
+
+impl TopicDelete {
+    pub fn new(zoo: Arc<KafkaZoo>, context: AgentContext) -> TopicDelete {
+        TopicDelete { context, zoo }
+    }
+}
+
+impl Action for TopicDelete {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "kafka.apache.org/topic.delete".into(),
+            description: "Delete a topic (requires delete.topic.enable on the brokers)".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args: DeleteArgs = decode_args(record)?;
+        let mut op_span = action_span(&self.context, "topicDelete", &span);
+        let assignment = existing_topic(&self.zoo, &args.topic, &mut op_span)?;
+        let pending = if args.dry_run {
+            false
+        } else {
+            !self.zoo.delete_topic(&args.topic, &mut op_span)?
+        };
+        let payload = json!({
+            "after": null,
+            "already_pending": pending,
+            "before": {
+                "partitions": assignment.partitions,
+            },
+            "dry_run": args.dry_run,
+            "topic": args.topic,
+        });
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        let args: DeleteArgs = validate_action_args(args.clone())?;
+        validate_topic(&args.topic)
+    }
+}
+
+/// Increase the number of partitions of a topic.
+///
+/// New partitions use the replication factor of the topic's first partition.
+pub struct TopicAlterPartitions {
+    context: AgentContext,
+    zoo: Arc<KafkaZoo>,
+}
+
+impl TopicAlterPartitions {
+    pub fn new(zoo: Arc<KafkaZoo>, context: AgentContext) -> TopicAlterPartitions {
+        TopicAlterPartitions { context, zoo }
+    }
+}
+
+impl Action for TopicAlterPartitions {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "kafka.apache.org/topic.partitions.alter".into(),
+            description: "Increase the number of partitions of a topic".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args: AlterPartitionsArgs = decode_args(record)?;
+        let mut op_span = action_span(&self.context, "topicAlterPartitions", &span);
+        let before = existing_topic(&self.zoo, &args.topic, &mut op_span)?;
+        let current = before.partitions.len() as i32;
+        let target = args.partitions as i32;
+        if target <= current {
+            let error = format!(
+                "topic {} already has {} partitions: partitions can only be increased",
+                args.topic, current
+            );
+            return Err(ErrorKind::TopicAdmin(error).into());
+        }
+        let replication_factor = before.partitions.values().next().map(Vec::len).unwrap_or(1);
+        let brokers = self.zoo.brokers(&mut op_span)?;
+        let added = assign_replicas(&brokers, current, target - current, replication_factor)?;
+        let mut after = before.clone();
+        after.partitions.extend(added);
+        if !args.dry_run {
+            self.zoo
+                .update_assignment(&args.topic, &after, &mut op_span)?;
+        }
+        let payload = json!({
+            "after": {
+                "partitions": after.partitions,
+            },
+            "before": {
+                "partitions": before.partitions,
+            },
+            "dry_run": args.dry_run,
+            "topic": args.topic,
+        });
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        let args: AlterPartitionsArgs = validate_action_args(args.clone())?;
+        validate_topic(&args.topic)?;
+        validate_partitions(args.partitions)
+    }
+}
+
+/// Start a span for an action, child of the action span if there is one.
+fn action_span(context: &AgentContext, name: &str, parent: &Option<&mut Span>) -> ChildSpan {
+    let parent = parent.as_ref().map(|parent| &**parent);
+    child_span(&context.tracer, name, parent)
+}
+
+/// Assign replicas of new partitions to brokers in a round-robin fashion.
+///
+/// The first replica of each partition is its preferred leader so leadership
+/// is spread across brokers as well.
+fn assign_replicas(
+    brokers: &[i32],
+    first: i32,
+    count: i32,
+    replication_factor: usize,
+) -> Result<BTreeMap<i32, Vec<i32>>> {
+    if replication_factor > brokers.len() {
+        let error = format!(
+            "replication factor {} is larger than the {} available brokers",
+            replication_factor,
+            brokers.len()
+        );
+        return Err(ErrorKind::TopicAdmin(error).into());
+    }
+    let assignment = (first..first + count)
+        .map(|partition| {
+            let replicas = (0..replication_factor)
+                .map(|replica| brokers[(partition as usize + replica) % brokers.len()])
+                .collect();
+            (partition, replicas)
+        })
+        .collect();
+    Ok(assignment)
+}
+
+/// Decode the arguments of an action record.
+fn decode_args<T>(record: &dyn ActionRecordView) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let args =
+        serde_json::from_value(record.args().clone()).with_context(|_| BaseKind::ActionDecode)?;
+    Ok(args)
+}
+
+/// Fetch the assignment of a topic that is expected to exist.
+fn existing_topic(zoo: &KafkaZoo, topic: &str, span: &mut Span) -> Result<TopicAssignment> {
+    match zoo.topic_assignment(topic, span)? {
+        Some(assignment) => Ok(assignment),
+        None => {
+            let error = format!("topic {} does not exist", topic);
+            Err(ErrorKind::TopicAdmin(error).into())
+        }
+    }
+}
+
+fn validate_partitions(partitions: u32) -> ActionValidity {
+    if partitions == 0 || partitions > i32::max_value() as u32 {
+        let error = format!("invalid number of partitions {}", partitions);
+        return Err(ActionValidityError::InvalidArgs(error));
+    }
+    Ok(())
+}
+
+/// Check a topic name is accepted by Kafka.
+fn validate_topic(topic: &str) -> ActionValidity {
+    let legal = topic
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-');
+    let valid = !topic.is_empty()
+        && topic.len() <= MAX_TOPIC_NAME
+        && topic != "."
+        && topic != ".."
+        && legal;
+    if !valid {
+        let error = format!("'{}' is not a valid topic name", topic);
+        return Err(ActionValidityError::InvalidArgs(error));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use replicante_agent::actions::utils::validate_action_args;
+
+    use super::assign_replicas;
+    use super::validate_topic;
+    use super::CreateArgs;
+
+    #[test]
+    fn assign_round_robin() {
+        let assignment = assign_replicas(&[1, 2, 3], 2, 3, 2).unwrap();
+        let mut expected = BTreeMap::new();
+        expected.insert(2, vec![3, 1]);
+        expected.insert(3, vec![1, 2]);
+        expected.insert(4, vec![2, 3]);
+        assert_eq!(assignment, expected);
+    }
+
+    #[test]
+    fn assign_too_few_brokers() {
+        assert!(assign_replicas(&[1, 2], 0, 1, 3).is_err());
+    }
+
+    #[test]
+    fn create_args_schema() {
+        let args = json!({"topic": "events", "partitions": 3, "replication_factor": 2});
+        let args: CreateArgs = validate_action_args(args).unwrap();
+        assert!(args.validate().is_ok());
+        assert!(!args.dry_run);
+        let args = json!({"topic": "events", "partitions": 3, "replicas": 2});
+        assert!(validate_action_args::<CreateArgs>(args).is_err());
+        let args = json!({"topic": "events", "partitions": 0, "replication_factor": 2});
+        let args: CreateArgs = validate_action_args(args).unwrap();
+        assert!(args.validate().is_err());
+    }
+
+    #[test]
+    fn topic_names() {
+        assert!(validate_topic("app.events_v2-eu").is_ok());
+        assert!(validate_topic("").is_err());
+        assert!(validate_topic("..").is_err());
+        assert!(validate_topic("with space").is_err());
+        assert!(validate_topic(&"t".repeat(250)).is_err());
+    }
+}
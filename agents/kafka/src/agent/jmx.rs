@@ -1,92 +1,282 @@
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering;
-
-use failure::ResultExt;
-use jmx::MBeanAddress;
-use jmx::MBeanClientTrait;
-use jmx::MBeanThreadedClient;
-use jmx::MBeanThreadedClientOptions;
-use opentracingrust::Log;
+use std::collections::HashMap;
+use std::panic::resume_unwind;
+use std::slice::Chunks;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
 use opentracingrust::Span;
-use slog::debug;
-use slog::info;
+use parking_lot::Mutex;
 
-use replicante_agent::fail_span;
+use replicante_agent::spans::child_span;
+use replicante_agent::spans::unsampled_span;
+use replicante_agent::spans::ChildSpan;
 use replicante_agent::AgentContext;
-use replicante_agent::Error;
 use replicante_agent::Result;
+use replicante_jmx_helper::JmxClient;
+use replicante_jmx_helper::JmxConfig;
 
 use super::super::error::ErrorKind;
-use super::super::metrics::OPS_COUNT;
-use super::super::metrics::OPS_DURATION;
-use super::super::metrics::OP_ERRORS_COUNT;
-use super::super::metrics::RECONNECT_COUNT;
 
 const KAFKA_BROKER_ID_MBEAN_QUERY: &str = "kafka.server:type=app-info,id=*";
 const KAFKA_BROKER_VERSION: &str = "kafka.server:type=app-info";
 const KAFKA_LAG_PREFIX: &str =
     "kafka.server:type=FetcherLagMetrics,name=ConsumerLag,clientId=ReplicaFetcherThread-0-";
+const JVM_RUNTIME_MBEAN: &str = "java.lang:type=Runtime";
 
-// Limit the number of pending JMX requests to avoid memory exhaustion.
-const JMX_REQUESTS_QUEUE: usize = 1024;
+/// Delay between checks that cached broker details are for the running broker.
+const CACHE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 
 /// Kafka specifics that rely on JMX.
 pub struct KafkaJmx {
+    cache: Mutex<BrokerCache>,
     context: AgentContext,
-    jmx: MBeanThreadedClient,
-    reconnect: AtomicBool,
-    reconnect_address: MBeanAddress,
+    jmx: JmxClient,
+    pool_size: usize,
+}
+
+/// Broker details that do not change while the broker is running.
+///
+/// Details are dropped when the JMX connection generation changes or when the
+/// start time of the broker JVM changes, as the broker may have been restarted
+/// (for example with a new version).
+/// Restarts that do not break the JMX connection are noticed by the start time check.
+#[derive(Default)]
+struct BrokerCache {
+    checked: Option<Instant>,
+    generation: u64,
+    name: Option<String>,
+    start_time: Option<i64>,
+    version: Option<String>,
+}
+
+impl BrokerCache {
+    /// Check if the cache was confirmed to be for the running broker less than `interval` ago.
+    fn checked_within(&self, generation: u64, now: Instant, interval: Duration) -> bool {
+        self.generation == generation
+            && self
+                .checked
+                .map(|checked| now.saturating_duration_since(checked) < interval)
+                .unwrap_or(false)
+    }
+
+    /// Record the start time of the broker JVM, dropping details of a previous JVM.
+    fn check_start_time(&mut self, generation: u64, start_time: i64, now: Instant) {
+        if generation < self.generation {
+            return;
+        }
+        if generation > self.generation || self.start_time != Some(start_time) {
+            *self = BrokerCache {
+                generation,
+                start_time: Some(start_time),
+                ..BrokerCache::default()
+            };
+        }
+        self.checked = Some(now);
+    }
+
+    /// Look up a cached detail, if the cache is for the given connection generation.
+    fn get<T, F>(&self, generation: u64, get: F) -> Option<T>
+    where
+        F: FnOnce(&BrokerCache) -> Option<T>,
+    {
+        if self.generation != generation {
+            return None;
+        }
+        get(self)
+    }
+
+    /// Store details fetched during the given connection generation.
+    ///
+    /// Details fetched before the latest reconnect are discarded.
+    fn store<F>(&mut self, generation: u64, set: F)
+    where
+        F: FnOnce(&mut BrokerCache),
+    {
+        if generation < self.generation {
+            return;
+        }
+        if generation > self.generation {
+            *self = BrokerCache {
+                generation,
+                ..BrokerCache::default()
+            };
+        }
+        set(self);
+    }
 }
 
 impl KafkaJmx {
-    pub fn with_context(context: AgentContext, target: String) -> Result<KafkaJmx> {
-        let address = MBeanAddress::address(target);
-        let options = MBeanThreadedClientOptions::default()
-            .requests_buffer_size(JMX_REQUESTS_QUEUE)
-            // Skip connecting the first time around.
-            .skip_connect(true);
-        let jmx = MBeanThreadedClient::connect_with_options(address.clone(), options)
-            .with_context(|_| {
-                ErrorKind::JmxConnection(match address.clone() {
-                    MBeanAddress::Address(address) => address,
-                    MBeanAddress::ServiceUrl(address) => address,
-                })
-            })?;
+    pub fn with_context(
+        context: AgentContext,
+        target: String,
+        config: JmxConfig,
+    ) -> Result<KafkaJmx> {
+        let pool_size = config.pool_size;
+        let jmx = JmxClient::connect(target, config, context.logger.clone())?;
         Ok(KafkaJmx {
+            cache: Mutex::new(BrokerCache::default()),
             context,
             jmx,
-            reconnect: AtomicBool::new(true),
-            reconnect_address: address,
+            pool_size,
         })
     }
 
-    /// Fetch the ID of the broker.
+    /// Fetch the ID of the broker, cached until the broker restarts.
     pub fn broker_name(&self, parent: &mut Span) -> Result<String> {
-        let mut names = {
-            let mut span = self.context.tracer.span("brokerName").auto_finish();
-            span.child_of(parent.context().clone());
-            span.tag("service", "jmx");
-            self.reconnect_if_needed(&mut span)
-                .map_err(|error| fail_span(error, &mut *span))?;
-            span.log(Log::new().log("span.kind", "client-send"));
-            OPS_COUNT.with_label_values(&["jmx", "queryNames"]).inc();
-            let timer = OPS_DURATION
-                .with_label_values(&["jmx", "queryNames"])
-                .start_timer();
-            let names = self
-                .jmx
-                .query_names(KAFKA_BROKER_ID_MBEAN_QUERY, "")
-                .map_err(|error| {
-                    OP_ERRORS_COUNT
-                        .with_label_values(&["jmx", "queryNames"])
-                        .inc();
-                    fail_span(error, &mut *span)
+        let generation = self.jmx.generation();
+        self.check_cache(generation, parent)?;
+        let cached = self
+            .cache
+            .lock()
+            .get(generation, |cache| cache.name.clone());
+        if let Some(name) = cached {
+            return Ok(name);
+        }
+        let name = self.fetch_broker_name(parent)?;
+        self.cache
+            .lock()
+            .store(generation, |cache| cache.name = Some(name.clone()));
+        Ok(name)
+    }
+
+    /// Fetch the version of the broker, cached until the broker restarts.
+    pub fn broker_version(&self, parent: &mut Span) -> Result<String> {
+        let generation = self.jmx.generation();
+        self.check_cache(generation, parent)?;
+        let cached = self
+            .cache
+            .lock()
+            .get(generation, |cache| cache.version.clone());
+        if let Some(version) = cached {
+            return Ok(version);
+        }
+        let mut span = child_span(&self.context.tracer, "brokerVersion", Some(&*parent));
+        let version: String = self.jmx.get_attribute(
+            "<jmx>.broker_version",
+            KAFKA_BROKER_VERSION,
+            "version",
+            &mut span,
+        )?;
+        self.cache
+            .lock()
+            .store(generation, |cache| cache.version = Some(version.clone()));
+        Ok(version)
+    }
+
+    /// Fetch replica lag for partitions of a topic, given as `(partition, leader)` pairs.
+    ///
+    /// JMX can't read attributes of several MBeans in one request and Kafka only
+    /// aggregates replica lag across all partitions, so one request per partition is
+    /// still needed. The requests are split across the JMX connection pool and sent
+    /// in parallel: the number of round trips is the same but a topic takes about
+    /// as long as `partitions / kafka.jmx.pool_size` sequential requests.
+    pub fn topic_replica_lag(
+        &self,
+        topic: &str,
+        replicas: &[(i32, i32)],
+        parent: &mut Span,
+    ) -> Result<HashMap<i32, i64>> {
+        if replicas.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let mut span = child_span(&self.context.tracer, "topicReplicaLag", Some(&*parent));
+        span.tag("topic", topic.to_string());
+        let batches: Vec<Result<Vec<(i32, i64)>>> = thread::scope(|scope| {
+            let workers: Vec<_> = replica_batches(replicas, self.pool_size)
+                .map(|replicas| {
+                    let child = child_span(&self.context.tracer, "replicaLag", Some(&*span));
+                    scope.spawn(move || self.replica_lag_batch(topic, replicas, child))
                 })
-                .with_context(|_| ErrorKind::StoreOpFailed("<jmx>.broker_name"))
-                .map_err(Error::from);
-            timer.observe_duration();
-            span.log(Log::new().log("span.kind", "client-receive"));
-            self.check_jmx_response(names)?
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap_or_else(|panic| resume_unwind(panic)))
+                .collect()
+        });
+        let mut lags = HashMap::new();
+        for batch in batches {
+            lags.extend(batch?);
+        }
+        Ok(lags)
+    }
+
+    /// Fetch a numeric attribute of all MBeans matching the given name or pattern.
+    ///
+    /// Returns the value of the attribute for each matching MBean, by MBean name.
+    /// Metrics are collected on every scrape, outside of any traced request,
+    /// so the JMX calls are not traced.
+    pub fn metric_values(&self, mbean: &str, attribute: &str) -> Result<Vec<(String, f64)>> {
+        let mut span = unsampled_span(&self.context.tracer, "jmxMetric");
+        span.tag("mbean", mbean.to_string());
+        let names = if mbean.contains('*') || mbean.contains('?') {
+            self.jmx
+                .query_names("<jmx>.metric_names", mbean, &mut span)?
+        } else {
+            vec![mbean.to_string()]
+        };
+        let mut values = Vec::new();
+        for name in names {
+            let value: f64 =
+                self.jmx
+                    .get_attribute("<jmx>.metric_value", name.clone(), attribute, &mut span)?;
+            values.push((name, value));
+        }
+        Ok(values)
+    }
+}
+
+impl KafkaJmx {
+    /// Drop cached broker details if the broker JVM restarted since they were fetched.
+    ///
+    /// The JVM start time is checked at most once every `CACHE_CHECK_INTERVAL` so
+    /// details looked up together (such as for `datastore_info`) share one JMX call.
+    fn check_cache(&self, generation: u64, parent: &mut Span) -> Result<()> {
+        let now = Instant::now();
+        let checked = self
+            .cache
+            .lock()
+            .checked_within(generation, now, CACHE_CHECK_INTERVAL);
+        if checked {
+            return Ok(());
+        }
+        let mut span = child_span(&self.context.tracer, "brokerStartTime", Some(&*parent));
+        let start_time: i64 = self.jmx.get_attribute(
+            "<jmx>.broker_start_time",
+            JVM_RUNTIME_MBEAN,
+            "StartTime",
+            &mut span,
+        )?;
+        self.cache
+            .lock()
+            .check_start_time(generation, start_time, now);
+        Ok(())
+    }
+
+    /// Fetch replica lag for a batch of partitions of a topic, one after the other.
+    fn replica_lag_batch(
+        &self,
+        topic: &str,
+        replicas: &[(i32, i32)],
+        mut span: ChildSpan,
+    ) -> Result<Vec<(i32, i64)>> {
+        let mut lags = Vec::with_capacity(replicas.len());
+        for (partition, leader) in replicas {
+            let key = replica_lag_mbean(topic, *partition, *leader);
+            let lag = self
+                .jmx
+                .get_attribute("<jmx>.partitionLag", key, "Value", &mut span)?;
+            lags.push((*partition, lag));
+        }
+        Ok(lags)
+    }
+
+    /// Query JMX for the ID of the broker.
+    fn fetch_broker_name(&self, parent: &mut Span) -> Result<String> {
+        let mut names = {
+            let mut span = child_span(&self.context.tracer, "brokerName", Some(&*parent));
+            self.jmx
+                .query_names("<jmx>.broker_name", KAFKA_BROKER_ID_MBEAN_QUERY, &mut span)?
         };
         let name: String = match names.len() {
             0 => return Err(ErrorKind::BrokerNoId.into()),
@@ -112,110 +302,128 @@ impl KafkaJmx {
         }
         Err(ErrorKind::BrokerIdFormat(name.clone()).into())
     }
+}
 
-    /// Fetch the version of the broker.
-    pub fn broker_version(&self, parent: &mut Span) -> Result<String> {
-        let mut span = self.context.tracer.span("brokerVersion").auto_finish();
-        span.child_of(parent.context().clone());
-        span.tag("service", "jmx");
-        self.reconnect_if_needed(&mut span)
-            .map_err(|error| fail_span(error, &mut *span))?;
-        span.log(Log::new().log("span.kind", "client-send"));
-        OPS_COUNT.with_label_values(&["jmx", "getAttribute"]).inc();
-        let timer = OPS_DURATION
-            .with_label_values(&["jmx", "getAttribute"])
-            .start_timer();
-        let version = self
-            .jmx
-            .get_attribute(KAFKA_BROKER_VERSION, "version")
-            .map_err(|error| {
-                OP_ERRORS_COUNT
-                    .with_label_values(&["jmx", "getAttribute"])
-                    .inc();
-                fail_span(error, &mut *span)
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("<jmx>.broker_version"))
-            .map_err(Error::from);
-        timer.observe_duration();
-        span.log(Log::new().log("span.kind", "client-receive"));
-        let version = self.check_jmx_response(version)?;
-        Ok(version)
+/// Split replicas into at most `pool_size` batches of about the same size.
+fn replica_batches(replicas: &[(i32, i32)], pool_size: usize) -> Chunks<'_, (i32, i32)> {
+    let batch = (replicas.len() + pool_size - 1) / pool_size;
+    replicas.chunks(batch.max(1))
+}
+
+/// Name of the MBean reporting the lag of a partition replicated from the given leader.
+fn replica_lag_mbean(topic: &str, partition: i32, leader: i32) -> String {
+    format!(
+        "{}{},topic={},partition={}",
+        KAFKA_LAG_PREFIX, leader, topic, partition
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::replica_batches;
+    use super::replica_lag_mbean;
+    use super::BrokerCache;
+
+    #[test]
+    fn cache_check_expires() {
+        let interval = Duration::from_secs(5);
+        let now = Instant::now();
+        let mut cache = BrokerCache::default();
+        assert!(!cache.checked_within(0, now, interval));
+        cache.check_start_time(0, 1000, now);
+        assert!(cache.checked_within(0, now + Duration::from_secs(1), interval));
+        assert!(!cache.checked_within(0, now + interval, interval));
+        assert!(!cache.checked_within(1, now, interval));
     }
 
-    /// Fetch replica lag information.
-    pub fn replica_lag(
-        &self,
-        topic: &str,
-        partition: i32,
-        leader: i32,
-        parent: &mut Span,
-    ) -> Result<i64> {
-        let mut span = self.context.tracer.span("replicaLag").auto_finish();
-        span.child_of(parent.context().clone());
-        span.tag("service", "jmx");
-        let key = format!(
-            "{}{},topic={},partition={}",
-            KAFKA_LAG_PREFIX, leader, topic, partition
+    #[test]
+    fn cache_invalidated_on_broker_restart() {
+        let now = Instant::now();
+        let mut cache = BrokerCache::default();
+        cache.check_start_time(1, 1000, now);
+        cache.store(1, |cache| cache.name = Some("1".into()));
+        cache.store(1, |cache| cache.version = Some("2.8.0".into()));
+
+        // Same JVM: details are kept.
+        cache.check_start_time(1, 1000, now);
+        assert_eq!(
+            cache.get(1, |cache| cache.version.clone()),
+            Some("2.8.0".into())
+        );
+
+        // Broker restarted (upgraded) in place without breaking the JMX connection.
+        cache.check_start_time(1, 2000, now);
+        assert_eq!(cache.get(1, |cache| cache.name.clone()), None);
+        assert_eq!(cache.get(1, |cache| cache.version.clone()), None);
+        cache.store(1, |cache| cache.version = Some("3.0.0".into()));
+        assert_eq!(
+            cache.get(1, |cache| cache.version.clone()),
+            Some("3.0.0".into())
         );
-        self.reconnect_if_needed(&mut span)
-            .map_err(|error| fail_span(error, &mut *span))?;
-        span.log(Log::new().log("span.kind", "client-send"));
-        OPS_COUNT.with_label_values(&["jmx", "getAttribute"]).inc();
-        let timer = OPS_DURATION
-            .with_label_values(&["jmx", "getAttribute"])
-            .start_timer();
-        let lag = self
-            .jmx
-            .get_attribute(key, "Value")
-            .map_err(|error| {
-                OP_ERRORS_COUNT
-                    .with_label_values(&["jmx", "getAttribute"])
-                    .inc();
-                fail_span(error, &mut *span)
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("<jmx>.partitionLag"))
-            .map_err(Error::from);
-        timer.observe_duration();
-        span.log(Log::new().log("span.kind", "client-receive"));
-        let lag = self.check_jmx_response(lag)?;
-        Ok(lag)
     }
-}
 
-impl KafkaJmx {
-    /// Check if JMX responded with an error.
-    ///
-    /// If so, flag the need to reconnect so it can be done prior to the next request.
-    fn check_jmx_response<T>(&self, result: Result<T>) -> Result<T> {
-        if result.is_err() {
-            self.reconnect.store(true, Ordering::Relaxed);
-        }
-        result
+    #[test]
+    fn cache_ignores_stale_start_time() {
+        let now = Instant::now();
+        let mut cache = BrokerCache::default();
+        cache.check_start_time(2, 2000, now);
+        cache.store(2, |cache| cache.version = Some("3.0.0".into()));
+        cache.check_start_time(1, 1000, now);
+        assert_eq!(
+            cache.get(2, |cache| cache.version.clone()),
+            Some("3.0.0".into())
+        );
     }
 
-    /// If there was an error in a past connection to the JMX server reconnect.
-    fn reconnect_if_needed(&self, span: &mut Span) -> Result<()> {
-        if self.reconnect.load(Ordering::Relaxed) {
-            debug!(self.context.logger, "Reconnecting to JMX server");
-            span.log(Log::new().log("action", "jmx.connect"));
-            RECONNECT_COUNT.with_label_values(&["jmx"]).inc();
-            let options = self.reconnect_options();
-            self.jmx
-                .reconnect_with_options(self.reconnect_address.clone(), options)
-                .with_context(|_| {
-                    ErrorKind::JmxConnection(match self.reconnect_address.clone() {
-                        MBeanAddress::Address(address) => address,
-                        MBeanAddress::ServiceUrl(address) => address,
-                    })
-                })?;
-            self.reconnect.store(false, Ordering::Relaxed);
-            info!(self.context.logger, "Reconnected to JMX server");
-        }
-        Ok(())
+    #[test]
+    fn cache_invalidated_on_reconnect() {
+        let mut cache = BrokerCache::default();
+        cache.store(1, |cache| cache.name = Some("1".into()));
+        cache.store(1, |cache| cache.version = Some("2.8.0".into()));
+        assert_eq!(cache.get(1, |cache| cache.name.clone()), Some("1".into()));
+        assert_eq!(cache.get(2, |cache| cache.name.clone()), None);
+
+        cache.store(2, |cache| cache.version = Some("3.0.0".into()));
+        assert_eq!(cache.get(2, |cache| cache.name.clone()), None);
+        assert_eq!(
+            cache.get(2, |cache| cache.version.clone()),
+            Some("3.0.0".into())
+        );
     }
 
-    /// Generate connection options for reconnecting to the JMX server.
-    fn reconnect_options(&self) -> MBeanThreadedClientOptions {
-        MBeanThreadedClientOptions::default().requests_buffer_size(JMX_REQUESTS_QUEUE)
+    #[test]
+    fn cache_ignores_stale_values() {
+        let mut cache = BrokerCache::default();
+        cache.store(2, |cache| cache.version = Some("3.0.0".into()));
+        cache.store(1, |cache| cache.version = Some("2.8.0".into()));
+        assert_eq!(
+            cache.get(2, |cache| cache.version.clone()),
+            Some("3.0.0".into())
+        );
+    }
+
+    #[test]
+    fn replica_batches_use_the_pool() {
+        let replicas: Vec<(i32, i32)> = (0..10).map(|partition| (partition, 1)).collect();
+        let sizes: Vec<usize> = replica_batches(&replicas, 4).map(|b| b.len()).collect();
+        assert_eq!(sizes, vec![3, 3, 3, 1]);
+        let sizes: Vec<usize> = replica_batches(&replicas, 1).map(|b| b.len()).collect();
+        assert_eq!(sizes, vec![10]);
+        let sizes: Vec<usize> = replica_batches(&replicas[..2], 4)
+            .map(|b| b.len())
+            .collect();
+        assert_eq!(sizes, vec![1, 1]);
+    }
+
+    #[test]
+    fn replica_lag_mbean_name() {
+        assert_eq!(
+            replica_lag_mbean("orders", 3, 2),
+            "kafka.server:type=FetcherLagMetrics,name=ConsumerLag,\
+             clientId=ReplicaFetcherThread-0-2,topic=orders,partition=3"
+        );
     }
 }
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use prometheus::core::Collector;
+use prometheus::core::Desc;
+use prometheus::proto::MetricFamily;
+use prometheus::GaugeVec;
+use prometheus::Opts;
+use slog::debug;
+use slog::Logger;
+
+use replicante_agent::Result;
+use replicante_agent::ResultExt;
+use replicante_util_failure::failure_info;
+
+use super::super::config::JmxMetric;
+use super::super::error::ErrorKind;
+use super::jmx::KafkaJmx;
+
+/// Prometheus collector exporting operator configured JMX MBean attributes.
+///
+/// MBeans are queried every time metrics are collected so gauges are as fresh as
+/// the scrape that requested them. MBeans that can't be queried are skipped.
+pub struct JmxMetricsCollector {
+    gauges: Vec<(JmxMetric, GaugeVec)>,
+    jmx: Arc<KafkaJmx>,
+    logger: Logger,
+}
+
+impl JmxMetricsCollector {
+    pub fn new(
+        jmx: Arc<KafkaJmx>,
+        metrics: Vec<JmxMetric>,
+        logger: Logger,
+    ) -> Result<JmxMetricsCollector> {
+        let mut gauges = Vec::new();
+        for metric in metrics {
+            let opts = Opts::new(metric.name.clone(), metric.help.clone());
+            let labels: Vec<&str> = metric.labels.iter().map(String::as_str).collect();
+            let gauge = GaugeVec::new(opts, &labels)
+                .with_context(|_| ErrorKind::ConfigOption("kafka.jmx_metrics"))?;
+            gauges.push((metric, gauge));
+        }
+        Ok(JmxMetricsCollector {
+            gauges,
+            jmx,
+            logger,
+        })
+    }
+}
+
+impl Collector for JmxMetricsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.gauges
+            .iter()
+            .flat_map(|(_, gauge)| gauge.desc())
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut families = Vec::new();
+        for (metric, gauge) in &self.gauges {
+            // Drop MBeans that are no longer reported, such as deleted topics.
+            gauge.reset();
+            match self.jmx.metric_values(&metric.mbean, &metric.attribute) {
+                Ok(values) => {
+                    for (name, value) in values {
+                        let labels: Vec<&str> = metric
+                            .labels
+                            .iter()
+                            .map(|label| mbean_property(&name, label).unwrap_or(""))
+                            .collect();
+                        gauge.with_label_values(&labels).set(value);
+                    }
+                }
+                Err(error) => debug!(
+                    self.logger,
+                    "Failed to collect JMX metric";
+                    "mbean" => &metric.mbean,
+                    "metric" => &metric.name,
+                    failure_info(&error.to_fail()),
+                ),
+            }
+            families.extend(gauge.collect());
+        }
+        families
+    }
+}
+
+/// Extract the value of a key property from an MBean name (`domain:key=value,...`).
+fn mbean_property<'a>(name: &'a str, key: &str) -> Option<&'a str> {
+    let properties = name.splitn(2, ':').nth(1)?;
+    properties.split(',').find_map(|property| {
+        let mut pair = property.splitn(2, '=');
+        match (pair.next(), pair.next()) {
+            (Some(name), Some(value)) if name == key => Some(value),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mbean_property;
+
+    #[test]
+    fn mbean_properties() {
+        let name = "kafka.server:type=BrokerTopicMetrics,name=MessagesInPerSec,topic=test";
+        assert_eq!(mbean_property(name, "topic"), Some("test"));
+        assert_eq!(mbean_property(name, "type"), Some("BrokerTopicMetrics"));
+        assert_eq!(mbean_property(name, "partition"), None);
+        assert_eq!(mbean_property("no-properties", "topic"), None);
+    }
+}
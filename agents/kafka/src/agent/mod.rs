@@ -1,17 +1,33 @@
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
-use failure::ResultExt;
-use failure::SyncFailure;
 use kafka::client::FetchOffset;
 use kafka::client::KafkaClient;
+use kafka::client::PartitionOffset;
 use lazy_static::lazy_static;
 use opentracingrust::Span;
+use parking_lot::Mutex;
+use slog::debug;
+use slog::warn;
 
+use replicante_agent::check_datastore_version;
+use replicante_agent::deadline;
+use replicante_agent::discovery;
+use replicante_agent::shards::PartialShards;
+use replicante_agent::stages::StageTimer;
+use replicante_agent::stages::STAGE_CONNECT;
+use replicante_agent::stages::STAGE_PARSE;
+use replicante_agent::stages::STAGE_QUERY;
+use replicante_agent::sync_compat;
 use replicante_agent::Agent;
 use replicante_agent::AgentContext;
+use replicante_agent::ErrorKind as BaseKind;
 use replicante_agent::Result;
+use replicante_agent::ResultExt;
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::AgentVersion;
 use replicante_models_agent::info::CommitOffset;
@@ -19,18 +35,25 @@ use replicante_models_agent::info::DatastoreInfo;
 use replicante_models_agent::info::Shard;
 use replicante_models_agent::info::ShardRole;
 use replicante_models_agent::info::Shards;
+use replicante_util_failure::failure_info;
 
+use super::config::BrokerTarget;
 use super::error::ErrorKind;
+use super::metrics::CLIENT_RECOVERIES;
 use super::metrics::OPS_COUNT;
 use super::metrics::OPS_DURATION;
 use super::metrics::OP_ERRORS_COUNT;
 use super::Config;
 
 mod jmx;
+mod jmx_metrics;
 mod zk;
 
 use self::jmx::KafkaJmx;
-use self::zk::KafkaZoo;
+use self::jmx_metrics::JmxMetricsCollector;
+use self::zk::chroot_display_name;
+pub use self::zk::KafkaZoo;
+pub use self::zk::TopicAssignment;
 
 lazy_static! {
     pub static ref AGENT_VERSION: AgentVersion = AgentVersion::new(
@@ -40,35 +63,95 @@ lazy_static! {
     );
 }
 
+/// Identity the agent reports to Kafka (`client.id`) and annotates Zookeeper sessions with.
+pub fn client_identity(context: &AgentContext) -> String {
+    context
+        .config
+        .client_identity
+        .identity("repliagent-kafka", env!("CARGO_PKG_VERSION"))
+}
+
+/// Topics in the cluster and the latest offsets of their partitions.
+///
+/// Loaded with one metadata and one offsets request for all topics
+/// and reused for all topics in a collection cycle.
+struct ClusterOffsets {
+    offsets: HashMap<String, HashMap<i32, i64>>,
+    topics: Vec<String>,
+}
+
 /// Kafka 1.0+ agent.
 pub struct KafkaAgent {
-    jmx: KafkaJmx,
-    kafka: Mutex<KafkaClient>,
-    zoo: KafkaZoo,
+    broker: BrokerTarget,
+    context: AgentContext,
+    display_name: Option<String>,
+    jmx: Arc<KafkaJmx>,
+
+    /// Kafka client, taken out of the lock while in use.
+    ///
+    /// If a panic occurs while the client is in use, or a call is abandoned because
+    /// the request deadline expired, the slot is left empty and a new client is created
+    /// the next time one is needed.
+    kafka: Mutex<Option<KafkaClient>>,
+    zoo: Arc<KafkaZoo>,
 }
 
 impl KafkaAgent {
     pub fn with_config(config: Config, context: AgentContext) -> Result<KafkaAgent> {
-        let jmx = KafkaJmx::with_context(context.clone(), config.kafka.target.jmx)?;
-        let kafka_timeout = Duration::from_secs(config.kafka.target.broker.timeout);
-        let mut kafka = KafkaClient::new(vec![config.kafka.target.broker.uri]);
-        kafka.set_client_id("replicante-kafka-agent".into());
-        kafka
-            .set_fetch_max_wait_time(kafka_timeout)
-            .map_err(SyncFailure::new)
-            .with_context(|_| ErrorKind::ConfigOption("kafka.target.broker.timeout"))?;
-        kafka.set_connection_idle_timeout(kafka_timeout);
+        config.kafka.jmx.validate("kafka.jmx")?;
+        let jmx =
+            KafkaJmx::with_context(context.clone(), config.kafka.target.jmx, config.kafka.jmx)?;
+        let jmx = Arc::new(jmx);
+        let broker = config.kafka.target.broker;
+        let kafka = KafkaAgent::kafka_client(&broker, &context)?;
+        let display_name = chroot_display_name(&config.kafka.target.zookeeper.uri);
         let zoo = KafkaZoo::connect(
-            context,
+            context.clone(),
             config.kafka.target.zookeeper.uri,
             config.kafka.target.zookeeper.timeout,
         )?;
+        let zoo = Arc::new(zoo);
+        if !config.kafka.jmx_metrics.is_empty() {
+            let collector = JmxMetricsCollector::new(
+                Arc::clone(&jmx),
+                config.kafka.jmx_metrics,
+                context.logger.clone(),
+            )?;
+            if let Err(error) = context.metrics.register(Box::new(collector)) {
+                debug!(context.logger, "Failed to register JMX metrics"; "error" => ?error);
+            }
+        }
         Ok(KafkaAgent {
+            broker,
+            context,
+            display_name,
             jmx,
-            kafka: Mutex::new(kafka),
+            kafka: Mutex::new(Some(kafka)),
             zoo,
         })
     }
+
+    /// Shared access to the Zookeeper client for topic administration.
+    pub fn zoo(&self) -> Arc<KafkaZoo> {
+        Arc::clone(&self.zoo)
+    }
+
+    /// Create a Kafka client, discovering the broker address if configured to do so.
+    fn kafka_client(broker: &BrokerTarget, context: &AgentContext) -> Result<KafkaClient> {
+        let uri = match &broker.discovery {
+            Some(discovery) => discovery::resolve(discovery, &context.logger)?,
+            None => broker.uri.clone(),
+        };
+        let kafka_timeout = Duration::from_secs(broker.timeout);
+        let mut kafka = KafkaClient::new(vec![uri]);
+        kafka.set_client_id(client_identity(context));
+        kafka
+            .set_fetch_max_wait_time(kafka_timeout)
+            .map_err(sync_compat)
+            .with_context(|_| ErrorKind::ConfigOption("kafka.target.broker.timeout"))?;
+        kafka.set_connection_idle_timeout(kafka_timeout);
+        Ok(kafka)
+    }
 }
 
 impl KafkaAgent {
@@ -78,10 +161,22 @@ impl KafkaAgent {
         shards: &mut Vec<Shard>,
         broker_id: i32,
         topic: &str,
+        cluster: &ClusterOffsets,
         span: &mut Span,
     ) -> Result<()> {
-        let offsets = self.topic_offsets(topic, span)?;
+        // Partitions are collected for each topic: stop once the requesting client gave up.
+        deadline::check("partitions")?;
+        let offsets = cluster
+            .offsets
+            .get(topic)
+            .ok_or_else(|| ErrorKind::TopicNoOffsets(topic.to_string()))?;
         let partitions = self.zoo.partitions(broker_id, topic, span)?;
+        let replicas: Vec<(i32, i32)> = partitions
+            .iter()
+            .filter(|meta| meta.leader != broker_id)
+            .map(|meta| (meta.partition, meta.leader))
+            .collect();
+        let lags = self.jmx.topic_replica_lag(topic, &replicas, span)?;
         for meta in partitions {
             let primary = meta.leader == broker_id;
             let role = if primary {
@@ -100,46 +195,148 @@ impl KafkaAgent {
             let lag = if primary {
                 None
             } else {
-                let lag = self
-                    .jmx
-                    .replica_lag(topic, meta.partition, meta.leader, span)?;
-                Some(CommitOffset::unit(lag, "messages"))
+                lags.get(&meta.partition)
+                    .map(|lag| CommitOffset::unit(*lag, "messages"))
             };
             shards.push(Shard::new(id, role, commit, lag));
         }
         Ok(())
     }
 
-    /// Return the latest partition offsets for all partitions in the topic.
-    fn topic_offsets(&self, topic: &str, _span: &mut Span) -> Result<HashMap<i32, i64>> {
-        let mut client = self.kafka.lock().expect("Kafka client lock was poisoned");
+    /// Return the topics in the cluster and the latest offsets of their partitions.
+    fn cluster_offsets(&self, span: &mut Span) -> Result<ClusterOffsets> {
+        let mut slot = self.kafka.lock();
+        if slot.is_none() {
+            warn!(
+                self.context.logger,
+                "Kafka client lost after a panic or an expired deadline, creating a new one"
+            );
+            CLIENT_RECOVERIES.inc();
+            let stages = StageTimer::new("kafka", "loadMetadata");
+            let client = stages.time(STAGE_CONNECT, span, |_| {
+                KafkaAgent::kafka_client(&self.broker, &self.context)
+            })?;
+            *slot = Some(client);
+        }
+        self.cluster_offsets_with(&mut slot, span)
+    }
+
+    /// Fetch cluster offsets with the client in the slot, replacing it if the broker moved.
+    fn cluster_offsets_with(
+        &self,
+        slot: &mut Option<KafkaClient>,
+        span: &mut Span,
+    ) -> Result<ClusterOffsets> {
         OPS_COUNT
             .with_label_values(&["kafka", "loadMetadata"])
             .inc();
         let timer = OPS_DURATION
             .with_label_values(&["kafka", "loadMetadata"])
             .start_timer();
-        client
-            .load_metadata(&[topic])
+        let stages = StageTimer::new("kafka", "loadMetadata");
+        let result = stages
+            .time(STAGE_QUERY, span, |_| {
+                bounded(slot, "loadMetadata", |client| client.load_metadata_all())
+            })?
             .map_err(|error| {
                 OP_ERRORS_COUNT
                     .with_label_values(&["kafka", "loadMetadata"])
                     .inc();
-                SyncFailure::new(error)
+                sync_compat(error)
             })
-            .with_context(|_| ErrorKind::StoreOpFailed("loadMetadata"))?;
+            .with_context(|_| ErrorKind::StoreOpFailed("loadMetadata"));
+        if let Err(error) = result {
+            // The broker may have moved: discover it again for the next request.
+            if self.broker.discovery.is_some() {
+                let kafka = stages.time(STAGE_CONNECT, span, |_| {
+                    KafkaAgent::kafka_client(&self.broker, &self.context)
+                });
+                match kafka {
+                    Ok(kafka) => *slot = Some(kafka),
+                    Err(error) => warn!(
+                        self.context.logger,
+                        "Failed to discover Kafka broker address";
+                        failure_info(&error.to_fail()),
+                    ),
+                }
+            }
+            return Err(error);
+        }
         timer.observe_duration();
-        let offsets = client
-            .fetch_offsets(&[topic], FetchOffset::Latest)
-            .map_err(SyncFailure::new)
+        let topics = sorted_topics(slot.as_ref().expect("Kafka client to be back in its slot"));
+        let stages = StageTimer::new("kafka", "fetchOffsets");
+        let request = topics.clone();
+        let offsets = stages
+            .time(STAGE_QUERY, span, |_| {
+                bounded(slot, "fetchOffsets", move |client| {
+                    client.fetch_offsets(&request, FetchOffset::Latest)
+                })
+            })?
+            .map_err(sync_compat)
             .with_context(|_| ErrorKind::StoreOpFailed("fetch_offsets"))?;
-        let offsets = offsets
-            .get(topic)
-            .ok_or_else(|| ErrorKind::TopicNoOffsets(topic.to_string()))?;
-        Ok(offsets
-            .iter()
-            .map(|item| (item.partition, item.offset))
-            .collect())
+        let offsets = stages.time(STAGE_PARSE, span, |_| partition_offsets(offsets));
+        Ok(ClusterOffsets { offsets, topics })
+    }
+}
+
+/// Index the offsets returned by `KafkaClient::fetch_offsets` by topic and partition.
+fn partition_offsets(
+    offsets: HashMap<String, Vec<PartitionOffset>>,
+) -> HashMap<String, HashMap<i32, i64>> {
+    offsets
+        .into_iter()
+        .map(|(topic, partitions)| {
+            let partitions = partitions
+                .iter()
+                .map(|item| (item.partition, item.offset))
+                .collect();
+            (topic, partitions)
+        })
+        .collect()
+}
+
+/// Names of the topics in the metadata loaded by the client, sorted.
+fn sorted_topics(client: &KafkaClient) -> Vec<String> {
+    let mut topics: Vec<String> = client.topics().names().map(String::from).collect();
+    topics.sort();
+    topics
+}
+
+/// Call the Kafka client in the slot, bounded by the deadline of the current request.
+///
+/// The Kafka client has no timeout for individual requests so calls made to serve requests
+/// with a deadline run on a thread of their own and are abandoned once the deadline expires.
+/// Abandoned calls keep the client and leave the slot empty for a new client to be created.
+fn bounded<F, T>(slot: &mut Option<KafkaClient>, operation: &'static str, call: F) -> Result<T>
+where
+    F: FnOnce(&mut KafkaClient) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let deadline = match deadline::current() {
+        None => {
+            // Take the client out of the slot so panics leave it empty.
+            let mut client = slot.take().expect("Kafka client to be in its slot");
+            let result = call(&mut client);
+            *slot = Some(client);
+            return Ok(result);
+        }
+        Some(deadline) => deadline,
+    };
+    deadline.check(operation)?;
+    let mut client = slot.take().expect("Kafka client to be in its slot");
+    let (send, receive) = mpsc::channel();
+    thread::spawn(move || {
+        let result = call(&mut client);
+        // The caller may have stopped waiting for the result.
+        let _ = send.send((client, result));
+    });
+    match receive.recv_timeout(deadline.remaining()) {
+        Ok((client, result)) => {
+            *slot = Some(client);
+            Ok(result)
+        }
+        Err(RecvTimeoutError::Timeout) => Err(BaseKind::DeadlineExceeded(operation).into()),
+        Err(RecvTimeoutError::Disconnected) => panic!("Kafka client call {} panicked", operation),
     }
 }
 
@@ -149,23 +346,114 @@ impl Agent for KafkaAgent {
         Ok(info)
     }
 
+    fn cluster_display_name(&self, _: &mut Span) -> Result<Option<String>> {
+        Ok(self.display_name.clone())
+    }
+
     fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
         let cluster = self.zoo.cluster_id(span)?;
         let name = self.jmx.broker_name(span)?;
         let version = self.jmx.broker_version(span)?;
+        check_datastore_version(&self.context, &version);
         Ok(DatastoreInfo::new(cluster, "Kafka", name, version, None))
     }
 
     fn shards(&self, span: &mut Span) -> Result<Shards> {
+        self.shards_partial(span)?.into_shards()
+    }
+
+    fn shards_partial(&self, span: &mut Span) -> Result<PartialShards> {
         let name = self.jmx.broker_name(span)?;
         let broker_id: i32 = name
             .parse::<i32>()
             .with_context(|_| ErrorKind::BrokerIdFormat(name))?;
-        let mut shards = Vec::new();
-        let topics = self.zoo.topics(span)?;
-        for topic in topics {
-            self.push_shard(&mut shards, broker_id, &topic, span)?;
+        let mut partial = PartialShards::new(Shards::new(Vec::new()));
+        let cluster = self.cluster_offsets(span)?;
+        for topic in &cluster.topics {
+            // Collect each topic on its own so a failure does not hide other topics.
+            let mut shards = Vec::new();
+            match self.push_shard(&mut shards, broker_id, topic, &cluster, span) {
+                Ok(()) => partial.shards.shards.extend(shards),
+                // Topic errors use the shard ID format so they are filtered like shards.
+                Err(error) => partial.fail(format!("{}/*", topic), error)?,
+            }
         }
-        Ok(Shards::new(shards))
+        Ok(partial)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::panic::catch_unwind;
+    use std::panic::AssertUnwindSafe;
+
+    use kafka::client::KafkaClient;
+    use kafka::client::PartitionOffset;
+
+    use super::bounded;
+    use super::partition_offsets;
+    use super::sorted_topics;
+
+    /// Kafka client for a broker address nothing listens on.
+    fn unreachable_client() -> KafkaClient {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        KafkaClient::new(vec![address])
+    }
+
+    #[test]
+    fn bounded_returns_client_to_slot() {
+        let mut slot = Some(unreachable_client());
+        let result = bounded(&mut slot, "test", |client| client.hosts().len()).unwrap();
+        assert_eq!(result, 1);
+        assert!(slot.is_some());
+    }
+
+    #[test]
+    fn bounded_panics_empty_the_slot() {
+        let mut slot = Some(unreachable_client());
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            bounded(&mut slot, "test", |_| -> () { panic!("test panic") })
+        }));
+        assert!(result.is_err());
+        assert!(slot.is_none());
+    }
+
+    #[test]
+    fn metadata_errors_keep_the_client() {
+        let mut slot = Some(unreachable_client());
+        let result = bounded(&mut slot, "loadMetadata", |client| {
+            client.load_metadata_all()
+        })
+        .unwrap();
+        assert!(result.is_err());
+        let client = slot.as_ref().expect("Kafka client to be back in its slot");
+        assert!(sorted_topics(client).is_empty());
+    }
+
+    #[test]
+    fn offsets_indexed_by_topic_and_partition() {
+        let mut offsets = HashMap::new();
+        offsets.insert(
+            "orders".to_string(),
+            vec![
+                PartitionOffset {
+                    offset: 42,
+                    partition: 0,
+                },
+                PartitionOffset {
+                    offset: 7,
+                    partition: 1,
+                },
+            ],
+        );
+        offsets.insert("empty".to_string(), Vec::new());
+        let offsets = partition_offsets(offsets);
+        assert_eq!(offsets["orders"][&0], 42);
+        assert_eq!(offsets["orders"][&1], 7);
+        assert!(offsets["empty"].is_empty());
     }
 }
@@ -1,38 +1,51 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering;
-use std::sync::Arc;
-use std::sync::Mutex;
 use std::time::Duration;
 
-use failure::ResultExt;
 use serde::Deserialize;
 use serde::Serialize;
-use slog::debug;
-use slog::error;
-use slog::info;
 use slog::warn;
-use slog::Logger;
 
-use opentracingrust::Log;
 use opentracingrust::Span;
 
-use zookeeper::ZkState;
-use zookeeper::ZooKeeper;
+use zookeeper::Acl;
+use zookeeper::CreateMode;
+use zookeeper::ZkError;
 
 use replicante_agent::fail_span;
+use replicante_agent::spans::child_span;
+use replicante_agent::spans::ChildSpan;
+use replicante_agent::stages::StageTimer;
+use replicante_agent::stages::STAGE_PARSE;
 use replicante_agent::AgentContext;
 use replicante_agent::Result;
+use replicante_agent::ResultExt;
+use replicante_util_failure::failure_info;
+use replicante_zk_helper::ZookeeperClient;
 
 use super::super::error::ErrorKind;
-use super::super::metrics::OPS_COUNT;
-use super::super::metrics::OPS_DURATION;
-use super::super::metrics::OP_ERRORS_COUNT;
-use super::super::metrics::RECONNECT_COUNT;
+use super::client_identity;
 
+const BROKERS_PATH: &str = "/brokers/ids";
 const CLUSTER_ID_PATH: &str = "/cluster/id";
+const DELETE_TOPICS_PATH: &str = "/admin/delete_topics";
+const TOPICS_CONFIG_PATH: &str = "/config/topics";
 const TOPICS_PATH: &str = "/brokers/topics";
 
+/// Human friendly name of a Kafka cluster, from the ZooKeeper chroot path it uses.
+///
+/// Kafka clusters sharing a ZooKeeper ensemble are kept apart by their chroot
+/// (for example `zk1:2181,zk2:2181/kafka/orders`), which operators name after the cluster.
+/// Clusters using the ZooKeeper root have no display name.
+pub fn chroot_display_name(uri: &str) -> Option<String> {
+    let (_, chroot) = uri.split_once('/')?;
+    let chroot = chroot.trim_matches('/');
+    if chroot.is_empty() {
+        return None;
+    }
+    Some(chroot.to_string())
+}
+
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
 struct ClusterId {
     /// Id of the kafka cluster.
@@ -44,49 +57,30 @@ struct ClusterId {
 
 /// Kafka specifics that rely on Zookeeper.
 pub struct KafkaZoo {
+    client: ZookeeperClient,
     context: AgentContext,
-    session: Mutex<ZookeeperSession>,
-    target: String,
-    timeout: Duration,
 }
 
 impl KafkaZoo {
     pub fn connect(context: AgentContext, target: String, timeout: u64) -> Result<KafkaZoo> {
         let timeout = Duration::from_secs(timeout);
-        let session = ZookeeperSession::connect(&target, timeout, context.logger.clone())?;
-        Ok(KafkaZoo {
-            context,
-            session: Mutex::new(session),
-            target,
-            timeout,
-        })
+        let identity = client_identity(&context);
+        let client = ZookeeperClient::connect(target, timeout, identity, context.logger.clone())?;
+        Ok(KafkaZoo { client, context })
     }
 
     /// Fetch the ID of the cluster.
     pub fn cluster_id(&self, parent: &mut Span) -> Result<String> {
-        let mut span = self.context.tracer.span("clusterId").auto_finish();
-        span.child_of(parent.context().clone());
-        span.tag("service", "zookeeper");
-        span.log(Log::new().log("span.kind", "client-send"));
-        let keeper = self
-            .keeper(&mut span)
-            .map_err(|error| fail_span(error, &mut *span))?;
-        OPS_COUNT.with_label_values(&["zookeeper", "getData"]).inc();
-        let timer = OPS_DURATION
-            .with_label_values(&["zookeeper", "getData"])
-            .start_timer();
-        let (id, _) = keeper
-            .get_data(CLUSTER_ID_PATH, false)
-            .map_err(|error| {
-                OP_ERRORS_COUNT
-                    .with_label_values(&["zookeeper", "getData"])
-                    .inc();
-                fail_span(error, &mut *span)
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.cluster_id"))?;
-        timer.observe_duration();
-        span.log(Log::new().log("span.kind", "client-receive"));
-        let id: ClusterId = serde_json::from_slice(&id)
+        let mut span = self.span("clusterId", parent);
+        let (id, _) = self.client.request_or_fail(
+            "<zookeeper>.cluster_id",
+            "getData",
+            &mut span,
+            |keeper| keeper.get_data(CLUSTER_ID_PATH, false),
+        )?;
+        let stages = StageTimer::new("zookeeper", "clusterId");
+        let id: ClusterId = stages
+            .time(STAGE_PARSE, &mut span, |_| serde_json::from_slice(&id))
             .with_context(|_| ErrorKind::JsonDecode("<zookeeper>.cluster_id"))?;
         Ok(id.id)
     }
@@ -98,31 +92,18 @@ impl KafkaZoo {
         topic: &str,
         parent: &mut Span,
     ) -> Result<Vec<PartitionMeta>> {
-        let mut span = self.context.tracer.span("partitions").auto_finish();
-        span.child_of(parent.context().clone());
-        span.tag("service", "zookeeper");
-        span.log(Log::new().log("span.kind", "client-send"));
+        let mut span = self.span("partitions", parent);
         let path = format!("{}/{}", TOPICS_PATH, topic);
-        let keeper = self
-            .keeper(&mut span)
-            .map_err(|error| fail_span(error, &mut *span))?;
-        OPS_COUNT.with_label_values(&["zookeeper", "getData"]).inc();
-        let timer = OPS_DURATION
-            .with_label_values(&["zookeeper", "getData"])
-            .start_timer();
-        let (meta, _) = keeper
-            .get_data(&path, false)
-            .map_err(|error| {
-                OP_ERRORS_COUNT
-                    .with_label_values(&["zookeeper", "getData"])
-                    .inc();
-                fail_span(error, &mut *span)
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.partitions"))?;
-        timer.observe_duration();
-        span.log(Log::new().log("span.kind", "client-receive"));
+        let (meta, _) = self.client.request_or_fail(
+            "<zookeeper>.partitions",
+            "getData",
+            &mut span,
+            |keeper| keeper.get_data(&path, false),
+        )?;
         let mut partitions = Vec::new();
-        let meta: PartitionsMap = serde_json::from_slice(&meta)
+        let stages = StageTimer::new("zookeeper", "partitions");
+        let meta: PartitionsMap = stages
+            .time(STAGE_PARSE, &mut span, |_| serde_json::from_slice(&meta))
             .with_context(|_| ErrorKind::JsonDecode("<zookeeper>.partitions"))?;
         for (partition, brokers) in meta.partitions {
             if !brokers.contains(&broker) {
@@ -142,54 +123,216 @@ impl KafkaZoo {
         }
         Ok(partitions)
     }
+}
 
-    /// Fetch a list of topics in the cluster.
-    pub fn topics(&self, parent: &mut Span) -> Result<Vec<String>> {
-        let mut span = self.context.tracer.span("topics").auto_finish();
-        span.child_of(parent.context().clone());
-        span.tag("service", "zookeeper");
-        span.log(Log::new().log("span.kind", "client-send"));
-        let keeper = self
-            .keeper(&mut span)
-            .map_err(|error| fail_span(error, &mut *span))?;
-        OPS_COUNT
-            .with_label_values(&["zookeeper", "getChildren"])
-            .inc();
-        let timer = OPS_DURATION
-            .with_label_values(&["zookeeper", "getChildren"])
-            .start_timer();
-        let topics = keeper
-            .get_children(TOPICS_PATH, false)
-            .map_err(|error| {
-                OP_ERRORS_COUNT
-                    .with_label_values(&["zookeeper", "getData"])
-                    .inc();
-                fail_span(error, &mut *span)
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.topics"))?;
-        timer.observe_duration();
-        span.log(Log::new().log("span.kind", "client-receive"));
-        Ok(topics)
+/// Topic administration performed the way Kafka's own admin tools do with Zookeeper.
+///
+/// Brokers watch the znodes updated here and apply the changes asynchronously.
+impl KafkaZoo {
+    /// Fetch the IDs of the brokers registered with the cluster, sorted.
+    pub fn brokers(&self, parent: &mut Span) -> Result<Vec<i32>> {
+        let mut span = self.span("brokers", parent);
+        let brokers = self.client.request_or_fail(
+            "<zookeeper>.brokers",
+            "getChildren",
+            &mut span,
+            |keeper| keeper.get_children(BROKERS_PATH, false),
+        )?;
+        let mut ids = Vec::new();
+        for broker in brokers {
+            let id = broker
+                .parse::<i32>()
+                .with_context(|_| ErrorKind::BrokerIdFormat(broker.clone()))?;
+            ids.push(id);
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Register a new topic with its replica assignment and configuration overrides.
+    ///
+    /// Existing topics are rejected before their configuration is touched and
+    /// the configuration is removed again if the topic can't be registered.
+    pub fn create_topic(
+        &self,
+        topic: &str,
+        partitions: &BTreeMap<i32, Vec<i32>>,
+        config: &BTreeMap<String, String>,
+        parent: &mut Span,
+    ) -> Result<()> {
+        let mut span = self.span("createTopic", parent);
+        let topic_path = format!("{}/{}", TOPICS_PATH, topic);
+        let exists = self
+            .client
+            .request_or_fail("<zookeeper>.create_topic", "exists", &mut span, |keeper| {
+                keeper.exists(&topic_path, false)
+            })?
+            .is_some();
+        if exists {
+            let error = format!("topic {} already exists", topic);
+            return Err(ErrorKind::TopicAdmin(error).into());
+        }
+
+        let assignment = serde_json::to_vec(&PartitionsMap::from(partitions))
+            .with_context(|_| ErrorKind::JsonEncode("<zookeeper>.create_topic"))?;
+
+        // Like Kafka's admin tools, write the configuration first so brokers
+        // creating the partitions find it already in place.
+        let config_path = format!("{}/{}", TOPICS_CONFIG_PATH, topic);
+        let data = serde_json::to_vec(&TopicConfig {
+            config: config.clone(),
+            version: 1,
+        })
+        .with_context(|_| ErrorKind::JsonEncode("<zookeeper>.create_topic"))?;
+        let result = self.client.request("create", &mut span, |keeper| {
+            keeper.create(
+                &config_path,
+                data.clone(),
+                Acl::open_unsafe().clone(),
+                CreateMode::Persistent,
+            )
+        })?;
+        // A leftover configuration from a deleted topic is replaced.
+        let result = match result {
+            Err(ZkError::NodeExists) => self
+                .client
+                .request("setData", &mut span, |keeper| {
+                    keeper.set_data(&config_path, data, None)
+                })?
+                .map(drop),
+            result => result.map(drop),
+        };
+        result
+            .map_err(|error| fail_span(error, &mut *span))
+            .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.create_topic"))?;
+
+        let result = self.client.request("create", &mut span, |keeper| {
+            keeper.create(
+                &topic_path,
+                assignment,
+                Acl::open_unsafe().clone(),
+                CreateMode::Persistent,
+            )
+        });
+        let result = match result {
+            // The topic was created concurrently and the configuration now belongs to it.
+            Ok(Err(ZkError::NodeExists)) => {
+                let error = format!("topic {} already exists", topic);
+                return Err(ErrorKind::TopicAdmin(error).into());
+            }
+            Ok(result) => result
+                .map_err(|error| fail_span(error, &mut *span))
+                .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.create_topic")),
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            // Remove the configuration written for the topic that was not created.
+            let cleanup = self.client.request_or_fail(
+                "<zookeeper>.create_topic",
+                "delete",
+                &mut span,
+                |keeper| keeper.delete(&config_path, None),
+            );
+            if let Err(cleanup) = cleanup {
+                warn!(
+                    self.context.logger,
+                    "Failed to remove the configuration of a topic that was not created";
+                    "topic" => topic,
+                    failure_info(&cleanup.to_fail()),
+                );
+            }
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Request the deletion of a topic.
+    ///
+    /// Returns `false` if the topic was already marked for deletion.
+    /// Brokers only delete topics when `delete.topic.enable` is set.
+    pub fn delete_topic(&self, topic: &str, parent: &mut Span) -> Result<bool> {
+        let mut span = self.span("deleteTopic", parent);
+        let path = format!("{}/{}", DELETE_TOPICS_PATH, topic);
+        let result = self.client.request("create", &mut span, |keeper| {
+            keeper.create(
+                &path,
+                Vec::new(),
+                Acl::open_unsafe().clone(),
+                CreateMode::Persistent,
+            )
+        })?;
+        if let Err(ZkError::NodeExists) = result {
+            return Ok(false);
+        }
+        result
+            .map_err(|error| fail_span(error, &mut *span))
+            .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.delete_topic"))?;
+        Ok(true)
+    }
+
+    /// Fetch the replica assignment of a topic, if the topic exists.
+    pub fn topic_assignment(
+        &self,
+        topic: &str,
+        parent: &mut Span,
+    ) -> Result<Option<TopicAssignment>> {
+        let mut span = self.span("topicAssignment", parent);
+        let path = format!("{}/{}", TOPICS_PATH, topic);
+        let result = self
+            .client
+            .request("getData", &mut span, |keeper| keeper.get_data(&path, false))?;
+        if let Err(ZkError::NoNode) = result {
+            return Ok(None);
+        }
+        let (meta, stat) = result
+            .map_err(|error| fail_span(error, &mut *span))
+            .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.topic_assignment"))?;
+        let meta: PartitionsMap = serde_json::from_slice(&meta)
+            .with_context(|_| ErrorKind::JsonDecode("<zookeeper>.topic_assignment"))?;
+        let mut partitions = BTreeMap::new();
+        for (partition, brokers) in meta.partitions {
+            let partition = partition
+                .parse::<i32>()
+                .with_context(|_| ErrorKind::JsonDecode("<zookeeper>.topic_assignment"))?;
+            partitions.insert(partition, brokers);
+        }
+        Ok(Some(TopicAssignment {
+            partitions,
+            version: stat.version,
+        }))
+    }
+
+    /// Replace the replica assignment of a topic.
+    ///
+    /// The update fails if the assignment changed since it was read.
+    pub fn update_assignment(
+        &self,
+        topic: &str,
+        assignment: &TopicAssignment,
+        parent: &mut Span,
+    ) -> Result<()> {
+        let mut span = self.span("updateAssignment", parent);
+        let path = format!("{}/{}", TOPICS_PATH, topic);
+        let data = serde_json::to_vec(&PartitionsMap::from(&assignment.partitions))
+            .with_context(|_| ErrorKind::JsonEncode("<zookeeper>.update_assignment"))?;
+        let result = self.client.request("setData", &mut span, |keeper| {
+            keeper.set_data(&path, data, Some(assignment.version))
+        })?;
+        if let Err(ZkError::BadVersion) = result {
+            let error = format!("assignment of topic {} changed while updating it", topic);
+            return Err(ErrorKind::TopicAdmin(error).into());
+        }
+        result
+            .map_err(|error| fail_span(error, &mut *span))
+            .with_context(|_| ErrorKind::StoreOpFailed("<zookeeper>.update_assignment"))?;
+        Ok(())
     }
 }
 
 impl KafkaZoo {
-    /// Grab a zookeeper session, re-creating it if needed.
-    fn keeper(&self, span: &mut Span) -> Result<Arc<ZooKeeper>> {
-        let mut session = self
-            .session
-            .lock()
-            .expect("Zookeeper session lock was poisoned");
-        if !session.active() {
-            debug!(self.context.logger, "Creating new zookeeper session");
-            span.log(Log::new().log("action", "zookeeper.connect"));
-            RECONNECT_COUNT.with_label_values(&["zookeeper"]).inc();
-            let new_session =
-                ZookeeperSession::connect(&self.target, self.timeout, self.context.logger.clone())?;
-            *session = new_session;
-            info!(self.context.logger, "New zookeeper session ready");
-        }
-        Ok(session.client())
+    /// Start a span for a Zookeeper operation.
+    fn span(&self, name: &str, parent: &mut Span) -> ChildSpan {
+        child_span(&self.context.tracer, name, Some(&*parent))
     }
 }
 
@@ -205,6 +348,16 @@ pub struct PartitionMeta {
     pub replicas: Vec<i32>,
 }
 
+/// Replica assignment of a topic and the version of the znode it was read from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TopicAssignment {
+    /// Map of partition IDs to the IDs of the brokers with a replica, preferred leader first.
+    pub partitions: BTreeMap<i32, Vec<i32>>,
+
+    /// Zookeeper version of the assignment, used to detect concurrent changes.
+    pub version: i32,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct PartitionsMap {
     /// Map of partitions to brokers.
@@ -214,72 +367,44 @@ struct PartitionsMap {
     pub version: i32,
 }
 
-/// Container for a zookeeper session.
-struct ZookeeperSession {
-    active: Arc<AtomicBool>,
-    client: Arc<ZooKeeper>,
+impl From<&BTreeMap<i32, Vec<i32>>> for PartitionsMap {
+    fn from(partitions: &BTreeMap<i32, Vec<i32>>) -> PartitionsMap {
+        let partitions = partitions
+            .iter()
+            .map(|(partition, brokers)| (partition.to_string(), brokers.clone()))
+            .collect();
+        PartitionsMap {
+            partitions,
+            version: 1,
+        }
+    }
 }
 
-impl ZookeeperSession {
-    /// Create a new zookeeper session.
-    pub fn connect(
-        connection: &str,
-        timeout: Duration,
-        logger: Logger,
-    ) -> Result<ZookeeperSession> {
-        let client = ZooKeeper::connect(connection, timeout, |_| {})
-            .with_context(|_| ErrorKind::ZookeeperConnection(connection.to_string()))?;
-        let active = Arc::new(AtomicBool::new(true));
-        let notify_close = Arc::clone(&active);
-        client.add_listener(move |state| {
-            let reset = match state {
-                ZkState::AuthFailed => {
-                    error!(logger, "Zookeeper authentication error");
-                    false
-                }
-                ZkState::Closed => {
-                    warn!(logger, "Zookeeper session closed");
-                    true
-                }
-                ZkState::Connected => {
-                    info!(logger, "Zookeeper connection successfull");
-                    false
-                }
-                ZkState::ConnectedReadOnly => {
-                    warn!(logger, "Zookeeper connection is read-only");
-                    false
-                }
-                ZkState::Connecting => {
-                    debug!(logger, "Zookeeper session connecting");
-                    false
-                }
-                event => {
-                    debug!(logger, "Ignoring deprecated zookeeper event"; "event" => ?event);
-                    false
-                }
-            };
-            if reset {
-                notify_close.store(false, Ordering::Relaxed);
-                debug!(logger, "Zookeeper session marked as not active");
-            }
-        });
-        let client = Arc::new(client);
-        Ok(ZookeeperSession { active, client })
-    }
+/// Configuration overrides of a topic.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct TopicConfig {
+    /// Map of topic configuration options to their values.
+    pub config: BTreeMap<String, String>,
 
-    /// Checks if the session is active.
-    ///
-    /// A session is active if the connection to ZooKeper is intact.
-    ///
-    /// There may be some time while the connection is broken but the session is marked as
-    /// active while the client tries to re-establish the connection.
-    /// If this cannot be done, the session is marked as not active.
-    pub fn active(&self) -> bool {
-        self.active.load(Ordering::Relaxed)
-    }
+    /// Version of the configuration znode format, always 1.
+    pub version: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::chroot_display_name;
 
-    /// Get a reference to the ZooKeeper client for this session.
-    pub fn client(&self) -> Arc<ZooKeeper> {
-        Arc::clone(&self.client)
+    #[test]
+    fn display_name_from_chroot() {
+        assert_eq!(chroot_display_name("zk1:2181,zk2:2181"), None);
+        assert_eq!(chroot_display_name("zk1:2181/"), None);
+        assert_eq!(
+            chroot_display_name("zk1:2181,zk2:2181/kafka/orders"),
+            Some("kafka/orders".into())
+        );
+        assert_eq!(
+            chroot_display_name("zk1:2181/payments/"),
+            Some("payments".into())
+        );
     }
 }
@@ -1,16 +1,13 @@
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
-
-use failure::ResultExt;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::json;
+use serde_json::Value as Json;
 
-use replicante_agent::config::APIConfig;
 use replicante_agent::config::Agent;
+use replicante_agent::config::DiscoveryConfig;
+use replicante_agent::ErrorKind;
 use replicante_agent::Result;
-
-use super::error::ErrorKind;
+use replicante_jmx_helper::JmxConfig;
 
 /// Kafka Agent configuration
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
@@ -24,23 +21,6 @@ pub struct Config {
 }
 
 impl Config {
-    /// Loads the configuration from the given [`std::fs::File`].
-    ///
-    /// [`std::fs::File`]: https://doc.rust-lang.org/std/fs/struct.File.html
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config> {
-        let path_for_error = path.as_ref().to_str().unwrap_or("<utf8 error>").to_string();
-        let config = File::open(path).with_context(|_| ErrorKind::Io(path_for_error))?;
-        Config::from_reader(config)
-    }
-
-    /// Loads the configuration from the given [`std::io::Read`].
-    ///
-    /// [`std::io::Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
-    pub fn from_reader<R: Read>(reader: R) -> Result<Config> {
-        let conf = serde_yaml::from_reader(reader).with_context(|_| ErrorKind::ConfigLoad)?;
-        Ok(conf)
-    }
-
     /// Apply transformations to the configuration to derive some parameters.
     ///
     /// Transvormation:
@@ -50,23 +30,96 @@ impl Config {
         self.agent = self.agent.transform();
         self
     }
+
+    /// Validate the Kafka specific configuration options.
+    ///
+    /// Common agent options are validated by the SDK.
+    pub fn validate(&self) -> Result<()> {
+        let kafka = &self.kafka;
+        kafka.jmx.validate("kafka.jmx")?;
+        for metric in &kafka.jmx_metrics {
+            if metric.name.is_empty() || metric.mbean.is_empty() {
+                let error = "name and mbean can't be empty".to_string();
+                return Err(ErrorKind::ConfigInvalid("kafka.jmx_metrics", error).into());
+            }
+        }
+        if let Some(discovery) = &kafka.target.broker.discovery {
+            discovery.validate("kafka.target.broker.discovery")?;
+        }
+        if kafka.target.broker.timeout == 0 {
+            let error = "must be at least 1 second".to_string();
+            return Err(ErrorKind::ConfigInvalid("kafka.target.broker.timeout", error).into());
+        }
+        if kafka.target.zookeeper.timeout == 0 {
+            let error = "must be at least 1 second".to_string();
+            return Err(ErrorKind::ConfigInvalid("kafka.target.zookeeper.timeout", error).into());
+        }
+        Ok(())
+    }
 }
 
 impl Config {
-    /// Override the base agent default configuration options.
-    pub fn override_defaults() {
-        APIConfig::set_default_bind("127.0.0.1:10092".into())
+    /// Agent specific defaults for the base agent configuration options.
+    pub fn defaults() -> Json {
+        json!({
+            "agent": {
+                "api": {
+                    "bind": "127.0.0.1:10092",
+                },
+            },
+        })
     }
 }
 
 /// Kafka related options.
 #[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct Kafka {
+    /// JMX client options.
+    #[serde(default)]
+    pub jmx: JmxConfig,
+
+    /// Additional JMX MBean attributes to export as agent metrics.
+    ///
+    /// Only the listed MBeans are queried for custom metrics.
+    #[serde(default)]
+    pub jmx_metrics: Vec<JmxMetric>,
+
     /// Addresses used to locate the kafka services.
     #[serde(default)]
     pub target: KafkaTarget,
 }
 
+/// JMX MBean attribute exported as a prometheus gauge.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct JmxMetric {
+    /// Numeric MBean attribute to export.
+    #[serde(default = "JmxMetric::default_attribute")]
+    pub attribute: String,
+
+    /// Help text of the exported metric.
+    #[serde(default = "JmxMetric::default_help")]
+    pub help: String,
+
+    /// MBean key properties to export as metric labels.
+    #[serde(default)]
+    pub labels: Vec<String>,
+
+    /// Name of the MBean to query, or an object name pattern to query all matching MBeans.
+    pub mbean: String,
+
+    /// Name of the exported prometheus metric.
+    pub name: String,
+}
+
+impl JmxMetric {
+    fn default_attribute() -> String {
+        "Value".into()
+    }
+    fn default_help() -> String {
+        "Kafka broker metric exported from JMX".into()
+    }
+}
+
 /// Kafka server listening locations.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct KafkaTarget {
@@ -102,6 +155,13 @@ impl Default for KafkaTarget {
 /// Kafka server location.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct BrokerTarget {
+    /// Discover the address of the Kafka broker to connect to.
+    ///
+    /// When set, the discovered address replaces `uri`.
+    /// Discovery is performed at startup and again when the broker can't be reached.
+    #[serde(default)]
+    pub discovery: Option<DiscoveryConfig>,
+
     /// Addresses "host:port" of the zookeeper ensamble.
     #[serde(default = "BrokerTarget::default_uri")]
     pub uri: String,
@@ -123,6 +183,7 @@ impl BrokerTarget {
 impl Default for BrokerTarget {
     fn default() -> Self {
         BrokerTarget {
+            discovery: None,
             uri: BrokerTarget::default_uri(),
             timeout: BrokerTarget::default_timeout(),
         }
@@ -163,18 +224,45 @@ impl Default for ZookeeperTarget {
 mod tests {
     use std::io::Cursor;
 
+    use replicante_agent::config::ConfigFormat;
+
     use super::Config;
 
     #[test]
     #[should_panic(expected = "invalid type: string")]
     fn from_reader_error() {
         let cursor = Cursor::new("some other text");
-        Config::from_reader(cursor).unwrap();
+        ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
     }
 
     #[test]
     fn from_reader_ok() {
         let cursor = Cursor::new("{agent: {db: test}, kafka: {cluster: test}}");
-        Config::from_reader(cursor).unwrap();
+        ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
+    }
+
+    #[test]
+    fn jmx_metrics_defaults() {
+        let cursor = Cursor::new(
+            "{agent: {db: test}, kafka: {jmx_metrics: [{mbean: 'kafka:type=A', name: a}]}}",
+        );
+        let config = ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
+        let metric = &config.kafka.jmx_metrics[0];
+        assert_eq!(metric.attribute, "Value");
+        assert!(metric.labels.is_empty());
+    }
+
+    #[test]
+    fn validate_defaults() {
+        let cursor = Cursor::new("{agent: {db: test}}");
+        let config = ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn validate_timeouts() {
+        let cursor = Cursor::new("{agent: {db: test}, kafka: {target: {broker: {timeout: 0}}}}");
+        let config = ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
+        assert!(config.validate().is_err());
     }
 }
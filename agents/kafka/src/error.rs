@@ -15,35 +15,29 @@ pub enum ErrorKind {
     /// `FreeForm` wrapper for too many broker IDs in JMX.
     BrokerTooManyIds,
 
-    /// Alias for `ConfigLoad`.
-    ConfigLoad,
-
     /// Alias for `ConfigOption`.
     ConfigOption(&'static str),
 
     /// Alias for `Initialisation`.
     Initialisation(String),
 
-    /// Alias for `Io`.
-    Io(String),
-
-    /// JMX specifc `Connection`.
-    JmxConnection(String),
-
     /// JSON specifc `ResponseDecode`.
     JsonDecode(&'static str),
 
+    /// `FreeForm` wrapper for JSON encoding failures.
+    JsonEncode(&'static str),
+
     /// `InvalidStoreState` wrapper for partitions without brokers.
     PartitionNoBrokers(String),
 
     /// Alias for `StoreOpFailed`.
     StoreOpFailed(&'static str),
 
+    /// `InvalidStoreState` wrapper for topic administration requests that can't be applied.
+    TopicAdmin(String),
+
     /// `FreeForm` wrapper for topics without offset metadata.
     TopicNoOffsets(String),
-
-    /// Zookeeper specifc `Connection`.
-    ZookeeperConnection(String),
 }
 
 impl fmt::Display for ErrorKind {
@@ -65,20 +59,18 @@ impl From<ErrorKind> for BaseKind {
             ErrorKind::BrokerTooManyIds => {
                 BaseKind::FreeForm("too many broker ids reported through JMX metric".into())
             }
-            ErrorKind::ConfigLoad => BaseKind::ConfigLoad,
             ErrorKind::ConfigOption(option) => BaseKind::ConfigOption(option),
             ErrorKind::Initialisation(message) => BaseKind::Initialisation(message),
-            ErrorKind::Io(path) => BaseKind::Io(path),
-            ErrorKind::JmxConnection(address) => BaseKind::Connection("jmx server", address),
             ErrorKind::JsonDecode(op) => BaseKind::ResponseDecode("json", op),
+            ErrorKind::JsonEncode(op) => BaseKind::FreeForm(format!("unable to encode {}", op)),
             ErrorKind::PartitionNoBrokers(partition) => {
                 BaseKind::InvalidStoreState(format!("partition {} has no brokers", partition))
             }
             ErrorKind::StoreOpFailed(op) => BaseKind::StoreOpFailed(op),
+            ErrorKind::TopicAdmin(message) => BaseKind::InvalidStoreState(message),
             ErrorKind::TopicNoOffsets(topic) => {
                 BaseKind::FreeForm(format!("unable to find offsets for topic {}", topic))
             }
-            ErrorKind::ZookeeperConnection(address) => BaseKind::Connection("zookeeper", address),
         }
     }
 }
@@ -1,4 +1,5 @@
 use lazy_static::lazy_static;
+use prometheus::Counter;
 use prometheus::CounterVec;
 use prometheus::HistogramOpts;
 use prometheus::HistogramVec;
@@ -8,10 +9,15 @@ use slog::debug;
 use replicante_agent::AgentContext;
 
 lazy_static! {
+    pub static ref CLIENT_RECOVERIES: Counter = Counter::new(
+        "repliagent_kafka_client_recoveries",
+        "Number of Kafka clients rebuilt after a panic while in use"
+    )
+    .expect("Failed to create CLIENT_RECOVERIES counter");
     pub static ref OP_ERRORS_COUNT: CounterVec = CounterVec::new(
         Opts::new(
             "repliagent_kafka_operation_errors",
-            "Number of Kafka/JMX/Zookeeper operations failed"
+            "Number of Kafka operations failed"
         ),
         &["service", "operation"]
     )
@@ -19,7 +25,7 @@ lazy_static! {
     pub static ref OPS_COUNT: CounterVec = CounterVec::new(
         Opts::new(
             "repliagent_kafka_operations",
-            "Number of Kafka/JMX/Zookeeper operations issued"
+            "Number of Kafka operations issued"
         ),
         &["service", "operation"]
     )
@@ -27,19 +33,11 @@ lazy_static! {
     pub static ref OPS_DURATION: HistogramVec = HistogramVec::new(
         HistogramOpts::new(
             "repliagent_kafka_operations_duration",
-            "Duration (in seconds) of Kafka/JMX/Zookeeper operations"
+            "Duration (in seconds) of Kafka operations"
         ),
         &["service", "operation"]
     )
     .expect("Failed to create OPS_DURATION histogram");
-    pub static ref RECONNECT_COUNT: CounterVec = CounterVec::new(
-        Opts::new(
-            "repliagent_kafka_reconnect",
-            "Number of Kafka/JMX/Zookeeper reconnect operations"
-        ),
-        &["service"]
-    )
-    .expect("Failed to create RECONNECT_COUNT counter");
 }
 
 /// Attemps to register metrics with the Repositoy.
@@ -48,6 +46,9 @@ lazy_static! {
 pub fn register_metrics(context: &AgentContext) {
     let logger = &context.logger;
     let registry = &context.metrics;
+    if let Err(error) = registry.register(Box::new(CLIENT_RECOVERIES.clone())) {
+        debug!(logger, "Failed to register CLIENT_RECOVERIES"; "error" => ?error);
+    }
     if let Err(error) = registry.register(Box::new(OPS_COUNT.clone())) {
         debug!(logger, "Failed to register OPS_COUNT"; "error" => ?error);
     }
@@ -57,7 +58,4 @@ pub fn register_metrics(context: &AgentContext) {
     if let Err(error) = registry.register(Box::new(OPS_DURATION.clone())) {
         debug!(logger, "Failed to register OPS_DURATION"; "error" => ?error);
     }
-    if let Err(error) = registry.register(Box::new(RECONNECT_COUNT.clone())) {
-        debug!(logger, "Failed to register RECONNECT_COUNT"; "error" => ?error);
-    }
 }
@@ -1,3 +1,24 @@
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use mongodb::sync::Client;
+use slog::debug;
+
+use replicante_agent::actions::ACTIONS;
+use replicante_agent::AgentContext;
+
 mod graceful_stop;
+mod replica_set;
 
 pub use self::graceful_stop::GracefulStop;
+pub use self::replica_set::ReplicaSetAddMember;
+pub use self::replica_set::ReplicaSetInitiate;
+pub use self::replica_set::ReplicaSetRemoveMember;
+
+/// Register MongoDB specific actions.
+pub fn register(client: Arc<RwLock<Client>>, context: &AgentContext) {
+    debug!(context.logger, "Registering MongoDB replica set actions");
+    ACTIONS::register(ReplicaSetInitiate::new(Arc::clone(&client)));
+    ACTIONS::register(ReplicaSetAddMember::new(Arc::clone(&client)));
+    ACTIONS::register(ReplicaSetRemoveMember::new(client));
+}
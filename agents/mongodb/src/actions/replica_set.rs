@@ -0,0 +1,577 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use mongodb::bson::doc;
+use mongodb::bson::Bson;
+use mongodb::bson::Document;
+use mongodb::sync::Client;
+use opentracingrust::Span;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::actions::utils::validate_action_args;
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionDescriptor;
+use replicante_agent::actions::ActionRecordView;
+use replicante_agent::actions::ActionState;
+use replicante_agent::actions::ActionValidity;
+use replicante_agent::actions::ActionValidityError;
+use replicante_agent::ErrorKind as BaseKind;
+use replicante_agent::Result;
+use replicante_agent::ResultExt;
+use replicante_agent::Transaction;
+
+use crate::error::ErrorKind;
+use crate::metrics::MONGODB_OPS_COUNT;
+use crate::metrics::MONGODB_OPS_DURATION;
+use crate::metrics::MONGODB_OP_ERRORS_COUNT;
+
+/// MongoDB limits the number of voting members in a replica set.
+const MAX_VOTING_MEMBERS: usize = 7;
+
+/// MongoDB limits the number of members in a replica set.
+const MAX_MEMBERS: usize = 50;
+
+/// Replica set member to add to the configuration.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MemberArgs {
+    /// Address "host:port" of the member.
+    pub host: String,
+
+    /// The member votes in elections but holds no data.
+    #[serde(default)]
+    pub arbiter_only: bool,
+
+    /// The member is not visible to clients (requires a priority of 0).
+    #[serde(default)]
+    pub hidden: bool,
+
+    /// Election priority of the member (MongoDB defaults to 1, or 0 for arbiters).
+    #[serde(default)]
+    pub priority: Option<f64>,
+
+    /// Number of votes of the member: 0 or 1 (MongoDB defaults to 1).
+    #[serde(default)]
+    pub votes: Option<u8>,
+}
+
+impl MemberArgs {
+    /// Check the member settings are accepted by MongoDB.
+    fn validate(&self) -> ActionValidity {
+        validate_host(&self.host)?;
+        if let Some(priority) = self.priority {
+            if !(0.0..=1000.0).contains(&priority) {
+                let error = format!("priority of {} must be between 0 and 1000", self.host);
+                return Err(ActionValidityError::InvalidArgs(error));
+            }
+        }
+        // Check rules against the priority MongoDB applies when none is given.
+        let priority = match self.priority {
+            Some(priority) => priority,
+            None if self.arbiter_only => 0.0,
+            None => 1.0,
+        };
+        if let Some(votes) = self.votes {
+            if votes > 1 {
+                let error = format!("votes of {} must be 0 or 1", self.host);
+                return Err(ActionValidityError::InvalidArgs(error));
+            }
+            if votes == 0 && priority > 0.0 {
+                let error = format!("non-voting member {} must have priority 0", self.host);
+                return Err(ActionValidityError::InvalidArgs(error));
+            }
+        }
+        if self.hidden && priority > 0.0 {
+            let error = format!("hidden member {} must have priority 0", self.host);
+            return Err(ActionValidityError::InvalidArgs(error));
+        }
+        if self.arbiter_only && (self.hidden || self.priority.unwrap_or(0.0) > 0.0) {
+            let error = format!("arbiter {} can't be hidden or have a priority", self.host);
+            return Err(ActionValidityError::InvalidArgs(error));
+        }
+        Ok(())
+    }
+
+    /// Check if the member will vote in elections.
+    fn voting(&self) -> bool {
+        self.votes.unwrap_or(1) > 0
+    }
+
+    /// Encode the member for a replica set configuration document.
+    fn to_document(&self, id: i32) -> Document {
+        let mut member = doc! {
+            "_id": id,
+            "host": &self.host,
+        };
+        if self.arbiter_only {
+            member.insert("arbiterOnly", true);
+        }
+        if self.hidden {
+            member.insert("hidden", true);
+        }
+        if let Some(priority) = self.priority {
+            member.insert("priority", priority);
+        }
+        if let Some(votes) = self.votes {
+            member.insert("votes", i32::from(votes));
+        }
+        member
+    }
+}
+
+/// Arguments of the replica set initiate action.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct InitiateArgs {
+    /// Initial members of the replica set.
+    members: Vec<MemberArgs>,
+
+    /// Name of the replica set, which must match the `--replSet` option of the nodes.
+    set: String,
+}
+
+impl InitiateArgs {
+    fn validate(&self) -> ActionValidity {
+        if self.set.is_empty() {
+            let error = "the replica set name can't be empty".to_string();
+            return Err(ActionValidityError::InvalidArgs(error));
+        }
+        if self.members.is_empty() {
+            let error = "at least one member is required".to_string();
+            return Err(ActionValidityError::InvalidArgs(error));
+        }
+        if self.members.len() > MAX_MEMBERS {
+            let error = format!("replica sets can have at most {} members", MAX_MEMBERS);
+            return Err(ActionValidityError::InvalidArgs(error));
+        }
+        let mut hosts = HashSet::new();
+        for member in &self.members {
+            member.validate()?;
+            if !hosts.insert(member.host.as_str()) {
+                let error = format!("member {} is listed more than once", member.host);
+                return Err(ActionValidityError::InvalidArgs(error));
+            }
+        }
+        let voting = self.members.iter().filter(|member| member.voting()).count();
+        if voting == 0 || voting > MAX_VOTING_MEMBERS {
+            let error = format!(
+                "replica sets need between 1 and {} voting members",
+                MAX_VOTING_MEMBERS
+            );
+            return Err(ActionValidityError::InvalidArgs(error));
+        }
+        Ok(())
+    }
+}
+
+/// Arguments of the replica set member remove action.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct RemoveArgs {
+    /// Address "host:port" of the member to remove.
+    host: String,
+}
+
+/// Initiate a new replica set with `replSetInitiate`.
+pub struct ReplicaSetInitiate {
+    client: Arc<RwLock<Client>>,
+}
+
+impl ReplicaSetInitiate {
+    pub fn new(client: Arc<RwLock<Client>>) -> ReplicaSetInitiate {
+        ReplicaSetInitiate { client }
+    }
+}
+
+impl Action for ReplicaSetInitiate {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "mongodb.com/replica_set.initiate".into(),
+            description: "Initiate a new replica set with the given members".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args: InitiateArgs = decode_args(record)?;
+        let members: Vec<Document> = args
+            .members
+            .iter()
+            .zip(0..)
+            .map(|(member, id)| member.to_document(id))
+            .collect();
+        let config = doc! {
+            "_id": &args.set,
+            "members": members,
+        };
+        let client = client(&self.client);
+        run_admin_command(
+            &client,
+            "replSetInitiate",
+            doc! { "replSetInitiate": config },
+        )?;
+        let hosts: Vec<&str> = args
+            .members
+            .iter()
+            .map(|member| member.host.as_str())
+            .collect();
+        let payload = json!({
+            "members": hosts,
+            "set": args.set,
+        });
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        let args: InitiateArgs = validate_action_args(args.clone())?;
+        args.validate()
+    }
+}
+
+/// Add a member to the replica set with `replSetReconfig`.
+pub struct ReplicaSetAddMember {
+    client: Arc<RwLock<Client>>,
+}
+
+impl ReplicaSetAddMember {
+    pub fn new(client: Arc<RwLock<Client>>) -> ReplicaSetAddMember {
+        ReplicaSetAddMember { client }
+    }
+}
+
+impl Action for ReplicaSetAddMember {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "mongodb.com/replica_set.member.add".into(),
+            description: "Add a member to the replica set (must run on the primary)".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let member: MemberArgs = decode_args(record)?;
+        let client = client(&self.client);
+        let mut config = replica_set_config(&client)?;
+        let mut members = config_members(&config)?;
+        if members
+            .iter()
+            .any(|existing| member_host(existing) == Some(&member.host))
+        {
+            let error = format!("{} is already a member of the replica set", member.host);
+            return Err(ErrorKind::ReplicaSetConfig(error).into());
+        }
+        if members.len() >= MAX_MEMBERS {
+            let error = format!("replica sets can have at most {} members", MAX_MEMBERS);
+            return Err(ErrorKind::ReplicaSetConfig(error).into());
+        }
+        let voting = members
+            .iter()
+            .filter(|member| member_voting(member))
+            .count();
+        if member.voting() && voting >= MAX_VOTING_MEMBERS {
+            let error = format!(
+                "replica sets can have at most {} voting members",
+                MAX_VOTING_MEMBERS
+            );
+            return Err(ErrorKind::ReplicaSetConfig(error).into());
+        }
+        let id = members
+            .iter()
+            .filter_map(|member| member.get_i32("_id").ok())
+            .max()
+            .map(|id| id + 1)
+            .unwrap_or(0);
+        members.push(member.to_document(id));
+        let version = reconfig(&client, &mut config, members)?;
+        let payload = json!({
+            "added": member.host,
+            "version": version,
+        });
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        let member: MemberArgs = validate_action_args(args.clone())?;
+        member.validate()
+    }
+}
+
+/// Remove a member from the replica set with `replSetReconfig`.
+pub struct ReplicaSetRemoveMember {
+    client: Arc<RwLock<Client>>,
+}
+
+impl ReplicaSetRemoveMember {
+    pub fn new(client: Arc<RwLock<Client>>) -> ReplicaSetRemoveMember {
+        ReplicaSetRemoveMember { client }
+    }
+}
+
+impl Action for ReplicaSetRemoveMember {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "mongodb.com/replica_set.member.remove".into(),
+            description: "Remove a member from the replica set (must run on the primary)".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args: RemoveArgs = decode_args(record)?;
+        let client = client(&self.client);
+        let mut config = replica_set_config(&client)?;
+        let mut members = config_members(&config)?;
+        let count = members.len();
+        members.retain(|member| member_host(member) != Some(&args.host));
+        if members.len() == count {
+            let error = format!("{} is not a member of the replica set", args.host);
+            return Err(ErrorKind::ReplicaSetConfig(error).into());
+        }
+        if !members.iter().any(member_voting) {
+            let error = format!("removing {} would leave no voting members", args.host);
+            return Err(ErrorKind::ReplicaSetConfig(error).into());
+        }
+        let version = reconfig(&client, &mut config, members)?;
+        let payload = json!({
+            "removed": args.host,
+            "version": version,
+        });
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        let args: RemoveArgs = validate_action_args(args.clone())?;
+        validate_host(&args.host)
+    }
+}
+
+/// Access the current MongoDB client.
+fn client(client: &Arc<RwLock<Client>>) -> Client {
+    client
+        .read()
+        .expect("MongoDB client lock was poisoned")
+        .clone()
+}
+
+/// Extract the members from a replica set configuration.
+fn config_members(config: &Document) -> Result<Vec<Document>> {
+    let members = config
+        .get_array("members")
+        .with_context(|_| ErrorKind::BsonDecode("replSetGetConfig"))?
+        .iter()
+        .filter_map(Bson::as_document)
+        .cloned()
+        .collect();
+    Ok(members)
+}
+
+/// Decode the arguments of an action record.
+fn decode_args<T>(record: &dyn ActionRecordView) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let args =
+        serde_json::from_value(record.args().clone()).with_context(|_| BaseKind::ActionDecode)?;
+    Ok(args)
+}
+
+fn member_host(member: &Document) -> Option<&String> {
+    match member.get("host") {
+        Some(Bson::String(host)) => Some(host),
+        _ => None,
+    }
+}
+
+fn member_voting(member: &Document) -> bool {
+    match member.get("votes") {
+        Some(Bson::Int32(votes)) => *votes > 0,
+        Some(Bson::Int64(votes)) => *votes > 0,
+        Some(Bson::Double(votes)) => *votes > 0.0,
+        _ => true,
+    }
+}
+
+/// Replace the members of the replica set and apply the new configuration.
+///
+/// Returns the version of the applied configuration.
+fn reconfig(client: &Client, config: &mut Document, members: Vec<Document>) -> Result<i64> {
+    let version = match config.get("version") {
+        Some(Bson::Int32(version)) => i64::from(*version),
+        Some(Bson::Int64(version)) => *version,
+        _ => return Err(ErrorKind::BsonDecode("replSetGetConfig").into()),
+    };
+    let version = version + 1;
+    config.insert("members", members);
+    config.insert("version", version);
+    let command = doc! { "replSetReconfig": config.clone() };
+    run_admin_command(client, "replSetReconfig", command)?;
+    Ok(version)
+}
+
+/// Fetch the current replica set configuration.
+fn replica_set_config(client: &Client) -> Result<Document> {
+    let response = run_admin_command(client, "replSetGetConfig", doc! { "replSetGetConfig": 1 })?;
+    let config = response
+        .get_document("config")
+        .with_context(|_| ErrorKind::BsonDecode("replSetGetConfig"))?
+        .clone();
+    Ok(config)
+}
+
+/// Run a command against the admin database, tracking it with the operation metrics.
+fn run_admin_command(client: &Client, op: &'static str, command: Document) -> Result<Document> {
+    MONGODB_OPS_COUNT.with_label_values(&[op]).inc();
+    let timer = MONGODB_OPS_DURATION.with_label_values(&[op]).start_timer();
+    let response = client
+        .database("admin")
+        .run_command(command, None)
+        .map_err(|error| {
+            MONGODB_OP_ERRORS_COUNT.with_label_values(&[op]).inc();
+            error
+        })
+        .with_context(|_| ErrorKind::StoreOpFailed(op))?;
+    timer.observe_duration();
+    Ok(response)
+}
+
+/// Check a member address is in the "host:port" format.
+fn validate_host(host: &str) -> ActionValidity {
+    let valid = match host.rsplit_once(':') {
+        Some((name, port)) => !name.is_empty() && port.parse::<u16>().is_ok(),
+        None => false,
+    };
+    if !valid {
+        let error = format!("member address '{}' is not in the host:port format", host);
+        return Err(ActionValidityError::InvalidArgs(error));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+    use serde_json::json;
+
+    use replicante_agent::actions::utils::validate_action_args;
+
+    use super::InitiateArgs;
+    use super::MemberArgs;
+
+    fn member(host: &str) -> MemberArgs {
+        MemberArgs {
+            host: host.into(),
+            arbiter_only: false,
+            hidden: false,
+            priority: None,
+            votes: None,
+        }
+    }
+
+    #[test]
+    fn initiate_duplicate_members() {
+        let args = InitiateArgs {
+            members: vec![member("mongo1:27017"), member("mongo1:27017")],
+            set: "rs0".into(),
+        };
+        assert!(args.validate().is_err());
+    }
+
+    #[test]
+    fn initiate_too_many_voters() {
+        let members = (0..8).map(|id| member(&format!("mongo{}:27017", id)));
+        let args = InitiateArgs {
+            members: members.collect(),
+            set: "rs0".into(),
+        };
+        assert!(args.validate().is_err());
+    }
+
+    #[test]
+    fn member_args_unknown_field() {
+        let args = json!({"host": "mongo1:27017", "slaveDelay": 10});
+        assert!(validate_action_args::<MemberArgs>(args).is_err());
+    }
+
+    #[test]
+    fn member_hidden_needs_priority_zero() {
+        let hidden = MemberArgs {
+            hidden: true,
+            ..member("mongo1:27017")
+        };
+        assert!(hidden.validate().is_err());
+        let hidden = MemberArgs {
+            priority: Some(0.0),
+            ..hidden
+        };
+        assert!(hidden.validate().is_ok());
+    }
+
+    #[test]
+    fn member_host_format() {
+        assert!(member("mongo1").validate().is_err());
+        assert!(member(":27017").validate().is_err());
+        assert!(member("mongo1:port").validate().is_err());
+        assert!(member("[::1]:27017").validate().is_ok());
+    }
+
+    #[test]
+    fn member_non_voting_needs_priority_zero() {
+        let non_voting = MemberArgs {
+            votes: Some(0),
+            ..member("mongo1:27017")
+        };
+        assert!(non_voting.validate().is_err());
+        let non_voting = MemberArgs {
+            priority: Some(1.0),
+            ..non_voting
+        };
+        assert!(non_voting.validate().is_err());
+        let non_voting = MemberArgs {
+            priority: Some(0.0),
+            ..non_voting
+        };
+        assert!(non_voting.validate().is_ok());
+    }
+
+    #[test]
+    fn member_to_document() {
+        let arbiter = MemberArgs {
+            arbiter_only: true,
+            ..member("mongo3:27017")
+        };
+        assert_eq!(
+            arbiter.to_document(2),
+            doc! {"_id": 2, "host": "mongo3:27017", "arbiterOnly": true}
+        );
+    }
+}
@@ -1,17 +1,14 @@
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
-
-use failure::ResultExt;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::json;
+use serde_json::Value as Json;
 
-use replicante_agent::config::APIConfig;
 use replicante_agent::config::Agent;
+use replicante_agent::config::DiscoveryConfig;
+use replicante_agent::config::PoolConfig;
+use replicante_agent::ErrorKind;
 use replicante_agent::Result;
 
-use super::error::ErrorKind;
-
 /// MongoDB Agent configuration
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct Config {
@@ -24,23 +21,6 @@ pub struct Config {
 }
 
 impl Config {
-    /// Loads the configuration from the given [`std::fs::File`].
-    ///
-    /// [`std::fs::File`]: https://doc.rust-lang.org/std/fs/struct.File.html
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config> {
-        let path_for_error = path.as_ref().to_str().unwrap_or("<utf8 error>").to_string();
-        let config = File::open(path).with_context(|_| ErrorKind::Io(path_for_error))?;
-        Config::from_reader(config)
-    }
-
-    /// Loads the configuration from the given [`std::io::Read`].
-    ///
-    /// [`std::io::Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
-    pub fn from_reader<R: Read>(reader: R) -> Result<Config> {
-        let conf = serde_yaml::from_reader(reader).with_context(|_| ErrorKind::ConfigLoad)?;
-        Ok(conf)
-    }
-
     /// Apply transformations to the configuration to derive some parameters.
     ///
     /// Transvormation:
@@ -51,6 +31,33 @@ impl Config {
         self
     }
 
+    /// Validate the MongoDB specific configuration options.
+    ///
+    /// Common agent options are validated by the SDK.
+    pub fn validate(&self) -> Result<()> {
+        let mongo = &self.mongo;
+        crate::version::app_name(&self.agent)?;
+        mongo.pool.validate("mongo.pool")?;
+        if let Some(discovery) = &mongo.discovery {
+            discovery.validate("mongo.discovery")?;
+        }
+        if mongo.host_select_timeout == 0 {
+            let error = "must be at least 1 millisecond".to_string();
+            return Err(ErrorKind::ConfigInvalid("mongo.host_select_timeout", error).into());
+        }
+        if mongo.timeouts.fast == 0 || mongo.timeouts.heavy == 0 {
+            let error = "fast and heavy must be at least 1 millisecond".to_string();
+            return Err(ErrorKind::ConfigInvalid("mongo.timeouts", error).into());
+        }
+        if let Some(sharding) = &mongo.sharding {
+            if sharding.cluster_name.is_empty() {
+                let error = "cluster_name can't be empty".to_string();
+                return Err(ErrorKind::ConfigInvalid("mongo.sharding", error).into());
+            }
+        }
+        Ok(())
+    }
+
     /// Return a mocked configuration.
     #[cfg(test)]
     pub fn mock() -> Config {
@@ -62,19 +69,36 @@ impl Config {
 }
 
 impl Config {
-    /// Override the base agent default configuration options.
-    pub fn override_defaults() {
-        APIConfig::set_default_bind("127.0.0.1:37017".into())
+    /// Agent specific defaults for the base agent configuration options.
+    pub fn defaults() -> Json {
+        json!({
+            "agent": {
+                "api": {
+                    "bind": "127.0.0.1:37017",
+                },
+            },
+        })
     }
 }
 
 /// MongoDB related options.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct MongoDB {
+    /// Discover the address of the MongoDB node to connect to.
+    ///
+    /// When set, the discovered address replaces the host(s) in `uri`.
+    /// Discovery is performed at startup and again when the node can't be reached.
+    #[serde(default)]
+    pub discovery: Option<DiscoveryConfig>,
+
     /// Timeout (in milliseconds) for selecting an appropriate server for operations.
     #[serde(default = "MongoDB::default_host_select_timeout")]
     pub host_select_timeout: u64,
 
+    /// Connection pool sizing for the MongoDB client.
+    #[serde(default)]
+    pub pool: PoolConfig,
+
     /// MongoDB connection URI.
     #[serde(default = "MongoDB::default_uri")]
     pub uri: String,
@@ -82,14 +106,26 @@ pub struct MongoDB {
     /// Configure MongoDB sharding mode.
     #[serde(default)]
     pub sharding: Option<Sharding>,
+
+    /// Report long-running operations from `currentOp` (disabled by default).
+    #[serde(default)]
+    pub slow_ops: Option<SlowOps>,
+
+    /// Time limits for commands, by class of operation.
+    #[serde(default)]
+    pub timeouts: Timeouts,
 }
 
 impl Default for MongoDB {
     fn default() -> Self {
         MongoDB {
+            discovery: None,
             host_select_timeout: Self::default_host_select_timeout(),
+            pool: PoolConfig::default(),
             uri: Self::default_uri(),
             sharding: None,
+            slow_ops: None,
+            timeouts: Timeouts::default(),
         }
     }
 }
@@ -131,22 +167,115 @@ impl Sharding {
     }
 }
 
+/// Configure reporting of long-running operations.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct SlowOps {
+    /// Maximum number of operations to report.
+    #[serde(default = "SlowOps::default_limit")]
+    pub limit: usize,
+
+    /// Report operations running for at least this many seconds.
+    #[serde(default = "SlowOps::default_threshold")]
+    pub threshold: u64,
+}
+
+impl Default for SlowOps {
+    fn default() -> Self {
+        SlowOps {
+            limit: Self::default_limit(),
+            threshold: Self::default_threshold(),
+        }
+    }
+}
+
+impl SlowOps {
+    /// Default value for `limit` used by serde.
+    fn default_limit() -> usize {
+        100
+    }
+
+    /// Default value for `threshold` used by serde.
+    fn default_threshold() -> u64 {
+        10
+    }
+}
+
+/// Time limits (in milliseconds) MongoDB can spend on commands, by class of operation.
+///
+/// Limits are sent to MongoDB as `maxTimeMS` so a slow command can't tie up
+/// the agent's connections for longer than expected.
+/// Requests with an earlier deadline are bound by their deadline instead.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Timeouts {
+    /// Limit for fast-path commands (`buildInfo`, `isMaster`, ...).
+    #[serde(default = "Timeouts::default_fast")]
+    pub fast: u64,
+
+    /// Limit for heavier commands (`replSetGetStatus`, `listDatabases`, ...).
+    #[serde(default = "Timeouts::default_heavy")]
+    pub heavy: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            fast: Self::default_fast(),
+            heavy: Self::default_heavy(),
+        }
+    }
+}
+
+impl Timeouts {
+    /// Default value for `fast` used by serde.
+    fn default_fast() -> u64 {
+        1000
+    }
+
+    /// Default value for `heavy` used by serde.
+    fn default_heavy() -> u64 {
+        5000
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
 
+    use replicante_agent::config::ConfigFormat;
+
     use super::Config;
 
     #[test]
     #[should_panic(expected = "invalid type: string")]
     fn from_reader_error() {
         let cursor = Cursor::new("some other text");
-        Config::from_reader(cursor).unwrap();
+        ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
     }
 
     #[test]
     fn from_reader_ok() {
         let cursor = Cursor::new("agent: {db: 'test.db'}");
-        Config::from_reader(cursor).unwrap();
+        ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
+    }
+
+    #[test]
+    fn validate_defaults() {
+        Config::mock().validate().unwrap();
+    }
+
+    #[test]
+    fn validate_pool() {
+        let mut config = Config::mock();
+        config.mongo.pool.max_size = 0;
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.kind().code(), "ConfigInvalid");
+    }
+
+    #[test]
+    fn validate_timeouts() {
+        let mut config = Config::mock();
+        config.mongo.timeouts.heavy = 0;
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.kind().code(), "ConfigInvalid");
     }
 }
@@ -9,9 +9,6 @@ pub enum ErrorKind {
     /// BSON specifc `ResponseDecode`.
     BsonDecode(&'static str),
 
-    /// Alias for `ConfigLoad`.
-    ConfigLoad,
-
     /// Alias for `ConfigOption`.
     ConfigOption(&'static str),
 
@@ -21,15 +18,15 @@ pub enum ErrorKind {
     /// Alias for `Initialisation`.
     Initialisation(String),
 
-    /// Alias for `Io`.
-    Io(String),
-
     /// `InvalidStoreState` caused by the inability to find a primary.
     MembersNoPrimary,
 
     /// `InvalidStoreState` caused by the inability to find self in the replica set.
     MembersNoSelf,
 
+    /// `InvalidStoreState` caused by a replica set configuration change that can't be applied.
+    ReplicaSetConfig(String),
+
     /// Alias for `StoreOpFailed`.
     StoreOpFailed(&'static str),
 
@@ -47,17 +44,16 @@ impl From<ErrorKind> for BaseKind {
     fn from(error: ErrorKind) -> BaseKind {
         match error {
             ErrorKind::BsonDecode(operation) => BaseKind::ResponseDecode("bson", operation),
-            ErrorKind::ConfigLoad => BaseKind::ConfigLoad,
             ErrorKind::ConfigOption(option) => BaseKind::ConfigOption(option),
             ErrorKind::Connection(system, address) => BaseKind::Connection(system, address),
             ErrorKind::Initialisation(message) => BaseKind::Initialisation(message),
-            ErrorKind::Io(path) => BaseKind::Io(path),
             ErrorKind::MembersNoPrimary => {
                 BaseKind::InvalidStoreState("primary node not in members list".into())
             }
             ErrorKind::MembersNoSelf => {
                 BaseKind::InvalidStoreState("self not in members list".into())
             }
+            ErrorKind::ReplicaSetConfig(message) => BaseKind::InvalidStoreState(message),
             ErrorKind::StoreOpFailed(op) => BaseKind::StoreOpFailed(op),
             ErrorKind::UnsupportedSateId(state) => {
                 BaseKind::InvalidStoreState(format!("unsupported node state {}", state))
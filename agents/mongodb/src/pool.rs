@@ -0,0 +1,54 @@
+use mongodb::event::cmap::CmapEventHandler;
+use mongodb::event::cmap::ConnectionCheckedInEvent;
+use mongodb::event::cmap::ConnectionCheckedOutEvent;
+use mongodb::event::cmap::ConnectionCheckoutFailedEvent;
+use mongodb::event::cmap::ConnectionCheckoutStartedEvent;
+use mongodb::event::cmap::ConnectionClosedEvent;
+use mongodb::event::cmap::ConnectionCreatedEvent;
+
+use replicante_agent::pool::PoolMetrics;
+
+/// Report MongoDB driver connection pool events as agent pool metrics.
+pub struct PoolEvents {
+    metrics: PoolMetrics,
+}
+
+impl PoolEvents {
+    pub fn new() -> PoolEvents {
+        PoolEvents {
+            metrics: PoolMetrics::new("mongodb"),
+        }
+    }
+}
+
+impl Default for PoolEvents {
+    fn default() -> PoolEvents {
+        PoolEvents::new()
+    }
+}
+
+impl CmapEventHandler for PoolEvents {
+    fn handle_connection_created_event(&self, _: ConnectionCreatedEvent) {
+        self.metrics.connection_created();
+    }
+
+    fn handle_connection_closed_event(&self, _: ConnectionClosedEvent) {
+        self.metrics.connection_closed();
+    }
+
+    fn handle_connection_checkout_started_event(&self, _: ConnectionCheckoutStartedEvent) {
+        self.metrics.checkout_started();
+    }
+
+    fn handle_connection_checkout_failed_event(&self, _: ConnectionCheckoutFailedEvent) {
+        self.metrics.checkout_failed();
+    }
+
+    fn handle_connection_checked_out_event(&self, _: ConnectionCheckedOutEvent) {
+        self.metrics.checked_out();
+    }
+
+    fn handle_connection_checked_in_event(&self, _: ConnectionCheckedInEvent) {
+        self.metrics.checked_in();
+    }
+}
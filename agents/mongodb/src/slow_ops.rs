@@ -0,0 +1,278 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::thread;
+
+use actix_web::web;
+use actix_web::web::ServiceConfig;
+use actix_web::HttpResponse;
+use mongodb::bson::doc;
+use mongodb::bson::Bson;
+use mongodb::bson::Document;
+use mongodb::sync::Client;
+use prometheus::core::Collector;
+use prometheus::core::Desc;
+use prometheus::proto::MetricFamily;
+use prometheus::IntGauge;
+use serde::Serialize;
+use serde_json::json;
+use slog::debug;
+use slog::Logger;
+
+use replicante_agent::AgentContext;
+use replicante_agent::Result;
+use replicante_agent::ResultExt;
+use replicante_util_failure::failure_info;
+
+use crate::config::SlowOps as SlowOpsConfig;
+use crate::config::Timeouts;
+use crate::error::ErrorKind;
+use crate::metrics::MONGODB_OPS_COUNT;
+use crate::metrics::MONGODB_OPS_DURATION;
+use crate::metrics::MONGODB_OP_ERRORS_COUNT;
+use crate::version::with_deadline;
+
+/// Long-running operation reported by `currentOp`.
+///
+/// Only details needed to identify the operation are reported: command arguments,
+/// queries and client information may contain sensitive data and are never included.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct SlowOperation {
+    pub command: Option<String>,
+    pub desc: Option<String>,
+    pub ns: Option<String>,
+    pub op: Option<String>,
+    pub opid: Option<String>,
+    pub plan_summary: Option<String>,
+    pub secs_running: i64,
+}
+
+impl SlowOperation {
+    /// Extract the reportable details of an operation from a `currentOp` entry.
+    fn from_document(operation: &Document) -> SlowOperation {
+        let string = |key: &str| operation.get_str(key).ok().map(String::from);
+        let command = operation
+            .get_document("command")
+            .ok()
+            .and_then(|command| command.keys().next().cloned());
+        let opid = match operation.get("opid") {
+            Some(Bson::Int32(opid)) => Some(opid.to_string()),
+            Some(Bson::Int64(opid)) => Some(opid.to_string()),
+            // Operations on mongos are identified by "shard:opid" strings.
+            Some(Bson::String(opid)) => Some(opid.clone()),
+            _ => None,
+        };
+        let secs_running = match operation.get("secs_running") {
+            Some(Bson::Int32(secs)) => i64::from(*secs),
+            Some(Bson::Int64(secs)) => *secs,
+            Some(Bson::Double(secs)) => *secs as i64,
+            _ => 0,
+        };
+        SlowOperation {
+            command,
+            desc: string("desc"),
+            ns: string("ns"),
+            op: string("op"),
+            opid,
+            plan_summary: string("planSummary"),
+            secs_running,
+        }
+    }
+}
+
+/// Collect long-running operations from the MongoDB node.
+pub struct SlowOps {
+    app_name: String,
+    client: Arc<RwLock<Client>>,
+    config: SlowOpsConfig,
+    timeouts: Timeouts,
+}
+
+impl SlowOps {
+    pub fn new(
+        client: Arc<RwLock<Client>>,
+        app_name: String,
+        config: SlowOpsConfig,
+        timeouts: Timeouts,
+    ) -> SlowOps {
+        SlowOps {
+            app_name,
+            client,
+            config,
+            timeouts,
+        }
+    }
+
+    /// Fetch operations running for longer than the threshold, longest running first.
+    ///
+    /// Operations issued by the agent itself are ignored.
+    /// Returns the total number of slow operations along with up to `limit` of them.
+    pub fn collect(&self) -> Result<(usize, Vec<SlowOperation>)> {
+        let threshold = self.config.threshold as i64;
+        let command = doc! {
+            "currentOp": 1,
+            "active": true,
+            "secs_running": { "$gte": threshold },
+            "appName": { "$ne": &self.app_name },
+        };
+        let command = with_deadline(command, "currentOp", &self.timeouts)?;
+        MONGODB_OPS_COUNT.with_label_values(&["currentOp"]).inc();
+        let timer = MONGODB_OPS_DURATION
+            .with_label_values(&["currentOp"])
+            .start_timer();
+        let client = self
+            .client
+            .read()
+            .expect("MongoDB client lock was poisoned")
+            .clone();
+        let response = client
+            .database("admin")
+            .run_command(command, None)
+            .map_err(|error| {
+                MONGODB_OP_ERRORS_COUNT
+                    .with_label_values(&["currentOp"])
+                    .inc();
+                error
+            })
+            .with_context(|_| ErrorKind::StoreOpFailed("currentOp"))?;
+        timer.observe_duration();
+        let operations = response
+            .get_array("inprog")
+            .with_context(|_| ErrorKind::BsonDecode("currentOp"))?;
+        let mut operations: Vec<SlowOperation> = operations
+            .iter()
+            .filter_map(Bson::as_document)
+            .map(SlowOperation::from_document)
+            .collect();
+        let total = operations.len();
+        operations.sort_by(|left, right| right.secs_running.cmp(&left.secs_running));
+        operations.truncate(self.config.limit);
+        Ok((total, operations))
+    }
+}
+
+/// Prometheus collector counting slow operations.
+///
+/// Metrics are collected by API server workers so the count is refreshed on a background
+/// thread when metrics are collected instead: collections never wait on `currentOp` and
+/// report the count from the latest completed refresh.
+pub struct SlowOpsCollector {
+    count: IntGauge,
+    logger: Logger,
+    refreshing: Arc<AtomicBool>,
+    slow_ops: Arc<SlowOps>,
+}
+
+impl SlowOpsCollector {
+    pub fn new(slow_ops: Arc<SlowOps>, logger: Logger) -> SlowOpsCollector {
+        let count = IntGauge::new(
+            "repliagent_mongodb_slow_operations",
+            "Number of operations running for longer than the slow operations threshold",
+        )
+        .expect("Failed to create slow operations gauge");
+        SlowOpsCollector {
+            count,
+            logger,
+            refreshing: Arc::new(AtomicBool::new(false)),
+            slow_ops,
+        }
+    }
+
+    /// Refresh the count in the background unless a refresh is already running.
+    fn refresh(&self) {
+        if self.refreshing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let count = self.count.clone();
+        let logger = self.logger.clone();
+        let refreshing = Arc::clone(&self.refreshing);
+        let slow_ops = Arc::clone(&self.slow_ops);
+        let spawned = thread::Builder::new()
+            .name("r:a:slow_ops".into())
+            .spawn(move || {
+                match slow_ops.collect() {
+                    Ok((total, _)) => count.set(total as i64),
+                    Err(error) => debug!(
+                        logger,
+                        "Failed to count slow operations";
+                        failure_info(&error.to_fail()),
+                    ),
+                }
+                refreshing.store(false, Ordering::Release);
+            });
+        if let Err(error) = spawned {
+            debug!(self.logger, "Failed to start slow operations count"; "error" => %error);
+            self.refreshing.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl Collector for SlowOpsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.count.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.refresh();
+        self.count.collect()
+    }
+}
+
+/// Mount the slow operations endpoint.
+pub fn configure(config: &mut ServiceConfig, slow_ops: Arc<SlowOps>) {
+    config
+        .app_data(web::Data::from(slow_ops))
+        .route("/slow-ops", web::get().to(responder));
+}
+
+async fn responder(
+    context: web::Data<AgentContext>,
+    slow_ops: web::Data<SlowOps>,
+) -> Result<HttpResponse> {
+    // Run currentOp on the datastore pool so a slow node does not hold API workers.
+    let slow_ops = slow_ops.into_inner();
+    let (total, operations) = context
+        .datastore_pool
+        .call("slow_ops", None, None, move |_| slow_ops.collect())
+        .await?;
+    let response = json!({
+        "operations": operations,
+        "total": total,
+    });
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::SlowOperation;
+
+    #[test]
+    fn redact_operation() {
+        let operation = doc! {
+            "opid": 42,
+            "op": "query",
+            "ns": "app.users",
+            "desc": "conn12",
+            "client": "10.0.0.7:51234",
+            "secs_running": 75_i64,
+            "planSummary": "COLLSCAN",
+            "command": { "find": "users", "filter": { "email": "someone@example.com" } },
+        };
+        let operation = SlowOperation::from_document(&operation);
+        assert_eq!(
+            operation,
+            SlowOperation {
+                command: Some("find".into()),
+                desc: Some("conn12".into()),
+                ns: Some("app.users".into()),
+                op: Some("query".into()),
+                opid: Some("42".into()),
+                plan_summary: Some("COLLSCAN".into()),
+                secs_running: 75,
+            }
+        );
+    }
+}
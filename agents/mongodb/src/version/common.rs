@@ -1,6 +1,18 @@
+use std::convert::TryFrom;
+use std::time::Duration;
+
 use lazy_static::lazy_static;
+use mongodb::bson::Document;
 
+use replicante_agent::deadline;
+use replicante_agent::shards::ExtendedShardRole;
+use replicante_agent::shards::ShardRoles;
+use replicante_agent::Result;
 use replicante_models_agent::info::AgentVersion;
+use replicante_models_agent::info::ShardRole;
+use replicante_models_agent::info::Shards;
+
+use crate::config::Timeouts;
 
 lazy_static! {
     pub static ref AGENT_VERSION: AgentVersion = AgentVersion::new(
@@ -9,3 +21,99 @@ lazy_static! {
         env!("GIT_BUILD_TAINT"),
     );
 }
+
+/// Commands that MongoDB serves cheaply, bound by the `fast` timeout.
+const FAST_COMMANDS: [&str; 4] = ["buildInfo", "hello", "isMaster", "ping"];
+
+/// Bound a command by its operation class timeout and the deadline of the request being served.
+///
+/// Fails without sending the command if the deadline has already expired,
+/// otherwise sets `maxTimeMS` so MongoDB aborts the command once the earliest
+/// of the two limits is reached.
+pub fn with_deadline(
+    mut command: Document,
+    operation: &'static str,
+    timeouts: &Timeouts,
+) -> Result<Document> {
+    deadline::check(operation)?;
+    let mut limit = operation_timeout(operation, timeouts);
+    if let Some(remaining) = deadline::remaining() {
+        limit = limit.min(remaining);
+    }
+    let millis = i64::try_from(limit.as_millis()).unwrap_or(i64::MAX).max(1);
+    command.insert("maxTimeMS", millis);
+    Ok(command)
+}
+
+/// Time limit for a command based on its class of operation.
+///
+/// Commands not known to be fast are considered heavy.
+pub fn operation_timeout(operation: &str, timeouts: &Timeouts) -> Duration {
+    if FAST_COMMANDS.contains(&operation) {
+        Duration::from_millis(timeouts.fast)
+    } else {
+        Duration::from_millis(timeouts.heavy)
+    }
+}
+
+/// Report replica set member states that are not standard shard roles.
+pub fn shards_roles(shards: &Shards) -> ShardRoles {
+    let mut roles = ShardRoles::new();
+    for shard in &shards.shards {
+        if let ShardRole::Unknown(state) = &shard.role {
+            if state == "ARBITER" {
+                roles.insert(shard.id.clone(), ExtendedShardRole::Arbiter);
+            }
+        }
+    }
+    roles
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use mongodb::bson::doc;
+
+    use replicante_agent::shards::ExtendedShardRole;
+    use replicante_models_agent::info::Shard;
+    use replicante_models_agent::info::ShardRole;
+    use replicante_models_agent::info::Shards;
+
+    use super::operation_timeout;
+    use super::shards_roles;
+    use super::with_deadline;
+    use crate::config::Timeouts;
+
+    fn shards(role: ShardRole) -> Shards {
+        Shards::new(vec![Shard::new(String::from("rs0"), role, None, None)])
+    }
+
+    #[test]
+    fn arbiters_have_extended_role() {
+        let roles = shards_roles(&shards(ShardRole::Unknown("ARBITER".into())));
+        assert_eq!(roles.get("rs0"), Some(&ExtendedShardRole::Arbiter));
+    }
+
+    #[test]
+    fn standard_roles_are_not_extended() {
+        assert!(shards_roles(&shards(ShardRole::Primary)).is_empty());
+        assert!(shards_roles(&shards(ShardRole::Secondary)).is_empty());
+        let roles = shards_roles(&shards(ShardRole::Unknown("RECOVERING".into())));
+        assert!(roles.is_empty());
+    }
+
+    #[test]
+    fn timeout_by_operation_class() {
+        let timeouts = Timeouts {
+            fast: 100,
+            heavy: 2000,
+        };
+        let fast = operation_timeout("buildInfo", &timeouts);
+        let heavy = operation_timeout("replSetGetStatus", &timeouts);
+        assert_eq!(fast, Duration::from_millis(100));
+        assert_eq!(heavy, Duration::from_millis(2000));
+        let command = with_deadline(doc! { "isMaster": 1 }, "isMaster", &timeouts).unwrap();
+        assert_eq!(command.get_i64("maxTimeMS").unwrap(), 100);
+    }
+}
@@ -1,98 +1,230 @@
 use std::sync::Arc;
+use std::sync::RwLock;
 use std::time::Duration;
 
-use failure::ResultExt;
+use actix_web::web::ServiceConfig;
 use mongodb::bson::doc;
 use mongodb::options::ClientOptions;
+use mongodb::options::ServerAddress;
 use mongodb::sync::Client;
 use semver::Version;
 use slog::debug;
-use slog::info;
 use slog::warn;
 
+use replicante_agent::config::Agent as AgentConfig;
+use replicante_agent::discovery;
+use replicante_agent::parse_version;
 use replicante_agent::ActiveAgent;
 use replicante_agent::Agent;
 use replicante_agent::AgentContext;
 use replicante_agent::AgentFactory;
 use replicante_agent::Error;
+use replicante_agent::ErrorKind as BaseKind;
 use replicante_agent::Result;
+use replicante_agent::ResultExt;
+use replicante_agent::VersionMap;
 use replicante_models_agent::info::DatastoreInfo;
 use replicante_util_failure::failure_info;
 
 use crate::config::Config;
+use crate::config::MongoDB;
 use crate::config::Sharding;
 use crate::error::ErrorKind;
 use crate::metrics::MONGODB_OPS_COUNT;
 use crate::metrics::MONGODB_OPS_DURATION;
 use crate::metrics::MONGODB_OP_ERRORS_COUNT;
+use crate::pool::PoolEvents;
+use crate::slow_ops::SlowOps;
+use crate::slow_ops::SlowOpsCollector;
 
 mod common;
 mod v3_0;
 mod v3_2;
 
-const MONGODB_MODE_RS: &str = "replica-set";
-const MONGODB_MODE_SHARDED: &str = "sharded-cluster";
+pub use self::common::with_deadline;
+
+/// Agent name used to build the application name the agent identifies itself with to MongoDB.
+const AGENT_NAME: &str = "repliagent-mongodb";
+
+/// Longest application name, in bytes, MongoDB accepts in the connection handshake.
+const APP_NAME_MAX_BYTES: usize = 128;
+
+/// Application name the agent identifies itself with to MongoDB.
+///
+/// Names longer than MongoDB accepts are rejected as invalid `client_identity` options.
+pub fn app_name(config: &AgentConfig) -> Result<String> {
+    let name = config
+        .client_identity
+        .identity(AGENT_NAME, env!("CARGO_PKG_VERSION"));
+    if name.len() > APP_NAME_MAX_BYTES {
+        let error = format!(
+            "the MongoDB appName '{}' is longer than {} bytes",
+            name, APP_NAME_MAX_BYTES,
+        );
+        return Err(BaseKind::ConfigInvalid("client_identity", error).into());
+    }
+    Ok(name)
+}
+
+const MONGODB_MODE_RS: &str = "mongodb/replica-set";
+const MONGODB_MODE_SHARDED: &str = "mongodb/sharded-cluster";
 
 /// An `AgentFactory` that returns a MongoDB 3.2+ Replica Set compatible agent.
 pub struct MongoDBFactory {
-    client: Client,
+    client: Arc<RwLock<Client>>,
+    config: MongoDB,
     context: AgentContext,
-    sharded_mode: bool,
-    sharding: Option<Sharding>,
+    slow_ops: Option<Arc<SlowOps>>,
+    versions: VersionMap<MongoDBFactory>,
 }
 
 impl MongoDBFactory {
     pub fn with_config(config: Config, context: AgentContext) -> Result<MongoDBFactory> {
+        let app_name = app_name(&context.config)?;
+        let client = MongoDBFactory::build_client(&config.mongo, &context)?;
+        let versions = match config.mongo.sharding.clone() {
+            Some(sharding) if sharding.enable => MongoDBFactory::sharded_versions(sharding),
+            _ => MongoDBFactory::rs_versions(),
+        };
+        let client = Arc::new(RwLock::new(client));
+        let slow_ops = config.mongo.slow_ops.clone().map(|slow_ops| {
+            let timeouts = config.mongo.timeouts.clone();
+            let slow_ops = Arc::new(SlowOps::new(
+                Arc::clone(&client),
+                app_name.clone(),
+                slow_ops,
+                timeouts,
+            ));
+            let collector = SlowOpsCollector::new(Arc::clone(&slow_ops), context.logger.clone());
+            if let Err(error) = context.metrics.register(Box::new(collector)) {
+                debug!(
+                    context.logger,
+                    "Failed to register slow operations metric";
+                    "error" => ?error,
+                );
+            }
+            slow_ops
+        });
+        Ok(MongoDBFactory {
+            client,
+            config: config.mongo,
+            context,
+            slow_ops,
+            versions,
+        })
+    }
+
+    /// Create a MongoDB client, discovering the node address if configured to do so.
+    fn build_client(config: &MongoDB, context: &AgentContext) -> Result<Client> {
         // Parse a URI config and set options after.
-        let mut options = ClientOptions::parse(&config.mongo.uri)
+        let mut options = ClientOptions::parse(&config.uri)
             .with_context(|_| ErrorKind::ConfigOption("mongo.uri"))?;
-        options.app_name = "repliagent-mongodb".to_string().into();
-        options.server_selection_timeout =
-            Duration::from_millis(config.mongo.host_select_timeout).into();
+        options.app_name = app_name(&context.config)?.into();
+        options.server_selection_timeout = Duration::from_millis(config.host_select_timeout).into();
+
+        // Replace the hosts in the URI with the discovered address.
+        let mut address = config.uri.clone();
+        if let Some(discovery) = &config.discovery {
+            address = discovery::resolve(discovery, &context.logger)?;
+            let host = ServerAddress::parse(&address)
+                .with_context(|_| ErrorKind::Connection("mongodb", address.clone()))?;
+            options.hosts = vec![host];
+        }
 
         // Ensure the client connects to the configured server and does not discover
         // a remote node to connect to.
         options.direct_connection = true.into();
 
         // Prevent the agent from opening too many connections to mongo.
-        options.max_pool_size = 10.into();
+        config.pool.validate("mongo.pool")?;
+        options.max_pool_size = config.pool.max_size.into();
+        options.min_pool_size = config.pool.min_size.into();
+        if let Some(idle) = config.pool.max_idle_time {
+            options.max_idle_time = Duration::from_secs(idle).into();
+        }
+        options.cmap_event_handler = Some(Arc::new(PoolEvents::new()));
 
         let client = Client::with_options(options)
-            .with_context(|_| ErrorKind::Connection("mongodb", config.mongo.uri.clone()))?;
+            .with_context(|_| ErrorKind::Connection("mongodb", address.clone()))?;
         debug!(
             context.logger,
             "MongoDB client created";
-            "uri" => &config.mongo.uri,
-            "host_select_timeout" => &config.mongo.host_select_timeout,
+            "address" => address,
+            "host_select_timeout" => &config.host_select_timeout,
         );
+        Ok(client)
+    }
 
-        let sharding = config.mongo.sharding;
-        let sharded_mode = sharding.is_some() && sharding.as_ref().unwrap().enable;
-        Ok(MongoDBFactory {
-            client,
-            context,
-            sharded_mode,
-            sharding,
-        })
+    /// Access the current MongoDB client.
+    fn client(&self) -> Client {
+        self.client
+            .read()
+            .expect("MongoDB client lock was poisoned")
+            .clone()
+    }
+
+    /// Shared handle to the MongoDB client, kept up to date on rediscovery.
+    pub fn shared_client(&self) -> Arc<RwLock<Client>> {
+        Arc::clone(&self.client)
+    }
+
+    /// Discover the MongoDB node address again and replace the client.
+    ///
+    /// Does nothing if discovery is not configured.
+    /// Rediscovery is only attempted when the node can't be reached at the current address.
+    fn rediscover(&self) -> Result<()> {
+        if self.config.discovery.is_none() {
+            return Ok(());
+        }
+        let client = MongoDBFactory::build_client(&self.config, &self.context)?;
+        *self
+            .client
+            .write()
+            .expect("MongoDB client lock was poisoned") = client;
+        Ok(())
     }
 }
 
 impl MongoDBFactory {
-    /// Make an agent to be used when a version could not be detected.
-    fn default_agent(&self) -> (Arc<dyn Agent>, &'static str, &'static str) {
-        if self.sharded_mode {
-            let agent = v3_2::Sharded::new(
-                self.sharding.as_ref().unwrap().clone(),
-                self.client.clone(),
-                self.context.clone(),
-            );
-            let agent = Arc::new(agent);
-            (agent, "3.2.0", MONGODB_MODE_SHARDED)
-        } else {
-            let agent = v3_2::ReplicaSet::new(self.client.clone(), self.context.clone());
-            let agent = Arc::new(agent);
-            (agent, "3.2.0", MONGODB_MODE_RS)
-        }
+    /// Map MongoDB versions to replica-set compatible agents.
+    fn rs_versions() -> VersionMap<MongoDBFactory> {
+        let make_v3_2 = |factory: &MongoDBFactory| -> Arc<dyn Agent> {
+            Arc::new(v3_2::ReplicaSet::new(
+                factory.client(),
+                factory.context.clone(),
+                factory.config.timeouts.clone(),
+            ))
+        };
+        VersionMap::new(MONGODB_MODE_RS, "3.2.0", make_v3_2)
+            .version(v3_2::REPLICA_SET_RANGE.clone(), "3.2.0", make_v3_2)
+            .version(
+                v3_0::REPLICA_SET_RANGE.clone(),
+                "3.0.0",
+                |factory: &MongoDBFactory| -> Arc<dyn Agent> {
+                    Arc::new(v3_0::ReplicaSet::new(
+                        factory.client(),
+                        factory.context.clone(),
+                        factory.config.timeouts.clone(),
+                    ))
+                },
+            )
+    }
+
+    /// Map MongoDB versions to sharded-cluster compatible agents.
+    fn sharded_versions(sharding: Sharding) -> VersionMap<MongoDBFactory> {
+        let make_v3_2 = move |factory: &MongoDBFactory| -> Arc<dyn Agent> {
+            Arc::new(v3_2::Sharded::new(
+                sharding.clone(),
+                factory.client(),
+                factory.context.clone(),
+                factory.config.timeouts.clone(),
+            ))
+        };
+        VersionMap::new(MONGODB_MODE_SHARDED, "3.2.0", make_v3_2.clone()).version(
+            v3_2::SHARDED_RANGE.clone(),
+            "3.2.0",
+            make_v3_2,
+        )
     }
 
     /// Fetch the currently running version of MongoDB.
@@ -101,10 +233,11 @@ impl MongoDBFactory {
         let timer = MONGODB_OPS_DURATION
             .with_label_values(&["buildInfo"])
             .start_timer();
+        let command = with_deadline(doc! { "buildInfo": 1 }, "buildInfo", &self.config.timeouts)?;
         let version = self
-            .client
+            .client()
             .database("test")
-            .run_command(doc! { "buildInfo": 1 }, None)
+            .run_command(command, None)
             .map_err(|error| {
                 MONGODB_OP_ERRORS_COUNT
                     .with_label_values(&["buildInfo"])
@@ -116,102 +249,48 @@ impl MongoDBFactory {
         let version = version
             .get_str("version")
             .with_context(|_| ErrorKind::BsonDecode("buildInfo"))?;
-        let version =
-            Version::parse(version).with_context(|_| ErrorKind::BsonDecode("buildInfo"))?;
-        Ok(version)
+        // Enterprise builds report versions like 4.4.18-ent that no semver range matches.
+        parse_version(version)
     }
 
     /// Instantiate a MongoDB agent based on the fetched version.
     ///
     /// If the version could not be determined returns a MongoDB 3.2 agent.
     fn make_agent(&self, version: Result<Version>) -> ActiveAgent {
-        match version {
-            Err(error) => {
-                let (agent, agent_version, mode) = self.default_agent();
-                warn!(
-                    self.context.logger,
-                    "Could not detect MongoDB version, using default agent";
-                    "agent_version" => agent_version,
-                    "mode" => mode,
-                    failure_info(&error),
-                );
-                ActiveAgent::new(agent, "unknown")
-            }
-            Ok(version) => {
-                let (agent, mode) = if self.sharded_mode {
-                    (self.make_sharded(&version), MONGODB_MODE_SHARDED)
-                } else {
-                    (self.make_rs(&version), MONGODB_MODE_RS)
-                };
-                agent
-                    .map(|(agent, agent_version)| {
-                        info!(
-                            self.context.logger,
-                            "Instantiated MongoDB agent";
-                            "agent_version" => agent_version,
-                            "mongo_version" => %version,
-                            "mode" => mode,
-                        );
-                        ActiveAgent::new(agent, version.to_string())
-                    })
-                    // Failed to find a compatible version.
-                    .unwrap_or_else(|| {
-                        let (agent, agent_version, mode) = self.default_agent();
-                        warn!(
-                            self.context.logger,
-                            "Unsupported MongoDB version, using default agent";
-                            "agent_version" => agent_version,
-                            "mongo_version" => %version,
-                            "mode" => mode,
-                        );
-                        ActiveAgent::new(agent, "unknown")
-                    })
-            }
-        }
-    }
-
-    /// Make a replica-set compatible agent, if versions allow it.
-    fn make_rs(&self, version: &Version) -> Option<(Arc<dyn Agent>, &'static str)> {
-        if v3_2::REPLICA_SET_RANGE.matches(version) {
-            let agent = v3_2::ReplicaSet::new(self.client.clone(), self.context.clone());
-            Some((Arc::new(agent), "3.2.0"))
-        } else if v3_0::REPLICA_SET_RANGE.matches(version) {
-            let agent = v3_0::ReplicaSet::new(self.client.clone(), self.context.clone());
-            Some((Arc::new(agent), "3.0.0"))
-        } else {
-            None
-        }
-    }
-
-    /// Make a sharded-cluster compatible agent, if versions allow it.
-    fn make_sharded(&self, version: &Version) -> Option<(Arc<dyn Agent>, &'static str)> {
-        if v3_2::SHARDED_RANGE.matches(version) {
-            let agent = v3_2::Sharded::new(
-                self.sharding.as_ref().unwrap().clone(),
-                self.client.clone(),
-                self.context.clone(),
-            );
-            Some((Arc::new(agent), "3.2.0"))
-        } else {
-            None
-        }
+        self.versions.make(self, version, &self.context.logger)
     }
 }
 
 impl AgentFactory for MongoDBFactory {
     fn make(&self) -> ActiveAgent {
         debug!(self.context.logger, "Instantiating a new MongoDB agent ...");
-        let version = self.mongo_version();
+        let mut version = self.mongo_version();
+        // The discovered address is reused until the node can't be reached at it.
+        if version.is_err() && self.config.discovery.is_some() {
+            match self.rediscover() {
+                Ok(()) => version = self.mongo_version(),
+                Err(error) => warn!(
+                    self.context.logger,
+                    "Failed to discover MongoDB node address, using previous address";
+                    failure_info(&error.to_fail()),
+                ),
+            }
+        }
         self.make_agent(version)
     }
 
     fn should_remake(&self, active: &ActiveAgent, info: &DatastoreInfo) -> bool {
-        let version = active.version_id();
-        version == "unknown" || *version != info.version
+        self.versions.should_remake(active, info)
     }
 
     fn should_remake_on_error(&self, active: &ActiveAgent, _: &Error) -> bool {
-        active.version_id() == "unknown"
+        self.versions.should_remake_on_error(active)
+    }
+
+    fn configure_api(&self, config: &mut ServiceConfig) {
+        if let Some(slow_ops) = &self.slow_ops {
+            crate::slow_ops::configure(config, Arc::clone(slow_ops));
+        }
     }
 }
 
@@ -223,10 +302,20 @@ mod tests {
     use replicante_agent::AgentFactory;
     use replicante_models_agent::info::DatastoreInfo;
 
+    use super::app_name;
     use super::Config;
     use super::ErrorKind;
     use super::MongoDBFactory;
 
+    #[test]
+    fn app_name_too_long() {
+        let mut context = AgentContext::mock();
+        assert!(app_name(&context.config).is_ok());
+        context.config.client_identity.name = Some("a".repeat(200));
+        let error = app_name(&context.config).unwrap_err();
+        assert_eq!(error.kind().code(), "ConfigInvalid");
+    }
+
     #[test]
     fn make_from_error() {
         let context = AgentContext::mock();
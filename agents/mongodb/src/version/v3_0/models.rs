@@ -16,6 +16,20 @@ pub struct ReplSetStatus {
 }
 
 impl ReplSetStatus {
+    /// Human friendly name of the replica set: the set name and its members hosts.
+    ///
+    /// Replica sets are often given generic names (such as `rs0`) that don't tell
+    /// different clusters apart, unlike the hosts of their members.
+    pub fn display_name(&self) -> String {
+        let mut hosts: Vec<&str> = self
+            .members
+            .iter()
+            .map(|member| member.name.as_str())
+            .collect();
+        hosts.sort_unstable();
+        format!("{} ({})", self.set, hosts.join(", "))
+    }
+
     /// Extracts the timestamp (in seconds) of the latest operation.
     pub fn last_op(&self) -> Result<i64> {
         for member in &self.members {
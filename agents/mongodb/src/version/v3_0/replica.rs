@@ -1,6 +1,5 @@
 use std::sync::Arc;
 
-use failure::ResultExt;
 use mongodb::bson::doc;
 use mongodb::bson::Bson;
 use mongodb::sync::Client;
@@ -11,9 +10,15 @@ use slog::error;
 
 use replicante_agent::actions::Action;
 use replicante_agent::actions::ActionHook;
+use replicante_agent::shards::ShardRoles;
+use replicante_agent::spans::child_span;
+use replicante_agent::stages::StageTimer;
+use replicante_agent::stages::STAGE_PARSE;
+use replicante_agent::stages::STAGE_QUERY;
 use replicante_agent::Agent;
 use replicante_agent::AgentContext;
 use replicante_agent::Result;
+use replicante_agent::ResultExt;
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::CommitOffset;
 use replicante_models_agent::info::DatastoreInfo;
@@ -23,10 +28,13 @@ use replicante_models_agent::info::Shards;
 use replicante_util_failure::failure_info;
 
 use crate::actions::GracefulStop;
+use crate::config::Timeouts;
 use crate::error::ErrorKind;
 use crate::metrics::MONGODB_OPS_COUNT;
 use crate::metrics::MONGODB_OPS_DURATION;
 use crate::metrics::MONGODB_OP_ERRORS_COUNT;
+use crate::version::common::shards_roles;
+use crate::version::common::with_deadline;
 use crate::version::common::AGENT_VERSION;
 
 use super::BuildInfo;
@@ -36,45 +44,53 @@ use super::ReplSetStatus;
 pub struct ReplicaSet {
     client: Client,
     context: AgentContext,
+    timeouts: Timeouts,
 }
 
 impl ReplicaSet {
-    pub fn new(client: Client, context: AgentContext) -> ReplicaSet {
-        ReplicaSet { client, context }
+    pub fn new(client: Client, context: AgentContext, timeouts: Timeouts) -> ReplicaSet {
+        ReplicaSet {
+            client,
+            context,
+            timeouts,
+        }
     }
 
     /// Executes the buildInfo command against the DB.
     fn build_info(&self, parent: &mut Span) -> Result<BuildInfo> {
-        let mut span = self.context.tracer.span("buildInfo").auto_finish();
-        span.child_of(parent.context().clone());
+        let mut span = child_span(&self.context.tracer, "buildInfo", Some(&*parent));
         span.log(Log::new().log("span.kind", "client-send"));
         MONGODB_OPS_COUNT.with_label_values(&["buildInfo"]).inc();
         let timer = MONGODB_OPS_DURATION
             .with_label_values(&["buildInfo"])
             .start_timer();
-        let info = self
-            .client
-            .database("test")
-            .run_command(doc! { "buildInfo": 1 }, None)
-            .fail_span(&mut span)
-            .map_err(|error| {
-                MONGODB_OP_ERRORS_COUNT
-                    .with_label_values(&["buildInfo"])
-                    .inc();
-                error
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("buildInfo"))?;
+        let command = with_deadline(doc! { "buildInfo": 1 }, "buildInfo", &self.timeouts)?;
+        let stages = StageTimer::new("mongodb", "buildInfo");
+        let info = stages.time(STAGE_QUERY, &mut span, |span| {
+            self.client
+                .database("test")
+                .run_command(command, None)
+                .fail_span(span)
+                .map_err(|error| {
+                    MONGODB_OP_ERRORS_COUNT
+                        .with_label_values(&["buildInfo"])
+                        .inc();
+                    error
+                })
+                .with_context(|_| ErrorKind::StoreOpFailed("buildInfo"))
+        })?;
         timer.observe_duration();
         span.log(Log::new().log("span.kind", "client-receive"));
-        let info = mongodb::bson::from_bson(Bson::Document(info))
-            .with_context(|_| ErrorKind::BsonDecode("buildInfo"))?;
+        let info = stages.time(STAGE_PARSE, &mut span, |_| {
+            mongodb::bson::from_bson(Bson::Document(info))
+                .with_context(|_| ErrorKind::BsonDecode("buildInfo"))
+        })?;
         Ok(info)
     }
 
     /// Executes the replSetGetStatus command against the DB.
     fn repl_set_get_status(&self, parent: &mut Span) -> Result<ReplSetStatus> {
-        let mut span = self.context.tracer.span("replSetGetStatus").auto_finish();
-        span.child_of(parent.context().clone());
+        let mut span = child_span(&self.context.tracer, "replSetGetStatus", Some(&*parent));
         span.log(Log::new().log("span.kind", "client-send"));
         MONGODB_OPS_COUNT
             .with_label_values(&["replSetGetStatus"])
@@ -82,22 +98,31 @@ impl ReplicaSet {
         let timer = MONGODB_OPS_DURATION
             .with_label_values(&["replSetGetStatus"])
             .start_timer();
-        let status = self
-            .client
-            .database("admin")
-            .run_command(doc! { "replSetGetStatus": 1 }, None)
-            .fail_span(&mut span)
-            .map_err(|error| {
-                MONGODB_OP_ERRORS_COUNT
-                    .with_label_values(&["replSetGetStatus"])
-                    .inc();
-                error
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("replSetGetStatus"))?;
+        let command = with_deadline(
+            doc! { "replSetGetStatus": 1 },
+            "replSetGetStatus",
+            &self.timeouts,
+        )?;
+        let stages = StageTimer::new("mongodb", "replSetGetStatus");
+        let status = stages.time(STAGE_QUERY, &mut span, |span| {
+            self.client
+                .database("admin")
+                .run_command(command, None)
+                .fail_span(span)
+                .map_err(|error| {
+                    MONGODB_OP_ERRORS_COUNT
+                        .with_label_values(&["replSetGetStatus"])
+                        .inc();
+                    error
+                })
+                .with_context(|_| ErrorKind::StoreOpFailed("replSetGetStatus"))
+        })?;
         timer.observe_duration();
         span.log(Log::new().log("span.kind", "client-receive"));
-        let status = mongodb::bson::from_bson(Bson::Document(status))
-            .with_context(|_| ErrorKind::BsonDecode("replSetGetStatus"))?;
+        let status = stages.time(STAGE_PARSE, &mut span, |_| {
+            mongodb::bson::from_bson(Bson::Document(status))
+                .with_context(|_| ErrorKind::BsonDecode("replSetGetStatus"))
+        })?;
         Ok(status)
     }
 }
@@ -117,6 +142,11 @@ impl Agent for ReplicaSet {
         Ok(info)
     }
 
+    fn cluster_display_name(&self, span: &mut Span) -> Result<Option<String>> {
+        let status = self.repl_set_get_status(span)?;
+        Ok(Some(status.display_name()))
+    }
+
     fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
         let info = self.build_info(span)?;
         let status = self.repl_set_get_status(span)?;
@@ -140,7 +170,11 @@ impl Agent for ReplicaSet {
             _ => match status.primary_optime() {
                 Ok(head) => Some(CommitOffset::seconds(head - last_op)),
                 Err(error) => {
-                    error!(self.context.logger, "Failed to compute lag"; failure_info(&error));
+                    error!(
+                        self.context.logger,
+                        "Failed to compute lag";
+                        failure_info(&error.to_fail()),
+                    );
                     span.tag("lag.error", format!("Failed lag computation: {:?}", error));
                     None
                 }
@@ -155,4 +189,8 @@ impl Agent for ReplicaSet {
         )];
         Ok(Shards::new(shards))
     }
+
+    fn shards_roles(&self, shards: &Shards, _: &mut Span) -> Result<ShardRoles> {
+        Ok(shards_roles(shards))
+    }
 }
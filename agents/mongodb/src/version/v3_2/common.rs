@@ -1,4 +1,6 @@
-use failure::ResultExt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
 use mongodb::bson::doc;
 use mongodb::bson::Bson;
 use mongodb::sync::Client;
@@ -7,8 +9,13 @@ use opentracingrust::Log;
 use opentracingrust::Span;
 use slog::error;
 
+use replicante_agent::spans::child_span;
+use replicante_agent::stages::StageTimer;
+use replicante_agent::stages::STAGE_PARSE;
+use replicante_agent::stages::STAGE_QUERY;
 use replicante_agent::AgentContext;
 use replicante_agent::Result;
+use replicante_agent::ResultExt;
 
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::CommitOffset;
@@ -17,24 +24,41 @@ use replicante_models_agent::info::ShardRole;
 use replicante_models_agent::info::Shards;
 use replicante_util_failure::failure_info;
 
+use crate::config::Timeouts;
 use crate::error::ErrorKind;
 use crate::metrics::MONGODB_OPS_COUNT;
 use crate::metrics::MONGODB_OPS_DURATION;
 use crate::metrics::MONGODB_OP_ERRORS_COUNT;
 
+use super::super::common::with_deadline;
 use super::super::common::AGENT_VERSION;
 use super::BuildInfo;
 use super::ReplSetStatus;
+use super::ServerStatus;
+
+/// Collection cache key of the buildInfo result.
+pub(super) const BUILD_INFO_KEY: &str = "mongodb.buildInfo";
+
+/// Collection cache key of the replSetGetStatus result.
+pub(super) const REPL_SET_STATUS_KEY: &str = "mongodb.replSetGetStatus";
+
+/// Collection cache key of the serverStatus result.
+pub(super) const SERVER_STATUS_KEY: &str = "mongodb.serverStatus";
 
 /// MongoDB 3.2+ logic common to both RS and Shareded modes.
 pub struct CommonLogic {
     client: Client,
     context: AgentContext,
+    timeouts: Timeouts,
 }
 
 impl CommonLogic {
-    pub fn new(client: Client, context: AgentContext) -> CommonLogic {
-        CommonLogic { client, context }
+    pub fn new(client: Client, context: AgentContext, timeouts: Timeouts) -> CommonLogic {
+        CommonLogic {
+            client,
+            context,
+            timeouts,
+        }
     }
 
     /// Returns agent information.
@@ -43,31 +67,42 @@ impl CommonLogic {
         Ok(info)
     }
 
+    /// Returns the buildInfo result for the current collection cycle.
+    pub fn build_info(&self, parent: &mut Span) -> Result<Arc<BuildInfo>> {
+        self.context
+            .collection_cache
+            .get_or_fetch(BUILD_INFO_KEY, || self.fetch_build_info(parent))
+    }
+
     /// Executes the buildInfo command against the DB.
-    pub fn build_info(&self, parent: &mut Span) -> Result<BuildInfo> {
-        let mut span = self.context.tracer.span("buildInfo").auto_finish();
-        span.child_of(parent.context().clone());
+    fn fetch_build_info(&self, parent: &mut Span) -> Result<BuildInfo> {
+        let mut span = child_span(&self.context.tracer, "buildInfo", Some(&*parent));
         span.log(Log::new().log("span.kind", "client-send"));
         MONGODB_OPS_COUNT.with_label_values(&["buildInfo"]).inc();
         let timer = MONGODB_OPS_DURATION
             .with_label_values(&["buildInfo"])
             .start_timer();
-        let info = self
-            .client
-            .database("test")
-            .run_command(doc! { "buildInfo": 1 }, None)
-            .fail_span(&mut span)
-            .map_err(|error| {
-                MONGODB_OP_ERRORS_COUNT
-                    .with_label_values(&["buildInfo"])
-                    .inc();
-                error
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("buildInfo"))?;
+        let command = with_deadline(doc! { "buildInfo": 1 }, "buildInfo", &self.timeouts)?;
+        let stages = StageTimer::new("mongodb", "buildInfo");
+        let info = stages.time(STAGE_QUERY, &mut span, |span| {
+            self.client
+                .database("test")
+                .run_command(command, None)
+                .fail_span(span)
+                .map_err(|error| {
+                    MONGODB_OP_ERRORS_COUNT
+                        .with_label_values(&["buildInfo"])
+                        .inc();
+                    error
+                })
+                .with_context(|_| ErrorKind::StoreOpFailed("buildInfo"))
+        })?;
         timer.observe_duration();
         span.log(Log::new().log("span.kind", "client-receive"));
-        let info = mongodb::bson::from_bson(Bson::Document(info))
-            .with_context(|_| ErrorKind::BsonDecode("buildInfo"))?;
+        let info = stages.time(STAGE_PARSE, &mut span, |_| {
+            mongodb::bson::from_bson(Bson::Document(info))
+                .with_context(|_| ErrorKind::BsonDecode("buildInfo"))
+        })?;
         Ok(info)
     }
 
@@ -76,10 +111,21 @@ impl CommonLogic {
         self.client.clone()
     }
 
+    /// Returns the replSetGetStatus result for the current collection cycle.
+    ///
+    /// Datastore info and shards requests from the same collection both need the replica
+    /// set status so the command result is shared through the SDK collection cache.
+    pub fn repl_set_get_status(&self, parent: &mut Span) -> Result<Arc<ReplSetStatus>> {
+        self.context
+            .collection_cache
+            .get_or_fetch(REPL_SET_STATUS_KEY, || {
+                self.fetch_repl_set_get_status(parent)
+            })
+    }
+
     /// Executes the replSetGetStatus command against the DB.
-    pub fn repl_set_get_status(&self, parent: &mut Span) -> Result<ReplSetStatus> {
-        let mut span = self.context.tracer.span("replSetGetStatus").auto_finish();
-        span.child_of(parent.context().clone());
+    fn fetch_repl_set_get_status(&self, parent: &mut Span) -> Result<ReplSetStatus> {
+        let mut span = child_span(&self.context.tracer, "replSetGetStatus", Some(&*parent));
         span.log(Log::new().log("span.kind", "client-send"));
         MONGODB_OPS_COUNT
             .with_label_values(&["replSetGetStatus"])
@@ -87,25 +133,83 @@ impl CommonLogic {
         let timer = MONGODB_OPS_DURATION
             .with_label_values(&["replSetGetStatus"])
             .start_timer();
-        let status = self
-            .client
-            .database("admin")
-            .run_command(doc! { "replSetGetStatus": 1 }, None)
-            .fail_span(&mut span)
-            .map_err(|error| {
-                MONGODB_OP_ERRORS_COUNT
-                    .with_label_values(&["replSetGetStatus"])
-                    .inc();
-                error
-            })
-            .with_context(|_| ErrorKind::StoreOpFailed("replSetGetStatus"))?;
+        let command = with_deadline(
+            doc! { "replSetGetStatus": 1 },
+            "replSetGetStatus",
+            &self.timeouts,
+        )?;
+        let stages = StageTimer::new("mongodb", "replSetGetStatus");
+        let status = stages.time(STAGE_QUERY, &mut span, |span| {
+            self.client
+                .database("admin")
+                .run_command(command, None)
+                .fail_span(span)
+                .map_err(|error| {
+                    MONGODB_OP_ERRORS_COUNT
+                        .with_label_values(&["replSetGetStatus"])
+                        .inc();
+                    error
+                })
+                .with_context(|_| ErrorKind::StoreOpFailed("replSetGetStatus"))
+        })?;
         timer.observe_duration();
         span.log(Log::new().log("span.kind", "client-receive"));
-        let status = mongodb::bson::from_bson(Bson::Document(status))
-            .with_context(|_| ErrorKind::BsonDecode("replSetGetStatus"))?;
+        let status = stages.time(STAGE_PARSE, &mut span, |_| {
+            mongodb::bson::from_bson(Bson::Document(status))
+                .with_context(|_| ErrorKind::BsonDecode("replSetGetStatus"))
+        })?;
         Ok(status)
     }
 
+    /// Returns the serverStatus result for the current collection cycle.
+    pub fn server_status(&self, parent: &mut Span) -> Result<Arc<ServerStatus>> {
+        self.context
+            .collection_cache
+            .get_or_fetch(SERVER_STATUS_KEY, || self.fetch_server_status(parent))
+    }
+
+    /// Executes the serverStatus command against the DB.
+    fn fetch_server_status(&self, parent: &mut Span) -> Result<ServerStatus> {
+        let mut span = child_span(&self.context.tracer, "serverStatus", Some(&*parent));
+        span.log(Log::new().log("span.kind", "client-send"));
+        MONGODB_OPS_COUNT.with_label_values(&["serverStatus"]).inc();
+        let timer = MONGODB_OPS_DURATION
+            .with_label_values(&["serverStatus"])
+            .start_timer();
+        let command = with_deadline(doc! { "serverStatus": 1 }, "serverStatus", &self.timeouts)?;
+        let stages = StageTimer::new("mongodb", "serverStatus");
+        let status = stages.time(STAGE_QUERY, &mut span, |span| {
+            self.client
+                .database("admin")
+                .run_command(command, None)
+                .fail_span(span)
+                .map_err(|error| {
+                    MONGODB_OP_ERRORS_COUNT
+                        .with_label_values(&["serverStatus"])
+                        .inc();
+                    error
+                })
+                .with_context(|_| ErrorKind::StoreOpFailed("serverStatus"))
+        })?;
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        let status = stages.time(STAGE_PARSE, &mut span, |_| {
+            mongodb::bson::from_bson(Bson::Document(status))
+                .with_context(|_| ErrorKind::BsonDecode("serverStatus"))
+        })?;
+        Ok(status)
+    }
+
+    /// Returns the time reported by the MongoD/MongoS instance.
+    ///
+    /// The serverStatus result may be shared with other requests so the time elapsed
+    /// since it was received is added to the reported time to keep clock skew accurate.
+    pub fn datastore_time(&self, span: &mut Span) -> Result<Option<SystemTime>> {
+        let status = self.server_status(span)?;
+        let time = status.local_time.to_system_time() + status.received.elapsed();
+        Ok(Some(time))
+    }
+
     /// Returns shard information from a MongoD instance.
     pub fn shards(&self, span: &mut Span) -> Result<Shards> {
         let status = self.repl_set_get_status(span)?;
@@ -116,13 +220,17 @@ impl CommonLogic {
             _ => match status.primary_optime() {
                 Ok(head) => Some(CommitOffset::seconds(head - last_op)),
                 Err(error) => {
-                    error!(self.context.logger, "Failed to compute lag"; failure_info(&error));
+                    error!(
+                        self.context.logger,
+                        "Failed to compute lag";
+                        failure_info(&error.to_fail()),
+                    );
                     span.tag("lag.error", format!("Failed lag computation: {:?}", error));
                     None
                 }
             },
         };
-        let name = status.set;
+        let name = status.set.clone();
         let shards = vec![Shard::new(
             name,
             role,
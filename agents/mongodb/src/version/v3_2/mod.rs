@@ -13,5 +13,6 @@ lazy_static! {
 
 pub use self::models::BuildInfo;
 pub use self::models::ReplSetStatus;
+pub use self::models::ServerStatus;
 pub use self::replica::ReplicaSet;
 pub use self::sharded::Sharded;
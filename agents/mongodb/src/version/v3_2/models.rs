@@ -1,3 +1,6 @@
+use std::time::Instant;
+
+use mongodb::bson::DateTime;
 use mongodb::bson::Timestamp;
 use serde::Deserialize;
 
@@ -12,6 +15,17 @@ pub struct BuildInfo {
     pub version: String,
 }
 
+/// Section of the serverStatus command that we care about.
+#[derive(Deserialize)]
+pub struct ServerStatus {
+    #[serde(rename = "localTime")]
+    pub local_time: DateTime,
+
+    /// Time the status was received and decoded by the agent.
+    #[serde(skip, default = "Instant::now")]
+    pub received: Instant,
+}
+
 /// Section of the replSetGetStatus command that we care about.
 #[derive(Debug, Deserialize)]
 pub struct ReplSetStatus {
@@ -22,6 +36,20 @@ pub struct ReplSetStatus {
 }
 
 impl ReplSetStatus {
+    /// Human friendly name of the replica set: the set name and its members hosts.
+    ///
+    /// Replica sets are often given generic names (such as `rs0`) that don't tell
+    /// different clusters apart, unlike the hosts of their members.
+    pub fn display_name(&self) -> String {
+        let mut hosts: Vec<&str> = self
+            .members
+            .iter()
+            .map(|member| member.name.as_str())
+            .collect();
+        hosts.sort_unstable();
+        format!("{} ({})", self.set, hosts.join(", "))
+    }
+
     /// Extracts the timestamp (in seconds) of the latest operation.
     pub fn last_op(&self) -> Result<i64> {
         for member in &self.members {
@@ -142,6 +170,12 @@ mod tests {
         })
     }
 
+    #[test]
+    fn display_name() {
+        let rs: ReplSetStatus = bson::from_bson(make_rs()).unwrap();
+        assert_eq!(rs.display_name(), "test-rs (host0, host1)");
+    }
+
     #[test]
     fn last_op() {
         let rs: ReplSetStatus = bson::from_bson(make_rs()).unwrap();
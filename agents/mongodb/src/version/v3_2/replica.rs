@@ -1,10 +1,12 @@
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use mongodb::sync::Client;
 use opentracingrust::Span;
 
 use replicante_agent::actions::Action;
 use replicante_agent::actions::ActionHook;
+use replicante_agent::shards::ShardRoles;
 use replicante_agent::Agent;
 use replicante_agent::AgentContext;
 use replicante_agent::Result;
@@ -12,8 +14,10 @@ use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::DatastoreInfo;
 use replicante_models_agent::info::Shards;
 
+use super::super::common::shards_roles;
 use super::common::CommonLogic;
 use crate::actions::GracefulStop;
+use crate::config::Timeouts;
 
 /// MongoDB 3.2+ replica set agent.
 pub struct ReplicaSet {
@@ -21,8 +25,8 @@ pub struct ReplicaSet {
 }
 
 impl ReplicaSet {
-    pub fn new(client: Client, context: AgentContext) -> ReplicaSet {
-        let common = CommonLogic::new(client, context);
+    pub fn new(client: Client, context: AgentContext, timeouts: Timeouts) -> ReplicaSet {
+        let common = CommonLogic::new(client, context, timeouts);
         ReplicaSet { common }
     }
 }
@@ -39,21 +43,131 @@ impl Agent for ReplicaSet {
         self.common.agent_info(span)
     }
 
+    fn cluster_display_name(&self, span: &mut Span) -> Result<Option<String>> {
+        let status = self.common.repl_set_get_status(span)?;
+        Ok(Some(status.display_name()))
+    }
+
     fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
         let info = self.common.build_info(span)?;
         let status = self.common.repl_set_get_status(span)?;
         let node_name = status.node_name()?;
-        let cluster = status.set;
+        let cluster = status.set.clone();
         Ok(DatastoreInfo::new(
             cluster,
             "MongoDB",
             node_name,
-            info.version,
+            info.version.clone(),
             None,
         ))
     }
 
+    fn datastore_time(&self, span: &mut Span) -> Result<Option<SystemTime>> {
+        self.common.datastore_time(span)
+    }
+
     fn shards(&self, span: &mut Span) -> Result<Shards> {
         self.common.shards(span)
     }
+
+    fn shards_roles(&self, shards: &Shards, _: &mut Span) -> Result<ShardRoles> {
+        Ok(shards_roles(shards))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::time::Instant;
+
+    use mongodb::bson;
+    use mongodb::bson::doc;
+    use mongodb::bson::Bson;
+    use mongodb::bson::DateTime;
+    use mongodb::bson::Timestamp;
+    use mongodb::sync::Client;
+
+    use replicante_agent::Agent;
+    use replicante_agent::AgentContext;
+    use replicante_agent::Result;
+    use replicante_models_agent::info::ShardRole;
+
+    use super::super::common::BUILD_INFO_KEY;
+    use super::super::common::REPL_SET_STATUS_KEY;
+    use super::super::common::SERVER_STATUS_KEY;
+    use super::super::BuildInfo;
+    use super::super::ReplSetStatus;
+    use super::super::ServerStatus;
+    use super::ReplicaSet;
+    use crate::config::Timeouts;
+
+    fn make_rs() -> Result<ReplSetStatus> {
+        let ts = Bson::Timestamp(Timestamp {
+            time: 1514677701,
+            increment: 0,
+        });
+        let status = doc! {
+            "set": "test-rs",
+            "members": [{
+                "_id": 0,
+                "name": "host0",
+                "optime": { "ts": ts },
+                "self": true,
+                "state": 1,
+            }],
+            "myState": 1,
+        };
+        Ok(bson::from_bson(Bson::Document(status)).unwrap())
+    }
+
+    #[test]
+    fn info_and_shards_share_commands() {
+        let context = AgentContext::mock();
+        // Nothing listens on this address: any command sent to it fails.
+        let client =
+            Client::with_uri_str("mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=10").unwrap();
+        let timeouts = Timeouts {
+            fast: 10,
+            heavy: 10,
+        };
+        let agent = ReplicaSet::new(client, context.clone(), timeouts);
+
+        // Simulate the first request of the collection cycle fetching the results.
+        let fetches = Cell::new(0);
+        let cache = &context.collection_cache;
+        cache
+            .get_or_fetch(BUILD_INFO_KEY, || {
+                fetches.set(fetches.get() + 1);
+                Ok(BuildInfo {
+                    version: "3.2.0".into(),
+                })
+            })
+            .unwrap();
+        cache
+            .get_or_fetch(REPL_SET_STATUS_KEY, || {
+                fetches.set(fetches.get() + 1);
+                make_rs()
+            })
+            .unwrap();
+        cache
+            .get_or_fetch(SERVER_STATUS_KEY, || {
+                fetches.set(fetches.get() + 1);
+                Ok(ServerStatus {
+                    local_time: DateTime::from_millis(1514677701000),
+                    received: Instant::now(),
+                })
+            })
+            .unwrap();
+
+        let mut span = context.tracer.span("test");
+        let info = agent.datastore_info(&mut span).unwrap();
+        assert_eq!(info.cluster_id, "test-rs");
+        assert_eq!(info.node_id, "host0");
+        assert_eq!(info.version, "3.2.0");
+        let shards = agent.shards(&mut span).unwrap();
+        assert_eq!(shards.shards[0].id, "test-rs");
+        assert_eq!(shards.shards[0].role, ShardRole::Primary);
+        assert!(agent.datastore_time(&mut span).unwrap().is_some());
+        assert_eq!(fetches.get(), 3);
+    }
 }
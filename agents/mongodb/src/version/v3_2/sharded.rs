@@ -1,10 +1,12 @@
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use mongodb::sync::Client;
 use opentracingrust::Span;
 
 use replicante_agent::actions::Action;
 use replicante_agent::actions::ActionHook;
+use replicante_agent::shards::ShardRoles;
 use replicante_agent::Agent;
 use replicante_agent::AgentContext;
 use replicante_agent::Result;
@@ -12,9 +14,11 @@ use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::DatastoreInfo;
 use replicante_models_agent::info::Shards;
 
+use super::super::common::shards_roles;
 use super::super::Sharding;
 use super::common::CommonLogic;
 use crate::actions::GracefulStop;
+use crate::config::Timeouts;
 
 /// MongoDB 3.2+ sharded agent.
 pub struct Sharded {
@@ -25,8 +29,13 @@ pub struct Sharded {
 }
 
 impl Sharded {
-    pub fn new(sharding: Sharding, client: Client, context: AgentContext) -> Sharded {
-        let common = CommonLogic::new(client, context);
+    pub fn new(
+        sharding: Sharding,
+        client: Client,
+        context: AgentContext,
+        timeouts: Timeouts,
+    ) -> Sharded {
+        let common = CommonLogic::new(client, context, timeouts);
         let is_mongos = sharding.mongos_node_name.is_some();
         Sharded {
             cluster_name: sharding.cluster_name,
@@ -58,7 +67,7 @@ impl Agent for Sharded {
                 cluster,
                 "MongoDB",
                 node_name,
-                info.version,
+                info.version.clone(),
                 None,
             ))
         } else {
@@ -68,12 +77,16 @@ impl Agent for Sharded {
                 cluster,
                 "MongoDB",
                 node_name,
-                info.version,
+                info.version.clone(),
                 None,
             ))
         }
     }
 
+    fn datastore_time(&self, span: &mut Span) -> Result<Option<SystemTime>> {
+        self.common.datastore_time(span)
+    }
+
     fn shards(&self, span: &mut Span) -> Result<Shards> {
         if self.is_mongos {
             Ok(Shards::new(Vec::new()))
@@ -81,4 +94,8 @@ impl Agent for Sharded {
             self.common.shards(span)
         }
     }
+
+    fn shards_roles(&self, shards: &Shards, _: &mut Span) -> Result<ShardRoles> {
+        Ok(shards_roles(shards))
+    }
 }
@@ -0,0 +1,23 @@
+use slog::debug;
+
+use replicante_agent::actions::ACTIONS;
+use replicante_agent::AgentContext;
+
+use super::config::Zookeeper;
+
+mod quorum;
+mod snapshot;
+
+pub use self::quorum::QuorumCheck;
+pub use self::snapshot::SnapshotBackup;
+
+/// Register Zookeeper specific actions enabled by the configuration.
+pub fn register(config: &Zookeeper, context: &AgentContext) {
+    if let Some(backup) = config.backup.clone() {
+        debug!(
+            context.logger,
+            "Registering Zookeeper snapshot backup action"
+        );
+        ACTIONS::register(SnapshotBackup::new(backup, config.target.clone()));
+    }
+}
@@ -0,0 +1,300 @@
+use opentracingrust::Span;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionDescriptor;
+use replicante_agent::actions::ActionHook;
+use replicante_agent::actions::ActionRecordView;
+use replicante_agent::actions::ActionState;
+use replicante_agent::actions::ActionValidity;
+use replicante_agent::Result;
+use replicante_agent::ResultExt;
+use replicante_agent::Transaction;
+use replicante_zk_helper::zk4lw::Conf;
+use replicante_zk_helper::zk4lw::EnsembleServer;
+use replicante_zk_helper::zk4lw::FourLetterClient;
+use replicante_zk_helper::zk4lw::FourLetterWord;
+use replicante_zk_helper::zk4lw::Mntr;
+use replicante_zk_helper::zk4lw::Srvr;
+
+use crate::error::ErrorKind;
+
+type ConfResponse = <Conf as FourLetterWord>::Response;
+type MntrResponse = <Mntr as FourLetterWord>::Response;
+
+/// Outcome of a successful quorum check.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct QuorumReport {
+    /// Number of voting members needed for the ensemble to have quorum.
+    pub quorum: usize,
+
+    /// Number of voting members in sync with the leader once this node stops.
+    pub remaining: usize,
+
+    /// Role of this node in the ensemble.
+    pub role: String,
+
+    /// Number of voting members in sync with the leader, including the leader.
+    pub synced: usize,
+
+    /// Number of voting members in the ensemble.
+    pub voters: usize,
+}
+
+/// Ensure the ensemble keeps quorum without this node before the service is stopped.
+///
+/// The leader's `mntr` follower counts are used to find how many voting members are
+/// in sync: if stopping this node leaves fewer than a quorum of them the action fails
+/// so the service stop stage never runs.
+/// Observers and standalone servers do not take part in quorum and are always safe to stop.
+///
+/// Zookeeper before 3.5 does not list the ensemble members in its configuration so
+/// they must be configured with `zookeeper.ensemble` for followers to locate the leader.
+pub struct QuorumCheck {
+    client: FourLetterClient,
+    ensemble: Vec<String>,
+}
+
+impl QuorumCheck {
+    pub fn new(target: String, ensemble: Vec<String>) -> QuorumCheck {
+        let client = FourLetterClient::new(target);
+        QuorumCheck { client, ensemble }
+    }
+}
+
+impl Action for QuorumCheck {
+    fn describe(&self) -> ActionDescriptor {
+        ActionHook::StoreGracefulStop.describe()
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let local = self.client.exec::<Mntr>(None)?;
+        let role = local.zk_server_state.clone();
+        let report = match local.zk_server_state.as_str() {
+            "observer" | "standalone" => QuorumReport {
+                quorum: 0,
+                remaining: 0,
+                role,
+                synced: 0,
+                voters: 0,
+            },
+            "leader" => {
+                let conf = self.client.exec::<Conf>(None)?;
+                let voters = match ensemble(&conf, &self.ensemble) {
+                    Ok(ensemble) => ensemble.voters,
+                    // Without the ensemble members the leader only knows connected followers.
+                    Err(_) => mntr_count(&local, "zk_followers")? + 1,
+                };
+                let synced = mntr_count(&local, "zk_synced_followers")?;
+                check_quorum(role, voters, synced + 1)?
+            }
+            _ => {
+                let conf = self.client.exec::<Conf>(None)?;
+                let ensemble = ensemble(&conf, &self.ensemble)?;
+                let leader = leader_mntr(&ensemble.members)?.ok_or_else(|| {
+                    ErrorKind::QuorumCheck("unable to locate the ensemble leader".into())
+                })?;
+                let synced = mntr_count(&leader, "zk_synced_followers")?;
+                check_quorum(role, ensemble.voters, synced + 1)?
+            }
+        };
+        let payload = json!(report);
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, _: &Json) -> ActionValidity {
+        Ok(())
+    }
+}
+
+/// Check the ensemble keeps quorum once a voting member in sync with the leader stops.
+fn check_quorum(role: String, voters: usize, synced: usize) -> Result<QuorumReport> {
+    let quorum = voters / 2 + 1;
+    let remaining = synced.saturating_sub(1);
+    if remaining < quorum {
+        let error = format!(
+            "stopping this {} would leave {} of {} voting members in sync (quorum is {})",
+            role, remaining, voters, quorum
+        );
+        return Err(ErrorKind::QuorumCheck(error).into());
+    }
+    Ok(QuorumReport {
+        quorum,
+        remaining,
+        role,
+        synced,
+        voters,
+    })
+}
+
+/// Voting members of the ensemble other than this node.
+#[derive(Debug, Eq, PartialEq)]
+struct Ensemble {
+    /// Host and port of the 4lw server of the other voting members.
+    members: Vec<String>,
+
+    /// Number of voting members in the ensemble.
+    voters: usize,
+}
+
+/// Find the voting members of the ensemble.
+///
+/// Servers before 3.5 do not list the ensemble members in their configuration
+/// so the members configured with `zookeeper.ensemble` are used instead.
+/// All configured members are assumed to be voting members (including this node).
+fn ensemble(conf: &ConfResponse, configured: &[String]) -> Result<Ensemble> {
+    let voters: Vec<&EnsembleServer> = conf
+        .zk_servers
+        .iter()
+        .filter(|server| server.peer_type == "participant")
+        .collect();
+    if !voters.is_empty() {
+        let members = voters
+            .iter()
+            .filter(|server| server.id != conf.zk_server_id)
+            .filter_map(|server| {
+                let port = server.client_port?;
+                Some(format!("{}:{}", server.host, port))
+            })
+            .collect();
+        return Ok(Ensemble {
+            members,
+            voters: voters.len(),
+        });
+    }
+    if configured.is_empty() {
+        let error = "the server does not list the ensemble members (Zookeeper before 3.5), \
+            set zookeeper.ensemble to check quorum"
+            .to_string();
+        return Err(ErrorKind::QuorumCheck(error).into());
+    }
+    Ok(Ensemble {
+        members: configured.to_vec(),
+        voters: configured.len(),
+    })
+}
+
+/// Query the leader of the ensemble for its `mntr` statistics.
+///
+/// Returns `None` if none of the given members reports itself as the leader.
+fn leader_mntr(members: &[String]) -> Result<Option<MntrResponse>> {
+    for member in members {
+        let client = FourLetterClient::new(member.clone());
+        // Unreachable members are not in sync with the leader anyway.
+        let leader = client
+            .exec::<Srvr>(None)
+            .map(|srvr| srvr.zk_mode == "leader")
+            .unwrap_or(false);
+        if leader {
+            return client.exec::<Mntr>(None).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Parse a counter reported by the leader's `mntr` statistics.
+fn mntr_count(mntr: &MntrResponse, key: &'static str) -> Result<usize> {
+    let value = mntr
+        .zk_extras
+        .get(key)
+        .ok_or_else(|| ErrorKind::QuorumCheck(format!("leader did not report {}", key)))?;
+    let count = value
+        .parse::<usize>()
+        .with_context(|_| ErrorKind::StoreOpFailed("mntr"))?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use replicante_zk_helper::zk4lw::Conf;
+    use replicante_zk_helper::zk4lw::FourLetterWord;
+
+    use super::check_quorum;
+    use super::ensemble;
+    use super::Ensemble;
+    use super::QuorumReport;
+
+    #[test]
+    fn quorum_kept() {
+        let report = check_quorum("follower".into(), 5, 5).unwrap();
+        assert_eq!(
+            report,
+            QuorumReport {
+                quorum: 3,
+                remaining: 4,
+                role: "follower".into(),
+                synced: 5,
+                voters: 5,
+            }
+        );
+        assert!(check_quorum("leader".into(), 3, 3).is_ok());
+    }
+
+    #[test]
+    fn quorum_lost() {
+        assert!(check_quorum("follower".into(), 3, 2).is_err());
+        assert!(check_quorum("leader".into(), 5, 3).is_err());
+    }
+
+    #[test]
+    fn ensemble_from_conf() {
+        let conf = Conf::parse_response(
+            r#"clientPort=2181
+serverId=1
+server.1=zk1:2888:3888:participant;0.0.0.0:2181
+server.2=zk2:2888:3888:participant;2181
+server.3=zk3:2888:3888:participant;2181
+server.4=zk4:2888:3888:observer;2181
+version=100000000"#,
+        )
+        .unwrap();
+        let configured = vec!["ignored:2181".to_string()];
+        assert_eq!(
+            ensemble(&conf, &configured).unwrap(),
+            Ensemble {
+                members: vec!["zk2:2181".into(), "zk3:2181".into()],
+                voters: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn ensemble_before_3_5() {
+        let conf = Conf::parse_response(
+            r#"clientPort=2181
+dataDir=/data/version-2
+serverId=1
+electionPort=3888
+quorumPort=2888
+peerType=0"#,
+        )
+        .unwrap();
+        let error = ensemble(&conf, &[]).unwrap_err();
+        assert_eq!(error.kind().code(), "InvalidStoreState");
+
+        let configured = vec![
+            "zk1:2181".to_string(),
+            "zk2:2181".to_string(),
+            "zk3:2181".to_string(),
+        ];
+        assert_eq!(
+            ensemble(&conf, &configured).unwrap(),
+            Ensemble {
+                members: configured.clone(),
+                voters: 3,
+            }
+        );
+    }
+}
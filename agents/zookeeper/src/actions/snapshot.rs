@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use opentracingrust::Span;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionDescriptor;
+use replicante_agent::actions::ActionRecordView;
+use replicante_agent::actions::ActionState;
+use replicante_agent::actions::ActionValidity;
+use replicante_agent::Result;
+use replicante_agent::ResultExt;
+use replicante_agent::Transaction;
+use replicante_zk_helper::zk4lw::FourLetterClient;
+use replicante_zk_helper::zk4lw::Srvr;
+
+use crate::config::Backup;
+use crate::error::ErrorKind;
+
+/// Copy the latest snapshot and the transaction logs needed to restore it to a backup location.
+///
+/// The zxid of the snapshot (encoded in the file name) is verified against
+/// the zxid reported by the server to ensure the data directory belongs to it.
+pub struct SnapshotBackup {
+    client: FourLetterClient,
+    config: Backup,
+}
+
+impl SnapshotBackup {
+    pub fn new(config: Backup, target: String) -> SnapshotBackup {
+        let client = FourLetterClient::new(target);
+        SnapshotBackup { client, config }
+    }
+
+    /// Fetch the last zxid processed by the server.
+    fn server_zxid(&self) -> Result<i64> {
+        let srvr = self.client.exec::<Srvr>(None)?;
+        Ok(srvr.zk_zxid)
+    }
+}
+
+impl Action for SnapshotBackup {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "zookeeper.apache.org/snapshot.backup".into(),
+            description: "Copy the latest snapshot and transaction logs to the backup destination"
+                .into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let snapshots_dir = Path::new(&self.config.snapshots_dir);
+        let logs_dir = self
+            .config
+            .logs_dir
+            .as_ref()
+            .map(Path::new)
+            .unwrap_or(snapshots_dir);
+
+        // Find the latest snapshot and verify it against the server's state.
+        let snapshots = list_files(snapshots_dir, "snapshot")?;
+        let (snapshot_zxid, snapshot) = match snapshots.last() {
+            Some(snapshot) => snapshot.clone(),
+            None => {
+                let error = format!("no snapshots found in {}", snapshots_dir.display());
+                return Err(ErrorKind::BackupFailed(error).into());
+            }
+        };
+        let server_zxid = self.server_zxid()?;
+        if snapshot_zxid > server_zxid {
+            let error = format!(
+                "snapshot zxid 0x{:x} is ahead of the server zxid 0x{:x}",
+                snapshot_zxid, server_zxid
+            );
+            return Err(ErrorKind::BackupFailed(error).into());
+        }
+
+        // Copy the snapshot and needed logs in a per-action directory.
+        let id = <dyn ActionRecordView>::id(record);
+        let target = Path::new(&self.config.destination).join(id.to_string());
+        let target_for_error = target.to_string_lossy().to_string();
+        fs::create_dir_all(&target).with_context(|_| ErrorKind::Io(target_for_error))?;
+        let mut files = vec![copy_file(&snapshot, &target)?];
+        let logs = list_files(logs_dir, "log")?;
+        for (_, log) in logs_for_snapshot(logs, snapshot_zxid) {
+            files.push(copy_file(&log, &target)?);
+        }
+
+        let payload = json!({
+            "destination": target.to_string_lossy(),
+            "files": files,
+            "verification": {
+                "server_zxid": format!("0x{:x}", server_zxid),
+                "snapshot_zxid": format!("0x{:x}", snapshot_zxid),
+            },
+        });
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            payload,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, _: &Json) -> ActionValidity {
+        Ok(())
+    }
+}
+
+/// Copy a file into the target directory, checking the full content was copied.
+fn copy_file(source: &Path, target: &Path) -> Result<String> {
+    let name = source
+        .file_name()
+        .expect("listed files to have a name")
+        .to_string_lossy()
+        .to_string();
+    let source_for_error = source.to_string_lossy().to_string();
+    let expected = fs::metadata(source)
+        .with_context(|_| ErrorKind::Io(source_for_error.clone()))?
+        .len();
+    let copied =
+        fs::copy(source, target.join(&name)).with_context(|_| ErrorKind::Io(source_for_error))?;
+    if copied != expected {
+        let error = format!(
+            "copied {} bytes of {} but expected {}",
+            copied, name, expected
+        );
+        return Err(ErrorKind::BackupFailed(error).into());
+    }
+    Ok(name)
+}
+
+/// Parse the zxid encoded in snapshot (`snapshot.<zxid>`) and log (`log.<zxid>`) file names.
+fn file_zxid(name: &str, prefix: &str) -> Option<i64> {
+    let zxid = name.strip_prefix(prefix)?.strip_prefix('.')?;
+    i64::from_str_radix(zxid, 16).ok()
+}
+
+/// List files with the given prefix in a directory, sorted by zxid.
+fn list_files(dir: &Path, prefix: &str) -> Result<Vec<(i64, PathBuf)>> {
+    let dir_for_error = dir.to_string_lossy().to_string();
+    let entries = fs::read_dir(dir).with_context(|_| ErrorKind::Io(dir_for_error.clone()))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|_| ErrorKind::Io(dir_for_error.clone()))?;
+        let zxid = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| file_zxid(name, prefix));
+        if let Some(zxid) = zxid {
+            files.push((zxid, entry.path()));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Select the transaction logs needed to replay transactions after the snapshot.
+///
+/// Log files are named after the first zxid they contain so the log that
+/// includes the snapshot zxid is the last one starting at or before it.
+fn logs_for_snapshot(logs: Vec<(i64, PathBuf)>, snapshot_zxid: i64) -> Vec<(i64, PathBuf)> {
+    let start = logs
+        .iter()
+        .rposition(|(zxid, _)| *zxid <= snapshot_zxid)
+        .unwrap_or(0);
+    logs.into_iter().skip(start).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::file_zxid;
+    use super::logs_for_snapshot;
+
+    #[test]
+    fn parse_file_zxid() {
+        assert_eq!(
+            file_zxid("snapshot.600000004", "snapshot"),
+            Some(25769803780)
+        );
+        assert_eq!(file_zxid("log.1", "log"), Some(1));
+        assert_eq!(file_zxid("log.1", "snapshot"), None);
+        assert_eq!(file_zxid("snapshot.junk", "snapshot"), None);
+        assert_eq!(file_zxid("snapshotted.1", "snapshot"), None);
+    }
+
+    #[test]
+    fn select_logs_for_snapshot() {
+        let logs = vec![
+            (1, PathBuf::from("log.1")),
+            (10, PathBuf::from("log.a")),
+            (20, PathBuf::from("log.14")),
+        ];
+        let logs: Vec<i64> = logs_for_snapshot(logs, 15)
+            .into_iter()
+            .map(|(zxid, _)| zxid)
+            .collect();
+        assert_eq!(logs, vec![10, 20]);
+    }
+
+    #[test]
+    fn select_logs_for_snapshot_before_all_logs() {
+        let logs = vec![(10, PathBuf::from("log.a"))];
+        let logs: Vec<i64> = logs_for_snapshot(logs, 5)
+            .into_iter()
+            .map(|(zxid, _)| zxid)
+            .collect();
+        assert_eq!(logs, vec![10]);
+    }
+}
@@ -1,12 +1,20 @@
-use failure::ResultExt;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
 use lazy_static::lazy_static;
-use opentracingrust::Log;
 use opentracingrust::Span;
-use opentracingrust::StartOptions;
-use zk_4lw::Client;
-use zk_4lw::FourLetterWord;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value as Json;
+use slog::debug;
 
-use replicante_agent::fail_span;
+use replicante_agent::actions::Action;
+use replicante_agent::actions::ActionHook;
+use replicante_agent::check_datastore_version;
+use replicante_agent::shards::ExtendedShardRole;
+use replicante_agent::shards::ShardRoles;
+use replicante_agent::spans::child_span;
 use replicante_agent::Agent;
 use replicante_agent::AgentContext;
 use replicante_agent::Result;
@@ -17,13 +25,15 @@ use replicante_models_agent::info::DatastoreInfo;
 use replicante_models_agent::info::Shard;
 use replicante_models_agent::info::ShardRole;
 use replicante_models_agent::info::Shards;
+use replicante_util_failure::failure_info;
+use replicante_zk_helper::zk4lw::Conf;
+use replicante_zk_helper::zk4lw::FourLetterClient;
+use replicante_zk_helper::zk4lw::FourLetterWord;
+use replicante_zk_helper::zk4lw::Mntr;
+use replicante_zk_helper::zk4lw::Srvr;
 
+use super::actions::QuorumCheck;
 use super::error::ErrorKind;
-use super::metrics::OPS_COUNT;
-use super::metrics::OPS_DURATION;
-use super::metrics::OP_ERRORS_COUNT;
-use super::zk4lw::Conf;
-use super::zk4lw::Srvr;
 use super::Config;
 
 lazy_static! {
@@ -34,6 +44,8 @@ lazy_static! {
     );
 }
 
+type SrvrResponse = <Srvr as FourLetterWord>::Response;
+
 /// Converts a Zookeeper version into a Semver compatible string.
 ///
 /// In particular it reformats the commit hash as metadata.
@@ -54,7 +66,10 @@ fn to_semver(version: &str) -> Result<String> {
 pub struct ZookeeperAgent {
     agent_context: AgentContext,
     cluster_name: String,
-    zk_client: Client,
+    ensemble: Vec<String>,
+    ensemble_timeout: Duration,
+    target: String,
+    zk_client: FourLetterClient,
 }
 
 impl ZookeeperAgent {
@@ -62,64 +77,199 @@ impl ZookeeperAgent {
         ZookeeperAgent {
             agent_context: context,
             cluster_name: config.zookeeper.cluster,
-            zk_client: Client::new(config.zookeeper.target),
+            ensemble: config.zookeeper.ensemble,
+            ensemble_timeout: Duration::from_secs(config.zookeeper.ensemble_timeout),
+            target: config.zookeeper.target.clone(),
+            zk_client: FourLetterClient::new(config.zookeeper.target),
         }
     }
 
     /// Executes the "conf" 4lw against the zookeeper server.
     fn conf(&self, root: &Span) -> Result<<Conf as FourLetterWord>::Response> {
-        let mut span = self
-            .agent_context
-            .tracer
-            .span_with_options(
-                "conf",
-                StartOptions::default().child_of(root.context().clone()),
+        self.exec::<Conf>(&self.zk_client, root)
+    }
+
+    /// Collect the view of the ensemble members from this node.
+    ///
+    /// Ensemble members are listed by the "conf" 4lw (Zookeeper 3.5+) and each member
+    /// is queried for its role and zxid with the "srvr" 4lw.
+    /// Members are queried in parallel, bounded by `zookeeper.ensemble_timeout`,
+    /// and those that can't be reached in time are reported with an unknown role.
+    fn ensemble(&self, span: &mut Span) -> Result<Json> {
+        let conf = self.conf(span)?;
+        let mntr = self.exec::<Mntr>(&self.zk_client, span)?;
+        let local_zxid = self.srvr(span)?.zk_zxid;
+        let local = |peer_type: String| {
+            EnsembleMember::new(
+                conf.zk_server_id.clone(),
+                Some(self.target.clone()),
+                peer_type,
+                mntr.zk_server_state.clone(),
+                Some(local_zxid),
             )
-            .auto_finish();
-        span.log(Log::new().log("span.kind", "client-send"));
-        OPS_COUNT.with_label_values(&["conf"]).inc();
-        let timer = OPS_DURATION.with_label_values(&["conf"]).start_timer();
-        let conf = self
-            .zk_client
-            .exec::<Conf>()
-            .map_err(|error| {
-                OP_ERRORS_COUNT.with_label_values(&["conf"]).inc();
-                fail_span(error, &mut *span)
+        };
+
+        let addresses: Vec<Option<String>> = conf
+            .zk_servers
+            .iter()
+            .map(|server| {
+                server
+                    .client_port
+                    .map(|port| format!("{}:{}", server.host, port))
             })
-            .with_context(|_| ErrorKind::StoreOpFailed("conf"))?;
-        timer.observe_duration();
-        span.log(Log::new().log("span.kind", "client-receive"));
-        Ok(conf)
+            .collect();
+        let remotes = conf
+            .zk_servers
+            .iter()
+            .zip(&addresses)
+            .map(|(server, address)| {
+                if server.id == conf.zk_server_id {
+                    None
+                } else {
+                    address.clone()
+                }
+            })
+            .collect();
+        let states = {
+            let _span = child_span(&self.agent_context.tracer, "ensemble.srvr", Some(span));
+            members_srvr(remotes, self.ensemble_timeout)
+        };
+
+        let mut members = Vec::new();
+        let servers = conf.zk_servers.iter().zip(addresses).zip(states);
+        for ((server, address), state) in servers {
+            if server.id == conf.zk_server_id {
+                members.push(local(server.peer_type.clone()));
+                continue;
+            }
+            let srvr = state.and_then(|state| {
+                state
+                    .map_err(|error| {
+                        debug!(
+                            self.agent_context.logger,
+                            "Unable to fetch ensemble member state";
+                            "member" => &server.id,
+                            failure_info(&error.to_fail()),
+                        );
+                    })
+                    .ok()
+            });
+            let (role, zxid) = match srvr {
+                Some(srvr) => (srvr.zk_mode, Some(srvr.zk_zxid)),
+                None => ("unknown".into(), None),
+            };
+            members.push(EnsembleMember::new(
+                server.id.clone(),
+                address,
+                server.peer_type.clone(),
+                role,
+                zxid,
+            ));
+        }
+
+        // Servers not listing the ensemble (standalone, before 3.5) report only themselves.
+        if members.is_empty() {
+            members.push(local("participant".into()));
+        }
+        Ok(json!({
+            "ensemble": {
+                "members": members,
+                "sync": mntr.sync_stats(),
+            },
+        }))
     }
 
-    /// Executes the "conf" 4lw against the zookeeper server.
+    /// Executes a 4lw against the given zookeeper server.
+    fn exec<W: FourLetterWord>(
+        &self,
+        client: &FourLetterClient,
+        root: &Span,
+    ) -> Result<W::Response> {
+        let command = W::command();
+        let mut span = child_span(&self.agent_context.tracer, command, Some(root));
+        client.exec::<W>(Some(&mut *span))
+    }
+
+    /// Executes the "srvr" 4lw against the zookeeper server.
     fn srvr(&self, root: &Span) -> Result<<Srvr as FourLetterWord>::Response> {
-        let mut span = self
-            .agent_context
-            .tracer
-            .span_with_options(
-                "srvr",
-                StartOptions::default().child_of(root.context().clone()),
-            )
-            .auto_finish();
-        span.log(Log::new().log("span.kind", "client-send"));
-        OPS_COUNT.with_label_values(&["srvr"]).inc();
-        let timer = OPS_DURATION.with_label_values(&["srvr"]).start_timer();
-        let srvr = self
-            .zk_client
-            .exec::<Srvr>()
-            .map_err(|error| {
-                OP_ERRORS_COUNT.with_label_values(&["srvr"]).inc();
-                fail_span(error, &mut *span)
+        self.exec::<Srvr>(&self.zk_client, root)
+    }
+}
+
+/// Query the given ensemble members with the "srvr" 4lw in parallel.
+///
+/// Results are in the same order as the addresses, with `None` for members without one.
+/// Each member is given `timeout` to connect and respond.
+fn members_srvr(
+    addresses: Vec<Option<String>>,
+    timeout: Duration,
+) -> Vec<Option<Result<SrvrResponse>>> {
+    let queries: Vec<_> = addresses
+        .into_iter()
+        .map(|address| {
+            address.map(|address| {
+                thread::spawn(move || {
+                    FourLetterClient::with_timeout(address, timeout).exec::<Srvr>(None)
+                })
             })
-            .with_context(|_| ErrorKind::StoreOpFailed("srvr"))?;
-        timer.observe_duration();
-        span.log(Log::new().log("span.kind", "client-receive"));
-        Ok(srvr)
+        })
+        .collect();
+    queries
+        .into_iter()
+        .map(|query| query.map(|query| query.join().expect("ensemble member query panicked")))
+        .collect()
+}
+
+/// View of an ensemble member as seen by this node.
+#[derive(Serialize)]
+struct EnsembleMember {
+    /// Client address of the member, if known.
+    address: Option<String>,
+
+    /// Server ID of the member.
+    id: String,
+
+    /// Configured peer type (participant or observer).
+    peer_type: String,
+
+    /// Role the member reports (leader, follower, observer, ...).
+    role: String,
+
+    /// The member is an active part of the ensemble and in sync with the leader.
+    synced: bool,
+
+    /// Last zxid processed by the member, if known.
+    zxid: Option<i64>,
+}
+
+impl EnsembleMember {
+    fn new(
+        id: String,
+        address: Option<String>,
+        peer_type: String,
+        role: String,
+        zxid: Option<i64>,
+    ) -> EnsembleMember {
+        let synced = matches!(role.as_str(), "leader" | "follower" | "observer");
+        EnsembleMember {
+            address,
+            id,
+            peer_type,
+            role,
+            synced,
+            zxid,
+        }
     }
 }
 
 impl Agent for ZookeeperAgent {
+    fn action_hooks(&self) -> Vec<(ActionHook, Arc<dyn Action>)> {
+        vec![(
+            ActionHook::StoreGracefulStop,
+            Arc::new(QuorumCheck::new(self.target.clone(), self.ensemble.clone())),
+        )]
+    }
+
     fn agent_info(&self, _: &mut Span) -> Result<AgentInfo> {
         let info = AgentInfo::new(AGENT_VERSION.clone());
         Ok(info)
@@ -128,6 +278,7 @@ impl Agent for ZookeeperAgent {
     fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
         let name = self.conf(span)?.zk_server_id;
         let version = to_semver(&self.srvr(span)?.zk_version)?;
+        check_datastore_version(&self.agent_context, &version);
         let info = DatastoreInfo::new(self.cluster_name.clone(), "Zookeeper", name, version, None);
         Ok(info)
     }
@@ -145,12 +296,55 @@ impl Agent for ZookeeperAgent {
         let shards = Shards::new(vec![shard]);
         Ok(shards)
     }
+
+    fn shards_extra(&self, span: &mut Span) -> Result<Option<Json>> {
+        self.ensemble(span).map(Some)
+    }
+
+    fn shards_roles(&self, shards: &Shards, _: &mut Span) -> Result<ShardRoles> {
+        let mut roles = ShardRoles::new();
+        for shard in &shards.shards {
+            if let ShardRole::Unknown(mode) = &shard.role {
+                if mode == "observer" {
+                    roles.insert(shard.id.clone(), ExtendedShardRole::Observer);
+                }
+            }
+        }
+        Ok(roles)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::net::TcpListener;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::members_srvr;
     use super::to_semver;
 
+    #[test]
+    fn members_srvr_in_parallel() {
+        // Listeners are never accepted from so members never respond.
+        let listeners: Vec<TcpListener> = (0..3)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let mut addresses: Vec<Option<String>> = listeners
+            .iter()
+            .map(|listener| Some(listener.local_addr().unwrap().to_string()))
+            .collect();
+        addresses.insert(1, None);
+        let start = Instant::now();
+        let states = members_srvr(addresses, Duration::from_secs(1));
+        assert!(start.elapsed() < Duration::from_millis(2500));
+        assert_eq!(states.len(), 4);
+        assert!(states[1].is_none());
+        for index in [0, 2, 3] {
+            let error = states[index].as_ref().unwrap().as_ref().unwrap_err();
+            assert_eq!(error.kind().code(), "StoreOpFailed");
+        }
+    }
+
     #[test]
     fn conver_to_semver() {
         let version = to_semver(
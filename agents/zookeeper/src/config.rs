@@ -1,17 +1,12 @@
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
-
-use failure::ResultExt;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::json;
+use serde_json::Value as Json;
 
-use replicante_agent::config::APIConfig;
 use replicante_agent::config::Agent;
+use replicante_agent::ErrorKind;
 use replicante_agent::Result;
 
-use super::error::ErrorKind;
-
 /// Zookeeper Agent configuration
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct Config {
@@ -23,23 +18,6 @@ pub struct Config {
 }
 
 impl Config {
-    /// Loads the configuration from the given [`std::fs::File`].
-    ///
-    /// [`std::fs::File`]: https://doc.rust-lang.org/std/fs/struct.File.html
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config> {
-        let path_for_error = path.as_ref().to_str().unwrap_or("<utf8 error>").to_string();
-        let config = File::open(path).with_context(|_| ErrorKind::Io(path_for_error))?;
-        Config::from_reader(config)
-    }
-
-    /// Loads the configuration from the given [`std::io::Read`].
-    ///
-    /// [`std::io::Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
-    pub fn from_reader<R: Read>(reader: R) -> Result<Config> {
-        let conf = serde_yaml::from_reader(reader).with_context(|_| ErrorKind::ConfigLoad)?;
-        Ok(conf)
-    }
-
     /// Apply transformations to the configuration to derive some parameters.
     ///
     /// Transvormation:
@@ -49,48 +27,148 @@ impl Config {
         self.agent = self.agent.transform();
         self
     }
+
+    /// Validate the Zookeeper specific configuration options.
+    ///
+    /// Common agent options are validated by the SDK.
+    pub fn validate(&self) -> Result<()> {
+        let zookeeper = &self.zookeeper;
+        if zookeeper.cluster.is_empty() {
+            let error = "can't be empty".to_string();
+            return Err(ErrorKind::ConfigInvalid("zookeeper.cluster", error).into());
+        }
+        if zookeeper.target.is_empty() {
+            let error = "can't be empty".to_string();
+            return Err(ErrorKind::ConfigInvalid("zookeeper.target", error).into());
+        }
+        if zookeeper.ensemble.iter().any(String::is_empty) {
+            let error = "members can't be empty".to_string();
+            return Err(ErrorKind::ConfigInvalid("zookeeper.ensemble", error).into());
+        }
+        if zookeeper.ensemble_timeout == 0 {
+            let error = "must be at least 1".to_string();
+            return Err(ErrorKind::ConfigInvalid("zookeeper.ensemble_timeout", error).into());
+        }
+        if let Some(backup) = &zookeeper.backup {
+            if backup.destination.is_empty() || backup.snapshots_dir.is_empty() {
+                let error = "destination and snapshots_dir can't be empty".to_string();
+                return Err(ErrorKind::ConfigInvalid("zookeeper.backup", error).into());
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Config {
-    /// Override the base agent default configuration options.
-    pub fn override_defaults() {
-        APIConfig::set_default_bind("127.0.0.1:3181".into())
+    /// Agent specific defaults for the base agent configuration options.
+    pub fn defaults() -> Json {
+        json!({
+            "agent": {
+                "api": {
+                    "bind": "127.0.0.1:3181",
+                },
+            },
+        })
     }
 }
 
 /// Zookeeper related options.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct Zookeeper {
+    /// Snapshot backup action options (the action is not available if not set).
+    #[serde(default)]
+    pub backup: Option<Backup>,
+
     /// Name of the zookeeper cluster.
     pub cluster: String,
 
+    /// Host and port (in host:port format) of the 4lw server of every ensemble member.
+    ///
+    /// Only needed by the graceful stop quorum check on Zookeeper before 3.5,
+    /// which does not list the ensemble members in its configuration.
+    #[serde(default)]
+    pub ensemble: Vec<String>,
+
+    /// Timeout, in seconds, for 4lw commands sent to other ensemble members.
+    ///
+    /// Members that do not respond in time are reported with an unknown role.
+    #[serde(default = "Zookeeper::default_ensemble_timeout")]
+    pub ensemble_timeout: u64,
+
     /// Host and port (in host:port format) of the zookeeper 4lw server.
     #[serde(default = "Zookeeper::default_target")]
     pub target: String,
 }
 
 impl Zookeeper {
+    pub fn default_ensemble_timeout() -> u64 {
+        2
+    }
+
     pub fn default_target() -> String {
         "localhost:2181".into()
     }
 }
 
+/// Snapshot backup action options.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Backup {
+    /// Directory to copy snapshots and transaction logs into.
+    ///
+    /// Each backup action creates a sub-directory named after the action ID.
+    pub destination: String,
+
+    /// Directory Zookeeper stores transaction logs in (defaults to `snapshots_dir`).
+    ///
+    /// This is the `version-2` directory inside the configured `dataLogDir`.
+    #[serde(default)]
+    pub logs_dir: Option<String>,
+
+    /// Directory Zookeeper stores snapshots in.
+    ///
+    /// This is the `version-2` directory inside the configured `dataDir`.
+    pub snapshots_dir: String,
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
 
+    use replicante_agent::config::ConfigFormat;
+
     use super::Config;
 
     #[test]
     #[should_panic(expected = "invalid type: string")]
     fn from_reader_error() {
         let cursor = Cursor::new("some other text");
-        Config::from_reader(cursor).unwrap();
+        ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
     }
 
     #[test]
     fn from_reader_ok() {
         let cursor = Cursor::new("{agent: {db: 'test'}, zookeeper: {cluster: test}}");
-        Config::from_reader(cursor).unwrap();
+        ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
+    }
+
+    #[test]
+    fn validate_cluster() {
+        let cursor = Cursor::new("{agent: {db: 'test'}, zookeeper: {cluster: test}}");
+        let config = ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
+        config.validate().unwrap();
+
+        let cursor = Cursor::new("{agent: {db: 'test'}, zookeeper: {cluster: ''}}");
+        let config = ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.kind().code(), "ConfigInvalid");
+    }
+
+    #[test]
+    fn validate_ensemble_timeout() {
+        let cursor =
+            Cursor::new("{agent: {db: 'test'}, zookeeper: {cluster: test, ensemble_timeout: 0}}");
+        let config = ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.kind().code(), "ConfigInvalid");
     }
 }
@@ -6,8 +6,8 @@ use replicante_agent::ErrorKind as BaseKind;
 /// Zookeeper specifc error kinds.
 #[derive(Debug)]
 pub enum ErrorKind {
-    /// Alias for `ConfigLoad`.
-    ConfigLoad,
+    /// Unable to backup Zookeeper snapshot.
+    BackupFailed(String),
 
     /// Alias for `ConfigOption`.
     ConfigOption(&'static str),
@@ -18,6 +18,9 @@ pub enum ErrorKind {
     /// Alias for `Io`.
     Io(String),
 
+    /// Stopping the node would cause the ensemble to lose quorum.
+    QuorumCheck(String),
+
     /// Alias for `StoreOpFailed`.
     StoreOpFailed(&'static str),
 
@@ -34,10 +37,11 @@ impl fmt::Display for ErrorKind {
 impl From<ErrorKind> for BaseKind {
     fn from(error: ErrorKind) -> BaseKind {
         match error {
-            ErrorKind::ConfigLoad => BaseKind::ConfigLoad,
+            ErrorKind::BackupFailed(error) => BaseKind::FreeForm(error),
             ErrorKind::ConfigOption(option) => BaseKind::ConfigOption(option),
             ErrorKind::Initialisation(message) => BaseKind::Initialisation(message),
             ErrorKind::Io(path) => BaseKind::Io(path),
+            ErrorKind::QuorumCheck(message) => BaseKind::InvalidStoreState(message),
             ErrorKind::StoreOpFailed(op) => BaseKind::StoreOpFailed(op),
             ErrorKind::VersionParse => BaseKind::ResponseDecode("text", "version"),
         }
@@ -1,59 +1,64 @@
 use lazy_static::lazy_static;
 
+use replicante_agent::BuildInfo;
 use replicante_agent::Result;
 use replicante_agent::SemVersion;
 
+mod actions;
 mod agent;
 mod config;
 mod error;
-mod metrics;
-mod zk4lw;
 
 use agent::ZookeeperAgent;
 use config::Config;
 
 const DEFAULT_CONFIG_FILE: &str = "agent-zookeeper.yaml";
+const ENV_PREFIX: &str = "REPLIAGENT_";
 const UPDATE_META: &str =
     "https://github.com/replicante-io/metadata/raw/main/replicante/agent/zookeeper/latest.json";
-const VERSION: &str = concat!(
-    env!("CARGO_PKG_VERSION"),
-    " [",
-    env!("GIT_BUILD_HASH"),
-    "; ",
-    env!("GIT_BUILD_TAINT"),
-    "]",
-);
 
 lazy_static! {
+    static ref BUILD: BuildInfo = replicante_agent::build_info!();
     static ref CURRENT_VERSION: SemVersion = SemVersion::parse(env!("CARGO_PKG_VERSION")).unwrap();
     static ref RELEASE: String = format!("repliagent-officials@{}", env!("GIT_BUILD_HASH"));
 }
 
 /// Configure and start the agent.
 pub fn run() -> Result<bool> {
+    replicante_agent::build::register(BUILD.clone());
+
     // Command line parsing.
     let cli_args = ::replicante_agent::process::clap(
         "Zookeeper Replicante Agent",
-        VERSION,
+        BUILD.display(),
         env!("CARGO_PKG_DESCRIPTION"),
         DEFAULT_CONFIG_FILE,
     )
     .get_matches();
+    if replicante_agent::process::version_command(&cli_args) {
+        return Ok(true);
+    }
 
     // Load configuration.
-    Config::override_defaults();
-    let config_location: &String = cli_args
-        .get_one("config")
-        .expect("CLI arguments to have a config value");
-    let config = Config::from_file(config_location)?;
+    let loader =
+        replicante_agent::process::config_loader(&cli_args, ENV_PREFIX, Config::defaults())?;
+    if cli_args.get_flag("print-config") {
+        print!("{}", loader.describe::<Config>()?);
+        return Ok(true);
+    }
+    let config: Config = loader.load()?;
     let config = config.transform();
+    if replicante_agent::process::commands(&cli_args, &config.agent, || config.validate())? {
+        return Ok(true);
+    }
 
     // Run the agent using the provided default helper.
     let agent_conf = config.agent.clone();
     let release = RELEASE.as_str();
     replicante_agent::process::run(agent_conf, "repliagent-zookeeper", release, |context, _| {
-        metrics::register_metrics(context);
-        let agent = ZookeeperAgent::new(config, context.clone());
+        replicante_zk_helper::register_metrics(context);
+        actions::register(&config.zookeeper, context);
+        let agent = ZookeeperAgent::new(config.clone(), context.clone());
         replicante_agent::process::update_checker(CURRENT_VERSION.clone(), UPDATE_META, context)?;
         Ok(agent)
     })
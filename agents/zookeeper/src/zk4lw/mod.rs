@@ -1,5 +0,0 @@
-mod conf;
-mod srvr;
-
-pub use self::conf::Conf;
-pub use self::srvr::Srvr;
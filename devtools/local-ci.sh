@@ -10,12 +10,20 @@ for_version() {
 
   echo "Clean up workspaces for version ${version}"
   rustup run "${version}" cargo clean --manifest-path libs/rust/sdk/Cargo.toml
+  rustup run "${version}" cargo clean --manifest-path libs/rust/jmx-helper/Cargo.toml
+  rustup run "${version}" cargo clean --manifest-path libs/rust/zk-helper/Cargo.toml
   rustup run "${version}" cargo clean --manifest-path agents/kafka/Cargo.toml
   rustup run "${version}" cargo clean --manifest-path agents/mongodb/Cargo.toml
   rustup run "${version}" cargo clean --manifest-path agents/zookeeper/Cargo.toml
 
   echo "Run CI for version ${version}"
   rustup run "${version}" ci/check-workspace.sh ${full_mode} "Legacy SDK" libs/rust/sdk/Cargo.toml
+  rustup run "${version}" cargo build --manifest-path libs/rust/sdk/Cargo.toml --no-default-features
+  rustup run "${version}" cargo build --manifest-path libs/rust/sdk/Cargo.toml --no-default-features --features api
+  rustup run "${version}" cargo build --manifest-path libs/rust/sdk/Cargo.toml --no-default-features --features store
+  rustup run "${version}" cargo build --manifest-path libs/rust/sdk/Cargo.toml --no-default-features --features api,store
+  rustup run "${version}" ci/check-workspace.sh ${full_mode} "JMX Helper" libs/rust/jmx-helper/Cargo.toml
+  rustup run "${version}" ci/check-workspace.sh ${full_mode} "Zookeeper Helper" libs/rust/zk-helper/Cargo.toml
   rustup run "${version}" ci/check-workspace.sh ${full_mode} Kafka agents/kafka/Cargo.toml
   rustup run "${version}" ci/check-workspace.sh ${full_mode} MongoDB agents/mongodb/Cargo.toml
   rustup run "${version}" ci/check-workspace.sh ${full_mode} Zookeeper agents/zookeeper/Cargo.toml
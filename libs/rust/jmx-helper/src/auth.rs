@@ -0,0 +1,151 @@
+//! JMX connections with credentials.
+//!
+//! The `jmx` crate connects without a connector environment, which is where the JMX
+//! RMI connector expects credentials, so authenticated connections are made through
+//! the JVM bindings the `jmx` crate is built on.
+use std::convert::TryFrom;
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use anyhow::Error;
+use j4rs::Instance;
+use j4rs::InvocationArg;
+use j4rs::Jvm;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// Connector environment key the JMX RMI connector reads credentials from.
+const CREDENTIALS_KEY: &str = "jmx.remote.credentials";
+
+/// Connection to a JMX server that requires authentication.
+pub struct AuthenticatedClient {
+    connection: Mutex<Option<Connection>>,
+    password: String,
+    service_url: String,
+    username: String,
+}
+
+/// JMX connector and the MBean server connection opened with it.
+struct Connection {
+    connector: Instance,
+    server: Instance,
+}
+
+/// Fields of a serialised `javax.management.ObjectName` that we care about.
+#[derive(Deserialize)]
+struct ObjectName {
+    #[serde(rename = "canonicalName")]
+    canonical_name: String,
+}
+
+impl AuthenticatedClient {
+    /// Create a client for the JMX server at the given service URL.
+    ///
+    /// The client must be connected with `reconnect` before it is used.
+    pub fn new(service_url: String, username: String, password: String) -> AuthenticatedClient {
+        AuthenticatedClient {
+            connection: Mutex::new(None),
+            password,
+            service_url,
+            username,
+        }
+    }
+
+    /// Fetch an attribute of an MBean.
+    pub fn get_attribute<T>(&self, mbean: &str, attribute: &str) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let jvm = Jvm::attach_thread()?;
+        let name = jvm.create_instance(
+            "javax.management.ObjectName",
+            &[InvocationArg::try_from(mbean)?],
+        )?;
+        let connection = self
+            .connection
+            .lock()
+            .expect("JMX connection lock poisoned");
+        let connection = connection
+            .as_ref()
+            .ok_or_else(|| anyhow!("JMX connection not established"))?;
+        let value = jvm.invoke(
+            &connection.server,
+            "getAttribute",
+            &[
+                InvocationArg::from(name),
+                InvocationArg::try_from(attribute)?,
+            ],
+        )?;
+        let value = jvm.to_rust(value)?;
+        Ok(value)
+    }
+
+    /// Fetch the names of the MBeans matching an object name pattern.
+    pub fn query_names(&self, pattern: &str) -> Result<Vec<String>, Error> {
+        let jvm = Jvm::attach_thread()?;
+        let name = || {
+            jvm.create_instance(
+                "javax.management.ObjectName",
+                &[InvocationArg::try_from(pattern)?],
+            )
+        };
+        // Object names are also queries that match the names they describe.
+        let query = name()?;
+        let name = name()?;
+        let connection = self
+            .connection
+            .lock()
+            .expect("JMX connection lock poisoned");
+        let connection = connection
+            .as_ref()
+            .ok_or_else(|| anyhow!("JMX connection not established"))?;
+        let names = jvm.invoke(
+            &connection.server,
+            "queryNames",
+            &[InvocationArg::from(name), InvocationArg::from(query)],
+        )?;
+        let names: Vec<ObjectName> = jvm.to_rust(names)?;
+        Ok(names.into_iter().map(|name| name.canonical_name).collect())
+    }
+
+    /// Connect to the JMX server, closing any existing connection.
+    pub fn reconnect(&self) -> Result<(), Error> {
+        let jvm = Jvm::attach_thread()?;
+        let mut connection = self
+            .connection
+            .lock()
+            .expect("JMX connection lock poisoned");
+        if let Some(previous) = connection.take() {
+            // The previous connection has already failed so errors closing it are ignored.
+            let _ = jvm.invoke(&previous.connector, "close", &[]);
+        }
+        let url = jvm.create_instance(
+            "javax.management.remote.JMXServiceURL",
+            &[InvocationArg::try_from(self.service_url.as_str())?],
+        )?;
+        let credentials = jvm.create_java_array(
+            "java.lang.String",
+            &[
+                InvocationArg::try_from(self.username.as_str())?,
+                InvocationArg::try_from(self.password.as_str())?,
+            ],
+        )?;
+        let environment = jvm.create_instance("java.util.HashMap", &[])?;
+        jvm.invoke(
+            &environment,
+            "put",
+            &[
+                InvocationArg::try_from(CREDENTIALS_KEY)?,
+                InvocationArg::from(credentials),
+            ],
+        )?;
+        let connector = jvm.invoke_static(
+            "javax.management.remote.JMXConnectorFactory",
+            "connect",
+            &[InvocationArg::from(url), InvocationArg::from(environment)],
+        )?;
+        let server = jvm.invoke(&connector, "getMBeanServerConnection", &[])?;
+        *connection = Some(Connection { connector, server });
+        Ok(())
+    }
+}
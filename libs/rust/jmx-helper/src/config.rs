@@ -0,0 +1,184 @@
+use std::fs;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use replicante_agent::ErrorKind;
+use replicante_agent::Result;
+use replicante_agent::ResultExt;
+
+/// JMX client options.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct JmxConfig {
+    /// Authenticate with the JMX server (disabled by default).
+    #[serde(default)]
+    pub auth: Option<JmxAuthConfig>,
+
+    /// Number of connections to open to the JMX server.
+    #[serde(default = "JmxConfig::default_pool_size")]
+    pub pool_size: usize,
+
+    /// Connect to JMX servers exposing RMI over SSL (disabled by default).
+    #[serde(default)]
+    pub tls: Option<JmxTlsConfig>,
+}
+
+impl Default for JmxConfig {
+    fn default() -> Self {
+        JmxConfig {
+            auth: None,
+            pool_size: Self::default_pool_size(),
+            tls: None,
+        }
+    }
+}
+
+impl JmxConfig {
+    fn default_pool_size() -> usize {
+        1
+    }
+
+    /// Validate the JMX options found at the given path.
+    pub fn validate(&self, path: &'static str) -> Result<()> {
+        if self.pool_size == 0 {
+            let error = "pool_size must be at least 1".to_string();
+            return Err(ErrorKind::ConfigInvalid(path, error).into());
+        }
+        if let Some(auth) = &self.auth {
+            if auth.username.is_empty() {
+                let error = "auth.username can't be empty".to_string();
+                return Err(ErrorKind::ConfigInvalid(path, error).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Credentials to authenticate with JMX servers.
+///
+/// The password is read from a file so it is not stored in the agent configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct JmxAuthConfig {
+    /// File with the password of the JMX user.
+    pub password_file: String,
+
+    /// JMX user to authenticate as.
+    pub username: String,
+}
+
+impl JmxAuthConfig {
+    /// Read the password of the JMX user.
+    pub fn password(&self) -> Result<String> {
+        read_secret(&self.password_file)
+    }
+}
+
+/// Trust and key stores used by the JVM to connect to JMX servers over SSL.
+///
+/// Stores are set as JVM system properties, which apply to the whole process:
+/// they must be the same for all JMX clients and are fixed once the JVM is started.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct JmxTlsConfig {
+    /// Key store with the client certificate, for servers requiring client authentication.
+    #[serde(default)]
+    pub key_store: Option<String>,
+
+    /// File with the password of the key store.
+    #[serde(default)]
+    pub key_store_password_file: Option<String>,
+
+    /// Trust store with the certificates of the CAs to trust.
+    pub trust_store: String,
+
+    /// File with the password of the trust store.
+    #[serde(default)]
+    pub trust_store_password_file: Option<String>,
+}
+
+impl JmxTlsConfig {
+    /// JVM options configuring the trust and key stores.
+    ///
+    /// Passwords are read from their files: the options must only be passed
+    /// to the JVM as it is created and never logged or exported to child processes.
+    pub fn jvm_options(&self) -> Result<Vec<String>> {
+        let mut options = vec![format!("-Djavax.net.ssl.trustStore={}", self.trust_store)];
+        if let Some(file) = &self.trust_store_password_file {
+            let password = read_secret(file)?;
+            options.push(format!("-Djavax.net.ssl.trustStorePassword={}", password));
+        }
+        if let Some(key_store) = &self.key_store {
+            options.push(format!("-Djavax.net.ssl.keyStore={}", key_store));
+        }
+        if let Some(file) = &self.key_store_password_file {
+            let password = read_secret(file)?;
+            options.push(format!("-Djavax.net.ssl.keyStorePassword={}", password));
+        }
+        Ok(options)
+    }
+}
+
+/// Read a secret from a file, ignoring trailing new lines.
+fn read_secret(file: &str) -> Result<String> {
+    let secret = fs::read_to_string(file)
+        .with_context(|_| ErrorKind::Initialisation(format!("unable to read {}", file)))?;
+    Ok(secret.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::JmxAuthConfig;
+    use super::JmxConfig;
+    use super::JmxTlsConfig;
+
+    #[test]
+    fn jvm_options() {
+        let file = std::env::temp_dir().join("repliagent-jmx-truststore-password");
+        fs::write(&file, "secret\n").unwrap();
+        let tls = JmxTlsConfig {
+            key_store: None,
+            key_store_password_file: None,
+            trust_store: "/etc/jmx/trust store.jks".into(),
+            trust_store_password_file: Some(file.to_str().unwrap().to_string()),
+        };
+        let options = tls.jvm_options();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(
+            options.unwrap(),
+            vec![
+                "-Djavax.net.ssl.trustStore=/etc/jmx/trust store.jks",
+                "-Djavax.net.ssl.trustStorePassword=secret",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_password_file() {
+        let auth = JmxAuthConfig {
+            password_file: "/this/file/does/not/exist".into(),
+            username: "monitor".into(),
+        };
+        let error = auth.password().unwrap_err();
+        assert_eq!(error.kind().code(), "Initialisation");
+    }
+
+    #[test]
+    fn validate() {
+        assert!(JmxConfig::default().validate("jmx").is_ok());
+        let config = JmxConfig {
+            pool_size: 0,
+            ..JmxConfig::default()
+        };
+        assert!(config.validate("jmx").is_err());
+        let auth = JmxAuthConfig {
+            password_file: "/etc/jmx/password".into(),
+            username: "".into(),
+        };
+        let config = JmxConfig {
+            auth: Some(auth),
+            ..JmxConfig::default()
+        };
+        assert!(config.validate("jmx").is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! JMX client helpers for Replicante agents managing JVM based datastores.
+//!
+//! The `JmxClient` wraps the `jmx` crate with the behaviours agents need:
+//!
+//!   * A pool of connections to the JMX server, used in turns.
+//!   * Connections are re-established before use after they fail a request.
+//!   * A connection generation to invalidate values cached from the server (see `generation`).
+//!   * Operations are traced and tracked with metrics (see `register_metrics`).
+//!   * Connect and query stages are timed (see `replicante_agent::stages`).
+//!   * Connections to servers exposing RMI over SSL (see `JmxTlsConfig`).
+//!   * Connections to servers requiring password authentication (see `JmxAuthConfig`).
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use j4rs::JavaOpt;
+use j4rs::JvmBuilder;
+use jmx::MBeanAddress;
+use jmx::MBeanClientTrait;
+use jmx::MBeanThreadedClient;
+use jmx::MBeanThreadedClientOptions;
+use lazy_static::lazy_static;
+use opentracingrust::Log;
+use opentracingrust::Span;
+use serde::de::DeserializeOwned;
+use slog::debug;
+use slog::info;
+use slog::Logger;
+
+use replicante_agent::fail_compat;
+use replicante_agent::fail_span;
+use replicante_agent::stages::StageTimer;
+use replicante_agent::stages::STAGE_CONNECT;
+use replicante_agent::stages::STAGE_QUERY;
+use replicante_agent::ErrorKind;
+use replicante_agent::Result;
+use replicante_agent::ResultExt;
+
+mod auth;
+mod config;
+mod metrics;
+
+pub use self::config::JmxAuthConfig;
+pub use self::config::JmxConfig;
+pub use self::config::JmxTlsConfig;
+pub use self::metrics::register_metrics;
+
+use self::auth::AuthenticatedClient;
+use self::metrics::OPS_COUNT;
+use self::metrics::OPS_DURATION;
+use self::metrics::OP_ERRORS_COUNT;
+use self::metrics::RECONNECT_COUNT;
+
+// Limit the number of pending JMX requests to avoid memory exhaustion.
+const JMX_REQUESTS_QUEUE: usize = 1024;
+
+lazy_static! {
+    /// Track if the JVM used to connect to JMX servers was started.
+    static ref JVM_STARTED: Mutex<bool> = Mutex::new(false);
+}
+
+/// Client to a JMX server backed by a pool of connections.
+pub struct JmxClient {
+    address: MBeanAddress,
+    connections: Vec<Connection>,
+    generation: AtomicU64,
+    logger: Logger,
+    next: AtomicUsize,
+}
+
+/// A connection to the JMX server and whether it needs to be re-established.
+struct Connection {
+    backend: Backend,
+    reconnect: AtomicBool,
+}
+
+/// Client used by a connection, depending on the server requiring authentication.
+enum Backend {
+    Anonymous(MBeanThreadedClient),
+    Authenticated(AuthenticatedClient),
+}
+
+impl JmxClient {
+    /// Create a client for the JMX server at the "host:port" address.
+    ///
+    /// Connections are established lazily, the first time they are used.
+    pub fn connect(target: String, config: JmxConfig, logger: Logger) -> Result<JmxClient> {
+        let jvm_options = match &config.tls {
+            None => Vec::new(),
+            Some(tls) => tls.jvm_options()?,
+        };
+        start_jvm(jvm_options, &logger)?;
+        let address = MBeanAddress::address(target);
+        let credentials = match &config.auth {
+            None => None,
+            Some(auth) => Some((auth.username.clone(), auth.password()?)),
+        };
+        let mut connections = Vec::with_capacity(config.pool_size);
+        for _ in 0..config.pool_size {
+            let backend = match &credentials {
+                None => {
+                    let options = MBeanThreadedClientOptions::default()
+                        .requests_buffer_size(JMX_REQUESTS_QUEUE)
+                        // Skip connecting the first time around.
+                        .skip_connect(true);
+                    let jmx = MBeanThreadedClient::connect_with_options(address.clone(), options)
+                        .map_err(fail_compat)
+                        .with_context(|_| connection_error(&address))?;
+                    Backend::Anonymous(jmx)
+                }
+                Some((username, password)) => Backend::Authenticated(AuthenticatedClient::new(
+                    service_url(&address),
+                    username.clone(),
+                    password.clone(),
+                )),
+            };
+            connections.push(Connection {
+                backend,
+                reconnect: AtomicBool::new(true),
+            });
+        }
+        Ok(JmxClient {
+            address,
+            connections,
+            generation: AtomicU64::new(0),
+            logger,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Number of times connections to the JMX server have been established.
+    ///
+    /// Connections are re-established after failures, which may be caused by the server
+    /// restarting, so values cached from the server are only valid while this does not change.
+    /// Read the generation before fetching the value to cache to avoid caching values
+    /// fetched from a previous server.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Fetch an attribute of an MBean.
+    ///
+    /// Failures are reported as `StoreOpFailed` errors for the given operation.
+    pub fn get_attribute<T, S>(
+        &self,
+        operation: &'static str,
+        mbean: S,
+        attribute: &str,
+        span: &mut Span,
+    ) -> Result<T>
+    where
+        T: DeserializeOwned,
+        S: Into<String>,
+    {
+        let mbean = mbean.into();
+        self.request(operation, "getAttribute", span, |backend| match backend {
+            Backend::Anonymous(jmx) => jmx
+                .get_attribute(mbean, attribute)
+                .map_err(|error| anyhow::Error::from(fail_compat(error))),
+            Backend::Authenticated(client) => client.get_attribute(&mbean, attribute),
+        })
+    }
+
+    /// Fetch the names of the MBeans matching an object name pattern.
+    ///
+    /// Failures are reported as `StoreOpFailed` errors for the given operation.
+    pub fn query_names(
+        &self,
+        operation: &'static str,
+        pattern: &str,
+        span: &mut Span,
+    ) -> Result<Vec<String>> {
+        self.request(operation, "queryNames", span, |backend| match backend {
+            Backend::Anonymous(jmx) => jmx
+                .query_names(pattern, "")
+                .map_err(|error| anyhow::Error::from(fail_compat(error))),
+            Backend::Authenticated(client) => client.query_names(pattern),
+        })
+    }
+}
+
+impl JmxClient {
+    /// Pick the next connection in the pool, reconnecting it if needed.
+    fn connection(&self, stages: &StageTimer, span: &mut Span) -> Result<&Connection> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.connections.len();
+        let connection = &self.connections[index];
+        if connection.reconnect.load(Ordering::Relaxed) {
+            debug!(self.logger, "Reconnecting to JMX server"; "connection" => index);
+            span.log(Log::new().log("action", "jmx.connect"));
+            RECONNECT_COUNT.inc();
+            stages
+                .time(STAGE_CONNECT, span, |_| match &connection.backend {
+                    Backend::Anonymous(jmx) => {
+                        let options = MBeanThreadedClientOptions::default()
+                            .requests_buffer_size(JMX_REQUESTS_QUEUE);
+                        jmx.reconnect_with_options(self.address.clone(), options)
+                            .map_err(|error| anyhow::Error::from(fail_compat(error)))
+                    }
+                    Backend::Authenticated(client) => client.reconnect(),
+                })
+                .with_context(|_| connection_error(&self.address))?;
+            connection.reconnect.store(false, Ordering::Relaxed);
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            info!(self.logger, "Reconnected to JMX server"; "connection" => index);
+        }
+        Ok(connection)
+    }
+
+    /// Perform a JMX request, tracking it with metrics and tracing.
+    ///
+    /// Connections that fail a request are flagged to reconnect before their next use.
+    fn request<F, T>(
+        &self,
+        operation: &'static str,
+        method: &'static str,
+        span: &mut Span,
+        request: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(&Backend) -> std::result::Result<T, anyhow::Error>,
+    {
+        span.tag("service", "jmx");
+        let stages = StageTimer::new("jmx", method);
+        let connection = self
+            .connection(&stages, span)
+            .map_err(|error| fail_span(error, &mut *span))?;
+        span.log(Log::new().log("span.kind", "client-send"));
+        OPS_COUNT.with_label_values(&[method]).inc();
+        let timer = OPS_DURATION.with_label_values(&[method]).start_timer();
+        let response = stages
+            .time(STAGE_QUERY, span, |_| request(&connection.backend))
+            .map_err(|error| {
+                OP_ERRORS_COUNT.with_label_values(&[method]).inc();
+                connection.reconnect.store(true, Ordering::Relaxed);
+                error
+            })
+            .with_context(|_| ErrorKind::StoreOpFailed(operation))
+            .map_err(|error| fail_span(error, &mut *span))?;
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        Ok(response)
+    }
+}
+
+fn connection_error(address: &MBeanAddress) -> ErrorKind {
+    let address = match address.clone() {
+        MBeanAddress::Address(address) => address,
+        MBeanAddress::ServiceUrl(address) => address,
+    };
+    ErrorKind::Connection("jmx server", address)
+}
+
+/// JMX service URL of the server at the given address.
+fn service_url(address: &MBeanAddress) -> String {
+    match address.clone() {
+        MBeanAddress::Address(address) => {
+            format!("service:jmx:rmi:///jndi/rmi://{}/jmxrmi", address)
+        }
+        MBeanAddress::ServiceUrl(url) => url,
+    }
+}
+
+/// Start the JVM used to connect to JMX servers with the given options.
+///
+/// Options are passed to the JVM as it is created instead of through the `JAVA_TOOL_OPTIONS`
+/// environment variable, which the JVM echoes to stderr and child processes inherit,
+/// so passwords in the options are not exposed.
+/// The JVM is started once per process so later calls are ignored.
+fn start_jvm(options: Vec<String>, logger: &Logger) -> Result<()> {
+    let mut started = JVM_STARTED.lock().expect("JVM_STARTED lock poisoned");
+    if *started {
+        return Ok(());
+    }
+    let options: Vec<JavaOpt> = options.iter().map(|option| JavaOpt::new(option)).collect();
+    JvmBuilder::new()
+        .java_opts(options)
+        .build()
+        .with_context(|_| ErrorKind::Initialisation("unable to start the JVM for JMX".into()))?;
+    *started = true;
+    debug!(logger, "Started the JVM for JMX connections");
+    Ok(())
+}
@@ -1,5 +1,10 @@
 // NOTE: this file MUST be in the sdk crate or it will fail to package.
 
+use std::process::Command;
+
+use chrono::SecondsFormat;
+use chrono::TimeZone;
+use chrono::Utc;
 use git2::Repository;
 use git2::Status;
 
@@ -24,16 +29,39 @@ fn is_workdir_status(status: Status) -> bool {
 }
 
 fn main() {
+    println!("cargo:rustc-env=BUILD_DATE={}", build_date());
+    println!("cargo:rustc-env=BUILD_RUSTC_VERSION={}", rustc_version());
     println!("cargo:rustc-env=GIT_BUILD_HASH={}", git_hash());
     println!("cargo:rustc-env=GIT_BUILD_TAINT={}", git_taint());
 }
 
+/// Build time, or `SOURCE_DATE_EPOCH` for reproducible builds.
+fn build_date() -> String {
+    let date = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .and_then(|epoch| Utc.timestamp_opt(epoch, 0).single())
+        .unwrap_or_else(Utc::now);
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
 fn git_hash() -> String {
     let repo = Repository::discover(".").unwrap();
     let checkout = repo.revparse_single("HEAD").unwrap();
     format!("{}", checkout.id())
 }
 
+fn rustc_version() -> String {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or_else(|| "unknown".into())
+}
+
 fn git_taint() -> String {
     let repo = Repository::discover(".").unwrap();
     let mut index_changed = false;
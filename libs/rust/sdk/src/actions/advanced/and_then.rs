@@ -1,6 +1,5 @@
 use std::sync::Arc;
 
-use failure::ResultExt;
 use opentracingrust::Span;
 use serde::Deserialize;
 use serde::Serialize;
@@ -15,9 +14,9 @@ use crate::actions::ActionState;
 use crate::actions::ActionValidity;
 use crate::actions::ActionValidityError;
 use crate::store::Transaction;
-use crate::Error;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
 lazy_static::lazy_static! {
     static ref DEFAULT_ARG_NULL: Json = json!(null);
@@ -126,7 +125,6 @@ impl Action for AndThen {
                     "can't find action for the current stage".into(),
                 ))
                 .with_context(|_| ErrorKind::ActionDecode)
-                .map_err(Error::from)
             }
             Some(stage) => stage,
         };
@@ -0,0 +1,93 @@
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use super::ActionsWake;
+
+/// Source of time for the actions engine loop.
+///
+/// The engine reads time and waits between iterations through a clock so tests
+/// can drive the loop with a virtual clock instead of sleeping.
+pub trait Clock: Send + Sync {
+    /// Current instant according to the clock.
+    fn now(&self) -> Instant;
+
+    /// Wait for the given duration or until woken.
+    ///
+    /// Returns `true` if the wait was interrupted by a wake notification.
+    fn wait<'a>(&'a self, duration: Duration, wake: &'a ActionsWake) -> BoxFuture<'a, bool>;
+}
+
+/// Clock backed by the system monotonic clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wait<'a>(&'a self, duration: Duration, wake: &'a ActionsWake) -> BoxFuture<'a, bool> {
+        wake.wait_timeout(duration).boxed()
+    }
+}
+
+#[cfg(test)]
+pub use self::mock::MockClock;
+
+#[cfg(test)]
+mod mock {
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+
+    use super::ActionsWake;
+    use super::Clock;
+
+    /// Virtual clock that only moves forward when waited on or advanced.
+    ///
+    /// Waits complete right away: pending wake notifications interrupt them without moving time.
+    pub struct MockClock {
+        now: Mutex<Instant>,
+        sleeps: Mutex<Vec<Duration>>,
+    }
+
+    impl MockClock {
+        pub fn new() -> MockClock {
+            MockClock {
+                now: Mutex::new(Instant::now()),
+                sleeps: Mutex::new(Vec::new()),
+            }
+        }
+
+        /// Move the clock forward without recording a wait.
+        pub fn advance(&self, duration: Duration) {
+            *self.now.lock().unwrap() += duration;
+        }
+
+        /// List of durations the clock was asked to wait for.
+        pub fn sleeps(&self) -> Vec<Duration> {
+            self.sleeps.lock().unwrap().clone()
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+
+        fn wait<'a>(&'a self, duration: Duration, wake: &'a ActionsWake) -> BoxFuture<'a, bool> {
+            self.sleeps.lock().unwrap().push(duration);
+            let woken = wake.take();
+            if !woken {
+                self.advance(duration);
+            }
+            futures::future::ready(woken).boxed()
+        }
+    }
+}
@@ -1,12 +1,13 @@
 use std::collections::HashMap;
 
+#[cfg(feature = "api")]
 use actix_web::http::StatusCode;
+#[cfg(feature = "api")]
 use actix_web::HttpResponse;
+#[cfg(feature = "api")]
 use actix_web::ResponseError;
 use chrono::DateTime;
 use chrono::Utc;
-use failure::Fail;
-use failure::ResultExt;
 use opentracingrust::ExtractFormat;
 use opentracingrust::InjectFormat;
 use opentracingrust::Span;
@@ -17,6 +18,7 @@ use serde::Deserialize;
 use serde::Serialize;
 use serde_json::json;
 use serde_json::Value as Json;
+use thiserror::Error;
 use uuid::Uuid;
 
 use replicante_models_agent::actions::ActionModel;
@@ -28,8 +30,10 @@ pub use replicante_models_agent::actions::ActionRequester;
 pub use replicante_models_agent::actions::ActionState;
 
 use crate::store::Transaction;
+use crate::sync_compat;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
 /// Abstraction of any action the agent can perform.
 ///
@@ -54,6 +58,30 @@ pub trait Action: Send + Sync + 'static {
         span: Option<&mut Span>,
     ) -> Result<()>;
 
+    /// Decide if an action left `Running` by a previous agent process can continue.
+    ///
+    /// Called for every running action left behind by another agent process, either as
+    /// the actions engine starts or once the lease held by that process expires.
+    /// Actions are failed with an "agent restarted" reason unless this returns `true`,
+    /// so only actions that can safely continue from their stored state should resume.
+    /// Implementations may transition the record to reset stale state before resuming.
+    fn resume(
+        &self,
+        _tx: &mut Transaction,
+        _record: &dyn ActionRecordView,
+        _span: Option<&mut Span>,
+    ) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// JSON schema of the arguments accepted by the action, if documented.
+    ///
+    /// The schema is only reported for introspection: request arguments
+    /// are still checked by `Action::validate_args`.
+    fn args_schema(&self) -> Option<Json> {
+        None
+    }
+
     /// Validate the arguments passed to an action request.
     fn validate_args(&self, args: &Json) -> ActionValidity;
 }
@@ -69,6 +97,19 @@ pub struct ActionDescriptor {
     pub description: String,
 }
 
+/// Free-form line of progress logged by an action while it runs.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ActionLogLine {
+    /// ID of the action that logged the line.
+    pub action_id: Uuid,
+
+    /// Content of the log line.
+    pub line: String,
+
+    /// Time the line was logged.
+    pub timestamp: DateTime<Utc>,
+}
+
 /// Possible actions agent implementations can provide to the SDK.
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub enum ActionHook {
@@ -120,6 +161,11 @@ pub struct ActionRecord {
     /// Type ID of the action to run.
     pub kind: String,
 
+    /// ID of the action that scheduled this action, if any.
+    ///
+    /// Links composed and follow-up actions to the action that caused them.
+    pub parent_action_id: Option<Uuid>,
+
     /// Entity (system or user) requesting the execution of the action.
     pub requester: ActionRequester,
 
@@ -147,6 +193,7 @@ impl ActionRecord {
         headers: HashMap<String, String>,
         id: Uuid,
         kind: String,
+        parent_action_id: Option<Uuid>,
         requester: ActionRequester,
         scheduled_ts: DateTime<Utc>,
         state: ActionState,
@@ -160,6 +207,7 @@ impl ActionRecord {
             headers,
             id,
             kind,
+            parent_action_id,
             requester,
             scheduled_ts,
             state,
@@ -189,6 +237,7 @@ impl ActionRecord {
             headers: HashMap::new(),
             id,
             kind,
+            parent_action_id: None,
             requester,
             scheduled_ts: Utc::now(),
             state: ActionState::New,
@@ -201,7 +250,7 @@ impl ActionRecord {
         let format = ExtractFormat::TextMap(Box::new(&self.headers));
         let context = tracer
             .extract(format)
-            .map_err(failure::SyncFailure::new)
+            .map_err(sync_compat)
             .with_context(|_| ErrorKind::ActionDecode)?;
         Ok(context)
     }
@@ -211,7 +260,7 @@ impl ActionRecord {
         let format = InjectFormat::TextMap(Box::new(&mut self.headers));
         tracer
             .inject(context, format)
-            .map_err(failure::SyncFailure::new)
+            .map_err(sync_compat)
             .with_context(|_| ErrorKind::ActionEncode)?;
         Ok(())
     }
@@ -332,9 +381,9 @@ impl ActionRecordView for ActionRecord {
 pub type ActionValidity<T = ()> = std::result::Result<T, ActionValidityError>;
 
 /// Result of action validation process.
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum ActionValidityError {
-    #[fail(display = "invalid action arguments: {}", _0)]
+    #[error("invalid action arguments: {0}")]
     InvalidArgs(String),
 }
 
@@ -346,16 +395,30 @@ impl ActionValidityError {
     }
 }
 
+#[cfg(feature = "api")]
 impl ResponseError for ActionValidityError {
     fn status_code(&self) -> StatusCode {
         StatusCode::BAD_REQUEST
     }
 
     fn error_response(&self) -> HttpResponse {
-        let status = self.status_code();
-        HttpResponse::build(status).json(json!({
+        self.api_response(None)
+    }
+}
+
+#[cfg(feature = "api")]
+impl ActionValidityError {
+    /// Build the API response for the error, tagged with the given correlation ID.
+    pub(crate) fn api_response(&self, correlation_id: Option<String>) -> HttpResponse {
+        let mut body = json!({
+            "code": self.kind(),
             "error": self.to_string(),
             "kind": self.kind(),
-        }))
+            "retryable": false,
+        });
+        if let Some(correlation_id) = correlation_id {
+            body["correlation_id"] = Json::from(correlation_id);
+        }
+        HttpResponse::build(self.status_code()).json(body)
     }
 }
@@ -1,100 +1,323 @@
+use std::cell::Cell;
+use std::cmp::min;
 use std::sync::Arc;
-use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
-use failure::ResultExt;
-use humthreads::Builder;
 use opentracingrust::Span;
 use slog::debug;
+use slog::info;
 use slog::trace;
 use slog::warn;
+use slog::Logger;
+use uuid::Uuid;
 
 use replicante_util_failure::capture_fail;
 use replicante_util_failure::failure_info;
 use replicante_util_failure::SerializableFail;
-use replicante_util_tracing::fail_span;
-use replicante_util_upkeep::Upkeep;
 
+use crate::actions::clock::Clock;
+use crate::actions::clock::SystemClock;
 use crate::actions::Action;
+use crate::actions::ActionListItem;
 use crate::actions::ActionRecord;
+use crate::actions::ActionRecordView;
 use crate::actions::ActionState;
+use crate::actions::ActionsProgress;
+use crate::actions::ActionsWake;
 use crate::actions::ACTIONS;
+use crate::fail_span;
 use crate::metrics::ACTION_COUNT;
 use crate::metrics::ACTION_DURATION;
 use crate::metrics::ACTION_ERRORS;
 use crate::metrics::ACTION_PRUNE_DURATION;
+use crate::runtime::blocking;
+use crate::runtime::AgentRuntime;
+use crate::store::ArchivedAction;
 use crate::store::Transaction;
 use crate::AgentContext;
 use crate::Error;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
-/// Start background thread to execute registered actions.
-pub fn spawn(context: AgentContext, upkeep: &mut Upkeep) -> Result<()> {
-    let thread = Builder::new("r:b:actions")
-        .full_name("replicante:base:actions:engine")
-        .spawn(move |scope| {
-            let logger = context.logger.clone();
-            let execute_interval = Duration::from_secs(context.config.actions.execute_interval);
-            let prune_interval = Duration::from_secs(context.config.actions.prune_interval);
-            let engine = Engine::new(context);
-            // Initialise last_prune to 2 * prune_interval ago to prune after start.
-            let mut last_prune = Instant::now() - (2 * prune_interval);
-            scope.activity("waiting to poll for actions");
-            while !scope.should_shutdown() {
-                let _activity = scope.scoped_activity("handling actions");
-                if let Err(error) = engine.poll() {
-                    capture_fail!(
-                        &error,
-                        logger,
-                        "Error while processing an action";
-                        failure_info(&error),
-                    );
-                }
-                if last_prune.elapsed() > prune_interval {
-                    last_prune = Instant::now();
-                    let _activity = scope.scoped_activity("pruning actions history");
-                    if let Err(error) = engine.clean() {
-                        capture_fail!(
-                            &error,
-                            logger,
-                            "Error while cleaning up historic actions";
-                            failure_info(&error),
-                        );
-                    }
-                }
-                thread::sleep(execute_interval);
+/// Number of transitions fetched at once when archiving an action history.
+const ARCHIVE_HISTORY_PAGE: u32 = 1000;
+
+/// Start the actions engine as a task on the agent async runtime.
+///
+/// Actions and store transactions block so each loop iteration runs on the blocking
+/// threads pool while waits between iterations don't hold on to any thread.
+pub fn spawn(context: AgentContext, runtime: &mut AgentRuntime) {
+    runtime.spawn("actions engine", |mut shutdown| async move {
+        let engine = EngineLoop::new(context, Arc::new(SystemClock));
+        let mut engine = match EngineLoop::step(engine, EngineLoop::recover).await {
+            None => return,
+            Some(engine) => engine,
+        };
+        loop {
+            engine = match EngineLoop::step(engine, EngineLoop::iterate).await {
+                None => return,
+                Some(engine) => engine,
+            };
+            tokio::select! {
+                biased;
+                _ = shutdown.requested() => return,
+                _ = engine.wait() => (),
             }
+        }
+    });
+}
+
+/// Actions engine loop state across iterations.
+///
+/// The delay between polls starts at `actions.execute_interval` and doubles after
+/// every poll that finds no action, up to `actions.execute_interval_max`.
+struct EngineLoop {
+    clock: Arc<dyn Clock>,
+    engine: Engine,
+    execute_interval: Duration,
+    execute_interval_max: Duration,
+    interval: Duration,
+    last_prune: Instant,
+    logger: Logger,
+    progress: ActionsProgress,
+    prune_interval: Duration,
+    wake: ActionsWake,
+}
+
+impl EngineLoop {
+    fn new(context: AgentContext, clock: Arc<dyn Clock>) -> EngineLoop {
+        let logger = context.logger.clone();
+        let execute_interval = Duration::from_secs(context.config.actions.execute_interval);
+        let execute_interval_max = Duration::from_secs(context.config.actions.execute_interval_max);
+        let prune_interval = Duration::from_secs(context.config.actions.prune_interval);
+        // Initialise last_prune to 2 * prune_interval ago to prune after start.
+        let last_prune = clock.now() - (2 * prune_interval);
+        let progress = context.actions_progress.clone();
+        let wake = context.actions_wake.clone();
+        let engine = Engine::new(context);
+        EngineLoop {
+            clock,
+            engine,
+            execute_interval,
+            execute_interval_max,
+            interval: execute_interval,
+            last_prune,
+            logger,
+            progress,
+            prune_interval,
+            wake,
+        }
+    }
+
+    /// Run a blocking step of the loop on the blocking threads pool.
+    ///
+    /// Returns `None` if the runtime is shutting down and the step did not run.
+    async fn step<F>(mut engine: EngineLoop, step: F) -> Option<EngineLoop>
+    where
+        F: FnOnce(&mut EngineLoop) + Send + 'static,
+    {
+        blocking(move || {
+            step(&mut engine);
+            engine
         })
-        .with_context(|_| ErrorKind::ThreadSpawn("actions engine"))?;
-    upkeep.register_thread(thread);
-    Ok(())
+        .await
+    }
+
+    /// Process the next action and prune historic actions when due.
+    fn iterate(&mut self) {
+        self.poll();
+        if self.prune_due() {
+            self.prune();
+        }
+    }
+
+    /// Process the next running or pending action, if any, and adapt the poll interval.
+    fn poll(&mut self) {
+        match self.engine.poll() {
+            Ok(true) => {
+                self.interval = self.execute_interval;
+                self.progress.notify();
+            }
+            Ok(false) => self.interval = min(self.interval * 2, self.execute_interval_max),
+            Err(error) => {
+                self.interval = self.execute_interval;
+                capture_fail!(
+                    &error.to_fail(),
+                    self.logger,
+                    "Error while processing an action";
+                    failure_info(&error.to_fail()),
+                );
+            }
+        }
+    }
+
+    /// Resume or fail actions left running by a previous agent process.
+    fn recover(&mut self) {
+        if let Err(error) = self.engine.recover() {
+            capture_fail!(
+                &error.to_fail(),
+                self.logger,
+                "Error while recovering orphaned actions";
+                failure_info(&error.to_fail()),
+            );
+        }
+    }
+
+    /// Prune historic actions.
+    fn prune(&mut self) {
+        self.last_prune = self.clock.now();
+        if let Err(error) = self.engine.clean() {
+            capture_fail!(
+                &error.to_fail(),
+                self.logger,
+                "Error while cleaning up historic actions";
+                failure_info(&error.to_fail()),
+            );
+        }
+    }
+
+    /// Check if it is time to prune historic actions.
+    fn prune_due(&self) -> bool {
+        self.clock.now().duration_since(self.last_prune) > self.prune_interval
+    }
+
+    /// Wait for the next loop iteration.
+    ///
+    /// Waits are cut short when new actions are scheduled.
+    async fn wait(&mut self) {
+        if self.clock.wait(self.interval, &self.wake).await {
+            self.interval = self.execute_interval;
+        }
+    }
 }
 
 /// Actions engine logic.
+///
+/// The engine only depends on the agent store and never on datastore collection:
+/// actions keep being executed, and their results recorded, while the datastore is down.
+/// Actions that fail to reach the datastore are failed like any other action
+/// so they never block the actions queued after them.
 struct Engine {
     context: AgentContext,
+
+    /// ID of the last action this engine invoked or recovered.
+    ///
+    /// Actions are executed in queue order so any other `Running` action returned
+    /// by the store was left behind by a process that no longer holds its lease.
+    current: Cell<Option<Uuid>>,
 }
 
 impl Engine {
     pub fn new(context: AgentContext) -> Engine {
-        Engine { context }
+        Engine {
+            context,
+            current: Cell::new(None),
+        }
     }
 
     /// Perform historic actions cleanup to prevent endless DB growth.
+    ///
+    /// If `actions.archive` is enabled actions are moved to the archive
+    /// in the same transaction they are pruned in.
     pub fn clean(&self) -> Result<()> {
         trace!(self.context.logger, "Pruning actions history");
+        let archive = self.context.config.actions.archive;
         let keep = self.context.config.actions.prune_keep;
         let limit = self.context.config.actions.prune_limit;
         let _timer = ACTION_PRUNE_DURATION.start_timer();
-        self.context
-            .store
-            .with_transaction(|tx| tx.actions().prune(keep, limit, None))
+        self.context.store.with_transaction(|tx| {
+            if archive {
+                let prunable: Vec<ActionListItem> = tx
+                    .actions()
+                    .prunable(keep, limit, None)?
+                    .collect::<Result<_>>()?;
+                for item in prunable {
+                    Engine::archive(tx, &item.id.to_string())?;
+                }
+            }
+            tx.actions().prune(keep, limit, None)
+        })
+    }
+
+    /// Move an action, with its full transition history, to the archive.
+    fn archive(tx: &mut Transaction, id: &str) -> Result<()> {
+        let action = match tx.action().get(id, None)? {
+            None => return Ok(()),
+            Some(action) => action,
+        };
+        let mut history = Vec::new();
+        let mut after = None;
+        loop {
+            let page = tx
+                .action()
+                .history(id, ARCHIVE_HISTORY_PAGE, after.as_deref(), None)?;
+            for item in page.items {
+                history.push(item?);
+            }
+            after = page.next;
+            if after.is_none() {
+                break;
+            }
+        }
+        let archived = ArchivedAction::new(action, history)?;
+        tx.actions().archive(&archived, None)
+    }
+
+    /// Handle actions found `Running` before the first poll.
+    ///
+    /// These actions were left running by a previous agent process (after a crash
+    /// or an unclean shutdown) and are either resumed, if the action allows it,
+    /// or failed with an explicit "agent restarted" reason.
+    ///
+    /// Actions still leased by other processes are skipped here and recovered by
+    /// `Engine::poll` if their lease expires.
+    pub fn recover(&self) -> Result<()> {
+        let mut span = Some(self.context.tracer.span("actions.recover").auto_finish());
+        let rv = self.context.store.with_transaction(|tx| {
+            let queue = tx
+                .actions()
+                .queue(span.as_ref().map(|span| span.context().clone()))?;
+            let mut running = Vec::new();
+            for item in queue {
+                let item = item?;
+                if item.state == ActionState::Running {
+                    running.push(item.id.to_string());
+                }
+            }
+            for id in running {
+                let record = tx
+                    .action()
+                    .get(&id, span.as_ref().map(|span| span.context().clone()))?;
+                let record = match record {
+                    None => continue,
+                    Some(record) => record,
+                };
+                // Skip actions another live agent process is executing.
+                // Should that process go away the action is recovered by `Engine::poll`
+                // once its lease expires.
+                let lease = Duration::from_secs(self.context.config.actions.lease_timeout);
+                let span_context = span.as_ref().map(|span| span.context().clone());
+                if !tx.action().lease(&record, lease, span_context)? {
+                    continue;
+                }
+                self.recover_action(tx, &record, span.as_deref_mut())?;
+            }
+            Ok(())
+        });
+        rv.map_err(|error| fail_span(error, span.as_deref_mut()))
     }
 
     /// Looks for running or pending actions and processes them.
-    pub fn poll(&self) -> Result<()> {
+    ///
+    /// Actions change the datastore so cached collection results are dropped
+    /// whenever the processed action transitions to a new state.
+    /// Follow-up actions scheduled by the processed action wake the engine up
+    /// so they are executed without waiting for the next poll.
+    ///
+    /// Returns `true` if an action was found.
+    pub fn poll(&self) -> Result<bool> {
         // Wrapped in `Some` to allow transition to optional Tracer easier.
         let mut span = Some(self.context.tracer.span("actions.poll").auto_finish());
         let rv = self.context.store.with_transaction(|tx| {
@@ -102,9 +325,21 @@ impl Engine {
                 .action()
                 .next(span.as_ref().map(|span| span.context().clone()))?;
             let record = match record {
-                None => return Ok(()),
+                None => return Ok((false, false, false)),
                 Some(record) => record,
             };
+            // Hold a lease on the action while it is invoked so others don't execute it too.
+            let lease = Duration::from_secs(self.context.config.actions.lease_timeout);
+            let span_context = span.as_ref().map(|span| span.context().clone());
+            if !tx.action().lease(&record, lease, span_context)? {
+                return Ok((false, false, false));
+            }
+            let state = <dyn ActionRecordView>::raw_state(&record);
+            if *state == ActionState::Running && self.current.get() != Some(record.id) {
+                self.recover_action(tx, &record, span.as_deref_mut())?;
+                return Ok((true, true, tx.follow_ups_scheduled()));
+            }
+            self.current.set(Some(record.id));
             if let Some(span) = span.as_mut() {
                 span.tag("action.kind", record.kind.clone());
                 span.tag("action.id", record.id.to_string());
@@ -113,10 +348,10 @@ impl Engine {
                     Ok(Some(context)) => span.follows(context),
                     Err(error) => {
                         capture_fail!(
-                            &error,
+                            &error.to_fail(),
                             self.context.logger,
                             "Failed to extract tracing context from action record";
-                            failure_info(&error),
+                            failure_info(&error.to_fail()),
                             "id" => %&record.id,
                             "kind" => &record.kind,
                         );
@@ -128,7 +363,9 @@ impl Engine {
                 Some(action) => action,
                 None => {
                     let error = ErrorKind::ActionNotAvailable(record.kind.clone());
-                    return self.fail(tx, &record, error.into(), span.as_deref());
+                    return self
+                        .fail(tx, &record, error.into(), span.as_deref())
+                        .map(|_| (true, true, false));
                 }
             };
             // To limit the noise generated by this message, emit it only once few cycles.
@@ -140,19 +377,67 @@ impl Engine {
                     "kind" => &record.kind,
                 );
             }
-            match self.call(tx, &record, action, span.as_deref_mut()) {
-                Err(error) => self.fail(tx, &record, error, span.as_deref()),
-                Ok(()) => Ok(()),
+            if let Err(error) = self.call(tx, &record, action, span.as_deref_mut()) {
+                self.fail(tx, &record, error, span.as_deref())?;
             }
+            let transitioned = match tx.action().get(&record.id.to_string(), None)? {
+                None => true,
+                Some(after) => <dyn ActionRecordView>::raw_state(&after) != state,
+            };
+            Ok((true, transitioned, tx.follow_ups_scheduled()))
         });
         match rv {
-            Ok(()) => Ok(()),
+            Ok((found, transitioned, follow_ups)) => {
+                if transitioned {
+                    self.context.collection_cache.clear();
+                }
+                if follow_ups {
+                    self.context.actions_wake.wake();
+                }
+                Ok(found)
+            }
             Err(error) => Err(fail_span(error, span.as_deref_mut())),
         }
     }
 }
 
 impl Engine {
+    /// Resume or fail an action left running by another agent process.
+    ///
+    /// The caller must hold the lease on the action.
+    fn recover_action(
+        &self,
+        tx: &mut Transaction,
+        record: &ActionRecord,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let action = match ACTIONS::get(&record.kind) {
+            Some(action) => action,
+            None => {
+                let error = ErrorKind::ActionNotAvailable(record.kind.clone());
+                return self.fail(tx, record, error.into(), span.as_deref());
+            }
+        };
+        let mut span = span;
+        match action.resume(tx, record, span.as_deref_mut()) {
+            Ok(true) => {
+                info!(
+                    self.context.logger,
+                    "Resuming action left running by a previous agent process";
+                    "id" => %&record.id,
+                    "kind" => &record.kind,
+                );
+                self.current.set(Some(record.id));
+                Ok(())
+            }
+            Ok(false) => {
+                let error = ErrorKind::ActionAgentRestarted(record.id.to_string());
+                self.fail(tx, record, error.into(), span.as_deref())
+            }
+            Err(error) => self.fail(tx, record, error, span.as_deref()),
+        }
+    }
+
     fn call(
         &self,
         tx: &mut Transaction,
@@ -178,10 +463,10 @@ impl Engine {
             "Action invocation failed";
             "id" => %&record.id,
             "kind" => &record.kind,
-            failure_info(&error),
+            failure_info(&error.to_fail()),
         );
         ACTION_ERRORS.with_label_values(&[&record.kind]).inc();
-        let error = SerializableFail::from(&error);
+        let error = SerializableFail::from(&error.to_fail());
         let error = serde_json::to_value(&error).with_context(|_| ErrorKind::ActionEncode)?;
         tx.action().transition(
             record,
@@ -194,19 +479,179 @@ impl Engine {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+    use opentracingrust::Span;
     use serde_json::json;
+    use serde_json::Value as Json;
 
     use replicante_util_failure::SerializableFail;
 
+    use super::super::advanced::NoOp;
     use super::super::impls::debug::Progress;
+    use super::super::impls::fence::NodeFence;
+    use super::super::impls::fence::NodeUnfence;
+    use super::super::impls::fence::MAX_ATTEMPT_FENCE;
+    use super::super::impls::service::stop::ServiceStop;
+    use super::super::impls::service::stop::MAX_ATTEMPT_STOP;
+    use super::super::impls::service::supervisor::MockSupervisor;
+    use super::super::impls::service::supervisor::Supervisor;
     use super::Engine;
+    use super::EngineLoop;
+    use crate::actions::clock::MockClock;
+    use crate::actions::fencing::MockFencer;
+    use crate::actions::Action;
+    use crate::actions::ActionDescriptor;
     use crate::actions::ActionRecord;
     use crate::actions::ActionRecordView;
     use crate::actions::ActionRequester;
     use crate::actions::ActionState;
+    use crate::actions::ActionValidity;
     use crate::actions::ActionsRegister;
+    use crate::actions::Fencer;
     use crate::actions::ACTIONS;
+    use crate::config::AgentConfig;
+    use crate::store::Store;
+    use crate::store::Transaction;
     use crate::AgentContext;
+    use crate::ErrorKind;
+    use crate::Result;
+
+    /// Action failing as if the datastore was down.
+    struct DatastoreDown;
+
+    impl Action for DatastoreDown {
+        fn describe(&self) -> ActionDescriptor {
+            ActionDescriptor {
+                kind: "test.datastore.down".into(),
+                description: "Fail to reach the datastore".into(),
+            }
+        }
+
+        fn invoke(
+            &self,
+            _: &mut Transaction,
+            _: &dyn ActionRecordView,
+            _: Option<&mut Span>,
+        ) -> Result<()> {
+            Err(ErrorKind::StoreOpFailed("test").into())
+        }
+
+        fn validate_args(&self, _: &Json) -> ActionValidity {
+            Ok(())
+        }
+    }
+
+    /// Insert an action and transition it through to done.
+    fn insert_finished(context: &AgentContext) -> ActionRecord {
+        let action = ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+        let id = action.id.to_string();
+        context
+            .store
+            .with_transaction(|tx| {
+                tx.action().insert(action.clone(), None)?;
+                tx.action()
+                    .transition(&action, ActionState::Running, None, None)?;
+                let running = tx.action().get(&id, None)?.unwrap();
+                tx.action()
+                    .transition(&running, ActionState::Done, None, None)?;
+                Ok(tx.action().get(&id, None)?.unwrap())
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn clean_archives_pruned_actions() {
+        let mut config = AgentConfig::mock();
+        config.actions.archive = true;
+        config.actions.prune_keep = 0;
+        let mut context = AgentContext::mock_with_config(config);
+        context.store = Store::sqlite();
+        let action = insert_finished(&context);
+        let id = action.id.to_string();
+        Engine::new(context.clone()).clean().unwrap();
+        let (current, archived) = context
+            .store
+            .with_transaction(|tx| {
+                let current = tx.action().get(&id, None)?;
+                let archived = tx.actions().archived(&id, None)?;
+                Ok((current, archived))
+            })
+            .unwrap();
+        assert!(current.is_none());
+        let details = archived.unwrap().decode().unwrap();
+        assert_eq!(details.action, action);
+        assert_eq!(details.history.len(), 3);
+    }
+
+    #[test]
+    fn clean_deletes_pruned_actions_by_default() {
+        let mut config = AgentConfig::mock();
+        config.actions.prune_keep = 0;
+        let mut context = AgentContext::mock_with_config(config);
+        context.store = Store::sqlite();
+        let action = insert_finished(&context);
+        let id = action.id.to_string();
+        Engine::new(context.clone()).clean().unwrap();
+        let (current, archived) = context
+            .store
+            .with_transaction(|tx| {
+                let current = tx.action().get(&id, None)?;
+                let archived = tx.actions().archived(&id, None)?;
+                Ok((current, archived))
+            })
+            .unwrap();
+        assert!(current.is_none());
+        assert!(archived.is_none());
+    }
+
+    #[test]
+    fn datastore_failures_do_not_block_actions() {
+        let failing = ActionRecord::new(
+            "test.datastore.down",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let failing_id = failing.id.to_string();
+        let action = ActionRecord::new(
+            "agent.replicante.io/debug.progress".to_string(),
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let id = action.id.to_string();
+        let context = AgentContext::mock();
+        context
+            .store
+            .with_transaction(|tx| {
+                tx.action().insert(failing, None)?;
+                tx.action().insert(action, None)
+            })
+            .unwrap();
+        let mut register = ActionsRegister::default();
+        register.register(DatastoreDown);
+        register.register_reserved(Progress {});
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            engine.poll().expect("poll failed to process action");
+            engine.poll().expect("poll failed to process action");
+        });
+        let state = |id: &str| {
+            let action = context
+                .store
+                .with_transaction(|tx| tx.action().get(id, None))
+                .unwrap()
+                .unwrap();
+            action.state().clone()
+        };
+        assert_eq!(ActionState::Failed, state(&failing_id));
+        assert_eq!(ActionState::Running, state(&id));
+    }
 
     #[test]
     fn fail_action_with_unkown_kind() {
@@ -237,6 +682,180 @@ mod tests {
         assert_eq!(payload.error, "actions with kind test are not available");
     }
 
+    #[test]
+    fn recover_fails_orphaned_actions() {
+        let mut action = ActionRecord::new(
+            "agent.replicante.io/debug.progress".to_string(),
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        action.set_state(ActionState::Running);
+        let id = action.id;
+        let context = AgentContext::mock();
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        let mut register = ActionsRegister::default();
+        register.register_reserved(Progress {});
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            engine.recover().expect("failed to recover actions");
+        });
+        let action = context
+            .store
+            .with_transaction(|tx| tx.action().get(&id.to_string(), None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(ActionState::Failed, *action.state());
+        let payload = action.state_payload().clone().unwrap();
+        let payload: SerializableFail = serde_json::from_value(payload).unwrap();
+        assert_eq!(
+            payload.error,
+            format!("the agent restarted while action {} was running", id)
+        );
+    }
+
+    #[test]
+    fn recover_actions_once_other_lease_expires() {
+        let mut action = ActionRecord::new(
+            "agent.replicante.io/debug.progress".to_string(),
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        action.set_state(ActionState::Running);
+        let id = action.id.to_string();
+        let context = AgentContext::mock();
+        context
+            .store
+            .with_transaction(|tx| {
+                let expires = chrono::Utc::now() + chrono::Duration::milliseconds(100);
+                tx.action().insert(action.clone(), None)?;
+                tx.action().lease_as(&action, "crashed-process", expires)
+            })
+            .unwrap();
+        let state = || {
+            let action = context
+                .store
+                .with_transaction(|tx| tx.action().get(&id, None))
+                .unwrap()
+                .unwrap();
+            action.state().clone()
+        };
+        let mut register = ActionsRegister::default();
+        register.register_reserved(Progress {});
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            engine.recover().expect("failed to recover actions");
+            assert_eq!(ActionState::Running, state());
+            assert!(!engine.poll().expect("poll failed"));
+
+            std::thread::sleep(Duration::from_millis(200));
+            assert!(engine.poll().expect("poll failed"));
+        });
+        assert_eq!(ActionState::Failed, state());
+    }
+
+    #[test]
+    fn loop_prunes_on_schedule() {
+        let context = AgentContext::mock();
+        let clock = Arc::new(MockClock::new());
+        let mut engine = EngineLoop::new(context, clock.clone());
+        assert!(engine.prune_due());
+        engine.prune();
+        assert!(!engine.prune_due());
+        clock.advance(Duration::from_secs(3600));
+        assert!(!engine.prune_due());
+        block_on(engine.wait());
+        assert!(engine.prune_due());
+        assert_eq!(clock.sleeps(), vec![Duration::from_secs(1)]);
+    }
+
+    #[test]
+    fn loop_backs_off_while_idle() {
+        let context = AgentContext::mock();
+        let clock = Arc::new(MockClock::new());
+        let mut engine = EngineLoop::new(context, clock.clone());
+        for _ in 0..5 {
+            engine.poll();
+            block_on(engine.wait());
+        }
+        let sleeps: Vec<u64> = clock.sleeps().iter().map(|sleep| sleep.as_secs()).collect();
+        assert_eq!(sleeps, vec![2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn loop_woken_by_scheduled_actions() {
+        let context = AgentContext::mock();
+        let wake = context.actions_wake.clone();
+        let clock = Arc::new(MockClock::new());
+        let mut engine = EngineLoop::new(context, clock.clone());
+        engine.poll();
+        engine.poll();
+        wake.wake();
+        block_on(engine.wait());
+        block_on(engine.wait());
+        let sleeps: Vec<u64> = clock.sleeps().iter().map(|sleep| sleep.as_secs()).collect();
+        assert_eq!(sleeps, vec![4, 1]);
+    }
+
+    /// Action scheduling a no-op follow-up action.
+    struct FollowUp;
+
+    impl Action for FollowUp {
+        fn describe(&self) -> ActionDescriptor {
+            ActionDescriptor {
+                kind: "test.follow.up".into(),
+                description: "Schedule a follow-up action".into(),
+            }
+        }
+
+        fn invoke(
+            &self,
+            tx: &mut Transaction,
+            record: &dyn ActionRecordView,
+            _: Option<&mut Span>,
+        ) -> Result<()> {
+            let kind = "agent.replicante.io/noop";
+            tx.action()
+                .schedule_follow_up(record, kind, json!(null), None)?;
+            tx.action()
+                .transition(record, ActionState::Done, None, None)
+        }
+
+        fn validate_args(&self, _: &Json) -> ActionValidity {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn follow_ups_wake_the_engine() {
+        let action = ActionRecord::new(
+            "test.follow.up",
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let context = AgentContext::mock();
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        let mut register = ActionsRegister::default();
+        register.register(FollowUp);
+        register.register_reserved(NoOp::new(None));
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            assert!(engine.poll().expect("poll failed to process action"));
+        });
+        assert!(context.actions_wake.take());
+    }
+
     #[test]
     fn no_action_noop() {
         let context = AgentContext::mock();
@@ -273,4 +892,159 @@ mod tests {
         assert_eq!(id, action.id);
         assert_eq!(ActionState::Running, *action.state());
     }
+
+    #[test]
+    fn transitions_clear_collection_cache() {
+        let action = ActionRecord::new(
+            "agent.replicante.io/debug.progress".to_string(),
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let context = AgentContext::mock();
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        context
+            .collection_cache
+            .get_or_fetch("test", || Ok(1))
+            .unwrap();
+        let mut register = ActionsRegister::default();
+        register.register_reserved(Progress {});
+        ACTIONS::test_with(register, || {
+            let engine = Engine::new(context.clone());
+            engine.poll().expect("poll failed to process action");
+        });
+        let cached = context.collection_cache.get_or_fetch("test", || Ok(2));
+        assert_eq!(*cached.unwrap(), 2);
+    }
+
+    /// Run the engine loop with a service stop action and return the updated record.
+    fn run_service_stop(supervisor: Arc<MockSupervisor>, clock: Arc<MockClock>) -> ActionRecord {
+        let action = ActionRecord::new(
+            "replicante.io/service.stop".to_string(),
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let id = action.id.to_string();
+        let context = AgentContext::mock();
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        let supervisor: Arc<dyn Supervisor> = supervisor;
+        let mut register = ActionsRegister::default();
+        register.register_reserved(ServiceStop::new(&supervisor));
+        ACTIONS::test_with(register, || {
+            let mut engine = EngineLoop::new(context.clone(), clock);
+            for _ in 0..=MAX_ATTEMPT_STOP {
+                engine.poll();
+                block_on(engine.wait());
+            }
+        });
+        context
+            .store
+            .with_transaction(|tx| tx.action().get(&id, None))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn service_stop_gives_up_on_stuck_service() {
+        let clock = Arc::new(MockClock::new());
+        let supervisor = Arc::new(MockSupervisor::running("1234"));
+        let action = run_service_stop(Arc::clone(&supervisor), Arc::clone(&clock));
+        assert_eq!(ActionState::Failed, *action.state());
+        let payload = action.state_payload().clone().unwrap();
+        assert_eq!(payload["attempt"], json!(MAX_ATTEMPT_STOP));
+        assert_eq!(
+            payload["message"],
+            json!("the service did not stop in time")
+        );
+        let calls = supervisor.calls();
+        assert_eq!(calls.iter().filter(|call| **call == "stop").count(), 1);
+        let sleeps = clock.sleeps().len();
+        assert_eq!(sleeps, usize::from(MAX_ATTEMPT_STOP) + 1);
+    }
+
+    #[test]
+    fn service_stop_waits_for_slow_service() {
+        let clock = Arc::new(MockClock::new());
+        let supervisor = Arc::new(MockSupervisor::running("1234").delay(3));
+        let action = run_service_stop(supervisor, clock);
+        assert_eq!(ActionState::Done, *action.state());
+        let payload = action.state_payload().clone().unwrap();
+        assert_eq!(payload["attempt"], json!(2));
+        assert_eq!(payload["pid"], json!(null));
+    }
+
+    /// Run the engine loop with a fencing action and return the updated record.
+    fn run_fencing(kind: &str, fencer: Arc<MockFencer>) -> ActionRecord {
+        let action = ActionRecord::new(
+            kind.to_string(),
+            None,
+            None,
+            json!({}),
+            ActionRequester::AgentApi,
+        );
+        let id = action.id.to_string();
+        let context = AgentContext::mock();
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+        let fencer: Arc<dyn Fencer> = fencer;
+        let mut register = ActionsRegister::default();
+        register.register_reserved(NodeFence::new(&fencer));
+        register.register_reserved(NodeUnfence::new(&fencer));
+        ACTIONS::test_with(register, || {
+            let clock = Arc::new(MockClock::new());
+            let mut engine = EngineLoop::new(context.clone(), clock);
+            for _ in 0..=MAX_ATTEMPT_FENCE {
+                engine.poll();
+                block_on(engine.wait());
+            }
+        });
+        context
+            .store
+            .with_transaction(|tx| tx.action().get(&id, None))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn node_fence_gives_up_on_stuck_fencer() {
+        let fencer = Arc::new(MockFencer::new(false));
+        let action = run_fencing("replicante.io/node.fence", Arc::clone(&fencer));
+        assert_eq!(ActionState::Failed, *action.state());
+        let payload = action.state_payload().clone().unwrap();
+        assert_eq!(payload["attempt"], json!(MAX_ATTEMPT_FENCE));
+        assert_eq!(payload["message"], json!("the node was not fenced in time"));
+        let calls = fencer.calls();
+        assert_eq!(calls.iter().filter(|call| **call == "fence").count(), 1);
+    }
+
+    #[test]
+    fn node_fence_waits_for_slow_fencer() {
+        let fencer = Arc::new(MockFencer::new(false).delay(3));
+        let action = run_fencing("replicante.io/node.fence", fencer);
+        assert_eq!(ActionState::Done, *action.state());
+        let payload = action.state_payload().clone().unwrap();
+        assert_eq!(payload["attempt"], json!(2));
+        assert_eq!(payload["message"], json!("the node is fenced"));
+    }
+
+    #[test]
+    fn node_unfence() {
+        let fencer = Arc::new(MockFencer::new(true).delay(0));
+        let action = run_fencing("replicante.io/node.unfence", Arc::clone(&fencer));
+        assert_eq!(ActionState::Done, *action.state());
+        let payload = action.state_payload().clone().unwrap();
+        assert_eq!(payload["attempt"], json!(0));
+        assert_eq!(fencer.calls(), vec!["unfence", "fenced"]);
+    }
 }
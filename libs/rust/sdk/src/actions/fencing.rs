@@ -0,0 +1,385 @@
+//! Isolate the datastore node from the cluster and clients.
+//!
+//! The SDK implements the `replicante.io/node.fence` and `replicante.io/node.unfence`
+//! actions on top of a `Fencer`, provided by the agent (see `Agent::fencer`)
+//! or configured by users with the `fencing` option.
+use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use slog::error;
+use slog::Logger;
+
+use crate::command::spawn_group;
+use crate::command::wait_with_timeout;
+use crate::config::FencingConfig;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// Comment attached to iptables rules added by the agent, to find them again.
+const IPTABLES_COMMENT: &str = "replicante-fence";
+
+/// Maximum time, in seconds, fencing commands can run for before they are killed.
+const COMMAND_TIMEOUT: u64 = 60;
+
+/// Instantiate a fencer based on the provided configuration.
+pub fn factory(logger: &Logger, fencing: FencingConfig) -> Arc<dyn Fencer> {
+    let logger = logger.clone();
+    match fencing {
+        FencingConfig::Commands(options) => Arc::new(CommandsFencer {
+            fence: options.fence,
+            logger,
+            status: options.status,
+            unfence: options.unfence,
+        }),
+        FencingConfig::Iptables(options) => Arc::new(IptablesFencer {
+            chain: options.chain,
+            logger,
+            ports: options.ports,
+        }),
+        FencingConfig::Systemd(options) => Arc::new(SystemdFencer {
+            logger,
+            unit: options.unit,
+        }),
+    }
+}
+
+/// Interface to isolate the datastore node.
+///
+/// Implementations must be idempotent: fencing a fenced node or unfencing
+/// a node that is not fenced should return successfully.
+pub trait Fencer: Send + Sync {
+    /// Attempt to fence the node.
+    ///
+    /// This method MAY return before the node is fully fenced.
+    fn fence(&self) -> Result<()>;
+
+    /// Check if the node is currently fenced.
+    fn fenced(&self) -> Result<bool>;
+
+    /// Attempt to restore access to the node.
+    ///
+    /// This method MAY return before access is fully restored.
+    fn unfence(&self) -> Result<()>;
+}
+
+#[cfg(test)]
+pub use self::mock::MockFencer;
+
+/// Fence the node by executing user-provided commands.
+struct CommandsFencer {
+    fence: Vec<String>,
+    logger: Logger,
+    status: Vec<String>,
+    unfence: Vec<String>,
+}
+
+impl Fencer for CommandsFencer {
+    fn fence(&self) -> Result<()> {
+        let output = run("fence", &self.fence)?;
+        check_success("fence", output, &self.logger)
+    }
+
+    fn fenced(&self) -> Result<bool> {
+        let output = run("status", &self.status)?;
+        match output.status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => check_success("status", output, &self.logger).map(|_| false),
+        }
+    }
+
+    fn unfence(&self) -> Result<()> {
+        let output = run("unfence", &self.unfence)?;
+        check_success("unfence", output, &self.logger)
+    }
+}
+
+/// Fence the node by rejecting incoming TCP connections to datastore ports.
+struct IptablesFencer {
+    chain: String,
+    logger: Logger,
+    ports: Vec<u16>,
+}
+
+impl IptablesFencer {
+    /// Build the iptables command to operate on the rule for a port.
+    ///
+    /// Connections over the loopback interface are not rejected so local tools
+    /// (including the agent itself) can still reach the datastore.
+    fn rule(&self, op: &str, port: u16) -> Vec<String> {
+        vec![
+            "iptables".into(),
+            op.into(),
+            self.chain.clone(),
+            "!".into(),
+            "-i".into(),
+            "lo".into(),
+            "-p".into(),
+            "tcp".into(),
+            "--dport".into(),
+            port.to_string(),
+            "-m".into(),
+            "comment".into(),
+            "--comment".into(),
+            IPTABLES_COMMENT.into(),
+            "-j".into(),
+            "REJECT".into(),
+        ]
+    }
+
+    /// Check if the rule for a port is in place.
+    fn rule_exists(&self, op: &'static str, port: u16) -> Result<bool> {
+        let output = run(op, &self.rule("-C", port))?;
+        Ok(output.status.success())
+    }
+}
+
+impl Fencer for IptablesFencer {
+    fn fence(&self) -> Result<()> {
+        for port in &self.ports {
+            if self.rule_exists("fence", *port)? {
+                continue;
+            }
+            let output = run("fence", &self.rule("-I", *port))?;
+            check_success("fence", output, &self.logger)?;
+        }
+        Ok(())
+    }
+
+    fn fenced(&self) -> Result<bool> {
+        for port in &self.ports {
+            if !self.rule_exists("status", *port)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn unfence(&self) -> Result<()> {
+        for port in &self.ports {
+            // Remove all copies of the rule in case it was added more than once.
+            while self.rule_exists("unfence", *port)? {
+                let output = run("unfence", &self.rule("-D", *port))?;
+                check_success("unfence", output, &self.logger)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fence the node by stopping the systemd unit listening for connections.
+struct SystemdFencer {
+    logger: Logger,
+    unit: String,
+}
+
+impl Fencer for SystemdFencer {
+    fn fence(&self) -> Result<()> {
+        let command = ["systemctl", "stop", "--no-block", &self.unit];
+        let output = run("fence", &command)?;
+        check_success("fence", output, &self.logger)
+    }
+
+    fn fenced(&self) -> Result<bool> {
+        // is-active exits with 0 only if the unit is active but units that are
+        // still starting, stopping or reloading also exit with an error.
+        let command = ["systemctl", "is-active", &self.unit];
+        let output = run("status", &command)?;
+        let state = String::from_utf8_lossy(&output.stdout);
+        Ok(systemd_unit_stopped(&state))
+    }
+
+    fn unfence(&self) -> Result<()> {
+        let command = ["systemctl", "start", "--no-block", &self.unit];
+        let output = run("unfence", &command)?;
+        check_success("unfence", output, &self.logger)
+    }
+}
+
+/// Check a fencing command succeeded, logging its standard error otherwise.
+fn check_success(op: &'static str, output: Output, logger: &Logger) -> Result<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    error!(logger, "Node fencing command failed"; "op" => op, "stderr" => %stderr);
+    Err(ErrorKind::FencingOpFailed(op).into())
+}
+
+/// Run a fencing command and collect its output.
+///
+/// Commands that do not complete within `COMMAND_TIMEOUT` are killed, along with
+/// any process they started, and the operation fails.
+fn run<S: AsRef<str>>(op: &'static str, command: &[S]) -> Result<Output> {
+    crate::faults::supervisor()?;
+    let args = command[1..].iter().map(AsRef::as_ref);
+    let mut command = Command::new(command[0].as_ref());
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let child = spawn_group(&mut command).with_context(|_| ErrorKind::FencingOpFailed(op))?;
+    let timeout = Duration::from_secs(COMMAND_TIMEOUT);
+    let (output, timed_out) =
+        wait_with_timeout(child, timeout).with_context(|_| ErrorKind::FencingOpFailed(op))?;
+    if timed_out {
+        return Err(ErrorKind::FencingOpTimeout(op, COMMAND_TIMEOUT).into());
+    }
+    Ok(output)
+}
+
+/// Check if the state reported by `systemctl is-active` is of a stopped unit.
+fn systemd_unit_stopped(state: &str) -> bool {
+    matches!(state.trim(), "inactive" | "failed")
+}
+
+#[cfg(test)]
+mod mock {
+    use std::sync::Mutex;
+
+    use super::Fencer;
+    use crate::Result;
+
+    /// Controllable fencer to test fencing actions.
+    ///
+    /// Fence and unfence requests take effect after a configurable number of
+    /// `fenced` checks, or never if no delay is set, to simulate slow or stuck fencers.
+    pub struct MockFencer {
+        delay: Option<u32>,
+        state: Mutex<MockFence>,
+    }
+
+    struct MockFence {
+        calls: Vec<&'static str>,
+        fenced: bool,
+        pending: Option<(bool, Option<u32>)>,
+    }
+
+    impl MockFencer {
+        pub fn new(fenced: bool) -> MockFencer {
+            let state = MockFence {
+                calls: Vec::new(),
+                fenced,
+                pending: None,
+            };
+            MockFencer {
+                delay: None,
+                state: Mutex::new(state),
+            }
+        }
+
+        /// Number of `fenced` checks before fence and unfence requests take effect.
+        pub fn delay(mut self, checks: u32) -> MockFencer {
+            self.delay = Some(checks);
+            self
+        }
+
+        /// List of fencer methods invoked so far.
+        pub fn calls(&self) -> Vec<&'static str> {
+            self.state.lock().unwrap().calls.clone()
+        }
+
+        fn request(&self, call: &'static str, fenced: bool) {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(call);
+            if state.fenced == fenced {
+                return;
+            }
+            match self.delay {
+                Some(0) => state.fenced = fenced,
+                delay => state.pending = Some((fenced, delay)),
+            }
+        }
+    }
+
+    impl Fencer for MockFencer {
+        fn fence(&self) -> Result<()> {
+            self.request("fence", true);
+            Ok(())
+        }
+
+        fn fenced(&self) -> Result<bool> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push("fenced");
+            match state.pending.take() {
+                Some((fenced, Some(checks))) if checks <= 1 => state.fenced = fenced,
+                Some((fenced, Some(checks))) => state.pending = Some((fenced, Some(checks - 1))),
+                pending => state.pending = pending,
+            }
+            Ok(state.fenced)
+        }
+
+        fn unfence(&self) -> Result<()> {
+            self.request("unfence", false);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::o;
+    use slog::Discard;
+    use slog::Logger;
+
+    use super::systemd_unit_stopped;
+    use super::CommandsFencer;
+    use super::Fencer;
+    use super::IptablesFencer;
+
+    fn commands(status: &str) -> CommandsFencer {
+        CommandsFencer {
+            fence: vec!["true".into()],
+            logger: Logger::root(Discard, o!()),
+            status: vec!["sh".into(), "-c".into(), status.into()],
+            unfence: vec!["false".into()],
+        }
+    }
+
+    #[test]
+    fn commands_fencer_status_codes() {
+        assert!(commands("exit 0").fenced().unwrap());
+        assert!(!commands("exit 1").fenced().unwrap());
+        let error = commands("exit 2").fenced().err().unwrap();
+        assert_eq!(error.kind().code(), "FencingOpFailed");
+    }
+
+    #[test]
+    fn commands_fencer_operations() {
+        let fencer = commands("exit 0");
+        assert!(fencer.fence().is_ok());
+        let error = fencer.unfence().err().unwrap();
+        assert_eq!(error.kind().code(), "FencingOpFailed");
+    }
+
+    #[test]
+    fn iptables_rule_spares_loopback() {
+        let fencer = IptablesFencer {
+            chain: "INPUT".into(),
+            logger: Logger::root(Discard, o!()),
+            ports: vec![27017],
+        };
+        let rule = fencer.rule("-I", 27017).join(" ");
+        assert_eq!(
+            rule,
+            "iptables -I INPUT ! -i lo -p tcp --dport 27017 \
+             -m comment --comment replicante-fence -j REJECT"
+        );
+    }
+
+    #[test]
+    fn systemd_stopped_states() {
+        assert!(systemd_unit_stopped("inactive\n"));
+        assert!(systemd_unit_stopped("failed\n"));
+        assert!(!systemd_unit_stopped("active\n"));
+        assert!(!systemd_unit_stopped("activating\n"));
+        assert!(!systemd_unit_stopped("deactivating\n"));
+        assert!(!systemd_unit_stopped("reloading\n"));
+        assert!(!systemd_unit_stopped(""));
+    }
+}
@@ -1,23 +1,45 @@
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use opentracingrust::Span;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
 use serde_json::Value as Json;
 use slog::debug;
 
+use crate::actions::utils::validate_action_args;
 use crate::actions::Action;
 use crate::actions::ActionDescriptor;
 use crate::actions::ActionRecordView;
 use crate::actions::ActionState;
 use crate::actions::ActionValidity;
+use crate::actions::ActionValidityError;
 use crate::actions::ACTIONS;
+use crate::faults;
+use crate::store::Store;
 use crate::store::Transaction;
 use crate::AgentContext;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
 /// Register debugging actions.
 pub fn register_debug_actions(context: &AgentContext) {
     debug!(context.logger, "Registering debug actions");
     ACTIONS::register_reserved(Fail {});
+    ACTIONS::register_reserved(FaultClear {});
+    ACTIONS::register_reserved(FaultLatency {});
+    ACTIONS::register_reserved(FaultStoreWrite {});
+    ACTIONS::register_reserved(FaultSupervisor {});
     ACTIONS::register_reserved(Progress {});
+    let backup_dir = context.config.actions.backup_dir.as_ref();
+    ACTIONS::register_reserved(StoreBackup {
+        dir: backup_dir.map(PathBuf::from),
+        store: context.store.clone(),
+    });
     ACTIONS::register_reserved(Success {});
 }
 
@@ -47,6 +69,206 @@ impl Action for Fail {
     }
 }
 
+/// Arguments of fault injection actions that fail a number of operations.
+#[derive(Deserialize)]
+struct FaultCountArgs {
+    count: u32,
+}
+
+impl FaultCountArgs {
+    fn schema() -> Json {
+        json!({
+            "type": "object",
+            "properties": {
+                "count": {"type": "integer", "minimum": 0},
+            },
+            "required": ["count"],
+        })
+    }
+}
+
+/// Arguments of the datastore latency fault injection action.
+#[derive(Deserialize)]
+struct FaultLatencyArgs {
+    /// Duration (in seconds) of the injected fault.
+    duration: u64,
+
+    /// Latency (in milliseconds) added to datastore requests.
+    latency: u64,
+}
+
+impl FaultLatencyArgs {
+    fn schema() -> Json {
+        json!({
+            "type": "object",
+            "properties": {
+                "duration": {"type": "integer", "minimum": 0},
+                "latency": {"type": "integer", "minimum": 0},
+            },
+            "required": ["duration", "latency"],
+        })
+    }
+}
+
+/// Decode the arguments of a debugging action.
+fn action_args<T>(record: &dyn ActionRecordView) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let args =
+        serde_json::from_value(record.args().clone()).with_context(|_| ErrorKind::ActionDecode)?;
+    Ok(args)
+}
+
+/// Debugging action that removes all injected faults.
+pub(crate) struct FaultClear {}
+
+impl Action for FaultClear {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "agent.replicante.io/debug.fault.clear".into(),
+            description: "Debugging action that removes all injected faults".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        faults::clear();
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            None,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn validate_args(&self, _: &Json) -> ActionValidity {
+        Ok(())
+    }
+}
+
+/// Debugging action that slows down datastore requests for a while.
+pub(crate) struct FaultLatency {}
+
+impl Action for FaultLatency {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "agent.replicante.io/debug.fault.latency".into(),
+            description: "Debugging action that slows down datastore requests".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args: FaultLatencyArgs = action_args(record)?;
+        faults::inject_datastore_latency(
+            Duration::from_millis(args.latency),
+            Duration::from_secs(args.duration),
+        );
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            None,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn args_schema(&self) -> Option<Json> {
+        Some(FaultLatencyArgs::schema())
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        validate_action_args::<FaultLatencyArgs>(args.clone())?;
+        Ok(())
+    }
+}
+
+/// Debugging action that fails the next store writes.
+///
+/// The action itself completes successfully because the fault is only
+/// injected once the action state is persisted.
+pub(crate) struct FaultStoreWrite {}
+
+impl Action for FaultStoreWrite {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "agent.replicante.io/debug.fault.store_write".into(),
+            description: "Debugging action that fails the next store writes".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args: FaultCountArgs = action_args(record)?;
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            None,
+            span.map(|span| span.context().clone()),
+        )?;
+        faults::inject_store_write_errors(args.count);
+        Ok(())
+    }
+
+    fn args_schema(&self) -> Option<Json> {
+        Some(FaultCountArgs::schema())
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        validate_action_args::<FaultCountArgs>(args.clone())?;
+        Ok(())
+    }
+}
+
+/// Debugging action that fails the next service supervisor calls.
+pub(crate) struct FaultSupervisor {}
+
+impl Action for FaultSupervisor {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "agent.replicante.io/debug.fault.supervisor".into(),
+            description: "Debugging action that fails the next service supervisor calls".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args: FaultCountArgs = action_args(record)?;
+        faults::inject_supervisor_errors(args.count);
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            None,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn args_schema(&self) -> Option<Json> {
+        Some(FaultCountArgs::schema())
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        validate_action_args::<FaultCountArgs>(args.clone())?;
+        Ok(())
+    }
+}
+
 /// Debugging action that progresses over time.
 pub(crate) struct Progress {}
 
@@ -82,6 +304,91 @@ impl Action for Progress {
     }
 }
 
+/// Arguments of the store backup debugging action.
+#[derive(Deserialize)]
+struct StoreBackupArgs {
+    /// Name of the file, in `actions.backup_dir`, to write the store snapshot to.
+    name: String,
+}
+
+impl StoreBackupArgs {
+    fn schema() -> Json {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+            },
+            "required": ["name"],
+        })
+    }
+}
+
+/// Debugging action that writes a snapshot of the agent store.
+///
+/// Snapshots are only written to the `actions.backup_dir` directory.
+/// The snapshot is taken before the action completes so restored
+/// stores include this action as still running.
+pub(crate) struct StoreBackup {
+    dir: Option<PathBuf>,
+    store: Store,
+}
+
+impl StoreBackup {
+    /// Path to write the snapshot to, if backups are enabled and the name is a plain file name.
+    fn path(&self, name: &str) -> ActionValidity<PathBuf> {
+        let dir = self.dir.as_ref().ok_or_else(|| {
+            let error = "store backups are disabled, set actions.backup_dir to enable them";
+            ActionValidityError::InvalidArgs(error.into())
+        })?;
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => Ok(dir.join(name)),
+            _ => {
+                let error = format!("backup name '{}' must be a plain file name", name);
+                Err(ActionValidityError::InvalidArgs(error))
+            }
+        }
+    }
+}
+
+impl Action for StoreBackup {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "agent.replicante.io/debug.store.backup".into(),
+            description: "Debugging action that writes a snapshot of the agent store".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        let args: StoreBackupArgs = action_args(record)?;
+        let path = self
+            .path(&args.name)
+            .map_err(|error| ErrorKind::FreeForm(error.to_string()))?;
+        self.store.backup(&path)?;
+        tx.action().transition(
+            record,
+            ActionState::Done,
+            None,
+            span.map(|span| span.context().clone()),
+        )
+    }
+
+    fn args_schema(&self) -> Option<Json> {
+        Some(StoreBackupArgs::schema())
+    }
+
+    fn validate_args(&self, args: &Json) -> ActionValidity {
+        let args = validate_action_args::<StoreBackupArgs>(args.clone())?;
+        self.path(&args.name)?;
+        Ok(())
+    }
+}
+
 /// Debugging action that always succeed.
 pub(crate) struct Success {}
 
@@ -111,3 +418,36 @@ impl Action for Success {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::StoreBackup;
+    use crate::store::Store;
+
+    #[test]
+    fn backup_names() {
+        let backup = StoreBackup {
+            dir: Some(PathBuf::from("/var/backups/agent")),
+            store: Store::mock(),
+        };
+        assert_eq!(
+            backup.path("snapshot.db").unwrap(),
+            PathBuf::from("/var/backups/agent/snapshot.db")
+        );
+        assert!(backup.path("/etc/passwd").is_err());
+        assert!(backup.path("../snapshot.db").is_err());
+        assert!(backup.path("nested/snapshot.db").is_err());
+        assert!(backup.path("").is_err());
+    }
+
+    #[test]
+    fn backups_disabled_without_dir() {
+        let backup = StoreBackup {
+            dir: None,
+            store: Store::mock(),
+        };
+        assert!(backup.path("snapshot.db").is_err());
+    }
+}
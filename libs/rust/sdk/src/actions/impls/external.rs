@@ -1,14 +1,28 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::ffi::CString;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
 use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::path::PathBuf;
 use std::process::Command;
 use std::process::Output;
 use std::process::Stdio;
+use std::time::Duration;
 
-use failure::ResultExt;
 use opentracingrust::Span;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::Value as Json;
+use sha2::Digest;
+use sha2::Sha256;
 use slog::debug;
 use slog::Logger;
 use uuid::Uuid;
@@ -19,14 +33,23 @@ use crate::actions::ActionRecordView;
 use crate::actions::ActionState;
 use crate::actions::ActionValidity;
 use crate::actions::ACTIONS;
+use crate::command::spawn_group;
+use crate::command::wait_with_timeout;
+use crate::config::ExternalActionChecksums;
 use crate::config::ExternalActionConfig;
+use crate::config::ExternalActionEnv;
 use crate::store::Transaction;
 use crate::AgentContext;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
 pub fn register(context: &AgentContext) -> Result<()> {
     debug!(context.logger, "Registering configured external actions");
+    let allowlist = match &context.config.actions.external_allowlist {
+        None => None,
+        Some(path) => Some(load_allowlist(path)?),
+    };
     for (kind, config) in &context.config.external_actions {
         if config.action.is_empty() {
             return Err(ErrorKind::Initialisation(format!(
@@ -42,8 +65,35 @@ pub fn register(context: &AgentContext) -> Result<()> {
             ))
             .into());
         }
+        let user = match &config.user {
+            None => None,
+            Some(name) => {
+                let user = users::get_user_by_name(name).ok_or_else(|| {
+                    ErrorKind::Initialisation(format!(
+                        "unknown user {} for external_actions.{}",
+                        name, kind
+                    ))
+                })?;
+                Some((user.uid(), user.primary_group_id()))
+            }
+        };
+        let checksums = match &allowlist {
+            None => None,
+            Some(allowlist) => {
+                let checksums = allowlist.get(kind).cloned().ok_or_else(|| {
+                    ErrorKind::Initialisation(format!(
+                        "external_actions.{} is not in the external actions allowlist",
+                        kind
+                    ))
+                })?;
+                Some(checksums)
+            }
+        };
         let kind = format!("external.agent.replicante.io/{}", kind);
-        let action = ExternalAction::new(kind, config.clone(), context.logger.clone());
+        let mut action = ExternalAction::new(kind, config.clone(), context.logger.clone());
+        action.checksums = checksums;
+        action.output_limit = context.config.actions.output_limit;
+        action.user = user;
         ACTIONS::register_reserved(action);
     }
     Ok(())
@@ -52,59 +102,90 @@ pub fn register(context: &AgentContext) -> Result<()> {
 /// Execute user-defined actions by executing commands.
 #[derive(Debug)]
 pub struct ExternalAction {
+    checksums: Option<ExternalActionChecksums>,
     config: ExternalActionConfig,
     kind: String,
     logger: Logger,
+    output_limit: usize,
+    user: Option<(u32, u32)>,
 }
 
 impl ExternalAction {
     pub fn new(kind: String, config: ExternalActionConfig, logger: Logger) -> ExternalAction {
         ExternalAction {
+            checksums: None,
             config,
             kind,
             logger,
+            output_limit: 0,
+            user: None,
         }
     }
 
+    /// Decode command output for storage, truncating it to the configured limit.
+    fn output_text(&self, output: &[u8]) -> String {
+        let text =
+            String::from_utf8(output.to_vec()).unwrap_or_else(|_| "{binary blob}".to_string());
+        truncate_output(text, self.output_limit)
+    }
+
     fn check_action(
         &self,
         tx: &mut Transaction,
         record: &dyn ActionRecordView,
         span: Option<&mut Span>,
     ) -> Result<()> {
-        let output = self.exec(record, &self.config.check, ErrorKind::ExternalActionCheck)?;
+        let checksum = self.checksums.as_ref().map(|checksums| &checksums.check);
+        let verified = self.verify(record, &self.config.check, checksum)?;
+        let output = self.exec(
+            record,
+            &self.config.check,
+            verified,
+            ErrorKind::ExternalActionCheck,
+        )?;
         let action_id = <dyn ActionRecordView>::id(record);
-        let stdout =
-            String::from_utf8(output.stdout).unwrap_or_else(|_| "{binary blob}".to_string());
         if !output.status.success() {
-            let stderr =
-                String::from_utf8(output.stderr).unwrap_or_else(|_| "{binary blob}".to_string());
+            let stdout = self.output_text(&output.stdout);
+            let stderr = self.output_text(&output.stderr);
             let error = ErrorKind::ExternalActionCheckResult(action_id, stdout, stderr);
             return Err(error.into());
         }
-        let report: ExternalActionReport = serde_json::from_str(&stdout)
+        let report: ExternalActionReport = serde_json::from_slice(&output.stdout)
             .with_context(|_| ErrorKind::ExternalActionCheckDecode(action_id))?;
+        let span = span.map(|span| span.context().clone());
+        for line in report.logs() {
+            tx.action().log(record, line, span.clone())?;
+        }
         match report {
-            ExternalActionReport::Failed(report) => tx.action().transition(
-                record,
-                ActionState::Failed,
-                serde_json::to_value(&report).expect("report serialisation must succeed"),
-                span.map(|span| span.context().clone()),
-            ),
-            ExternalActionReport::Finished => tx.action().transition(
-                record,
-                ActionState::Done,
-                None,
-                span.map(|span| span.context().clone()),
-            ),
-            ExternalActionReport::Running => Ok(()),
+            ExternalActionReport::Failed(mut report) => {
+                report.error = report
+                    .error
+                    .map(|error| truncate_output(error, self.output_limit));
+                let stderr = self.output_text(&output.stderr);
+                if !stderr.trim().is_empty() {
+                    report.stderr = Some(stderr);
+                }
+                tx.action().transition(
+                    record,
+                    ActionState::Failed,
+                    serde_json::to_value(&report).expect("report serialisation must succeed"),
+                    span,
+                )
+            }
+            ExternalActionReport::Finished(_) => {
+                tx.action()
+                    .transition(record, ActionState::Done, None, span)
+            }
+            ExternalActionReport::Running(_) => Ok(()),
         }
     }
 
+    /// Execute a command, or its verified version if the action has an allowlist entry.
     fn exec<F>(
         &self,
         record: &dyn ActionRecordView,
         command: &[String],
+        verified: Option<VerifiedCommand>,
         error_kind: F,
     ) -> Result<Output>
     where
@@ -119,24 +200,57 @@ impl ExternalAction {
         };
         let info =
             serde_json::to_vec(&info).with_context(|_| error_kind(self.kind.clone(), action_id))?;
-        let cmd = &command[0];
-        let args = &command[1..];
-        let mut child = Command::new(cmd)
+        let original = &command[0];
+        let (cmd, args) = match &verified {
+            None => (&command[0], &command[1..]),
+            Some(verified) => (&verified.command[0], &verified.command[1..]),
+        };
+        let mut command = Command::new(cmd);
+        if let Some(verified) = &verified {
+            command.arg0(original);
+            verified.inherit_files(&mut command);
+        }
+        command
             .args(args)
             .stderr(Stdio::piped())
             .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .with_context(|_| error_kind(self.kind.clone(), action_id))?;
+            .stdout(Stdio::piped());
+        for (name, value) in &self.config.env {
+            let value =
+                resolve_env(value).with_context(|_| error_kind(self.kind.clone(), action_id))?;
+            command.env(name, value);
+        }
+        if let Some(workdir) = &self.config.workdir {
+            command.current_dir(workdir);
+        }
+        if let Some((uid, gid)) = self.user {
+            command.uid(uid).gid(gid);
+        }
+        let mut child =
+            spawn_group(&mut command).with_context(|_| error_kind(self.kind.clone(), action_id))?;
+        // The child has its own copy of the sealed files once spawned.
+        drop(verified);
         {
-            let stdin = child.stdin.as_mut().expect("failed to open stdin");
+            let mut stdin = child.stdin.take().expect("failed to open stdin");
             stdin
                 .write_all(&info)
                 .with_context(|_| error_kind(self.kind.clone(), action_id))?;
         }
-        let output = child
-            .wait_with_output()
+        let timeout = match self.config.timeout {
+            None => {
+                let output = child
+                    .wait_with_output()
+                    .with_context(|_| error_kind(self.kind.clone(), action_id))?;
+                return Ok(output);
+            }
+            Some(timeout) => timeout,
+        };
+        let (output, timed_out) = wait_with_timeout(child, Duration::from_secs(timeout))
             .with_context(|_| error_kind(self.kind.clone(), action_id))?;
+        if timed_out {
+            let stderr = self.output_text(&output.stderr);
+            return Err(ErrorKind::ExternalActionTimeout(action_id, timeout, stderr).into());
+        }
         Ok(output)
     }
 
@@ -146,9 +260,15 @@ impl ExternalAction {
         record: &dyn ActionRecordView,
         span: Option<&mut Span>,
     ) -> Result<()> {
-        let output = self.exec(record, &self.config.action, ErrorKind::ExternalActionStart)?;
-        let stdout =
-            String::from_utf8(output.stdout).unwrap_or_else(|_| "{binary blob}".to_string());
+        let checksum = self.checksums.as_ref().map(|checksums| &checksums.action);
+        let verified = self.verify(record, &self.config.action, checksum)?;
+        let output = self.exec(
+            record,
+            &self.config.action,
+            verified,
+            ErrorKind::ExternalActionStart,
+        )?;
+        let stdout = self.output_text(&output.stdout);
         let action_id = <dyn ActionRecordView>::id(record);
         debug!(
             self.logger,
@@ -158,8 +278,7 @@ impl ExternalAction {
             "stdout" => &stdout,
         );
         if !output.status.success() {
-            let stderr =
-                String::from_utf8(output.stderr).unwrap_or_else(|_| "{binary blob}".to_string());
+            let stderr = self.output_text(&output.stderr);
             let error = ErrorKind::ExternalActionExec(action_id, stdout, stderr);
             return Err(error.into());
         }
@@ -170,6 +289,54 @@ impl ExternalAction {
             span.map(|span| span.context().clone()),
         )
     }
+
+    /// Resolve the `PATH` commands are executed with, if set for the action.
+    fn search_path(&self) -> std::result::Result<Option<String>, anyhow::Error> {
+        self.config.env.get("PATH").map(resolve_env).transpose()
+    }
+
+    /// Verify the files run by a command against their allowlisted checksums, if any.
+    ///
+    /// The executable and any argument naming an existing file must match their checksum.
+    /// The returned command runs sealed copies of the verified files so they can't be
+    /// replaced between verification and execution.
+    fn verify(
+        &self,
+        record: &dyn ActionRecordView,
+        command: &[String],
+        checksum: Option<&String>,
+    ) -> Result<Option<VerifiedCommand>> {
+        let (checksum, files) = match (checksum, &self.checksums) {
+            (Some(checksum), Some(checksums)) => (checksum, &checksums.files),
+            _ => return Ok(None),
+        };
+        let action_id = <dyn ActionRecordView>::id(record);
+        let workdir = self.config.workdir.as_deref();
+        let cmd = &command[0];
+        let error = |file: &String| ErrorKind::ExternalActionChecksum(action_id, file.clone());
+        let search_path = self.search_path().with_context(|_| error(cmd))?;
+        let path =
+            resolve_executable(cmd, workdir, search_path.as_deref()).ok_or_else(|| error(cmd))?;
+        let mut verified = VerifiedCommand::default();
+        verified
+            .seal(&path, checksum)
+            .with_context(|_| error(cmd))?;
+        for arg in &command[1..] {
+            // Listed files must exist and existing files must be listed.
+            let (path, checksum) = match (resolve_argument(arg, workdir), files.get(arg)) {
+                (None, None) => {
+                    verified.command.push(arg.clone());
+                    continue;
+                }
+                (Some(path), Some(checksum)) => (path, checksum),
+                _ => return Err(error(arg).into()),
+            };
+            verified
+                .seal(&path, checksum)
+                .with_context(|_| error(arg))?;
+        }
+        Ok(Some(verified))
+    }
 }
 
 impl Action for ExternalAction {
@@ -199,6 +366,44 @@ impl Action for ExternalAction {
     }
 }
 
+/// Command with sealed copies of the verified files it runs in place of the originals.
+#[derive(Default)]
+struct VerifiedCommand {
+    command: Vec<String>,
+    files: Vec<File>,
+}
+
+impl VerifiedCommand {
+    /// Make the sealed files available to the command once it is executed.
+    fn inherit_files(&self, command: &mut Command) {
+        let fds: Vec<RawFd> = self.files.iter().map(File::as_raw_fd).collect();
+        // SAFETY: fcntl is async-signal-safe and the descriptors are owned by self,
+        // which outlives the spawning of the command.
+        unsafe {
+            command.pre_exec(move || {
+                for fd in &fds {
+                    if libc::fcntl(*fd, libc::F_SETFD, 0) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Seal a copy of the file and reference it in the command if it matches the checksum.
+    fn seal(&mut self, path: &Path, checksum: &str) -> io::Result<()> {
+        let (file, actual) = sealed_copy(path)?;
+        if !actual.eq_ignore_ascii_case(checksum) {
+            let error = format!("checksum of {} is {}", path.display(), actual);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+        }
+        self.command.push(format!("/dev/fd/{}", file.as_raw_fd()));
+        self.files.push(file);
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct ExternalActionInfo {
     args: Json,
@@ -215,14 +420,386 @@ enum ExternalActionReport {
     Failed(ExternalActionFailed),
 
     #[serde(rename = "finished")]
-    Finished,
+    Finished(ExternalActionProgress),
 
     #[serde(rename = "running")]
-    Running,
+    Running(ExternalActionProgress),
+}
+
+impl ExternalActionReport {
+    /// Progress log lines to append to the action logs.
+    fn logs(&self) -> &[String] {
+        match self {
+            ExternalActionReport::Failed(report) => &report.logs,
+            ExternalActionReport::Finished(report) => &report.logs,
+            ExternalActionReport::Running(report) => &report.logs,
+        }
+    }
 }
 
 /// If the action failed, information about the error is expected.
 #[derive(Serialize, Deserialize)]
 struct ExternalActionFailed {
     error: Option<String>,
+
+    /// Progress log lines, appended to the action logs instead of the transition payload.
+    #[serde(default, skip_serializing)]
+    logs: Vec<String>,
+
+    /// Standard error of the check command, attached by the agent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stderr: Option<String>,
+}
+
+/// Progress reported by checks of running or finished actions.
+#[derive(Serialize, Deserialize)]
+struct ExternalActionProgress {
+    /// Progress log lines to append to the action logs.
+    #[serde(default)]
+    logs: Vec<String>,
+}
+
+/// Load the allowlist of external actions with the checksums of their commands.
+fn load_allowlist(path: &str) -> Result<BTreeMap<String, ExternalActionChecksums>> {
+    let file = File::open(path).with_context(|_| ErrorKind::Io(path.to_string()))?;
+    let allowlist: BTreeMap<String, ExternalActionChecksums> = serde_yaml::from_reader(file)
+        .with_context(|_| {
+            ErrorKind::Initialisation(format!("invalid external actions allowlist {}", path))
+        })?;
+    for (kind, checksums) in &allowlist {
+        let files = checksums.files.values();
+        for checksum in vec![&checksums.action, &checksums.check]
+            .into_iter()
+            .chain(files)
+        {
+            let valid = checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit());
+            if !valid {
+                return Err(ErrorKind::Initialisation(format!(
+                    "invalid SHA-256 checksum for {} in the external actions allowlist",
+                    kind
+                ))
+                .into());
+            }
+        }
+    }
+    Ok(allowlist)
+}
+
+/// Locate the file a command argument names, if it is an existing file.
+fn resolve_argument(arg: &str, workdir: Option<&str>) -> Option<PathBuf> {
+    if arg.is_empty() {
+        return None;
+    }
+    let path = Path::new(arg);
+    let path = match workdir {
+        Some(workdir) if path.is_relative() => Path::new(workdir).join(path),
+        _ => path.to_path_buf(),
+    };
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Locate the executable a command runs, searching `PATH` for bare names.
+///
+/// The `PATH` set for the command is searched if given, the agent's `PATH` otherwise.
+fn resolve_executable(
+    cmd: &str,
+    workdir: Option<&str>,
+    search_path: Option<&str>,
+) -> Option<PathBuf> {
+    if cmd.contains('/') {
+        let path = Path::new(cmd);
+        let path = match workdir {
+            Some(workdir) if path.is_relative() => Path::new(workdir).join(path),
+            _ => path.to_path_buf(),
+        };
+        return Some(path);
+    }
+    let paths = match search_path {
+        Some(paths) => OsString::from(paths),
+        None => std::env::var_os("PATH")?,
+    };
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(cmd))
+        .find(|path| path.is_file())
+}
+
+/// Resolve the value of an environment variable for external commands.
+fn resolve_env(value: &ExternalActionEnv) -> std::result::Result<String, anyhow::Error> {
+    let value = match value {
+        ExternalActionEnv::FromEnv { from_env } => std::env::var(from_env)?,
+        ExternalActionEnv::FromFile { from_file } => fs::read_to_string(from_file)?
+            .trim_end_matches(|c| c == '\n' || c == '\r')
+            .to_string(),
+        ExternalActionEnv::Value(value) => value.clone(),
+    };
+    Ok(value)
+}
+
+/// Copy a file into a sealed in-memory file and compute the SHA-256 checksum of the copy.
+///
+/// The file is read once so the checksum always matches the content of the copy.
+/// The returned file is read-only, close-on-exec and can't be changed by anyone.
+fn sealed_copy(path: &Path) -> io::Result<(File, String)> {
+    let content = fs::read(path)?;
+    let checksum = format!("{:x}", Sha256::digest(&content));
+    let name = CString::new("repliagent-external-action").expect("memfd name has no NUL bytes");
+    let flags = libc::MFD_ALLOW_SEALING | libc::MFD_CLOEXEC;
+    // SAFETY: name is a valid NUL terminated string that outlives the call.
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), flags) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just created and is not owned by anything else.
+    let mut memfd = unsafe { File::from_raw_fd(fd) };
+    memfd.write_all(&content)?;
+    let seals = libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+    // SAFETY: fcntl has no memory safety requirements.
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // Executing files open for writing fails so keep a read-only descriptor only.
+    let file = File::open(format!("/proc/self/fd/{}", fd))?;
+    Ok((file, checksum))
+}
+
+/// Truncate command output longer than `limit` bytes, keeping its head and tail.
+///
+/// A `limit` of 0 disables truncation.
+fn truncate_output(text: String, limit: usize) -> String {
+    if limit == 0 || text.len() <= limit {
+        return text;
+    }
+    let mut head = limit / 2;
+    while !text.is_char_boundary(head) {
+        head -= 1;
+    }
+    let mut tail = text.len() - (limit - limit / 2);
+    while !text.is_char_boundary(tail) {
+        tail += 1;
+    }
+    format!(
+        "{}\n[... {} bytes truncated ...]\n{}",
+        &text[..head],
+        tail - head,
+        &text[tail..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use super::register;
+    use super::resolve_env;
+    use super::resolve_executable;
+    use super::sealed_copy;
+    use super::truncate_output;
+    use super::ExternalAction;
+    use super::ExternalActionReport;
+    use crate::actions::ActionRecord;
+    use crate::actions::ActionRequester;
+    use crate::config::AgentConfig;
+    use crate::config::ExternalActionChecksums;
+    use crate::config::ExternalActionConfig;
+    use crate::config::ExternalActionEnv;
+    use crate::AgentContext;
+
+    const HELLO_SHA: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+
+    fn action_config(workdir: &PathBuf) -> ExternalActionConfig {
+        ExternalActionConfig {
+            action: vec!["sh".into(), "action.sh".into()],
+            check: vec!["sh".into(), "check.sh".into()],
+            description: "test".into(),
+            env: BTreeMap::new(),
+            timeout: None,
+            user: None,
+            workdir: Some(workdir.to_string_lossy().to_string()),
+        }
+    }
+
+    fn workdir(name: &str) -> PathBuf {
+        let workdir = std::env::temp_dir().join(name);
+        fs::create_dir_all(&workdir).unwrap();
+        fs::write(workdir.join("action.sh"), "hello\n").unwrap();
+        workdir
+    }
+
+    fn sh_checksum() -> String {
+        let sh = resolve_executable("sh", None, None).unwrap();
+        sealed_copy(&sh).unwrap().1
+    }
+
+    #[test]
+    fn resolve_env_value() {
+        let value = ExternalActionEnv::Value("test".into());
+        assert_eq!(resolve_env(&value).unwrap(), "test");
+    }
+
+    #[test]
+    fn resolve_executable_paths() {
+        let path = resolve_executable("bin/check.sh", Some("/opt/actions"), None).unwrap();
+        assert_eq!(path.to_str(), Some("/opt/actions/bin/check.sh"));
+        let path = resolve_executable("/usr/bin/check.sh", Some("/opt/actions"), None).unwrap();
+        assert_eq!(path.to_str(), Some("/usr/bin/check.sh"));
+        assert!(resolve_executable("sh", None, None).is_some());
+    }
+
+    #[test]
+    fn resolve_executable_with_command_path() {
+        let workdir = workdir("repliagent-external-action-path");
+        let search_path = workdir.to_string_lossy().to_string();
+        let path = resolve_executable("action.sh", None, Some(&search_path)).unwrap();
+        assert_eq!(path, workdir.join("action.sh"));
+        assert!(resolve_executable("sh", None, Some(&search_path)).is_none());
+    }
+
+    #[test]
+    fn sealed_copy_of_command() {
+        let path = std::env::temp_dir().join("repliagent-external-action.sh");
+        fs::write(&path, "hello\n").unwrap();
+        let (mut file, checksum) = sealed_copy(&path).unwrap();
+        fs::write(&path, "replaced\n").unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(checksum, HELLO_SHA);
+        let mut content = String::new();
+        file.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "hello\n");
+    }
+
+    #[test]
+    fn verify_seals_listed_files() {
+        let workdir = workdir("repliagent-external-action-listed");
+        let mut action = ExternalAction::new(
+            "test".into(),
+            action_config(&workdir),
+            AgentContext::mock().logger,
+        );
+        let mut files = BTreeMap::new();
+        files.insert("action.sh".to_string(), HELLO_SHA.to_string());
+        action.checksums = Some(ExternalActionChecksums {
+            action: sh_checksum(),
+            check: sh_checksum(),
+            files,
+        });
+        let record = ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+        let checksum = action.checksums.as_ref().map(|checksums| &checksums.action);
+        let verified = action
+            .verify(&record, &action.config.action, checksum)
+            .unwrap()
+            .unwrap();
+        assert_eq!(verified.files.len(), 2);
+        assert!(verified
+            .command
+            .iter()
+            .all(|arg| arg.starts_with("/dev/fd/")));
+    }
+
+    #[test]
+    fn verify_rejects_checksum_mismatch() {
+        let workdir = workdir("repliagent-external-action-mismatch");
+        let mut action = ExternalAction::new(
+            "test".into(),
+            action_config(&workdir),
+            AgentContext::mock().logger,
+        );
+        let mut files = BTreeMap::new();
+        files.insert("action.sh".to_string(), "0".repeat(64));
+        action.checksums = Some(ExternalActionChecksums {
+            action: sh_checksum(),
+            check: sh_checksum(),
+            files,
+        });
+        let record = ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+        let checksum = action.checksums.as_ref().map(|checksums| &checksums.action);
+        let error = action
+            .verify(&record, &action.config.action, checksum)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind().code(), "ExternalActionChecksum");
+    }
+
+    #[test]
+    fn verify_rejects_unlisted_files() {
+        let workdir = workdir("repliagent-external-action-unlisted");
+        let mut action = ExternalAction::new(
+            "test".into(),
+            action_config(&workdir),
+            AgentContext::mock().logger,
+        );
+        action.checksums = Some(ExternalActionChecksums {
+            action: sh_checksum(),
+            check: sh_checksum(),
+            files: BTreeMap::new(),
+        });
+        let record = ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+        let checksum = action.checksums.as_ref().map(|checksums| &checksums.action);
+        let error = action
+            .verify(&record, &action.config.action, checksum)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind().code(), "ExternalActionChecksum");
+    }
+
+    #[test]
+    fn register_rejects_unlisted_actions() {
+        let workdir = workdir("repliagent-external-action-register");
+        let allowlist = workdir.join("allowlist.yaml");
+        let content = format!(
+            "other:\n  action: '{}'\n  check: '{}'\n",
+            HELLO_SHA, HELLO_SHA
+        );
+        fs::write(&allowlist, content).unwrap();
+        let mut config = AgentConfig::mock();
+        config.actions.external_allowlist = Some(allowlist.to_string_lossy().to_string());
+        config
+            .external_actions
+            .insert("backup".into(), action_config(&workdir));
+        let context = AgentContext::mock_with_config(config);
+        let error = register(&context).err().unwrap();
+        assert_eq!(error.kind().code(), "Initialisation");
+    }
+
+    #[test]
+    fn decode_reports_with_logs() {
+        let report = r#"{"status": "running", "logs": ["copied 10 of 20 files"]}"#;
+        let report: ExternalActionReport = serde_json::from_str(report).unwrap();
+        assert_eq!(report.logs(), ["copied 10 of 20 files".to_string()]);
+        let report: ExternalActionReport =
+            serde_json::from_str(r#"{"status": "finished"}"#).unwrap();
+        assert!(report.logs().is_empty());
+        let report = r#"{"status": "failed", "error": "disk full", "logs": ["copy failed"]}"#;
+        let report: ExternalActionReport = serde_json::from_str(report).unwrap();
+        assert_eq!(report.logs(), ["copy failed".to_string()]);
+        match report {
+            ExternalActionReport::Failed(report) => {
+                let payload = serde_json::to_value(&report).unwrap();
+                assert_eq!(payload, json!({"error": "disk full"}));
+            }
+            _ => panic!("expected a failed report"),
+        }
+    }
+
+    #[test]
+    fn truncate_keeps_head_and_tail() {
+        let text = truncate_output("0123456789".into(), 4);
+        assert_eq!(text, "01\n[... 6 bytes truncated ...]\n89");
+        assert_eq!(truncate_output("0123".into(), 4), "0123");
+        assert_eq!(truncate_output("0123456789".into(), 0), "0123456789");
+    }
+
+    #[test]
+    fn truncate_respects_char_boundaries() {
+        let text = truncate_output("ààààà".into(), 5);
+        assert_eq!(text, "à\n[... 6 bytes truncated ...]\nàà");
+    }
 }
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use opentracingrust::Span;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value as Json;
+
+use crate::actions::fencing::Fencer;
+use crate::actions::Action;
+use crate::actions::ActionDescriptor;
+use crate::actions::ActionRecordView;
+use crate::actions::ActionState;
+use crate::actions::ActionValidity;
+use crate::actions::ACTIONS;
+use crate::store::Transaction;
+use crate::AgentContext;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// Number of checks before fencing operations are considered failed.
+pub(crate) const MAX_ATTEMPT_FENCE: u8 = 30;
+
+/// Persisted progress of fence/unfence actions.
+#[derive(Default, Serialize, Deserialize)]
+struct FenceActionState {
+    attempt: u8,
+    message: Option<String>,
+}
+
+/// Register node fencing actions.
+///
+/// The fencer configured by users takes precedence over the one provided by the agent.
+pub fn register(context: &AgentContext, fencer: Option<Arc<dyn Fencer>>) {
+    let fencer = match &context.config.fencing {
+        Some(fencing) => crate::actions::fencing::factory(&context.logger, fencing.clone()),
+        None => match fencer {
+            None => return,
+            Some(fencer) => fencer,
+        },
+    };
+    ACTIONS::register_reserved(NodeFence::new(&fencer));
+    ACTIONS::register_reserved(NodeUnfence::new(&fencer));
+}
+
+/// Isolate the node from the cluster and its clients.
+pub struct NodeFence {
+    fencer: Arc<dyn Fencer>,
+}
+
+impl NodeFence {
+    pub fn new(fencer: &Arc<dyn Fencer>) -> NodeFence {
+        let fencer = Arc::clone(fencer);
+        NodeFence { fencer }
+    }
+}
+
+impl Action for NodeFence {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "replicante.io/node.fence".into(),
+            description: "Isolate the node from the cluster and its clients".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        // If the action is new attempt to fence the node.
+        if *record.state() == ActionState::New {
+            self.fencer.fence()?;
+        }
+        let fenced = self.fencer.fenced()?;
+        progress(tx, record, span, fenced, "fenced")
+    }
+
+    fn validate_args(&self, _: &Json) -> ActionValidity {
+        Ok(())
+    }
+}
+
+/// Restore access to a fenced node.
+pub struct NodeUnfence {
+    fencer: Arc<dyn Fencer>,
+}
+
+impl NodeUnfence {
+    pub fn new(fencer: &Arc<dyn Fencer>) -> NodeUnfence {
+        let fencer = Arc::clone(fencer);
+        NodeUnfence { fencer }
+    }
+}
+
+impl Action for NodeUnfence {
+    fn describe(&self) -> ActionDescriptor {
+        ActionDescriptor {
+            kind: "replicante.io/node.unfence".into(),
+            description: "Restore access to a fenced node".into(),
+        }
+    }
+
+    fn invoke(
+        &self,
+        tx: &mut Transaction,
+        record: &dyn ActionRecordView,
+        span: Option<&mut Span>,
+    ) -> Result<()> {
+        // If the action is new attempt to unfence the node.
+        if *record.state() == ActionState::New {
+            self.fencer.unfence()?;
+        }
+        let fenced = self.fencer.fenced()?;
+        progress(tx, record, span, !fenced, "unfenced")
+    }
+
+    fn validate_args(&self, _: &Json) -> ActionValidity {
+        Ok(())
+    }
+}
+
+/// Transition fencing actions based on the outcome of the latest check.
+fn progress(
+    tx: &mut Transaction,
+    record: &dyn ActionRecordView,
+    span: Option<&mut Span>,
+    complete: bool,
+    target: &str,
+) -> Result<()> {
+    let mut progress: FenceActionState =
+        <dyn ActionRecordView>::structured_state_payload(record)?.unwrap_or_default();
+
+    // The node reached the target state.
+    let state = if complete {
+        progress.message = Some(format!("the node is {}", target));
+        ActionState::Done
+
+    // If we have been waiting too long fail.
+    } else if progress.attempt >= MAX_ATTEMPT_FENCE {
+        progress.message = Some(format!("the node was not {} in time", target));
+        ActionState::Failed
+
+    // Operation still in progress, record attempt and wait.
+    } else {
+        progress.attempt += 1;
+        ActionState::Running
+    };
+    let payload = serde_json::to_value(progress).with_context(|_| ErrorKind::ActionEncode)?;
+    tx.action().transition(
+        record,
+        state,
+        payload,
+        span.as_ref().map(|span| span.context().clone()),
+    )
+}
@@ -5,23 +5,27 @@ use slog::debug;
 
 use crate::actions::Action;
 use crate::actions::ActionHook;
+use crate::actions::Fencer;
 use crate::AgentContext;
 use crate::Result;
 
 #[cfg(any(debug_assertions, test))]
 pub(crate) mod debug;
 mod external;
-mod service;
+pub(crate) mod fence;
+pub(crate) mod service;
 mod test;
 
 /// Register standard agent actions.
 pub fn register_std_actions(
     context: &AgentContext,
     hooks: HashMap<ActionHook, Arc<dyn Action>>,
+    fencer: Option<Arc<dyn Fencer>>,
 ) -> Result<()> {
     debug!(context.logger, "Registering standard actions");
     let graceful = hooks.get(&ActionHook::StoreGracefulStop).cloned();
     self::external::register(context)?;
+    self::fence::register(context, fencer);
     self::service::register(context, graceful);
     self::test::register(context);
 
@@ -9,8 +9,8 @@ use crate::AgentContext;
 
 mod composed;
 mod start;
-mod stop;
-mod supervisor;
+pub(crate) mod stop;
+pub(crate) mod supervisor;
 
 use self::composed::GracefulRestart;
 use self::composed::GracefulStop;
@@ -1,6 +1,5 @@
 use std::sync::Arc;
 
-use failure::ResultExt;
 use opentracingrust::Span;
 use serde_json::Value as Json;
 
@@ -12,6 +11,7 @@ use crate::actions::ActionValidity;
 use crate::store::Transaction;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
 use super::supervisor::Supervisor;
 use super::ServiceActionState;
@@ -1,6 +1,5 @@
 use std::sync::Arc;
 
-use failure::ResultExt;
 use opentracingrust::Span;
 use serde_json::Value as Json;
 
@@ -12,12 +11,13 @@ use crate::actions::ActionValidity;
 use crate::store::Transaction;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
 use super::supervisor::Supervisor;
 use super::ServiceActionState;
 
 // This is a minimum of 30 seconds, maybe it should become a configuration option.
-const MAX_ATTEMPT_STOP: u8 = 30;
+pub(crate) const MAX_ATTEMPT_STOP: u8 = 30;
 
 /// Stop the datastore service.
 pub struct ServiceStop {
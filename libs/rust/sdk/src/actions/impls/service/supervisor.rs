@@ -1,13 +1,13 @@
 use std::process::Command;
 use std::sync::Arc;
 
-use failure::ResultExt;
 use slog::error;
 use slog::Logger;
 
 use crate::config::ServiceConfig;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
 /// Instantiate a service supervisor based on the provided configuration.
 pub fn factory(logger: &Logger, service: ServiceConfig) -> Arc<dyn Supervisor> {
@@ -46,6 +46,9 @@ pub trait Supervisor: Send + Sync {
     fn stop(&self) -> Result<()>;
 }
 
+#[cfg(test)]
+pub use self::mock::MockSupervisor;
+
 /// Type alias to command functions for brevity.
 type CmdFn<T> = Box<dyn Fn(&Logger) -> Result<T> + Send + Sync>;
 
@@ -84,14 +87,17 @@ impl CommandSupervisor {
 
 impl Supervisor for CommandSupervisor {
     fn pid(&self) -> Result<Option<String>> {
+        crate::faults::supervisor()?;
         (self.cmd_pid)(&self.logger)
     }
 
     fn start(&self) -> Result<()> {
+        crate::faults::supervisor()?;
         (self.cmd_start)(&self.logger)
     }
 
     fn stop(&self) -> Result<()> {
+        crate::faults::supervisor()?;
         (self.cmd_stop)(&self.logger)
     }
 }
@@ -208,3 +214,97 @@ fn systemd_stop(service_name: String) -> CmdFn<()> {
         Ok(())
     })
 }
+
+#[cfg(test)]
+mod mock {
+    use std::sync::Mutex;
+
+    use super::Supervisor;
+    use crate::Result;
+
+    /// Service state tracked by the `MockSupervisor`.
+    struct MockService {
+        calls: Vec<&'static str>,
+        pending: Option<(Option<String>, Option<u32>)>,
+        pid: Option<String>,
+    }
+
+    /// Controllable supervisor to test service actions.
+    ///
+    /// Start and stop requests take effect after a configurable number of `pid` checks,
+    /// or never if no delay is set, to simulate slow or stuck services.
+    pub struct MockSupervisor {
+        delay: Option<u32>,
+        service: Mutex<MockService>,
+    }
+
+    impl MockSupervisor {
+        /// Supervisor for a running service.
+        pub fn running(pid: &str) -> MockSupervisor {
+            MockSupervisor::new(Some(pid.to_string()))
+        }
+
+        /// Supervisor for a stopped service.
+        pub fn stopped() -> MockSupervisor {
+            MockSupervisor::new(None)
+        }
+
+        /// Number of `pid` checks before start and stop requests take effect.
+        pub fn delay(mut self, checks: u32) -> MockSupervisor {
+            self.delay = Some(checks);
+            self
+        }
+
+        /// List of supervisor methods invoked so far.
+        pub fn calls(&self) -> Vec<&'static str> {
+            self.service.lock().unwrap().calls.clone()
+        }
+
+        fn new(pid: Option<String>) -> MockSupervisor {
+            let service = MockService {
+                calls: Vec::new(),
+                pending: None,
+                pid,
+            };
+            MockSupervisor {
+                delay: None,
+                service: Mutex::new(service),
+            }
+        }
+
+        fn request(&self, call: &'static str, pid: Option<String>) {
+            let mut service = self.service.lock().unwrap();
+            service.calls.push(call);
+            if service.pid == pid {
+                return;
+            }
+            match self.delay {
+                Some(0) => service.pid = pid,
+                delay => service.pending = Some((pid, delay)),
+            }
+        }
+    }
+
+    impl Supervisor for MockSupervisor {
+        fn pid(&self) -> Result<Option<String>> {
+            let mut service = self.service.lock().unwrap();
+            service.calls.push("pid");
+            match service.pending.take() {
+                Some((pid, Some(checks))) if checks <= 1 => service.pid = pid,
+                Some((pid, Some(checks))) => service.pending = Some((pid, Some(checks - 1))),
+                pending => service.pending = pending,
+            }
+            Ok(service.pid.clone())
+        }
+
+        fn start(&self) -> Result<()> {
+            self.request("start", Some("4321".into()));
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<()> {
+            self.request("stop", None);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::RwLock;
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::Utc;
+use slog::info;
+use slog::warn;
+
+use replicante_util_failure::failure_info;
+
+use crate::actions::ActionState;
+use crate::metrics::ACTIONS_ENGINE_LAG;
+use crate::metrics::ACTIONS_ENGINE_LAG_ERRORS;
+use crate::runtime::blocking;
+use crate::runtime::AgentRuntime;
+use crate::AgentContext;
+
+/// Delay between checks of the oldest pending action.
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+lazy_static::lazy_static! {
+    /// Warning about pending actions waiting too long for the actions engine.
+    static ref LAG_WARNING: RwLock<Option<String>> = RwLock::new(None);
+}
+
+/// Warning about pending actions waiting longer than `actions.lag_threshold`, if any.
+///
+/// Lagging actions often mean the actions engine is stuck on an action that never completes.
+pub fn actions_lag_warning() -> Option<String> {
+    LAG_WARNING
+        .read()
+        .expect("LAG_WARNING lock poisoned")
+        .clone()
+}
+
+/// Start a task to periodically check the age of the oldest pending action.
+///
+/// The check runs outside of the actions engine so it keeps reporting while the engine is stuck.
+pub fn spawn(context: AgentContext, runtime: &mut AgentRuntime) {
+    runtime.spawn("actions lag", |mut shutdown| async move {
+        loop {
+            let check_context = context.clone();
+            if blocking(move || check(&check_context)).await.is_none() {
+                return;
+            }
+            tokio::select! {
+                _ = shutdown.requested() => return,
+                _ = tokio::time::sleep(CHECK_INTERVAL) => (),
+            }
+        }
+    });
+}
+
+/// Update the lag gauge and warning from the oldest pending action.
+fn check(context: &AgentContext) {
+    let oldest = context.store.with_transaction(|tx| {
+        let mut pending = None;
+        for item in tx.actions().queue(None)? {
+            let item = item?;
+            if item.state == ActionState::New {
+                pending = Some(item.id.to_string());
+                break;
+            }
+        }
+        match pending {
+            None => Ok(None),
+            Some(id) => Ok(tx
+                .action()
+                .get(&id, None)?
+                .map(|record| record.scheduled_ts)),
+        }
+    });
+    // Failed checks reset the lag so it is not reported based on stale data.
+    let oldest = match oldest {
+        Ok(oldest) => oldest,
+        Err(error) => {
+            ACTIONS_ENGINE_LAG_ERRORS.inc();
+            ACTIONS_ENGINE_LAG.set(0.0);
+            warn!(
+                context.logger,
+                "Failed to look up the oldest pending action";
+                failure_info(&error.to_fail()),
+            );
+            return;
+        }
+    };
+    let lag = oldest
+        .map(|scheduled| lag_seconds(scheduled, Utc::now()))
+        .unwrap_or(0.0);
+    ACTIONS_ENGINE_LAG.set(lag);
+
+    let warning = lag_warning(lag, context.config.actions.lag_threshold);
+    let mut current = LAG_WARNING.write().expect("LAG_WARNING lock poisoned");
+    match (current.is_some(), &warning) {
+        (true, None) => info!(context.logger, "Actions engine caught up"; "lag" => lag),
+        (false, Some(warning)) => warn!(
+            context.logger,
+            "Actions engine is lagging";
+            "lag" => lag,
+            "warning" => warning,
+        ),
+        _ => (),
+    }
+    *current = warning;
+}
+
+/// Time, in seconds, an action scheduled at the given time has been waiting for.
+fn lag_seconds(scheduled: DateTime<Utc>, now: DateTime<Utc>) -> f64 {
+    now.signed_duration_since(scheduled)
+        .to_std()
+        .map(|lag| lag.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Warning to report for the given lag, if above the threshold.
+fn lag_warning(lag: f64, threshold: u64) -> Option<String> {
+    if lag > threshold as f64 {
+        Some(format!(
+            "oldest pending action has been waiting for {:.0} seconds",
+            lag
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::Utc;
+
+    use super::lag_seconds;
+    use super::lag_warning;
+
+    #[test]
+    fn lag_from_scheduled_time() {
+        let now = Utc::now();
+        let scheduled = now - Duration::seconds(90);
+        assert!((lag_seconds(scheduled, now) - 90.0).abs() < 1e-9);
+        // Actions scheduled by a host with a clock ahead of ours are not lagging.
+        let future = now + Duration::seconds(10);
+        assert_eq!(lag_seconds(future, now), 0.0);
+    }
+
+    #[test]
+    fn warn_above_threshold() {
+        assert_eq!(lag_warning(300.0, 300), None);
+        assert_eq!(
+            lag_warning(301.0, 300),
+            Some("oldest pending action has been waiting for 301 seconds".into())
+        );
+    }
+}
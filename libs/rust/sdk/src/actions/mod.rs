@@ -1,41 +1,62 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
+#[cfg(feature = "actions")]
 use std::sync::Arc;
 
+#[cfg(feature = "actions")]
 use slog::debug;
+#[cfg(feature = "actions")]
 use slog::info;
+#[cfg(feature = "actions")]
 use slog::warn;
 
-use replicante_util_upkeep::Upkeep;
-
 use crate::config::Agent as Config;
+#[cfg(feature = "actions")]
+use crate::runtime::AgentRuntime;
+#[cfg(feature = "actions")]
 use crate::Agent;
+#[cfg(feature = "actions")]
 use crate::AgentContext;
 use crate::ErrorKind;
 use crate::Result;
 
 pub mod advanced;
+mod clock;
 mod definition;
+#[cfg(feature = "actions")]
 mod engine;
+#[cfg(feature = "actions")]
+mod fencing;
+#[cfg(feature = "actions")]
 mod impls;
+#[cfg(feature = "actions")]
+mod lag;
 mod register;
-#[cfg(test)]
+#[cfg(all(test, feature = "actions", feature = "api"))]
 mod tests;
 pub mod utils;
+mod wake;
 
 pub use self::definition::Action;
 pub use self::definition::ActionDescriptor;
 pub use self::definition::ActionHistoryItem;
 pub use self::definition::ActionHook;
 pub use self::definition::ActionListItem;
+pub use self::definition::ActionLogLine;
 pub use self::definition::ActionRecord;
 pub use self::definition::ActionRecordView;
 pub use self::definition::ActionRequester;
 pub use self::definition::ActionState;
 pub use self::definition::ActionValidity;
 pub use self::definition::ActionValidityError;
+#[cfg(feature = "actions")]
+pub use self::fencing::Fencer;
+#[cfg(feature = "actions")]
+pub use self::lag::actions_lag_warning;
 pub use self::register::ActionsRegister;
 pub use self::register::ACTIONS;
+pub use self::wake::ActionsProgress;
+pub use self::wake::ActionsWake;
 
 lazy_static::lazy_static! {
     /// Codified version of the state transitions from docs/docs/assets/action-states.dot
@@ -65,7 +86,17 @@ lazy_static::lazy_static! {
 ///   * Agent actions can be explicitly disabled with the `actions.enabled` option.
 ///   * An error is returned if `actions.enabled` is `true` but `tls.clients_ca_bundle`
 ///     is not set.
+///   * Agent actions are never enabled if the SDK is built without the `actions` feature.
 pub fn actions_enabled(config: &Config) -> Result<bool> {
+    if !cfg!(feature = "actions") {
+        if let Some(true) = config.actions.enabled {
+            return Err(ErrorKind::ConfigClash(
+                "can't enable actions without the SDK actions feature",
+            )
+            .into());
+        }
+        return Ok(false);
+    }
     if let Some(false) = config.actions.enabled {
         return Ok(false);
     }
@@ -88,26 +119,44 @@ pub fn actions_enabled(config: &Config) -> Result<bool> {
 
 /// Ensure the action state transition is allowed.
 ///
+/// Disallowed transitions return an `ActionTransitionNotAllowed` error so the engine
+/// can fail the action with diagnostics instead of bringing down its thread.
+///
 /// # Panics
-/// If the state transition is not allowed this function panics.
-pub fn ensure_transition_allowed(from: &ActionState, to: &ActionState) {
-    let allowed = ALLOWED_TRANSITIONS
-        .get(from)
-        .map(|from| from.contains(to))
-        .unwrap_or(false);
-    if !allowed {
+/// In debug builds this function panics if the state transition is not allowed
+/// to make bugs in action implementations obvious during development.
+pub fn ensure_transition_allowed(id: &str, from: &ActionState, to: &ActionState) -> Result<()> {
+    if transition_allowed(from, to) {
+        return Ok(());
+    }
+    if cfg!(debug_assertions) {
         panic!(
             "actions are not allowed to transition from {:?} to {:?}",
             from, to
         );
     }
+    let error = ErrorKind::ActionTransitionNotAllowed(
+        id.to_string(),
+        format!("{:?}", from),
+        format!("{:?}", to),
+    );
+    Err(error.into())
+}
+
+/// Check if the action state transition is allowed.
+pub fn transition_allowed(from: &ActionState, to: &ActionState) -> bool {
+    ALLOWED_TRANSITIONS
+        .get(from)
+        .map(|from| from.contains(to))
+        .unwrap_or(false)
 }
 
 /// Initialise the actions system based on configuration.
-pub fn initialise(
+#[cfg(feature = "actions")]
+pub(crate) fn initialise(
     agent: &dyn Agent,
     context: &mut AgentContext,
-    upkeep: &mut Upkeep,
+    runtime: &mut AgentRuntime,
 ) -> Result<()> {
     let enabled = actions_enabled(&context.config)?;
     if !enabled {
@@ -117,16 +166,21 @@ pub fn initialise(
 
     debug!(context.logger, "Initialising actions system ...");
     let hooks = self::register_agent_actions(agent, context);
-    self::impls::register_std_actions(context, hooks)?;
+    self::impls::register_std_actions(context, hooks, agent.fencer())?;
+    for (alias, kind) in &context.config.actions.aliases {
+        ACTIONS::register_alias(alias, kind)?;
+    }
     ACTIONS::complete_registration();
     debug!(context.logger, "Actions registration phase completed");
 
-    self::engine::spawn(context.clone(), upkeep)?;
+    self::engine::spawn(context.clone(), runtime);
+    self::lag::spawn(context.clone(), runtime);
     info!(context.logger, "Actions system initialised");
     Ok(())
 }
 
 /// Register standard agent actions.
+#[cfg(feature = "actions")]
 fn register_agent_actions(
     agent: &dyn Agent,
     context: &AgentContext,
@@ -7,6 +7,8 @@ use std::sync::Arc;
 use std::sync::Mutex;
 
 use super::Action;
+use crate::ErrorKind;
+use crate::Result;
 
 lazy_static::lazy_static! {
     /// Process-global actions register used once registration is complete.
@@ -93,6 +95,32 @@ impl ACTIONS {
         })
     }
 
+    /// Look up the action kind a deprecated alias maps to.
+    ///
+    /// # Panics
+    ///
+    ///   * If the global actions register is poisoned.
+    ///   * If called while still in the actions registration phase.
+    pub fn alias(alias: &str) -> Option<String> {
+        ACTIVE_REG.with(|register| {
+            ensure_thread_register(register);
+            register.borrow().as_ref().unwrap().alias(alias)
+        })
+    }
+
+    /// All deprecated aliases mapped to the action kind that replaced them.
+    ///
+    /// # Panics
+    ///
+    ///   * If the global actions register is poisoned.
+    ///   * If called while still in the actions registration phase.
+    pub fn aliases() -> BTreeMap<String, String> {
+        ACTIVE_REG.with(|register| {
+            ensure_thread_register(register);
+            register.borrow().as_ref().unwrap().aliases().clone()
+        })
+    }
+
     /// Iterate over all registered actions.
     ///
     /// # Panics
@@ -137,6 +165,34 @@ impl ACTIONS {
         });
     }
 
+    /// Process-global equivalent of `ActionsRegister::register_alias`.
+    ///
+    /// # Panics
+    ///
+    ///   * If the global actions register is poisoned.
+    ///   * If called after the registration phase is completed.
+    #[allow(dead_code)]
+    pub(crate) fn register_alias(alias: &str, kind: &str) -> Result<()> {
+        ACTIVE_REG.with(|register| {
+            // To support tests, use the thread local if available.
+            if register.borrow().is_some() {
+                return register
+                    .borrow_mut()
+                    .as_mut()
+                    .unwrap()
+                    .register_alias(alias, kind);
+            }
+
+            // Otherwise register the alias with the global registry.
+            GLOBAL_REG
+                .lock()
+                .expect("global actions register poisoned")
+                .as_mut()
+                .expect("attempted alias registration after registration phase is complete")
+                .register_alias(alias, kind)
+        })
+    }
+
     /// Process-global equivalent of `ActionsRegister::register_reserved`.
     #[allow(dead_code)]
     pub(crate) fn register_reserved<A>(action: A)
@@ -239,7 +295,7 @@ impl<'a> ActionKind<'a> {
         ActionKind { kind, scope_end }
     }
 
-    /// TODO
+    /// Check if the action kind is in a scope reserved to replicante.
     pub fn is_reserved(&self) -> bool {
         let (scope, _) = self.kind.split_at(self.scope_end);
         scope.ends_with("replicante.io")
@@ -250,14 +306,33 @@ impl<'a> ActionKind<'a> {
 #[derive(Clone, Default)]
 pub struct ActionsRegister {
     actions: BTreeMap<String, Arc<dyn Action>>,
+    aliases: BTreeMap<String, String>,
 }
 
 impl ActionsRegister {
+    /// Look up the action kind a deprecated alias maps to.
+    pub fn alias(&self, alias: &str) -> Option<String> {
+        self.aliases.get(alias).cloned()
+    }
+
+    /// All deprecated aliases mapped to the action kind that replaced them.
+    pub fn aliases(&self) -> &BTreeMap<String, String> {
+        &self.aliases
+    }
+
     /// Fetch an action from the register.
     pub fn get(&self, kind: &str) -> Option<Arc<dyn Action>> {
         self.actions.get(kind).cloned()
     }
 
+    /// Check if an action kind is in a scope reserved to replicante.
+    ///
+    /// # Panics
+    /// If the given `kind` is not scoped.
+    pub fn is_reserved(kind: &str) -> bool {
+        ActionKind::new(kind).is_reserved()
+    }
+
     /// Iterate over all registered actions.
     pub fn iter(&self) -> Iter {
         Iter(self.actions.clone().into_iter())
@@ -287,6 +362,29 @@ impl ActionsRegister {
         };
     }
 
+    /// Register a deprecated alias for an action kind that was renamed.
+    ///
+    /// Aliases must map to a registered action and can't shadow a registered
+    /// action or other alias so actions must be registered before their aliases.
+    pub fn register_alias(&mut self, alias: &str, kind: &str) -> Result<()> {
+        if self.actions.contains_key(alias) {
+            let error = format!("alias {} is a registered action kind", alias);
+            return Err(ErrorKind::ConfigInvalid("actions.aliases", error).into());
+        }
+        if !self.actions.contains_key(kind) {
+            let error = format!("alias {} maps to unknown action kind {}", alias, kind);
+            return Err(ErrorKind::ConfigInvalid("actions.aliases", error).into());
+        }
+        match self.aliases.entry(alias.to_string()) {
+            Entry::Vacant(entry) => entry.insert(kind.to_string()),
+            Entry::Occupied(entry) => {
+                let error = format!("alias {} is already registered", entry.key());
+                return Err(ErrorKind::ConfigInvalid("actions.aliases", error).into());
+            }
+        };
+        Ok(())
+    }
+
     /// Same as `ActionsRegister::register` for registration of reserved IDs.
     pub(crate) fn register_reserved<A>(&mut self, action: A)
     where
@@ -479,6 +577,47 @@ mod tests {
         assert_eq!(actions.actions.len(), 1);
     }
 
+    #[test]
+    fn register_alias() {
+        let mut actions = ActionsRegister::default();
+        actions.register(MockAction {});
+        actions
+            .register_alias("test.example.io/old.action", "test.example.io/mock.action")
+            .unwrap();
+        assert_eq!(
+            actions.alias("test.example.io/old.action"),
+            Some("test.example.io/mock.action".to_string())
+        );
+        assert_eq!(actions.alias("test.example.io/mock.action"), None);
+        assert_eq!(actions.aliases().len(), 1);
+    }
+
+    #[test]
+    fn reserved_kinds() {
+        assert!(ActionsRegister::is_reserved(
+            "test.replicante.io/mock.action"
+        ));
+        assert!(!ActionsRegister::is_reserved("test.example.io/mock.action"));
+    }
+
+    #[test]
+    fn register_alias_fail_invalid() {
+        let mut actions = ActionsRegister::default();
+        actions.register(MockAction {});
+        let unknown =
+            actions.register_alias("test.example.io/old.action", "test.example.io/missing");
+        assert!(unknown.is_err());
+        let shadow =
+            actions.register_alias("test.example.io/mock.action", "test.example.io/mock.action");
+        assert!(shadow.is_err());
+        actions
+            .register_alias("test.example.io/old.action", "test.example.io/mock.action")
+            .unwrap();
+        let twice =
+            actions.register_alias("test.example.io/old.action", "test.example.io/mock.action");
+        assert!(twice.is_err());
+    }
+
     #[test]
     fn register_reserved_action() {
         let mut actions = ActionsRegister::default();
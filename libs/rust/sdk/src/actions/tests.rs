@@ -5,7 +5,6 @@ use actix_web::test::TestRequest;
 use actix_web::web;
 use actix_web::App;
 use actix_web::HttpResponse;
-use failure::Fail;
 use opentracingrust::Span;
 use serde_json::json;
 use serde_json::Value as Json;
@@ -89,7 +88,7 @@ fn enabled_explicitly_without_tls() {
     config.actions.enabled = Some(true);
     match super::actions_enabled(&config) {
         Ok(_) => panic!("expected configuration error"),
-        Err(error) => assert_eq!(error.name().unwrap(), "ConfigClash"),
+        Err(error) => assert_eq!(error.kind().code(), "ConfigClash"),
     };
 }
 
@@ -104,7 +103,7 @@ async fn validation_fails() {
     let body = read_body(res).await;
     assert_eq!(
         body,
-        r#"{"error":"invalid action arguments: test","kind":"InvalidArgs"}"#
+        r#"{"code":"InvalidArgs","error":"invalid action arguments: test","kind":"InvalidArgs","retryable":false}"#
     );
 }
 
@@ -0,0 +1,154 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::FutureExt;
+use tokio::sync::Notify;
+
+/// Notify the actions engine that new actions are waiting to be executed.
+///
+/// The engine waits on the notification between polls so newly scheduled actions
+/// start right away instead of at the next poll, even with long poll intervals.
+/// Notifications sent while the engine is busy are remembered until its next wait.
+#[derive(Clone, Debug, Default)]
+pub struct ActionsWake {
+    inner: Arc<Notify>,
+}
+
+impl ActionsWake {
+    /// Wake the actions engine.
+    pub fn wake(&self) {
+        self.inner.notify_one();
+    }
+
+    /// Consume a pending notification without waiting.
+    ///
+    /// Returns `true` if the engine was woken since the last wait.
+    pub(crate) fn take(&self) -> bool {
+        self.inner.notified().now_or_never().is_some()
+    }
+
+    /// Wait until woken or the timeout expires.
+    ///
+    /// Returns `true` if the engine was woken.
+    pub(crate) async fn wait_timeout(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, self.inner.notified())
+            .await
+            .is_ok()
+    }
+}
+
+/// Notify API long-poll requests that actions made progress.
+///
+/// Each notification bumps a generation counter so waiters can tell
+/// if progress was made since they last checked the store.
+/// Waiting is asynchronous so long-poll requests don't hold on to threads.
+#[derive(Clone, Debug, Default)]
+pub struct ActionsProgress {
+    inner: Arc<Mutex<ProgressState>>,
+}
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    generation: u64,
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+impl ActionsProgress {
+    /// Current progress generation.
+    pub fn generation(&self) -> u64 {
+        self.inner
+            .lock()
+            .expect("ActionsProgress lock poisoned")
+            .generation
+    }
+
+    /// Record that actions made progress and wake all waiters.
+    pub fn notify(&self) {
+        let mut state = self.inner.lock().expect("ActionsProgress lock poisoned");
+        state.generation += 1;
+        for waiter in state.waiters.drain(..) {
+            let _ = waiter.send(());
+        }
+    }
+
+    /// Wait until the generation moves past `seen`.
+    ///
+    /// Callers should bound the wait with a timeout.
+    pub async fn wait(&self, seen: u64) {
+        let receiver = {
+            let mut state = self.inner.lock().expect("ActionsProgress lock poisoned");
+            if state.generation != seen {
+                return;
+            }
+            // Drop waiters that gave up so they don't pile up between notifications.
+            state.waiters.retain(|waiter| !waiter.is_canceled());
+            let (sender, receiver) = oneshot::channel();
+            state.waiters.push(sender);
+            receiver
+        };
+        let _ = receiver.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+
+    use super::ActionsProgress;
+    use super::ActionsWake;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn pending_wake_is_consumed() {
+        let wake = ActionsWake::default();
+        wake.wake();
+        assert!(runtime().block_on(wake.wait_timeout(Duration::from_secs(10))));
+        assert!(!wake.take());
+    }
+
+    #[test]
+    fn wait_times_out() {
+        let wake = ActionsWake::default();
+        assert!(!runtime().block_on(wake.wait_timeout(Duration::from_millis(10))));
+    }
+
+    #[test]
+    fn wake_waiting_task() {
+        let wake = ActionsWake::default();
+        let waker = wake.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            waker.wake();
+        });
+        assert!(runtime().block_on(wake.wait_timeout(Duration::from_secs(10))));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn progress_since_generation() {
+        let progress = ActionsProgress::default();
+        let seen = progress.generation();
+        progress.notify();
+        block_on(progress.wait(seen));
+        let seen = progress.generation();
+        let notifier = progress.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            notifier.notify();
+        });
+        block_on(progress.wait(seen));
+        handle.join().unwrap();
+        assert_eq!(progress.generation(), seen + 1);
+    }
+}
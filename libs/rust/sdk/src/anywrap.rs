@@ -1,9 +1,7 @@
+use std::error::Error as StdError;
 use std::fmt;
 
-use failure::Backtrace;
-use failure::Fail;
-
-/// Dumb wrapper to carry `anyhow::Error`s as `failure::Fail`s.
+/// Dumb wrapper to carry `anyhow::Error`s as `std::error::Error`s.
 pub struct AnyWrap(anyhow::Error);
 
 impl From<anyhow::Error> for AnyWrap {
@@ -12,17 +10,9 @@ impl From<anyhow::Error> for AnyWrap {
     }
 }
 
-impl Fail for AnyWrap {
-    fn cause(&self) -> Option<&dyn Fail> {
-        None
-    }
-
-    fn backtrace(&self) -> Option<&Backtrace> {
-        None
-    }
-
-    fn name(&self) -> Option<&str> {
-        Some("AnyWrap")
+impl StdError for AnyWrap {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.0.source()
     }
 }
 
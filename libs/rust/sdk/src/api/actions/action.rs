@@ -7,21 +7,31 @@ use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use actix_web::Responder;
 use actix_web::Result;
-use failure::ResultExt;
+use serde::Deserialize;
+use serde::Serialize;
 use serde_json::json;
+use slog::warn;
+use uuid::Uuid;
 
 use replicante_models_agent::actions::api::ActionInfoResponse;
 use replicante_models_agent::actions::api::ActionScheduleRequest;
 use replicante_util_actixweb::with_request_span;
 use replicante_util_actixweb::TracingMiddleware;
-use replicante_util_tracing::fail_span;
 
+use crate::actions::ActionListItem;
+use crate::actions::ActionLogLine;
 use crate::actions::ActionRecord;
 use crate::actions::ActionRequester;
 use crate::actions::ACTIONS;
+use crate::api::audit::AuditAction;
+use crate::api::format::ResponseFormat;
+use crate::api::namespace;
+use crate::api::sampling::TraceSampling;
+use crate::fail_span;
 use crate::AgentContext;
 use crate::Error;
 use crate::ErrorKind;
+use crate::ResultExt;
 
 lazy_static::lazy_static! {
     /// Set of HTTP headers to exclude when collecting action headers.
@@ -37,11 +47,56 @@ lazy_static::lazy_static! {
     };
 }
 
+/// Number of history transitions returned by default.
+const HISTORY_LIMIT_DEFAULT: u32 = 100;
+
+/// Maximum number of history transitions returned in one request.
+const HISTORY_LIMIT_MAX: u32 = 1000;
+
+/// Pagination of action history transitions and optional progress logs.
+#[derive(Deserialize)]
+struct InfoQuery {
+    history_after: Option<String>,
+    history_limit: Option<u32>,
+    logs: Option<bool>,
+}
+
+/// Action details with the token to fetch more history, if available.
+///
+/// Actions also include links to the action that scheduled them, if any,
+/// and to the actions they scheduled.
+#[derive(Serialize)]
+struct InfoResponse {
+    #[serde(flatten)]
+    info: ActionInfoResponse,
+
+    children: Vec<ActionListItem>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    history_next: Option<String>,
+
+    /// More history is available than returned, fetch it with `history_next`.
+    ///
+    /// At most `HISTORY_LIMIT_DEFAULT` transitions are returned unless
+    /// the request sets `?history_limit`.
+    history_truncated: bool,
+
+    /// Progress logs of the action, oldest line first, when requested with `?logs=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logs: Option<Vec<ActionLogLine>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+
+    parent_action_id: Option<Uuid>,
+}
+
 /// Fetch an action details.
 pub fn info(context: &AgentContext) -> impl HttpServiceFactory {
     let logger = context.logger.clone();
     let tracer = Arc::clone(&context.tracer);
     let tracer = TracingMiddleware::with_name(logger, tracer, "/actions/info/{id}");
+    let tracer = TraceSampling::new(context, tracer);
     web::resource("/info/{id}")
         .wrap(tracer)
         .route(web::get().to(info_responder))
@@ -50,33 +105,67 @@ pub fn info(context: &AgentContext) -> impl HttpServiceFactory {
 async fn info_responder(
     context: web::Data<AgentContext>,
     id: web::Path<String>,
+    query: web::Query<InfoQuery>,
     request: HttpRequest,
 ) -> Result<impl Responder> {
     let mut request = request;
+    let format = ResponseFormat::from_request(&request);
     let id = id.into_inner();
+    let query = query.into_inner();
+    let namespace = context.config.namespace.clone();
+    let limit = query
+        .history_limit
+        .unwrap_or(HISTORY_LIMIT_DEFAULT)
+        .clamp(1, HISTORY_LIMIT_MAX);
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let info = context
+        .store
+        .with_transaction_async(move |tx| {
+            let action = tx.action().get(&id, span_context.clone())?;
+            let action = match action {
+                None => return Ok(None),
+                Some(action) => action,
+            };
+            let parent_action_id = action.parent_action_id;
+            let action = action.into();
+            let mut children = Vec::new();
+            for child in tx.action().children(&id, span_context.clone())? {
+                children.push(child?);
+            }
+            let after = query.history_after.as_deref();
+            let page = tx
+                .action()
+                .history(&id, limit, after, span_context.clone())?;
+            let mut history = Vec::new();
+            for item in page.items {
+                history.push(item?);
+            }
+            let logs = if query.logs.unwrap_or(false) {
+                let logs = tx.action().logs(&id, span_context)?;
+                Some(logs.collect::<crate::Result<Vec<_>>>()?)
+            } else {
+                None
+            };
+            let info = InfoResponse {
+                info: ActionInfoResponse { action, history },
+                children,
+                history_truncated: page.next.is_some(),
+                history_next: page.next,
+                logs,
+                namespace,
+                parent_action_id,
+            };
+            Ok(Some(info))
+        })
+        .await;
     let info = with_request_span(&mut request, |span| {
-        let span_context = span.as_ref().map(|span| span.context().clone());
-        context
-            .store
-            .with_transaction(|tx| {
-                let action = tx.action().get(&id, span_context.clone())?;
-                let action = match action {
-                    None => return Ok(None),
-                    Some(action) => action.into(),
-                };
-                let iter = tx.action().history(&id, span_context)?;
-                let mut history = Vec::new();
-                for item in iter {
-                    history.push(item?);
-                }
-                let info = ActionInfoResponse { action, history };
-                Ok(Some(info))
-            })
-            .map_err(|error| fail_span(error, span))
+        info.map_err(|error| fail_span(error, span))
     })?;
     match info {
         None => Ok(HttpResponse::NotFound().finish()),
-        Some(info) => Ok(HttpResponse::Ok().json(info)),
+        Some(info) => Ok(format.respond(&info)),
     }
 }
 
@@ -85,6 +174,7 @@ pub fn schedule(context: &AgentContext) -> impl HttpServiceFactory {
     let logger = context.logger.clone();
     let tracer = Arc::clone(&context.tracer);
     let tracer = TracingMiddleware::with_name(logger, tracer, "/actions/schedule/{kind}");
+    let tracer = TraceSampling::new(context, tracer);
     web::resource("/schedule/{kind:.*}")
         .wrap(tracer)
         .route(web::post().to(schedule_responder))
@@ -97,7 +187,29 @@ async fn schedule_responder(
     request: HttpRequest,
 ) -> Result<impl Responder> {
     let mut request = request;
+    if let Err(error) = namespace::check(&request, &context.config.namespace) {
+        return Err(with_request_span(&mut request, |span| fail_span(error, span)).into());
+    }
+    if context.store.health().is_degraded() {
+        let error = Error::from(ErrorKind::PersistentDegraded);
+        return Err(with_request_span(&mut request, |span| fail_span(error, span)).into());
+    }
     let kind = kind.into_inner();
+
+    // Schedule renamed actions requested with their deprecated kind.
+    let (kind, warning) = match ACTIONS::alias(&kind) {
+        None => (kind, None),
+        Some(target) => {
+            warn!(
+                context.logger,
+                "Action scheduled with a deprecated kind";
+                "alias" => &kind,
+                "kind" => &target,
+            );
+            let warning = format!("action kind {} is deprecated, use {}", kind, target);
+            (target, Some(warning))
+        }
+    };
     let action = with_request_span(&mut request, |span| {
         ACTIONS::get(&kind)
             .ok_or_else(|| ErrorKind::ActionNotAvailable(kind.clone()))
@@ -128,31 +240,121 @@ async fn schedule_responder(
             let value = value
                 .to_str()
                 .with_context(|_| ErrorKind::ActionEncode)
-                .map_err(Error::from)
                 .map_err(|error| fail_span(error, span))?
                 .to_string();
             Ok(value)
         })?;
         record.headers.insert(name, value);
     }
-    with_request_span(&mut request, |span| -> Result<_> {
-        let span_context = span.as_ref().map(|span| span.context().clone());
-        if let Some(span_context) = span_context.as_ref() {
-            record
-                .trace_set(span_context, &context.tracer)
-                .map_err(Error::from)
-                .map_err(|error| fail_span(error, span))?;
-        }
-        Ok(())
-    })?;
     let id = record.id;
-    with_request_span(&mut request, |span| -> Result<_> {
-        let span_context = span.as_ref().map(|span| span.context().clone());
+    let audit = AuditAction {
+        id,
+        kind: record.kind.clone(),
+        warning,
+    };
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let result = context
+        .store
+        .with_transaction_async(move |tx| tx.action().insert(record, span_context))
+        .await;
+    with_request_span(&mut request, |span| {
+        result.map_err(|error| fail_span(error, span))
+    })?;
+    context.actions_wake.wake();
+    let mut response = HttpResponse::Ok().json(json!({ "id": id }));
+    response.extensions_mut().insert(audit);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body_json;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use actix_web::App;
+    use serde_json::json;
+    use serde_json::Value as Json;
+
+    use crate::actions::ActionRecord;
+    use crate::actions::ActionRequester;
+    use crate::actions::ActionState;
+    use crate::store::Store;
+    use crate::AgentContext;
+
+    #[actix_web::test]
+    async fn info_with_logs() {
+        let mut context = AgentContext::mock();
+        context.store = Store::sqlite();
+        let action = ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+        let id = action.id.to_string();
         context
             .store
-            .with_transaction(|tx| tx.action().insert(record, span_context))
-            .map_err(|error| fail_span(error, span))?;
-        Ok(())
-    })?;
-    Ok(HttpResponse::Ok().json(json!({ "id": id })))
+            .with_transaction(|tx| {
+                tx.action().insert(action.clone(), None)?;
+                tx.action().log(&action, "step 1", None)?;
+                tx.action().log(&action, "step 2", None)
+            })
+            .unwrap();
+        let app = App::new()
+            .app_data(web::Data::new(context.clone()))
+            .service(super::info(&context));
+        let app = init_service(app).await;
+
+        let uri = format!("/info/{}?logs=true", id);
+        let request = TestRequest::get().uri(&uri).to_request();
+        let response: Json = read_body_json(call_service(&app, request).await).await;
+        let lines: Vec<&str> = response["logs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|line| line["line"].as_str().unwrap())
+            .collect();
+        assert_eq!(lines, vec!["step 1", "step 2"]);
+
+        let uri = format!("/info/{}", id);
+        let request = TestRequest::get().uri(&uri).to_request();
+        let response: Json = read_body_json(call_service(&app, request).await).await;
+        assert!(response.get("logs").is_none());
+    }
+
+    #[actix_web::test]
+    async fn info_signals_truncated_history() {
+        let context = AgentContext::mock();
+        let action = ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+        let id = action.id.to_string();
+        context
+            .store
+            .with_transaction(|tx| {
+                tx.action().insert(action.clone(), None)?;
+                tx.action()
+                    .transition(&action, ActionState::Running, None, None)?;
+                tx.action()
+                    .transition(&action, ActionState::Done, None, None)
+            })
+            .unwrap();
+        let app = App::new()
+            .app_data(web::Data::new(context.clone()))
+            .service(super::info(&context));
+        let app = init_service(app).await;
+
+        let uri = format!("/info/{}?history_limit=2", id);
+        let request = TestRequest::get().uri(&uri).to_request();
+        let response: Json = read_body_json(call_service(&app, request).await).await;
+        assert_eq!(response["history"].as_array().unwrap().len(), 2);
+        assert_eq!(response["history"][0]["state"], json!(ActionState::Done));
+        assert_eq!(response["history_truncated"], true);
+        let next = response["history_next"].as_str().unwrap();
+
+        let uri = format!("/info/{}?history_limit=2&history_after={}", id, next);
+        let request = TestRequest::get().uri(&uri).to_request();
+        let response: Json = read_body_json(call_service(&app, request).await).await;
+        assert_eq!(response["history"].as_array().unwrap().len(), 1);
+        assert_eq!(response["history"][0]["state"], json!(ActionState::New));
+        assert_eq!(response["history_truncated"], false);
+        assert!(response.get("history_next").is_none());
+    }
 }
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+
+use replicante_util_actixweb::with_request_span;
+use replicante_util_actixweb::TracingMiddleware;
+
+use crate::api::format::ResponseFormat;
+use crate::api::sampling::TraceSampling;
+use crate::fail_span;
+use crate::store::ArchivedActionDetails;
+use crate::store::ArchivedActionItem;
+use crate::AgentContext;
+
+/// Number of archived actions returned by default.
+const ARCHIVE_LIMIT_DEFAULT: u32 = 100;
+
+/// Maximum number of archived actions returned in one request.
+const ARCHIVE_LIMIT_MAX: u32 = 1000;
+
+/// Pagination of archived actions.
+#[derive(Deserialize)]
+struct ArchiveQuery {
+    after: Option<String>,
+    limit: Option<u32>,
+}
+
+/// Page of archived actions with the token to fetch the next page, if available.
+#[derive(Serialize)]
+struct ArchiveResponse {
+    actions: Vec<ArchivedActionItem>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+}
+
+/// Archived action details.
+#[derive(Serialize)]
+struct ArchivedResponse {
+    #[serde(flatten)]
+    details: ArchivedActionDetails,
+
+    archived_ts: DateTime<Utc>,
+}
+
+/// List archived actions, most recently archived first.
+pub fn list(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
+    web::resource("/archive")
+        .wrap(tracer)
+        .route(web::get().to(list_responder))
+}
+
+async fn list_responder(
+    context: web::Data<AgentContext>,
+    query: web::Query<ArchiveQuery>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    let format = ResponseFormat::from_request(&request);
+    let query = query.into_inner();
+    let limit = query
+        .limit
+        .unwrap_or(ARCHIVE_LIMIT_DEFAULT)
+        .clamp(1, ARCHIVE_LIMIT_MAX);
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let response = context
+        .store
+        .with_transaction_async(move |tx| {
+            let after = query.after.as_deref();
+            let page = tx.actions().archived_list(limit, after, span_context)?;
+            let mut actions = Vec::new();
+            for action in page.items {
+                actions.push(action?);
+            }
+            Ok(ArchiveResponse {
+                actions,
+                next: page.next,
+            })
+        })
+        .await;
+    let response = with_request_span(&mut request, |span| {
+        response.map_err(|error| fail_span(error, span))
+    })?;
+    Ok(format.respond(&response))
+}
+
+/// Fetch an archived action with its transition history.
+pub fn info(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::with_name(logger, tracer, "/actions/archive/{id}");
+    let tracer = TraceSampling::new(context, tracer);
+    web::resource("/archive/{id}")
+        .wrap(tracer)
+        .route(web::get().to(info_responder))
+}
+
+async fn info_responder(
+    context: web::Data<AgentContext>,
+    id: web::Path<String>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    let format = ResponseFormat::from_request(&request);
+    let id = id.into_inner();
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let archived = context
+        .store
+        .with_transaction_async(move |tx| {
+            let archived = match tx.actions().archived(&id, span_context)? {
+                None => return Ok(None),
+                Some(archived) => archived,
+            };
+            let details = archived.decode()?;
+            Ok(Some(ArchivedResponse {
+                details,
+                archived_ts: archived.archived_ts,
+            }))
+        })
+        .await;
+    let archived = with_request_span(&mut request, |span| {
+        archived.map_err(|error| fail_span(error, span))
+    })?;
+    match archived {
+        None => Ok(HttpResponse::NotFound().finish()),
+        Some(archived) => Ok(format.respond(&archived)),
+    }
+}
@@ -3,14 +3,16 @@ use std::sync::Arc;
 use actix_web::dev::HttpServiceFactory;
 use actix_web::web;
 use actix_web::HttpRequest;
-use actix_web::HttpResponse;
 use actix_web::Responder;
 use actix_web::Result;
 
 use replicante_util_actixweb::with_request_span;
 use replicante_util_actixweb::TracingMiddleware;
-use replicante_util_tracing::fail_span;
 
+use crate::api::format::ResponseFormat;
+use crate::api::namespace::Namespaced;
+use crate::api::sampling::TraceSampling;
+use crate::fail_span;
 use crate::AgentContext;
 
 /// List finished actions.
@@ -18,6 +20,7 @@ pub fn finished(context: &AgentContext) -> impl HttpServiceFactory {
     let logger = context.logger.clone();
     let tracer = Arc::clone(&context.tracer);
     let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
     web::resource("/finished")
         .wrap(tracer)
         .route(web::get().to(finished_responder))
@@ -28,21 +31,26 @@ async fn finished_responder(
     request: HttpRequest,
 ) -> Result<impl Responder> {
     let mut request = request;
+    let format = ResponseFormat::from_request(&request);
+    let namespace = context.config.namespace.clone();
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let actions = context
+        .store
+        .with_transaction_async(move |tx| {
+            let mut actions = Vec::new();
+            let iter = tx.actions().finished(span_context)?;
+            for action in iter {
+                actions.push(Namespaced::new(action?, &namespace));
+            }
+            Ok(actions)
+        })
+        .await;
     let actions = with_request_span(&mut request, |span| {
-        let span_context = span.as_ref().map(|span| span.context().clone());
-        context
-            .store
-            .with_transaction(|tx| {
-                let mut actions = Vec::new();
-                let iter = tx.actions().finished(span_context)?;
-                for action in iter {
-                    actions.push(action?);
-                }
-                Ok(actions)
-            })
-            .map_err(|error| fail_span(error, span))
+        actions.map_err(|error| fail_span(error, span))
     })?;
-    Ok(HttpResponse::Ok().json(actions))
+    Ok(format.respond(&actions))
 }
 
 /// List running and pending actions.
@@ -50,6 +58,7 @@ pub fn queue(context: &AgentContext) -> impl HttpServiceFactory {
     let logger = context.logger.clone();
     let tracer = Arc::clone(&context.tracer);
     let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
     web::resource("/queue")
         .wrap(tracer)
         .route(web::get().to(queue_responder))
@@ -60,19 +69,24 @@ async fn queue_responder(
     request: HttpRequest,
 ) -> Result<impl Responder> {
     let mut request = request;
+    let format = ResponseFormat::from_request(&request);
+    let namespace = context.config.namespace.clone();
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let actions = context
+        .store
+        .with_transaction_async(move |tx| {
+            let mut actions = Vec::new();
+            let iter = tx.actions().queue(span_context)?;
+            for action in iter {
+                actions.push(Namespaced::new(action?, &namespace));
+            }
+            Ok(actions)
+        })
+        .await;
     let actions = with_request_span(&mut request, |span| {
-        let span_context = span.as_ref().map(|span| span.context().clone());
-        context
-            .store
-            .with_transaction(|tx| {
-                let mut actions = Vec::new();
-                let iter = tx.actions().queue(span_context)?;
-                for action in iter {
-                    actions.push(action?);
-                }
-                Ok(actions)
-            })
-            .map_err(|error| fail_span(error, span))
+        actions.map_err(|error| fail_span(error, span))
     })?;
-    Ok(HttpResponse::Ok().json(actions))
+    Ok(format.respond(&actions))
 }
@@ -0,0 +1,565 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value as Json;
+use uuid::Uuid;
+
+use replicante_util_actixweb::with_request_span;
+use replicante_util_actixweb::TracingMiddleware;
+
+use crate::actions::transition_allowed;
+use crate::actions::ActionRecord;
+use crate::actions::ActionRecordView;
+use crate::actions::ActionRequester;
+use crate::actions::ActionState;
+use crate::actions::ACTIONS;
+use crate::api::audit::AuditAction;
+use crate::api::namespace;
+use crate::api::sampling::TraceSampling;
+use crate::fail_span;
+use crate::AgentContext;
+use crate::Error;
+use crate::ErrorKind;
+
+/// Cancel all pending (NEW) actions.
+///
+/// Cancelled actions transition to FAILED with a `cancelled` payload.
+/// Actions leased by other agent processes are skipped and reported.
+/// The store queue query is bounded so it is repeated until no more actions can be cancelled.
+pub fn cancel_queued(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
+    web::resource("/queue/cancel")
+        .wrap(tracer)
+        .route(web::post().to(cancel_queued_responder))
+}
+
+async fn cancel_queued_responder(
+    context: web::Data<AgentContext>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    if let Err(error) = namespace::check(&request, &context.config.namespace) {
+        return Err(with_request_span(&mut request, |span| fail_span(error, span)).into());
+    }
+    if context.store.health().is_degraded() {
+        let error = Error::from(ErrorKind::PersistentDegraded);
+        return Err(with_request_span(&mut request, |span| fail_span(error, span)).into());
+    }
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let result = context
+        .store
+        .with_transaction_async(move |tx| {
+            let mut response = CancelResponse::default();
+            let mut seen = HashSet::new();
+            let payload = json!({"cancelled": true, "reason": "cancelled through the agent API"});
+            // Cancelled actions leave the queue so each pass sees the actions after them.
+            // Skipped actions stay queued and are remembered to know when to stop.
+            loop {
+                let mut pending = Vec::new();
+                for item in tx.actions().queue(span_context.clone())? {
+                    let item = item?;
+                    if item.state == ActionState::New && seen.insert(item.id) {
+                        pending.push(item.id.to_string());
+                    }
+                }
+                if pending.is_empty() {
+                    break;
+                }
+                for id in pending {
+                    let record = match tx.action().get(&id, span_context.clone())? {
+                        None => continue,
+                        Some(record) => record,
+                    };
+                    let transition = tx.action().transition(
+                        &record,
+                        ActionState::Failed,
+                        payload.clone(),
+                        span_context.clone(),
+                    );
+                    match transition {
+                        Ok(()) => response.cancelled.push(record.id),
+                        Err(error) => match error.kind() {
+                            ErrorKind::ActionLeaseLost(_) => response.skipped.push(record.id),
+                            _ => return Err(error),
+                        },
+                    }
+                }
+            }
+            Ok(response)
+        })
+        .await;
+    let response = with_request_span(&mut request, |span| {
+        result.map_err(|error| fail_span(error, span))
+    })?;
+    if !response.cancelled.is_empty() {
+        context.actions_progress.notify();
+    }
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Actions affected by a bulk cancel request.
+#[derive(Debug, Default, Serialize)]
+struct CancelResponse {
+    cancelled: Vec<Uuid>,
+    skipped: Vec<Uuid>,
+}
+
+/// Schedule a new action with the same kind and arguments as a FAILED action.
+///
+/// The new action is linked to the failed one as its parent.
+pub fn requeue(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::with_name(logger, tracer, "/actions/requeue/{id}");
+    let tracer = TraceSampling::new(context, tracer);
+    web::resource("/requeue/{id}")
+        .wrap(tracer)
+        .route(web::post().to(requeue_responder))
+}
+
+async fn requeue_responder(
+    context: web::Data<AgentContext>,
+    id: web::Path<String>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    if let Err(error) = namespace::check(&request, &context.config.namespace) {
+        return Err(with_request_span(&mut request, |span| fail_span(error, span)).into());
+    }
+    if context.store.health().is_degraded() {
+        let error = Error::from(ErrorKind::PersistentDegraded);
+        return Err(with_request_span(&mut request, |span| fail_span(error, span)).into());
+    }
+    let id = id.into_inner();
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let lookup_span = span_context.clone();
+    let failed = context
+        .store
+        .with_transaction_async(move |tx| {
+            let failed = match tx.action().get(&id, lookup_span)? {
+                None => return Ok(None),
+                Some(record) => record,
+            };
+            if *ActionRecordView::state(&failed) != ActionState::Failed {
+                return Err(ErrorKind::ActionNotFailed(id).into());
+            }
+            Ok(Some(failed))
+        })
+        .await;
+    let failed = with_request_span(&mut request, |span| {
+        failed.map_err(|error| fail_span(error, span))
+    })?;
+    let failed = match failed {
+        None => return Ok(HttpResponse::NotFound().finish()),
+        Some(failed) => failed,
+    };
+
+    // Arguments valid for the failed action may not be for the current implementation.
+    let args = ActionRecordView::args(&failed).clone();
+    let action = with_request_span(&mut request, |span| {
+        ACTIONS::get(&failed.kind)
+            .ok_or_else(|| ErrorKind::ActionNotAvailable(failed.kind.clone()))
+            .map_err(Error::from)
+            .map_err(|error| fail_span(error, span))
+    })?;
+    with_request_span(&mut request, |span| {
+        action
+            .validate_args(&args)
+            .map_err(|error| fail_span(error, span))
+    })?;
+
+    let mut record = ActionRecord::new(
+        failed.kind.clone(),
+        None,
+        None,
+        args,
+        ActionRequester::AgentApi,
+    );
+    record.parent_action_id = Some(failed.id);
+    let audit = AuditAction {
+        id: record.id,
+        kind: record.kind.clone(),
+        warning: None,
+    };
+    let result = context
+        .store
+        .with_transaction_async(move |tx| tx.action().insert(record, span_context))
+        .await;
+    with_request_span(&mut request, |span| {
+        result.map_err(|error| fail_span(error, span))
+    })?;
+    context.actions_wake.wake();
+    let mut response = HttpResponse::Ok().json(json!({ "id": audit.id }));
+    response.extensions_mut().insert(audit);
+    Ok(response)
+}
+
+/// Transition an action to a new state if it is still in the expected state.
+///
+/// Intended for external orchestrators that drive actions themselves:
+/// the transition is rejected with a `409 Conflict` if the action changed state
+/// since the orchestrator last looked at it or if an agent process holds its lease.
+/// Only actions requested by external orchestrators can be transitioned.
+pub fn transition(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::with_name(logger, tracer, "/actions/transition/{id}");
+    let tracer = TraceSampling::new(context, tracer);
+    web::resource("/transition/{id}")
+        .wrap(tracer)
+        .route(web::post().to(transition_responder))
+}
+
+async fn transition_responder(
+    context: web::Data<AgentContext>,
+    id: web::Path<String>,
+    body: web::Json<TransitionRequest>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    if let Err(error) = namespace::check(&request, &context.config.namespace) {
+        return Err(with_request_span(&mut request, |span| fail_span(error, span)).into());
+    }
+    if context.store.health().is_degraded() {
+        let error = Error::from(ErrorKind::PersistentDegraded);
+        return Err(with_request_span(&mut request, |span| fail_span(error, span)).into());
+    }
+    let id = id.into_inner();
+    let body = body.into_inner();
+    let finished = matches!(body.state, ActionState::Done | ActionState::Failed);
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let result = context
+        .store
+        .with_transaction_async(move |tx| {
+            let record = match tx.action().get(&id, span_context.clone())? {
+                None => return Ok(None),
+                Some(record) => record,
+            };
+            if record.requester != ActionRequester::External {
+                return Err(ErrorKind::ActionNotExternal(id).into());
+            }
+            let current = ActionRecordView::state(&record);
+            if *current != body.expected_state {
+                let error = ErrorKind::ActionStateMismatch(
+                    id,
+                    format!("{:?}", current),
+                    format!("{:?}", body.expected_state),
+                );
+                return Err(error.into());
+            }
+            if !transition_allowed(current, &body.state) {
+                let error = ErrorKind::ActionTransitionNotAllowed(
+                    id,
+                    format!("{:?}", current),
+                    format!("{:?}", body.state),
+                );
+                return Err(error.into());
+            }
+            tx.action()
+                .transition_external(&record, body.state, body.payload, span_context)?;
+            Ok(Some(record.id))
+        })
+        .await;
+    let id = with_request_span(&mut request, |span| {
+        result.map_err(|error| fail_span(error, span))
+    })?;
+    let id = match id {
+        None => return Ok(HttpResponse::NotFound().finish()),
+        Some(id) => id,
+    };
+    if finished {
+        context.actions_progress.notify();
+    }
+    Ok(HttpResponse::Ok().json(json!({ "id": id })))
+}
+
+/// Requested state transition and the state the action is expected to be in.
+#[derive(Debug, Deserialize)]
+struct TransitionRequest {
+    expected_state: ActionState,
+    #[serde(default)]
+    payload: Option<Json>,
+    state: ActionState,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::http::StatusCode;
+    use actix_web::rt::System;
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body_json;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use actix_web::App;
+    use opentracingrust::Span;
+    use serde_json::json;
+    use serde_json::Value as Json;
+
+    use crate::actions::Action;
+    use crate::actions::ActionDescriptor;
+    use crate::actions::ActionRecord;
+    use crate::actions::ActionRecordView;
+    use crate::actions::ActionRequester;
+    use crate::actions::ActionState;
+    use crate::actions::ActionValidity;
+    use crate::actions::ActionValidityError;
+    use crate::actions::ActionsRegister;
+    use crate::actions::ACTIONS;
+    use crate::store::Store;
+    use crate::store::Transaction;
+    use crate::AgentContext;
+    use crate::Error;
+    use crate::ErrorKind;
+    use crate::Result;
+
+    /// Action that only accepts `{"valid": true}` as arguments.
+    struct Validated;
+
+    impl Action for Validated {
+        fn describe(&self) -> ActionDescriptor {
+            ActionDescriptor {
+                kind: "test.validated".into(),
+                description: "Validate arguments".into(),
+            }
+        }
+
+        fn invoke(
+            &self,
+            _: &mut Transaction,
+            _: &dyn ActionRecordView,
+            _: Option<&mut Span>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn validate_args(&self, args: &Json) -> ActionValidity {
+            if args["valid"] == json!(true) {
+                return Ok(());
+            }
+            Err(ActionValidityError::InvalidArgs(
+                "valid must be true".into(),
+            ))
+        }
+    }
+
+    /// Insert a failed action with the given arguments.
+    fn insert_failed(context: &AgentContext, args: Json) -> ActionRecord {
+        let action = ActionRecord::new(
+            "test.validated",
+            None,
+            None,
+            args,
+            ActionRequester::AgentApi,
+        );
+        context
+            .store
+            .with_transaction(|tx| {
+                tx.action().insert(action.clone(), None)?;
+                tx.action()
+                    .transition(&action, ActionState::Failed, None, None)
+            })
+            .unwrap();
+        action
+    }
+
+    #[actix_web::test]
+    async fn cancel_queued_past_queue_limit() {
+        let mut context = AgentContext::mock();
+        context.store = Store::sqlite();
+        context
+            .store
+            .with_transaction(|tx| {
+                for _ in 0..150 {
+                    let action =
+                        ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+                    tx.action().insert(action, None)?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        let app = App::new()
+            .app_data(web::Data::new(context.clone()))
+            .service(super::cancel_queued(&context));
+        let app = init_service(app).await;
+
+        let request = TestRequest::post().uri("/queue/cancel").to_request();
+        let response: Json = read_body_json(call_service(&app, request).await).await;
+        assert_eq!(response["cancelled"].as_array().unwrap().len(), 150);
+        let queued = context
+            .store
+            .with_transaction(|tx| Ok(tx.actions().queue(None)?.count()))
+            .unwrap();
+        assert_eq!(queued, 0);
+    }
+
+    #[actix_web::test]
+    async fn cancel_queued_rejected_when_degraded() {
+        let context = AgentContext::mock();
+        let error = Error::from(ErrorKind::PersistentCommit);
+        context
+            .store
+            .health()
+            .transaction_failed(&error, &context.logger);
+        let app = App::new()
+            .app_data(web::Data::new(context.clone()))
+            .service(super::cancel_queued(&context));
+        let app = init_service(app).await;
+
+        let request = TestRequest::post().uri("/queue/cancel").to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn requeue_validates_args() {
+        let mut context = AgentContext::mock();
+        context.store = Store::sqlite();
+        let invalid = insert_failed(&context, json!({"valid": false}));
+        let valid = insert_failed(&context, json!({"valid": true}));
+        let mut register = ActionsRegister::default();
+        register.register(Validated);
+        ACTIONS::test_with(register, || {
+            System::new().block_on(async {
+                let app = App::new()
+                    .app_data(web::Data::new(context.clone()))
+                    .service(super::requeue(&context));
+                let app = init_service(app).await;
+
+                let uri = format!("/requeue/{}", invalid.id);
+                let request = TestRequest::post().uri(&uri).to_request();
+                let response = call_service(&app, request).await;
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+                let uri = format!("/requeue/{}", valid.id);
+                let request = TestRequest::post().uri(&uri).to_request();
+                let response: Json = read_body_json(call_service(&app, request).await).await;
+                let id = response["id"].as_str().unwrap().to_string();
+                let requeued = context
+                    .store
+                    .with_transaction(|tx| tx.action().get(&id, None))
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(requeued.parent_action_id, Some(valid.id));
+                assert_eq!(ActionRecordView::args(&requeued), &json!({"valid": true}));
+            });
+        });
+    }
+
+    #[actix_web::test]
+    async fn requeue_rejects_actions_that_did_not_fail() {
+        let mut context = AgentContext::mock();
+        context.store = Store::sqlite();
+        let action = ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action.clone(), None))
+            .unwrap();
+        let app = App::new()
+            .app_data(web::Data::new(context.clone()))
+            .service(super::requeue(&context));
+        let app = init_service(app).await;
+
+        let uri = format!("/requeue/{}", action.id);
+        let request = TestRequest::post().uri(&uri).to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    /// Insert a NEW action requested by an external orchestrator.
+    fn insert_external(context: &AgentContext) -> ActionRecord {
+        let action = ActionRecord::new("test", None, None, json!({}), ActionRequester::External);
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action.clone(), None))
+            .unwrap();
+        action
+    }
+
+    /// Request the action to move from NEW to RUNNING and return the response status.
+    async fn start(context: &AgentContext, action: &ActionRecord) -> StatusCode {
+        let app = App::new()
+            .app_data(web::Data::new(context.clone()))
+            .service(super::transition(context));
+        let app = init_service(app).await;
+        let uri = format!("/transition/{}", action.id);
+        let body = json!({
+            "expected_state": ActionState::New,
+            "state": ActionState::Running,
+        });
+        let request = TestRequest::post().uri(&uri).set_json(&body).to_request();
+        call_service(&app, request).await.status()
+    }
+
+    /// Current state of the action in the store.
+    fn state(context: &AgentContext, action: &ActionRecord) -> ActionState {
+        let id = action.id.to_string();
+        let action = context
+            .store
+            .with_transaction(|tx| tx.action().get(&id, None))
+            .unwrap()
+            .unwrap();
+        ActionRecordView::state(&action).clone()
+    }
+
+    #[actix_web::test]
+    async fn transition_external_action() {
+        let mut context = AgentContext::mock();
+        context.store = Store::sqlite();
+        let action = insert_external(&context);
+        assert_eq!(start(&context, &action).await, StatusCode::OK);
+        assert_eq!(state(&context, &action), ActionState::Running);
+        // The action is no longer in the expected state.
+        assert_eq!(start(&context, &action).await, StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn transition_rejects_engine_actions() {
+        let mut context = AgentContext::mock();
+        context.store = Store::sqlite();
+        let action = ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+        context
+            .store
+            .with_transaction(|tx| tx.action().insert(action.clone(), None))
+            .unwrap();
+        assert_eq!(start(&context, &action).await, StatusCode::CONFLICT);
+        assert_eq!(state(&context, &action), ActionState::New);
+    }
+
+    #[actix_web::test]
+    async fn transition_respects_engine_lease() {
+        let mut context = AgentContext::mock();
+        context.store = Store::sqlite();
+        let action = insert_external(&context);
+        context
+            .store
+            .with_transaction(|tx| {
+                tx.action()
+                    .lease(&action, Duration::from_secs(60), None)
+                    .map(|_| ())
+            })
+            .unwrap();
+        assert_eq!(start(&context, &action).await, StatusCode::CONFLICT);
+        assert_eq!(state(&context, &action), ActionState::New);
+    }
+}
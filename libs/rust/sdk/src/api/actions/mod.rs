@@ -7,11 +7,15 @@ use replicante_util_actixweb::RootDescriptor;
 
 use crate::actions::ActionDescriptor;
 use crate::actions::ACTIONS;
+use crate::api::body_logging::BodyLogging;
 use crate::api::APIRoot;
 use crate::api::AppConfigContext;
 
 mod action;
+mod archive;
 mod list;
+mod manage;
+mod next;
 
 /// Return a list of available agent actions.
 #[actix_web::get("/available")]
@@ -22,39 +26,43 @@ async fn available() -> impl Responder {
     HttpResponse::Ok().json(actions)
 }
 
-/// Static 2xx response to confirm the actions API is NOT enabled.
-#[actix_web::get("/actions")]
-async fn index_disabled() -> impl Responder {
-    HttpResponse::Ok().json(json!({"actions": false}))
-}
-
 /// Static 2xx response to confirm the actions API is enabled.
 #[actix_web::get("/")]
 async fn index_enabled() -> impl Responder {
     HttpResponse::Ok().json(json!({"actions": true}))
 }
 
-/// Configure the API server with actions API disabled.
-pub fn configure_disabled(conf: &mut AppConfigContext) {
-    APIRoot::UnstableAPI.and_then(&conf.context.flags, |root| {
-        conf.scoped_service(root.prefix(), index_disabled);
-    });
-}
-
 /// Configure the API server with actions API enabled.
 pub fn configure_enabled(conf: &mut AppConfigContext) {
     APIRoot::UnstableAPI.and_then(&conf.context.flags, |root| {
+        let archive = self::archive::list(&conf.context.agent);
+        let archive_info = self::archive::info(&conf.context.agent);
         let finished = self::list::finished(&conf.context.agent);
+        let cancel_queued = self::manage::cancel_queued(&conf.context.agent);
         let info = self::action::info(&conf.context.agent);
         let queue = self::list::queue(&conf.context.agent);
+        let next = self::next::next(&conf.context.agent);
+        let requeue = self::manage::requeue(&conf.context.agent);
         let schedule = self::action::schedule(&conf.context.agent);
+        let transition = self::manage::transition(&conf.context.agent);
+        let body_logging = BodyLogging::new(
+            conf.context.agent.config.api.body_logging.clone(),
+            conf.context.agent.logger.clone(),
+        );
         let scope = web::scope("/actions")
+            .wrap(body_logging)
             .service(index_enabled)
             .service(available)
+            .service(archive)
+            .service(archive_info)
             .service(finished)
             .service(queue)
+            .service(cancel_queued)
+            .service(next)
             .service(info)
-            .service(schedule);
+            .service(requeue)
+            .service(schedule)
+            .service(transition);
         conf.scoped_service(root.prefix(), scope);
     });
 }
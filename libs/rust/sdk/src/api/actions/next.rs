@@ -0,0 +1,148 @@
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::rt::time::timeout;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+
+use replicante_models_agent::actions::ActionModel;
+use replicante_util_actixweb::with_request_span;
+use replicante_util_actixweb::TracingMiddleware;
+
+use crate::api::format::ResponseFormat;
+use crate::api::sampling::TraceSampling;
+use crate::fail_span;
+use crate::AgentContext;
+use crate::ErrorKind;
+
+/// Default time, in seconds, to wait for a finished action.
+const WAIT_DEFAULT: u64 = 30;
+
+/// Maximum time, in seconds, clients can wait for a finished action.
+const WAIT_MAX: u64 = 60;
+
+/// Query parameters for the next finished action.
+#[derive(Debug, Deserialize)]
+struct NextQuery {
+    /// Return the action finished after the one this cursor was returned with.
+    ///
+    /// Takes precedence over `since`.
+    cursor: Option<String>,
+
+    /// Return actions finished after this time (defaults to the time of the request).
+    since: Option<DateTime<Utc>>,
+
+    /// Time to wait for an action to finish, in seconds with an optional `s` suffix.
+    wait: Option<String>,
+}
+
+/// Next finished action and the cursor to request the one after it.
+#[derive(Debug, Serialize)]
+struct NextResponse {
+    action: ActionModel,
+
+    /// Pass as the `cursor` parameter of the next request to not miss any action.
+    cursor: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+}
+
+/// Long-poll for the next action to finish (DONE or FAILED).
+///
+/// Responds with `204 No Content` if no action finished before the wait expired.
+pub fn next(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
+    web::resource("/next")
+        .wrap(tracer)
+        .route(web::get().to(next_responder))
+}
+
+async fn next_responder(
+    context: web::Data<AgentContext>,
+    query: web::Query<NextQuery>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    let format = ResponseFormat::from_request(&request);
+    let query = query.into_inner();
+    let wait = parse_wait(query.wait.as_deref())
+        .map_err(|error| with_request_span(&mut request, |span| fail_span(error, span)))?;
+    let deadline = Instant::now() + wait;
+    let since = query.since.unwrap_or_else(Utc::now);
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    loop {
+        // Read the generation before the store so progress made while querying is not missed.
+        let generation = context.actions_progress.generation();
+        let span_context = span_context.clone();
+        let cursor = query.cursor.clone();
+        let next = context
+            .store
+            .with_transaction_async(move |tx| {
+                tx.action()
+                    .next_finished(since, cursor.as_deref(), span_context)
+            })
+            .await;
+        let next = with_request_span(&mut request, |span| {
+            next.map_err(|error| fail_span(error, span))
+        })?;
+        if let Some(next) = next {
+            let response = NextResponse {
+                action: next.action.into(),
+                cursor: next.cursor,
+                namespace: context.config.namespace.clone(),
+            };
+            return Ok(format.respond(&response));
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(HttpResponse::NoContent().finish());
+        }
+        let progress = &context.actions_progress;
+        let _ = timeout(remaining, progress.wait(generation)).await;
+    }
+}
+
+/// Parse the `wait` query parameter, capped to `WAIT_MAX` seconds.
+fn parse_wait(wait: Option<&str>) -> crate::Result<Duration> {
+    let seconds = match wait {
+        None => WAIT_DEFAULT,
+        Some(wait) => wait
+            .strip_suffix('s')
+            .unwrap_or(wait)
+            .parse::<u64>()
+            .map_err(|_| ErrorKind::InvalidQueryParam("wait", wait.to_string()))?,
+    };
+    Ok(Duration::from_secs(seconds.min(WAIT_MAX)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::parse_wait;
+
+    #[test]
+    fn wait_formats() {
+        assert_eq!(parse_wait(None).unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_wait(Some("10")).unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_wait(Some("10s")).unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_wait(Some("600s")).unwrap(), Duration::from_secs(60));
+        assert!(parse_wait(Some("ten")).is_err());
+    }
+}
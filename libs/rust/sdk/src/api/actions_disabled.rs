@@ -0,0 +1,70 @@
+//! Actions API served when actions are disabled or the SDK is built without them.
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Scope;
+use serde_json::json;
+
+use replicante_util_actixweb::RootDescriptor;
+
+use crate::api::APIRoot;
+use crate::api::AppConfigContext;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Configure the API server with actions API disabled.
+pub fn configure(conf: &mut AppConfigContext) {
+    APIRoot::UnstableAPI.and_then(&conf.context.flags, |root| {
+        conf.scoped_service(root.prefix(), scope());
+    });
+}
+
+/// Static 2xx response to confirm the actions API is NOT enabled.
+async fn index() -> impl Responder {
+    HttpResponse::Ok().json(json!({"actions": false}))
+}
+
+/// Reject requests for all other actions endpoints with an explicit error.
+async fn reject() -> Result<HttpResponse> {
+    Err(ErrorKind::ActionsDisabled.into())
+}
+
+fn scope() -> Scope {
+    web::scope("/actions")
+        .route("", web::get().to(index))
+        .default_service(web::to(reject))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body_json;
+    use actix_web::test::TestRequest;
+    use actix_web::App;
+    use serde_json::json;
+    use serde_json::Value as Json;
+
+    #[actix_web::test]
+    async fn endpoints_report_actions_disabled() {
+        let app = App::new()
+            .wrap(crate::api::errors::handlers())
+            .service(super::scope());
+        let app = init_service(app).await;
+
+        let req = TestRequest::get().uri("/actions").to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status().as_u16(), 200);
+        let body: Json = read_body_json(res).await;
+        assert_eq!(body, json!({"actions": false}));
+
+        let req = TestRequest::post()
+            .uri("/actions/schedule/test")
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status().as_u16(), 403);
+        let body: Json = read_body_json(res).await;
+        assert_eq!(body["code"], "ActionsDisabled");
+        assert_eq!(body["retryable"], false);
+    }
+}
@@ -3,23 +3,78 @@ use std::sync::Arc;
 use actix_web::dev::HttpServiceFactory;
 use actix_web::web;
 use actix_web::HttpRequest;
-use actix_web::HttpResponse;
 use actix_web::Responder;
 use actix_web::Result;
 use opentracingrust::Log;
+use opentracingrust::Span;
+use serde::Serialize;
+use slog::warn;
+use slog::Logger;
+
+use replicante_models_agent::info::AgentInfo;
+use replicante_models_agent::info::DatastoreInfo;
 
 use replicante_util_actixweb::with_request_span;
 use replicante_util_actixweb::TracingMiddleware;
-use replicante_util_tracing::fail_span;
+use replicante_util_failure::failure_info;
 
+use crate::api::format::ResponseFormat;
+use crate::api::namespace::Namespaced;
+use crate::api::payloads::PayloadVersion;
+use crate::api::payloads::VersionedPayload;
+use crate::api::sampling::TraceSampling;
+use crate::api::snapshot::SnapshotRequest;
+use crate::datastore_version_warning;
+use crate::deadline::Deadline;
+use crate::fail_span;
 use crate::Agent;
 use crate::AgentContext;
 
+/// Datastore info payload with the supported version warning, if any.
+#[derive(Serialize)]
+struct DatastoreInfoResponse {
+    #[serde(flatten)]
+    info: DatastoreInfo,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version_warning: Option<String>,
+}
+
+impl VersionedPayload for DatastoreInfoResponse {
+    fn encode(
+        &self,
+        version: PayloadVersion,
+        format: ResponseFormat,
+    ) -> std::result::Result<Vec<u8>, String> {
+        match version {
+            PayloadVersion::V1 => format.encode(&self.info),
+            PayloadVersion::V2 => format.encode(self),
+        }
+    }
+}
+
+impl VersionedPayload for Namespaced<AgentInfo> {
+    fn encode(
+        &self,
+        version: PayloadVersion,
+        format: ResponseFormat,
+    ) -> std::result::Result<Vec<u8>, String> {
+        match version {
+            PayloadVersion::V1 => format.encode(&self.inner),
+            PayloadVersion::V2 => format.encode(self),
+        }
+    }
+}
+
 /// API interface to Agent::agent_info
 pub fn agent(context: &AgentContext) -> impl HttpServiceFactory {
     let logger = context.logger.clone();
     let tracer = Arc::clone(&context.tracer);
     let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
     web::resource("/agent")
         .wrap(tracer)
         .route(web::get().to(agent_respoder))
@@ -27,16 +82,40 @@ pub fn agent(context: &AgentContext) -> impl HttpServiceFactory {
 
 async fn agent_respoder(
     agent: web::Data<Arc<dyn Agent>>,
+    context: web::Data<AgentContext>,
     mut request: HttpRequest,
 ) -> Result<impl Responder> {
-    with_request_span(&mut request, |span| {
-        let span = span.expect("unable to find tracing span for request");
-        span.log(Log::new().log("span.kind", "server-receive"));
-        let info = agent
-            .agent_info(span)
-            .map_err(|error| fail_span(error, &mut *span))?;
-        let response = HttpResponse::Ok().json(info);
-        span.log(Log::new().log("span.kind", "server-send"));
+    let version = PayloadVersion::from_request(&request);
+    let snapshot = SnapshotRequest::new(&request);
+    let deadline = Deadline::from_request(&request);
+    let (version, span_context) = with_request_span(&mut request, |mut span| {
+        if let Some(span) = span.as_mut() {
+            span.log(Log::new().log("span.kind", "server-receive"));
+        }
+        let span_context = span.as_ref().map(|span| span.context().clone());
+        let version = version.map_err(|error| fail_span(error, span))?;
+        Ok::<_, crate::Error>((version, span_context))
+    })?;
+    let breaker = context.datastore_breaker.clone();
+    let info = async {
+        let limiter = &context.datastore_limiter;
+        let slot = limiter.acquire("agent_info", deadline).await?;
+        context
+            .datastore_pool
+            .call("agent_info", span_context, deadline, move |span| {
+                let _slot = slot;
+                breaker.call("agent_info", || agent.agent_info(span))
+            })
+            .await
+    }
+    .await;
+    with_request_span(&mut request, |mut span| {
+        let info = info.map_err(|error| fail_span(error, span.as_deref_mut()))?;
+        let info = Namespaced::new(info, &context.config.namespace);
+        let response = snapshot.respond_versioned(version, &info);
+        if let Some(span) = span {
+            span.log(Log::new().log("span.kind", "server-send"));
+        }
         Ok(response)
     })
 }
@@ -47,6 +126,7 @@ pub fn datastore(context: &AgentContext) -> impl HttpServiceFactory {
     let logger = context.logger.clone();
     let tracer = Arc::clone(&context.tracer);
     let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
     web::resource("/datastore")
         .app_data(web::Data::new(cluster_display_name_override))
         .wrap(tracer)
@@ -56,24 +136,162 @@ pub fn datastore(context: &AgentContext) -> impl HttpServiceFactory {
 async fn datastore_responder(
     agent: web::Data<Arc<dyn Agent>>,
     cluster_display_name_override: web::Data<Option<String>>,
+    context: web::Data<AgentContext>,
     mut request: HttpRequest,
 ) -> Result<impl Responder> {
-    with_request_span(&mut request, |span| {
-        let span = span.expect("unable to find tracing span for request");
-        span.log(Log::new().log("span.kind", "server-receive"));
-        let mut info = agent
-            .datastore_info(span)
-            .map_err(|error| fail_span(error, &mut *span))?;
-
-        // Inject the cluster_display_name override if configured.
-        info.cluster_display_name = cluster_display_name_override
-            .as_ref()
-            .as_ref()
-            .cloned()
-            .or(info.cluster_display_name);
-
-        let response = HttpResponse::Ok().json(info);
-        span.log(Log::new().log("span.kind", "server-send"));
+    let version = PayloadVersion::from_request(&request);
+    let snapshot = SnapshotRequest::new(&request);
+    let deadline = Deadline::from_request(&request);
+    let (version, span_context) = with_request_span(&mut request, |mut span| {
+        if let Some(span) = span.as_mut() {
+            span.log(Log::new().log("span.kind", "server-receive"));
+        }
+        let span_context = span.as_ref().map(|span| span.context().clone());
+        let version = version.map_err(|error| fail_span(error, span))?;
+        Ok::<_, crate::Error>((version, span_context))
+    })?;
+    let breaker = context.datastore_breaker.clone();
+    let logger = context.logger.clone();
+    let info = async {
+        crate::faults::datastore_latency().await;
+        let limiter = &context.datastore_limiter;
+        let slot = limiter.acquire("datastore_info", deadline).await?;
+        context
+            .datastore_pool
+            .call("datastore_info", span_context, deadline, move |span| {
+                let _slot = slot;
+                breaker.call("datastore_info", || {
+                    let mut info = agent.datastore_info(span)?;
+                    info.cluster_display_name = cluster_display_name(
+                        &**agent.get_ref(),
+                        &info,
+                        cluster_display_name_override.get_ref().as_ref(),
+                        &logger,
+                        span,
+                    );
+                    Ok(info)
+                })
+            })
+            .await
+    }
+    .await;
+    with_request_span(&mut request, |mut span| {
+        let info = info.map_err(|error| fail_span(error, span.as_deref_mut()))?;
+
+        let info = DatastoreInfoResponse {
+            info,
+            namespace: context.config.namespace.clone(),
+            version_warning: datastore_version_warning(),
+        };
+        let response = snapshot.respond_versioned(version, &info);
+        if let Some(span) = span {
+            span.log(Log::new().log("span.kind", "server-send"));
+        }
         Ok(response)
     })
 }
+
+/// Cluster display name to report: the configured override, the one in the datastore info
+/// or the one detected by the agent, in this order.
+///
+/// Detection is best-effort: failures are logged and leave the display name unset
+/// instead of failing the datastore info request.
+fn cluster_display_name(
+    agent: &dyn Agent,
+    info: &DatastoreInfo,
+    display_override: Option<&String>,
+    logger: &Logger,
+    span: &mut Span,
+) -> Option<String> {
+    if let Some(name) = display_override.or_else(|| info.cluster_display_name.as_ref()) {
+        return Some(name.clone());
+    }
+    match agent.cluster_display_name(span) {
+        Ok(name) => name,
+        Err(error) => {
+            warn!(
+                logger,
+                "Failed to detect the cluster display name";
+                failure_info(&error.to_fail()),
+            );
+            span.tag("cluster_display_name.error", error.to_string());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use actix_web::App;
+
+    use super::cluster_display_name;
+    use crate::breaker::BreakerState;
+    use crate::config::Agent as Config;
+    use crate::config::CircuitBreakerConfig;
+    use crate::testing::MockAgent;
+    use crate::Agent;
+    use crate::AgentContext;
+
+    #[actix_web::test]
+    async fn agent_info_failures_open_the_breaker() {
+        let breaker = CircuitBreakerConfig {
+            failure_threshold: 1,
+            open_seconds: 60,
+        };
+        let config = Config {
+            circuit_breaker: Some(breaker),
+            ..Config::mock()
+        };
+        let context = AgentContext::mock_with_config(config);
+        let mut agent = MockAgent::new();
+        agent.agent_info = Err("datastore down".into());
+        let agent: Arc<dyn Agent> = Arc::new(agent);
+        let app = App::new()
+            .app_data(web::Data::new(agent))
+            .app_data(web::Data::new(context.clone()))
+            .wrap(crate::api::errors::handlers())
+            .service(super::agent(&context));
+        let app = init_service(app).await;
+
+        let req = TestRequest::get().uri("/agent").to_request();
+        let res = call_service(&app, req).await;
+        assert!(res.status().is_server_error());
+        assert_eq!(context.datastore_breaker.state(), BreakerState::Open);
+    }
+
+    #[test]
+    fn display_name_precedence() {
+        let context = AgentContext::mock();
+        let mut span = context.tracer.span("test");
+        let mut agent = MockAgent::new();
+        agent.cluster_display_name = Ok(Some("detected".into()));
+        let mut info = agent.datastore_info.clone().unwrap();
+
+        let name = "override".to_string();
+        let display = cluster_display_name(&agent, &info, Some(&name), &context.logger, &mut span);
+        assert_eq!(display, Some("override".into()));
+        let display = cluster_display_name(&agent, &info, None, &context.logger, &mut span);
+        assert_eq!(display, Some("display".into()));
+        info.cluster_display_name = None;
+        let display = cluster_display_name(&agent, &info, None, &context.logger, &mut span);
+        assert_eq!(display, Some("detected".into()));
+    }
+
+    #[test]
+    fn display_name_detection_is_best_effort() {
+        let context = AgentContext::mock();
+        let mut span = context.tracer.span("test");
+        let mut agent = MockAgent::new();
+        agent.cluster_display_name = Err("detection failed".into());
+        let mut info = agent.datastore_info.clone().unwrap();
+        info.cluster_display_name = None;
+        let display = cluster_display_name(&agent, &info, None, &context.logger, &mut span);
+        assert_eq!(display, None);
+    }
+}
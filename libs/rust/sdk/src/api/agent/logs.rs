@@ -0,0 +1,406 @@
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::os::unix::fs::MetadataExt;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::web;
+use actix_web::web::Bytes;
+use actix_web::Error;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Result;
+use futures::stream;
+use futures::StreamExt;
+use regex::Regex;
+use serde::Deserialize;
+
+use replicante_util_actixweb::TracingMiddleware;
+
+use crate::api::sampling::TraceSampling;
+use crate::config::DatastoreLogsConfig;
+use crate::AgentContext;
+use crate::ErrorKind;
+use crate::ResultExt;
+
+/// Content type of log responses.
+const CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// Delay between checks for new lines while following the log.
+const FOLLOW_POLL: Duration = Duration::from_secs(1);
+
+/// Maximum number of bytes read from the log on each poll while following it.
+///
+/// Logs growing faster than this are sent over several polls.
+const FOLLOW_READ_MAX: u64 = 1024 * 1024;
+
+/// Number of lines returned when clients do not specify one.
+const LINES_DEFAULT: usize = 100;
+
+/// Text that replaces content matching redaction patterns.
+const REDACTED: &str = "[REDACTED]";
+
+/// Size of blocks read while searching backwards for the last lines of the log.
+const TAIL_BLOCK: u64 = 8192;
+
+/// Serve the last lines of the datastore log file, optionally following it.
+///
+/// Only complete lines are served: a line still being written is sent
+/// once terminated, if the client is following the log.
+pub fn logs(context: &AgentContext, config: &DatastoreLogsConfig) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
+    let tail = DatastoreLogs {
+        follow_timeout: Duration::from_secs(config.follow_timeout),
+        followers: Arc::new(AtomicUsize::new(0)),
+        max_followers: config.max_followers,
+        max_lines: config.max_lines,
+        path: config.path.clone(),
+        redactor: Arc::new(LogRedactor::new(&config.redact)),
+    };
+    web::resource("/datastore/logs")
+        .app_data(web::Data::new(tail))
+        .wrap(tracer)
+        .route(web::get().to(responder))
+}
+
+async fn responder(
+    logs: web::Data<DatastoreLogs>,
+    query: web::Query<LogsQuery>,
+) -> Result<impl Responder> {
+    let lines = query.lines.unwrap_or(LINES_DEFAULT).min(logs.max_lines);
+    let follow = query.follow.unwrap_or(false);
+    let slot = if follow {
+        Some(FollowerSlot::acquire(&logs)?)
+    } else {
+        None
+    };
+    let path = logs.path.clone();
+    let (lines, position) = web::block(move || tail(&path, lines)).await??;
+    let slot = match slot {
+        None => {
+            let body = logs.redactor.encode(&lines);
+            return Ok(HttpResponse::Ok().content_type(CONTENT_TYPE).body(body));
+        }
+        Some(slot) => slot,
+    };
+
+    let initial = if lines.is_empty() {
+        None
+    } else {
+        Some(Ok(logs.redactor.encode(&lines)))
+    };
+    let follow = FollowState {
+        deadline: Instant::now() + logs.follow_timeout,
+        path: logs.path.clone(),
+        position,
+        redactor: Arc::clone(&logs.redactor),
+        _slot: slot,
+    };
+    let follow = stream::unfold(Some(follow), |state| async move {
+        let mut state = state?;
+        loop {
+            if Instant::now() >= state.deadline {
+                return None;
+            }
+            actix_web::rt::time::sleep(FOLLOW_POLL).await;
+            let path = state.path.clone();
+            let position = state.position;
+            let read = web::block(move || read_from(&path, position))
+                .await
+                .map_err(Error::from)
+                .and_then(|read| read.map_err(Error::from));
+            match read {
+                Err(error) => return Some((Err(error), None)),
+                Ok((lines, position)) if lines.is_empty() => state.position = position,
+                Ok((lines, position)) => {
+                    state.position = position;
+                    let chunk = state.redactor.encode(&lines);
+                    return Some((Ok(chunk), Some(state)));
+                }
+            }
+        }
+    });
+    let body = stream::iter(initial).chain(follow);
+    Ok(HttpResponse::Ok()
+        .content_type(CONTENT_TYPE)
+        .streaming(body))
+}
+
+/// Datastore log access options.
+struct DatastoreLogs {
+    follow_timeout: Duration,
+    followers: Arc<AtomicUsize>,
+    max_followers: usize,
+    max_lines: usize,
+    path: String,
+    redactor: Arc<LogRedactor>,
+}
+
+/// Slot taken by a client following the log, released when dropped.
+struct FollowerSlot {
+    followers: Arc<AtomicUsize>,
+}
+
+impl FollowerSlot {
+    /// Take a follower slot, unless `max_followers` clients already follow the log.
+    fn acquire(logs: &DatastoreLogs) -> crate::Result<FollowerSlot> {
+        let followers = Arc::clone(&logs.followers);
+        let previous = followers.fetch_add(1, Ordering::SeqCst);
+        let slot = FollowerSlot { followers };
+        if previous >= logs.max_followers {
+            return Err(ErrorKind::ConcurrencyLimit("datastore log follow").into());
+        }
+        Ok(slot)
+    }
+}
+
+impl Drop for FollowerSlot {
+    fn drop(&mut self) {
+        self.followers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// State of a client following the log.
+struct FollowState {
+    deadline: Instant,
+    path: String,
+    position: LogPosition,
+    redactor: Arc<LogRedactor>,
+    _slot: FollowerSlot,
+}
+
+/// Position in a specific log file, identified by device and inode.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct LogPosition {
+    dev: u64,
+    ino: u64,
+    offset: u64,
+}
+
+/// Replace sensitive log content before it is sent to clients.
+struct LogRedactor {
+    patterns: Vec<Regex>,
+}
+
+impl LogRedactor {
+    fn new(patterns: &[String]) -> LogRedactor {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern).expect("redact patterns to be validated"))
+            .collect();
+        LogRedactor { patterns }
+    }
+
+    /// Redact lines and encode them into a response chunk.
+    fn encode(&self, lines: &[String]) -> Bytes {
+        let mut chunk = String::new();
+        for line in lines {
+            let mut line = line.clone();
+            for pattern in &self.patterns {
+                line = pattern.replace_all(&line, REDACTED).into_owned();
+            }
+            chunk.push_str(&line);
+            chunk.push('\n');
+        }
+        Bytes::from(chunk)
+    }
+}
+
+#[derive(Deserialize)]
+struct LogsQuery {
+    follow: Option<bool>,
+    lines: Option<usize>,
+}
+
+/// Split complete lines out of a buffer, returning them with the length of the complete portion.
+fn complete_lines(buffer: &[u8]) -> (Vec<String>, usize) {
+    let complete = buffer
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map(|index| index + 1)
+        .unwrap_or(0);
+    let lines = String::from_utf8_lossy(&buffer[..complete])
+        .lines()
+        .map(String::from)
+        .collect();
+    (lines, complete)
+}
+
+/// Read complete lines added to the log after the given position.
+///
+/// If the log was rotated (it is a different file) or truncated (it is shorter than
+/// the offset) it is read from the start.
+/// At most `FOLLOW_READ_MAX` bytes are read: lines longer than that are split.
+fn read_from(path: &str, position: LogPosition) -> crate::Result<(Vec<String>, LogPosition)> {
+    let mut file = File::open(path).with_context(|_| ErrorKind::Io(path.to_string()))?;
+    let metadata = file
+        .metadata()
+        .with_context(|_| ErrorKind::Io(path.to_string()))?;
+    let same_file = metadata.dev() == position.dev && metadata.ino() == position.ino;
+    let offset = if same_file && metadata.len() >= position.offset {
+        position.offset
+    } else {
+        0
+    };
+    let mut buffer = Vec::new();
+    file.seek(SeekFrom::Start(offset))
+        .and_then(|_| file.by_ref().take(FOLLOW_READ_MAX).read_to_end(&mut buffer))
+        .with_context(|_| ErrorKind::Io(path.to_string()))?;
+    let (mut lines, mut complete) = complete_lines(&buffer);
+    if complete == 0 && buffer.len() as u64 == FOLLOW_READ_MAX {
+        lines.push(String::from_utf8_lossy(&buffer).into_owned());
+        complete = buffer.len();
+    }
+    let position = LogPosition {
+        dev: metadata.dev(),
+        ino: metadata.ino(),
+        offset: offset + complete as u64,
+    };
+    Ok((lines, position))
+}
+
+/// Read the last complete lines of the log, returning them with the position they end at.
+fn tail(path: &str, lines: usize) -> crate::Result<(Vec<String>, LogPosition)> {
+    let mut file = File::open(path).with_context(|_| ErrorKind::Io(path.to_string()))?;
+    let metadata = file
+        .metadata()
+        .with_context(|_| ErrorKind::Io(path.to_string()))?;
+    let mut start = metadata.len();
+
+    // Read blocks backwards until enough lines are found or the start of the file.
+    let mut buffer = Vec::new();
+    while start > 0 && buffer.iter().filter(|byte| **byte == b'\n').count() <= lines {
+        let size = TAIL_BLOCK.min(start);
+        start -= size;
+        let mut block = vec![0; size as usize];
+        file.seek(SeekFrom::Start(start))
+            .and_then(|_| file.read_exact(&mut block))
+            .with_context(|_| ErrorKind::Io(path.to_string()))?;
+        block.extend_from_slice(&buffer);
+        buffer = block;
+    }
+
+    // Unless the start of the file was reached the first line may be partial.
+    let (mut found, complete) = complete_lines(&buffer);
+    if start > 0 && !found.is_empty() {
+        found.remove(0);
+    }
+    let skip = found.len().saturating_sub(lines);
+    found.drain(..skip);
+    let position = LogPosition {
+        dev: metadata.dev(),
+        ino: metadata.ino(),
+        offset: start + complete as u64,
+    };
+    Ok((found, position))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::DatastoreLogs;
+    use super::FollowerSlot;
+    use super::LogRedactor;
+    use super::FOLLOW_READ_MAX;
+
+    #[test]
+    fn redact_lines() {
+        let redactor = LogRedactor::new(&["password=\\S+".to_string()]);
+        let lines = vec![
+            "user=admin password=hunter2 ok".to_string(),
+            "nothing to see".to_string(),
+        ];
+        let chunk = redactor.encode(&lines);
+        assert_eq!(
+            chunk.as_ref(),
+            b"user=admin [REDACTED] ok\nnothing to see\n".as_ref()
+        );
+    }
+
+    #[test]
+    fn tail_and_follow() {
+        let path = std::env::temp_dir().join("repliagent-datastore-logs.log");
+        let path_str = path.to_str().unwrap().to_string();
+        let content: String = (1..=5000).map(|line| format!("line {}\n", line)).collect();
+        fs::write(&path, format!("{}partial", content)).unwrap();
+
+        let (lines, position) = super::tail(&path_str, 3).unwrap();
+        assert_eq!(lines, vec!["line 4998", "line 4999", "line 5000"]);
+        assert_eq!(position.offset, content.len() as u64);
+
+        fs::write(&path, format!("{}partial line\nnext", content)).unwrap();
+        let (lines, next) = super::read_from(&path_str, position).unwrap();
+        assert_eq!(lines, vec!["partial line"]);
+        assert_eq!(next.offset, position.offset + "partial line\n".len() as u64);
+
+        fs::write(&path, "truncated\n").unwrap();
+        let (lines, _) = super::read_from(&path_str, next).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(lines, vec!["truncated"]);
+    }
+
+    #[test]
+    fn follow_rotated_log() {
+        let path = std::env::temp_dir().join("repliagent-datastore-logs-rotated.log");
+        let path_str = path.to_str().unwrap().to_string();
+        fs::write(&path, "old 1\n").unwrap();
+        let (_, position) = super::tail(&path_str, 1).unwrap();
+
+        // The new file is already longer than the offset in the rotated one.
+        let rotated = std::env::temp_dir().join("repliagent-datastore-logs-rotated.log.1");
+        fs::rename(&path, &rotated).unwrap();
+        fs::write(&path, "new 1\nnew 2\n").unwrap();
+        let (lines, next) = super::read_from(&path_str, position).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+        assert_eq!(lines, vec!["new 1", "new 2"]);
+        assert_eq!(next.offset, "new 1\nnew 2\n".len() as u64);
+    }
+
+    #[test]
+    fn follow_reads_are_bounded() {
+        let path = std::env::temp_dir().join("repliagent-datastore-logs-bounded.log");
+        let path_str = path.to_str().unwrap().to_string();
+        fs::write(&path, "").unwrap();
+        let (_, position) = super::tail(&path_str, 1).unwrap();
+
+        let line = "x".repeat(FOLLOW_READ_MAX as usize + 10);
+        fs::write(&path, format!("{}\n", line)).unwrap();
+        let (lines, next) = super::read_from(&path_str, position).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(next.offset, FOLLOW_READ_MAX);
+        let (lines, _) = super::read_from(&path_str, next).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(lines, vec!["x".repeat(10)]);
+    }
+
+    #[test]
+    fn followers_are_limited() {
+        let logs = DatastoreLogs {
+            follow_timeout: Duration::from_secs(1),
+            followers: Arc::new(AtomicUsize::new(0)),
+            max_followers: 1,
+            max_lines: 10,
+            path: "unused".into(),
+            redactor: Arc::new(LogRedactor::new(&[])),
+        };
+        let slot = FollowerSlot::acquire(&logs).unwrap();
+        let error = FollowerSlot::acquire(&logs).err().unwrap();
+        assert_eq!(error.kind().code(), "ConcurrencyLimit");
+        drop(slot);
+        assert!(FollowerSlot::acquire(&logs).is_ok());
+    }
+}
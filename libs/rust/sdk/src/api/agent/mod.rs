@@ -1,12 +1,16 @@
+use std::sync::Arc;
+
 use actix_web::web;
 
 use replicante_util_actixweb::RootDescriptor;
 
 mod info;
+mod logs;
 mod shards;
 
 use crate::api::APIRoot;
 use crate::api::AppConfigContext;
+use crate::Agent;
 
 /// Configure all agent endpoints.
 pub fn configure(conf: &mut AppConfigContext) {
@@ -18,5 +22,18 @@ pub fn configure(conf: &mut AppConfigContext) {
         let prefix = root.prefix();
         conf.scoped_service(prefix, scope);
         conf.scoped_service(prefix, shards);
+        if let Some(config) = &conf.context.agent.config.api.datastore_logs {
+            let logs = self::logs::logs(&conf.context.agent, config);
+            conf.scoped_service(prefix, logs);
+        }
+    });
+}
+
+/// Configure agent specific endpoints.
+pub fn configure_extensions(conf: &mut AppConfigContext, agent: &Arc<dyn Agent>) {
+    APIRoot::UnstableAPI.and_then(&conf.context.flags, |root| {
+        let agent = Arc::clone(agent);
+        let scope = web::scope("/agent").configure(|config| agent.configure_api(config));
+        conf.scoped_service(root.prefix(), scope);
     });
 }
@@ -3,23 +3,92 @@ use std::sync::Arc;
 use actix_web::dev::HttpServiceFactory;
 use actix_web::web;
 use actix_web::HttpRequest;
-use actix_web::HttpResponse;
 use actix_web::Responder;
 use opentracingrust::Log;
+use opentracingrust::Span;
+use serde::Serialize;
+use serde_json::Value as Json;
+use slog::warn;
+use slog::Logger;
 
+use replicante_models_agent::info::Shards;
 use replicante_util_actixweb::with_request_span;
 use replicante_util_actixweb::TracingMiddleware;
-use replicante_util_tracing::fail_span;
+use replicante_util_failure::failure_info;
 
+use crate::api::format::ResponseFormat;
+use crate::api::payloads::PayloadVersion;
+use crate::api::payloads::VersionedPayload;
+use crate::api::sampling::TraceSampling;
+use crate::api::snapshot::SnapshotRequest;
+use crate::deadline::Deadline;
+use crate::fail_span;
+use crate::shards::PartialShards;
+use crate::shards::ShardError;
+use crate::shards::ShardRoles;
 use crate::Agent;
 use crate::AgentContext;
 use crate::Result;
 
-/// API interface to Agent::shards
+/// Shards payload with optional agent specific details.
+#[derive(Serialize)]
+struct ShardsResponse {
+    #[serde(flatten)]
+    shards: Shards,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<ShardErrorResponse>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extra: Option<Json>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+
+    #[serde(skip_serializing_if = "ShardRoles::is_empty")]
+    roles: ShardRoles,
+}
+
+impl VersionedPayload for ShardsResponse {
+    fn encode(
+        &self,
+        version: PayloadVersion,
+        format: ResponseFormat,
+    ) -> std::result::Result<Vec<u8>, String> {
+        match version {
+            PayloadVersion::V1 => format.encode(&self.shards),
+            PayloadVersion::V2 => format.encode(self),
+        }
+    }
+}
+
+/// Details of a shard, or group of shards, that could not be collected.
+#[derive(Serialize)]
+struct ShardErrorResponse {
+    code: &'static str,
+    message: String,
+    retryable: bool,
+    shard: String,
+}
+
+impl From<ShardError> for ShardErrorResponse {
+    fn from(error: ShardError) -> ShardErrorResponse {
+        let kind = error.error.kind();
+        ShardErrorResponse {
+            code: kind.code(),
+            message: error.error.to_string(),
+            retryable: kind.retryable(),
+            shard: error.shard,
+        }
+    }
+}
+
+/// API interface to Agent::shards_partial
 pub fn shards(context: &AgentContext) -> impl HttpServiceFactory {
     let logger = context.logger.clone();
     let tracer = Arc::clone(&context.tracer);
     let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
     web::resource("/shards")
         .wrap(tracer)
         .route(web::get().to(shards_responder))
@@ -27,16 +96,98 @@ pub fn shards(context: &AgentContext) -> impl HttpServiceFactory {
 
 async fn shards_responder(
     agent: web::Data<Arc<dyn Agent>>,
+    context: web::Data<AgentContext>,
     mut request: HttpRequest,
 ) -> Result<impl Responder> {
-    with_request_span(&mut request, |span| {
-        let span = span.expect("unable to find tracing span for request");
-        span.log(Log::new().log("span.kind", "server-receive"));
-        let shards = agent
-            .shards(span)
-            .map_err(|error| fail_span(error, &mut *span))?;
-        let response = HttpResponse::Ok().json(shards);
-        span.log(Log::new().log("span.kind", "server-send"));
+    let version = PayloadVersion::from_request(&request);
+    let snapshot = SnapshotRequest::new(&request);
+    let deadline = Deadline::from_request(&request);
+    let (version, span_context) = with_request_span(&mut request, |mut span| {
+        if let Some(span) = span.as_mut() {
+            span.log(Log::new().log("span.kind", "server-receive"));
+        }
+        let span_context = span.as_ref().map(|span| span.context().clone());
+        let version = version.map_err(|error| fail_span(error, span))?;
+        Ok::<_, crate::Error>((version, span_context))
+    })?;
+    let call_context = context.clone();
+    let response = async {
+        crate::faults::datastore_latency().await;
+        let limiter = &context.datastore_limiter;
+        let slot = limiter.acquire("shards", deadline).await?;
+        context
+            .datastore_pool
+            .call("shards", span_context, deadline, move |span| {
+                let _slot = slot;
+                let context = call_context;
+                let breaker = &context.datastore_breaker;
+                breaker.call("shards", || {
+                    let partial = agent.shards_partial(span)?;
+                    let mut partial = context.shard_filter.apply(partial);
+                    // Clients on the first payload version do not know about partial results.
+                    if version == PayloadVersion::V1 {
+                        partial = PartialShards::new(partial.into_shards()?);
+                    }
+                    let shards = partial.shards;
+                    let errors = partial.errors.into_iter().map(Into::into).collect();
+                    let extra = shards_extra(&**agent.get_ref(), &context.logger, span);
+                    let roles = agent.shards_roles(&shards, span)?;
+                    let span_context = span.context().clone();
+                    crate::shards::record_role_changes(&context, &shards, span_context);
+                    Ok(ShardsResponse {
+                        shards,
+                        errors,
+                        extra,
+                        namespace: context.config.namespace.clone(),
+                        roles,
+                    })
+                })
+            })
+            .await
+    }
+    .await;
+    with_request_span(&mut request, |mut span| {
+        let response = response.map_err(|error| fail_span(error, span.as_deref_mut()))?;
+        let response = snapshot.respond_versioned(version, &response);
+        if let Some(span) = span {
+            span.log(Log::new().log("span.kind", "server-send"));
+        }
         Ok(response)
     })
 }
+
+/// Agent specific details to attach to the shards payload.
+///
+/// Details are best-effort: failures are logged and leave the details unset
+/// instead of failing the shards request.
+fn shards_extra(agent: &dyn Agent, logger: &Logger, span: &mut Span) -> Option<Json> {
+    match agent.shards_extra(span) {
+        Ok(extra) => extra,
+        Err(error) => {
+            warn!(
+                logger,
+                "Failed to fetch agent specific shards details";
+                failure_info(&error.to_fail()),
+            );
+            span.tag("shards.extra.error", error.to_string());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::shards_extra;
+    use crate::testing::MockAgent;
+    use crate::AgentContext;
+
+    #[test]
+    fn extra_details_are_best_effort() {
+        let context = AgentContext::mock();
+        let mut span = context.tracer.span("test");
+        let mut agent = MockAgent::new();
+        agent.shards_extra = Err("details failed".into());
+        let extra = shards_extra(&agent, &context.logger, &mut span);
+        assert_eq!(extra, None);
+    }
+}
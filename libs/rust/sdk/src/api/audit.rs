@@ -0,0 +1,361 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use actix_web::dev::forward_ready;
+use actix_web::dev::Extensions;
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::dev::Transform;
+use actix_web::web;
+use actix_web::Error;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use chrono::DateTime;
+use chrono::Utc;
+use futures::future::ready;
+use futures::future::Ready;
+use serde::Serialize;
+use slog::info;
+use slog::Logger;
+use uuid::Uuid;
+
+use crate::config::AuditConfig;
+
+/// Subject of the certificate presented by the client of a TLS connection.
+#[derive(Clone, Debug)]
+pub struct ClientIdentity(String);
+
+/// Action scheduled by a request, attached to the response for the audit trail.
+#[derive(Clone, Debug)]
+pub struct AuditAction {
+    pub id: Uuid,
+    pub kind: String,
+
+    /// Deprecation warning if the action was requested with an alias of its kind.
+    pub warning: Option<String>,
+}
+
+/// Record the identity of TLS clients in the connection data.
+///
+/// Used as the `HttpServer::on_connect` callback.
+pub fn on_connect(connection: &dyn Any, extensions: &mut Extensions) {
+    if let Some(subject) = client_subject(connection) {
+        extensions.insert(ClientIdentity(subject));
+    }
+}
+
+#[cfg(feature = "tls-rustls")]
+fn client_subject(connection: &dyn Any) -> Option<String> {
+    use actix_tls::accept::rustls::TlsStream;
+    use actix_web::rt::net::TcpStream;
+    let stream = connection.downcast_ref::<TlsStream<TcpStream>>()?;
+    let cert = stream.get_ref().1.peer_certificates()?.first()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert.0).ok()?;
+    Some(cert.subject().to_string())
+}
+
+#[cfg(all(feature = "tls-openssl", not(feature = "tls-rustls")))]
+fn client_subject(connection: &dyn Any) -> Option<String> {
+    use actix_tls::accept::openssl::TlsStream;
+    use actix_web::rt::net::TcpStream;
+    let stream = connection.downcast_ref::<TlsStream<TcpStream>>()?;
+    let cert = stream.ssl().peer_certificate()?;
+    let subject: Vec<String> = cert
+        .subject_name()
+        .entries()
+        .map(|entry| {
+            let name = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry
+                .data()
+                .as_utf8()
+                .map(|value| value.to_string())
+                .unwrap_or_default();
+            format!("{}={}", name, value)
+        })
+        .collect();
+    Some(subject.join(", "))
+}
+
+#[cfg(not(any(feature = "tls-openssl", feature = "tls-rustls")))]
+fn client_subject(_: &dyn Any) -> Option<String> {
+    None
+}
+
+/// Audit record of an API request.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditRecord {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action_kind: Option<String>,
+    client: Option<String>,
+    method: String,
+    path: String,
+    status: u16,
+    timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+}
+
+/// In-memory buffer of the most recent audit records.
+#[derive(Clone)]
+pub struct AuditTrail {
+    keep: usize,
+    records: Arc<Mutex<VecDeque<AuditRecord>>>,
+}
+
+impl AuditTrail {
+    pub fn new(keep: usize) -> AuditTrail {
+        AuditTrail {
+            keep,
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(keep))),
+        }
+    }
+
+    /// Add a record, dropping the oldest one if the trail is full.
+    fn push(&self, record: AuditRecord) {
+        if self.keep == 0 {
+            return;
+        }
+        let mut records = self.records.lock().expect("AuditTrail lock poisoned");
+        if records.len() >= self.keep {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Most recent audit records, newest first.
+    fn records(&self) -> Vec<AuditRecord> {
+        let records = self.records.lock().expect("AuditTrail lock poisoned");
+        records.iter().rev().cloned().collect()
+    }
+}
+
+/// Expose the most recent audit records.
+#[actix_web::get("/audit")]
+pub async fn responder(trail: web::Data<AuditTrail>) -> impl Responder {
+    HttpResponse::Ok().json(trail.records())
+}
+
+/// Middleware to log an audit record for each API request.
+///
+/// When no configuration is given requests are passed through untouched.
+#[derive(Clone)]
+pub struct Audit {
+    enabled: bool,
+    logger: Logger,
+    trail: AuditTrail,
+}
+
+impl Audit {
+    pub fn new(config: Option<&AuditConfig>, trail: AuditTrail, logger: Logger) -> Audit {
+        Audit {
+            enabled: config.is_some(),
+            logger,
+            trail,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Audit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AuditService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuditService {
+            enabled: self.enabled,
+            logger: self.logger.clone(),
+            service: Rc::new(service),
+            trail: self.trail.clone(),
+        }))
+    }
+}
+
+/// Service wrapper created by the `Audit` middleware.
+pub struct AuditService<S> {
+    enabled: bool,
+    logger: Logger,
+    service: Rc<S>,
+    trail: AuditTrail,
+}
+
+impl<S, B> Service<ServiceRequest> for AuditService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let response = self.service.call(request);
+        if !self.enabled {
+            return Box::pin(response);
+        }
+        let logger = self.logger.clone();
+        let trail = self.trail.clone();
+        Box::pin(async move {
+            let response = response.await?;
+            let client = response
+                .request()
+                .conn_data::<ClientIdentity>()
+                .map(|identity| identity.0.clone());
+            let action = response
+                .response()
+                .extensions()
+                .get::<AuditAction>()
+                .cloned();
+            let record = AuditRecord {
+                action_id: action.as_ref().map(|action| action.id),
+                action_kind: action.as_ref().map(|action| action.kind.clone()),
+                client,
+                method: response.request().method().to_string(),
+                path: response.request().path().to_string(),
+                status: response.status().as_u16(),
+                timestamp: Utc::now(),
+                warning: action.and_then(|action| action.warning),
+            };
+            info!(
+                logger, "API request audit";
+                "action_id" => record.action_id.map(|id| id.to_string()),
+                "action_kind" => &record.action_kind,
+                "client" => &record.client,
+                "method" => &record.method,
+                "path" => &record.path,
+                "status" => record.status,
+                "warning" => &record.warning,
+            );
+            trail.push(record);
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body_json;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use actix_web::App;
+    use actix_web::HttpResponse;
+    use chrono::Utc;
+    use serde_json::Value as Json;
+    use slog::o;
+    use slog::Discard;
+    use slog::Logger;
+    use uuid::Uuid;
+
+    use super::Audit;
+    use super::AuditAction;
+    use super::AuditRecord;
+    use super::AuditTrail;
+    use crate::config::AuditConfig;
+
+    async fn schedule() -> HttpResponse {
+        let mut response = HttpResponse::Created().finish();
+        response.extensions_mut().insert(AuditAction {
+            id: Uuid::nil(),
+            kind: "test.action".into(),
+            warning: Some("deprecated alias".into()),
+        });
+        response
+    }
+
+    fn record(path: &str) -> AuditRecord {
+        AuditRecord {
+            action_id: None,
+            action_kind: None,
+            client: Some("CN=core".into()),
+            method: "GET".into(),
+            path: path.into(),
+            status: 200,
+            timestamp: Utc::now(),
+            warning: None,
+        }
+    }
+
+    #[test]
+    fn trail_keeps_most_recent() {
+        let trail = AuditTrail::new(2);
+        trail.push(record("/a"));
+        trail.push(record("/b"));
+        trail.push(record("/c"));
+        let paths: Vec<String> = trail.records().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, vec!["/c".to_string(), "/b".to_string()]);
+    }
+
+    #[actix_web::test]
+    async fn middleware_records_requests() {
+        let config = AuditConfig::default();
+        let trail = AuditTrail::new(config.keep);
+        let logger = Logger::root(Discard, o!());
+        let app = App::new()
+            .app_data(web::Data::new(trail.clone()))
+            .wrap(Audit::new(Some(&config), trail.clone(), logger))
+            .service(super::responder)
+            .route("/schedule", web::post().to(schedule));
+        let app = init_service(app).await;
+
+        let req = TestRequest::post().uri("/schedule").to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status().as_u16(), 201);
+
+        let records = trail.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].action_id, Some(Uuid::nil()));
+        assert_eq!(records[0].action_kind.as_deref(), Some("test.action"));
+        assert_eq!(records[0].client, None);
+        assert_eq!(records[0].method, "POST");
+        assert_eq!(records[0].path, "/schedule");
+        assert_eq!(records[0].status, 201);
+        assert_eq!(records[0].warning.as_deref(), Some("deprecated alias"));
+
+        let req = TestRequest::get().uri("/audit").to_request();
+        let res = call_service(&app, req).await;
+        let body: Json = read_body_json(res).await;
+        assert_eq!(body[0]["path"], "/schedule");
+        assert_eq!(body[0]["action_kind"], "test.action");
+    }
+
+    #[actix_web::test]
+    async fn middleware_disabled() {
+        let trail = AuditTrail::new(10);
+        let logger = Logger::root(Discard, o!());
+        let app = App::new()
+            .wrap(Audit::new(None, trail.clone(), logger))
+            .route("/schedule", web::post().to(schedule));
+        let app = init_service(app).await;
+
+        let req = TestRequest::post().uri("/schedule").to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status().as_u16(), 201);
+        assert!(trail.records().is_empty());
+    }
+
+    #[test]
+    fn trail_disabled() {
+        let trail = AuditTrail::new(0);
+        trail.push(record("/a"));
+        assert!(trail.records().is_empty());
+    }
+}
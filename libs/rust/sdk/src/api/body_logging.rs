@@ -0,0 +1,353 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_web::body::to_bytes;
+use actix_web::body::BodySize;
+use actix_web::body::BoxBody;
+use actix_web::body::MessageBody;
+use actix_web::dev::forward_ready;
+use actix_web::dev::Payload;
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::dev::Transform;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::http::header::HeaderMap;
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::http::header::TRANSFER_ENCODING;
+use actix_web::web::Bytes;
+use actix_web::web::BytesMut;
+use actix_web::Error;
+use futures::future::ready;
+use futures::future::Ready;
+use futures::StreamExt;
+use serde_json::Value;
+use slog::debug;
+use slog::Logger;
+use uuid::Uuid;
+
+use crate::config::BodyLoggingConfig;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Middleware to log sampled request and response bodies at debug level.
+///
+/// Only JSON bodies, which can be redacted, of known size up to `max_size` are logged.
+/// These are buffered in full so they can be logged and then passed on, after the values
+/// of configured keys are redacted.
+/// Other bodies are passed on untouched and only their size and content type are logged.
+/// When no configuration is given requests are passed through untouched.
+#[derive(Clone)]
+pub struct BodyLogging {
+    config: Option<Rc<BodyLoggingConfig>>,
+    logger: Logger,
+}
+
+impl BodyLogging {
+    pub fn new(config: Option<BodyLoggingConfig>, logger: Logger) -> BodyLogging {
+        let config = config.map(Rc::new);
+        BodyLogging { config, logger }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for BodyLogging
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = BodyLoggingService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(BodyLoggingService {
+            config: self.config.clone(),
+            logger: self.logger.clone(),
+            service: Rc::new(service),
+        }))
+    }
+}
+
+/// Service wrapper created by the `BodyLogging` middleware.
+pub struct BodyLoggingService<S> {
+    config: Option<Rc<BodyLoggingConfig>>,
+    logger: Logger,
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for BodyLoggingService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut request: ServiceRequest) -> Self::Future {
+        let config = match &self.config {
+            Some(config) if sampled(config.sample_percent) => Rc::clone(config),
+            _ => {
+                let response = self.service.call(request);
+                return Box::pin(async move { Ok(response.await?.map_into_boxed_body()) });
+            }
+        };
+        let logger = self.logger.clone();
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let method = request.method().to_string();
+            let path = request.path().to_string();
+
+            // Buffer the request body to log it and hand it back to the request.
+            let size = request_size(request.headers());
+            match loggable(size, content_type(request.headers()), &config) {
+                Err(summary) => debug!(
+                    logger, "Actions API request body";
+                    "method" => &method,
+                    "path" => &path,
+                    "body" => summary,
+                ),
+                Ok(()) => {
+                    let mut payload = request.take_payload();
+                    let mut body = BytesMut::new();
+                    while let Some(chunk) = payload.next().await {
+                        body.extend_from_slice(&chunk?);
+                    }
+                    let body = body.freeze();
+                    debug!(
+                        logger, "Actions API request body";
+                        "method" => &method,
+                        "path" => &path,
+                        "body" => render(&body, &config),
+                    );
+                    request.set_payload(Payload::from(body));
+                }
+            }
+
+            // Buffer the response body to log it and rebuild the response.
+            let response = service.call(request).await?;
+            let status = response.status().as_u16();
+            let size = match response.response().body().size() {
+                BodySize::None => Some(0),
+                BodySize::Sized(size) => Some(size),
+                BodySize::Stream => None,
+            };
+            if let Err(summary) = loggable(size, content_type(response.headers()), &config) {
+                debug!(
+                    logger, "Actions API response body";
+                    "method" => &method,
+                    "path" => &path,
+                    "status" => status,
+                    "body" => summary,
+                );
+                return Ok(response.map_into_boxed_body());
+            }
+            let (request, response) = response.into_parts();
+            let (response, body) = response.into_parts();
+            let body = to_bytes(body)
+                .await
+                .map_err(|error| ErrorInternalServerError(error.into().to_string()))?;
+            debug!(
+                logger, "Actions API response body";
+                "method" => &method,
+                "path" => &path,
+                "status" => status,
+                "body" => render(&body, &config),
+            );
+            let response = response.set_body(body).map_into_boxed_body();
+            Ok(ServiceResponse::new(request, response))
+        })
+    }
+}
+
+/// Content type of a request or response, if set.
+fn content_type(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Check if a body is buffered and logged, or return the summary to log in its place.
+///
+/// Bodies of unknown size are never buffered as they could be of any size.
+fn loggable(
+    size: Option<u64>,
+    content_type: Option<&str>,
+    config: &BodyLoggingConfig,
+) -> std::result::Result<(), String> {
+    let json = content_type
+        .map(|content_type| content_type.contains("json"))
+        .unwrap_or(false);
+    match size {
+        Some(0) => Ok(()),
+        None => Err("[body of unknown size not logged]".into()),
+        Some(size) if !json => Err(format!(
+            "[{} bytes of {} not logged]",
+            size,
+            content_type.unwrap_or("unknown content type"),
+        )),
+        Some(size) if size > config.max_size as u64 => {
+            Err(format!("[{} bytes not logged: larger than max_size]", size))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// Size of a request body from its headers, if known.
+///
+/// Requests with neither a length nor a transfer encoding have no body.
+fn request_size(headers: &HeaderMap) -> Option<u64> {
+    match headers.get(CONTENT_LENGTH) {
+        Some(length) => length.to_str().ok().and_then(|length| length.parse().ok()),
+        None if headers.contains_key(TRANSFER_ENCODING) => None,
+        None => Some(0),
+    }
+}
+
+/// Replace the values of redacted keys anywhere in a JSON document.
+fn redact(value: &mut Value, keys: &[String]) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                redact(item, keys);
+            }
+        }
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if keys.iter().any(|redacted| redacted == key) {
+                    *value = Value::String(REDACTED.into());
+                } else {
+                    redact(value, keys);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Render a JSON body for logging, redacting it and truncating it if long.
+///
+/// Bodies that are not valid JSON can't be redacted so they are not logged.
+fn render(body: &Bytes, config: &BodyLoggingConfig) -> String {
+    if body.is_empty() {
+        return String::new();
+    }
+    let mut rendered = match serde_json::from_slice::<Value>(body) {
+        Ok(mut value) => {
+            redact(&mut value, &config.redact);
+            value.to_string()
+        }
+        Err(_) => return format!("[{} bytes of invalid JSON not logged]", body.len()),
+    };
+    if rendered.len() > config.max_size {
+        let mut end = config.max_size;
+        while !rendered.is_char_boundary(end) {
+            end -= 1;
+        }
+        rendered.truncate(end);
+        rendered.push_str("...");
+    }
+    rendered
+}
+
+/// Decide if a request is sampled for body logging.
+fn sampled(percent: u8) -> bool {
+    (Uuid::new_v4().as_u128() % 100) < u128::from(percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::HeaderMap;
+    use actix_web::http::header::HeaderValue;
+    use actix_web::http::header::CONTENT_LENGTH;
+    use actix_web::http::header::TRANSFER_ENCODING;
+    use actix_web::web::Bytes;
+
+    use super::loggable;
+    use super::render;
+    use super::request_size;
+    use super::sampled;
+    use crate::config::BodyLoggingConfig;
+
+    #[test]
+    fn loggable_json_within_max_size() {
+        let config = BodyLoggingConfig::default();
+        assert!(loggable(Some(0), None, &config).is_ok());
+        assert!(loggable(Some(128), Some("application/json"), &config).is_ok());
+        assert_eq!(
+            loggable(Some(4097), Some("application/json"), &config),
+            Err("[4097 bytes not logged: larger than max_size]".to_string()),
+        );
+        assert_eq!(
+            loggable(None, Some("application/json"), &config),
+            Err("[body of unknown size not logged]".to_string()),
+        );
+    }
+
+    #[test]
+    fn loggable_skips_binary_bodies() {
+        let config = BodyLoggingConfig::default();
+        assert_eq!(
+            loggable(Some(12), Some("application/msgpack"), &config),
+            Err("[12 bytes of application/msgpack not logged]".to_string()),
+        );
+        assert_eq!(
+            loggable(Some(12), None, &config),
+            Err("[12 bytes of unknown content type not logged]".to_string()),
+        );
+    }
+
+    #[test]
+    fn render_skips_invalid_json() {
+        let config = BodyLoggingConfig::default();
+        let body = Bytes::from_static(b"password=hunter2");
+        assert_eq!(
+            render(&body, &config),
+            "[16 bytes of invalid JSON not logged]"
+        );
+    }
+
+    #[test]
+    fn render_redacts_json() {
+        let config = BodyLoggingConfig::default();
+        let body = Bytes::from_static(br#"{"args":{"password":"hunter2","user":"me"}}"#);
+        assert_eq!(
+            render(&body, &config),
+            r#"{"args":{"password":"[REDACTED]","user":"me"}}"#
+        );
+    }
+
+    #[test]
+    fn render_truncates() {
+        let config = BodyLoggingConfig {
+            max_size: 4,
+            ..BodyLoggingConfig::default()
+        };
+        let body = Bytes::from_static(br#"{"user":"me"}"#);
+        assert_eq!(render(&body, &config), r#"{"us..."#);
+    }
+
+    #[test]
+    fn request_size_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(request_size(&headers), Some(0));
+        headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        assert_eq!(request_size(&headers), None);
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("42"));
+        assert_eq!(request_size(&headers), Some(42));
+    }
+
+    #[test]
+    fn sampling_bounds() {
+        assert!(!sampled(0));
+        assert!(sampled(100));
+    }
+}
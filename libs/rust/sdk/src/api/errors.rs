@@ -0,0 +1,131 @@
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::HeaderName;
+use actix_web::http::header::HeaderValue;
+use actix_web::middleware::ErrorHandlerResponse;
+use actix_web::middleware::ErrorHandlers;
+use actix_web::HttpResponse;
+use serde_json::json;
+use uuid::Uuid;
+
+#[cfg(feature = "store")]
+use crate::actions::ActionValidityError;
+use crate::Error;
+
+/// Header used to correlate API errors with requests.
+const CORRELATION_HEADER: &str = "x-request-id";
+
+/// Middleware to standardise API error responses.
+///
+/// Error responses are tagged with a correlation ID, taken from the request
+/// `X-Request-Id` header if set or generated otherwise, and returned in
+/// the response body as well as in the `X-Request-Id` response header.
+/// Errors not generated by the agent are also given a `code` and `retryable` flag.
+pub fn handlers<B: 'static>() -> ErrorHandlers<B> {
+    ErrorHandlers::new().default_handler(error_response)
+}
+
+fn error_response<B>(response: ServiceResponse<B>) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let correlation_id = response
+        .request()
+        .headers()
+        .get(CORRELATION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let status = response.status();
+    let body = match response.response().error() {
+        None => None,
+        Some(error) => match error.as_error::<Error>() {
+            Some(error) => Some(error.api_response(Some(correlation_id.clone()))),
+            None => match action_validity_response(error, &correlation_id) {
+                Some(body) => Some(body),
+                None => {
+                    let code = if status.is_client_error() {
+                        "RequestError"
+                    } else {
+                        "ServerError"
+                    };
+                    let body = json!({
+                        "code": code,
+                        "correlation_id": &correlation_id,
+                        "error": error.to_string(),
+                        "layers": [error.to_string()],
+                        "retryable": status.is_server_error(),
+                    });
+                    Some(HttpResponse::build(status).json(body))
+                }
+            },
+        },
+    };
+
+    // Responses without an error attached are from the framework (for example a 404)
+    // and are only tagged with the correlation header.
+    let mut response = match body {
+        None => response.map_into_left_body(),
+        Some(body) => {
+            let (request, _) = response.into_parts();
+            ServiceResponse::new(request, body).map_into_right_body()
+        }
+    };
+    if let Ok(value) = HeaderValue::from_str(&correlation_id) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(CORRELATION_HEADER), value);
+    }
+    Ok(ErrorHandlerResponse::Response(response))
+}
+
+/// Build the response for action arguments validation errors.
+#[cfg(feature = "store")]
+fn action_validity_response(
+    error: &actix_web::Error,
+    correlation_id: &str,
+) -> Option<HttpResponse> {
+    error
+        .as_error::<ActionValidityError>()
+        .map(|error| error.api_response(Some(correlation_id.to_string())))
+}
+
+/// Action arguments validation errors are not possible without actions.
+#[cfg(not(feature = "store"))]
+fn action_validity_response(_: &actix_web::Error, _: &str) -> Option<HttpResponse> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body_json;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use actix_web::App;
+    use actix_web::HttpResponse;
+    use serde_json::Value as Json;
+
+    use crate::ErrorKind;
+
+    async fn failing_handler() -> crate::Result<HttpResponse> {
+        Err(ErrorKind::StoreOpFailed("test").into())
+    }
+
+    #[actix_web::test]
+    async fn error_tagged_with_code_and_correlation_id() {
+        let app = App::new()
+            .wrap(super::handlers())
+            .route("/", web::get().to(failing_handler));
+        let app = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header(("X-Request-Id", "abc"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status().as_u16(), 500);
+        assert_eq!(res.headers().get("x-request-id").unwrap(), "abc");
+        let body: Json = read_body_json(res).await;
+        assert_eq!(body["code"], "StoreOpFailed");
+        assert_eq!(body["correlation_id"], "abc");
+        assert_eq!(body["retryable"], true);
+    }
+}
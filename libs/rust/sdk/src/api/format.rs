@@ -0,0 +1,127 @@
+use actix_web::http::header::Accept;
+use actix_web::http::header::Header;
+use actix_web::http::header::ACCEPT;
+use actix_web::http::header::VARY;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use serde::Serialize;
+
+const CBOR: &str = "application/cbor";
+const JSON: &str = "application/json";
+const MSGPACK: &str = "application/msgpack";
+const MSGPACK_LEGACY: &str = "application/x-msgpack";
+
+/// Encoding of API response bodies, negotiated with the `Accept` header.
+///
+/// Binary formats are only offered by endpoints that can return large payloads:
+/// datastore info and shards, along with the action info, queue, finished, next
+/// and archive endpoints. JSON remains the default for any other `Accept` value.
+/// All other endpoints and error responses are always encoded as JSON.
+///
+/// Negotiated responses set `Vary: Accept` so caches keep one copy per format.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ResponseFormat {
+    Cbor,
+    Json,
+    MsgPack,
+}
+
+impl ResponseFormat {
+    /// Select the preferred supported format from the request `Accept` header.
+    pub fn from_request(request: &HttpRequest) -> ResponseFormat {
+        let accept = match Accept::parse(request) {
+            Ok(accept) => accept,
+            Err(_) => return ResponseFormat::Json,
+        };
+        for mime in accept.ranked() {
+            let format = match mime.essence_str() {
+                CBOR => ResponseFormat::Cbor,
+                JSON | "*/*" | "application/*" => ResponseFormat::Json,
+                MSGPACK | MSGPACK_LEGACY => ResponseFormat::MsgPack,
+                _ => continue,
+            };
+            return format;
+        }
+        ResponseFormat::Json
+    }
+
+    /// MIME type of bodies encoded with this format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ResponseFormat::Cbor => CBOR,
+            ResponseFormat::Json => JSON,
+            ResponseFormat::MsgPack => MSGPACK,
+        }
+    }
+
+    /// Encode a value with this format.
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            ResponseFormat::Cbor => {
+                let mut body = Vec::new();
+                ciborium::ser::into_writer(value, &mut body).map_err(|error| error.to_string())?;
+                Ok(body)
+            }
+            ResponseFormat::Json => serde_json::to_vec(value).map_err(|error| error.to_string()),
+            ResponseFormat::MsgPack => {
+                rmp_serde::to_vec_named(value).map_err(|error| error.to_string())
+            }
+        }
+    }
+
+    /// Respond with a value encoded in this format.
+    pub fn respond<T: Serialize>(self, value: &T) -> HttpResponse {
+        match self.encode(value) {
+            Ok(body) => HttpResponse::Ok()
+                .insert_header((VARY, ACCEPT.as_str()))
+                .content_type(self.content_type())
+                .body(body),
+            Err(error) => HttpResponse::InternalServerError().body(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+    use serde_json::Value as Json;
+
+    use super::ResponseFormat;
+
+    fn format(accept: &str) -> ResponseFormat {
+        let request = TestRequest::default()
+            .insert_header(("Accept", accept))
+            .to_http_request();
+        ResponseFormat::from_request(&request)
+    }
+
+    #[test]
+    fn negotiate_format() {
+        let request = TestRequest::default().to_http_request();
+        assert_eq!(ResponseFormat::from_request(&request), ResponseFormat::Json);
+        assert_eq!(format("text/html"), ResponseFormat::Json);
+        assert_eq!(format("application/cbor"), ResponseFormat::Cbor);
+        assert_eq!(
+            format("application/json;q=0.5, application/x-msgpack"),
+            ResponseFormat::MsgPack
+        );
+    }
+
+    #[test]
+    fn respond_varies_on_accept() {
+        let response = ResponseFormat::Cbor.respond(&json!({"actions": []}));
+        let vary = response.headers().get("vary").unwrap().to_str().unwrap();
+        assert_eq!(vary, "accept");
+        let content_type = response.headers().get("content-type").unwrap();
+        assert_eq!(content_type.to_str().unwrap(), "application/cbor");
+    }
+
+    #[test]
+    fn encode_msgpack() {
+        let value = json!({"shards": [{"id": "test"}]});
+        let body = ResponseFormat::MsgPack.encode(&value).unwrap();
+        let decoded: Json = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(decoded, value);
+    }
+}
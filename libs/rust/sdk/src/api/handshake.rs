@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Result;
+use serde::Deserialize;
+use serde::Serialize;
+
+use replicante_util_actixweb::with_request_span;
+use replicante_util_actixweb::APIFlags;
+use replicante_util_actixweb::RootDescriptor;
+use replicante_util_actixweb::TracingMiddleware;
+
+#[cfg(feature = "actions")]
+use crate::actions::actions_enabled;
+#[cfg(feature = "actions")]
+use crate::actions::ACTIONS;
+use crate::api::protocol::Protocol;
+use crate::api::protocol::PROTOCOL_MAX;
+use crate::api::protocol::PROTOCOL_MIN;
+use crate::api::sampling::TraceSampling;
+#[cfg(feature = "store")]
+use crate::api::trees::RuntimeTrees;
+use crate::api::APIRoot;
+use crate::fail_span;
+use crate::AgentContext;
+
+/// Negotiate the protocol version and discover agent capabilities.
+///
+/// Clients present the latest protocol version they speak and should send the
+/// negotiated version back with the `x-replicante-protocol` header on later requests.
+pub fn handshake(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
+    web::resource("/handshake")
+        .wrap(tracer)
+        .route(web::post().to(responder))
+}
+
+async fn responder(
+    context: web::Data<AgentContext>,
+    #[cfg(feature = "store")] trees: Option<web::Data<RuntimeTrees>>,
+    body: web::Json<HandshakeRequest>,
+    mut request: HttpRequest,
+) -> Result<impl Responder> {
+    let protocol = Protocol::negotiate(body.protocol);
+    let protocol = with_request_span(&mut request, |span| {
+        protocol.map_err(|error| fail_span(error, span))
+    })?;
+
+    #[cfg(feature = "store")]
+    let flags: APIFlags = match trees {
+        Some(trees) => trees.current().into(),
+        None => context.config.api.trees.clone().into(),
+    };
+    #[cfg(not(feature = "store"))]
+    let flags: APIFlags = context.config.api.trees.clone().into();
+    let roots = [APIRoot::UnstableAPI, APIRoot::UnstableIntrospect]
+        .iter()
+        .filter(|root| root.enabled(&flags))
+        .map(|root| root.prefix())
+        .collect();
+
+    #[cfg(feature = "actions")]
+    let actions = if actions_enabled(&context.config).unwrap_or(false) {
+        let mut kinds: Vec<String> = ACTIONS::iter()
+            .map(|action| action.describe().kind)
+            .collect();
+        kinds.sort();
+        kinds
+    } else {
+        Vec::new()
+    };
+    #[cfg(not(feature = "actions"))]
+    let actions = Vec::new();
+
+    Ok(HttpResponse::Ok().json(HandshakeResponse {
+        actions,
+        payloads: protocol.payloads(),
+        protocol: protocol.version(),
+        protocol_max: PROTOCOL_MAX,
+        protocol_min: PROTOCOL_MIN,
+        roots,
+    }))
+}
+
+/// Latest protocol version the client speaks.
+#[derive(Debug, Deserialize)]
+struct HandshakeRequest {
+    protocol: u32,
+}
+
+/// Negotiated protocol and capabilities of the agent.
+#[derive(Debug, Serialize)]
+struct HandshakeResponse {
+    /// Kinds of actions the agent can execute, empty if actions are disabled.
+    actions: Vec<String>,
+
+    /// Version of each payload shape served with the negotiated protocol.
+    payloads: BTreeMap<&'static str, u32>,
+
+    /// Protocol version to use for later requests.
+    protocol: u32,
+
+    /// Latest protocol version the agent speaks.
+    protocol_max: u32,
+
+    /// Oldest protocol version the agent speaks.
+    protocol_min: u32,
+
+    /// Prefixes of the API roots currently enabled.
+    roots: Vec<&'static str>,
+}
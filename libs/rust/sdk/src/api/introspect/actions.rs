@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use serde::Serialize;
+use serde_json::Value as Json;
+
+use crate::actions::ActionsRegister;
+use crate::actions::ACTIONS;
+use crate::AgentContext;
+
+/// Prefix of the kinds of actions defined in the `external_actions` configuration.
+const EXTERNAL_PREFIX: &str = "external.agent.replicante.io/";
+
+/// Engine lane actions are executed in.
+///
+/// The actions engine executes one action at a time, in queue order,
+/// so all registered actions share the same lane.
+const ENGINE_LANE: &str = "serial";
+
+/// Expose the full actions register to debug action registration.
+///
+/// Unlike `/actions/available` this includes reserved actions details,
+/// deprecated aliases, arguments schemas, engine lanes and execution timeouts.
+#[actix_web::get("/actions-register")]
+pub async fn responder(context: web::Data<AgentContext>) -> impl Responder {
+    let aliases = ACTIONS::aliases();
+    let mut actions: Vec<RegisteredAction> = ACTIONS::iter()
+        .map(|action| {
+            let descriptor = action.describe();
+            let timeout = descriptor
+                .kind
+                .strip_prefix(EXTERNAL_PREFIX)
+                .and_then(|name| context.config.external_actions.get(name))
+                .and_then(|config| config.timeout);
+            let action_aliases = aliases
+                .iter()
+                .filter(|(_, kind)| **kind == descriptor.kind)
+                .map(|(alias, _)| alias.clone())
+                .collect();
+            RegisteredAction {
+                aliases: action_aliases,
+                reserved: ActionsRegister::is_reserved(&descriptor.kind),
+                description: descriptor.description,
+                kind: descriptor.kind,
+                lane: ENGINE_LANE,
+                schema: action.args_schema(),
+                timeout,
+            }
+        })
+        .collect();
+    actions.sort_by(|left, right| left.kind.cmp(&right.kind));
+    HttpResponse::Ok().json(RegisterResponse {
+        actions,
+        aliases,
+        lease_timeout: context.config.actions.lease_timeout,
+    })
+}
+
+/// Details of an action in the register.
+#[derive(Debug, Serialize)]
+struct RegisteredAction {
+    /// Deprecated kinds that are scheduled as this action.
+    aliases: Vec<String>,
+    description: String,
+    kind: String,
+
+    /// Engine lane the action is executed in.
+    lane: &'static str,
+
+    /// The action kind is in a scope reserved to replicante.
+    reserved: bool,
+
+    /// JSON schema of the action arguments, if the action documents one.
+    schema: Option<Json>,
+
+    /// Maximum time, in seconds, the action commands can run for (external actions only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout: Option<u64>,
+}
+
+/// Contents of the actions register.
+#[derive(Debug, Serialize)]
+struct RegisterResponse {
+    actions: Vec<RegisteredAction>,
+    aliases: BTreeMap<String, String>,
+
+    /// Time, in seconds, the agent holds a lease on the actions it executes.
+    lease_timeout: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::rt::System;
+    use actix_web::test::call_service;
+    use actix_web::test::init_service;
+    use actix_web::test::read_body_json;
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+    use actix_web::App;
+    use opentracingrust::Span;
+    use serde_json::json;
+    use serde_json::Value as Json;
+
+    use crate::actions::Action;
+    use crate::actions::ActionDescriptor;
+    use crate::actions::ActionRecordView;
+    use crate::actions::ActionValidity;
+    use crate::actions::ActionsRegister;
+    use crate::actions::ACTIONS;
+    use crate::store::Transaction;
+    use crate::AgentContext;
+    use crate::Result;
+
+    /// Action that documents the schema of its arguments.
+    struct Documented;
+
+    impl Action for Documented {
+        fn describe(&self) -> ActionDescriptor {
+            ActionDescriptor {
+                kind: "test.documented".into(),
+                description: "Action with an arguments schema".into(),
+            }
+        }
+
+        fn invoke(
+            &self,
+            _: &mut Transaction,
+            _: &dyn ActionRecordView,
+            _: Option<&mut Span>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn args_schema(&self) -> Option<Json> {
+            Some(json!({"type": "object"}))
+        }
+
+        fn validate_args(&self, _: &Json) -> ActionValidity {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn register_contents() {
+        let context = AgentContext::mock();
+        let mut register = ActionsRegister::default();
+        register.register(Documented);
+        register
+            .register_alias("test.alias", "test.documented")
+            .unwrap();
+        ACTIONS::test_with(register, || {
+            System::new().block_on(async {
+                let app = App::new()
+                    .app_data(web::Data::new(context.clone()))
+                    .service(super::responder);
+                let app = init_service(app).await;
+
+                let request = TestRequest::get().uri("/actions-register").to_request();
+                let body: Json = read_body_json(call_service(&app, request).await).await;
+                let action = &body["actions"][0];
+                assert_eq!(action["kind"], "test.documented");
+                assert_eq!(action["aliases"], json!(["test.alias"]));
+                assert_eq!(action["lane"], "serial");
+                assert_eq!(action["reserved"], false);
+                assert_eq!(action["schema"], json!({"type": "object"}));
+                assert!(action.get("timeout").is_none());
+                assert_eq!(body["aliases"]["test.alias"], "test.documented");
+                assert_eq!(body["lease_timeout"], context.config.actions.lease_timeout);
+            });
+        });
+    }
+}
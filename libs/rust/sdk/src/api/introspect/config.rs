@@ -0,0 +1,19 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+
+use crate::config::effective_config;
+use crate::config::warnings;
+use crate::AgentContext;
+
+/// Expose the running configuration, with secrets redacted.
+#[actix_web::get("/config")]
+pub async fn responder(context: web::Data<AgentContext>) -> impl Responder {
+    HttpResponse::Ok().json(effective_config(&context.config))
+}
+
+/// Expose warnings about unknown or deprecated options found when loading the configuration.
+#[actix_web::get("/config/warnings")]
+pub async fn warnings_responder() -> impl Responder {
+    HttpResponse::Ok().json(warnings())
+}
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Result;
+
+use replicante_util_actixweb::with_request_span;
+use replicante_util_actixweb::TracingMiddleware;
+
+use crate::api::sampling::TraceSampling;
+use crate::fail_span;
+use crate::AgentContext;
+
+/// Expose the most recent events observed by the agent, newest first.
+pub fn events(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
+    web::resource("/events")
+        .wrap(tracer)
+        .route(web::get().to(responder))
+}
+
+async fn responder(
+    context: web::Data<AgentContext>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    let keep = context.config.events.keep;
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let events = context
+        .store
+        .with_transaction_async(move |tx| {
+            let mut events = Vec::new();
+            for event in tx.events().history(keep, span_context)? {
+                events.push(event?);
+            }
+            Ok(events)
+        })
+        .await;
+    let events = with_request_span(&mut request, |span| {
+        events.map_err(|error| fail_span(error, span))
+    })?;
+    Ok(HttpResponse::Ok().json(events))
+}
@@ -0,0 +1,81 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use serde::Serialize;
+
+#[cfg(feature = "actions")]
+use crate::actions::actions_enabled;
+use crate::AgentContext;
+
+/// Describe optional agent behaviours so clients can adapt to them.
+#[actix_web::get("/features")]
+pub async fn responder(context: web::Data<AgentContext>) -> impl Responder {
+    #[cfg(feature = "actions")]
+    let actions = actions_enabled(&context.config).unwrap_or(false);
+    #[cfg(not(feature = "actions"))]
+    let actions = false;
+    // The actions engine only runs independently of collection when it is built in.
+    let actions_without_datastore = actions && cfg!(feature = "actions");
+    let datastore_breaker = context.config.circuit_breaker.is_some();
+    let datastore_limiter = context.config.concurrency_limit.is_some();
+    HttpResponse::Ok().json(FeaturesResponse {
+        actions,
+        actions_without_datastore,
+        datastore_breaker,
+        datastore_limiter,
+        sdk_features: sdk_features(),
+    })
+}
+
+/// Cargo features the agent SDK was built with.
+fn sdk_features() -> Vec<&'static str> {
+    let features = [
+        ("actions", cfg!(feature = "actions")),
+        ("api", cfg!(feature = "api")),
+        ("journald", cfg!(feature = "journald")),
+        ("store", cfg!(feature = "store")),
+        ("tls-openssl", cfg!(feature = "tls-openssl")),
+        ("tls-rustls", cfg!(feature = "tls-rustls")),
+    ];
+    features
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Agent features and behaviours.
+#[derive(Debug, Serialize)]
+struct FeaturesResponse {
+    /// The actions API and engine are enabled.
+    actions: bool,
+
+    /// Actions are scheduled, executed and their results recorded even while
+    /// the datastore is down and collection requests fail.
+    ///
+    /// Actions that operate on the datastore fail on their own without blocking
+    /// other actions, like `service.start` or external diagnostics, in the queue.
+    actions_without_datastore: bool,
+
+    /// Datastore calls are guarded by a circuit breaker (see the health endpoint).
+    datastore_breaker: bool,
+
+    /// Concurrent datastore calls are limited and excess requests rejected with a 429.
+    datastore_limiter: bool,
+
+    /// Cargo features the agent SDK was built with, such as `actions` or `store`.
+    sdk_features: Vec<&'static str>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sdk_features;
+
+    #[test]
+    fn sdk_features_follow_cfg() {
+        let features = sdk_features();
+        assert_eq!(features.contains(&"actions"), cfg!(feature = "actions"));
+        assert_eq!(features.contains(&"api"), cfg!(feature = "api"));
+        assert_eq!(features.contains(&"store"), cfg!(feature = "store"));
+    }
+}
@@ -0,0 +1,69 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use serde::Serialize;
+
+#[cfg(feature = "actions")]
+use crate::actions::actions_lag_warning;
+use crate::breaker::BreakerStatus;
+use crate::clock_skew_warning;
+#[cfg(feature = "store")]
+use crate::store::StoreDegraded;
+use crate::AgentContext;
+
+/// Without the store feature there is no store to degrade the agent.
+#[cfg(not(feature = "store"))]
+type StoreDegraded = ();
+
+/// Expose the agent health, failing with a 503 while the agent is degraded.
+///
+/// An open datastore circuit breaker, skewed clocks and lagging actions are reported as
+/// warnings but do not degrade the agent: restarting it would not fix them.
+#[actix_web::get("/health")]
+pub async fn responder(context: web::Data<AgentContext>) -> impl Responder {
+    #[cfg(feature = "store")]
+    let store = context.store.health().degraded();
+    #[cfg(not(feature = "store"))]
+    let store = None;
+    let datastore_breaker = context.datastore_breaker.status();
+    let mut health = HealthResponse::new(store, datastore_breaker);
+    #[cfg(feature = "actions")]
+    {
+        health.actions_lag = actions_lag_warning();
+    }
+    health.clock_skew = clock_skew_warning();
+    if health.degraded {
+        HttpResponse::ServiceUnavailable().json(health)
+    } else {
+        HttpResponse::Ok().json(health)
+    }
+}
+
+/// Agent health details.
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actions_lag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clock_skew: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    datastore_breaker: Option<BreakerStatus>,
+    degraded: bool,
+    store: Option<StoreDegraded>,
+}
+
+impl HealthResponse {
+    fn new(
+        store: Option<StoreDegraded>,
+        datastore_breaker: Option<BreakerStatus>,
+    ) -> HealthResponse {
+        let degraded = store.is_some();
+        HealthResponse {
+            actions_lag: None,
+            clock_skew: None,
+            datastore_breaker,
+            degraded,
+            store,
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Result;
+use serde::Serialize;
+
+use replicante_util_actixweb::with_request_span;
+use replicante_util_actixweb::TracingMiddleware;
+
+use crate::api::sampling::TraceSampling;
+use crate::fail_span;
+use crate::heartbeat::Heartbeat;
+use crate::heartbeat::PROCESS_ID;
+use crate::AgentContext;
+
+/// Expose the heartbeats of the current and previous agent processes.
+pub fn heartbeat(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
+    web::resource("/heartbeat")
+        .wrap(tracer)
+        .route(web::get().to(responder))
+}
+
+async fn responder(
+    context: web::Data<AgentContext>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    let keep = context.config.heartbeat.keep;
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let heartbeats = context
+        .store
+        .with_transaction_async(move |tx| {
+            let mut heartbeats = Vec::new();
+            let iter = tx.heartbeats().history(keep, span_context)?;
+            for heartbeat in iter {
+                heartbeats.push(heartbeat?);
+            }
+            Ok(heartbeats)
+        })
+        .await;
+    let heartbeats = with_request_span(&mut request, |span| {
+        heartbeats.map_err(|error| fail_span(error, span))
+    })?;
+    let response = HeartbeatResponse::new(heartbeats);
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Heartbeat with the derived process uptime.
+#[derive(Debug, Serialize)]
+struct HeartbeatInfo {
+    #[serde(flatten)]
+    heartbeat: Heartbeat,
+    uptime: i64,
+}
+
+impl From<Heartbeat> for HeartbeatInfo {
+    fn from(heartbeat: Heartbeat) -> HeartbeatInfo {
+        let uptime = heartbeat.uptime();
+        HeartbeatInfo { heartbeat, uptime }
+    }
+}
+
+/// Split heartbeats between the current and previous agent processes.
+#[derive(Debug, Serialize)]
+struct HeartbeatResponse {
+    current: Option<HeartbeatInfo>,
+    previous: Vec<HeartbeatInfo>,
+}
+
+impl HeartbeatResponse {
+    fn new(heartbeats: Vec<Heartbeat>) -> HeartbeatResponse {
+        let mut current = None;
+        let mut previous = Vec::new();
+        for heartbeat in heartbeats {
+            if heartbeat.process_id == *PROCESS_ID {
+                current = Some(heartbeat.into());
+            } else {
+                previous.push(heartbeat.into());
+            }
+        }
+        HeartbeatResponse { current, previous }
+    }
+}
@@ -4,19 +4,60 @@ use actix_web::web;
 use replicante_util_actixweb::MetricsExporter;
 use replicante_util_actixweb::RootDescriptor;
 
+#[cfg(feature = "actions")]
+use crate::actions::actions_enabled;
 use crate::api::APIRoot;
 use crate::api::AppConfigContext;
 use crate::AgentContext;
 
+#[cfg(feature = "actions")]
+mod actions;
+mod config;
+#[cfg(feature = "store")]
+mod events;
+mod features;
+mod health;
+#[cfg(feature = "store")]
+mod heartbeat;
+#[cfg(feature = "store")]
+mod store;
 mod threads;
+#[cfg(feature = "store")]
+mod trees;
+mod updates;
+mod version;
 
 /// Configure all introspection endpoints.
 pub fn configure(conf: &mut AppConfigContext) {
     APIRoot::UnstableIntrospect.and_then(&conf.context.flags, |root| {
         let metrics = metrics(&conf.context.agent);
         let prefix = root.prefix();
+        #[cfg(feature = "actions")]
+        if actions_enabled(&conf.context.agent.config).unwrap_or(false) {
+            conf.scoped_service(prefix, self::actions::responder);
+        }
+        let audit = conf.context.agent.config.api.audit.as_ref();
+        if audit.map(|audit| audit.expose).unwrap_or(false) {
+            conf.scoped_service(prefix, crate::api::audit::responder);
+        }
+        conf.scoped_service(prefix, self::config::responder);
+        conf.scoped_service(prefix, self::config::warnings_responder);
+        #[cfg(feature = "store")]
+        conf.scoped_service(prefix, self::events::events(&conf.context.agent));
+        conf.scoped_service(prefix, self::features::responder);
+        #[cfg(feature = "store")]
+        conf.scoped_service(prefix, self::heartbeat::heartbeat(&conf.context.agent));
+        conf.scoped_service(prefix, self::health::responder);
         conf.scoped_service(prefix, metrics);
+        #[cfg(feature = "store")]
+        conf.scoped_service(prefix, self::store::responder);
         conf.scoped_service(prefix, self::threads::responder);
+        #[cfg(feature = "store")]
+        if conf.context.agent.config.api.runtime_trees.is_some() {
+            conf.scoped_service(prefix, self::trees::trees(&conf.context.agent));
+        }
+        conf.scoped_service(prefix, self::updates::responder);
+        conf.scoped_service(prefix, self::version::responder);
     });
 }
 
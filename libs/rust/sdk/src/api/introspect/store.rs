@@ -0,0 +1,13 @@
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Result;
+
+use crate::AgentContext;
+
+/// Expose the store schema version with applied and pending migrations.
+#[actix_web::get("/store")]
+pub async fn responder(context: web::Data<AgentContext>) -> Result<impl Responder> {
+    let schema = context.store.schema_async().await?;
+    Ok(HttpResponse::Ok().json(schema))
+}
@@ -0,0 +1,212 @@
+use std::sync::Arc;
+
+use actix_web::dev::HttpServiceFactory;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use actix_web::Result;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use slog::info;
+
+use replicante_util_actixweb::with_request_span;
+use replicante_util_actixweb::TracingMiddleware;
+
+use crate::api::sampling::TraceSampling;
+use crate::api::trees::RuntimeTrees;
+use crate::config::APITrees;
+use crate::fail_span;
+use crate::store::APITreeOverride;
+use crate::AgentContext;
+use crate::ErrorKind;
+
+/// View and change the API trees enabled at runtime.
+///
+/// All methods require the `api.runtime_trees.token` as a bearer token.
+pub fn trees(context: &AgentContext) -> impl HttpServiceFactory {
+    let logger = context.logger.clone();
+    let tracer = Arc::clone(&context.tracer);
+    let tracer = TracingMiddleware::new(logger, tracer);
+    let tracer = TraceSampling::new(context, tracer);
+    web::resource("/trees")
+        .wrap(tracer)
+        .route(web::get().to(get_responder))
+        .route(web::post().to(set_responder))
+        .route(web::delete().to(reset_responder))
+}
+
+async fn get_responder(
+    context: web::Data<AgentContext>,
+    trees: web::Data<RuntimeTrees>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    authorize(&mut request, &context)?;
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let overrides: crate::Result<Vec<APITreeOverride>> = context
+        .store
+        .with_transaction_async(move |tx| tx.api_tree_overrides().list(span_context)?.collect())
+        .await;
+    let overrides = with_request_span(&mut request, |span| {
+        overrides.map_err(|error| fail_span(error, span))
+    })?;
+    Ok(HttpResponse::Ok().json(TreesResponse::new(&trees, overrides)))
+}
+
+async fn set_responder(
+    context: web::Data<AgentContext>,
+    trees: web::Data<RuntimeTrees>,
+    body: web::Json<TreesRequest>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    authorize(&mut request, &context)?;
+    let body = body.into_inner();
+    let now = Utc::now();
+    let mut changes = Vec::new();
+    if let Some(enabled) = body.introspect {
+        changes.push(APITreeOverride {
+            enabled,
+            tree: "introspect".into(),
+            updated_ts: now,
+        });
+    }
+    if let Some(enabled) = body.unstable {
+        changes.push(APITreeOverride {
+            enabled,
+            tree: "unstable".into(),
+            updated_ts: now,
+        });
+    }
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let persist = changes.clone();
+    let overrides: crate::Result<Vec<APITreeOverride>> = context
+        .store
+        .with_transaction_async(move |tx| {
+            for tree in &persist {
+                tx.api_tree_overrides().set(tree, span_context.clone())?;
+            }
+            tx.api_tree_overrides().list(span_context)?.collect()
+        })
+        .await;
+    let overrides = with_request_span(&mut request, |span| {
+        overrides.map_err(|error| fail_span(error, span))
+    })?;
+    for tree in &changes {
+        trees.apply(tree);
+        info!(
+            context.logger, "API tree changed at runtime";
+            "tree" => &tree.tree,
+            "enabled" => tree.enabled,
+        );
+    }
+    Ok(HttpResponse::Ok().json(TreesResponse::new(&trees, overrides)))
+}
+
+async fn reset_responder(
+    context: web::Data<AgentContext>,
+    trees: web::Data<RuntimeTrees>,
+    request: HttpRequest,
+) -> Result<impl Responder> {
+    let mut request = request;
+    authorize(&mut request, &context)?;
+    let span_context = with_request_span(&mut request, |span| {
+        span.as_ref().map(|span| span.context().clone())
+    });
+    let result = context
+        .store
+        .with_transaction_async(move |tx| tx.api_tree_overrides().clear(span_context))
+        .await;
+    with_request_span(&mut request, |span| {
+        result.map_err(|error| fail_span(error, span))
+    })?;
+    trees.reset();
+    info!(
+        context.logger,
+        "API trees reverted to their configured state"
+    );
+    Ok(HttpResponse::Ok().json(TreesResponse::new(&trees, Vec::new())))
+}
+
+/// Check the request carries the configured bearer token.
+fn authorize(request: &mut HttpRequest, context: &AgentContext) -> Result<()> {
+    let expected = context
+        .config
+        .api
+        .runtime_trees
+        .as_ref()
+        .map(|config| config.token.as_bytes());
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .map(str::as_bytes);
+    let authorized = match (expected, token) {
+        (Some(expected), Some(token)) => constant_time_eq(expected, token),
+        _ => false,
+    };
+    if !authorized {
+        let error = ErrorKind::Unauthorized("API trees").into();
+        return Err(with_request_span(request, |span| fail_span(error, span)).into());
+    }
+    Ok(())
+}
+
+/// Compare secrets without leaking how much of them matched through timing.
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    left.iter()
+        .zip(right.iter())
+        .fold(0, |diff, (left, right)| diff | (left ^ right))
+        == 0
+}
+
+/// API trees to enable or disable, trees not given are left unchanged.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TreesRequest {
+    #[serde(default)]
+    introspect: Option<bool>,
+    #[serde(default)]
+    unstable: Option<bool>,
+}
+
+/// Current, configured and overridden API trees.
+#[derive(Debug, Serialize)]
+struct TreesResponse {
+    configured: APITrees,
+    current: APITrees,
+    overrides: Vec<APITreeOverride>,
+}
+
+impl TreesResponse {
+    fn new(trees: &RuntimeTrees, overrides: Vec<APITreeOverride>) -> TreesResponse {
+        TreesResponse {
+            configured: trees.configured().clone(),
+            current: trees.current(),
+            overrides,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::constant_time_eq;
+
+    #[test]
+    fn compare_tokens() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
@@ -0,0 +1,18 @@
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use serde::Serialize;
+
+use crate::updates::UpdateStatus;
+
+/// Expose the outcome of the update checker.
+#[actix_web::get("/updates")]
+pub async fn responder() -> impl Responder {
+    let status = crate::updates::status();
+    HttpResponse::Ok().json(UpdatesResponse { status })
+}
+
+/// Wrap the update status to report agents that never started the checker.
+#[derive(Debug, Serialize)]
+struct UpdatesResponse {
+    status: Option<UpdateStatus>,
+}
@@ -0,0 +1,8 @@
+use actix_web::HttpResponse;
+use actix_web::Responder;
+
+/// Expose the version and build details of the agent.
+#[actix_web::get("/version")]
+pub async fn responder() -> impl Responder {
+    HttpResponse::Ok().json(crate::build::current())
+}
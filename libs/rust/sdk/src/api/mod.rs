@@ -1,40 +1,61 @@
-use std::sync::mpsc::sync_channel;
 use std::sync::Arc;
 use std::time::Duration;
 
+use actix_cors::Cors;
+use actix_web::http::Method;
 use actix_web::middleware;
+use actix_web::middleware::Condition;
 use actix_web::web::Data;
 use actix_web::App;
 use actix_web::HttpServer;
-use failure::ResultExt;
-use humthreads::Builder;
-use openssl::ssl::SslAcceptor;
-use openssl::ssl::SslFiletype;
-use openssl::ssl::SslMethod;
-use openssl::ssl::SslVerifyMode;
 use slog::info;
 
 use replicante_util_actixweb::APIFlags;
 use replicante_util_actixweb::LoggingMiddleware;
 use replicante_util_actixweb::MetricsMiddleware;
 use replicante_util_actixweb::RootDescriptor;
-use replicante_util_upkeep::Upkeep;
 
+#[cfg(feature = "actions")]
 mod actions;
+mod actions_disabled;
 mod agent;
+mod audit;
+mod body_logging;
+mod errors;
+mod format;
+mod handshake;
 mod index;
 mod introspect;
+mod namespace;
+mod payloads;
+mod protocol;
 mod roots;
+mod sampling;
+mod snapshot;
+mod tls;
+mod trace_headers;
+#[cfg(feature = "store")]
+mod trees;
 
+#[cfg(feature = "actions")]
 use crate::actions::actions_enabled;
+#[cfg(feature = "store")]
+use crate::config::APITrees;
+use crate::config::CorsConfig;
 use crate::metrics::REQUESTS;
+use crate::runtime::AgentRuntime;
 use crate::Agent;
 use crate::AgentContext;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
 pub use self::roots::APIRoot;
 
+use self::audit::Audit;
+use self::audit::AuditTrail;
+use self::trace_headers::TraceHeaders;
+
 /// Context for `AppConfig` configuration callbacks.
 pub type AppConfigContext<'a> = replicante_util_actixweb::AppConfigContext<'a, APIContext>;
 
@@ -54,6 +75,37 @@ fn configure(conf: &mut AppConfigContext) {
             conf.scoped_service(root.prefix(), index::index);
         });
     }
+    APIRoot::UnstableAPI.and_then(&conf.context.flags, |root| {
+        let handshake = handshake::handshake(&conf.context.agent);
+        conf.scoped_service(root.prefix(), handshake);
+    });
+}
+
+/// Configure the CORS middleware for the API server.
+///
+/// When CORS is not configured the returned middleware is not registered
+/// and browsers apply the default same-origin policy.
+fn cors(config: Option<&CorsConfig>) -> Cors {
+    let config = match config {
+        None => return Cors::default(),
+        Some(config) => config,
+    };
+    let methods: Vec<Method> = config
+        .allowed_methods
+        .iter()
+        .filter_map(|method| Method::from_bytes(method.as_bytes()).ok())
+        .collect();
+    let mut cors = Cors::default()
+        .allowed_methods(methods)
+        .allow_any_header()
+        .max_age(config.max_age);
+    for origin in &config.allowed_origins {
+        cors = match origin.as_str() {
+            "*" => cors.allow_any_origin(),
+            origin => cors.allowed_origin(origin),
+        };
+    }
+    cors
 }
 
 /// Start the HTTP server.
@@ -62,131 +114,158 @@ fn configure(conf: &mut AppConfigContext) {
 ///
 /// This method panics if:
 ///
-///   * It fails to bind to the configured port.
+///   * It fails to bind to any of the configured addresses.
+///   * It fails to load the configured TLS certificates.
 ///   * It fails to start the HTTP server.
-pub fn spawn_server<A>(agent: A, context: AgentContext, upkeep: &mut Upkeep) -> Result<()>
-where
-    A: Agent + 'static,
-{
-    let agent: Arc<dyn Agent> = Arc::new(agent);
-    let (send_server, receive_server) = sync_channel(0);
-    let thread = Builder::new("r:b:api")
-        .full_name("replicante:base:api")
-        .spawn(move |scope| {
-            let config = context.config.api.clone();
-            let logger = context.logger.clone();
-            let sentry_capture_api = context
-                .config
-                .sentry
-                .as_ref()
-                .map(|sentry| sentry.capture_api_errors)
-                .unwrap_or(true);
-
-            // Extend the API server configuration with routes.
-            // Only `app_config` will then move into the closure, with all the dependencies
-            // tucked away into the `AppConfig::register`ed closures.
-            let api_conf = {
-                let mut api_conf = context.api_conf.clone();
-                api_conf.register(configure);
-                if actions_enabled(&context.config).unwrap_or(false) {
-                    api_conf.register(actions::configure_enabled);
-                } else {
-                    api_conf.register(actions::configure_disabled);
-                }
-                api_conf.register(agent::configure);
-                api_conf.register(introspect::configure);
-                api_conf
-            };
-            let api_context = APIContext {
-                agent: context.clone(),
-                flags: context.config.api.trees.clone().into(),
-            };
-
-            // Initialise and configure HTTP server and App factory.
-            let mut server = HttpServer::new(move || {
-                // Give every mounted route access to the global context.
-                let app = App::new()
-                    .app_data(Data::new(Arc::clone(&agent)))
-                    .app_data(Data::new(context.clone()));
-
-                // Register application middleware.
-                // Remember that middleware are executed in reverse registration order.
-                let app = app
-                    .wrap(LoggingMiddleware::new(context.logger.clone()))
-                    .wrap(MetricsMiddleware::new(REQUESTS.clone()))
-                    .wrap(middleware::Compress::default());
-
-                // Add the sentry middleware if configured.
-                let sentry_capture = sentry_actix::Sentry::builder()
-                    .capture_server_errors(sentry_capture_api)
-                    .emit_header(true)
-                    .finish();
-                let app = app.wrap(sentry_capture);
-
-                // Configure and return the ActixWeb App
-                let mut api_conf = api_conf.clone();
-                app.configure(|app| api_conf.configure(app, &api_context))
-            });
-            if let Some(keep_alive) = config.timeouts.keep_alive {
-                let keep_alive = Duration::from_secs(keep_alive);
-                server = server.keep_alive(keep_alive);
-            }
-            if let Some(read) = config.timeouts.read {
-                let read = Duration::from_secs(read);
-                server = server.client_request_timeout(read);
-            }
-            if let Some(write) = config.timeouts.write {
-                let write = Duration::from_secs(write);
-                server = server.client_disconnect_timeout(write);
-            }
-            if let Some(threads_count) = config.threads_count {
-                server = server.workers(threads_count);
-            }
+pub fn spawn_server(
+    agent: Arc<dyn Agent>,
+    context: AgentContext,
+    runtime: &mut AgentRuntime,
+) -> Result<()> {
+    // Mount all API trees if they can be enabled at runtime and gate requests instead.
+    #[cfg_attr(not(feature = "store"), allow(unused_mut))]
+    let mut flags: APIFlags = context.config.api.trees.clone().into();
+    #[cfg(feature = "store")]
+    let runtime_trees = self::trees::RuntimeTrees::load(&context)?;
+    #[cfg(feature = "store")]
+    if runtime_trees.is_some() {
+        flags = APITrees::default().into();
+    }
+
+    let config = context.config.api.clone();
+    let audit_config = config.audit.clone();
+    let audit_trail = AuditTrail::new(audit_config.as_ref().map(|c| c.keep).unwrap_or(0));
+    let cors_config = config.cors.clone();
+    let logger = context.logger.clone();
+    let sentry_capture_api = context
+        .config
+        .sentry
+        .as_ref()
+        .map(|sentry| sentry.capture_api_errors)
+        .unwrap_or(true);
+
+    // Extend the API server configuration with routes.
+    // Only `app_config` will then move into the closure, with all the dependencies
+    // tucked away into the `AppConfig::register`ed closures.
+    let api_conf = {
+        let mut api_conf = context.api_conf.clone();
+        api_conf.register(configure);
+        #[cfg(feature = "actions")]
+        if actions_enabled(&context.config).unwrap_or(false) {
+            api_conf.register(actions::configure_enabled);
+        } else {
+            api_conf.register(actions_disabled::configure);
+        }
+        #[cfg(not(feature = "actions"))]
+        api_conf.register(actions_disabled::configure);
+        api_conf.register(agent::configure);
+        let extensions = Arc::clone(&agent);
+        api_conf.register(move |conf| agent::configure_extensions(conf, &extensions));
+        api_conf.register(introspect::configure);
+        api_conf
+    };
+    let api_context = APIContext {
+        agent: context.clone(),
+        flags,
+    };
 
-            // Configure TLS/HTTPS if enabled and bind to the given address.
-            let server = match config.tls {
-                None => server
-                    .bind(&config.bind)
-                    .expect("unable to bind API server"),
-                Some(tls) => {
-                    let mut builder = SslAcceptor::mozilla_modern(SslMethod::tls())
-                        .expect("unable to initialise TLS acceptor for API server");
-                    builder
-                        .set_certificate_file(&tls.server_cert, SslFiletype::PEM)
-                        .expect("unable to set TLS server public certificate");
-                    builder
-                        .set_private_key_file(&tls.server_key, SslFiletype::PEM)
-                        .expect("unable to set TLS server private key");
-                    if let Some(bundle) = tls.clients_ca_bundle {
-                        builder
-                            .set_ca_file(&bundle)
-                            .expect("unable to set clients CAs bundle");
-                        builder
-                            .set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
-                    }
-                    server
-                        .bind_openssl(&config.bind, builder)
-                        .expect("unable to bind API server")
+    // Initialise and configure HTTP server and App factory.
+    let mut server = HttpServer::new(move || {
+        // Give every mounted route access to the global context.
+        let app = App::new()
+            .app_data(Data::new(Arc::clone(&agent)))
+            .app_data(Data::new(context.clone()))
+            .app_data(Data::new(audit_trail.clone()));
+        #[cfg(feature = "store")]
+        let app = match &runtime_trees {
+            None => app,
+            Some(trees) => app.app_data(Data::new(trees.clone())),
+        };
+        // Reject requests to API trees disabled at runtime.
+        #[cfg(feature = "store")]
+        let app = app.wrap(self::trees::TreesGate::new(runtime_trees.clone()));
+
+        // Register application middleware.
+        // Remember that middleware are executed in reverse registration order.
+        let app = app
+            .wrap(LoggingMiddleware::new(context.logger.clone()))
+            .wrap(MetricsMiddleware::new(REQUESTS.clone()))
+            .wrap(errors::handlers())
+            .wrap(Audit::new(
+                audit_config.as_ref(),
+                audit_trail.clone(),
+                context.logger.clone(),
+            ))
+            .wrap(middleware::Compress::default())
+            .wrap(TraceHeaders);
+
+        // Add the sentry middleware if configured.
+        let sentry_capture = sentry_actix::Sentry::builder()
+            .capture_server_errors(sentry_capture_api)
+            .emit_header(true)
+            .finish();
+        let app = app.wrap(sentry_capture);
+
+        // Allow cross-origin requests from browser-based tools if configured.
+        let cors_enabled = cors_config.is_some();
+        let app = app.wrap(Condition::new(cors_enabled, cors(cors_config.as_ref())));
+
+        // Configure and return the ActixWeb App
+        let mut api_conf = api_conf.clone();
+        app.configure(|app| api_conf.configure(app, &api_context))
+    });
+    if let Some(keep_alive) = config.timeouts.keep_alive {
+        let keep_alive = Duration::from_secs(keep_alive);
+        server = server.keep_alive(keep_alive);
+    }
+    if let Some(read) = config.timeouts.read {
+        let read = Duration::from_secs(read);
+        server = server.client_request_timeout(read);
+    }
+    if let Some(write) = config.timeouts.write {
+        let write = Duration::from_secs(write);
+        server = server.client_disconnect_timeout(write);
+    }
+    if let Some(threads_count) = config.threads_count {
+        server = server.workers(threads_count);
+    }
+    server = server.on_connect(audit::on_connect);
+
+    // Configure TLS/HTTPS if enabled and bind to the given addresses.
+    for bind in &config.bind {
+        server = match &config.tls {
+            None => server.bind(bind).expect("unable to bind API server"),
+            #[cfg(feature = "tls-rustls")]
+            Some(tls) => server
+                .bind_rustls(bind, tls::rustls_config(tls))
+                .expect("unable to bind API server"),
+            #[cfg(all(feature = "tls-openssl", not(feature = "tls-rustls")))]
+            Some(tls) => server
+                .bind_openssl(bind, tls::openssl_acceptor(tls))
+                .expect("unable to bind API server"),
+            #[cfg(not(any(feature = "tls-openssl", feature = "tls-rustls")))]
+            Some(_) => panic!("the agent was built without TLS support"),
+        };
+    }
+
+    // Bound sockets are listening from here on and the server runs on the async runtime.
+    // Signals are handled by `Upkeep`, which stops the server gracefully on shutdown.
+    info!(logger, "Starting API server"; "bind" => config.bind.join(", "));
+    let server = server.disable_signals();
+    runtime.spawn("api server", |mut shutdown| {
+        let mut server = server.run();
+        async move {
+            let handle = server.handle();
+            tokio::select! {
+                result = &mut server => {
+                    result.expect("unable to run API server");
+                    return;
                 }
-            };
-
-            // Start HTTP server and block until shutdown.
-            info!(logger, "Starting API server"; "bind" => &config.bind);
-            scope.activity("running https://actix.rs/ HTTP(S) server");
-            let runner = actix_web::rt::System::new();
-            let server = server.run();
-            send_server
-                .send(server.handle())
-                .expect("unable to send back server handle");
-            runner.block_on(server).expect("unable to run API server");
-        })
-        .with_context(|_| ErrorKind::ThreadSpawn("api server"))?;
-    upkeep.register_thread(thread);
-    let server = receive_server
-        .recv()
-        .with_context(|_| ErrorKind::Initialisation("failed to spawn API server".into()))?;
-    upkeep.on_shutdown(move || {
-        futures::executor::block_on(server.stop(true));
+                _ = shutdown.requested() => (),
+            }
+            let (result, ()) = tokio::join!(server, handle.stop(true));
+            result.expect("unable to stop API server");
+        }
     });
     Ok(())
 }
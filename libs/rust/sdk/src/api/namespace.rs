@@ -0,0 +1,83 @@
+use actix_web::HttpRequest;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Header clients set to the namespace of the agent they mean to operate on.
+#[cfg_attr(not(feature = "actions"), allow(dead_code))]
+const NAMESPACE_HEADER: &str = "x-replicante-namespace";
+
+/// API payload tagged with the agent namespace, if one is configured.
+#[derive(Serialize)]
+pub struct Namespaced<T> {
+    #[serde(flatten)]
+    pub inner: T,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+impl<T> Namespaced<T> {
+    pub fn new(inner: T, namespace: &Option<String>) -> Namespaced<T> {
+        Namespaced {
+            inner,
+            namespace: namespace.clone(),
+        }
+    }
+}
+
+/// Reject requests that target a namespace other than the agent's.
+///
+/// Requests without the namespace header are accepted so that deployments
+/// not using namespaces (or clients not yet aware of them) keep working.
+#[cfg_attr(not(feature = "actions"), allow(dead_code))]
+pub fn check(request: &HttpRequest, namespace: &Option<String>) -> Result<()> {
+    let requested = match request.headers().get(NAMESPACE_HEADER) {
+        None => return Ok(()),
+        Some(requested) => String::from_utf8_lossy(requested.as_bytes()).to_string(),
+    };
+    if namespace.as_deref() == Some(requested.as_str()) {
+        return Ok(());
+    }
+    let namespace = namespace.clone().unwrap_or_default();
+    Err(ErrorKind::NamespaceMismatch(requested, namespace).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+
+    use super::check;
+    use super::Namespaced;
+
+    #[test]
+    fn check_namespace_header() {
+        let namespace = Some("tenant-1".to_string());
+        let request = TestRequest::default().to_http_request();
+        assert!(check(&request, &namespace).is_ok());
+        assert!(check(&request, &None).is_ok());
+
+        let request = TestRequest::default()
+            .insert_header(("X-Replicante-Namespace", "tenant-1"))
+            .to_http_request();
+        assert!(check(&request, &namespace).is_ok());
+        assert!(check(&request, &None).is_err());
+
+        let request = TestRequest::default()
+            .insert_header(("X-Replicante-Namespace", "tenant-2"))
+            .to_http_request();
+        assert!(check(&request, &namespace).is_err());
+    }
+
+    #[test]
+    fn namespaced_payload() {
+        let payload = Namespaced::new(json!({"id": "a"}), &Some("tenant-1".into()));
+        let payload = serde_json::to_value(&payload).unwrap();
+        assert_eq!(payload, json!({"id": "a", "namespace": "tenant-1"}));
+        let payload = Namespaced::new(json!({"id": "a"}), &None);
+        let payload = serde_json::to_value(&payload).unwrap();
+        assert_eq!(payload, json!({"id": "a"}));
+    }
+}
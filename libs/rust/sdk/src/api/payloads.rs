@@ -0,0 +1,151 @@
+use actix_web::http::header::Accept;
+use actix_web::http::header::Header;
+use actix_web::web;
+use actix_web::HttpRequest;
+use serde::Deserialize;
+
+use crate::api::format::ResponseFormat;
+use crate::api::protocol::Protocol;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Version of the payload shapes served to a client.
+///
+/// The version is selected, in order of preference, with:
+///
+///   1. The `payload` query parameter (`?payload=v1`).
+///   2. The `version` parameter of the `Accept` header (`application/json; version=1`).
+///   3. The protocol version negotiated with `/handshake`.
+///
+/// This lets Core instances keep decoding payloads while agents are upgraded
+/// and `replicante_models_agent` models change shape.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum PayloadVersion {
+    /// Plain `replicante_models_agent` models.
+    V1,
+
+    /// Models with agent extensions (shard errors, extra details, namespace, ...).
+    V2,
+}
+
+impl PayloadVersion {
+    /// Select the payload version requested by the client.
+    pub fn from_request(request: &HttpRequest) -> Result<PayloadVersion> {
+        let query = web::Query::<PayloadQuery>::from_query(request.query_string())
+            .ok()
+            .and_then(|query| query.into_inner().payload);
+        if let Some(version) = query {
+            return PayloadVersion::parse(&version)
+                .ok_or_else(|| ErrorKind::InvalidQueryParam("payload", version).into());
+        }
+
+        let accept = Accept::parse(request).ok().and_then(|accept| {
+            accept.ranked().into_iter().find_map(|mime| {
+                mime.get_param("version")
+                    .map(|version| version.as_str().to_string())
+            })
+        });
+        if let Some(version) = accept {
+            return PayloadVersion::parse(&version)
+                .ok_or_else(|| ErrorKind::PayloadVersionUnsupported(version).into());
+        }
+
+        Ok(PayloadVersion::from(Protocol::from_request(request)))
+    }
+
+    /// Numeric version reported to clients.
+    pub fn number(self) -> u32 {
+        match self {
+            PayloadVersion::V1 => 1,
+            PayloadVersion::V2 => 2,
+        }
+    }
+
+    fn parse(version: &str) -> Option<PayloadVersion> {
+        match version.trim().trim_start_matches('v') {
+            "1" => Some(PayloadVersion::V1),
+            "2" => Some(PayloadVersion::V2),
+            _ => None,
+        }
+    }
+}
+
+impl From<Protocol> for PayloadVersion {
+    fn from(protocol: Protocol) -> PayloadVersion {
+        if protocol.extended() {
+            PayloadVersion::V2
+        } else {
+            PayloadVersion::V1
+        }
+    }
+}
+
+/// Payloads that can be serialized in the shape of older versions.
+///
+/// Payloads are built in the latest shape and each implementation picks
+/// the parts to encode for the requested version.
+pub trait VersionedPayload {
+    /// Encode the payload in the shape of the given version.
+    fn encode(
+        &self,
+        version: PayloadVersion,
+        format: ResponseFormat,
+    ) -> std::result::Result<Vec<u8>, String>;
+}
+
+#[derive(Deserialize)]
+struct PayloadQuery {
+    #[serde(default)]
+    payload: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::PayloadVersion;
+
+    #[test]
+    fn default_to_latest() {
+        let request = TestRequest::default().to_http_request();
+        let version = PayloadVersion::from_request(&request).unwrap();
+        assert_eq!(version, PayloadVersion::V2);
+    }
+
+    #[test]
+    fn select_with_accept() {
+        let request = TestRequest::default()
+            .insert_header(("Accept", "application/msgpack; version=1"))
+            .to_http_request();
+        let version = PayloadVersion::from_request(&request).unwrap();
+        assert_eq!(version, PayloadVersion::V1);
+
+        let request = TestRequest::default()
+            .insert_header(("Accept", "application/json; version=7"))
+            .to_http_request();
+        let error = PayloadVersion::from_request(&request).unwrap_err();
+        assert_eq!(error.kind().code(), "PayloadVersionUnsupported");
+    }
+
+    #[test]
+    fn select_with_protocol() {
+        let request = TestRequest::default()
+            .insert_header(("X-Replicante-Protocol", "1"))
+            .to_http_request();
+        let version = PayloadVersion::from_request(&request).unwrap();
+        assert_eq!(version, PayloadVersion::V1);
+    }
+
+    #[test]
+    fn select_with_query() {
+        let request = TestRequest::with_uri("/shards?payload=v1")
+            .insert_header(("Accept", "application/json; version=2"))
+            .to_http_request();
+        let version = PayloadVersion::from_request(&request).unwrap();
+        assert_eq!(version, PayloadVersion::V1);
+
+        let request = TestRequest::with_uri("/shards?payload=v9").to_http_request();
+        let error = PayloadVersion::from_request(&request).unwrap_err();
+        assert_eq!(error.kind().code(), "InvalidQueryParam");
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use actix_web::HttpRequest;
+
+use crate::api::payloads::PayloadVersion;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Header clients send the protocol version negotiated with `/handshake` in.
+pub(crate) const PROTOCOL_HEADER: &str = "x-replicante-protocol";
+
+/// Oldest protocol version the agent can speak.
+pub const PROTOCOL_MIN: u32 = 1;
+
+/// Latest protocol version the agent can speak.
+pub const PROTOCOL_MAX: u32 = 2;
+
+/// Version of the protocol spoken with a client, which determines default payload shapes.
+///
+///   * Version 1: payloads are the plain `replicante_models_agent` models and
+///     shard collection fails if any shard fails.
+///   * Version 2: payloads carry agent extensions (shard errors, extra details and roles,
+///     the agent namespace and datastore version warnings).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Protocol(u32);
+
+impl Protocol {
+    /// Protocol version requested with the `x-replicante-protocol` header.
+    ///
+    /// Clients that did not negotiate a version, or send one the agent does not
+    /// speak, are served the latest payloads.
+    pub fn from_request(request: &HttpRequest) -> Protocol {
+        request
+            .headers()
+            .get(PROTOCOL_HEADER)
+            .and_then(|version| version.to_str().ok())
+            .and_then(|version| version.trim().parse::<u32>().ok())
+            .and_then(|version| Protocol::negotiate(version).ok())
+            .unwrap_or(Protocol(PROTOCOL_MAX))
+    }
+
+    /// Pick the latest protocol version both the client and the agent speak.
+    pub fn negotiate(requested: u32) -> Result<Protocol> {
+        if requested < PROTOCOL_MIN {
+            return Err(
+                ErrorKind::ProtocolUnsupported(requested, PROTOCOL_MIN, PROTOCOL_MAX).into(),
+            );
+        }
+        Ok(Protocol(requested.min(PROTOCOL_MAX)))
+    }
+
+    /// Payloads include agent extensions to the plain models.
+    pub fn extended(self) -> bool {
+        self.0 >= 2
+    }
+
+    /// Version of each payload shape exchanged at this protocol version.
+    pub fn payloads(self) -> BTreeMap<&'static str, u32> {
+        let version = PayloadVersion::from(self).number();
+        let mut payloads = BTreeMap::new();
+        payloads.insert("info.agent", version);
+        payloads.insert("info.datastore", version);
+        payloads.insert("shards", version);
+        payloads
+    }
+
+    pub fn version(self) -> u32 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::Protocol;
+    use super::PROTOCOL_MAX;
+
+    #[test]
+    fn negotiate_versions() {
+        assert!(Protocol::negotiate(0).is_err());
+        assert_eq!(Protocol::negotiate(1).unwrap().version(), 1);
+        assert_eq!(Protocol::negotiate(42).unwrap().version(), PROTOCOL_MAX);
+    }
+
+    #[test]
+    fn protocol_from_request() {
+        let request = TestRequest::default().to_http_request();
+        assert_eq!(Protocol::from_request(&request).version(), PROTOCOL_MAX);
+        let request = TestRequest::default()
+            .insert_header(("X-Replicante-Protocol", "1"))
+            .to_http_request();
+        let protocol = Protocol::from_request(&request);
+        assert!(!protocol.extended());
+        assert_eq!(protocol.payloads().get("shards"), Some(&1));
+        let request = TestRequest::default()
+            .insert_header(("X-Replicante-Protocol", "junk"))
+            .to_http_request();
+        assert!(Protocol::from_request(&request).extended());
+    }
+}
@@ -0,0 +1,188 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+use actix_web::dev::forward_ready;
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::dev::Transform;
+use actix_web::http::header::HeaderMap;
+use actix_web::Error;
+use rand::Rng;
+
+use replicante_util_actixweb::TracingMiddleware;
+
+use crate::config::TraceSamplingConfig;
+use crate::AgentContext;
+
+const B3_SAMPLED: &str = "x-b3-sampled";
+
+/// Service created by the `TracingMiddleware` around the shared endpoint service.
+type Traced<S> = <TracingMiddleware as Transform<SharedService<S>, ServiceRequest>>::Transform;
+
+/// Middleware to trace only a sample of the requests to an endpoint.
+///
+/// Sampled requests go through the wrapped `TracingMiddleware` while all other requests
+/// skip it and reach handlers without a request span.
+/// Client sampling decisions are read from B3 headers, so this middleware must run
+/// after `TraceHeaders` has normalised other trace context formats.
+pub struct TraceSampling {
+    config: Arc<TraceSamplingConfig>,
+    tracing: TracingMiddleware,
+}
+
+impl TraceSampling {
+    pub fn new(context: &AgentContext, tracing: TracingMiddleware) -> TraceSampling {
+        let config = Arc::new(context.config.trace_sampling.clone());
+        TraceSampling { config, tracing }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for TraceSampling
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+    TracingMiddleware: Transform<
+        SharedService<S>,
+        ServiceRequest,
+        Response = ServiceResponse<B>,
+        Error = Error,
+        InitError = (),
+    >,
+    <TracingMiddleware as Transform<SharedService<S>, ServiceRequest>>::Future: 'static,
+    <Traced<S> as Service<ServiceRequest>>::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TraceSamplingService<S, Traced<S>>;
+    type InitError = ();
+    type Future =
+        Pin<Box<dyn Future<Output = std::result::Result<Self::Transform, Self::InitError>>>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        let service = Rc::new(service);
+        let shared = SharedService {
+            service: Rc::clone(&service),
+        };
+        let traced = self.tracing.new_transform(shared);
+        let config = Arc::clone(&self.config);
+        Box::pin(async move {
+            let traced = traced.await?;
+            Ok(TraceSamplingService {
+                config,
+                service,
+                traced,
+            })
+        })
+    }
+}
+
+/// Service wrapper created by the `TraceSampling` middleware.
+pub struct TraceSamplingService<S, T> {
+    config: Arc<TraceSamplingConfig>,
+    service: Rc<S>,
+    traced: T,
+}
+
+impl<S, T, B> Service<ServiceRequest> for TraceSamplingService<S, T>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    T: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    T::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = std::result::Result<Self::Response, Self::Error>>>>;
+
+    // The traced service wraps the shared one so it is ready only when both are.
+    forward_ready!(traced);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let percent = self.config.percent_for(request.path());
+        if sampled(request.headers(), percent) {
+            Box::pin(self.traced.call(request))
+        } else {
+            Box::pin(self.service.call(request))
+        }
+    }
+}
+
+/// Endpoint service shared between sampled and not sampled requests.
+pub struct SharedService<S> {
+    service: Rc<S>,
+}
+
+impl<S> Service<ServiceRequest> for SharedService<S>
+where
+    S: Service<ServiceRequest>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        self.service.call(request)
+    }
+}
+
+/// Decide if a request should be traced.
+///
+/// Decisions made by the client take precedence so traces are not broken up.
+fn sampled(headers: &HeaderMap, percent: u8) -> bool {
+    let decision = headers
+        .get(B3_SAMPLED)
+        .and_then(|value| value.to_str().ok());
+    match decision {
+        Some("1") | Some("true") => return true,
+        Some("0") | Some("false") => return false,
+        _ => (),
+    };
+    match percent {
+        0 => false,
+        percent if percent >= 100 => true,
+        percent => rand::thread_rng().gen_range(0..100) < percent,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::HeaderMap;
+    use actix_web::http::header::HeaderName;
+    use actix_web::http::header::HeaderValue;
+
+    use super::sampled;
+
+    fn headers(sampled: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("x-b3-sampled"),
+            HeaderValue::from_static(sampled),
+        );
+        headers
+    }
+
+    #[test]
+    fn client_decision_wins() {
+        assert!(sampled(&headers("1"), 0));
+        assert!(!sampled(&headers("0"), 100));
+        assert!(sampled(&headers("junk"), 100));
+    }
+
+    #[test]
+    fn percent_bounds() {
+        let headers = HeaderMap::new();
+        assert!((0..100).all(|_| sampled(&headers, 100)));
+        assert!((0..100).all(|_| !sampled(&headers, 0)));
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+use actix_web::http::header::ETag;
+use actix_web::http::header::EntityTag;
+use actix_web::http::header::Header;
+use actix_web::http::header::IfNoneMatch;
+use actix_web::http::header::ACCEPT;
+use actix_web::http::header::VARY;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use serde::Serialize;
+
+use crate::api::format::ResponseFormat;
+use crate::api::payloads::PayloadVersion;
+use crate::api::payloads::VersionedPayload;
+use crate::api::protocol::PROTOCOL_HEADER;
+
+/// Request details needed to respond with a datastore snapshot (info, shards, ...).
+///
+/// Snapshot responses are tagged with an `ETag` computed over the encoded body
+/// so clients polling for changes can send `If-None-Match` and receive a
+/// `304 Not Modified` response when the snapshot did not change.
+/// Snapshots are encoded in the format negotiated with the client.
+/// Responses list the request headers that select their format and shape in `Vary`
+/// so caches do not serve a tag computed for one variant to clients of another.
+///
+/// The details are extracted from the request upfront so responses can be
+/// generated while the request is borrowed for tracing.
+pub struct SnapshotRequest {
+    format: ResponseFormat,
+    if_none_match: Option<IfNoneMatch>,
+}
+
+impl SnapshotRequest {
+    pub fn new(request: &HttpRequest) -> SnapshotRequest {
+        let format = ResponseFormat::from_request(request);
+        let if_none_match = IfNoneMatch::parse(request).ok();
+        SnapshotRequest {
+            format,
+            if_none_match,
+        }
+    }
+
+    /// Encode the snapshot as the response body, unless the client has it already.
+    pub fn respond<T: Serialize>(&self, snapshot: &T) -> HttpResponse {
+        self.respond_encoded(self.format.encode(snapshot), &[ACCEPT.as_str()])
+    }
+
+    /// Encode the snapshot in the shape of the given payload version.
+    pub fn respond_versioned<T: VersionedPayload>(
+        &self,
+        version: PayloadVersion,
+        snapshot: &T,
+    ) -> HttpResponse {
+        self.respond_encoded(
+            snapshot.encode(version, self.format),
+            &[ACCEPT.as_str(), PROTOCOL_HEADER],
+        )
+    }
+
+    fn respond_encoded(
+        &self,
+        body: std::result::Result<Vec<u8>, String>,
+        vary: &[&str],
+    ) -> HttpResponse {
+        let body = match body {
+            Ok(body) => body,
+            Err(error) => return HttpResponse::InternalServerError().body(error),
+        };
+        let etag = etag(&body);
+        let vary = vary.join(", ");
+        if self.matches(&etag) {
+            return HttpResponse::NotModified()
+                .insert_header(ETag(etag))
+                .insert_header((VARY, vary))
+                .finish();
+        }
+        HttpResponse::Ok()
+            .insert_header(ETag(etag))
+            .insert_header((VARY, vary))
+            .content_type(self.format.content_type())
+            .body(body)
+    }
+
+    /// Check if the client already has the snapshot with the given tag.
+    fn matches(&self, etag: &EntityTag) -> bool {
+        match &self.if_none_match {
+            None => false,
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        }
+    }
+}
+
+/// Compute the entity tag of an encoded snapshot.
+///
+/// Tags are weak because responses may be compressed after they are generated.
+fn etag(body: &[u8]) -> EntityTag {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(body);
+    EntityTag::new_weak(format!("{:016x}", hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+
+    use super::SnapshotRequest;
+    use crate::api::format::ResponseFormat;
+    use crate::api::payloads::PayloadVersion;
+    use crate::api::payloads::VersionedPayload;
+
+    struct Versioned;
+
+    impl VersionedPayload for Versioned {
+        fn encode(
+            &self,
+            version: PayloadVersion,
+            format: ResponseFormat,
+        ) -> std::result::Result<Vec<u8>, String> {
+            format.encode(&json!({"version": version.number()}))
+        }
+    }
+
+    #[test]
+    fn changed_snapshot() {
+        let request = TestRequest::default().to_http_request();
+        let response = SnapshotRequest::new(&request).respond(&json!({"shards": []}));
+        let etag = response.headers().get("etag").unwrap().to_str().unwrap();
+
+        let request = TestRequest::default()
+            .insert_header(("If-None-Match", etag))
+            .to_http_request();
+        let response = SnapshotRequest::new(&request).respond(&json!({"shards": [1]}));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn unchanged_snapshot() {
+        let request = TestRequest::default().to_http_request();
+        let response = SnapshotRequest::new(&request).respond(&json!({"shards": []}));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("vary").unwrap(), "accept");
+        let etag = response.headers().get("etag").unwrap().to_str().unwrap();
+
+        let request = TestRequest::default()
+            .insert_header(("If-None-Match", etag))
+            .to_http_request();
+        let response = SnapshotRequest::new(&request).respond(&json!({"shards": []}));
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn versioned_snapshot_varies() {
+        let request = TestRequest::default().to_http_request();
+        let response =
+            SnapshotRequest::new(&request).respond_versioned(PayloadVersion::V1, &Versioned);
+        let vary = response.headers().get("vary").unwrap().to_str().unwrap();
+        assert_eq!(vary, "accept, x-replicante-protocol");
+        let etag = response.headers().get("etag").unwrap().to_str().unwrap();
+
+        let request = TestRequest::default()
+            .insert_header(("If-None-Match", etag))
+            .to_http_request();
+        let response =
+            SnapshotRequest::new(&request).respond_versioned(PayloadVersion::V1, &Versioned);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(response.headers().contains_key("vary"));
+    }
+}
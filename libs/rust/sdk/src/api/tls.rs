@@ -0,0 +1,102 @@
+#[cfg(feature = "tls-rustls")]
+use std::fs::File;
+#[cfg(feature = "tls-rustls")]
+use std::io::BufReader;
+
+#[cfg(all(feature = "tls-openssl", not(feature = "tls-rustls")))]
+use openssl::ssl::SslAcceptor;
+#[cfg(all(feature = "tls-openssl", not(feature = "tls-rustls")))]
+use openssl::ssl::SslAcceptorBuilder;
+#[cfg(all(feature = "tls-openssl", not(feature = "tls-rustls")))]
+use openssl::ssl::SslFiletype;
+#[cfg(all(feature = "tls-openssl", not(feature = "tls-rustls")))]
+use openssl::ssl::SslMethod;
+#[cfg(all(feature = "tls-openssl", not(feature = "tls-rustls")))]
+use openssl::ssl::SslVerifyMode;
+#[cfg(feature = "tls-rustls")]
+use rustls::server::AllowAnyAuthenticatedClient;
+#[cfg(feature = "tls-rustls")]
+use rustls::Certificate;
+#[cfg(feature = "tls-rustls")]
+use rustls::PrivateKey;
+#[cfg(feature = "tls-rustls")]
+use rustls::RootCertStore;
+#[cfg(feature = "tls-rustls")]
+use rustls::ServerConfig;
+#[cfg(feature = "tls-rustls")]
+use rustls_pemfile::Item;
+
+#[cfg(any(feature = "tls-openssl", feature = "tls-rustls"))]
+use crate::config::TlsConfig;
+
+/// Configure the OpenSSL TLS acceptor for the API server.
+#[cfg(all(feature = "tls-openssl", not(feature = "tls-rustls")))]
+pub fn openssl_acceptor(tls: &TlsConfig) -> SslAcceptorBuilder {
+    let mut builder = SslAcceptor::mozilla_modern(SslMethod::tls())
+        .expect("unable to initialise TLS acceptor for API server");
+    builder
+        .set_certificate_file(&tls.server_cert, SslFiletype::PEM)
+        .expect("unable to set TLS server public certificate");
+    builder
+        .set_private_key_file(&tls.server_key, SslFiletype::PEM)
+        .expect("unable to set TLS server private key");
+    if let Some(bundle) = &tls.clients_ca_bundle {
+        builder
+            .set_ca_file(bundle)
+            .expect("unable to set clients CAs bundle");
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+    builder
+}
+
+/// Configure the rustls server for the API server.
+///
+/// Clients must present a certificate signed by one of the `clients_ca_bundle` CAs, if set.
+#[cfg(feature = "tls-rustls")]
+pub fn rustls_config(tls: &TlsConfig) -> ServerConfig {
+    let certs = load_certs(
+        &tls.server_cert,
+        "unable to read TLS server public certificate",
+    );
+    let key = load_key(&tls.server_key);
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &tls.clients_ca_bundle {
+        None => builder.with_no_client_auth(),
+        Some(bundle) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(bundle, "unable to read clients CAs bundle") {
+                roots.add(&cert).expect("unable to set clients CAs bundle");
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+        }
+    };
+    builder
+        .with_single_cert(certs, key)
+        .expect("unable to set TLS server certificate")
+}
+
+/// Load all certificates from a PEM file.
+#[cfg(feature = "tls-rustls")]
+fn load_certs(path: &str, error: &'static str) -> Vec<Certificate> {
+    let file = File::open(path).expect(error);
+    rustls_pemfile::certs(&mut BufReader::new(file))
+        .expect(error)
+        .into_iter()
+        .map(Certificate)
+        .collect()
+}
+
+/// Load the first private key from a PEM file.
+#[cfg(feature = "tls-rustls")]
+fn load_key(path: &str) -> PrivateKey {
+    let error = "unable to read TLS server private key";
+    let file = File::open(path).expect(error);
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file)).expect(error);
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            Item::ECKey(key) | Item::PKCS8Key(key) | Item::RSAKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .expect("no private key found in TLS server private key file")
+}
@@ -0,0 +1,234 @@
+use actix_web::dev::forward_ready;
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::dev::Transform;
+use actix_web::http::header::HeaderMap;
+use actix_web::http::header::HeaderName;
+use actix_web::http::header::HeaderValue;
+use actix_web::Error;
+use futures::future::ready;
+use futures::future::Ready;
+
+const B3_SINGLE: &str = "b3";
+const B3_SAMPLED: &str = "x-b3-sampled";
+const B3_SPAN_ID: &str = "x-b3-spanid";
+const B3_TRACE_ID: &str = "x-b3-traceid";
+const W3C_TRACEPARENT: &str = "traceparent";
+
+/// Trace context extracted from correlation headers.
+#[derive(Debug, Eq, PartialEq)]
+struct TraceContext {
+    sampled: Option<bool>,
+    span_id: String,
+    trace_id: String,
+}
+
+/// Middleware to accept W3C `traceparent` and single header B3 trace context.
+///
+/// The `TracingMiddleware` extracts the parent span from B3 multi-headers,
+/// so any other supported format is converted into them before the request
+/// reaches handlers. Requests that already carry B3 multi-headers are left untouched.
+#[derive(Clone, Debug, Default)]
+pub struct TraceHeaders;
+
+impl<S, B> Transform<S, ServiceRequest> for TraceHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TraceHeadersService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TraceHeadersService { service }))
+    }
+}
+
+/// Service wrapper created by the `TraceHeaders` middleware.
+pub struct TraceHeadersService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for TraceHeadersService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = S::Future;
+
+    forward_ready!(service);
+
+    fn call(&self, mut request: ServiceRequest) -> Self::Future {
+        normalise(request.headers_mut());
+        self.service.call(request)
+    }
+}
+
+/// Convert supported trace context headers into B3 multi-headers.
+fn normalise(headers: &mut HeaderMap) {
+    if headers.contains_key(B3_TRACE_ID) {
+        return;
+    }
+    let context = header_str(headers, W3C_TRACEPARENT)
+        .and_then(parse_traceparent)
+        .or_else(|| header_str(headers, B3_SINGLE).and_then(parse_b3_single));
+    let context = match context {
+        None => return,
+        Some(context) => context,
+    };
+    insert(headers, B3_TRACE_ID, &context.trace_id);
+    insert(headers, B3_SPAN_ID, &context.span_id);
+    if let Some(sampled) = context.sampled {
+        insert(headers, B3_SAMPLED, if sampled { "1" } else { "0" });
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn insert(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(HeaderName::from_static(name), value);
+    }
+}
+
+/// Check the value is a non-zero hex ID of the given length.
+fn valid_id(id: &str, len: usize) -> bool {
+    id.len() == len && id.chars().all(|c| c.is_ascii_hexdigit()) && id.chars().any(|c| c != '0')
+}
+
+/// Parse a single B3 header: `{trace_id}-{span_id}[-{sampling}[-{parent_span_id}]]`.
+fn parse_b3_single(value: &str) -> Option<TraceContext> {
+    let mut parts = value.trim().split('-');
+    let trace_id = parts.next()?;
+    let span_id = parts.next()?;
+    if !(valid_id(trace_id, 16) || valid_id(trace_id, 32)) || !valid_id(span_id, 16) {
+        return None;
+    }
+    let sampled = match parts.next() {
+        None => None,
+        Some("1") | Some("d") => Some(true),
+        Some("0") => Some(false),
+        Some(_) => return None,
+    };
+    Some(TraceContext {
+        sampled,
+        span_id: span_id.to_lowercase(),
+        trace_id: trace_id.to_lowercase(),
+    })
+}
+
+/// Parse a W3C `traceparent` header: `{version}-{trace_id}-{parent_id}-{flags}`.
+fn parse_traceparent(value: &str) -> Option<TraceContext> {
+    let parts: Vec<&str> = value.trim().split('-').collect();
+    if parts.len() < 4 {
+        return None;
+    }
+    let version = parts[0];
+    if version.len() != 2 || version == "ff" || u8::from_str_radix(version, 16).is_err() {
+        return None;
+    }
+    // Version 00 defines exactly four fields, future versions may append more.
+    if version == "00" && parts.len() != 4 {
+        return None;
+    }
+    let trace_id = parts[1];
+    let span_id = parts[2];
+    if !valid_id(trace_id, 32) || !valid_id(span_id, 16) || parts[3].len() != 2 {
+        return None;
+    }
+    let flags = u8::from_str_radix(parts[3], 16).ok()?;
+    Some(TraceContext {
+        sampled: Some(flags & 0x01 == 0x01),
+        span_id: span_id.to_lowercase(),
+        trace_id: trace_id.to_lowercase(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::HeaderMap;
+    use actix_web::http::header::HeaderName;
+    use actix_web::http::header::HeaderValue;
+
+    use super::normalise;
+    use super::parse_b3_single;
+    use super::parse_traceparent;
+    use super::TraceContext;
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static(name),
+            HeaderValue::from_static(value),
+        );
+        headers
+    }
+
+    #[test]
+    fn b3_single() {
+        let context = parse_b3_single("80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1");
+        assert_eq!(
+            context,
+            Some(TraceContext {
+                sampled: Some(true),
+                span_id: "e457b5a2e4d86bd1".into(),
+                trace_id: "80f198ee56343ba864fe8b2a57d3eff7".into(),
+            })
+        );
+        assert_eq!(parse_b3_single("0"), None);
+    }
+
+    #[test]
+    fn normalise_keeps_b3_headers() {
+        let mut headers = headers("x-b3-traceid", "463ac35c9f6413ad");
+        headers.insert(
+            HeaderName::from_static("traceparent"),
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        normalise(&mut headers);
+        assert_eq!(headers.get("x-b3-traceid").unwrap(), "463ac35c9f6413ad");
+        assert!(headers.get("x-b3-spanid").is_none());
+    }
+
+    #[test]
+    fn normalise_traceparent() {
+        let mut headers = headers(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+        );
+        normalise(&mut headers);
+        assert_eq!(
+            headers.get("x-b3-traceid").unwrap(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(headers.get("x-b3-spanid").unwrap(), "00f067aa0ba902b7");
+        assert_eq!(headers.get("x-b3-sampled").unwrap(), "0");
+    }
+
+    #[test]
+    fn traceparent_invalid() {
+        assert_eq!(parse_traceparent("junk"), None);
+        assert_eq!(
+            parse_traceparent("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(
+            parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"),
+            None
+        );
+    }
+}
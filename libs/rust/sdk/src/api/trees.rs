@@ -0,0 +1,290 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::forward_ready;
+use actix_web::dev::Service;
+use actix_web::dev::ServiceRequest;
+use actix_web::dev::ServiceResponse;
+use actix_web::dev::Transform;
+use actix_web::Error;
+use actix_web::HttpResponse;
+use futures::future::ready;
+use futures::future::Ready;
+use slog::warn;
+
+use replicante_util_actixweb::APIFlags;
+use replicante_util_actixweb::RootDescriptor;
+
+use crate::api::APIRoot;
+use crate::config::APITrees;
+use crate::store::APITreeOverride;
+use crate::AgentContext;
+use crate::Result;
+
+/// Path of the endpoint to change API trees, which is never disabled.
+const TREES_ENDPOINT: &str = "/api/unstable/introspect/trees";
+
+/// API trees state that can be changed at runtime.
+///
+/// The state starts from the configured `api.trees` with overrides persisted
+/// in the agent store applied on top.
+#[derive(Clone)]
+pub struct RuntimeTrees {
+    configured: APITrees,
+    current: Arc<RwLock<APITrees>>,
+}
+
+impl RuntimeTrees {
+    /// Load the runtime API trees state, if enabled by the configuration.
+    pub fn load(context: &AgentContext) -> Result<Option<RuntimeTrees>> {
+        if context.config.api.runtime_trees.is_none() {
+            return Ok(None);
+        }
+        let configured = context.config.api.trees.clone();
+        let trees = RuntimeTrees {
+            current: Arc::new(RwLock::new(configured.clone())),
+            configured,
+        };
+        let overrides: Vec<APITreeOverride> = context
+            .store
+            .with_transaction(|tx| tx.api_tree_overrides().list(None)?.collect())?;
+        for tree in &overrides {
+            if !trees.apply(tree) {
+                warn!(
+                    context.logger,
+                    "Ignoring override of unknown API tree";
+                    "tree" => &tree.tree,
+                );
+            }
+        }
+        Ok(Some(trees))
+    }
+
+    /// Enable or disable an API tree, returning false if the tree is unknown.
+    pub fn apply(&self, tree: &APITreeOverride) -> bool {
+        let mut current = self.current.write().expect("RuntimeTrees lock poisoned");
+        match tree.tree.as_str() {
+            "introspect" => current.introspect = tree.enabled,
+            "unstable" => current.unstable = tree.enabled,
+            _ => return false,
+        };
+        true
+    }
+
+    /// API trees as configured, without runtime overrides.
+    pub fn configured(&self) -> &APITrees {
+        &self.configured
+    }
+
+    /// API trees currently enabled or disabled.
+    pub fn current(&self) -> APITrees {
+        self.current
+            .read()
+            .expect("RuntimeTrees lock poisoned")
+            .clone()
+    }
+
+    /// Revert all API trees to their configured state.
+    pub fn reset(&self) {
+        let mut current = self.current.write().expect("RuntimeTrees lock poisoned");
+        *current = self.configured.clone();
+    }
+
+    /// Check if a request path falls under a currently enabled API tree.
+    ///
+    /// Paths are normalised first so encoded or redundant characters can't be used
+    /// to reach a disabled tree through a path the router would still match.
+    fn path_enabled(&self, path: &str) -> bool {
+        let path = normalise_path(path);
+        let path = path.as_str();
+        if path == TREES_ENDPOINT {
+            return true;
+        }
+        let root = if path.starts_with(APIRoot::UnstableIntrospect.prefix()) {
+            APIRoot::UnstableIntrospect
+        } else if path.starts_with(APIRoot::UnstableAPI.prefix()) {
+            APIRoot::UnstableAPI
+        } else {
+            return true;
+        };
+        let flags: APIFlags = self.current().into();
+        root.enabled(&flags)
+    }
+}
+
+/// Percent-decode a request path and drop empty and `.` segments from it.
+fn normalise_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escape = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    let decoded = String::from_utf8_lossy(&decoded);
+    let mut normalised = String::with_capacity(decoded.len());
+    for segment in decoded.split('/') {
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+        normalised.push('/');
+        normalised.push_str(segment);
+    }
+    if normalised.is_empty() {
+        normalised.push('/');
+    }
+    normalised
+}
+
+/// Middleware to reject requests to API trees disabled at runtime.
+///
+/// When API trees can be changed at runtime all trees are mounted and requests
+/// to disabled trees are answered with a `404 Not Found`, as if they were not mounted.
+/// When runtime API trees are not enabled requests are passed through untouched.
+#[derive(Clone)]
+pub struct TreesGate {
+    trees: Option<RuntimeTrees>,
+}
+
+impl TreesGate {
+    pub fn new(trees: Option<RuntimeTrees>) -> TreesGate {
+        TreesGate { trees }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for TreesGate
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = TreesGateService<S>;
+    type InitError = ();
+    type Future = Ready<std::result::Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TreesGateService {
+            service: Rc::new(service),
+            trees: self.trees.clone(),
+        }))
+    }
+}
+
+/// Service wrapper created by the `TreesGate` middleware.
+pub struct TreesGateService<S> {
+    service: Rc<S>,
+    trees: Option<RuntimeTrees>,
+}
+
+impl<S, B> Service<ServiceRequest> for TreesGateService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = std::result::Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        let enabled = match &self.trees {
+            None => true,
+            Some(trees) => trees.path_enabled(request.path()),
+        };
+        if !enabled {
+            let response = HttpResponse::NotFound().finish().map_into_right_body();
+            return Box::pin(ready(Ok(request.into_response(response))));
+        }
+        let response = self.service.call(request);
+        Box::pin(async move { Ok(response.await?.map_into_left_body()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::RwLock;
+
+    use chrono::Utc;
+
+    use super::normalise_path;
+    use super::RuntimeTrees;
+    use crate::config::APITrees;
+    use crate::store::APITreeOverride;
+
+    fn trees() -> RuntimeTrees {
+        RuntimeTrees {
+            configured: APITrees::default(),
+            current: Arc::new(RwLock::new(APITrees::default())),
+        }
+    }
+
+    fn tree(tree: &str, enabled: bool) -> APITreeOverride {
+        APITreeOverride {
+            enabled,
+            tree: tree.into(),
+            updated_ts: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn disable_introspect() {
+        let trees = trees();
+        assert!(trees.apply(&tree("introspect", false)));
+        assert!(!trees.path_enabled("/api/unstable/introspect/threads"));
+        assert!(trees.path_enabled("/api/unstable/introspect/trees"));
+        assert!(trees.path_enabled("/api/unstable/info/agent"));
+        assert!(trees.path_enabled("/"));
+    }
+
+    #[test]
+    fn disable_unstable() {
+        let trees = trees();
+        assert!(trees.apply(&tree("unstable", false)));
+        assert!(!trees.path_enabled("/api/unstable/info/agent"));
+        assert!(!trees.path_enabled("/api/unstable/introspect/threads"));
+        assert!(trees.path_enabled("/api/unstable/introspect/trees"));
+        trees.reset();
+        assert!(trees.path_enabled("/api/unstable/info/agent"));
+    }
+
+    #[test]
+    fn encoded_paths() {
+        let trees = trees();
+        assert!(trees.apply(&tree("unstable", false)));
+        assert!(!trees.path_enabled("/api/unstable/%61ctions"));
+        assert!(!trees.path_enabled("/api/%75nstable/info/agent"));
+        assert!(!trees.path_enabled("//api/./unstable//info/agent"));
+        assert!(trees.path_enabled("/api/unstable/introspect/%74rees"));
+        assert_eq!(normalise_path("/api/%zz/"), "/api/%zz");
+        assert_eq!(normalise_path(""), "/");
+    }
+
+    #[test]
+    fn unknown_tree() {
+        let trees = trees();
+        assert!(!trees.apply(&tree("stable", false)));
+        assert_eq!(trees.current(), APITrees::default());
+    }
+}
@@ -0,0 +1,328 @@
+//! Run blocking datastore calls on a dedicated pool of threads.
+//!
+//! Datastore clients (such as the MongoDB sync driver or JMX connections) block
+//! the thread calling them.
+//! When these calls run on API server workers a slow datastore can occupy all workers
+//! and starve endpoints that do not need the datastore, such as health checks.
+//! API requests hand datastore calls to the pool instead and wait for the result
+//! without holding on to a worker.
+//!
+//! The pool has a fixed number of threads and queues up to `queue_size` calls.
+//! Calls beyond that are rejected with `ErrorKind::BlockingPoolFull`
+//! (`503 Service Unavailable` over the API).
+//! Time spent in the queue counts against the request deadline: callers stop waiting
+//! once the deadline expires and calls still queued by then are never run.
+//!
+//! Pool state is exported with the following metrics:
+//!
+//!   * `repliagent_blocking_active`: calls currently running on the pool.
+//!   * `repliagent_blocking_queued`: calls waiting for a free thread.
+//!   * `repliagent_blocking_rejected{operation}`: calls rejected because the queue is full.
+//!   * `repliagent_blocking_wait_duration`: time calls waited for a free thread.
+use std::panic::catch_unwind;
+use std::panic::resume_unwind;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::sync_channel;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::TrySendError;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::channel::oneshot;
+use humthreads::Builder;
+use humthreads::Thread;
+use humthreads::ThreadScope;
+use opentracingrust::Span;
+use opentracingrust::SpanContext;
+use opentracingrust::StartOptions;
+use opentracingrust::Tracer;
+use replicante_util_upkeep::Upkeep;
+
+use crate::config::BlockingPoolConfig;
+use crate::deadline::Deadline;
+use crate::fail_compat;
+use crate::metrics::BLOCKING_ACTIVE;
+use crate::metrics::BLOCKING_QUEUED;
+use crate::metrics::BLOCKING_REJECTED;
+use crate::metrics::BLOCKING_WAIT_DURATION;
+use crate::spans::mark_unsampled;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// Delay between shutdown checks of idle pool threads.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(500);
+
+/// Bounded pool of threads for blocking datastore calls.
+///
+/// Pools are cheap to clone and clones share the same threads.
+/// Threads exit once all clones of the pool are dropped or on process shutdown.
+#[derive(Clone)]
+pub struct BlockingPool {
+    sender: SyncSender<Job>,
+    threads: Arc<Mutex<Vec<Thread<()>>>>,
+    tracer: Arc<Tracer>,
+}
+
+/// Call queued for execution on the pool.
+struct Job {
+    queued: Instant,
+    run: Box<dyn FnOnce() + Send>,
+}
+
+impl BlockingPool {
+    pub fn new(config: &BlockingPoolConfig, tracer: Arc<Tracer>) -> Result<BlockingPool> {
+        let (sender, receiver) = sync_channel(config.queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut threads = Vec::with_capacity(config.threads);
+        for index in 0..config.threads {
+            let receiver = Arc::clone(&receiver);
+            let thread = Builder::new(format!("r:b:datastore:{}", index))
+                .full_name(format!("replicante:base:datastore:pool:{}", index))
+                .spawn(move |scope| worker(receiver, scope))
+                .map_err(fail_compat)
+                .with_context(|_| ErrorKind::ThreadSpawn("datastore blocking pool"))?;
+            threads.push(thread);
+        }
+        Ok(BlockingPool {
+            sender,
+            threads: Arc::new(Mutex::new(threads)),
+            tracer,
+        })
+    }
+
+    /// Register the pool threads with `Upkeep` so they are stopped on shutdown.
+    pub fn register_threads(&self, upkeep: &mut Upkeep) {
+        let mut threads = self
+            .threads
+            .lock()
+            .expect("BlockingPool threads lock poisoned");
+        for thread in threads.drain(..) {
+            upkeep.register_thread(thread);
+        }
+    }
+
+    /// Run the given datastore call on the pool and wait for its result.
+    ///
+    /// The call is given a span, child of the given context, that tracks its execution.
+    /// Calls without a parent context come from requests that were not sampled
+    /// so their span is marked as unsampled (see `crate::spans`) and never reported.
+    /// The call runs with the given deadline set (see `crate::deadline`) and fails with
+    /// `ErrorKind::DeadlineExceeded` if it expires, even while the call is queued.
+    /// Panics in the call are propagated to the caller.
+    pub async fn call<F, T>(
+        &self,
+        operation: &'static str,
+        parent: Option<SpanContext>,
+        deadline: Option<Deadline>,
+        f: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(&mut Span) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (send, receive) = oneshot::channel();
+        let tracer = Arc::clone(&self.tracer);
+        let run = move || {
+            let sampled = parent.is_some();
+            let mut opts = StartOptions::default();
+            if let Some(parent) = parent {
+                opts = opts.child_of(parent);
+            }
+            let mut span = tracer.span_with_options(operation, opts);
+            if !sampled {
+                mark_unsampled(&mut span);
+            }
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                crate::deadline::run(deadline, operation, || f(&mut span))
+            }));
+            if sampled {
+                let _ = span.finish();
+            }
+            // The caller may have gone away while the call was running.
+            let _ = send.send(result);
+        };
+        let job = Job {
+            queued: Instant::now(),
+            run: Box::new(run),
+        };
+
+        BLOCKING_QUEUED.inc();
+        match self.sender.try_send(job) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                BLOCKING_QUEUED.dec();
+                BLOCKING_REJECTED.with_label_values(&[operation]).inc();
+                return Err(ErrorKind::BlockingPoolFull(operation).into());
+            }
+            Err(TrySendError::Disconnected(_)) => panic!("blocking pool threads have exited"),
+        }
+        let result = match deadline {
+            None => receive.await,
+            Some(deadline) => match wait_until(deadline, receive).await {
+                Some(result) => result,
+                None => return Err(ErrorKind::DeadlineExceeded(operation).into()),
+            },
+        };
+        match result.expect("blocking pool dropped a queued call") {
+            Ok(result) => result,
+            Err(panic) => resume_unwind(panic),
+        }
+    }
+}
+
+/// Wait for the result of a call until the deadline expires.
+#[cfg(feature = "api")]
+async fn wait_until<T>(
+    deadline: Deadline,
+    receive: oneshot::Receiver<T>,
+) -> Option<Result<T, oneshot::Canceled>> {
+    actix_web::rt::time::timeout(deadline.remaining(), receive)
+        .await
+        .ok()
+}
+
+/// Wait for the result of a call until the deadline expires.
+///
+/// Without the API server there is no timer to stop waiting early with, so
+/// calls queued past the deadline fail once a thread picks them up.
+#[cfg(not(feature = "api"))]
+async fn wait_until<T>(
+    _: Deadline,
+    receive: oneshot::Receiver<T>,
+) -> Option<Result<T, oneshot::Canceled>> {
+    Some(receive.await)
+}
+
+/// Run queued calls until all senders are dropped or shutdown is requested.
+fn worker(receiver: Arc<Mutex<Receiver<Job>>>, scope: ThreadScope) {
+    scope.activity("waiting for datastore calls");
+    loop {
+        if scope.should_shutdown() {
+            return;
+        }
+        let job = {
+            let receiver = receiver
+                .lock()
+                .expect("BlockingPool receiver lock poisoned");
+            match receiver.recv_timeout(SHUTDOWN_POLL) {
+                Ok(job) => job,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        };
+        let _activity = scope.scoped_activity("running a datastore call");
+        BLOCKING_QUEUED.dec();
+        BLOCKING_WAIT_DURATION.observe(job.queued.elapsed().as_secs_f64());
+        BLOCKING_ACTIVE.inc();
+        (job.run)();
+        BLOCKING_ACTIVE.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+    use futures::FutureExt;
+
+    use super::BlockingPool;
+    use crate::config::BlockingPoolConfig;
+    use crate::deadline::Deadline;
+    use crate::AgentContext;
+
+    fn pool(queue_size: usize) -> BlockingPool {
+        let context = AgentContext::mock();
+        let config = BlockingPoolConfig {
+            queue_size,
+            threads: 1,
+        };
+        BlockingPool::new(&config, Arc::clone(&context.tracer)).unwrap()
+    }
+
+    #[test]
+    fn runs_calls() {
+        let pool = pool(1);
+        let result = block_on(pool.call("test", None, None, |_| Ok(42))).unwrap();
+        assert_eq!(result, 42);
+    }
+
+    #[test]
+    fn calls_run_with_deadline() {
+        let pool = pool(1);
+        let deadline = Deadline::after(Duration::from_secs(60));
+        let current = block_on(pool.call("test", None, Some(deadline), |_| {
+            Ok(crate::deadline::current())
+        }));
+        assert_eq!(current.unwrap(), Some(deadline));
+    }
+
+    #[test]
+    #[cfg(feature = "api")]
+    fn queued_calls_expire() {
+        let pool = pool(1);
+        let (held_send, held) = channel();
+        let (release, release_recv) = channel::<()>();
+        let busy = {
+            let pool = pool.clone();
+            std::thread::spawn(move || {
+                block_on(pool.call("test", None, None, move |_| {
+                    held_send.send(()).unwrap();
+                    release_recv.recv().unwrap();
+                    Ok(())
+                }))
+            })
+        };
+        held.recv().unwrap();
+        let deadline = Deadline::after(Duration::from_millis(50));
+        let ran = Arc::new(AtomicBool::new(false));
+        let call_ran = Arc::clone(&ran);
+        let call = pool.call("test", None, Some(deadline), move |_| {
+            call_ran.store(true, Ordering::SeqCst);
+            Ok(())
+        });
+        let error = actix_web::rt::System::new().block_on(call).unwrap_err();
+        assert_eq!(error.kind().code(), "DeadlineExceeded");
+
+        release.send(()).unwrap();
+        busy.join().unwrap().unwrap();
+        block_on(pool.call("test", None, None, |_| Ok(()))).unwrap();
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn rejects_when_queue_full() {
+        let pool = pool(1);
+        let (held_send, held) = channel();
+        let (release, release_recv) = channel::<()>();
+        let busy = {
+            let pool = pool.clone();
+            std::thread::spawn(move || {
+                block_on(pool.call("test", None, None, move |_| {
+                    held_send.send(()).unwrap();
+                    release_recv.recv().unwrap();
+                    Ok(())
+                }))
+            })
+        };
+        held.recv().unwrap();
+        // Fill the queue with a call the test does not wait for.
+        assert!(pool
+            .call("test", None, None, |_| Ok(()))
+            .now_or_never()
+            .is_none());
+        let error = block_on(pool.call("test", None, None, |_| Ok(()))).unwrap_err();
+        assert_eq!(error.kind().code(), "BlockingPoolFull");
+        release.send(()).unwrap();
+        busy.join().unwrap().unwrap();
+    }
+}
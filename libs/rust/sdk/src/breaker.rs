@@ -0,0 +1,263 @@
+//! Circuit breaker to stop calling a datastore that keeps failing.
+//!
+//! After a number of consecutive failures the breaker opens and calls fail fast
+//! with `ErrorKind::CircuitOpen` instead of waiting on a datastore that is down.
+//! Once the open interval passes a single probe call is let through (half-open):
+//! the breaker closes if the probe succeeds and opens again if it fails.
+//!
+//! Breaker state is exported with the following metrics:
+//!
+//!   * `repliagent_breaker_state{breaker}`: 0 when closed, 1 when half-open, 2 when open.
+//!   * `repliagent_breaker_rejected{breaker}`: calls rejected while the breaker is open.
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use prometheus::Counter;
+use prometheus::Gauge;
+use serde::Serialize;
+
+use crate::config::CircuitBreakerConfig;
+use crate::metrics::BREAKER_REJECTED;
+use crate::metrics::BREAKER_STATE;
+use crate::ErrorKind;
+use crate::Result;
+
+/// State of a circuit breaker.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Calls go through to the datastore.
+    Closed,
+
+    /// A probe call is checking if the datastore has recovered.
+    HalfOpen,
+
+    /// Calls are rejected without reaching the datastore.
+    Open,
+}
+
+impl BreakerState {
+    fn metric_value(self) -> f64 {
+        match self {
+            BreakerState::Closed => 0.0,
+            BreakerState::HalfOpen => 1.0,
+            BreakerState::Open => 2.0,
+        }
+    }
+}
+
+/// Details about a circuit breaker that is not closed.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct BreakerStatus {
+    /// Number of consecutive failed calls.
+    pub failures: u32,
+
+    /// Name of the breaker.
+    pub name: String,
+
+    /// Current state of the breaker.
+    pub state: BreakerState,
+}
+
+/// Fail fast calls to a datastore after repeated failures.
+///
+/// Breakers are cheap to clone and clones share the same state.
+/// Breakers created without a configuration never open and only forward calls.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: Option<CircuitBreakerConfig>,
+    name: String,
+    rejected: Counter,
+    state: Mutex<State>,
+    state_gauge: Gauge,
+}
+
+struct State {
+    failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool,
+    state: BreakerState,
+}
+
+impl CircuitBreaker {
+    /// Create a breaker, identified in metrics by the given name.
+    pub fn new(name: &str, config: Option<CircuitBreakerConfig>) -> CircuitBreaker {
+        let state_gauge = BREAKER_STATE.with_label_values(&[name]);
+        state_gauge.set(BreakerState::Closed.metric_value());
+        let inner = Inner {
+            config,
+            name: name.to_string(),
+            rejected: BREAKER_REJECTED.with_label_values(&[name]),
+            state: Mutex::new(State {
+                failures: 0,
+                opened_at: None,
+                probing: false,
+                state: BreakerState::Closed,
+            }),
+            state_gauge,
+        };
+        CircuitBreaker {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Invoke the given operation unless the breaker is open.
+    ///
+    /// Operation errors count as failures towards opening the breaker.
+    /// Rejected calls fail with `ErrorKind::CircuitOpen`.
+    pub fn call<F, T>(&self, operation: &'static str, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let config = match &self.inner.config {
+            None => return f(),
+            Some(config) => config,
+        };
+        let probe = self.acquire(operation, config)?;
+        let result = f();
+        self.record(result.is_ok(), probe, config);
+        result
+    }
+
+    /// Current state of the breaker.
+    pub fn state(&self) -> BreakerState {
+        self.lock().state
+    }
+
+    /// Details of the breaker, unless it is closed.
+    pub fn status(&self) -> Option<BreakerStatus> {
+        let state = self.lock();
+        if state.state == BreakerState::Closed {
+            return None;
+        }
+        Some(BreakerStatus {
+            failures: state.failures,
+            name: self.inner.name.clone(),
+            state: state.state,
+        })
+    }
+
+    /// Check if a call can go through, returning whether the call is a probe.
+    fn acquire(&self, operation: &'static str, config: &CircuitBreakerConfig) -> Result<bool> {
+        let mut state = self.lock();
+        match state.state {
+            BreakerState::Closed => Ok(false),
+            BreakerState::HalfOpen if !state.probing => {
+                state.probing = true;
+                Ok(true)
+            }
+            BreakerState::Open if self.can_probe(&state, config) => {
+                state.probing = true;
+                self.transition(&mut state, BreakerState::HalfOpen);
+                Ok(true)
+            }
+            _ => {
+                self.inner.rejected.inc();
+                Err(ErrorKind::CircuitOpen(operation).into())
+            }
+        }
+    }
+
+    fn can_probe(&self, state: &State, config: &CircuitBreakerConfig) -> bool {
+        let open_for = Duration::from_secs(config.open_seconds);
+        state
+            .opened_at
+            .map(|opened_at| opened_at.elapsed() >= open_for)
+            .unwrap_or(true)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<State> {
+        self.inner
+            .state
+            .lock()
+            .expect("CircuitBreaker state lock poisoned")
+    }
+
+    /// Update the breaker with the outcome of a call.
+    fn record(&self, success: bool, probe: bool, config: &CircuitBreakerConfig) {
+        let mut state = self.lock();
+        if probe {
+            state.probing = false;
+        }
+        if success {
+            state.failures = 0;
+            state.opened_at = None;
+            self.transition(&mut state, BreakerState::Closed);
+            return;
+        }
+        state.failures = state.failures.saturating_add(1);
+        if probe || state.failures >= config.failure_threshold {
+            state.opened_at = Some(Instant::now());
+            self.transition(&mut state, BreakerState::Open);
+        }
+    }
+
+    fn transition(&self, state: &mut State, to: BreakerState) {
+        state.state = to;
+        self.inner.state_gauge.set(to.metric_value());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BreakerState;
+    use super::CircuitBreaker;
+    use crate::config::CircuitBreakerConfig;
+    use crate::ErrorKind;
+    use crate::Result;
+
+    fn fail() -> Result<()> {
+        Err(ErrorKind::StoreOpFailed("test").into())
+    }
+
+    fn breaker(open_seconds: u64) -> CircuitBreaker {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 2,
+            open_seconds,
+        };
+        CircuitBreaker::new("test", Some(config))
+    }
+
+    #[test]
+    fn disabled_never_opens() {
+        let breaker = CircuitBreaker::new("test", None);
+        for _ in 0..10 {
+            assert!(breaker.call("test", fail).is_err());
+        }
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(breaker.call("test", || Ok(())).is_ok());
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker(60);
+        assert!(breaker.call("test", fail).is_err());
+        assert!(breaker.call("test", || Ok(())).is_ok());
+        assert!(breaker.call("test", fail).is_err());
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(breaker.call("test", fail).is_err());
+        assert_eq!(breaker.state(), BreakerState::Open);
+        let error = breaker.call("test", || Ok(())).unwrap_err();
+        assert_eq!(error.kind().code(), "CircuitOpen");
+        assert_eq!(breaker.status().unwrap().failures, 2);
+    }
+
+    #[test]
+    fn probe_closes_or_reopens() {
+        let breaker = breaker(0);
+        assert!(breaker.call("test", fail).is_err());
+        assert!(breaker.call("test", fail).is_err());
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(breaker.call("test", fail).is_err());
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(breaker.call("test", || Ok(())).is_ok());
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(breaker.status().is_none());
+    }
+}
@@ -0,0 +1,132 @@
+//! Details about how the agent process was built.
+//!
+//! Agents capture their build details with the `build_info!` macro and register
+//! them before running so the SDK can log them at startup and expose them
+//! with the `/introspect/version` endpoint.
+//! If no details are registered the details of the SDK itself are reported.
+use std::sync::RwLock;
+
+use serde::Serialize;
+
+lazy_static::lazy_static! {
+    /// Build details registered by the agent.
+    static ref BUILD_INFO: RwLock<Option<BuildInfo>> = RwLock::new(None);
+}
+
+/// Capture the build details of the crate invoking the macro.
+///
+/// The crate must use the SDK `build.rs` script (or one setting the same variables).
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::build::BuildInfo::new(
+            env!("CARGO_PKG_VERSION"),
+            env!("GIT_BUILD_HASH"),
+            env!("GIT_BUILD_TAINT"),
+            option_env!("BUILD_DATE"),
+            option_env!("BUILD_RUSTC_VERSION"),
+        )
+    };
+}
+
+/// Version and build details of an agent.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct BuildInfo {
+    /// Time the agent was built at, in RFC 3339 format.
+    pub build_date: Option<&'static str>,
+
+    /// SDK features the agent was built with.
+    pub features: Vec<&'static str>,
+
+    /// Git commit the agent was built from.
+    pub git_hash: &'static str,
+
+    /// State of the git working directory the agent was built from.
+    pub git_taint: &'static str,
+
+    /// Version of the compiler used to build the agent.
+    pub rustc: Option<&'static str>,
+
+    /// Semantic version of the agent.
+    pub version: &'static str,
+
+    /// Version string for humans, including the git details.
+    #[serde(skip)]
+    display: String,
+}
+
+impl BuildInfo {
+    pub fn new(
+        version: &'static str,
+        git_hash: &'static str,
+        git_taint: &'static str,
+        build_date: Option<&'static str>,
+        rustc: Option<&'static str>,
+    ) -> BuildInfo {
+        let display = format!("{} [{}; {}]", version, git_hash, git_taint);
+        BuildInfo {
+            build_date,
+            features: features(),
+            git_hash,
+            git_taint,
+            rustc,
+            version,
+            display,
+        }
+    }
+
+    /// Version string for humans, such as the `--version` output.
+    pub fn display(&self) -> &str {
+        &self.display
+    }
+}
+
+/// Return the build details registered by the agent, or the SDK ones.
+pub fn current() -> BuildInfo {
+    BUILD_INFO
+        .read()
+        .expect("BuildInfo lock poisoned")
+        .clone()
+        .unwrap_or_else(|| crate::build_info!())
+}
+
+/// Register the build details of the agent.
+pub fn register(info: BuildInfo) {
+    *BUILD_INFO.write().expect("BuildInfo lock poisoned") = Some(info);
+}
+
+/// SDK features enabled in this build.
+fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "actions") {
+        features.push("actions");
+    }
+    if cfg!(feature = "api") {
+        features.push("api");
+    }
+    if cfg!(feature = "journald") {
+        features.push("journald");
+    }
+    if cfg!(feature = "store") {
+        features.push("store");
+    }
+    if cfg!(feature = "tls-openssl") {
+        features.push("tls-openssl");
+    }
+    if cfg!(feature = "tls-rustls") {
+        features.push("tls-rustls");
+    }
+    features
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BuildInfo;
+
+    #[test]
+    fn display_version() {
+        let info = BuildInfo::new("1.2.3", "abcdef", "not tainted", None, None);
+        assert_eq!(info.display(), "1.2.3 [abcdef; not tainted]");
+        assert_eq!(info.features.contains(&"api"), cfg!(feature = "api"));
+    }
+}
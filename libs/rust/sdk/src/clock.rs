@@ -0,0 +1,144 @@
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use humthreads::Builder;
+use slog::debug;
+use slog::info;
+use slog::warn;
+
+use replicante_util_failure::failure_info;
+use replicante_util_upkeep::Upkeep;
+
+use crate::fail_compat;
+use crate::metrics::DATASTORE_CLOCK_SKEW;
+use crate::Agent;
+use crate::AgentContext;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+lazy_static::lazy_static! {
+    /// Warning about the datastore clock drifting away from the agent clock.
+    static ref SKEW_WARNING: RwLock<Option<String>> = RwLock::new(None);
+}
+
+/// Warning about the agent and datastore clocks being skewed, if any.
+///
+/// Skewed clocks silently corrupt time based calculations, such as replication lag.
+pub fn clock_skew_warning() -> Option<String> {
+    SKEW_WARNING
+        .read()
+        .expect("SKEW_WARNING lock poisoned")
+        .clone()
+}
+
+/// Start background thread to periodically compare the agent and datastore clocks.
+pub fn spawn(agent: Arc<dyn Agent>, context: AgentContext, upkeep: &mut Upkeep) -> Result<()> {
+    let thread = Builder::new("r:b:clock_skew")
+        .full_name("replicante:base:clock_skew")
+        .spawn(move |scope| {
+            let interval = Duration::from_secs(context.config.clock_skew.interval);
+            scope.activity("waiting to check clock skew");
+            while !scope.should_shutdown() {
+                let _activity = scope.scoped_activity("checking clock skew");
+                check(&*agent, &context);
+                thread::sleep(interval);
+            }
+        })
+        .map_err(fail_compat)
+        .with_context(|_| ErrorKind::ThreadSpawn("clock skew"))?;
+    upkeep.register_thread(thread);
+    Ok(())
+}
+
+/// Compare the agent and datastore clocks once and update the gauge and warning.
+fn check(agent: &dyn Agent, context: &AgentContext) {
+    let mut span = context.tracer.span("clock.skew").auto_finish();
+    let before = SystemTime::now();
+    let datastore = match agent.datastore_time(&mut span) {
+        Ok(Some(datastore)) => datastore,
+        Ok(None) => {
+            debug!(context.logger, "Agent does not report the datastore time");
+            unset(context);
+            return;
+        }
+        Err(error) => {
+            debug!(context.logger, "Failed to fetch the datastore time"; failure_info(&error.to_fail()));
+            unset(context);
+            return;
+        }
+    };
+    let after = SystemTime::now();
+    let skew = skew_seconds(before, after, datastore);
+    DATASTORE_CLOCK_SKEW.set(skew);
+
+    let threshold = context.config.clock_skew.threshold as f64;
+    let warning = if skew.abs() > threshold {
+        Some(format!(
+            "datastore clock is {:.3} seconds {} of the agent clock",
+            skew.abs(),
+            if skew > 0.0 { "ahead" } else { "behind" },
+        ))
+    } else {
+        None
+    };
+    let mut current = SKEW_WARNING.write().expect("SKEW_WARNING lock poisoned");
+    match (current.is_some(), &warning) {
+        (true, None) => info!(context.logger, "Datastore clock is in sync"; "skew" => skew),
+        (false, Some(warning)) => warn!(
+            context.logger,
+            "Datastore clock is skewed";
+            "skew" => skew,
+            "warning" => warning,
+        ),
+        _ => (),
+    }
+    *current = warning;
+}
+
+/// Forget the last measured skew when it can't be measured any more.
+///
+/// The gauge is set to NaN, rather than 0, so it does not report clocks in sync.
+fn unset(context: &AgentContext) {
+    DATASTORE_CLOCK_SKEW.set(f64::NAN);
+    let mut current = SKEW_WARNING.write().expect("SKEW_WARNING lock poisoned");
+    if current.take().is_some() {
+        info!(
+            context.logger,
+            "Datastore clock skew can no longer be measured"
+        );
+    }
+}
+
+/// Skew of the datastore clock, in seconds, positive if the datastore is ahead.
+///
+/// The datastore time is assumed to be taken half way through the request.
+fn skew_seconds(before: SystemTime, after: SystemTime, datastore: SystemTime) -> f64 {
+    let rtt = after.duration_since(before).unwrap_or_default();
+    let local = before + rtt / 2;
+    match datastore.duration_since(local) {
+        Ok(ahead) => ahead.as_secs_f64(),
+        Err(behind) => -behind.duration().as_secs_f64(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use super::skew_seconds;
+
+    #[test]
+    fn skew_ahead_and_behind() {
+        let before = SystemTime::now();
+        let after = before + Duration::from_secs(2);
+        let ahead = before + Duration::from_secs(4);
+        assert!((skew_seconds(before, after, ahead) - 3.0).abs() < 1e-9);
+        let behind = before;
+        assert!((skew_seconds(before, after, behind) + 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,221 @@
+//! Share datastore command results across the requests of a collection cycle.
+//!
+//! Replicante Core collects a node by requesting its datastore info and shards
+//! one after the other, and agents often need the same datastore command to serve both.
+//! Agents can store command results in the `CollectionCache` so requests that arrive
+//! within `collection_cache.ttl` milliseconds of each other issue the command only once.
+//! Calls that are more (or less) expensive than the average can set their own TTL
+//! with `CollectionCache::get_or_fetch_for`.
+//!
+//! Keys can be static strings or built at runtime for commands with arguments
+//! (for example to cache one result per topic).
+//!
+//! Only successful results are cached and entries are dropped once they expire
+//! or are explicitly invalidated.
+//! The actions engine clears the cache every time an action transitions to a new state.
+//!
+//! Cache usage is exported with the following metrics:
+//!
+//!   * `repliagent_collection_cache_hits{key}`: lookups served from the cache.
+//!   * `repliagent_collection_cache_misses{key}`: lookups that had to fetch the result.
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::config::CollectionCacheConfig;
+use crate::metrics::COLLECTION_CACHE_HITS;
+use crate::metrics::COLLECTION_CACHE_MISSES;
+use crate::Result;
+
+/// Short lived cache of datastore command results.
+///
+/// Caches are cheap to clone and clones share the same entries.
+#[derive(Clone)]
+pub struct CollectionCache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    entries: Mutex<HashMap<Cow<'static, str>, Entry>>,
+    ttl: Duration,
+}
+
+/// Cached result and the time it expires at.
+struct Entry {
+    expires: Instant,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+impl CollectionCache {
+    pub fn new(config: &CollectionCacheConfig) -> CollectionCache {
+        let inner = Inner {
+            entries: Mutex::new(HashMap::new()),
+            ttl: Duration::from_millis(config.ttl),
+        };
+        CollectionCache {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Drop all cached results.
+    pub fn clear(&self) {
+        self.inner
+            .entries
+            .lock()
+            .expect("CollectionCache lock poisoned")
+            .clear();
+    }
+
+    /// Return the cached result for `key` or fetch, cache and return it.
+    ///
+    /// Results are cached for the configured `collection_cache.ttl`.
+    /// Concurrent lookups that miss the cache all fetch the result:
+    /// the cache reduces load across sequential requests and is no substitute
+    /// for the concurrency limiter.
+    pub fn get_or_fetch<K, F, T>(&self, key: K, fetch: F) -> Result<Arc<T>>
+    where
+        K: Into<Cow<'static, str>>,
+        F: FnOnce() -> Result<T>,
+        T: Send + Sync + 'static,
+    {
+        self.get_or_fetch_for(key, self.inner.ttl, fetch)
+    }
+
+    /// Return the cached result for `key` or fetch and cache it for the given TTL.
+    ///
+    /// A zero TTL, or a zero `collection_cache.ttl`, disables caching of the result.
+    pub fn get_or_fetch_for<K, F, T>(&self, key: K, ttl: Duration, fetch: F) -> Result<Arc<T>>
+    where
+        K: Into<Cow<'static, str>>,
+        F: FnOnce() -> Result<T>,
+        T: Send + Sync + 'static,
+    {
+        let disabled = Duration::from_secs(0);
+        if self.inner.ttl == disabled || ttl == disabled {
+            return fetch().map(Arc::new);
+        }
+        let key = key.into();
+        if let Some(value) = self.get(&key) {
+            COLLECTION_CACHE_HITS
+                .with_label_values(&[key.as_ref()])
+                .inc();
+            return Ok(value);
+        }
+        COLLECTION_CACHE_MISSES
+            .with_label_values(&[key.as_ref()])
+            .inc();
+        let value = Arc::new(fetch()?);
+        let entry = Entry {
+            expires: Instant::now() + ttl,
+            value: Arc::clone(&value) as Arc<dyn Any + Send + Sync>,
+        };
+        self.inner
+            .entries
+            .lock()
+            .expect("CollectionCache lock poisoned")
+            .insert(key, entry);
+        Ok(value)
+    }
+
+    /// Drop the cached result for `key`, if any.
+    pub fn invalidate(&self, key: &str) {
+        self.inner
+            .entries
+            .lock()
+            .expect("CollectionCache lock poisoned")
+            .remove(key);
+    }
+
+    /// Look up an unexpired entry of the expected type, dropping it if expired.
+    fn get<T>(&self, key: &str) -> Option<Arc<T>>
+    where
+        T: Send + Sync + 'static,
+    {
+        let mut entries = self
+            .inner
+            .entries
+            .lock()
+            .expect("CollectionCache lock poisoned");
+        let expired = match entries.get(key) {
+            None => return None,
+            Some(entry) => entry.expires <= Instant::now(),
+        };
+        if expired {
+            entries.remove(key);
+            return None;
+        }
+        entries
+            .get(key)
+            .and_then(|entry| Arc::clone(&entry.value).downcast::<T>().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    use super::CollectionCache;
+    use crate::config::CollectionCacheConfig;
+    use crate::ErrorKind;
+
+    #[test]
+    fn fetches_once_within_ttl() {
+        let cache = CollectionCache::new(&CollectionCacheConfig { ttl: 60000 });
+        let fetches = Cell::new(0);
+        let fetch = || {
+            fetches.set(fetches.get() + 1);
+            Ok(42)
+        };
+        assert_eq!(*cache.get_or_fetch("test", fetch).unwrap(), 42);
+        assert_eq!(*cache.get_or_fetch("test", fetch).unwrap(), 42);
+        assert_eq!(fetches.get(), 1);
+    }
+
+    #[test]
+    fn explicit_ttl_and_invalidation() {
+        let cache = CollectionCache::new(&CollectionCacheConfig { ttl: 60000 });
+        let expired = cache.get_or_fetch_for("short", Duration::from_millis(1), || Ok(1));
+        assert_eq!(*expired.unwrap(), 1);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(*cache.get_or_fetch("short", || Ok(2)).unwrap(), 2);
+
+        cache.invalidate("short");
+        assert_eq!(*cache.get_or_fetch("short", || Ok(3)).unwrap(), 3);
+        cache.clear();
+        assert_eq!(*cache.get_or_fetch("short", || Ok(4)).unwrap(), 4);
+    }
+
+    #[test]
+    fn runtime_keys() {
+        let cache = CollectionCache::new(&CollectionCacheConfig { ttl: 60000 });
+        for topic in &["orders", "payments"] {
+            let key = format!("test.{}", topic);
+            assert_eq!(*cache.get_or_fetch(key, || Ok(*topic)).unwrap(), *topic);
+        }
+        let cached = cache.get_or_fetch(String::from("test.orders"), || Ok("other"));
+        assert_eq!(*cached.unwrap(), "orders");
+        cache.invalidate(&format!("test.{}", "orders"));
+        let fetched = cache.get_or_fetch("test.orders", || Ok("other"));
+        assert_eq!(*fetched.unwrap(), "other");
+    }
+
+    #[test]
+    fn errors_are_not_cached() {
+        let cache = CollectionCache::new(&CollectionCacheConfig { ttl: 60000 });
+        let error = cache.get_or_fetch::<_, _, u32>("test", || Err(ErrorKind::ActionEncode.into()));
+        assert!(error.is_err());
+        assert_eq!(*cache.get_or_fetch("test", || Ok(42)).unwrap(), 42);
+    }
+
+    #[test]
+    fn disabled_with_zero_ttl() {
+        let cache = CollectionCache::new(&CollectionCacheConfig { ttl: 0 });
+        assert_eq!(*cache.get_or_fetch("test", || Ok(1)).unwrap(), 1);
+        assert_eq!(*cache.get_or_fetch("test", || Ok(2)).unwrap(), 2);
+    }
+}
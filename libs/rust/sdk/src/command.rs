@@ -0,0 +1,179 @@
+//! Run external commands without letting them wedge the agent threads that wait on them.
+use std::io;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::process::Child;
+use std::process::Command;
+use std::process::Output;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Time to wait for output pipes to close once the child process has exited.
+///
+/// Processes started by the child may inherit its pipes and keep them open
+/// long after the child itself exited: output they write after this is lost.
+const PIPES_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Spawn the command as the leader of a new session and process group.
+///
+/// Running commands in their own group lets `wait_with_timeout` kill
+/// any process they start along with them.
+pub fn spawn_group(command: &mut Command) -> io::Result<Child> {
+    // SAFETY: setsid is async-signal-safe and does not allocate.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+/// Wait for a child started with `spawn_group` to exit, killing it if the timeout expires.
+///
+/// On timeout the entire process group of the child is killed.
+/// Output is collected until the pipes close or shortly after the child exits,
+/// whichever comes first, so processes holding on to the pipes can't block the caller.
+///
+/// Returns the process output and a flag set if the process timed out.
+pub fn wait_with_timeout(mut child: Child, timeout: Duration) -> io::Result<(Output, bool)> {
+    let stdout = PipeReader::spawn(child.stdout.take());
+    let stderr = PipeReader::spawn(child.stderr.take());
+    let deadline = Instant::now() + timeout;
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            timed_out = true;
+            kill_group(&mut child)?;
+            break child.wait()?;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    let drain = Instant::now() + PIPES_DRAIN_TIMEOUT;
+    let output = Output {
+        status,
+        stdout: stdout.collect(drain),
+        stderr: stderr.collect(drain),
+    };
+    Ok((output, timed_out))
+}
+
+/// Kill the process group led by the child, falling back to the child alone.
+fn kill_group(child: &mut Child) -> io::Result<()> {
+    let pgid = child.id() as libc::pid_t;
+    // SAFETY: kill has no memory safety requirements.
+    if unsafe { libc::kill(-pgid, libc::SIGKILL) } == 0 {
+        return Ok(());
+    }
+    child.kill()
+}
+
+/// Data read from a child process pipe by a background thread.
+struct PipeReader {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    closed: Receiver<()>,
+}
+
+impl PipeReader {
+    fn spawn<R>(pipe: Option<R>) -> PipeReader
+    where
+        R: Read + Send + 'static,
+    {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let (notify, closed) = mpsc::channel();
+        let shared = Arc::clone(&buffer);
+        thread::spawn(move || {
+            if let Some(mut pipe) = pipe {
+                let mut chunk = [0; 4096];
+                loop {
+                    // Read errors are treated like the end of the output.
+                    match pipe.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(size) => shared
+                            .lock()
+                            .expect("PipeReader lock poisoned")
+                            .extend_from_slice(&chunk[..size]),
+                        Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(_) => break,
+                    }
+                }
+            }
+            let _ = notify.send(());
+        });
+        PipeReader { buffer, closed }
+    }
+
+    /// Wait for the pipe to close, up to the deadline, and return the data read so far.
+    fn collect(self, deadline: Instant) -> Vec<u8> {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        let _ = self.closed.recv_timeout(wait);
+        let mut buffer = self.buffer.lock().expect("PipeReader lock poisoned");
+        std::mem::take(&mut *buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use std::process::Stdio;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::spawn_group;
+    use super::wait_with_timeout;
+
+    #[test]
+    fn wait_kills_on_timeout() {
+        let child = spawn_group(
+            Command::new("sleep")
+                .arg("10")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .unwrap();
+        let (_, timed_out) = wait_with_timeout(child, Duration::from_millis(100)).unwrap();
+        assert!(timed_out);
+    }
+
+    #[test]
+    fn wait_kills_processes_holding_pipes() {
+        let start = Instant::now();
+        let child = spawn_group(
+            Command::new("sh")
+                .arg("-c")
+                .arg("sleep 10 & echo started; sleep 10")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .unwrap();
+        let (output, timed_out) = wait_with_timeout(child, Duration::from_millis(500)).unwrap();
+        assert!(timed_out);
+        assert_eq!(output.stdout, b"started\n");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn wait_collects_output() {
+        let child = spawn_group(
+            Command::new("echo")
+                .arg("done")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .unwrap();
+        let (output, timed_out) = wait_with_timeout(child, Duration::from_secs(10)).unwrap();
+        assert!(!timed_out);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"done\n");
+    }
+}
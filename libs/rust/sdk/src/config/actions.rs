@@ -1,9 +1,34 @@
+use std::collections::BTreeMap;
+
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::ErrorKind;
+use crate::Result;
+
 /// Actions configuration
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct ActionsConfig {
+    /// Deprecated action kinds mapped to the kind of the action that replaced them.
+    ///
+    /// Actions scheduled with an alias are recorded with the new kind
+    /// and a deprecation warning is added to the API audit trail.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+
+    /// Move pruned actions to a compressed archive instead of deleting them (disabled by default).
+    ///
+    /// Archived actions are never deleted by the agent.
+    #[serde(default = "ActionsConfig::default_archive")]
+    pub archive: bool,
+
+    /// Directory the `debug.store.backup` action writes store snapshots to.
+    ///
+    /// The action only accepts file names so snapshots can't be written anywhere else.
+    /// Store backups through the actions API are disabled if not set.
+    #[serde(default)]
+    pub backup_dir: Option<String>,
+
     /// Enable/disable agent actions.
     #[serde(default)]
     pub enabled: Option<bool>,
@@ -12,6 +37,46 @@ pub struct ActionsConfig {
     #[serde(default = "ActionsConfig::default_execute_interval")]
     pub execute_interval: u64,
 
+    /// Maximum delay, in seconds, between polls when there are no actions to execute.
+    ///
+    /// While idle the delay doubles after each empty poll, starting from `execute_interval`.
+    /// Set to the same value as `execute_interval` to disable the backoff.
+    #[serde(default = "ActionsConfig::default_execute_interval_max")]
+    pub execute_interval_max: u64,
+
+    /// Path to a YAML file listing the external actions allowed to run.
+    ///
+    /// The file maps external action names to the SHA-256 checksums of the executables
+    /// their `action` and `check` commands run and of the files given to them as arguments.
+    /// When set, external actions not in the file can't be registered and commands are
+    /// verified against their checksum every time they are executed.
+    ///
+    /// Verified files are copied into sealed in-memory files before they are checksummed
+    /// and the copies are executed so files replaced after verification are never run.
+    #[serde(default)]
+    pub external_allowlist: Option<String>,
+
+    /// Time, in seconds, the oldest pending action can wait before the agent health warns.
+    ///
+    /// Actions waiting longer than this usually mean the actions engine is stuck.
+    #[serde(default = "ActionsConfig::default_lag_threshold")]
+    pub lag_threshold: u64,
+
+    /// Time, in seconds, the agent process executing an action holds a lease on it.
+    ///
+    /// Leases are renewed every time the action is invoked and prevent other agent
+    /// processes using the same DB from executing the action until they expire.
+    /// Must be longer than `execute_interval_max`.
+    #[serde(default = "ActionsConfig::default_lease_timeout")]
+    pub lease_timeout: u64,
+
+    /// Maximum number of bytes of each external command output stream to store.
+    ///
+    /// Longer outputs keep their head and tail around a truncation marker.
+    /// Set to 0 to store outputs in full.
+    #[serde(default = "ActionsConfig::default_output_limit")]
+    pub output_limit: usize,
+
     /// Delay, in seconds, between historical action prune cycles.
     #[serde(default = "ActionsConfig::default_prune_interval")]
     pub prune_interval: u64,
@@ -28,8 +93,16 @@ pub struct ActionsConfig {
 impl Default for ActionsConfig {
     fn default() -> Self {
         ActionsConfig {
+            aliases: BTreeMap::new(),
+            archive: Self::default_archive(),
+            backup_dir: None,
             enabled: None,
             execute_interval: Self::default_execute_interval(),
+            execute_interval_max: Self::default_execute_interval_max(),
+            external_allowlist: None,
+            lag_threshold: Self::default_lag_threshold(),
+            lease_timeout: Self::default_lease_timeout(),
+            output_limit: Self::default_output_limit(),
             prune_interval: Self::default_prune_interval(),
             prune_keep: Self::default_prune_keep(),
             prune_limit: Self::default_prune_limit(),
@@ -38,10 +111,49 @@ impl Default for ActionsConfig {
 }
 
 impl ActionsConfig {
+    /// Validate the actions configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.execute_interval == 0 {
+            let error = "must be at least 1 second".to_string();
+            return Err(ErrorKind::ConfigInvalid("actions.execute_interval", error).into());
+        }
+        if self.execute_interval_max < self.execute_interval {
+            let error = "must not be less than actions.execute_interval".to_string();
+            return Err(ErrorKind::ConfigInvalid("actions.execute_interval_max", error).into());
+        }
+        if self.lease_timeout <= self.execute_interval_max {
+            let error = "must be greater than actions.execute_interval_max".to_string();
+            return Err(ErrorKind::ConfigInvalid("actions.lease_timeout", error).into());
+        }
+        Ok(())
+    }
+}
+
+impl ActionsConfig {
+    fn default_archive() -> bool {
+        false
+    }
+
     fn default_execute_interval() -> u64 {
         1
     }
 
+    fn default_execute_interval_max() -> u64 {
+        10
+    }
+
+    fn default_lag_threshold() -> u64 {
+        300
+    }
+
+    fn default_lease_timeout() -> u64 {
+        60
+    }
+
+    fn default_output_limit() -> usize {
+        8192
+    }
+
     fn default_prune_interval() -> u64 {
         3600
     }
@@ -95,11 +207,85 @@ pub struct ExternalActionConfig {
     /// ```json
     /// {
     ///   "status": "running" | "finished" | "failed",
-    ///   "error": <error message, required it status == failed>
+    ///   "error": <error message, required it status == failed>,
+    ///   "logs": [<optional progress lines to append to the action logs>]
     /// }
     /// ```
     pub check: Vec<String>,
 
     /// Operator friendly description of what the action does.
     pub description: String,
+
+    /// Additional environment variables set for the action and check commands.
+    ///
+    /// Values can be given inline or resolved, when the command is executed,
+    /// from an environment variable of the agent or from a file (useful for secrets).
+    #[serde(default)]
+    pub env: BTreeMap<String, ExternalActionEnv>,
+
+    /// Maximum time, in seconds, the action and check commands can run for.
+    ///
+    /// Commands run in their own process group: commands that do not exit in time
+    /// are killed, along with any process they started, and the action fails.
+    #[serde(default)]
+    pub timeout: Option<u64>,
+
+    /// Name of the user to run the action and check commands as.
+    ///
+    /// The agent process must have the privileges needed to switch user.
+    #[serde(default)]
+    pub user: Option<String>,
+
+    /// Working directory for the action and check commands.
+    ///
+    /// If not set, commands inherit the working directory of the agent.
+    #[serde(default)]
+    pub workdir: Option<String>,
+}
+
+/// SHA-256 checksums of the executables run by an allowed external action.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ExternalActionChecksums {
+    /// Checksum of the executable run by the action command.
+    pub action: String,
+
+    /// Checksum of the executable run by the check command.
+    pub check: String,
+
+    /// Checksums of files passed as arguments to the commands, such as interpreted scripts.
+    ///
+    /// Keys are the arguments as given in the command configuration.
+    /// Command arguments that name an existing file must be listed here.
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+}
+
+/// Value of an environment variable set for external action commands.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ExternalActionEnv {
+    /// Resolve the value from an environment variable of the agent process.
+    FromEnv { from_env: String },
+
+    /// Resolve the value from the content of a file, without trailing new lines.
+    FromFile { from_file: String },
+
+    /// Use the given value as is.
+    Value(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ActionsConfig;
+
+    #[test]
+    fn validate_execute_interval_max() {
+        let config = ActionsConfig {
+            execute_interval: 5,
+            execute_interval_max: 2,
+            ..ActionsConfig::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(ActionsConfig::default().validate().is_ok());
+    }
 }
@@ -1,21 +1,74 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
 use std::sync::RwLock;
 
+#[cfg(feature = "api")]
+use actix_web::http::Method;
+#[cfg(feature = "api")]
+use actix_web::http::Uri;
 use lazy_static::lazy_static;
+use regex::Regex;
 use serde::Deserialize;
+use serde::Deserializer;
 use serde::Serialize;
 
+use crate::ErrorKind;
+use crate::Result;
+
 // Define some globals to hold the default overrides.
 lazy_static! {
     static ref DEFAULT_BIND: RwLock<Option<String>> = RwLock::new(None);
 }
 
+/// Check if two different bind addresses would conflict when listening at the same time.
+///
+/// Wildcard addresses accept connections for every address of their family on the port.
+/// IPv6 wildcards also accept IPv4 connections unless the system is set to bind IPv6
+/// sockets only (`net.ipv6.bindv6only` on Linux, off by default) so they are assumed
+/// to overlap with all addresses on the port.
+fn bind_overlaps(left: SocketAddr, right: SocketAddr) -> bool {
+    let covers = |wildcard: SocketAddr, other: SocketAddr| {
+        wildcard.ip().is_unspecified() && (wildcard.is_ipv6() || other.is_ipv4())
+    };
+    left != right
+        && left.port() != 0
+        && left.port() == right.port()
+        && (covers(left, right) || covers(right, left))
+}
+
 /// Web server configuration options.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct APIConfig {
-    /// Local addess to bind the API server to.
-    #[serde(default = "APIConfig::default_bind")]
-    pub bind: String,
+    /// Local addesses to bind the API server to.
+    ///
+    /// Accepts a single address or a list of addresses.
+    #[serde(
+        default = "APIConfig::default_bind",
+        deserialize_with = "APIConfig::deserialize_bind"
+    )]
+    pub bind: Vec<String>,
+
+    /// Audit trail of API requests and the clients making them (disabled by default).
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+
+    /// Log sampled request and response bodies of actions endpoints (disabled by default).
+    #[serde(default)]
+    pub body_logging: Option<BodyLoggingConfig>,
+
+    /// Cross-Origin Resource Sharing for browser-based tools (disabled by default).
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+
+    /// Serve the datastore log file to mutually authenticated clients (disabled by default).
+    #[serde(default)]
+    pub datastore_logs: Option<DatastoreLogsConfig>,
+
+    /// Enable/disable API trees at runtime through the introspection API (disabled by default).
+    #[serde(default)]
+    pub runtime_trees: Option<RuntimeTreesConfig>,
 
     /// The number of request handling threads.
     #[serde(default)]
@@ -37,7 +90,12 @@ pub struct APIConfig {
 impl Default for APIConfig {
     fn default() -> Self {
         APIConfig {
+            audit: None,
             bind: Self::default_bind(),
+            body_logging: None,
+            cors: None,
+            datastore_logs: None,
+            runtime_trees: None,
             threads_count: None,
             timeouts: Timeouts::default(),
             tls: None,
@@ -48,13 +106,89 @@ impl Default for APIConfig {
 
 impl APIConfig {
     /// Default value for `bind` used by serde.
-    fn default_bind() -> String {
-        DEFAULT_BIND
+    fn default_bind() -> Vec<String> {
+        let bind = DEFAULT_BIND
             .read()
             .unwrap()
             .as_ref()
             .map(Clone::clone)
-            .unwrap_or_else(|| String::from("127.0.0.1:8000"))
+            .unwrap_or_else(|| String::from("127.0.0.1:8000"));
+        vec![bind]
+    }
+
+    /// Deserialize `bind` from a single address or a list of addresses.
+    fn deserialize_bind<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Bind {
+            One(String),
+            Many(Vec<String>),
+        }
+        let bind = match Bind::deserialize(deserializer)? {
+            Bind::One(bind) => vec![bind],
+            Bind::Many(bind) => bind,
+        };
+        Ok(bind)
+    }
+}
+
+impl APIConfig {
+    /// Validate the API server configuration.
+    ///
+    /// Checks that at least one bind address is given, that all bind addresses
+    /// are valid and that no address is listed more than once or overlaps with
+    /// a wildcard address on the same port.
+    pub fn validate(&self) -> Result<()> {
+        if self.bind.is_empty() {
+            let error = "at least one address is required".to_string();
+            return Err(ErrorKind::ConfigInvalid("api.bind", error).into());
+        }
+        let mut seen: HashSet<SocketAddr> = HashSet::new();
+        for bind in &self.bind {
+            let addrs = bind.to_socket_addrs().map_err(|error| {
+                let error = format!("invalid address '{}': {}", bind, error);
+                ErrorKind::ConfigInvalid("api.bind", error)
+            })?;
+            for addr in addrs {
+                if !seen.insert(addr) {
+                    let error = format!("address '{}' is bound more than once", addr);
+                    return Err(ErrorKind::ConfigInvalid("api.bind", error).into());
+                }
+                if let Some(other) = seen.iter().find(|other| bind_overlaps(**other, addr)) {
+                    let error = format!("address '{}' overlaps with '{}'", addr, other);
+                    return Err(ErrorKind::ConfigInvalid("api.bind", error).into());
+                }
+            }
+        }
+        if let Some(body_logging) = &self.body_logging {
+            body_logging.validate()?;
+        }
+        if let Some(cors) = &self.cors {
+            cors.validate()?;
+        }
+        if let Some(datastore_logs) = &self.datastore_logs {
+            datastore_logs.validate()?;
+            let mutual_tls = self
+                .tls
+                .as_ref()
+                .map(|tls| tls.clients_ca_bundle.is_some())
+                .unwrap_or(false);
+            if !mutual_tls {
+                let error = "requires TLS client certificates (api.tls.clients_ca_bundle)".into();
+                return Err(ErrorKind::ConfigInvalid("api.datastore_logs", error).into());
+            }
+        }
+        if let Some(runtime_trees) = &self.runtime_trees {
+            runtime_trees.validate()?;
+        }
+        if self.tls.is_some() && !cfg!(any(feature = "tls-openssl", feature = "tls-rustls")) {
+            let error = "the agent was built without TLS support".to_string();
+            return Err(ErrorKind::ConfigInvalid("api.tls", error).into());
+        }
+        Ok(())
     }
 }
 
@@ -63,6 +197,7 @@ impl APIConfig {
     ///
     /// This should be done at the very beginning of your agent and
     /// BEFORE ANY CONFIGURATION IS LOADED/INSTANTIATED.
+    /// Agents loading configuration with a `ConfigLoader` should set defaults with it instead.
     ///
     /// # Panics
     /// If the default is set more then once.
@@ -102,6 +237,36 @@ impl APITrees {
     }
 }
 
+/// Audit trail of API requests.
+///
+/// Each request is logged with the subject of the client TLS certificate (if any),
+/// the endpoint called and the ID of the action it scheduled (if any).
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Expose the most recent audit records on the introspection `/audit` endpoint.
+    #[serde(default)]
+    pub expose: bool,
+
+    /// Number of audit records to keep in memory for the `/audit` endpoint.
+    #[serde(default = "AuditConfig::default_keep")]
+    pub keep: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            expose: false,
+            keep: Self::default_keep(),
+        }
+    }
+}
+
+impl AuditConfig {
+    fn default_keep() -> usize {
+        100
+    }
+}
+
 // We can's fulfill the wish of the implicit-hasher clippy because
 // we do not use the genieric hasher parameter in any LOCAL type.
 #[allow(clippy::implicit_hasher)]
@@ -114,6 +279,211 @@ impl From<APITrees> for HashMap<&'static str, bool> {
     }
 }
 
+/// Request and response body logging for actions endpoints.
+///
+/// Intended to troubleshoot schema mismatches between Core and agents:
+/// bodies are logged at debug level so the logger must be configured accordingly.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct BodyLoggingConfig {
+    /// Maximum number of bytes of each body to log, larger bodies are not logged.
+    #[serde(default = "BodyLoggingConfig::default_max_size")]
+    pub max_size: usize,
+
+    /// JSON object keys whose values are replaced before bodies are logged.
+    #[serde(default = "BodyLoggingConfig::default_redact")]
+    pub redact: Vec<String>,
+
+    /// Percentage of requests (0 to 100) to log bodies for.
+    #[serde(default = "BodyLoggingConfig::default_sample_percent")]
+    pub sample_percent: u8,
+}
+
+impl Default for BodyLoggingConfig {
+    fn default() -> Self {
+        BodyLoggingConfig {
+            max_size: Self::default_max_size(),
+            redact: Self::default_redact(),
+            sample_percent: Self::default_sample_percent(),
+        }
+    }
+}
+
+impl BodyLoggingConfig {
+    fn default_max_size() -> usize {
+        4096
+    }
+
+    fn default_redact() -> Vec<String> {
+        vec!["password".into(), "secret".into(), "token".into()]
+    }
+
+    fn default_sample_percent() -> u8 {
+        10
+    }
+
+    /// Validate the body logging configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.sample_percent > 100 {
+            let error = format!("{} is not a percentage", self.sample_percent);
+            return Err(ErrorKind::ConfigInvalid("api.body_logging.sample_percent", error).into());
+        }
+        Ok(())
+    }
+}
+
+/// Cross-Origin Resource Sharing (CORS) configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// HTTP methods browsers are allowed to use in cross-origin requests.
+    #[serde(default = "CorsConfig::default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+
+    /// Origins allowed to make cross-origin requests, or `*` for any origin.
+    pub allowed_origins: Vec<String>,
+
+    /// Time, in seconds, browsers can cache preflight responses for.
+    #[serde(default)]
+    pub max_age: Option<usize>,
+}
+
+impl CorsConfig {
+    fn default_allowed_methods() -> Vec<String> {
+        vec!["GET".into()]
+    }
+
+    /// Validate the CORS configuration.
+    ///
+    /// Origins must be `*` on its own or `scheme://host[:port]` values, as sent by browsers.
+    pub fn validate(&self) -> Result<()> {
+        if self.allowed_origins.is_empty() {
+            let error = "at least one origin is required".to_string();
+            return Err(ErrorKind::ConfigInvalid("api.cors.allowed_origins", error).into());
+        }
+        let any_origin = self.allowed_origins.iter().any(|origin| origin == "*");
+        if any_origin && self.allowed_origins.len() > 1 {
+            let error = "'*' can't be combined with other origins".to_string();
+            return Err(ErrorKind::ConfigInvalid("api.cors.allowed_origins", error).into());
+        }
+        #[cfg(feature = "api")]
+        for origin in self.allowed_origins.iter().filter(|origin| *origin != "*") {
+            if !valid_origin(origin) {
+                let error = format!("invalid origin '{}', expected scheme://host[:port]", origin);
+                return Err(ErrorKind::ConfigInvalid("api.cors.allowed_origins", error).into());
+            }
+        }
+        #[cfg(feature = "api")]
+        for method in &self.allowed_methods {
+            if Method::from_bytes(method.as_bytes()).is_err() {
+                let error = format!("invalid HTTP method '{}'", method);
+                return Err(ErrorKind::ConfigInvalid("api.cors.allowed_methods", error).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check an origin is a URI made only of a scheme and an authority.
+#[cfg(feature = "api")]
+fn valid_origin(origin: &str) -> bool {
+    let uri: Uri = match origin.parse() {
+        Ok(uri) => uri,
+        Err(_) => return false,
+    };
+    match (uri.scheme_str(), uri.authority()) {
+        (Some(scheme), Some(authority)) => origin == format!("{}://{}", scheme, authority),
+        _ => false,
+    }
+}
+
+/// Datastore log file tailing configuration.
+///
+/// Operators can fetch the most recent lines of the datastore log, or follow it,
+/// through the agent API without shell access to the node.
+/// Only clients authenticated with TLS certificates can access the log.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct DatastoreLogsConfig {
+    /// Maximum time, in seconds, a client can follow the log for.
+    #[serde(default = "DatastoreLogsConfig::default_follow_timeout")]
+    pub follow_timeout: u64,
+
+    /// Maximum number of clients following the log at the same time.
+    #[serde(default = "DatastoreLogsConfig::default_max_followers")]
+    pub max_followers: usize,
+
+    /// Maximum number of lines clients can request.
+    #[serde(default = "DatastoreLogsConfig::default_max_lines")]
+    pub max_lines: usize,
+
+    /// Path to the datastore log file.
+    pub path: String,
+
+    /// Regular expressions matching log content to redact before it is sent to clients.
+    #[serde(default)]
+    pub redact: Vec<String>,
+}
+
+impl DatastoreLogsConfig {
+    fn default_follow_timeout() -> u64 {
+        300
+    }
+
+    fn default_max_followers() -> usize {
+        4
+    }
+
+    fn default_max_lines() -> usize {
+        1000
+    }
+
+    /// Validate the datastore logs configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.path.is_empty() {
+            let error = "path must not be empty".to_string();
+            return Err(ErrorKind::ConfigInvalid("api.datastore_logs.path", error).into());
+        }
+        if self.max_followers == 0 {
+            let error = "at least one follower must be allowed".to_string();
+            return Err(ErrorKind::ConfigInvalid("api.datastore_logs.max_followers", error).into());
+        }
+        if self.max_lines == 0 {
+            let error = "at least one line must be allowed".to_string();
+            return Err(ErrorKind::ConfigInvalid("api.datastore_logs.max_lines", error).into());
+        }
+        for pattern in &self.redact {
+            if let Err(error) = Regex::new(pattern) {
+                let error = format!("invalid pattern '{}': {}", pattern, error);
+                return Err(ErrorKind::ConfigInvalid("api.datastore_logs.redact", error).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Enable/disable API trees at runtime.
+///
+/// Changes are persisted to the agent store so they survive restarts
+/// and can be reverted to the configured `trees` at any time.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct RuntimeTreesConfig {
+    /// Bearer token clients must present to view or change API trees.
+    pub token: String,
+}
+
+impl RuntimeTreesConfig {
+    /// Validate the runtime API trees configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.token.is_empty() {
+            let error = "a token is required".to_string();
+            return Err(ErrorKind::ConfigInvalid("api.runtime_trees.token", error).into());
+        }
+        if !cfg!(feature = "store") {
+            let error = "the agent was built without store support".to_string();
+            return Err(ErrorKind::ConfigInvalid("api.runtime_trees", error).into());
+        }
+        Ok(())
+    }
+}
+
 /// API server timeouts.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct Timeouts {
@@ -167,3 +537,203 @@ pub struct TlsConfig {
     /// Path to a PEM file with the server's PRIVATE certificate.
     pub server_key: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::APIConfig;
+    use super::BodyLoggingConfig;
+    use super::CorsConfig;
+    use super::DatastoreLogsConfig;
+    use super::TlsConfig;
+
+    #[test]
+    fn bind_list() {
+        let config: APIConfig = serde_yaml::from_str("bind: ['[::1]:8000', '127.0.0.1:8000']")
+            .expect("config to decode");
+        assert_eq!(config.bind, vec!["[::1]:8000", "127.0.0.1:8000"]);
+        config.validate().expect("config to be valid");
+    }
+
+    #[test]
+    fn bind_single() {
+        let config: APIConfig =
+            serde_yaml::from_str("bind: '[::]:8000'").expect("config to decode");
+        assert_eq!(config.bind, vec!["[::]:8000"]);
+        config.validate().expect("config to be valid");
+    }
+
+    #[test]
+    fn validate_body_logging() {
+        let body_logging = BodyLoggingConfig {
+            sample_percent: 101,
+            ..BodyLoggingConfig::default()
+        };
+        let config = APIConfig {
+            body_logging: Some(body_logging),
+            ..APIConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_datastore_logs() {
+        let datastore_logs = DatastoreLogsConfig {
+            follow_timeout: 300,
+            max_followers: 4,
+            max_lines: 1000,
+            path: "/var/log/store.log".into(),
+            redact: vec!["password=\\S+".into()],
+        };
+        let config = APIConfig {
+            datastore_logs: Some(datastore_logs.clone()),
+            ..APIConfig::default()
+        };
+        assert!(config.validate().is_err());
+
+        let tls = TlsConfig {
+            clients_ca_bundle: Some("/etc/agent/ca.pem".into()),
+            server_cert: "/etc/agent/cert.pem".into(),
+            server_key: "/etc/agent/key.pem".into(),
+        };
+        let mut config = APIConfig {
+            datastore_logs: Some(datastore_logs),
+            tls: Some(tls),
+            ..APIConfig::default()
+        };
+        let tls_support = cfg!(any(feature = "tls-openssl", feature = "tls-rustls"));
+        assert_eq!(config.validate().is_ok(), tls_support);
+
+        config.datastore_logs.as_mut().unwrap().redact = vec!["(".into()];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_cors() {
+        let cors = CorsConfig {
+            allowed_methods: vec!["GET".into(), "NOT A METHOD".into()],
+            allowed_origins: vec!["https://ui.example.com".into()],
+            max_age: None,
+        };
+        let config = APIConfig {
+            cors: Some(cors.clone()),
+            ..APIConfig::default()
+        };
+        assert!(config.validate().is_err());
+        let cors = CorsConfig {
+            allowed_methods: vec!["GET".into(), "POST".into()],
+            ..cors
+        };
+        assert!(cors.validate().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "api")]
+    fn validate_cors_origins() {
+        let cors = CorsConfig {
+            allowed_methods: vec!["GET".into()],
+            allowed_origins: vec!["https://ui.example.com:8443".into(), "*".into()],
+            max_age: None,
+        };
+        let error = cors.validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid value for configuration option api.cors.allowed_origins: '*' can't be combined with other origins",
+        );
+        let valid = ["*", "https://ui.example.com", "http://127.0.0.1:8080"];
+        for origin in &valid {
+            let cors = CorsConfig {
+                allowed_origins: vec![origin.to_string()],
+                ..cors.clone()
+            };
+            assert!(cors.validate().is_ok(), "origin {} is valid", origin);
+        }
+        let invalid = [
+            "not an origin",
+            "ui.example.com",
+            "https://ui.example.com/",
+            "https://ui.example.com/path",
+        ];
+        for origin in &invalid {
+            let cors = CorsConfig {
+                allowed_origins: vec![origin.to_string()],
+                ..cors.clone()
+            };
+            assert!(cors.validate().is_err(), "origin {} is invalid", origin);
+        }
+    }
+
+    #[test]
+    fn validate_bind_different_ports() {
+        let config = APIConfig {
+            bind: vec!["[::]:8000".into(), "0.0.0.0:8001".into()],
+            ..APIConfig::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_bind_dual_stack_loopback() {
+        let config = APIConfig {
+            bind: vec!["[::1]:8000".into(), "127.0.0.1:8000".into()],
+            ..APIConfig::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_bind_duplicate() {
+        let config = APIConfig {
+            bind: vec!["127.0.0.1:8000".into(), "127.0.0.1:8000".into()],
+            ..APIConfig::default()
+        };
+        let error = config.validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid value for configuration option api.bind: address '127.0.0.1:8000' is bound more than once",
+        );
+    }
+
+    #[test]
+    fn validate_bind_empty() {
+        let config = APIConfig {
+            bind: Vec::new(),
+            ..APIConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_bind_ipv4_wildcard_overlap() {
+        let config = APIConfig {
+            bind: vec!["127.0.0.1:8000".into(), "0.0.0.0:8000".into()],
+            ..APIConfig::default()
+        };
+        let error = config.validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid value for configuration option api.bind: address '0.0.0.0:8000' overlaps with '127.0.0.1:8000'",
+        );
+    }
+
+    #[test]
+    fn validate_bind_ipv6_wildcard_overlap() {
+        let config = APIConfig {
+            bind: vec!["[::]:8000".into(), "0.0.0.0:8000".into()],
+            ..APIConfig::default()
+        };
+        let error = config.validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid value for configuration option api.bind: address '0.0.0.0:8000' overlaps with '[::]:8000'",
+        );
+    }
+
+    #[test]
+    fn validate_bind_invalid() {
+        let config = APIConfig {
+            bind: vec!["not an address".into()],
+            ..APIConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,47 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Pool of threads running blocking datastore calls made to serve API requests.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct BlockingPoolConfig {
+    /// Maximum number of calls waiting for a free thread.
+    ///
+    /// Calls beyond this are rejected straight away.
+    #[serde(default = "BlockingPoolConfig::default_queue_size")]
+    pub queue_size: usize,
+
+    /// Number of threads running datastore calls.
+    #[serde(default = "BlockingPoolConfig::default_threads")]
+    pub threads: usize,
+}
+
+impl Default for BlockingPoolConfig {
+    fn default() -> Self {
+        BlockingPoolConfig {
+            queue_size: Self::default_queue_size(),
+            threads: Self::default_threads(),
+        }
+    }
+}
+
+impl BlockingPoolConfig {
+    fn default_queue_size() -> usize {
+        32
+    }
+
+    fn default_threads() -> usize {
+        4
+    }
+
+    /// Validate the blocking pool configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.threads == 0 {
+            let error = "must be at least 1".to_string();
+            return Err(ErrorKind::ConfigInvalid("blocking_pool.threads", error).into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Circuit breaker around datastore calls.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failed calls after which the breaker opens.
+    #[serde(default = "CircuitBreakerConfig::default_failure_threshold")]
+    pub failure_threshold: u32,
+
+    /// Time, in seconds, the breaker stays open before a probe call is allowed.
+    #[serde(default = "CircuitBreakerConfig::default_open_seconds")]
+    pub open_seconds: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: Self::default_failure_threshold(),
+            open_seconds: Self::default_open_seconds(),
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    fn default_failure_threshold() -> u32 {
+        5
+    }
+
+    fn default_open_seconds() -> u64 {
+        30
+    }
+
+    /// Validate the circuit breaker configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.failure_threshold == 0 {
+            let error = "failure_threshold must be at least 1".to_string();
+            return Err(ErrorKind::ConfigInvalid("circuit_breaker", error).into());
+        }
+        Ok(())
+    }
+}
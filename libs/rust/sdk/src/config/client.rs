@@ -0,0 +1,62 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// How the agent identifies itself to the datastore it connects to.
+///
+/// The identity is reported to datastores that support client identification
+/// (MongoDB's `appName`, Kafka's `client.id`, ...) so operators can tell
+/// agent connections apart when auditing the datastore.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ClientIdentityConfig {
+    /// Replace the `<agent>/<version>` part of the identity.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Identifier of the node the agent runs on, included in the identity if set.
+    #[serde(default)]
+    pub node_id: Option<String>,
+}
+
+impl ClientIdentityConfig {
+    /// Client identity for the given agent, formatted as `<agent>/<version> (<node_id>)`.
+    pub fn identity(&self, agent: &str, version: &str) -> String {
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{}/{}", agent, version),
+        };
+        match &self.node_id {
+            Some(node_id) => format!("{} ({})", name, node_id),
+            None => name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClientIdentityConfig;
+
+    #[test]
+    fn identity_formats() {
+        let config = ClientIdentityConfig::default();
+        assert_eq!(
+            config.identity("repliagent-test", "1.2.3"),
+            "repliagent-test/1.2.3"
+        );
+        let config = ClientIdentityConfig {
+            name: None,
+            node_id: Some("node-1".into()),
+        };
+        assert_eq!(
+            config.identity("repliagent-test", "1.2.3"),
+            "repliagent-test/1.2.3 (node-1)"
+        );
+        let config = ClientIdentityConfig {
+            name: Some("custom".into()),
+            node_id: Some("node-1".into()),
+        };
+        assert_eq!(
+            config.identity("repliagent-test", "1.2.3"),
+            "custom (node-1)"
+        );
+    }
+}
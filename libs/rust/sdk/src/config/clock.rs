@@ -0,0 +1,60 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Clock skew detection configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ClockSkewConfig {
+    /// Delay, in seconds, between clock skew checks.
+    #[serde(default = "ClockSkewConfig::default_interval")]
+    pub interval: u64,
+
+    /// Skew, in seconds, between the agent and datastore clocks to warn about.
+    #[serde(default = "ClockSkewConfig::default_threshold")]
+    pub threshold: u64,
+}
+
+impl Default for ClockSkewConfig {
+    fn default() -> Self {
+        ClockSkewConfig {
+            interval: Self::default_interval(),
+            threshold: Self::default_threshold(),
+        }
+    }
+}
+
+impl ClockSkewConfig {
+    fn default_interval() -> u64 {
+        60
+    }
+
+    fn default_threshold() -> u64 {
+        2
+    }
+
+    /// Validate the clock skew options.
+    pub fn validate(&self) -> Result<()> {
+        if self.interval == 0 {
+            let error = "must be at least 1".to_string();
+            return Err(ErrorKind::ConfigInvalid("clock_skew.interval", error).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClockSkewConfig;
+
+    #[test]
+    fn validate_interval() {
+        assert!(ClockSkewConfig::default().validate().is_ok());
+        let config = ClockSkewConfig {
+            interval: 0,
+            ..ClockSkewConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,24 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Reuse datastore command results across the requests of a collection cycle.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct CollectionCacheConfig {
+    /// Time, in milliseconds, cached results are reused for (0 disables the cache).
+    #[serde(default = "CollectionCacheConfig::default_ttl")]
+    pub ttl: u64,
+}
+
+impl Default for CollectionCacheConfig {
+    fn default() -> Self {
+        CollectionCacheConfig {
+            ttl: Self::default_ttl(),
+        }
+    }
+}
+
+impl CollectionCacheConfig {
+    fn default_ttl() -> u64 {
+        2000
+    }
+}
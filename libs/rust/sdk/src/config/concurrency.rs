@@ -0,0 +1,56 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Limit concurrent datastore calls made to serve API requests.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ConcurrencyLimitConfig {
+    /// Maximum number of concurrent calls for each agent operation.
+    #[serde(default = "ConcurrencyLimitConfig::default_max_concurrent")]
+    pub max_concurrent: usize,
+
+    /// Maximum number of calls for each agent operation waiting for a free slot.
+    ///
+    /// Calls beyond this are rejected straight away.
+    #[serde(default = "ConcurrencyLimitConfig::default_max_queued")]
+    pub max_queued: usize,
+
+    /// Time, in milliseconds, calls wait for a free slot before they are rejected.
+    #[serde(default = "ConcurrencyLimitConfig::default_queue_timeout")]
+    pub queue_timeout: u64,
+}
+
+impl Default for ConcurrencyLimitConfig {
+    fn default() -> Self {
+        ConcurrencyLimitConfig {
+            max_concurrent: Self::default_max_concurrent(),
+            max_queued: Self::default_max_queued(),
+            queue_timeout: Self::default_queue_timeout(),
+        }
+    }
+}
+
+impl ConcurrencyLimitConfig {
+    fn default_max_concurrent() -> usize {
+        2
+    }
+
+    fn default_max_queued() -> usize {
+        10
+    }
+
+    fn default_queue_timeout() -> u64 {
+        5000
+    }
+
+    /// Validate the concurrency limit configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_concurrent == 0 {
+            let error = "max_concurrent must be at least 1".to_string();
+            return Err(ErrorKind::ConfigInvalid("concurrency_limit", error).into());
+        }
+        Ok(())
+    }
+}
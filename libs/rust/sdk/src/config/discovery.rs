@@ -0,0 +1,106 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Datastore endpoint discovery configuration.
+///
+/// Discovery resolves the address ("host:port") of the local datastore node
+/// when the agent starts and whenever the datastore can't be reached.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct DiscoveryConfig {
+    /// Method used to discover the datastore address.
+    #[serde(flatten)]
+    pub method: DiscoveryMethod,
+
+    /// Time, in seconds, the discovery command can run for before it is killed.
+    #[serde(default = "DiscoveryConfig::default_timeout")]
+    pub timeout: u64,
+}
+
+impl DiscoveryConfig {
+    fn default_timeout() -> u64 {
+        10
+    }
+
+    /// Validate the discovery options found at the given path.
+    pub fn validate(&self, path: &'static str) -> Result<()> {
+        if self.timeout == 0 {
+            let error = "timeout must be at least 1 second".to_string();
+            return Err(ErrorKind::ConfigInvalid(path, error).into());
+        }
+        let empty = match &self.method {
+            DiscoveryMethod::Command(command) => command.is_empty(),
+            DiscoveryMethod::Srv(name) => name.is_empty(),
+        };
+        if empty {
+            let error = "options can't be empty".to_string();
+            return Err(ErrorKind::ConfigInvalid(path, error).into());
+        }
+        Ok(())
+    }
+}
+
+/// Supported datastore endpoint discovery methods.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(tag = "method", content = "options")]
+pub enum DiscoveryMethod {
+    /// Execute a command that prints the datastore address to standard output.
+    ///
+    /// The first element in the list is the command to run.
+    /// All following elements in the list are optional and are passed to the command as arguments.
+    /// The first non-empty line of standard output is used as the address.
+    #[serde(rename = "command")]
+    Command(Vec<String>),
+
+    /// Lookup a DNS SRV record for the datastore address.
+    ///
+    /// When multiple records are returned the one with the lowest priority
+    /// and highest weight is selected.
+    #[serde(rename = "srv")]
+    Srv(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiscoveryConfig;
+    use super::DiscoveryMethod;
+
+    #[test]
+    fn command_with_default_timeout() {
+        let config = "method: command\noptions: ['discover', '--local']";
+        let config: DiscoveryConfig = serde_yaml::from_str(config).unwrap();
+        let command = vec!["discover".to_string(), "--local".to_string()];
+        assert_eq!(config.method, DiscoveryMethod::Command(command));
+        assert_eq!(config.timeout, 10);
+    }
+
+    #[test]
+    fn srv_with_timeout() {
+        let config = "method: srv\noptions: '_db._tcp.example.com'\ntimeout: 2";
+        let config: DiscoveryConfig = serde_yaml::from_str(config).unwrap();
+        let name = "_db._tcp.example.com".to_string();
+        assert_eq!(config.method, DiscoveryMethod::Srv(name));
+        assert_eq!(config.timeout, 2);
+    }
+
+    #[test]
+    fn validate_options() {
+        let config = "method: command\noptions: ['discover']";
+        let config: DiscoveryConfig = serde_yaml::from_str(config).unwrap();
+        assert!(config.validate("discovery").is_ok());
+
+        let zero = DiscoveryConfig {
+            timeout: 0,
+            ..config.clone()
+        };
+        assert!(zero.validate("discovery").is_err());
+
+        let empty = DiscoveryConfig {
+            method: DiscoveryMethod::Command(Vec::new()),
+            ..config
+        };
+        assert!(empty.validate("discovery").is_err());
+    }
+}
@@ -0,0 +1,24 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Agent events configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct EventsConfig {
+    /// Number of events to keep in the store.
+    #[serde(default = "EventsConfig::default_keep")]
+    pub keep: u32,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            keep: Self::default_keep(),
+        }
+    }
+}
+
+impl EventsConfig {
+    fn default_keep() -> u32 {
+        100
+    }
+}
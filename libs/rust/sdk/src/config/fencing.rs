@@ -0,0 +1,97 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Node fencing configuration.
+///
+/// Fencers isolate the datastore node from the rest of the cluster and from clients
+/// when the `replicante.io/node.fence` action is executed (for example by Core while
+/// resolving a split-brain) and restore access with `replicante.io/node.unfence`.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(tag = "fencer", content = "options")]
+pub enum FencingConfig {
+    /// Fence the node through execution of custom commands (such as detaching a cloud NIC).
+    #[serde(rename = "commands")]
+    Commands(CommandsFencer),
+
+    /// Fence the node by rejecting incoming connections to datastore ports with `iptables`.
+    #[serde(rename = "iptables")]
+    Iptables(IptablesFencer),
+
+    /// Fence the node by stopping the systemd unit listening for connections.
+    #[serde(rename = "systemd")]
+    Systemd(SystemdFencer),
+}
+
+impl FencingConfig {
+    /// Validate the fencing configuration.
+    pub fn validate(&self) -> Result<()> {
+        let error = match self {
+            FencingConfig::Commands(options) => {
+                if options.fence.is_empty() {
+                    Some(("fencing.options.fence", "command must not be empty"))
+                } else if options.status.is_empty() {
+                    Some(("fencing.options.status", "command must not be empty"))
+                } else if options.unfence.is_empty() {
+                    Some(("fencing.options.unfence", "command must not be empty"))
+                } else {
+                    None
+                }
+            }
+            FencingConfig::Iptables(options) if options.ports.is_empty() => {
+                Some(("fencing.options.ports", "at least one port is required"))
+            }
+            FencingConfig::Iptables(_) => None,
+            FencingConfig::Systemd(options) if options.unit.is_empty() => {
+                Some(("fencing.options.unit", "unit name must not be empty"))
+            }
+            FencingConfig::Systemd(_) => None,
+        };
+        match error {
+            None => Ok(()),
+            Some((key, error)) => Err(ErrorKind::ConfigInvalid(key, error.to_string()).into()),
+        }
+    }
+}
+
+/// Custom commands fencer configuration options.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct CommandsFencer {
+    /// Command to fence the node.
+    pub fence: Vec<String>,
+
+    /// Command to check if the node is fenced.
+    ///
+    /// The command exits with 0 if the node is fenced and with 1 if it is not.
+    /// Any other exit code is an error.
+    pub status: Vec<String>,
+
+    /// Command to restore access to the node.
+    pub unfence: Vec<String>,
+}
+
+/// Iptables fencer configuration options.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct IptablesFencer {
+    /// Chain to add the fencing rules to.
+    #[serde(default = "IptablesFencer::default_chain")]
+    pub chain: String,
+
+    /// TCP ports to reject incoming connections on.
+    pub ports: Vec<u16>,
+}
+
+impl IptablesFencer {
+    fn default_chain() -> String {
+        "INPUT".into()
+    }
+}
+
+/// Systemd fencer configuration options.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct SystemdFencer {
+    /// Name of the unit (service or socket) listening for connections.
+    pub unit: String,
+}
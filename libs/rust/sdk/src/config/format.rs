@@ -0,0 +1,104 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// Supported configuration file formats.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Detect the format of a configuration file from its extension.
+    ///
+    /// Files with an unknown or no extension are assumed to be YAML.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> ConfigFormat {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        match extension.as_deref() {
+            Some("json") => ConfigFormat::Json,
+            Some("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+
+    /// Decode a configuration object in this format from the given [`std::io::Read`].
+    ///
+    /// [`std::io::Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+    pub fn from_reader<T, R>(self, mut reader: R) -> Result<T>
+    where
+        T: DeserializeOwned,
+        R: Read,
+    {
+        let conf = match self {
+            ConfigFormat::Json => {
+                serde_json::from_reader(reader).with_context(|_| ErrorKind::ConfigLoad)?
+            }
+            ConfigFormat::Toml => {
+                let mut text = String::new();
+                reader
+                    .read_to_string(&mut text)
+                    .with_context(|_| ErrorKind::ConfigLoad)?;
+                toml::from_str(&text).with_context(|_| ErrorKind::ConfigLoad)?
+            }
+            ConfigFormat::Yaml => {
+                serde_yaml::from_reader(reader).with_context(|_| ErrorKind::ConfigLoad)?
+            }
+        };
+        Ok(conf)
+    }
+}
+
+/// Load a configuration file, detecting its format from the file extension.
+///
+/// JSON (`.json`) and TOML (`.toml`) files are supported in addition to YAML.
+pub fn load_file<T, P>(path: P) -> Result<T>
+where
+    T: DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let path_for_error = path.to_str().unwrap_or("<utf8 error>").to_string();
+    let file = File::open(path).with_context(|_| ErrorKind::Io(path_for_error))?;
+    ConfigFormat::from_path(path).from_reader(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::ConfigFormat;
+    use crate::config::Agent;
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(ConfigFormat::from_path("agent.json"), ConfigFormat::Json);
+        assert_eq!(ConfigFormat::from_path("agent.TOML"), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::from_path("agent.yml"), ConfigFormat::Yaml);
+        assert_eq!(ConfigFormat::from_path("agent"), ConfigFormat::Yaml);
+    }
+
+    #[test]
+    fn load_all_formats() {
+        let json = Cursor::new(r#"{"db": "test.db", "heartbeat": {"keep": 3}}"#);
+        let toml = Cursor::new("db = 'test.db'\n[heartbeat]\nkeep = 3\n");
+        let yaml = Cursor::new("{db: test.db, heartbeat: {keep: 3}}");
+        let json: Agent = ConfigFormat::Json.from_reader(json).unwrap();
+        let toml: Agent = ConfigFormat::Toml.from_reader(toml).unwrap();
+        let yaml: Agent = ConfigFormat::Yaml.from_reader(yaml).unwrap();
+        assert_eq!(json.heartbeat.keep, 3);
+        assert_eq!(json, toml);
+        assert_eq!(json, yaml);
+    }
+}
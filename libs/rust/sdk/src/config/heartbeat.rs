@@ -0,0 +1,60 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Agent heartbeat configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct HeartbeatConfig {
+    /// Delay, in seconds, between heartbeats.
+    #[serde(default = "HeartbeatConfig::default_interval")]
+    pub interval: u64,
+
+    /// Number of agent processes to keep heartbeats for.
+    #[serde(default = "HeartbeatConfig::default_keep")]
+    pub keep: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval: Self::default_interval(),
+            keep: Self::default_keep(),
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    fn default_interval() -> u64 {
+        10
+    }
+
+    fn default_keep() -> u32 {
+        10
+    }
+
+    /// Validate the heartbeat options.
+    pub fn validate(&self) -> Result<()> {
+        if self.interval == 0 {
+            let error = "must be at least 1".to_string();
+            return Err(ErrorKind::ConfigInvalid("heartbeat.interval", error).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeartbeatConfig;
+
+    #[test]
+    fn validate_interval() {
+        assert!(HeartbeatConfig::default().validate().is_ok());
+        let config = HeartbeatConfig {
+            interval: 0,
+            ..HeartbeatConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
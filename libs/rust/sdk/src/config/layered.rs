@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Map;
+use serde_json::Value as Json;
+
+use super::effective::set_loaded;
+use super::format::load_file;
+use super::warnings::set_warnings;
+use super::warnings::ConfigWarning;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// Separator between path elements in environment variable names.
+const ENV_SEPARATOR: &str = "__";
+
+/// Source of a configuration value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConfigSource {
+    /// Set on the command line with `--set`.
+    Cli,
+
+    /// Default value, provided by the agent or by the configuration model.
+    Default,
+
+    /// Set by the given environment variable.
+    Env(String),
+
+    /// Set in the given configuration file.
+    File(String),
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigSource::Cli => write!(f, "command line"),
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::Env(name) => write!(f, "env:{}", name),
+            ConfigSource::File(path) => write!(f, "file:{}", path),
+        }
+    }
+}
+
+/// Build a configuration object from layered sources.
+///
+/// Layers are merged in a fixed order regardless of the order they are added in,
+/// with later layers overriding earlier ones: defaults < file < environment < command line.
+/// The source of each value is tracked so it can be reported to users.
+#[derive(Debug, Default)]
+pub struct ConfigLoader {
+    cli: Vec<Json>,
+    defaults: Option<Json>,
+    deprecated: Vec<(String, String)>,
+    env: Vec<(String, Json)>,
+    file: Option<(String, Json)>,
+}
+
+impl ConfigLoader {
+    pub fn new() -> ConfigLoader {
+        ConfigLoader::default()
+    }
+
+    /// Set values on the command line, in the `path.to.option=value` format.
+    ///
+    /// Values are decoded as YAML so numbers, booleans and lists can be set.
+    pub fn cli<I, S>(mut self, overrides: I) -> Result<ConfigLoader>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for item in overrides {
+            let item = item.as_ref();
+            let (path, value) = match item.split_once('=') {
+                Some((path, value)) if !path.is_empty() => (path, value),
+                _ => {
+                    let message = format!("expected 'path.to.option=value' but got '{}'", item);
+                    return Err(ErrorKind::ConfigInvalid("--set", message).into());
+                }
+            };
+            let value = nest(path.split('.'), parse_value(value));
+            self.cli.push(value);
+        }
+        Ok(self)
+    }
+
+    /// Set default values that take precedence over the configuration model defaults.
+    ///
+    /// This replaces the need for agents to change SDK defaults (like the API bind address).
+    pub fn defaults(mut self, defaults: Json) -> ConfigLoader {
+        self.defaults = Some(defaults);
+        self
+    }
+
+    /// Mark an option as deprecated so users setting it are warned.
+    ///
+    /// The note should explain what to do instead of setting the option.
+    pub fn deprecated<P, N>(mut self, path: P, note: N) -> ConfigLoader
+    where
+        P: Into<String>,
+        N: Into<String>,
+    {
+        self.deprecated.push((path.into(), note.into()));
+        self
+    }
+
+    /// Set values from environment variables starting with the given prefix.
+    ///
+    /// Path elements are separated by a double underscore so, with a prefix of
+    /// `REPLIAGENT_`, the `agent.update_checker` option is set by `REPLIAGENT_AGENT__UPDATE_CHECKER`.
+    pub fn env(self, prefix: &str) -> ConfigLoader {
+        self.env_vars(prefix, std::env::vars())
+    }
+
+    /// Set values from the given environment variables, as for `ConfigLoader::env`.
+    pub fn env_vars<I>(mut self, prefix: &str, vars: I) -> ConfigLoader
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut vars: Vec<(String, String)> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(prefix) && name.len() > prefix.len())
+            .collect();
+        vars.sort();
+        for (name, value) in vars {
+            let path = name[prefix.len()..].to_lowercase();
+            let value = nest(path.split(ENV_SEPARATOR), parse_value(&value));
+            self.env.push((name, value));
+        }
+        self
+    }
+
+    /// Set values from a configuration file, in any of the supported formats.
+    pub fn file<P: AsRef<Path>>(mut self, path: P) -> Result<ConfigLoader> {
+        let path = path.as_ref();
+        let value: Json = load_file(path)?;
+        let path = path.to_string_lossy().to_string();
+        self.file = Some((path, value));
+        Ok(self)
+    }
+
+    /// Merge all layers and decode the resulting configuration object.
+    ///
+    /// Unknown and deprecated options are recorded and can be retrieved with
+    /// `config::warnings` to be reported once the logger is configured.
+    /// The merged layers are recorded for `config::effective_config` to report.
+    pub fn load<T: DeserializeOwned>(&self) -> Result<T> {
+        let (config, _) = self.load_recorded()?;
+        Ok(config)
+    }
+
+    /// Merge all layers and decode the resulting configuration object, returning any warnings.
+    pub fn load_with_warnings<T: DeserializeOwned>(&self) -> Result<(T, Vec<ConfigWarning>)> {
+        let (value, sources) = self.merge();
+        self.decode(value, &sources)
+    }
+
+    /// Decode the merged configuration object and collect warnings about its options.
+    fn decode<T: DeserializeOwned>(
+        &self,
+        value: Json,
+        sources: &BTreeMap<String, ConfigSource>,
+    ) -> Result<(T, Vec<ConfigWarning>)> {
+        let mut unknown = Vec::new();
+        let config = serde_ignored::deserialize(value, |path| unknown.push(path.to_string()))
+            .with_context(|_| ErrorKind::ConfigLoad)?;
+
+        let mut warnings = Vec::new();
+        for (path, note) in &self.deprecated {
+            if let Some(source) = source_of(sources, path) {
+                warnings.push(ConfigWarning::Deprecated {
+                    note: note.clone(),
+                    path: path.clone(),
+                    source: source.to_string(),
+                });
+            }
+        }
+        for path in unknown {
+            let source = source_of(sources, &path).unwrap_or(&ConfigSource::Default);
+            let source = source.to_string();
+            warnings.push(ConfigWarning::Unknown { path, source });
+        }
+        Ok((config, warnings))
+    }
+
+    /// Merge all layers once, decode the configuration and record it for `load`.
+    ///
+    /// The source of each option is returned alongside the configuration.
+    fn load_recorded<T: DeserializeOwned>(&self) -> Result<(T, BTreeMap<String, ConfigSource>)> {
+        let (value, sources) = self.merge();
+        let (config, warnings) = self.decode(value.clone(), &sources)?;
+        set_warnings(warnings);
+        set_loaded(value);
+        Ok((config, sources))
+    }
+
+    /// Describe the loaded configuration with the source of each option.
+    ///
+    /// Each line of the output is in the `path.to.option = value  # source` format,
+    /// with values set by none of the layers reported as defaults.
+    pub fn describe<T>(&self) -> Result<String>
+    where
+        T: DeserializeOwned + Serialize,
+    {
+        let (config, sources): (T, _) = self.load_recorded()?;
+        let config = serde_json::to_value(config).with_context(|_| ErrorKind::ConfigLoad)?;
+        let mut leaves = BTreeMap::new();
+        flatten(String::new(), &config, &mut leaves);
+        let mut description = String::new();
+        for (path, value) in leaves {
+            let source = sources.get(&path).unwrap_or(&ConfigSource::Default);
+            description.push_str(&format!("{} = {}  # {}\n", path, value, source));
+        }
+        Ok(description)
+    }
+
+    /// Merge all layers in order and track the source of each leaf value.
+    fn merge(&self) -> (Json, BTreeMap<String, ConfigSource>) {
+        let mut layers = Vec::new();
+        if let Some(defaults) = &self.defaults {
+            layers.push((defaults, ConfigSource::Default));
+        }
+        if let Some((path, value)) = &self.file {
+            layers.push((value, ConfigSource::File(path.clone())));
+        }
+        for (name, value) in &self.env {
+            layers.push((value, ConfigSource::Env(name.clone())));
+        }
+        for value in &self.cli {
+            layers.push((value, ConfigSource::Cli));
+        }
+
+        let mut merged = Json::Object(Map::new());
+        let mut sources = BTreeMap::new();
+        for (layer, source) in layers {
+            let mut leaves = BTreeMap::new();
+            flatten(String::new(), layer, &mut leaves);
+            for path in leaves.keys() {
+                let prefix = format!("{}.", path);
+                sources.retain(|known: &String, _| !known.starts_with(&prefix));
+                sources.insert(path.clone(), source.clone());
+            }
+            merge(&mut merged, layer.clone());
+        }
+        (merged, sources)
+    }
+}
+
+/// Find the source of an option, or of any option nested under it.
+fn source_of<'a>(
+    sources: &'a BTreeMap<String, ConfigSource>,
+    path: &str,
+) -> Option<&'a ConfigSource> {
+    let prefix = format!("{}.", path);
+    sources
+        .iter()
+        .find(|(known, _)| *known == path || known.starts_with(&prefix))
+        .map(|(_, source)| source)
+}
+
+/// Collect the leaf values of a JSON tree, keyed by their dotted path.
+///
+/// Arrays are treated as leaf values as layers replace them entirely.
+fn flatten(path: String, value: &Json, leaves: &mut BTreeMap<String, Json>) {
+    match value {
+        Json::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                flatten(path, value, leaves);
+            }
+        }
+        _ if path.is_empty() => (),
+        _ => {
+            leaves.insert(path, value.clone());
+        }
+    }
+}
+
+/// Recursively merge objects, replacing any other value in `target` with the one in `layer`.
+fn merge(target: &mut Json, layer: Json) {
+    match (target, layer) {
+        (Json::Object(target), Json::Object(layer)) => {
+            for (key, value) in layer {
+                match target.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, layer) => *target = layer,
+    }
+}
+
+/// Wrap a value into nested objects, one for each path element.
+fn nest<'a, I>(path: I, value: Json) -> Json
+where
+    I: DoubleEndedIterator<Item = &'a str>,
+{
+    path.rev().fold(value, |value, key| {
+        let mut map = Map::new();
+        map.insert(key.to_string(), value);
+        Json::Object(map)
+    })
+}
+
+/// Decode a value as YAML, falling back to a plain string.
+fn parse_value(value: &str) -> Json {
+    serde_yaml::from_str(value).unwrap_or_else(|_| Json::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::ConfigLoader;
+    use super::ConfigSource;
+    use crate::config::Agent;
+    use crate::config::ConfigWarning;
+
+    fn env(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn cli_requires_path_and_value() {
+        assert!(ConfigLoader::new().cli(vec!["agent.db"]).is_err());
+        assert!(ConfigLoader::new().cli(vec!["=test"]).is_err());
+    }
+
+    #[test]
+    fn warn_about_deprecated_and_unknown_options() {
+        let loader = ConfigLoader::new()
+            .cli(vec![
+                "db=test.db",
+                "actoins.enabled=true",
+                "heartbeat.keep=3",
+            ])
+            .unwrap()
+            .deprecated("heartbeat", "test deprecation")
+            .deprecated("update_checker", "not set so not reported");
+        let (_, warnings) = loader.load_with_warnings::<Agent>().unwrap();
+        assert_eq!(
+            warnings,
+            vec![
+                ConfigWarning::Deprecated {
+                    note: "test deprecation".into(),
+                    path: "heartbeat".into(),
+                    source: "command line".into(),
+                },
+                ConfigWarning::Unknown {
+                    path: "actoins".into(),
+                    source: "command line".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn layers_precedence() {
+        let loader = ConfigLoader::new()
+            .defaults(json!({"db": "default.db", "update_checker": true}))
+            .env_vars(
+                "TEST_",
+                vec![
+                    env("TEST_DB", "env.db"),
+                    env("TEST_HEARTBEAT__KEEP", "3"),
+                    env("OTHER_DB", "other.db"),
+                ],
+            )
+            .cli(vec!["db=cli.db"])
+            .unwrap();
+        let config: Agent = loader.load().unwrap();
+        assert_eq!(config.db, "cli.db");
+        assert_eq!(config.heartbeat.keep, 3);
+        assert!(config.update_checker);
+
+        let (_, sources) = loader.merge();
+        assert_eq!(sources["db"], ConfigSource::Cli);
+        assert_eq!(
+            sources["heartbeat.keep"],
+            ConfigSource::Env("TEST_HEARTBEAT__KEEP".into())
+        );
+        assert_eq!(sources["update_checker"], ConfigSource::Default);
+    }
+
+    #[test]
+    fn describe_sources() {
+        let loader = ConfigLoader::new()
+            .cli(vec!["db=cli.db", "api.bind=[a, b]"])
+            .unwrap();
+        let description = loader.describe::<Agent>().unwrap();
+        assert!(description.contains("api.bind = [\"a\",\"b\"]  # command line\n"));
+        assert!(description.contains("db = \"cli.db\"  # command line\n"));
+        assert!(description.contains("heartbeat.keep = 10  # default\n"));
+    }
+}
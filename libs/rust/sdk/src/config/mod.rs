@@ -7,17 +7,83 @@ use replicante_logging::Config as LoggingConfig;
 use replicante_logging::LoggingLevel;
 use replicante_util_tracing::Config as TracerConfig;
 
+use crate::metrics::RESERVED_LABELS;
+use crate::ErrorKind;
+use crate::Result;
+
 mod actions;
 mod api;
+mod blocking;
+mod breaker;
+mod client;
+mod clock;
+mod collection;
+mod concurrency;
+mod discovery;
+mod effective;
+mod events;
+mod fencing;
+mod format;
+mod heartbeat;
+mod layered;
+mod pool;
+mod proxy;
+mod sampling;
+mod sandbox;
 mod sentry;
 mod service;
+mod shards;
+mod startup;
+mod updates;
+mod versions;
+mod warnings;
 
 pub use self::actions::ActionsConfig;
+pub use self::actions::ExternalActionChecksums;
 pub use self::actions::ExternalActionConfig;
+pub use self::actions::ExternalActionEnv;
 pub use self::api::APIConfig;
+pub use self::api::APITrees;
+pub use self::api::AuditConfig;
+pub use self::api::BodyLoggingConfig;
+pub use self::api::CorsConfig;
+pub use self::api::DatastoreLogsConfig;
+pub use self::api::RuntimeTreesConfig;
 pub use self::api::TlsConfig;
+pub use self::blocking::BlockingPoolConfig;
+pub use self::breaker::CircuitBreakerConfig;
+pub use self::client::ClientIdentityConfig;
+pub use self::clock::ClockSkewConfig;
+pub use self::collection::CollectionCacheConfig;
+pub use self::concurrency::ConcurrencyLimitConfig;
+pub use self::discovery::DiscoveryConfig;
+pub use self::discovery::DiscoveryMethod;
+pub use self::effective::effective_config;
+pub use self::events::EventsConfig;
+pub use self::fencing::CommandsFencer;
+pub use self::fencing::FencingConfig;
+pub use self::fencing::IptablesFencer;
+pub use self::fencing::SystemdFencer;
+pub use self::format::load_file;
+pub use self::format::ConfigFormat;
+pub use self::heartbeat::HeartbeatConfig;
+pub use self::layered::ConfigLoader;
+pub use self::layered::ConfigSource;
+pub use self::pool::PoolConfig;
+pub use self::proxy::ProxyConfig;
+pub use self::sampling::TraceSamplingConfig;
+pub use self::sampling::TraceSamplingRule;
+pub use self::sandbox::LandlockConfig;
+pub use self::sandbox::SandboxConfig;
 pub use self::sentry::SentryConfig;
 pub use self::service::ServiceConfig;
+pub use self::shards::ShardsConfig;
+pub use self::startup::StartupConfig;
+pub use self::updates::UpdateChannel;
+pub use self::updates::UpdatesConfig;
+pub use self::versions::DatastoreVersionConfig;
+pub use self::warnings::warnings;
+pub use self::warnings::ConfigWarning;
 
 /// Stores the base agent configuration options.
 ///
@@ -37,21 +103,77 @@ pub struct Agent {
     #[serde(default)]
     pub api: APIConfig,
 
+    /// Threads running blocking datastore calls made to serve API requests.
+    #[serde(default)]
+    pub blocking_pool: BlockingPoolConfig,
+
+    /// Circuit breaker around datastore calls (disabled by default).
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+
+    /// How the agent identifies itself to the datastore.
+    #[serde(default)]
+    pub client_identity: ClientIdentityConfig,
+
+    /// Detection of skew between the agent and datastore clocks.
+    #[serde(default)]
+    pub clock_skew: ClockSkewConfig,
+
     /// Override the cluster display name, or set it if none was detected.
     #[serde(default)]
     pub cluster_display_name_override: Option<String>,
 
+    /// Reuse datastore command results across the requests of a collection cycle.
+    #[serde(default)]
+    pub collection_cache: CollectionCacheConfig,
+
+    /// Limit concurrent datastore calls made to serve API requests (disabled by default).
+    #[serde(default)]
+    pub concurrency_limit: Option<ConcurrencyLimitConfig>,
+
+    /// Range of datastore versions the agent is tested against.
+    #[serde(default)]
+    pub datastore_version: DatastoreVersionConfig,
+
     /// Location for the agent to store persistent data.
     pub db: String,
 
+    /// Agent events configuration.
+    #[serde(default)]
+    pub events: EventsConfig,
+
     /// User defined external actions.
     #[serde(default)]
     pub external_actions: BTreeMap<String, ExternalActionConfig>,
 
+    /// Node fencing configuration for the `replicante.io/node.fence` action.
+    #[serde(default)]
+    pub fencing: Option<FencingConfig>,
+
+    /// Agent heartbeat configuration.
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+
     /// Logging configuration.
     #[serde(default)]
     pub logging: LoggingConfig,
 
+    /// Static labels attached to every exported metric.
+    #[serde(default)]
+    pub metrics_labels: BTreeMap<String, String>,
+
+    /// Namespace of the agent in multi-tenant Replicante Core deployments (optional).
+    #[serde(default)]
+    pub namespace: Option<String>,
+
+    /// Proxies for outbound HTTP requests (defaults to the proxy environment variables).
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+
+    /// Harden the agent process once it is running (disabled by default).
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+
     /// Sentry integration configuration.
     #[serde(default)]
     pub sentry: Option<SentryConfig>,
@@ -60,6 +182,18 @@ pub struct Agent {
     #[serde(default)]
     pub service: Option<ServiceConfig>,
 
+    /// Rules to select the shards reported to clients (all shards by default).
+    #[serde(default)]
+    pub shards: ShardsConfig,
+
+    /// Agent startup configuration.
+    #[serde(default)]
+    pub startup: StartupConfig,
+
+    /// Select which API requests are traced (all requests by default).
+    #[serde(default)]
+    pub trace_sampling: TraceSamplingConfig,
+
     /// OpenTracing configuration.
     #[serde(default)]
     pub tracing: TracerConfig,
@@ -67,6 +201,10 @@ pub struct Agent {
     /// Enable the update checker (optional).
     #[serde(default = "Agent::default_update_checker")]
     pub update_checker: bool,
+
+    /// Update checker channel and version pinning.
+    #[serde(default)]
+    pub updates: UpdatesConfig,
 }
 
 impl Agent {
@@ -90,33 +228,181 @@ impl Agent {
         self
     }
 
+    /// Validate the configuration, reporting the first invalid option found.
+    pub fn validate(&self) -> Result<()> {
+        self.actions.validate()?;
+        self.blocking_pool.validate()?;
+        if let Some(breaker) = &self.circuit_breaker {
+            breaker.validate()?;
+        }
+        self.clock_skew.validate()?;
+        if let Some(limit) = &self.concurrency_limit {
+            limit.validate()?;
+        }
+        self.datastore_version.validate()?;
+        if let Some(fencing) = &self.fencing {
+            fencing.validate()?;
+        }
+        self.heartbeat.validate()?;
+        validate_metrics_labels(&self.metrics_labels)?;
+        if let Some(namespace) = &self.namespace {
+            validate_namespace(namespace)?;
+        }
+        self.proxy.validate()?;
+        self.sandbox.validate()?;
+        let external_users = self
+            .external_actions
+            .values()
+            .any(|action| action.user.is_some());
+        if self.sandbox.user.is_some() && external_users {
+            return Err(ErrorKind::ConfigClash(
+                "external actions can't switch user once the agent drops privileges",
+            )
+            .into());
+        }
+        self.shards.validate()?;
+        self.trace_sampling.validate()?;
+        self.updates.validate()?;
+        self.api.validate()
+    }
+
     /// Mock an agent configuration.
     #[cfg(any(test, feature = "with_test_support"))]
     pub fn mock() -> Self {
         Agent {
             actions: ActionsConfig::default(),
             api: APIConfig::default(),
+            blocking_pool: BlockingPoolConfig::default(),
+            circuit_breaker: None,
+            client_identity: ClientIdentityConfig::default(),
+            clock_skew: ClockSkewConfig::default(),
             cluster_display_name_override: None,
+            collection_cache: CollectionCacheConfig::default(),
+            concurrency_limit: None,
+            datastore_version: DatastoreVersionConfig::default(),
             db: "mock.db".into(),
+            events: EventsConfig::default(),
             external_actions: BTreeMap::default(),
+            fencing: None,
+            heartbeat: HeartbeatConfig::default(),
             logging: LoggingConfig::default(),
+            metrics_labels: BTreeMap::new(),
+            namespace: None,
+            proxy: ProxyConfig::default(),
+            sandbox: SandboxConfig::default(),
             sentry: None,
             service: None,
+            shards: ShardsConfig::default(),
+            startup: StartupConfig::default(),
+            trace_sampling: TraceSamplingConfig::default(),
             tracing: TracerConfig::default(),
             update_checker: false,
+            updates: UpdatesConfig::default(),
         }
     }
 }
 
+/// Namespaces are DNS labels: up to 63 lowercase alphanumeric characters or `-`,
+/// starting and ending with an alphanumeric character.
+fn validate_namespace(namespace: &str) -> Result<()> {
+    let valid_chars = namespace
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    let valid = !namespace.is_empty()
+        && namespace.len() <= 63
+        && valid_chars
+        && !namespace.starts_with('-')
+        && !namespace.ends_with('-');
+    if !valid {
+        let error = "must be a DNS label (lowercase alphanumeric characters or '-')";
+        return Err(ErrorKind::ConfigInvalid("namespace", error.into()).into());
+    }
+    Ok(())
+}
+
+/// Ensure static metrics labels are valid Prometheus label names.
+///
+/// Names starting with `__` are reserved for Prometheus internal use and names
+/// used by agent metrics would be exported twice (see `metrics::RESERVED_LABELS`).
+fn validate_metrics_labels(labels: &BTreeMap<String, String>) -> Result<()> {
+    for name in labels.keys() {
+        let mut chars = name.chars();
+        let valid_first = chars
+            .next()
+            .map(|c| c.is_ascii_alphabetic() || c == '_')
+            .unwrap_or(false);
+        let valid_rest = chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_first || !valid_rest || name.starts_with("__") {
+            let error = format!("'{}' is not a valid Prometheus label name", name);
+            return Err(ErrorKind::ConfigInvalid("metrics_labels", error).into());
+        }
+        if RESERVED_LABELS.contains(&name.as_str()) {
+            let error = format!("'{}' is already a label of agent metrics", name);
+            return Err(ErrorKind::ConfigInvalid("metrics_labels", error).into());
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
+    use super::validate_metrics_labels;
+    use super::validate_namespace;
     use super::APIConfig;
     use super::Agent;
+    use super::ExternalActionConfig;
 
     #[test]
     fn override_defauts() {
         APIConfig::set_default_bind(String::from("1.2.3.4:5678"));
         let agent = Agent::mock();
-        assert_eq!(agent.api.bind, "1.2.3.4:5678");
+        assert_eq!(agent.api.bind, vec!["1.2.3.4:5678"]);
+    }
+
+    #[test]
+    fn metrics_labels_names() {
+        let labels = |name: &str| {
+            let mut labels = BTreeMap::new();
+            labels.insert(name.to_string(), "value".to_string());
+            labels
+        };
+        assert!(validate_metrics_labels(&labels("cluster")).is_ok());
+        assert!(validate_metrics_labels(&labels("_team_2")).is_ok());
+        assert!(validate_metrics_labels(&labels("")).is_err());
+        assert!(validate_metrics_labels(&labels("2team")).is_err());
+        assert!(validate_metrics_labels(&labels("team-name")).is_err());
+        assert!(validate_metrics_labels(&labels("__name__")).is_err());
+        assert!(validate_metrics_labels(&labels("operation")).is_err());
+    }
+
+    #[test]
+    fn sandbox_user_clashes_with_external_action_users() {
+        let mut agent = Agent::mock();
+        agent.sandbox.user = Some("nobody".into());
+        assert!(agent.validate().is_ok());
+        let action = ExternalActionConfig {
+            action: vec!["/bin/true".into()],
+            check: vec!["/bin/true".into()],
+            description: "Test action".into(),
+            env: BTreeMap::new(),
+            timeout: None,
+            user: Some("datastore".into()),
+            workdir: None,
+        };
+        agent.external_actions.insert("test".into(), action);
+        let error = agent.validate().unwrap_err();
+        assert_eq!(error.kind().code(), "ConfigClash");
+    }
+
+    #[test]
+    fn namespace_format() {
+        assert!(validate_namespace("tenant-1").is_ok());
+        assert!(validate_namespace("").is_err());
+        assert!(validate_namespace("-tenant").is_err());
+        assert!(validate_namespace("Tenant").is_err());
+        assert!(validate_namespace("tenant_1").is_err());
+        assert!(validate_namespace(&"a".repeat(64)).is_err());
     }
 }
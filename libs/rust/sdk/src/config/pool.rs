@@ -0,0 +1,75 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Datastore client connection pool sizing.
+///
+/// Agents with pooled datastore clients embed this in their datastore options.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct PoolConfig {
+    /// Time, in seconds, idle connections are kept open for (client default if not set).
+    #[serde(default)]
+    pub max_idle_time: Option<u64>,
+
+    /// Maximum number of connections the agent opens to the datastore.
+    #[serde(default = "PoolConfig::default_max_size")]
+    pub max_size: u32,
+
+    /// Minimum number of connections kept open to the datastore.
+    #[serde(default)]
+    pub min_size: u32,
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig {
+            max_idle_time: None,
+            max_size: PoolConfig::default_max_size(),
+            min_size: 0,
+        }
+    }
+}
+
+impl PoolConfig {
+    fn default_max_size() -> u32 {
+        10
+    }
+
+    /// Validate the pool configuration found at the given path.
+    pub fn validate(&self, path: &'static str) -> Result<()> {
+        if self.max_size == 0 {
+            let error = "max_size must be at least 1".to_string();
+            return Err(ErrorKind::ConfigInvalid(path, error).into());
+        }
+        if self.min_size > self.max_size {
+            let error = format!(
+                "min_size ({}) is larger than max_size ({})",
+                self.min_size, self.max_size
+            );
+            return Err(ErrorKind::ConfigInvalid(path, error).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PoolConfig;
+
+    #[test]
+    fn validate_sizes() {
+        assert!(PoolConfig::default().validate("pool").is_ok());
+        let config = PoolConfig {
+            max_size: 0,
+            ..PoolConfig::default()
+        };
+        assert!(config.validate("pool").is_err());
+        let config = PoolConfig {
+            min_size: 20,
+            ..PoolConfig::default()
+        };
+        assert!(config.validate("pool").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Proxies for outbound HTTP requests made by the agent (such as the update checker and Sentry).
+///
+/// Proxies set here take precedence over the `HTTP_PROXY`, `HTTPS_PROXY`
+/// and `NO_PROXY` environment variables, which are used otherwise.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// Proxy URL for plain HTTP requests.
+    #[serde(default)]
+    pub http: Option<String>,
+
+    /// Proxy URL for HTTPS requests.
+    #[serde(default)]
+    pub https: Option<String>,
+
+    /// Hosts, domains (`.example.com`) or IP ranges to reach without a proxy.
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+    /// Check if any proxy is configured, ignoring environment variables.
+    pub fn is_configured(&self) -> bool {
+        self.http.is_some() || self.https.is_some()
+    }
+
+    /// Validate the proxy configuration.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(url) = &self.http {
+            reqwest::Proxy::http(url)
+                .map_err(|error| ErrorKind::ConfigInvalid("proxy.http", error.to_string()))?;
+        }
+        if let Some(url) = &self.https {
+            reqwest::Proxy::https(url)
+                .map_err(|error| ErrorKind::ConfigInvalid("proxy.https", error.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProxyConfig;
+
+    #[test]
+    fn validate_urls() {
+        let config = ProxyConfig::default();
+        assert!(!config.is_configured());
+        assert!(config.validate().is_ok());
+        let config = ProxyConfig {
+            https: Some("http://proxy.example.com:3128".into()),
+            ..ProxyConfig::default()
+        };
+        assert!(config.is_configured());
+        assert!(config.validate().is_ok());
+        let config = ProxyConfig {
+            http: Some("http://bad host:3128".into()),
+            ..ProxyConfig::default()
+        };
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.kind().code(), "ConfigInvalid");
+    }
+}
@@ -0,0 +1,112 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Select which API requests are traced.
+///
+/// Requests carrying a sampling decision from the client (`x-b3-sampled`, single header B3
+/// or W3C `traceparent` flags) follow that decision.
+/// Other requests are sampled according to the first rule with a `path` prefix matching
+/// the request path or, if no rule matches, the default `percent`.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct TraceSamplingConfig {
+    /// Percentage of requests to trace when no rule matches.
+    #[serde(default = "TraceSamplingConfig::default_percent")]
+    pub percent: u8,
+
+    /// Per-endpoint sampling rules, checked in order.
+    #[serde(default = "TraceSamplingConfig::default_rules")]
+    pub rules: Vec<TraceSamplingRule>,
+}
+
+impl Default for TraceSamplingConfig {
+    fn default() -> Self {
+        TraceSamplingConfig {
+            percent: Self::default_percent(),
+            rules: Self::default_rules(),
+        }
+    }
+}
+
+impl TraceSamplingConfig {
+    fn default_percent() -> u8 {
+        100
+    }
+
+    fn default_rules() -> Vec<TraceSamplingRule> {
+        vec![TraceSamplingRule {
+            path: "/api/unstable/actions".into(),
+            percent: 100,
+        }]
+    }
+
+    /// Percentage of requests to the given path that should be traced.
+    pub fn percent_for(&self, path: &str) -> u8 {
+        self.rules
+            .iter()
+            .find(|rule| path.starts_with(&rule.path))
+            .map(|rule| rule.percent)
+            .unwrap_or(self.percent)
+    }
+
+    /// Validate the sampling configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.percent > 100 {
+            let error = "must be between 0 and 100".to_string();
+            return Err(ErrorKind::ConfigInvalid("trace_sampling.percent", error).into());
+        }
+        for rule in &self.rules {
+            if rule.percent > 100 {
+                let error = format!("percent for '{}' must be between 0 and 100", rule.path);
+                return Err(ErrorKind::ConfigInvalid("trace_sampling.rules", error).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sampling percentage for requests to paths starting with a prefix.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct TraceSamplingRule {
+    /// Prefix of the request paths the rule applies to.
+    pub path: String,
+
+    /// Percentage of matching requests to trace.
+    pub percent: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceSamplingConfig;
+    use super::TraceSamplingRule;
+
+    #[test]
+    fn rules_match_in_order() {
+        let config = TraceSamplingConfig {
+            percent: 10,
+            rules: vec![
+                TraceSamplingRule {
+                    path: "/api/unstable/shards".into(),
+                    percent: 1,
+                },
+                TraceSamplingRule {
+                    path: "/api/unstable".into(),
+                    percent: 50,
+                },
+            ],
+        };
+        assert_eq!(config.percent_for("/api/unstable/shards"), 1);
+        assert_eq!(config.percent_for("/api/unstable/info/agent"), 50);
+        assert_eq!(config.percent_for("/metrics"), 10);
+    }
+
+    #[test]
+    fn validate_percent() {
+        let mut config = TraceSamplingConfig::default();
+        assert!(config.validate().is_ok());
+        config.rules[0].percent = 101;
+        assert!(config.validate().is_err());
+    }
+}
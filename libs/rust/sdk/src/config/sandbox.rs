@@ -0,0 +1,68 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Harden the agent process.
+///
+/// The `no_new_privs` and `landlock` restrictions apply from startup while the
+/// `user` and `seccomp` restrictions apply once the API server is listening.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct SandboxConfig {
+    /// Group to switch to along with `user` (defaults to the primary group of `user`).
+    #[serde(default)]
+    pub group: Option<String>,
+
+    /// Limit the files the agent and the commands it runs can access (Linux 5.13+ only).
+    #[serde(default)]
+    pub landlock: Option<LandlockConfig>,
+
+    /// Prevent the agent and the commands it runs from gaining new privileges (Linux only).
+    ///
+    /// Always enabled when `landlock` or `seccomp` are enabled.
+    #[serde(default)]
+    pub no_new_privs: bool,
+
+    /// Deny system calls agents never need, such as loading kernel modules,
+    /// mounting filesystems or tracing other processes (Linux only).
+    #[serde(default)]
+    pub seccomp: bool,
+
+    /// User to switch to, dropping root privileges.
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+impl SandboxConfig {
+    /// Validate the sandbox configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.group.is_some() && self.user.is_none() {
+            let error = "group can only be set along with user".to_string();
+            return Err(ErrorKind::ConfigInvalid("sandbox.group", error).into());
+        }
+        let linux_only = self.no_new_privs || self.seccomp || self.landlock.is_some();
+        if linux_only && !cfg!(target_os = "linux") {
+            let error =
+                "no_new_privs, landlock and seccomp are only supported on Linux".to_string();
+            return Err(ErrorKind::ConfigInvalid("sandbox", error).into());
+        }
+        Ok(())
+    }
+}
+
+/// Filesystem paths the agent can access once Landlock is applied.
+///
+/// Access to a path extends to everything beneath it and any other path is denied.
+/// The agent must be able to reach its configuration, store, logs and certificates
+/// as well as the datastore files and commands used by actions.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct LandlockConfig {
+    /// Paths the agent can read and execute.
+    #[serde(default)]
+    pub read: Vec<String>,
+
+    /// Paths the agent can read, write and execute.
+    #[serde(default)]
+    pub write: Vec<String>,
+}
@@ -0,0 +1,82 @@
+use regex::RegexSet;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Rules to select the shards reported to clients.
+///
+/// Rules are regular expressions matched against shard IDs
+/// (for example `topic/partition` for Kafka or `database.collection` for MongoDB).
+/// Shards are reported if they match any `include` rule (or no `include` rules are
+/// set) and they do not match any `exclude` rule.
+/// Errors about groups of shards are filtered the same way, with `*` in place
+/// of the varying part of their IDs (for example `topic/*` for a Kafka topic).
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ShardsConfig {
+    /// Exclude shards with IDs matching any of these regular expressions.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Only report shards with IDs matching any of these regular expressions.
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    /// Delay, in seconds, between background checks for shard role changes.
+    #[serde(default = "ShardsConfig::default_roles_interval")]
+    pub roles_interval: u64,
+}
+
+impl Default for ShardsConfig {
+    fn default() -> Self {
+        ShardsConfig {
+            exclude: Vec::new(),
+            include: Vec::new(),
+            roles_interval: Self::default_roles_interval(),
+        }
+    }
+}
+
+impl ShardsConfig {
+    fn default_roles_interval() -> u64 {
+        30
+    }
+
+    /// Validate the shard filtering rules and role checks interval.
+    pub fn validate(&self) -> Result<()> {
+        if self.roles_interval == 0 {
+            let error = "must be at least 1 second".to_string();
+            return Err(ErrorKind::ConfigInvalid("shards.roles_interval", error).into());
+        }
+        RegexSet::new(&self.exclude)
+            .map_err(|error| ErrorKind::ConfigInvalid("shards.exclude", error.to_string()))?;
+        RegexSet::new(&self.include)
+            .map_err(|error| ErrorKind::ConfigInvalid("shards.include", error.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShardsConfig;
+
+    #[test]
+    fn validate_rules() {
+        let config = ShardsConfig {
+            exclude: vec!["^__consumer_offsets/".into()],
+            ..ShardsConfig::default()
+        };
+        assert!(config.validate().is_ok());
+        let config = ShardsConfig {
+            include: vec!["(unclosed".into()],
+            ..ShardsConfig::default()
+        };
+        assert!(config.validate().is_err());
+        let config = ShardsConfig {
+            roles_interval: 0,
+            ..ShardsConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,36 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Agent startup configuration.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct StartupConfig {
+    /// Maximum delay, in seconds, between attempts to reach the datastore.
+    #[serde(default = "StartupConfig::default_max_retry_delay")]
+    pub max_retry_delay: u64,
+
+    /// Time, in seconds, to wait for the datastore to respond before the agent starts.
+    ///
+    /// Only the connection to the datastore is retried (see `process::wait_for_datastore`).
+    /// Set to 0 to start without waiting and fail on the first connection error.
+    #[serde(default = "StartupConfig::default_wait_for_datastore")]
+    pub wait_for_datastore: u64,
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        StartupConfig {
+            max_retry_delay: Self::default_max_retry_delay(),
+            wait_for_datastore: Self::default_wait_for_datastore(),
+        }
+    }
+}
+
+impl StartupConfig {
+    fn default_max_retry_delay() -> u64 {
+        30
+    }
+
+    fn default_wait_for_datastore() -> u64 {
+        0
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+
+use semver::Version;
+use semver::VersionReq;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Release channel to look for updates in.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum UpdateChannel {
+    /// Consider pre-release versions as well as stable ones.
+    #[serde(rename = "beta")]
+    Beta,
+
+    /// Consider stable versions only.
+    #[serde(rename = "stable")]
+    Stable,
+}
+
+impl Default for UpdateChannel {
+    fn default() -> Self {
+        UpdateChannel::Stable
+    }
+}
+
+impl fmt::Display for UpdateChannel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateChannel::Beta => write!(f, "beta"),
+            UpdateChannel::Stable => write!(f, "stable"),
+        }
+    }
+}
+
+/// Update checker configuration.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct UpdatesConfig {
+    /// Release channel to look for updates in.
+    #[serde(default)]
+    pub channel: UpdateChannel,
+
+    /// Seconds between update checks, if unset updates are checked once at startup.
+    #[serde(default)]
+    pub interval: Option<u64>,
+
+    /// Only report updates matching this semver requirement (for example `~0.7`).
+    #[serde(default)]
+    pub pin: Option<String>,
+
+    /// URL or local file path to fetch version metadata from instead of the agent default.
+    ///
+    /// Useful for deployments without internet access that can serve the metadata
+    /// from an internal mirror or ship it alongside the agent.
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+impl UpdatesConfig {
+    /// Check if a released version is a candidate update for the configured channel and pin.
+    ///
+    /// The pin is assumed valid: unparsable pins are rejected by `validate`.
+    pub fn accepts(&self, version: &Version) -> bool {
+        if self.channel == UpdateChannel::Stable && !version.pre.is_empty() {
+            return false;
+        }
+        match self.pin.as_deref().map(VersionReq::parse) {
+            Some(Ok(pin)) => pin.matches(version),
+            _ => true,
+        }
+    }
+
+    /// Validate the update checker configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.interval == Some(0) {
+            let error = "must be at least 1 second".to_string();
+            return Err(ErrorKind::ConfigInvalid("updates.interval", error).into());
+        }
+        if let Some(pin) = &self.pin {
+            VersionReq::parse(pin)
+                .map_err(|error| ErrorKind::ConfigInvalid("updates.pin", error.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+
+    use super::UpdateChannel;
+    use super::UpdatesConfig;
+
+    #[test]
+    fn accepts_channel() {
+        let beta = Version::parse("0.8.0-beta.1").unwrap();
+        let stable = Version::parse("0.8.0").unwrap();
+        let config = UpdatesConfig::default();
+        assert!(config.accepts(&stable));
+        assert!(!config.accepts(&beta));
+        let config = UpdatesConfig {
+            channel: UpdateChannel::Beta,
+            ..UpdatesConfig::default()
+        };
+        assert!(config.accepts(&stable));
+        assert!(config.accepts(&beta));
+    }
+
+    #[test]
+    fn accepts_pin() {
+        let config = UpdatesConfig {
+            pin: Some("~0.7".into()),
+            ..UpdatesConfig::default()
+        };
+        assert!(config.accepts(&Version::parse("0.7.4").unwrap()));
+        assert!(!config.accepts(&Version::parse("0.8.0").unwrap()));
+        assert!(config.validate().is_ok());
+        let config = UpdatesConfig {
+            pin: Some("not a version".into()),
+            ..UpdatesConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_interval() {
+        let config = UpdatesConfig {
+            interval: Some(3600),
+            ..UpdatesConfig::default()
+        };
+        assert!(config.validate().is_ok());
+        let config = UpdatesConfig {
+            interval: Some(0),
+            ..UpdatesConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use semver::Version;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::parse_version;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Range of datastore versions the agent was tested against.
+///
+/// Agents keep running against versions outside of the range but report
+/// a warning so operators know they are running an untested combination.
+#[derive(Clone, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct DatastoreVersionConfig {
+    /// First unsupported datastore version (exclusive upper bound).
+    #[serde(default)]
+    pub max: Option<String>,
+
+    /// Oldest supported datastore version (inclusive lower bound).
+    #[serde(default)]
+    pub min: Option<String>,
+
+    /// Interval, in seconds, to re-detect the datastore version in the background.
+    ///
+    /// Only used by `VersionedAgent`s, which otherwise re-detect the version on requests.
+    #[serde(default)]
+    pub redetect_interval: Option<u64>,
+}
+
+impl DatastoreVersionConfig {
+    /// Check a detected datastore version against the supported range.
+    ///
+    /// Returns a warning message if the version is outside the range or can't be parsed.
+    pub fn check(&self, version: &str) -> Option<String> {
+        if self.max.is_none() && self.min.is_none() {
+            return None;
+        }
+        let detected = match parse_version(version) {
+            Ok(detected) => detected,
+            Err(_) => {
+                return Some(format!(
+                    "unable to check unparsable datastore version {}",
+                    version
+                ))
+            }
+        };
+        let min = self.min.as_deref().and_then(|min| Version::parse(min).ok());
+        if let Some(min) = min {
+            if detected < min {
+                return Some(format!(
+                    "datastore version {} is older than the oldest supported version {}",
+                    detected, min
+                ));
+            }
+        }
+        let max = self.max.as_deref().and_then(|max| Version::parse(max).ok());
+        if let Some(max) = max {
+            if detected >= max {
+                return Some(format!(
+                    "datastore version {} is not older than the first unsupported version {}",
+                    detected, max
+                ));
+            }
+        }
+        None
+    }
+
+    /// Validate the supported versions range.
+    pub fn validate(&self) -> Result<()> {
+        let min = match &self.min {
+            None => None,
+            Some(min) => Some(Version::parse(min).map_err(|error| {
+                ErrorKind::ConfigInvalid("datastore_version.min", error.to_string())
+            })?),
+        };
+        let max = match &self.max {
+            None => None,
+            Some(max) => Some(Version::parse(max).map_err(|error| {
+                ErrorKind::ConfigInvalid("datastore_version.max", error.to_string())
+            })?),
+        };
+        if let Some(0) = self.redetect_interval {
+            let error = "must be greater than 0".to_string();
+            return Err(
+                ErrorKind::ConfigInvalid("datastore_version.redetect_interval", error).into(),
+            );
+        }
+        if let (Some(min), Some(max)) = (min, max) {
+            if min >= max {
+                let error = "must be greater than datastore_version.min".to_string();
+                return Err(ErrorKind::ConfigInvalid("datastore_version.max", error).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DatastoreVersionConfig;
+
+    fn config() -> DatastoreVersionConfig {
+        DatastoreVersionConfig {
+            max: Some("4.0.0".into()),
+            min: Some("3.2.0".into()),
+            redetect_interval: None,
+        }
+    }
+
+    #[test]
+    fn check_in_range() {
+        assert_eq!(config().check("3.2.0"), None);
+        assert_eq!(config().check("3.6.8"), None);
+        assert_eq!(config().check("3.6.8-ent"), None);
+        assert_eq!(DatastoreVersionConfig::default().check("junk"), None);
+    }
+
+    #[test]
+    fn check_out_of_range() {
+        assert!(config().check("3.0.15").is_some());
+        assert!(config().check("4.0.0").is_some());
+        assert!(config().check("junk").is_some());
+    }
+
+    #[test]
+    fn validate_range() {
+        assert!(config().validate().is_ok());
+        let config = DatastoreVersionConfig {
+            max: Some("3.0.0".into()),
+            ..config()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_redetect_interval() {
+        let config = DatastoreVersionConfig {
+            redetect_interval: Some(60),
+            ..config()
+        };
+        assert!(config.validate().is_ok());
+        let config = DatastoreVersionConfig {
+            redetect_interval: Some(0),
+            ..config
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use slog::warn;
+use slog::Logger;
+
+lazy_static! {
+    /// Warnings about the configuration loaded by the agent process.
+    static ref WARNINGS: RwLock<Vec<ConfigWarning>> = RwLock::new(Vec::new());
+}
+
+/// Issue detected while loading the configuration that does not prevent the agent from running.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ConfigWarning {
+    /// The option is deprecated and may be removed in future versions.
+    Deprecated {
+        note: String,
+        path: String,
+        source: String,
+    },
+
+    /// The option is not known to the agent and was ignored.
+    Unknown { path: String, source: String },
+}
+
+impl ConfigWarning {
+    /// Log the warning with structured details.
+    pub fn log(&self, logger: &Logger) {
+        match self {
+            ConfigWarning::Deprecated { note, path, source } => warn!(
+                logger,
+                "Deprecated configuration option set";
+                "note" => note,
+                "path" => path,
+                "source" => source,
+            ),
+            ConfigWarning::Unknown { path, source } => warn!(
+                logger,
+                "Unknown configuration option ignored";
+                "path" => path,
+                "source" => source,
+            ),
+        }
+    }
+}
+
+/// Warnings detected while loading the agent configuration.
+pub fn warnings() -> Vec<ConfigWarning> {
+    WARNINGS
+        .read()
+        .expect("config warnings lock poisoned")
+        .clone()
+}
+
+/// Replace the warnings about the loaded agent configuration.
+pub(crate) fn set_warnings(warnings: Vec<ConfigWarning>) {
+    *WARNINGS.write().expect("config warnings lock poisoned") = warnings;
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::sync::Arc;
 
@@ -9,14 +10,29 @@ use slog::o;
 use slog::Discard;
 use slog::Logger;
 
+#[cfg(feature = "api")]
 use replicante_util_actixweb::AppConfig;
-use replicante_util_tracing::MaybeTracer;
 
+#[cfg(feature = "store")]
+use crate::actions::ActionsProgress;
+#[cfg(feature = "store")]
+use crate::actions::ActionsWake;
+#[cfg(feature = "api")]
 use crate::api::APIContext;
+use crate::blocking::BlockingPool;
+use crate::breaker::CircuitBreaker;
+use crate::collection::CollectionCache;
 use crate::config::Agent as AgentConfig;
+use crate::limiter::ConcurrencyLimiter;
+use crate::shards::RoleTracker;
+use crate::shards::ShardFilter;
+#[cfg(feature = "store")]
 use crate::store::backend_factory;
+#[cfg(feature = "store")]
 use crate::store::Store;
+use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
 /// Agent services injection.
 ///
@@ -26,18 +42,46 @@ use crate::Result;
 // Any new field must be added to the implementation of Debug.
 #[derive(Clone)]
 pub struct AgentContext {
+    /// Notify API long-poll requests that actions made progress.
+    #[cfg(feature = "store")]
+    pub actions_progress: ActionsProgress,
+
+    /// Notify the actions engine of newly scheduled actions.
+    #[cfg(feature = "store")]
+    pub actions_wake: ActionsWake,
+    #[cfg(feature = "api")]
     pub api_conf: AppConfig<APIContext>,
+
+    /// Datastore command results shared across the requests of a collection cycle.
+    pub collection_cache: CollectionCache,
     pub config: AgentConfig,
+
+    /// Threads running blocking datastore calls made to serve API requests.
+    pub datastore_pool: BlockingPool,
+
+    /// Circuit breaker around datastore calls made to serve API requests.
+    pub datastore_breaker: CircuitBreaker,
+
+    /// Limit concurrent datastore calls made to serve API requests.
+    pub datastore_limiter: ConcurrencyLimiter,
     pub logger: Logger,
 
     /// Access the agent's metrics [`Registry`].
     ///
     /// Agents MUST register their metrics at creation time and as part of the same [`Registry`].
+    /// Labels from the `metrics_labels` option are attached to all metrics when they are gathered.
     ///
     /// [`Registry`]: https://docs.rs/prometheus/0.3.13/prometheus/struct.Registry.html
     pub metrics: Registry,
 
+    /// Select the shards reported to clients.
+    pub shard_filter: ShardFilter,
+
+    /// Roles of the shards on the node as of the latest collection.
+    pub shard_roles: RoleTracker,
+
     /// Access the agent's persistent store.
+    #[cfg(feature = "store")]
     pub store: Store,
 
     /// Access the agent's [`Tracer`].
@@ -50,30 +94,66 @@ pub struct AgentContext {
 
 impl fmt::Debug for AgentContext {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("AgentContext")
+        let mut debug = f.debug_struct("AgentContext");
+        #[cfg(feature = "store")]
+        debug
+            .field("actions_progress", &self.actions_progress)
+            .field("actions_wake", &self.actions_wake);
+        debug
+            .field("collection_cache", &"<CollectionCache>")
             .field("config", &self.config)
+            .field("datastore_breaker", &self.datastore_breaker.state())
+            .field("datastore_limiter", &"<ConcurrencyLimiter>")
+            .field("datastore_pool", &"<BlockingPool>")
             .field("logger", &self.logger)
             .field("metrics", &"<Registry>")
-            .field("store", &"<Store>")
-            .field("tracer", &"<Tracer>")
-            .finish()
+            .field("shard_filter", &self.shard_filter)
+            .field("shard_roles", &"<RoleTracker>");
+        #[cfg(feature = "store")]
+        debug.field("store", &"<Store>");
+        debug.field("tracer", &"<Tracer>").finish()
     }
 }
 
 impl AgentContext {
     pub fn new(config: AgentConfig, logger: Logger, tracer: Tracer) -> Result<AgentContext> {
-        let metrics = Registry::new();
+        let labels: HashMap<String, String> = config
+            .metrics_labels
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let labels = if labels.is_empty() {
+            None
+        } else {
+            Some(labels)
+        };
+        let metrics = Registry::new_custom(None, labels)
+            .with_context(|error| ErrorKind::ConfigInvalid("metrics_labels", error.to_string()))?;
         let tracer = Arc::new(tracer);
-        let store = backend_factory(
-            &config,
-            logger.clone(),
-            MaybeTracer::new(Arc::clone(&tracer)),
-        )?;
+        #[cfg(feature = "store")]
+        let store = backend_factory(&config, logger.clone(), Arc::clone(&tracer))?;
+        let collection_cache = CollectionCache::new(&config.collection_cache);
+        let datastore_breaker = CircuitBreaker::new("datastore", config.circuit_breaker.clone());
+        let datastore_limiter = ConcurrencyLimiter::new(config.concurrency_limit.clone());
+        let datastore_pool = BlockingPool::new(&config.blocking_pool, Arc::clone(&tracer))?;
+        let shard_filter = ShardFilter::new(&config.shards)?;
         Ok(AgentContext {
+            #[cfg(feature = "store")]
+            actions_progress: ActionsProgress::default(),
+            #[cfg(feature = "store")]
+            actions_wake: ActionsWake::default(),
+            #[cfg(feature = "api")]
             api_conf: AppConfig::default(),
+            collection_cache,
             config,
+            datastore_breaker,
+            datastore_limiter,
+            datastore_pool,
             logger,
             metrics,
+            shard_filter,
+            shard_roles: RoleTracker::default(),
+            #[cfg(feature = "store")]
             store,
             tracer,
         })
@@ -89,17 +169,36 @@ impl AgentContext {
         let mut upkeep = ::replicante_util_upkeep::Upkeep::new();
         let logger = Logger::root(Discard, o!());
         let metrics = Registry::new();
+        #[cfg(feature = "store")]
         let store = Store::mock();
         let opts = ::replicante_util_tracing::Opts::new("test", logger.clone(), &mut upkeep);
         let tracer =
             ::replicante_util_tracing::tracer(::replicante_util_tracing::Config::Noop, opts)
                 .unwrap();
         let tracer = Arc::new(tracer);
+        let collection_cache = CollectionCache::new(&config.collection_cache);
+        let datastore_breaker = CircuitBreaker::new("datastore", config.circuit_breaker.clone());
+        let datastore_limiter = ConcurrencyLimiter::new(config.concurrency_limit.clone());
+        let datastore_pool = BlockingPool::new(&config.blocking_pool, Arc::clone(&tracer))
+            .expect("failed to start blocking pool in mock");
+        let shard_filter = ShardFilter::new(&config.shards).expect("invalid shards config in mock");
         AgentContext {
+            #[cfg(feature = "store")]
+            actions_progress: ActionsProgress::default(),
+            #[cfg(feature = "store")]
+            actions_wake: ActionsWake::default(),
+            #[cfg(feature = "api")]
             api_conf: AppConfig::default(),
+            collection_cache,
             config,
+            datastore_breaker,
+            datastore_limiter,
+            datastore_pool,
             logger,
             metrics,
+            shard_filter,
+            shard_roles: RoleTracker::default(),
+            #[cfg(feature = "store")]
             store,
             tracer,
         }
@@ -0,0 +1,175 @@
+//! Request deadlines propagated from API clients to agent collectors.
+//!
+//! Clients can set the `X-Request-Deadline` header to tell the agent when they
+//! will stop waiting for a response, either as an RFC3339 timestamp or as a number
+//! of milliseconds from when the request is received.
+//!
+//! The deadline of the request being served is available to `Agent` methods
+//! on the thread serving it: collectors should `check` it before starting expensive
+//! operations and can use the `remaining` time to bound datastore requests.
+use std::cell::Cell;
+use std::time::Duration;
+use std::time::Instant;
+
+#[cfg(feature = "api")]
+use actix_web::HttpRequest;
+use chrono::DateTime;
+use chrono::Utc;
+
+use crate::ErrorKind;
+use crate::Result;
+
+/// Name of the HTTP header clients set the deadline with.
+pub const HEADER: &str = "x-request-deadline";
+
+thread_local! {
+    /// Deadline of the request being served by the current thread, if any.
+    static CURRENT: Cell<Option<Deadline>> = Cell::new(None);
+}
+
+/// Point in time after which the result of an operation is no longer useful.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Deadline {
+    at: Instant,
+}
+
+impl Deadline {
+    /// Deadline expiring after the given duration.
+    pub fn after(duration: Duration) -> Deadline {
+        Deadline {
+            at: Instant::now() + duration,
+        }
+    }
+
+    /// Parse a deadline header value.
+    ///
+    /// Timestamps in the past result in deadlines that have already expired.
+    pub fn parse(value: &str) -> Option<Deadline> {
+        let value = value.trim();
+        if let Ok(millis) = value.parse::<u64>() {
+            return Some(Deadline::after(Duration::from_millis(millis)));
+        }
+        let at = DateTime::parse_from_rfc3339(value).ok()?;
+        let remaining = at
+            .with_timezone(&Utc)
+            .signed_duration_since(Utc::now())
+            .to_std()
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        Some(Deadline::after(remaining))
+    }
+
+    /// Extract the deadline from an API request, ignoring invalid headers.
+    #[cfg(feature = "api")]
+    pub(crate) fn from_request(request: &HttpRequest) -> Option<Deadline> {
+        request
+            .headers()
+            .get(HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Deadline::parse)
+    }
+
+    /// Fail with `ErrorKind::DeadlineExceeded` if the deadline has expired.
+    pub fn check(&self, operation: &'static str) -> Result<()> {
+        if self.expired() {
+            return Err(ErrorKind::DeadlineExceeded(operation).into());
+        }
+        Ok(())
+    }
+
+    /// Check if the deadline has expired.
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    /// Time left before the deadline expires.
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Fail with `ErrorKind::DeadlineExceeded` if the current request deadline has expired.
+///
+/// Operations performed outside of requests with deadlines always pass the check.
+pub fn check(operation: &'static str) -> Result<()> {
+    match current() {
+        None => Ok(()),
+        Some(deadline) => deadline.check(operation),
+    }
+}
+
+/// Deadline of the request being served by the current thread, if any.
+pub fn current() -> Option<Deadline> {
+    CURRENT.with(Cell::get)
+}
+
+/// Time left before the current request deadline expires, if one is set.
+pub fn remaining() -> Option<Duration> {
+    current().map(|deadline| deadline.remaining())
+}
+
+/// Run an operation with the given deadline set for the current thread.
+///
+/// The deadline is checked before and after the operation so clients that
+/// have already given up receive an error instead of a late response.
+/// Operations that fail once the deadline has expired, for example because datastore
+/// requests were bounded by the remaining time, fail with `ErrorKind::DeadlineExceeded`.
+pub(crate) fn run<T, F>(deadline: Option<Deadline>, operation: &'static str, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    let deadline = match deadline {
+        None => return f(),
+        Some(deadline) => deadline,
+    };
+    deadline.check(operation)?;
+    let previous = CURRENT.with(|current| current.replace(Some(deadline)));
+    let result = f();
+    CURRENT.with(|current| current.set(previous));
+    deadline.check(operation)?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Deadline;
+    use crate::ErrorKind;
+
+    #[test]
+    fn parse_formats() {
+        let deadline = Deadline::parse("5000").unwrap();
+        assert!(!deadline.expired());
+        assert!(deadline.remaining() <= Duration::from_millis(5000));
+        let deadline = Deadline::parse("2020-01-01T00:00:00Z").unwrap();
+        assert!(deadline.expired());
+        assert_eq!(Deadline::parse("soon"), None);
+    }
+
+    #[test]
+    fn run_sets_current() {
+        let deadline = Deadline::after(Duration::from_secs(60));
+        let current = super::run(Some(deadline), "test", || Ok(super::current())).unwrap();
+        assert_eq!(current, Some(deadline));
+        assert_eq!(super::current(), None);
+    }
+
+    #[test]
+    fn run_failed_after_deadline() {
+        let deadline = Deadline::after(Duration::from_millis(10));
+        let result: crate::Result<()> = super::run(Some(deadline), "test", || {
+            std::thread::sleep(Duration::from_millis(20));
+            Err(ErrorKind::StoreOpFailed("test").into())
+        });
+        let error = result.unwrap_err();
+        assert_eq!(error.kind().code(), "DeadlineExceeded");
+    }
+
+    #[test]
+    fn run_expired() {
+        let deadline = Deadline::after(Duration::from_secs(0));
+        let result = super::run(Some(deadline), "test", || Ok(()));
+        let error = result.unwrap_err();
+        assert_eq!(error.kind().code(), "DeadlineExceeded");
+    }
+}
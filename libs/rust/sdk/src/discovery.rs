@@ -0,0 +1,148 @@
+//! Datastore endpoint discovery.
+use std::process::Command;
+use std::process::Stdio;
+use std::time::Duration;
+
+use slog::debug;
+use slog::Logger;
+use trust_dns_resolver::Resolver;
+
+use crate::command::spawn_group;
+use crate::command::wait_with_timeout;
+use crate::config::DiscoveryConfig;
+use crate::config::DiscoveryMethod;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// Resolve the address ("host:port") of the datastore node based on the configuration.
+pub fn resolve(config: &DiscoveryConfig, logger: &Logger) -> Result<String> {
+    let address = match &config.method {
+        DiscoveryMethod::Command(command) => resolve_command(command, config.timeout)?,
+        DiscoveryMethod::Srv(name) => resolve_srv(name)?,
+    };
+    debug!(logger, "Discovered datastore address"; "address" => &address);
+    Ok(address)
+}
+
+/// Run the discovery command and use the first non-empty line it outputs.
+///
+/// Commands that do not exit within the timeout are killed and discovery fails.
+fn resolve_command(command: &[String], timeout: u64) -> Result<String> {
+    let cmd = command
+        .get(0)
+        .ok_or(ErrorKind::ConfigOption("discovery.options"))?;
+    if timeout == 0 {
+        let error = "discovery commands must be allowed to run for at least 1 second";
+        return Err(ErrorKind::ConfigInvalid("discovery.timeout", error.into()).into());
+    }
+    let child = spawn_group(
+        Command::new(cmd)
+            .args(&command[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )
+    .with_context(|_| ErrorKind::Discovery(format!("unable to execute {}", cmd)))?;
+    let (output, timed_out) = wait_with_timeout(child, Duration::from_secs(timeout))
+        .with_context(|_| ErrorKind::Discovery(format!("unable to execute {}", cmd)))?;
+    if timed_out {
+        let error = format!("command {} did not exit within {} seconds", cmd, timeout);
+        return Err(ErrorKind::Discovery(error).into());
+    }
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let error = format!("command {} failed: {}", cmd, stderr.trim());
+        return Err(ErrorKind::Discovery(error).into());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    first_line(&stdout).ok_or_else(|| {
+        let error = format!("command {} did not output an address", cmd);
+        ErrorKind::Discovery(error).into()
+    })
+}
+
+/// Lookup SRV records and select the preferred target.
+fn resolve_srv(name: &str) -> Result<String> {
+    let resolver = Resolver::from_system_conf()
+        .with_context(|_| ErrorKind::Discovery("unable to configure DNS resolver".into()))?;
+    let records = resolver
+        .srv_lookup(name)
+        .with_context(|_| ErrorKind::Discovery(format!("SRV lookup for {} failed", name)))?;
+    let records = records.iter().map(|record| {
+        let target = record.target().to_utf8();
+        let target = target.trim_end_matches('.').to_string();
+        (record.priority(), record.weight(), target, record.port())
+    });
+    select_srv(records).ok_or_else(|| {
+        let error = format!("no SRV records found for {}", name);
+        ErrorKind::Discovery(error).into()
+    })
+}
+
+/// Return the first non-empty line of the given text, trimmed.
+fn first_line(text: &str) -> Option<String> {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(String::from)
+}
+
+/// Select the SRV record with the lowest priority and the highest weight.
+fn select_srv<I>(records: I) -> Option<String>
+where
+    I: Iterator<Item = (u16, u16, String, u16)>,
+{
+    records
+        .min_by(|(p1, w1, _, _), (p2, w2, _, _)| p1.cmp(p2).then(w2.cmp(w1)))
+        .map(|(_, _, target, port)| format!("{}:{}", target, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::first_line;
+    use super::resolve_command;
+    use super::select_srv;
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn command_output() {
+        let command = command(&["echo", "db1.example.com:27017"]);
+        let address = resolve_command(&command, 5).unwrap();
+        assert_eq!(address, "db1.example.com:27017");
+    }
+
+    #[test]
+    fn command_timeout() {
+        let command = command(&["sleep", "10"]);
+        let error = resolve_command(&command, 1).unwrap_err();
+        assert!(error.to_string().contains("did not exit within 1 seconds"));
+    }
+
+    #[test]
+    fn first_line_skips_empty() {
+        let line = first_line("\n  \n  db1.example.com:27017  \nother\n");
+        assert_eq!(line, Some("db1.example.com:27017".into()));
+        assert_eq!(first_line("  \n"), None);
+    }
+
+    #[test]
+    fn select_srv_prefers_priority_then_weight() {
+        let records = vec![
+            (20, 100, "c.example.com".to_string(), 3),
+            (10, 5, "a.example.com".to_string(), 1),
+            (10, 50, "b.example.com".to_string(), 2),
+        ];
+        let address = select_srv(records.into_iter());
+        assert_eq!(address, Some("b.example.com:2".into()));
+    }
+
+    #[test]
+    fn select_srv_empty() {
+        let address = select_srv(Vec::new().into_iter());
+        assert_eq!(address, None);
+    }
+}
@@ -1,218 +1,783 @@
+use std::error::Error as StdError;
 use std::fmt;
+use std::sync::Arc;
 
+#[cfg(feature = "api")]
 use actix_web::http::StatusCode;
+#[cfg(feature = "api")]
 use actix_web::HttpResponse;
+#[cfg(feature = "api")]
 use actix_web::ResponseError;
 use failure::Backtrace;
-use failure::Context;
 use failure::Fail;
+use opentracingrust::Span;
+use serde::Serialize;
 use uuid::Uuid;
 
 use replicante_util_failure::SerializableFail;
 
 /// Error information returned by functions in case of errors.
+///
+/// Errors have an `ErrorKind` and, optionally, the error that caused them
+/// as their `std::error::Error::source`.
 #[derive(Debug)]
-pub struct Error(Context<ErrorKind>);
+pub struct Error {
+    backtrace: Arc<Backtrace>,
+    kind: ErrorKind,
+    source: Option<Box<dyn StdError + Send + Sync>>,
+}
 
 impl Error {
-    pub fn kind(&self) -> &ErrorKind {
-        self.0.get_context()
+    /// Wrap the error that caused an operation to fail in an error of the given kind.
+    pub fn with_source<K, E>(kind: K, source: E) -> Error
+    where
+        K: Into<ErrorKind>,
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let mut error = Error::from(kind.into());
+        error.source = Some(source.into());
+        error
     }
-}
 
-impl Fail for Error {
-    fn cause(&self) -> Option<&dyn Fail> {
-        self.0.cause()
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
     }
 
-    fn backtrace(&self) -> Option<&Backtrace> {
-        self.0.backtrace()
+    /// View the error and its sources as a `failure::Fail`.
+    ///
+    /// Reporting utilities shared with other replicante crates (`replicante_util_failure`
+    /// and `replicante_util_tracing`) still work with `failure::Fail`s.
+    /// `Error` is a `Fail` through the `failure` blanket implementation for
+    /// `std::error::Error`s but that hides the error sources from these utilities.
+    pub fn to_fail(&self) -> FailChain {
+        FailChain {
+            backtrace: Some(Arc::clone(&self.backtrace)),
+            cause: self
+                .source
+                .as_deref()
+                .map(|source| Box::new(FailChain::new(source))),
+            message: self.kind.to_string(),
+            name: Some(self.kind.code()),
+        }
     }
 
-    fn name(&self) -> Option<&str> {
-        self.kind().kind_name()
+    /// Build the API response for the error, tagged with the given correlation ID.
+    #[cfg(feature = "api")]
+    pub fn api_response(&self, correlation_id: Option<String>) -> HttpResponse {
+        let kind = self.kind();
+        let body = ErrorResponse {
+            category: kind.category(),
+            code: kind.code(),
+            correlation_id,
+            info: SerializableFail::from(&self.to_fail()),
+            retryable: kind.retryable(),
+        };
+        HttpResponse::build(kind.http_status()).json(body)
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(&self.0, f)
+        fmt::Display::fmt(&self.kind, f)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn StdError + 'static))
     }
 }
 
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Error {
-        Error(Context::new(kind))
+        Error {
+            backtrace: Arc::new(Backtrace::new()),
+            kind,
+            source: None,
+        }
     }
 }
 
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Error {
+        let message = format!("{:#}", error);
+        Error::from(ErrorKind::FreeForm(message))
+    }
+}
+
+#[cfg(feature = "api")]
 impl ResponseError for Error {
     fn status_code(&self) -> StatusCode {
         self.kind().http_status()
     }
 
     fn error_response(&self) -> HttpResponse {
-        let info = SerializableFail::from(self);
-        let status = self.status_code();
-        HttpResponse::build(status).json(info)
+        self.api_response(None)
+    }
+}
+
+/// `failure::Fail` view of an `Error` and its sources, see `Error::to_fail`.
+#[derive(Debug)]
+pub struct FailChain {
+    backtrace: Option<Arc<Backtrace>>,
+    cause: Option<Box<FailChain>>,
+    message: String,
+    name: Option<&'static str>,
+}
+
+impl FailChain {
+    fn new(error: &(dyn StdError + 'static)) -> FailChain {
+        if let Some(error) = error.downcast_ref::<Error>() {
+            return error.to_fail();
+        }
+        FailChain {
+            backtrace: None,
+            cause: error
+                .source()
+                .map(|source| Box::new(FailChain::new(source))),
+            message: error.to_string(),
+            name: None,
+        }
+    }
+}
+
+impl Fail for FailChain {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.cause.as_deref().map(|cause| cause as &dyn Fail)
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_deref()
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.name
+    }
+}
+
+impl fmt::Display for FailChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Owned `std::error::Error` copy of an error and its causes.
+///
+/// Wraps errors that can't be the source of an `Error` as they are:
+/// `failure::Fail`s (see `fail_compat`) and errors that are not `Sync` (see `sync_compat`).
+#[derive(Debug)]
+pub struct ErrorCompat {
+    message: String,
+    source: Option<Box<ErrorCompat>>,
+}
+
+impl ErrorCompat {
+    fn from_fail(fail: &dyn Fail) -> ErrorCompat {
+        ErrorCompat {
+            message: fail.to_string(),
+            source: fail
+                .cause()
+                .map(|cause| Box::new(ErrorCompat::from_fail(cause))),
+        }
+    }
+
+    fn from_std(error: &(dyn StdError + 'static)) -> ErrorCompat {
+        ErrorCompat {
+            message: error.to_string(),
+            source: error
+                .source()
+                .map(|source| Box::new(ErrorCompat::from_std(source))),
+        }
+    }
+}
+
+impl fmt::Display for ErrorCompat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for ErrorCompat {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn StdError + 'static))
     }
 }
 
-// Support conversion from custom ErrorKind to allow agents to define their own kinds that
-// can be converted into base agent error kinds and wrapped in an error.
-// See the MongoDB agent code for an example of this.
-impl<E> From<Context<E>> for Error
+/// Convert errors from crates still based on `failure` into `std::error::Error`s.
+///
+/// Useful to wrap these errors with `ResultExt::with_context`:
+/// `.map_err(fail_compat).with_context(|_| ErrorKind::...)`.
+pub fn fail_compat<F: Fail>(fail: F) -> ErrorCompat {
+    ErrorCompat::from_fail(&fail)
+}
+
+/// Convert errors that are not `Sync` (such as `error-chain` errors) into `Sync` ones.
+pub fn sync_compat<E>(error: E) -> ErrorCompat
+where
+    E: StdError + 'static,
+{
+    ErrorCompat::from_std(&error)
+}
+
+/// Extension methods to wrap errors returned by other crates in `Error`s.
+///
+/// Agents can define their own error kinds as long as they convert
+/// into base agent error kinds (see the MongoDB agent code for an example).
+pub trait ResultExt<T, E> {
+    /// Wrap the error, if any, in an `Error` of the kind returned by `f`.
+    fn with_context<F, K>(self, f: F) -> Result<T>
+    where
+        F: FnOnce(&E) -> K,
+        K: Into<ErrorKind>;
+}
+
+impl<T, E> ResultExt<T, E> for ::std::result::Result<T, E>
 where
-    E: Into<ErrorKind> + fmt::Display + Sync + Send,
+    E: Into<Box<dyn StdError + Send + Sync>>,
 {
-    fn from(context: Context<E>) -> Error {
-        let context = context.map(Into::into);
-        Error(context)
+    fn with_context<F, K>(self, f: F) -> Result<T>
+    where
+        F: FnOnce(&E) -> K,
+        K: Into<ErrorKind>,
+    {
+        self.map_err(|error| {
+            let kind = f(&error);
+            Error::with_source(kind, error)
+        })
+    }
+}
+
+/// Error information returned by the API.
+#[cfg(feature = "api")]
+#[derive(Serialize)]
+struct ErrorResponse {
+    /// Broad source of the error: the agent, the datastore, the configuration or the request.
+    category: ErrorCategory,
+
+    /// Stable, machine-readable, error code.
+    code: &'static str,
+
+    /// ID to correlate the error with the request that caused it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<String>,
+
+    #[serde(flatten)]
+    info: SerializableFail,
+
+    /// Indicates the request may succeed if retried later.
+    retryable: bool,
+}
+
+/// Mark the span as failed with the given error.
+///
+/// Extends `replicante_util_tracing::fail_span` by tagging agent errors
+/// with their category (`error.category`).
+pub fn fail_span<'a, E, S>(error: E, span: S) -> E
+where
+    E: Fail,
+    S: Into<Option<&'a mut Span>>,
+{
+    let mut span = span.into();
+    let error_ref: &dyn Fail = &error;
+    if let Some(known) = error_ref.downcast_ref::<Error>() {
+        if let Some(span) = span.as_mut() {
+            span.tag("error.category", known.kind().category().as_str());
+        }
+        replicante_util_tracing::fail_span(known.to_fail(), span);
+        return error;
+    }
+    replicante_util_tracing::fail_span(error, span)
+}
+
+/// Broad source of errors, to tell agent bugs apart from datastore issues.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorCategory {
+    /// The agent itself failed (bugs, local store or I/O issues, ...).
+    Agent,
+
+    /// The agent is at capacity and rejected the request (the request can be retried).
+    Capacity,
+
+    /// The agent configuration is invalid.
+    Config,
+
+    /// The datastore (or its service) is unavailable or failing.
+    Datastore,
+
+    /// The request is invalid or not allowed (the client must change it).
+    Request,
+}
+
+impl ErrorCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCategory::Agent => "agent",
+            ErrorCategory::Capacity => "capacity",
+            ErrorCategory::Config => "config",
+            ErrorCategory::Datastore => "datastore",
+            ErrorCategory::Request => "request",
+        }
     }
 }
 
 /// Exhaustive list of possible errors emitted by this crate.
-#[derive(Debug, Fail)]
+///
+/// Kinds implement `std::error::Error` and, through the blanket implementation
+/// provided by `failure`, `failure::Fail` so they work with both error ecosystems.
+#[derive(Debug, thiserror::Error)]
 pub enum ErrorKind {
-    #[fail(display = "an action with id '{}' already exists", _0)]
+    #[error("the agent restarted while action {0} was running")]
+    ActionAgentRestarted(String),
+
+    #[error("an action with id '{0}' already exists")]
     ActionAlreadyExists(String),
 
-    #[fail(display = "unable to decode action information")]
+    #[error("unable to decode action information")]
     ActionDecode,
 
-    #[fail(display = "unable to encode action information")]
+    #[error("unable to encode action information")]
     ActionEncode,
 
-    #[fail(display = "actions with kind {} are not available", _0)]
+    #[error("invalid arguments for follow-up action {0}")]
+    ActionFollowUpArgs(String),
+
+    #[error("action {0} can't schedule follow-up actions: it has {1} ancestors")]
+    ActionFollowUpDepth(String, u32),
+
+    #[error("action {0} is leased by another agent process")]
+    ActionLeaseLost(String),
+
+    #[error("actions with kind {0} are not available")]
     ActionNotAvailable(String),
 
-    #[fail(display = "invalid configuration: {}", _0)]
+    #[error("action {0} can't be requeued because it has not failed")]
+    ActionNotFailed(String),
+
+    #[error("action {0} is run by the actions engine and can't be transitioned externally")]
+    ActionNotExternal(String),
+
+    #[error("action {0} is in state {1} but {2} was expected")]
+    ActionStateMismatch(String, String, String),
+
+    #[error("action {0} is not allowed to transition from {1} to {2}")]
+    ActionTransitionNotAllowed(String, String, String),
+
+    #[error("agent actions are disabled")]
+    ActionsDisabled,
+
+    #[error("too many '{0}' calls waiting for a datastore thread")]
+    BlockingPoolFull(&'static str),
+
+    #[error("datastore calls are failing, '{0}' rejected by circuit breaker")]
+    CircuitOpen(&'static str),
+
+    #[error("too many concurrent '{0}' calls")]
+    ConcurrencyLimit(&'static str),
+
+    #[error("invalid configuration: {0}")]
     ConfigClash(&'static str),
 
-    #[fail(display = "unable to load configuration")]
+    #[error("invalid value for configuration option {0}: {1}")]
+    ConfigInvalid(&'static str, String),
+
+    #[error("unable to load configuration")]
     ConfigLoad,
 
-    #[fail(display = "invalid configuration for option {}", _0)]
+    #[error("invalid configuration for option {0}")]
     ConfigOption(&'static str),
 
-    #[fail(display = "connection error to {} with address '{}'", _0, _1)]
+    #[error("connection error to {0} with address '{1}'")]
     Connection(&'static str, String),
 
-    #[fail(display = "unable to check external action {} with ID {}", _0, _1)]
+    #[error("request deadline exceeded during '{0}'")]
+    DeadlineExceeded(&'static str),
+
+    #[error("datastore discovery failed: {0}")]
+    Discovery(String),
+
+    #[error("unable to check external action {0} with ID {1}")]
     ExternalActionCheck(String, Uuid),
 
-    #[fail(display = "unable to decode check result for external action {}", _0)]
+    #[error("unable to decode check result for external action {0}")]
     ExternalActionCheckDecode(Uuid),
 
-    #[fail(
-        display = "external action {} check command failed\n--> Standard out:\n{}\n--> Standard error:\n{}",
-        _0, _1, _2
-    )]
+    #[error("external action {0} check command failed\n--> Standard out:\n{1}\n--> Standard error:\n{2}")]
     ExternalActionCheckResult(Uuid, String, String),
 
-    #[fail(
-        display = "external action {} start command failed\n--> Standard out:\n{}\n--> Standard error:\n{}",
-        _0, _1, _2
-    )]
+    #[error("external action {0} refused to run '{1}': checksum does not match the allowlist")]
+    ExternalActionChecksum(Uuid, String),
+
+    #[error("external action {0} start command failed\n--> Standard out:\n{1}\n--> Standard error:\n{2}")]
     ExternalActionExec(Uuid, String, String),
 
-    #[fail(display = "external action {} with ID {} failed to start", _0, _1)]
+    #[error("external action {0} with ID {1} failed to start")]
     ExternalActionStart(String, Uuid),
 
+    #[error("external action {0} command timed out after {1} seconds\n--> Standard error:\n{2}")]
+    ExternalActionTimeout(Uuid, u64, String),
+
+    #[error("node fencing operation '{0}' failed")]
+    FencingOpFailed(&'static str),
+
+    #[error("node fencing operation '{0}' timed out after {1} seconds")]
+    FencingOpTimeout(&'static str, u64),
+
     /// Generic context agents can use if provided contexts are not enough.
-    #[fail(display = "{}", _0)]
+    #[error("{0}")]
     FreeForm(String),
 
-    #[fail(display = "agent initialisation error: {}", _0)]
+    #[error("agent initialisation error: {0}")]
     Initialisation(String),
 
-    #[fail(display = "invalid datastore state: {}", _0)]
+    #[error("invalid pagination token '{0}'")]
+    InvalidPageToken(String),
+
+    #[error("invalid value for query parameter {0}: '{1}'")]
+    InvalidQueryParam(&'static str, String),
+
+    #[error("invalid datastore state: {0}")]
     InvalidStoreState(String),
 
-    #[fail(display = "I/O error on file {}", _0)]
+    #[error("I/O error on file {0}")]
     Io(String),
 
-    #[fail(display = "unable to commit transaction to persistent DB")]
+    #[error("request for namespace '{0}' does not match the agent namespace '{1}'")]
+    NamespaceMismatch(String, String),
+
+    #[error("payload version '{0}' is not supported")]
+    PayloadVersionUnsupported(String),
+
+    #[error("unable to back up persistent DB to {0}")]
+    PersistentBackup(String),
+
+    #[error("unable to commit transaction to persistent DB")]
     PersistentCommit,
 
-    #[fail(display = "unable to migrate persistent DB")]
+    #[error("persistent DB is failing to store writes, new actions are rejected")]
+    PersistentDegraded,
+
+    #[error("persistent DB is in use by agent process {0}, stop the agent before changing it")]
+    PersistentInUse(String),
+
+    #[error("unable to migrate persistent DB")]
     PersistentMigrate,
 
-    #[fail(display = "connection to persistent DB available")]
+    #[error("unable to revert persistent DB migrations: {0}")]
+    PersistentMigrateDown(String),
+
+    #[error("connection to persistent DB available")]
     PersistentNoConnection,
 
-    #[fail(display = "failed to read {} from persistent store", _0)]
+    #[error("failed to read {0} from persistent store")]
     PersistentRead(&'static str),
 
-    #[fail(display = "failed to write {} to persistent store", _0)]
+    #[error("unable to restore persistent DB from {0}")]
+    PersistentRestore(String),
+
+    #[error("persistent DB has migrations unknown to this agent version ({0}), revert them with --migrate-down-to using the agent version that applied them")]
+    PersistentSchemaUnknown(String),
+
+    #[error("failed to write {0} to persistent store")]
     PersistentWrite(&'static str),
 
-    #[fail(display = "unable to open persistent DB {}", _0)]
+    #[error("unable to open persistent DB {0}")]
     PersistentOpen(String),
 
-    #[fail(display = "unable to initialse persistent DB connections pool")]
+    #[error("unable to initialse persistent DB connections pool")]
     PersistentPool,
 
-    #[fail(
-        display = "could not decode {} response from store for '{}' operation",
-        _0, _1
-    )]
+    #[error("protocol version {0} is not supported (supported versions: {1}-{2})")]
+    ProtocolUnsupported(u32, u32, u32),
+
+    #[error("could not decode {0} response from store for '{1}' operation")]
     ResponseDecode(&'static str, &'static str),
 
-    #[fail(display = "service operation '{}' failed", _0)]
+    #[error("unable to sandbox the agent process: {0} failed")]
+    Sandbox(&'static str),
+
+    #[error("service operation '{0}' failed")]
     ServiceOpFailed(&'static str),
 
-    #[fail(display = "datastore operation '{}' failed", _0)]
+    #[error("datastore operation '{0}' failed")]
     StoreOpFailed(&'static str),
 
-    #[fail(display = "unable to spawn '{}' thread", _0)]
+    #[error("unable to spawn '{0}' thread")]
     ThreadSpawn(&'static str),
+
+    #[error("request to {0} is not authorized")]
+    Unauthorized(&'static str),
+
+    #[error("unable to fetch update metadata from '{0}'")]
+    UpdateMetadata(String),
+
+    #[error("unable to parse datastore version '{0}'")]
+    VersionParse(String),
 }
 
 impl ErrorKind {
+    #[cfg(feature = "api")]
     fn http_status(&self) -> StatusCode {
         match self {
             ErrorKind::ActionAlreadyExists(_) => StatusCode::CONFLICT,
             ErrorKind::ActionEncode => StatusCode::BAD_REQUEST,
+            ErrorKind::ActionLeaseLost(_) => StatusCode::CONFLICT,
             ErrorKind::ActionNotAvailable(_) => StatusCode::BAD_REQUEST,
+            ErrorKind::ActionNotFailed(_) => StatusCode::CONFLICT,
+            ErrorKind::ActionNotExternal(_) => StatusCode::CONFLICT,
+            ErrorKind::ActionStateMismatch(_, _, _) => StatusCode::CONFLICT,
+            ErrorKind::ActionTransitionNotAllowed(_, _, _) => StatusCode::BAD_REQUEST,
+            ErrorKind::ActionsDisabled => StatusCode::FORBIDDEN,
+            ErrorKind::BlockingPoolFull(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::CircuitOpen(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::ConcurrencyLimit(_) => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::DeadlineExceeded(_) => StatusCode::GATEWAY_TIMEOUT,
+            ErrorKind::InvalidPageToken(_) => StatusCode::BAD_REQUEST,
+            ErrorKind::InvalidQueryParam(_, _) => StatusCode::BAD_REQUEST,
+            ErrorKind::NamespaceMismatch(_, _) => StatusCode::FORBIDDEN,
+            ErrorKind::PayloadVersionUnsupported(_) => StatusCode::NOT_ACCEPTABLE,
+            ErrorKind::PersistentDegraded => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::ProtocolUnsupported(_, _, _) => StatusCode::BAD_REQUEST,
+            ErrorKind::Unauthorized(_) => StatusCode::UNAUTHORIZED,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
-    fn kind_name(&self) -> Option<&str> {
-        let name = match self {
+    /// Broad source of the error.
+    ///
+    /// The match is exhaustive so new kinds must be explicitly classified.
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            ErrorKind::ActionAgentRestarted(_) => ErrorCategory::Agent,
+            ErrorKind::ActionAlreadyExists(_) => ErrorCategory::Request,
+            ErrorKind::ActionDecode => ErrorCategory::Agent,
+            ErrorKind::ActionEncode => ErrorCategory::Request,
+            ErrorKind::ActionFollowUpArgs(_) => ErrorCategory::Agent,
+            ErrorKind::ActionFollowUpDepth(_, _) => ErrorCategory::Agent,
+            ErrorKind::ActionLeaseLost(_) => ErrorCategory::Agent,
+            ErrorKind::ActionNotAvailable(_) => ErrorCategory::Request,
+            ErrorKind::ActionNotFailed(_) => ErrorCategory::Request,
+            ErrorKind::ActionNotExternal(_) => ErrorCategory::Request,
+            ErrorKind::ActionStateMismatch(_, _, _) => ErrorCategory::Request,
+            ErrorKind::ActionTransitionNotAllowed(_, _, _) => ErrorCategory::Request,
+            ErrorKind::ActionsDisabled => ErrorCategory::Request,
+            ErrorKind::BlockingPoolFull(_) => ErrorCategory::Capacity,
+            ErrorKind::CircuitOpen(_) => ErrorCategory::Datastore,
+            ErrorKind::ConcurrencyLimit(_) => ErrorCategory::Capacity,
+            ErrorKind::ConfigClash(_) => ErrorCategory::Config,
+            ErrorKind::ConfigInvalid(_, _) => ErrorCategory::Config,
+            ErrorKind::ConfigLoad => ErrorCategory::Config,
+            ErrorKind::ConfigOption(_) => ErrorCategory::Config,
+            ErrorKind::Connection(_, _) => ErrorCategory::Datastore,
+            ErrorKind::DeadlineExceeded(_) => ErrorCategory::Datastore,
+            ErrorKind::Discovery(_) => ErrorCategory::Datastore,
+            ErrorKind::ExternalActionCheck(_, _) => ErrorCategory::Agent,
+            ErrorKind::ExternalActionCheckDecode(_) => ErrorCategory::Agent,
+            ErrorKind::ExternalActionCheckResult(_, _, _) => ErrorCategory::Agent,
+            ErrorKind::ExternalActionChecksum(_, _) => ErrorCategory::Config,
+            ErrorKind::ExternalActionExec(_, _, _) => ErrorCategory::Agent,
+            ErrorKind::ExternalActionStart(_, _) => ErrorCategory::Agent,
+            ErrorKind::ExternalActionTimeout(_, _, _) => ErrorCategory::Agent,
+            ErrorKind::FencingOpFailed(_) => ErrorCategory::Agent,
+            ErrorKind::FencingOpTimeout(_, _) => ErrorCategory::Agent,
+            ErrorKind::FreeForm(_) => ErrorCategory::Agent,
+            ErrorKind::Initialisation(_) => ErrorCategory::Agent,
+            ErrorKind::InvalidPageToken(_) => ErrorCategory::Request,
+            ErrorKind::InvalidQueryParam(_, _) => ErrorCategory::Request,
+            ErrorKind::InvalidStoreState(_) => ErrorCategory::Datastore,
+            ErrorKind::Io(_) => ErrorCategory::Agent,
+            ErrorKind::NamespaceMismatch(_, _) => ErrorCategory::Request,
+            ErrorKind::PayloadVersionUnsupported(_) => ErrorCategory::Request,
+            ErrorKind::PersistentBackup(_) => ErrorCategory::Agent,
+            ErrorKind::PersistentCommit => ErrorCategory::Agent,
+            ErrorKind::PersistentDegraded => ErrorCategory::Agent,
+            ErrorKind::PersistentInUse(_) => ErrorCategory::Agent,
+            ErrorKind::PersistentMigrate => ErrorCategory::Agent,
+            ErrorKind::PersistentMigrateDown(_) => ErrorCategory::Agent,
+            ErrorKind::PersistentNoConnection => ErrorCategory::Agent,
+            ErrorKind::PersistentOpen(_) => ErrorCategory::Agent,
+            ErrorKind::PersistentPool => ErrorCategory::Agent,
+            ErrorKind::PersistentRead(_) => ErrorCategory::Agent,
+            ErrorKind::PersistentRestore(_) => ErrorCategory::Agent,
+            ErrorKind::PersistentSchemaUnknown(_) => ErrorCategory::Agent,
+            ErrorKind::PersistentWrite(_) => ErrorCategory::Agent,
+            ErrorKind::ProtocolUnsupported(_, _, _) => ErrorCategory::Request,
+            ErrorKind::ResponseDecode(_, _) => ErrorCategory::Datastore,
+            ErrorKind::Sandbox(_) => ErrorCategory::Agent,
+            ErrorKind::ServiceOpFailed(_) => ErrorCategory::Datastore,
+            ErrorKind::StoreOpFailed(_) => ErrorCategory::Datastore,
+            ErrorKind::ThreadSpawn(_) => ErrorCategory::Agent,
+            ErrorKind::Unauthorized(_) => ErrorCategory::Request,
+            ErrorKind::UpdateMetadata(_) => ErrorCategory::Agent,
+            ErrorKind::VersionParse(_) => ErrorCategory::Datastore,
+        }
+    }
+
+    /// Stable, machine-readable, code for the error kind.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::ActionAgentRestarted(_) => "ActionAgentRestarted",
             ErrorKind::ActionAlreadyExists(_) => "ActionAlreadyExists",
             ErrorKind::ActionDecode => "ActionDecode",
             ErrorKind::ActionEncode => "ActionEncode",
+            ErrorKind::ActionFollowUpArgs(_) => "ActionFollowUpArgs",
+            ErrorKind::ActionFollowUpDepth(_, _) => "ActionFollowUpDepth",
+            ErrorKind::ActionLeaseLost(_) => "ActionLeaseLost",
             ErrorKind::ActionNotAvailable(_) => "ActionNotAvailable",
+            ErrorKind::ActionNotFailed(_) => "ActionNotFailed",
+            ErrorKind::ActionNotExternal(_) => "ActionNotExternal",
+            ErrorKind::ActionStateMismatch(_, _, _) => "ActionStateMismatch",
+            ErrorKind::ActionTransitionNotAllowed(_, _, _) => "ActionTransitionNotAllowed",
+            ErrorKind::ActionsDisabled => "ActionsDisabled",
+            ErrorKind::BlockingPoolFull(_) => "BlockingPoolFull",
+            ErrorKind::CircuitOpen(_) => "CircuitOpen",
+            ErrorKind::ConcurrencyLimit(_) => "ConcurrencyLimit",
             ErrorKind::ConfigClash(_) => "ConfigClash",
+            ErrorKind::ConfigInvalid(_, _) => "ConfigInvalid",
             ErrorKind::ConfigLoad => "ConfigLoad",
             ErrorKind::ConfigOption(_) => "ConfigOption",
             ErrorKind::Connection(_, _) => "Connection",
+            ErrorKind::DeadlineExceeded(_) => "DeadlineExceeded",
+            ErrorKind::Discovery(_) => "Discovery",
             ErrorKind::ExternalActionCheck(_, _) => "ExternalActionCheck",
             ErrorKind::ExternalActionCheckDecode(_) => "ExternalActionCheckDecode",
             ErrorKind::ExternalActionCheckResult(_, _, _) => "ExternalActionCheckResult",
+            ErrorKind::ExternalActionChecksum(_, _) => "ExternalActionChecksum",
             ErrorKind::ExternalActionExec(_, _, _) => "ExternalActionExec",
             ErrorKind::ExternalActionStart(_, _) => "ExternalActionStart",
+            ErrorKind::ExternalActionTimeout(_, _, _) => "ExternalActionTimeout",
+            ErrorKind::FencingOpFailed(_) => "FencingOpFailed",
+            ErrorKind::FencingOpTimeout(_, _) => "FencingOpTimeout",
             ErrorKind::FreeForm(_) => "FreeForm",
             ErrorKind::Initialisation(_) => "Initialisation",
+            ErrorKind::InvalidPageToken(_) => "InvalidPageToken",
+            ErrorKind::InvalidQueryParam(_, _) => "InvalidQueryParam",
             ErrorKind::InvalidStoreState(_) => "InvalidStoreState",
             ErrorKind::Io(_) => "Io",
+            ErrorKind::NamespaceMismatch(_, _) => "NamespaceMismatch",
+            ErrorKind::PayloadVersionUnsupported(_) => "PayloadVersionUnsupported",
+            ErrorKind::PersistentBackup(_) => "PersistentBackup",
             ErrorKind::PersistentCommit => "PersistentCommit",
+            ErrorKind::PersistentDegraded => "PersistentDegraded",
+            ErrorKind::PersistentInUse(_) => "PersistentInUse",
             ErrorKind::PersistentMigrate => "PersistentMigrate",
+            ErrorKind::PersistentMigrateDown(_) => "PersistentMigrateDown",
             ErrorKind::PersistentNoConnection => "PersistentNoConnection",
             ErrorKind::PersistentOpen(_) => "PersistentOpen",
             ErrorKind::PersistentPool => "PersistentPool",
             ErrorKind::PersistentRead(_) => "PersistentRead",
+            ErrorKind::PersistentRestore(_) => "PersistentRestore",
+            ErrorKind::PersistentSchemaUnknown(_) => "PersistentSchemaUnknown",
             ErrorKind::PersistentWrite(_) => "PersistentWrite",
+            ErrorKind::ProtocolUnsupported(_, _, _) => "ProtocolUnsupported",
             ErrorKind::ResponseDecode(_, _) => "ResponseDecode",
+            ErrorKind::Sandbox(_) => "Sandbox",
             ErrorKind::ServiceOpFailed(_) => "ServiceOpFailed",
             ErrorKind::StoreOpFailed(_) => "StoreOpFailed",
             ErrorKind::ThreadSpawn(_) => "ThreadSpawn",
-        };
-        Some(name)
+            ErrorKind::Unauthorized(_) => "Unauthorized",
+            ErrorKind::UpdateMetadata(_) => "UpdateMetadata",
+            ErrorKind::VersionParse(_) => "VersionParse",
+        }
+    }
+
+    /// Indicates if an operation failing with this error may succeed if retried later.
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::BlockingPoolFull(_)
+                | ErrorKind::CircuitOpen(_)
+                | ErrorKind::ConcurrencyLimit(_)
+                | ErrorKind::Connection(_, _)
+                | ErrorKind::DeadlineExceeded(_)
+                | ErrorKind::Discovery(_)
+                | ErrorKind::PersistentCommit
+                | ErrorKind::PersistentDegraded
+                | ErrorKind::PersistentNoConnection
+                | ErrorKind::PersistentPool
+                | ErrorKind::PersistentRead(_)
+                | ErrorKind::PersistentWrite(_)
+                | ErrorKind::ServiceOpFailed(_)
+                | ErrorKind::StoreOpFailed(_)
+        )
     }
 }
 
 /// Short form alias for functions returning `Error`s.
 pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as StdError;
+    use std::io;
+
+    use failure::Fail;
+
+    use super::Error;
+    use super::ErrorKind;
+    use super::ResultExt;
+
+    #[test]
+    fn from_anyhow_keeps_chain() {
+        let error = anyhow::anyhow!("root cause").context("operation failed");
+        let error = Error::from(error);
+        assert_eq!(error.kind().code(), "FreeForm");
+        assert_eq!(error.to_string(), "operation failed: root cause");
+    }
+
+    #[test]
+    fn categories() {
+        let error = Error::from(ErrorKind::StoreOpFailed("test"));
+        assert_eq!(error.kind().category().as_str(), "datastore");
+        let error = Error::from(ErrorKind::ConfigLoad);
+        assert_eq!(error.kind().category().as_str(), "config");
+        let error = Error::from(ErrorKind::PersistentCommit);
+        assert_eq!(error.kind().category().as_str(), "agent");
+        let error = Error::from(ErrorKind::InvalidQueryParam("limit", "-1".into()));
+        assert_eq!(error.kind().category().as_str(), "request");
+        let error = Error::from(ErrorKind::Unauthorized("actions"));
+        assert_eq!(error.kind().category().as_str(), "request");
+        let error = Error::from(ErrorKind::ConcurrencyLimit("shards"));
+        assert_eq!(error.kind().category().as_str(), "capacity");
+    }
+
+    #[test]
+    fn into_anyhow() {
+        let error = Error::from(ErrorKind::StoreOpFailed("test"));
+        let error = anyhow::Error::from(error);
+        assert_eq!(error.to_string(), "datastore operation 'test' failed");
+    }
+
+    #[test]
+    fn to_fail_keeps_sources() {
+        let source = io::Error::new(io::ErrorKind::Other, "root cause");
+        let error = Error::with_source(ErrorKind::StoreOpFailed("test"), source);
+        let error = Error::with_source(ErrorKind::ConfigLoad, error);
+        let fail = error.to_fail();
+        let chain: Vec<String> = fail.iter_chain().map(ToString::to_string).collect();
+        assert_eq!(
+            chain,
+            vec![
+                "unable to load configuration".to_string(),
+                "datastore operation 'test' failed".to_string(),
+                "root cause".to_string(),
+            ]
+        );
+        assert_eq!(fail.name(), Some("ConfigLoad"));
+    }
+
+    #[test]
+    fn with_context_wraps_source() {
+        let result: std::result::Result<(), io::Error> =
+            Err(io::Error::new(io::ErrorKind::Other, "root cause"));
+        let error = result
+            .with_context(|_| ErrorKind::StoreOpFailed("test"))
+            .unwrap_err();
+        assert_eq!(error.kind().code(), "StoreOpFailed");
+        assert_eq!(error.source().unwrap().to_string(), "root cause");
+    }
+}
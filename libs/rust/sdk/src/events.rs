@@ -0,0 +1,93 @@
+use chrono::DateTime;
+use chrono::Utc;
+use opentracingrust::SpanContext;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value as Json;
+use uuid::Uuid;
+
+use replicante_util_failure::capture_fail;
+use replicante_util_failure::failure_info;
+
+use crate::shards::RoleChange;
+use crate::AgentContext;
+
+/// Event kind for changes of the datastore version.
+pub const EVENT_DATASTORE_VERSION_CHANGED: &str = "DATASTORE_VERSION_CHANGED";
+
+/// Event kind for changes of the role of a shard on the node.
+pub const EVENT_SHARD_ROLE_CHANGED: &str = "SHARD_ROLE_CHANGED";
+
+/// Record of something the agent observed, kept in the store as a local trail.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// Unique ID of the event.
+    pub event_id: Uuid,
+
+    /// Time the event was observed by the agent.
+    pub event_ts: DateTime<Utc>,
+
+    /// Type of event, which determines the payload format.
+    pub kind: String,
+
+    /// Event specific details.
+    pub payload: Json,
+}
+
+impl Event {
+    /// Create a new event of the given kind, observed now.
+    pub fn new<S: Into<String>>(kind: S, payload: Json) -> Event {
+        Event {
+            event_id: Uuid::new_v4(),
+            event_ts: Utc::now(),
+            kind: kind.into(),
+            payload,
+        }
+    }
+
+    /// The datastore version changed from the one the active agent was made for.
+    pub fn datastore_version_changed(previous: &str, current: &str) -> Event {
+        let payload = json!({
+            "current": current,
+            "previous": previous,
+        });
+        Event::new(EVENT_DATASTORE_VERSION_CHANGED, payload)
+    }
+
+    /// The role of a shard changed since the previous shard collection.
+    pub fn shard_role_changed(change: &RoleChange) -> Event {
+        let payload = json!({
+            "current": change.current,
+            "previous": change.previous,
+            "shard": change.shard,
+        });
+        Event::new(EVENT_SHARD_ROLE_CHANGED, payload)
+    }
+}
+
+/// Persist an event and prune old ones.
+///
+/// Failures to record events are logged and otherwise ignored so they do not
+/// interfere with the operation that observed the event.
+pub fn emit<S>(context: &AgentContext, event: Event, span: S)
+where
+    S: Into<Option<SpanContext>>,
+{
+    let keep = context.config.events.keep;
+    let span = span.into();
+    let result = context.store.with_transaction(|tx| {
+        tx.events().persist(&event, span.clone())?;
+        tx.events().prune(keep, span)
+    });
+    if let Err(error) = result {
+        capture_fail!(
+            &error.to_fail(),
+            context.logger,
+            "Failed to record agent event";
+            failure_info(&error.to_fail()),
+            "event_id" => %event.event_id,
+            "kind" => &event.kind,
+        );
+    }
+}
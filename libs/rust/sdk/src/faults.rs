@@ -0,0 +1,138 @@
+//! Fault injection to exercise failure handling in debug and test builds.
+//!
+//! Faults are enabled by the `agent.replicante.io/debug.fault.*` actions and
+//! checked at the points of the agent they affect.
+//! In release builds all checks are no-ops.
+#[cfg(any(debug_assertions, test))]
+pub use self::enabled::*;
+
+#[cfg(not(any(debug_assertions, test)))]
+pub use self::disabled::*;
+
+#[cfg(any(debug_assertions, test))]
+mod enabled {
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use crate::ErrorKind;
+    use crate::Result;
+
+    lazy_static::lazy_static! {
+        static ref FAULTS: Mutex<Faults> = Mutex::new(Faults::default());
+    }
+
+    /// Currently injected faults.
+    #[derive(Default)]
+    struct Faults {
+        // Only API handlers are delayed by injected latency.
+        #[cfg_attr(not(feature = "api"), allow(dead_code))]
+        datastore_latency: Option<(Duration, Instant)>,
+        store_writes: u32,
+        supervisor_calls: u32,
+    }
+
+    fn faults() -> std::sync::MutexGuard<'static, Faults> {
+        FAULTS.lock().expect("FAULTS lock poisoned")
+    }
+
+    /// Remove all injected faults.
+    pub fn clear() {
+        *faults() = Faults::default();
+    }
+
+    /// Delay datastore requests by `latency` until `duration` expires.
+    pub fn inject_datastore_latency(latency: Duration, duration: Duration) {
+        faults().datastore_latency = Some((latency, Instant::now() + duration));
+    }
+
+    /// Fail the next `count` store writes.
+    pub fn inject_store_write_errors(count: u32) {
+        faults().store_writes = count;
+    }
+
+    /// Fail the next `count` service supervisor calls.
+    pub fn inject_supervisor_errors(count: u32) {
+        faults().supervisor_calls = count;
+    }
+
+    /// Delay the caller if datastore latency is injected.
+    ///
+    /// The delay is asynchronous so API workers keep serving other requests.
+    #[cfg(feature = "api")]
+    pub async fn datastore_latency() {
+        let latency = {
+            let mut faults = faults();
+            match faults.datastore_latency {
+                Some((_, until)) if until <= Instant::now() => {
+                    faults.datastore_latency = None;
+                    None
+                }
+                Some((latency, _)) => Some(latency),
+                None => None,
+            }
+        };
+        if let Some(latency) = latency {
+            actix_web::rt::time::sleep(latency).await;
+        }
+    }
+
+    /// Fail if store write errors are injected.
+    pub fn store_write() -> Result<()> {
+        let mut faults = faults();
+        if faults.store_writes == 0 {
+            return Ok(());
+        }
+        faults.store_writes -= 1;
+        Err(ErrorKind::PersistentWrite("injected fault").into())
+    }
+
+    /// Fail if supervisor errors are injected.
+    pub fn supervisor() -> Result<()> {
+        let mut faults = faults();
+        if faults.supervisor_calls == 0 {
+            return Ok(());
+        }
+        faults.supervisor_calls -= 1;
+        Err(ErrorKind::ServiceOpFailed("injected fault").into())
+    }
+}
+
+#[cfg(not(any(debug_assertions, test)))]
+mod disabled {
+    use crate::Result;
+
+    /// Delay the caller if datastore latency is injected.
+    #[cfg(feature = "api")]
+    pub async fn datastore_latency() {}
+
+    /// Fail if store write errors are injected.
+    pub fn store_write() -> Result<()> {
+        Ok(())
+    }
+
+    /// Fail if supervisor errors are injected.
+    pub fn supervisor() -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[actix_web::test]
+    #[cfg(feature = "api")]
+    async fn datastore_latency_delays_asynchronously() {
+        let latency = std::time::Duration::from_millis(20);
+        super::inject_datastore_latency(latency, std::time::Duration::from_millis(50));
+        let start = std::time::Instant::now();
+        super::datastore_latency().await;
+        assert!(start.elapsed() >= latency);
+    }
+
+    #[test]
+    fn supervisor_errors_expire() {
+        super::inject_supervisor_errors(1);
+        assert!(super::supervisor().is_err());
+        assert!(super::supervisor().is_ok());
+    }
+}
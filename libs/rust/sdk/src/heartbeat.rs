@@ -0,0 +1,118 @@
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use chrono::DateTime;
+use chrono::Utc;
+use humthreads::Builder;
+use humthreads::ThreadScope;
+use serde::Deserialize;
+use serde::Serialize;
+use slog::debug;
+use uuid::Uuid;
+
+use replicante_util_failure::capture_fail;
+use replicante_util_failure::failure_info;
+use replicante_util_upkeep::Upkeep;
+
+use crate::fail_compat;
+use crate::AgentContext;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// Delay between checks for shutdown requests while waiting for the next heartbeat.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(500);
+
+lazy_static::lazy_static! {
+    /// Unique ID of the running agent process.
+    pub static ref PROCESS_ID: Uuid = Uuid::new_v4();
+
+    /// Time the running agent process started.
+    pub static ref PROCESS_STARTED: DateTime<Utc> = Utc::now();
+}
+
+/// Record of an agent process being alive.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    /// Time of the latest heartbeat from the agent process.
+    pub heartbeat_ts: DateTime<Utc>,
+
+    /// Unique ID of the agent process the heartbeat is for.
+    pub process_id: Uuid,
+
+    /// Time the agent process started.
+    pub started_ts: DateTime<Utc>,
+
+    /// Version of the agent that recorded the heartbeat.
+    ///
+    /// This is the version registered with `build::register` and not the SDK version.
+    pub version: String,
+}
+
+impl Heartbeat {
+    /// Heartbeat for the running agent process.
+    pub fn current() -> Heartbeat {
+        Heartbeat {
+            heartbeat_ts: Utc::now(),
+            process_id: *PROCESS_ID,
+            started_ts: *PROCESS_STARTED,
+            version: crate::build::current().version.to_string(),
+        }
+    }
+
+    /// Number of seconds the agent process was running for as of the latest heartbeat.
+    pub fn uptime(&self) -> i64 {
+        (self.heartbeat_ts - self.started_ts).num_seconds()
+    }
+}
+
+/// Start background thread to periodically record heartbeats.
+pub fn spawn(context: AgentContext, upkeep: &mut Upkeep) -> Result<()> {
+    // Ensure the process start time is initialised before the first heartbeat.
+    lazy_static::initialize(&PROCESS_STARTED);
+    let thread = Builder::new("r:b:heartbeat")
+        .full_name("replicante:base:heartbeat")
+        .spawn(move |scope| {
+            let interval = Duration::from_secs(context.config.heartbeat.interval);
+            let keep = context.config.heartbeat.keep;
+            scope.activity("waiting to record heartbeat");
+            while !scope.should_shutdown() {
+                {
+                    let _activity = scope.scoped_activity("recording heartbeat");
+                    record(&context, keep);
+                }
+                wait(&scope, interval);
+            }
+        })
+        .map_err(fail_compat)
+        .with_context(|_| ErrorKind::ThreadSpawn("heartbeat"))?;
+    upkeep.register_thread(thread);
+    Ok(())
+}
+
+/// Record a heartbeat for the running process and prune heartbeats of old processes.
+fn record(context: &AgentContext, keep: u32) {
+    let heartbeat = Heartbeat::current();
+    debug!(context.logger, "Recording agent heartbeat"; "uptime" => heartbeat.uptime());
+    let result = context.store.with_transaction(|tx| {
+        tx.heartbeats().persist(&heartbeat, None)?;
+        tx.heartbeats().prune(keep, None)
+    });
+    if let Err(error) = result {
+        capture_fail!(
+            &error.to_fail(),
+            context.logger,
+            "Failed to record agent heartbeat";
+            failure_info(&error.to_fail()),
+        );
+    }
+}
+
+/// Wait for the next heartbeat in short steps so shutdown requests are not delayed.
+fn wait(scope: &ThreadScope, interval: Duration) {
+    let next = Instant::now() + interval;
+    while !scope.should_shutdown() && Instant::now() < next {
+        thread::sleep(SHUTDOWN_POLL.min(next.saturating_duration_since(Instant::now())));
+    }
+}
@@ -1,35 +1,89 @@
 //! This crate provides interfaces and structs to build Replicante agents.
 //!
 //! The crate provides a base `Agent` trait defining a common interface.
+//!
+//! # Features
+//! Sub-systems can be left out of lightweight builds with `default-features = false`:
+//!
+//!   * `api`: the HTTP API server (pulls in actix-web).
+//!   * `tls-openssl`: API server TLS and mTLS with OpenSSL.
+//!   * `tls-rustls`: API server TLS and mTLS with rustls, used over OpenSSL if both are enabled.
+//!   * `store`: the persistent store for actions and heartbeats (pulls in rusqlite).
+//!   * `actions`: the actions engine, action hooks and actions API (requires `store`).
+//!
+//! All features except `tls-rustls` are enabled by default.
+//! Static builds (for example musl targets) can use rustls instead of OpenSSL with
+//! `default-features = false, features = ["actions", "store", "tls-rustls"]`.
 #![doc(html_root_url = "https://docs.rs/replicante_agent/0.6.0")]
 pub use semver::Version as SemVersion;
 
-pub use replicante_util_tracing::fail_span;
-
+#[cfg(feature = "store")]
 pub mod actions;
 mod anywrap;
+#[cfg(feature = "api")]
 mod api;
+pub mod blocking;
+pub mod breaker;
+pub mod build;
+mod clock;
+pub mod collection;
+mod command;
 mod context;
+pub mod deadline;
 mod error;
+#[cfg(feature = "store")]
+pub mod events;
+mod faults;
+#[cfg(feature = "store")]
+mod heartbeat;
+pub mod limiter;
 mod metrics;
-mod store;
+pub mod outbound;
+pub mod pool;
+mod runtime;
+mod sandbox;
+pub mod shards;
+pub mod spans;
+pub mod stages;
+#[cfg(feature = "store")]
+pub mod store;
 mod traits;
+mod updates;
+mod version_map;
 mod versioned;
 
 pub mod config;
+pub mod discovery;
 pub mod process;
 
 #[cfg(any(test, feature = "with_test_support"))]
 pub mod testing;
 
 pub use self::anywrap::AnyWrap;
+pub use self::build::BuildInfo;
+pub use self::clock::clock_skew_warning;
 pub use self::context::AgentContext;
+pub use self::error::fail_compat;
+pub use self::error::fail_span;
+pub use self::error::sync_compat;
 pub use self::error::Error;
+pub use self::error::ErrorCategory;
+pub use self::error::ErrorCompat;
 pub use self::error::ErrorKind;
+pub use self::error::FailChain;
 pub use self::error::Result;
+pub use self::error::ResultExt;
+#[cfg(feature = "store")]
+pub use self::heartbeat::Heartbeat;
 pub use self::metrics::register_metrics;
+#[cfg(feature = "store")]
 pub use self::store::Transaction;
 pub use self::traits::Agent;
+pub use self::version_map::parse_version;
+pub use self::version_map::VersionMap;
+pub use self::version_map::VERSION_UNKNOWN;
+pub use self::versioned::check_datastore_version;
+pub use self::versioned::datastore_version_warning;
 pub use self::versioned::ActiveAgent;
 pub use self::versioned::AgentFactory;
 pub use self::versioned::VersionedAgent;
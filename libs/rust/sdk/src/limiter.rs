@@ -0,0 +1,271 @@
+//! Limit concurrent datastore calls to protect it from thundering herds.
+//!
+//! When multiple Core instances poll an agent at the same time each request
+//! would otherwise turn into a datastore call (shards collection can be expensive).
+//! The limiter allows at most `max_concurrent` calls for each operation at once,
+//! queues up to `max_queued` more for a limited time and rejects the rest
+//! with `ErrorKind::ConcurrencyLimit` (`429 Too Many Requests` over the API).
+//! Queued calls wait without holding on to a thread and give up when the request
+//! deadline expires (`ErrorKind::DeadlineExceeded`, `504 Gateway Timeout` over the API).
+//!
+//! Limiter state is exported with the following metrics:
+//!
+//!   * `repliagent_concurrency_active{operation}`: calls currently in progress.
+//!   * `repliagent_concurrency_rejected{operation}`: calls rejected by the limiter.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::channel::oneshot;
+
+use crate::config::ConcurrencyLimitConfig;
+use crate::deadline::Deadline;
+use crate::metrics::CONCURRENCY_ACTIVE;
+use crate::metrics::CONCURRENCY_REJECTED;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Limit the number of concurrent calls for each operation.
+///
+/// Limiters are cheap to clone and clones share the same state.
+/// Limiters created without a configuration only forward calls.
+#[derive(Clone)]
+pub struct ConcurrencyLimiter {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: Option<ConcurrencyLimitConfig>,
+    slots: Mutex<HashMap<&'static str, Slots>>,
+}
+
+/// Calls in progress and waiting for an operation.
+#[derive(Default)]
+struct Slots {
+    active: usize,
+    queued: usize,
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(config: Option<ConcurrencyLimitConfig>) -> ConcurrencyLimiter {
+        let inner = Inner {
+            config,
+            slots: Mutex::new(HashMap::new()),
+        };
+        ConcurrencyLimiter {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Wait for a free slot for the given operation, if allowed to queue, and take it.
+    ///
+    /// Waiting happens asynchronously so queued calls do not hold on to any thread.
+    /// The slot is held until the returned `LimiterSlot` is dropped so it can be moved
+    /// along with the call it guards (for example onto the blocking pool).
+    ///
+    /// Calls that can't get a slot within `queue_timeout` fail with `ErrorKind::ConcurrencyLimit`
+    /// and calls still waiting when the deadline expires fail with `ErrorKind::DeadlineExceeded`.
+    pub async fn acquire(
+        &self,
+        operation: &'static str,
+        deadline: Option<Deadline>,
+    ) -> Result<LimiterSlot> {
+        let config = match &self.inner.config {
+            None => return Ok(LimiterSlot::unlimited(operation)),
+            Some(config) => config,
+        };
+        if let Some(deadline) = deadline {
+            deadline.check(operation)?;
+        }
+        let timeout = Instant::now() + Duration::from_millis(config.queue_timeout);
+        let mut queued = false;
+        loop {
+            let released = {
+                let mut slots = self.lock();
+                let entry = slots.entry(operation).or_default();
+                if entry.active < config.max_concurrent {
+                    if queued {
+                        entry.queued -= 1;
+                    }
+                    entry.active += 1;
+                    CONCURRENCY_ACTIVE.with_label_values(&[operation]).inc();
+                    return Ok(LimiterSlot {
+                        limiter: Some(self.clone()),
+                        operation,
+                    });
+                }
+                if !queued && entry.queued >= config.max_queued {
+                    return Err(self.reject(operation));
+                }
+                if !queued {
+                    entry.queued += 1;
+                    queued = true;
+                }
+                let (notify, released) = oneshot::channel();
+                entry.waiters.push(notify);
+                released
+            };
+
+            // Wait for a slot to be released, the queue timeout or the deadline, whichever is first.
+            let mut wait = timeout.saturating_duration_since(Instant::now());
+            if let Some(deadline) = deadline {
+                wait = wait.min(deadline.remaining());
+            }
+            if wait_for_release(wait, released).await {
+                continue;
+            }
+            self.dequeue(operation);
+            match deadline {
+                Some(deadline) if deadline.expired() => {
+                    return Err(ErrorKind::DeadlineExceeded(operation).into());
+                }
+                _ => return Err(self.reject(operation)),
+            }
+        }
+    }
+
+    /// Stop waiting for a slot without taking it.
+    fn dequeue(&self, operation: &'static str) {
+        let mut slots = self.lock();
+        if let Some(entry) = slots.get_mut(operation) {
+            entry.queued = entry.queued.saturating_sub(1);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<HashMap<&'static str, Slots>> {
+        self.inner
+            .slots
+            .lock()
+            .expect("ConcurrencyLimiter slots lock poisoned")
+    }
+
+    fn reject(&self, operation: &'static str) -> crate::Error {
+        CONCURRENCY_REJECTED.with_label_values(&[operation]).inc();
+        ErrorKind::ConcurrencyLimit(operation).into()
+    }
+
+    fn release(&self, operation: &'static str) {
+        let mut slots = self.lock();
+        if let Some(entry) = slots.get_mut(operation) {
+            entry.active = entry.active.saturating_sub(1);
+            // Waiters race for the free slot and those that lose it queue up again.
+            for waiter in entry.waiters.drain(..) {
+                let _ = waiter.send(());
+            }
+        }
+        CONCURRENCY_ACTIVE.with_label_values(&[operation]).dec();
+    }
+}
+
+/// Slot taken for an operation, released when dropped (even if the call panics).
+pub struct LimiterSlot {
+    limiter: Option<ConcurrencyLimiter>,
+    operation: &'static str,
+}
+
+impl LimiterSlot {
+    /// Slot handed out by limiters without a configuration.
+    fn unlimited(operation: &'static str) -> LimiterSlot {
+        LimiterSlot {
+            limiter: None,
+            operation,
+        }
+    }
+}
+
+impl Drop for LimiterSlot {
+    fn drop(&mut self) {
+        if let Some(limiter) = self.limiter.take() {
+            limiter.release(self.operation);
+        }
+    }
+}
+
+/// Wait for a slot to be released, up to the given time.
+///
+/// Returns `false` if the time runs out before a slot is released.
+#[cfg(feature = "api")]
+async fn wait_for_release(wait: Duration, released: oneshot::Receiver<()>) -> bool {
+    actix_web::rt::time::timeout(wait, released).await.is_ok()
+}
+
+/// Wait for a slot to be released, up to the given time.
+///
+/// Without the API server there is no timer to stop waiting early with, so
+/// queued calls wait for a slot to be released and check the time after.
+#[cfg(not(feature = "api"))]
+async fn wait_for_release(wait: Duration, released: oneshot::Receiver<()>) -> bool {
+    let until = Instant::now() + wait;
+    let _ = released.await;
+    Instant::now() < until
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ConcurrencyLimiter;
+    use crate::config::ConcurrencyLimitConfig;
+    use crate::deadline::Deadline;
+
+    fn limiter(max_queued: usize, queue_timeout: u64) -> ConcurrencyLimiter {
+        let config = ConcurrencyLimitConfig {
+            max_concurrent: 1,
+            max_queued,
+            queue_timeout,
+        };
+        ConcurrencyLimiter::new(Some(config))
+    }
+
+    #[actix_web::test]
+    async fn disabled_forwards_calls() {
+        let limiter = ConcurrencyLimiter::new(None);
+        let _first = limiter.acquire("test", None).await.unwrap();
+        assert!(limiter.acquire("test", None).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn rejects_when_queue_full() {
+        let limiter = limiter(0, 1000);
+        let held = limiter.acquire("test", None).await.unwrap();
+        let error = limiter.acquire("test", None).await.err().unwrap();
+        assert_eq!(error.kind().code(), "ConcurrencyLimit");
+        assert!(limiter.acquire("other", None).await.is_ok());
+        drop(held);
+        assert!(limiter.acquire("test", None).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn rejects_after_queue_timeout() {
+        let limiter = limiter(1, 10);
+        let _held = limiter.acquire("test", None).await.unwrap();
+        let error = limiter.acquire("test", None).await.err().unwrap();
+        assert_eq!(error.kind().code(), "ConcurrencyLimit");
+    }
+
+    #[actix_web::test]
+    async fn queue_wait_bound_by_deadline() {
+        let limiter = limiter(1, 60_000);
+        let _held = limiter.acquire("test", None).await.unwrap();
+        let deadline = Deadline::after(Duration::from_millis(10));
+        let error = limiter.acquire("test", Some(deadline)).await.err().unwrap();
+        assert_eq!(error.kind().code(), "DeadlineExceeded");
+    }
+
+    #[actix_web::test]
+    async fn queued_call_runs_when_released() {
+        let limiter = limiter(1, 5000);
+        let held = limiter.acquire("test", None).await.unwrap();
+        let queued = {
+            let limiter = limiter.clone();
+            actix_web::rt::spawn(async move { limiter.acquire("test", None).await.map(|_| 42) })
+        };
+        // Let the queued call start waiting before the slot is released.
+        actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+        drop(held);
+        assert_eq!(queued.await.unwrap().unwrap(), 42);
+    }
+}
@@ -1,16 +1,64 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
 use lazy_static::lazy_static;
+use prometheus::core::Collector;
+use prometheus::core::Desc;
+use prometheus::process_collector::ProcessCollector;
+use prometheus::proto::MetricFamily;
 use prometheus::Counter;
 use prometheus::CounterVec;
 use prometheus::Gauge;
+use prometheus::GaugeVec;
 use prometheus::Histogram;
 use prometheus::HistogramOpts;
 use prometheus::HistogramVec;
+use prometheus::IntGauge;
+use prometheus::IntGaugeVec;
 use prometheus::Opts;
+use prometheus::Registry;
 use slog::debug;
 
+#[cfg(feature = "api")]
 use replicante_util_actixweb::MetricsCollector;
 
 use crate::AgentContext;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Label names used by the metrics of the SDK and the shared helper crates.
+///
+/// Static `metrics_labels` are appended to metrics without checking for duplicates,
+/// and Prometheus rejects entire scrapes with duplicate labels, so these names are
+/// rejected as static labels.
+pub const RESERVED_LABELS: &[&str] = &[
+    "action",
+    "agent_version",
+    "breaker",
+    "channel",
+    "current",
+    "datastore",
+    "detection",
+    "key",
+    "latest",
+    "method",
+    "operation",
+    "outcome",
+    "path",
+    "pool",
+    "previous",
+    "service",
+    "stage",
+    "state",
+    "status",
+];
 
 lazy_static! {
     pub static ref ACTION_COUNT: CounterVec = CounterVec::new(
@@ -39,6 +87,130 @@ lazy_static! {
         "Duration (in seconds) of actions DB pruning"
     ))
     .expect("Failed to create ACTION_DURATION histogram");
+    pub static ref ACTIONS_ENGINE_LAG: Gauge = Gauge::new(
+        "repliagent_actions_engine_lag_seconds",
+        "Time (in seconds) the oldest pending action has been waiting for the actions engine",
+    )
+    .expect("Failed to create ACTIONS_ENGINE_LAG gauge");
+    pub static ref ACTIONS_ENGINE_LAG_ERRORS: Counter = Counter::new(
+        "repliagent_actions_engine_lag_errors",
+        "Number of failed checks of the oldest pending action",
+    )
+    .expect("Failed to create ACTIONS_ENGINE_LAG_ERRORS counter");
+    pub static ref BLOCKING_ACTIVE: IntGauge = IntGauge::new(
+        "repliagent_blocking_active",
+        "Number of datastore calls running on the blocking pool",
+    )
+    .expect("Failed to create BLOCKING_ACTIVE gauge");
+    pub static ref BLOCKING_QUEUED: IntGauge = IntGauge::new(
+        "repliagent_blocking_queued",
+        "Number of datastore calls waiting for a blocking pool thread",
+    )
+    .expect("Failed to create BLOCKING_QUEUED gauge");
+    pub static ref BLOCKING_REJECTED: CounterVec = CounterVec::new(
+        Opts::new(
+            "repliagent_blocking_rejected",
+            "Number of datastore calls rejected because the blocking pool queue is full",
+        ),
+        &["operation"],
+    )
+    .expect("Failed to create BLOCKING_REJECTED counter");
+    pub static ref BLOCKING_WAIT_DURATION: Histogram = Histogram::with_opts(HistogramOpts::new(
+        "repliagent_blocking_wait_duration",
+        "Time (in seconds) datastore calls waited for a blocking pool thread"
+    ))
+    .expect("Failed to create BLOCKING_WAIT_DURATION histogram");
+    pub static ref BREAKER_REJECTED: CounterVec = CounterVec::new(
+        Opts::new(
+            "repliagent_breaker_rejected",
+            "Number of calls rejected by an open circuit breaker",
+        ),
+        &["breaker"],
+    )
+    .expect("Failed to create BREAKER_REJECTED counter");
+    pub static ref BREAKER_STATE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "repliagent_breaker_state",
+            "State of circuit breakers (0: closed, 1: half-open, 2: open)",
+        ),
+        &["breaker"],
+    )
+    .expect("Failed to create BREAKER_STATE gauge");
+    pub static ref COLLECTION_CACHE_HITS: CounterVec = CounterVec::new(
+        Opts::new(
+            "repliagent_collection_cache_hits",
+            "Number of collection cache lookups served from the cache",
+        ),
+        &["key"],
+    )
+    .expect("Failed to create COLLECTION_CACHE_HITS counter");
+    pub static ref COLLECTION_CACHE_MISSES: CounterVec = CounterVec::new(
+        Opts::new(
+            "repliagent_collection_cache_misses",
+            "Number of collection cache lookups that fetched the result",
+        ),
+        &["key"],
+    )
+    .expect("Failed to create COLLECTION_CACHE_MISSES counter");
+    pub static ref COLLECTOR_STAGE_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "repliagent_collector_stage_duration",
+            "Duration (in seconds) of each stage of datastore collection operations"
+        ),
+        &["service", "operation", "stage"],
+    )
+    .expect("Failed to create COLLECTOR_STAGE_DURATION histogram");
+    pub static ref CONCURRENCY_ACTIVE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "repliagent_concurrency_active",
+            "Number of calls in progress for operations with limited concurrency",
+        ),
+        &["operation"],
+    )
+    .expect("Failed to create CONCURRENCY_ACTIVE gauge");
+    pub static ref CONCURRENCY_REJECTED: CounterVec = CounterVec::new(
+        Opts::new(
+            "repliagent_concurrency_rejected",
+            "Number of calls rejected by the concurrency limiter",
+        ),
+        &["operation"],
+    )
+    .expect("Failed to create CONCURRENCY_REJECTED counter");
+    pub static ref DATASTORE_CLOCK_SKEW: Gauge = Gauge::new(
+        "repliagent_datastore_clock_skew_seconds",
+        "Skew of the datastore clock compared to the agent clock (positive if ahead, NaN if unknown)",
+    )
+    .expect("Failed to create DATASTORE_CLOCK_SKEW gauge");
+    pub static ref DATASTORE_VERSION_UNSUPPORTED: Gauge = Gauge::new(
+        "repliagent_datastore_version_unsupported",
+        "Set to 1 while the datastore version is outside the supported range",
+    )
+    .expect("Failed to create DATASTORE_VERSION_UNSUPPORTED gauge");
+    pub static ref POOL_CHECKOUT_ERRORS: CounterVec = CounterVec::new(
+        Opts::new(
+            "repliagent_pool_checkout_errors",
+            "Number of failed attempts to get a connection from a datastore client pool",
+        ),
+        &["pool"],
+    )
+    .expect("Failed to create POOL_CHECKOUT_ERRORS counter");
+    pub static ref POOL_CONNECTIONS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "repliagent_pool_connections",
+            "Number of datastore client pool connections by state",
+        ),
+        &["pool", "state"],
+    )
+    .expect("Failed to create POOL_CONNECTIONS gauge");
+    pub static ref POOL_WAIT_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "repliagent_pool_wait_duration",
+            "Time (in seconds) spent waiting for a datastore client pool connection (approximate)"
+        ),
+        &["pool"],
+    )
+    .expect("Failed to create POOL_WAIT_DURATION histogram");
+    #[cfg(feature = "api")]
     pub static ref REQUESTS: MetricsCollector = MetricsCollector::new("repliagent");
     pub static ref SQLITE_CONNECTION_ERRORS: Counter = Counter::new(
         "repliagent_sqlite_connection_errors",
@@ -69,11 +241,200 @@ lazy_static! {
         &["operation"],
     )
     .expect("Failed to create SQLITE_OPS_DURATION histogram");
+    pub static ref SQLITE_OPEN_TRANSACTIONS: OpenTransactions = OpenTransactions::new();
+    pub static ref SQLITE_TRANSACTION_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "repliagent_sqlite_transaction_duration",
+            "Duration (in seconds) SQLite transactions were open for"
+        ),
+        &["outcome"],
+    )
+    .expect("Failed to create SQLITE_TRANSACTION_DURATION histogram");
+    pub static ref SHARD_ROLE_CHANGES: CounterVec = CounterVec::new(
+        Opts::new(
+            "repliagent_shard_role_changes",
+            "Number of shard role changes observed between shard collections",
+        ),
+        &["previous", "current"],
+    )
+    .expect("Failed to create SHARD_ROLE_CHANGES counter");
+    pub static ref STORE_DEGRADED: Gauge = Gauge::new(
+        "repliagent_store_degraded",
+        "Set to 1 while the store is failing to persist writes",
+    )
+    .expect("Failed to create STORE_DEGRADED gauge");
+    pub static ref STORE_MIGRATIONS_APPLIED: Gauge = Gauge::new(
+        "repliagent_store_migrations_applied",
+        "Number of store schema migrations applied",
+    )
+    .expect("Failed to create STORE_MIGRATIONS_APPLIED gauge");
+    pub static ref STORE_MIGRATIONS_PENDING: Gauge = Gauge::new(
+        "repliagent_store_migrations_pending",
+        "Number of store schema migrations known to the agent but not applied",
+    )
+    .expect("Failed to create STORE_MIGRATIONS_PENDING gauge");
     pub static ref UPDATE_AVAILABLE: Gauge = Gauge::new(
         "repliagent_updateable",
         "Set to 1 when an updateded version is available (checked at start only)",
     )
     .expect("Failed to create UPDATE_AVAILABLE gauge");
+    pub static ref UPDATE_INFO: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "repliagent_update_info",
+            "Versions found by the update checker (always set to 1)",
+        ),
+        &["channel", "current", "latest"],
+    )
+    .expect("Failed to create UPDATE_INFO gauge");
+    pub static ref VERSIONED_AGENT_MADE: CounterVec = CounterVec::new(
+        Opts::new(
+            "repliagent_versioned_agent_made",
+            "Number of agents made for a detected datastore version",
+        ),
+        &["datastore", "agent_version", "detection"],
+    )
+    .expect("Failed to create VERSIONED_AGENT_MADE counter");
+}
+
+/// Collector for agent process metrics, to alert on leaking agents.
+///
+/// Extends the standard prometheus `ProcessCollector` (`process_*` metrics for CPU,
+/// resident memory, open FDs, threads, ...) with the agent uptime.
+/// Values are refreshed every time metrics are collected.
+pub struct AgentProcessCollector {
+    descs: Vec<Desc>,
+    process: ProcessCollector,
+    started: Instant,
+    uptime: Gauge,
+}
+
+impl AgentProcessCollector {
+    pub fn new() -> AgentProcessCollector {
+        let process = ProcessCollector::for_self();
+        let uptime = Gauge::new(
+            "repliagent_process_uptime_seconds",
+            "Time (in seconds) since the agent started",
+        )
+        .expect("Failed to create repliagent_process_uptime_seconds gauge");
+        let mut descs: Vec<Desc> = process.desc().into_iter().cloned().collect();
+        descs.extend(uptime.desc().into_iter().cloned());
+        AgentProcessCollector {
+            descs,
+            process,
+            started: Instant::now(),
+            uptime,
+        }
+    }
+}
+
+impl Default for AgentProcessCollector {
+    fn default() -> AgentProcessCollector {
+        AgentProcessCollector::new()
+    }
+}
+
+impl Collector for AgentProcessCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.uptime.set(self.started.elapsed().as_secs_f64());
+        let mut families = self.process.collect();
+        families.extend(self.uptime.collect());
+        families
+    }
+}
+
+/// Track open store transactions to report the longest running one.
+///
+/// Long running transactions block other writers so the age of the oldest open
+/// transaction helps diagnose stalls of the actions engine.
+#[derive(Clone)]
+pub struct OpenTransactions {
+    longest: Gauge,
+    next_id: Arc<AtomicU64>,
+    open: Arc<Mutex<HashMap<u64, Instant>>>,
+}
+
+impl OpenTransactions {
+    pub fn new() -> OpenTransactions {
+        let longest = Gauge::new(
+            "repliagent_sqlite_transaction_longest_open",
+            "Time (in seconds) the oldest SQLite transaction currently open has been open for",
+        )
+        .expect("Failed to create SQLITE_OPEN_TRANSACTIONS gauge");
+        OpenTransactions {
+            longest,
+            next_id: Arc::new(AtomicU64::new(0)),
+            open: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Track a transaction until the returned guard is dropped.
+    pub fn open(&self) -> OpenTransaction {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let since = Instant::now();
+        self.open
+            .lock()
+            .expect("OpenTransactions lock poisoned")
+            .insert(id, since);
+        OpenTransaction {
+            id,
+            since,
+            tracker: self.clone(),
+        }
+    }
+
+    /// Time the oldest open transaction has been open for.
+    pub fn longest(&self) -> Duration {
+        self.open
+            .lock()
+            .expect("OpenTransactions lock poisoned")
+            .values()
+            .map(|since| since.elapsed())
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+impl Default for OpenTransactions {
+    fn default() -> OpenTransactions {
+        OpenTransactions::new()
+    }
+}
+
+impl Collector for OpenTransactions {
+    fn desc(&self) -> Vec<&Desc> {
+        self.longest.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.longest.set(self.longest().as_secs_f64());
+        self.longest.collect()
+    }
+}
+
+/// Guard for a transaction tracked by `OpenTransactions`.
+pub struct OpenTransaction {
+    id: u64,
+    since: Instant,
+    tracker: OpenTransactions,
+}
+
+impl OpenTransaction {
+    /// Time the transaction has been open for.
+    pub fn elapsed(&self) -> Duration {
+        self.since.elapsed()
+    }
+}
+
+impl Drop for OpenTransaction {
+    fn drop(&mut self) {
+        if let Ok(mut open) = self.tracker.open.lock() {
+            open.remove(&self.id);
+        }
+    }
 }
 
 /// Attemps to register metrics with the Registry.
@@ -82,6 +443,7 @@ lazy_static! {
 pub fn register_metrics(context: &AgentContext) {
     let logger = &context.logger;
     let registry = &context.metrics;
+    #[cfg(feature = "api")]
     REQUESTS.register(logger, registry);
     if let Err(error) = registry.register(Box::new(ACTION_COUNT.clone())) {
         debug!(logger, "Failed to register ACTION_COUNT"; "error" => ?error);
@@ -92,6 +454,60 @@ pub fn register_metrics(context: &AgentContext) {
     if let Err(error) = registry.register(Box::new(ACTION_ERRORS.clone())) {
         debug!(logger, "Failed to register ACTION_ERRORS"; "error" => ?error);
     }
+    if let Err(error) = registry.register(Box::new(ACTIONS_ENGINE_LAG.clone())) {
+        debug!(logger, "Failed to register ACTIONS_ENGINE_LAG"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(ACTIONS_ENGINE_LAG_ERRORS.clone())) {
+        debug!(logger, "Failed to register ACTIONS_ENGINE_LAG_ERRORS"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(BLOCKING_ACTIVE.clone())) {
+        debug!(logger, "Failed to register BLOCKING_ACTIVE"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(BLOCKING_QUEUED.clone())) {
+        debug!(logger, "Failed to register BLOCKING_QUEUED"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(BLOCKING_REJECTED.clone())) {
+        debug!(logger, "Failed to register BLOCKING_REJECTED"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(BLOCKING_WAIT_DURATION.clone())) {
+        debug!(logger, "Failed to register BLOCKING_WAIT_DURATION"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(BREAKER_REJECTED.clone())) {
+        debug!(logger, "Failed to register BREAKER_REJECTED"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(BREAKER_STATE.clone())) {
+        debug!(logger, "Failed to register BREAKER_STATE"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(COLLECTION_CACHE_HITS.clone())) {
+        debug!(logger, "Failed to register COLLECTION_CACHE_HITS"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(COLLECTION_CACHE_MISSES.clone())) {
+        debug!(logger, "Failed to register COLLECTION_CACHE_MISSES"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(COLLECTOR_STAGE_DURATION.clone())) {
+        debug!(logger, "Failed to register COLLECTOR_STAGE_DURATION"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(CONCURRENCY_ACTIVE.clone())) {
+        debug!(logger, "Failed to register CONCURRENCY_ACTIVE"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(CONCURRENCY_REJECTED.clone())) {
+        debug!(logger, "Failed to register CONCURRENCY_REJECTED"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(DATASTORE_CLOCK_SKEW.clone())) {
+        debug!(logger, "Failed to register DATASTORE_CLOCK_SKEW"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(DATASTORE_VERSION_UNSUPPORTED.clone())) {
+        debug!(logger, "Failed to register DATASTORE_VERSION_UNSUPPORTED"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(POOL_CHECKOUT_ERRORS.clone())) {
+        debug!(logger, "Failed to register POOL_CHECKOUT_ERRORS"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(POOL_CONNECTIONS.clone())) {
+        debug!(logger, "Failed to register POOL_CONNECTIONS"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(POOL_WAIT_DURATION.clone())) {
+        debug!(logger, "Failed to register POOL_WAIT_DURATION"; "error" => ?error);
+    }
     if let Err(error) = registry.register(Box::new(SQLITE_OP_ERRORS_COUNT.clone())) {
         debug!(logger, "Failed to register SQLITE_OP_ERRORS_COUNT"; "error" => ?error);
     }
@@ -101,7 +517,132 @@ pub fn register_metrics(context: &AgentContext) {
     if let Err(error) = registry.register(Box::new(SQLITE_OPS_DURATION.clone())) {
         debug!(logger, "Failed to register SQLITE_OPS_DURATION"; "error" => ?error);
     }
+    if let Err(error) = registry.register(Box::new(SQLITE_OPEN_TRANSACTIONS.clone())) {
+        debug!(logger, "Failed to register SQLITE_OPEN_TRANSACTIONS"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(SQLITE_TRANSACTION_DURATION.clone())) {
+        debug!(logger, "Failed to register SQLITE_TRANSACTION_DURATION"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(SHARD_ROLE_CHANGES.clone())) {
+        debug!(logger, "Failed to register SHARD_ROLE_CHANGES"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(STORE_DEGRADED.clone())) {
+        debug!(logger, "Failed to register STORE_DEGRADED"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(STORE_MIGRATIONS_APPLIED.clone())) {
+        debug!(logger, "Failed to register STORE_MIGRATIONS_APPLIED"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(STORE_MIGRATIONS_PENDING.clone())) {
+        debug!(logger, "Failed to register STORE_MIGRATIONS_PENDING"; "error" => ?error);
+    }
     if let Err(error) = registry.register(Box::new(UPDATE_AVAILABLE.clone())) {
         debug!(logger, "Failed to register UPDATE_AVAILABLE"; "error" => ?error);
     }
+    if let Err(error) = registry.register(Box::new(UPDATE_INFO.clone())) {
+        debug!(logger, "Failed to register UPDATE_INFO"; "error" => ?error);
+    }
+    if let Err(error) = registry.register(Box::new(VERSIONED_AGENT_MADE.clone())) {
+        debug!(logger, "Failed to register VERSIONED_AGENT_MADE"; "error" => ?error);
+    }
+}
+
+/// Check static `metrics_labels` against the labels of registered metrics.
+///
+/// Agent metrics are registered at creation time so this is called once the agent
+/// is initialised to catch clashes with agent specific labels.
+/// Only metrics with values can be inspected, `RESERVED_LABELS` covers the others.
+pub fn check_static_labels(registry: &Registry, labels: &BTreeMap<String, String>) -> Result<()> {
+    for family in registry.gather() {
+        for metric in family.get_metric() {
+            let mut seen = HashSet::new();
+            for label in metric.get_label() {
+                let name = label.get_name();
+                if !seen.insert(name) && labels.contains_key(name) {
+                    let error = format!(
+                        "'{}' is already a label of metric {}",
+                        name,
+                        family.get_name()
+                    );
+                    return Err(ErrorKind::ConfigInvalid("metrics_labels", error).into());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::collections::HashMap;
+
+    use prometheus::core::Collector;
+    use prometheus::CounterVec;
+    use prometheus::Opts;
+    use prometheus::Registry;
+
+    use super::check_static_labels;
+    use super::AgentProcessCollector;
+    use super::OpenTransactions;
+
+    fn registry_with_counter(labels: &BTreeMap<String, String>, label: &str) -> Registry {
+        let common: HashMap<String, String> = labels.clone().into_iter().collect();
+        let registry = Registry::new_custom(None, Some(common)).unwrap();
+        let counter = CounterVec::new(Opts::new("test_total", "Test counter"), &[label]).unwrap();
+        counter.with_label_values(&["value"]).inc();
+        registry.register(Box::new(counter)).unwrap();
+        registry
+    }
+
+    #[test]
+    fn static_labels_are_gathered() {
+        let mut labels = BTreeMap::new();
+        labels.insert("cluster".to_string(), "mongo-prod-1".to_string());
+        let registry = registry_with_counter(&labels, "agent_label");
+        assert!(check_static_labels(&registry, &labels).is_ok());
+
+        let families = registry.gather();
+        let pairs: Vec<(&str, &str)> = families[0].get_metric()[0]
+            .get_label()
+            .iter()
+            .map(|label| (label.get_name(), label.get_value()))
+            .collect();
+        assert!(pairs.contains(&("cluster", "mongo-prod-1")));
+        assert!(pairs.contains(&("agent_label", "value")));
+    }
+
+    #[test]
+    fn static_labels_clash() {
+        let mut labels = BTreeMap::new();
+        labels.insert("agent_label".to_string(), "static".to_string());
+        let registry = registry_with_counter(&labels, "agent_label");
+        let error = check_static_labels(&registry, &labels).unwrap_err();
+        assert_eq!(error.kind().code(), "ConfigInvalid");
+    }
+
+    #[test]
+    fn longest_open_transaction() {
+        let tracker = OpenTransactions::new();
+        let first = tracker.open();
+        let second = tracker.open();
+        let longest = tracker.longest();
+        assert!(first.elapsed() >= longest);
+        drop(first);
+        assert!(tracker.longest() <= second.elapsed());
+        drop(second);
+        assert_eq!(tracker.longest().as_secs_f64(), 0.0);
+    }
+
+    #[test]
+    fn collect_process_metrics() {
+        let collector = AgentProcessCollector::new();
+        let families = collector.collect();
+        let names: Vec<&str> = families.iter().map(|family| family.get_name()).collect();
+        assert!(names.contains(&"repliagent_process_uptime_seconds"));
+        if cfg!(target_os = "linux") {
+            assert!(names.contains(&"process_resident_memory_bytes"));
+            assert!(names.contains(&"process_open_fds"));
+            assert!(names.contains(&"process_threads"));
+        }
+    }
 }
@@ -0,0 +1,69 @@
+//! HTTP clients for outbound requests made by the agent.
+//!
+//! Operator networks often only allow egress through proxies so all outbound
+//! HTTP requests should use clients created here to honour the `proxy` configuration.
+use reqwest::blocking::Client;
+use reqwest::NoProxy;
+use reqwest::Proxy;
+
+use crate::config::ProxyConfig;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// Create a blocking HTTP client using the configured proxies.
+///
+/// When no proxy is configured the standard proxy environment variables are used.
+pub fn http_client(config: &ProxyConfig) -> Result<Client> {
+    let mut builder = Client::builder();
+    if config.is_configured() {
+        let no_proxy = NoProxy::from_string(&config.no_proxy.join(","));
+        if let Some(url) = &config.http {
+            let proxy = Proxy::http(url)
+                .with_context(|_| ErrorKind::ConfigInvalid("proxy.http", url.clone()))?
+                .no_proxy(no_proxy.clone());
+            builder = builder.proxy(proxy);
+        }
+        if let Some(url) = &config.https {
+            let proxy = Proxy::https(url)
+                .with_context(|_| ErrorKind::ConfigInvalid("proxy.https", url.clone()))?
+                .no_proxy(no_proxy);
+            builder = builder.proxy(proxy);
+        }
+    }
+    let client = builder
+        .build()
+        .with_context(|_| ErrorKind::Initialisation("HTTP client configuration failed".into()))?;
+    Ok(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::http_client;
+    use crate::config::ProxyConfig;
+
+    #[test]
+    fn client_with_proxies() {
+        let config = ProxyConfig {
+            http: Some("http://proxy.example.com:3128".into()),
+            https: Some("http://proxy.example.com:3128".into()),
+            no_proxy: vec!["localhost".into(), ".example.com".into()],
+        };
+        assert!(http_client(&config).is_ok());
+    }
+
+    #[test]
+    fn client_without_proxies() {
+        assert!(http_client(&ProxyConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn invalid_proxy_url() {
+        let config = ProxyConfig {
+            https: Some("http://bad host:3128".into()),
+            ..ProxyConfig::default()
+        };
+        let error = http_client(&config).unwrap_err();
+        assert_eq!(error.kind().code(), "ConfigInvalid");
+    }
+}
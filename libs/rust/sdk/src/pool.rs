@@ -0,0 +1,143 @@
+//! Metrics for datastore client connection pools.
+//!
+//! Agents report events from their datastore client pool to a `PoolMetrics`
+//! so all agents export pool usage with the same metrics:
+//!
+//!   * `repliagent_pool_connections{pool, state}`: connections in use or idle.
+//!   * `repliagent_pool_wait_duration{pool}`: time spent waiting for a connection.
+//!   * `repliagent_pool_checkout_errors{pool}`: failed attempts to get a connection.
+//!
+//! Wait times are approximate: pool events don't say which request they refer to
+//! so each connection handed out (or checkout failure) is matched with the oldest
+//! pending request.
+//! This is exact for pools that serve requests in order but pools can serve requests
+//! out of order, for example when a newer request times out before an older one is served.
+//! In that case the number of observations is still correct but individual waits
+//! are attributed to the wrong request.
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use prometheus::Counter;
+use prometheus::Histogram;
+use prometheus::IntGauge;
+
+use crate::metrics::POOL_CHECKOUT_ERRORS;
+use crate::metrics::POOL_CONNECTIONS;
+use crate::metrics::POOL_WAIT_DURATION;
+
+/// Track the usage of a datastore client connection pool.
+///
+/// Gauges are updated incrementally so pools replaced at runtime (for example after
+/// the datastore address is discovered again) keep reporting accurate totals as
+/// long as the old pool reports its connections closing.
+pub struct PoolMetrics {
+    errors: Counter,
+    idle: IntGauge,
+    in_use: IntGauge,
+    wait: Histogram,
+    waiting: Mutex<VecDeque<Instant>>,
+}
+
+impl PoolMetrics {
+    /// Track a pool, identified in metrics by the given name.
+    pub fn new(pool: &str) -> PoolMetrics {
+        PoolMetrics {
+            errors: POOL_CHECKOUT_ERRORS.with_label_values(&[pool]),
+            idle: POOL_CONNECTIONS.with_label_values(&[pool, "idle"]),
+            in_use: POOL_CONNECTIONS.with_label_values(&[pool, "in_use"]),
+            wait: POOL_WAIT_DURATION.with_label_values(&[pool]),
+            waiting: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// A connection was requested from the pool.
+    pub fn checkout_started(&self) {
+        self.waiting
+            .lock()
+            .expect("PoolMetrics lock poisoned")
+            .push_back(Instant::now());
+    }
+
+    /// A connection was handed out by the pool.
+    pub fn checked_out(&self) {
+        self.observe_wait();
+        self.idle.dec();
+        self.in_use.inc();
+    }
+
+    /// A connection could not be handed out by the pool.
+    pub fn checkout_failed(&self) {
+        self.observe_wait();
+        self.errors.inc();
+    }
+
+    /// A connection was returned to the pool.
+    pub fn checked_in(&self) {
+        self.in_use.dec();
+        self.idle.inc();
+    }
+
+    /// The pool opened a new connection.
+    pub fn connection_created(&self) {
+        self.idle.inc();
+    }
+
+    /// The pool closed an idle connection.
+    pub fn connection_closed(&self) {
+        self.idle.dec();
+    }
+
+    /// Record the wait of the oldest pending request.
+    ///
+    /// Assumes the oldest pending request is the one being served,
+    /// see the module documentation for when this is not the case.
+    fn observe_wait(&self) {
+        let started = self
+            .waiting
+            .lock()
+            .expect("PoolMetrics lock poisoned")
+            .pop_front();
+        if let Some(started) = started {
+            self.wait.observe(started.elapsed().as_secs_f64());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PoolMetrics;
+
+    #[test]
+    fn track_connections() {
+        let metrics = PoolMetrics::new("test");
+        metrics.connection_created();
+        metrics.connection_created();
+        metrics.checkout_started();
+        metrics.checked_out();
+        assert_eq!(metrics.idle.get(), 1);
+        assert_eq!(metrics.in_use.get(), 1);
+        assert_eq!(metrics.wait.get_sample_count(), 1);
+        metrics.checked_in();
+        metrics.connection_closed();
+        assert_eq!(metrics.idle.get(), 1);
+        assert_eq!(metrics.in_use.get(), 0);
+    }
+
+    #[test]
+    fn wait_observed_once_per_request() {
+        let metrics = PoolMetrics::new("test-wait");
+        metrics.connection_created();
+        metrics.checkout_started();
+        metrics.checkout_started();
+        metrics.checkout_failed();
+        metrics.checked_out();
+        assert_eq!(metrics.wait.get_sample_count(), 2);
+        assert!(metrics.waiting.lock().unwrap().is_empty());
+
+        // Events without a pending request are not observed.
+        metrics.checked_in();
+        metrics.checked_out();
+        assert_eq!(metrics.wait.get_sample_count(), 2);
+    }
+}
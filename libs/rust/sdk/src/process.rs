@@ -1,17 +1,23 @@
 use std::borrow::Cow;
+use std::cmp::min;
 use std::collections::BTreeMap;
 use std::env;
 use std::process::exit;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
+use chrono::Utc;
 use clap::Arg;
+use clap::ArgAction;
+use clap::ArgMatches;
 use clap::Command;
-use failure::ResultExt;
 use humthreads::Builder;
-use prometheus::process_collector::ProcessCollector;
 use semver::Version;
 use sentry::ClientInitGuard;
 use sentry::IntoDsn;
-use serde::Deserialize;
+use serde_json::Value as Json;
 use slog::debug;
 use slog::info;
 use slog::warn;
@@ -24,20 +30,49 @@ use replicante_util_failure::format_fail;
 use replicante_util_tracing::tracer;
 use replicante_util_upkeep::Upkeep;
 
+#[cfg(feature = "actions")]
 use crate::actions;
+#[cfg(feature = "api")]
 use crate::api;
+use crate::clock;
 use crate::config::Agent as Config;
+use crate::config::ConfigLoader;
+use crate::config::ProxyConfig;
 use crate::config::SentryConfig;
-use crate::metrics::UPDATE_AVAILABLE;
+use crate::config::UpdatesConfig;
+use crate::fail_compat;
+#[cfg(feature = "store")]
+use crate::heartbeat;
+use crate::metrics::AgentProcessCollector;
+use crate::runtime::AgentRuntime;
+use crate::shards;
+#[cfg(feature = "store")]
+use crate::store::backend_factory;
+#[cfg(feature = "store")]
+use crate::store::Store;
+use crate::updates;
+use crate::updates::UpdateStatus;
+use crate::updates::VersionMeta;
 use crate::Agent;
 use crate::AgentContext;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
 /// Configure a command line parser.
 ///
 /// The parser is configure with all the arguments every agent is required to implement.
 /// Additional arguments can be added by each agent if needed.
+///
+/// Operational tasks are available as subcommands shared by all agents:
+///
+///   * `run`: run the agent (the default when no subcommand is given).
+///   * `check-config`: load and validate the configuration and exit.
+///   * `version [--json]`: print the agent version and build details and exit.
+///   * `migrate [--down-to TAG]`: apply (or revert) agent store migrations and exit.
+///   * `backup-store PATH` and `restore-store PATH`: snapshot or replace the agent store.
+///
+/// Agents handle subcommands with `version_command` and `commands`.
 pub fn clap<S1, S2, S3, S4>(
     name: S1,
     version: S2,
@@ -50,16 +85,164 @@ where
     S3: Into<clap::builder::StyledStr>,
     S4: Into<clap::builder::OsStr>,
 {
-    Command::new(name).version(version).about(description).arg(
-        Arg::new("config")
-            .short('c')
-            .long("config")
-            .value_name("FILE")
-            .num_args(1)
-            .default_value(default_config_location)
-            .value_parser(clap::value_parser!(String))
-            .help("Specifies the configuration file to use"),
-    )
+    let command = Command::new(name)
+        .version(version)
+        .about(description)
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .num_args(1)
+                .default_value(default_config_location)
+                .global(true)
+                .value_parser(clap::value_parser!(String))
+                .help("Specifies the configuration file to use"),
+        )
+        .arg(
+            Arg::new("print-config")
+                .long("print-config")
+                .action(ArgAction::SetTrue)
+                .help("Print the configuration, with the source of each option, and exit"),
+        )
+        .arg(
+            Arg::new("set")
+                .long("set")
+                .value_name("PATH=VALUE")
+                .action(ArgAction::Append)
+                .global(true)
+                .value_parser(clap::value_parser!(String))
+                .help("Override a configuration option (for example agent.api.bind=0.0.0.0:8000)"),
+        )
+        .subcommand(Command::new("run").about("Run the agent (default)"))
+        .subcommand(Command::new("check-config").about("Validate the configuration and exit"))
+        .subcommand(
+            Command::new("version")
+                .about("Print the agent version and build details and exit")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Print build details as JSON"),
+                ),
+        );
+    #[cfg(feature = "store")]
+    let command = command
+        .arg(
+            Arg::new("migrate-down-to")
+                .long("migrate-down-to")
+                .value_name("TAG")
+                .num_args(1)
+                .value_parser(clap::value_parser!(String))
+                .help(
+                    "Revert store migrations applied after TAG and exit (before agent rollbacks)",
+                ),
+        )
+        .arg(
+            Arg::new("store-backup")
+                .long("store-backup")
+                .value_name("PATH")
+                .num_args(1)
+                .value_parser(clap::value_parser!(String))
+                .help("Write a snapshot of the agent store to PATH and exit"),
+        )
+        .arg(
+            Arg::new("store-restore")
+                .long("store-restore")
+                .value_name("PATH")
+                .num_args(1)
+                .value_parser(clap::value_parser!(String))
+                .help("Replace the agent store with the snapshot at PATH and exit"),
+        )
+        .subcommand(
+            Command::new("migrate")
+                .about("Apply agent store migrations and exit")
+                .arg(
+                    Arg::new("down-to")
+                        .long("down-to")
+                        .value_name("TAG")
+                        .num_args(1)
+                        .value_parser(clap::value_parser!(String))
+                        .help("Revert store migrations applied after TAG instead"),
+                ),
+        )
+        .subcommand(
+            Command::new("backup-store")
+                .about("Write a snapshot of the agent store to PATH and exit")
+                .arg(store_path_arg()),
+        )
+        .subcommand(
+            Command::new("restore-store")
+                .about("Replace the agent store with the snapshot at PATH and exit")
+                .arg(store_path_arg()),
+        );
+    command
+}
+
+/// Run the operational subcommand requested on the command line, if any.
+///
+/// Agents call this once the configuration is loaded and exit if a command was performed.
+/// Subcommands that do not need the configuration are handled by `version_command`.
+///
+/// The `validate` callback checks the agent specific sections of the configuration
+/// and is only invoked by `check-config`, after the SDK configuration is validated.
+pub fn commands<V>(args: &ArgMatches, config: &Config, validate: V) -> Result<bool>
+where
+    V: FnOnce() -> Result<()>,
+{
+    if let Some(("check-config", _)) = args.subcommand() {
+        config.validate()?;
+        validate()?;
+        println!("Configuration is valid");
+        return Ok(true);
+    }
+    store_commands(args, config)
+}
+
+/// Print the agent version and build details if requested on the command line.
+///
+/// Agents call this before loading the configuration and exit if it returns `true`.
+/// Build details are the ones registered with `build::register`.
+pub fn version_command(args: &ArgMatches) -> bool {
+    let args = match args.subcommand() {
+        Some(("version", args)) => args,
+        _ => return false,
+    };
+    let build = crate::build::current();
+    if args.get_flag("json") {
+        let build = serde_json::to_string_pretty(&build).expect("BuildInfo to encode as JSON");
+        println!("{}", build);
+    } else {
+        println!("{}", build.display());
+        println!("Built: {}", build.build_date.unwrap_or("unknown"));
+        println!("Compiler: {}", build.rustc.unwrap_or("unknown"));
+        println!("Features: {}", build.features.join(", "));
+    }
+    true
+}
+
+#[cfg(feature = "store")]
+fn store_path_arg() -> Arg {
+    Arg::new("path")
+        .value_name("PATH")
+        .required(true)
+        .value_parser(clap::value_parser!(String))
+}
+
+/// Configure a layered configuration loader from the command line arguments.
+///
+/// Options are loaded from the agent defaults, the configuration file,
+/// environment variables with the given prefix and `--set` arguments, in this order.
+pub fn config_loader(args: &ArgMatches, env_prefix: &str, defaults: Json) -> Result<ConfigLoader> {
+    let config_location: &String = args
+        .get_one("config")
+        .expect("CLI arguments to have a config value");
+    let overrides = args.get_many::<String>("set").unwrap_or_default();
+    ConfigLoader::new()
+        .defaults(defaults)
+        .file(config_location)?
+        .env(env_prefix)
+        .cli(overrides)
 }
 
 /// Main logic for the `run` function.
@@ -76,24 +259,56 @@ where
     A: Agent + 'static,
     F: FnOnce(&AgentContext, &mut Upkeep) -> Result<A>,
 {
+    let build = crate::build::current();
+    info!(
+        logger, "Starting {}", service;
+        "version" => build.version,
+        "git_hash" => build.git_hash,
+        "git_taint" => build.git_taint,
+        "build_date" => build.build_date.unwrap_or("unknown"),
+        "rustc" => build.rustc.unwrap_or("unknown"),
+        "features" => build.features.join(","),
+    );
+    config.validate()?;
+    for warning in crate::config::warnings() {
+        warning.log(&logger);
+    }
     let mut upkeep = Upkeep::new();
     upkeep.set_logger(logger.clone());
     upkeep
         .register_signal()
+        .map_err(fail_compat)
         .with_context(|_| ErrorKind::Initialisation("signal handler registration failed".into()))?;
 
     let tracer_opts = replicante_util_tracing::Opts::new(service, logger.clone(), &mut upkeep);
     let tracer = tracer(config.tracing.clone(), tracer_opts)
-        .map_err(crate::AnyWrap::from)
         .with_context(|_| ErrorKind::Initialisation("tracer configuration failed".into()))?;
 
+    #[cfg_attr(not(feature = "actions"), allow(unused_mut))]
     let mut context = AgentContext::new(config, logger.clone(), tracer)?;
+    context.datastore_pool.register_threads(&mut upkeep);
     register_process_metrics(&context);
     super::register_metrics(&context);
-    context.store.migrate()?;
+    #[cfg(feature = "store")]
+    {
+        context.store.migrate()?;
+        heartbeat::spawn(context.clone(), &mut upkeep)?;
+    }
     let agent = initialise(&context, &mut upkeep)?;
-    actions::initialise(&agent, &mut context, &mut upkeep)?;
-    api::spawn_server(agent, context, &mut upkeep)?;
+    probe_datastore(&context, &agent)?;
+    crate::metrics::check_static_labels(&context.metrics, &context.config.metrics_labels)?;
+    let agent: Arc<dyn Agent> = Arc::new(agent);
+    #[cfg_attr(not(any(feature = "actions", feature = "api")), allow(unused_mut))]
+    let mut runtime = AgentRuntime::new()?;
+    #[cfg(feature = "actions")]
+    actions::initialise(&*agent, &mut context, &mut runtime)?;
+    clock::spawn(Arc::clone(&agent), context.clone(), &mut upkeep)?;
+    shards::spawn(Arc::clone(&agent), context.clone(), &mut upkeep)?;
+    let sandbox = context.config.sandbox.clone();
+    #[cfg(feature = "api")]
+    api::spawn_server(agent, context, &mut runtime)?;
+    runtime.run(&mut upkeep)?;
+    crate::sandbox::apply(&sandbox, &logger)?;
     let clean_exit = upkeep.keepalive();
     if clean_exit {
         info!(logger, "Agent stopped gracefully");
@@ -104,6 +319,59 @@ where
     Ok(clean_exit)
 }
 
+/// Wait for the datastore to respond to the agent before starting background tasks.
+///
+/// Agents are probed with `Agent::datastore_info` so `VersionedAgent`s get a chance
+/// to replace the default agent made while the datastore was unavailable.
+/// Nothing is checked when `startup.wait_for_datastore` is 0 so agents can start
+/// while the datastore is down, as they always have.
+fn probe_datastore(context: &AgentContext, agent: &dyn Agent) -> Result<()> {
+    if context.config.startup.wait_for_datastore == 0 {
+        return Ok(());
+    }
+    wait_for_datastore(context, || {
+        let mut span = context.tracer.span("startup.datastore").auto_finish();
+        agent.datastore_info(&mut span).map(drop)
+    })
+}
+
+/// Connect to the datastore, retrying for a while if it is not available.
+///
+/// Only errors flagged as retryable are retried, until the configured
+/// `startup.wait_for_datastore` window expires, with exponential backoff between attempts.
+///
+/// The `connect` function may be called more than once so it should only attempt
+/// to reach the datastore: registering metrics, threads, or other side effects
+/// belong in the agent initialisation after the connection is established.
+pub fn wait_for_datastore<T, F>(context: &AgentContext, mut connect: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let startup = &context.config.startup;
+    let deadline = Instant::now() + Duration::from_secs(startup.wait_for_datastore);
+    let max_delay = Duration::from_secs(startup.max_retry_delay);
+    let mut delay = Duration::from_secs(1);
+    loop {
+        let error = match connect() {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        let now = Instant::now();
+        if !error.kind().retryable() || now >= deadline {
+            return Err(error);
+        }
+        let wait = min(delay, deadline - now);
+        warn!(
+            context.logger,
+            "Unable to reach the datastore, retrying while waiting for it";
+            "retry_in" => ?wait,
+            failure_info(&error.to_fail()),
+        );
+        thread::sleep(wait);
+        delay = min(delay * 2, max_delay);
+    }
+}
+
 /// Configure and instantiate the logger.
 pub fn logger(config: &Config) -> (Logger, GlobalLoggerGuard) {
     let logger_opts = ::replicante_logging::Opts::new(env!("GIT_BUILD_HASH").into());
@@ -129,7 +397,7 @@ where
     let result = run();
     match result {
         Err(error) => {
-            let message = format_fail(&error);
+            let message = format_fail(&error.to_fail());
             eprintln!("{}", message);
             exit(1);
         }
@@ -138,10 +406,10 @@ where
     };
 }
 
-/// Register default process metrics.
+/// Register default process metrics, extended with the agent uptime.
 pub fn register_process_metrics(context: &AgentContext) {
     let logger = &context.logger;
-    let process = ProcessCollector::for_self();
+    let process = AgentProcessCollector::new();
     let registry = &context.metrics;
     if let Err(error) = registry.register(Box::new(process)) {
         debug!(logger, "Failed to register process metrics"; "error" => ?error);
@@ -151,6 +419,10 @@ pub fn register_process_metrics(context: &AgentContext) {
 /// Run the agent process.
 ///
 /// This function initialises all needed components and pipes them together.
+/// The `initialise` function is called once: agents that connect to the datastore
+/// while initialising should wrap only that step in `wait_for_datastore`.
+/// Once initialised, the agent is probed until the datastore responds if the
+/// process is configured to wait for it (see `StartupConfig`).
 ///
 /// Once done, the process blocks until shutdown is initiated.
 /// See `replicante_util_upkeep::Upkeep` for details on blocking and shutdown.
@@ -165,21 +437,127 @@ where
     F: FnOnce(&AgentContext, &mut Upkeep) -> Result<A>,
     R: Into<Cow<'static, str>>,
 {
+    // Restrictions inherited by threads must apply before the logger starts any.
+    crate::sandbox::restrict(&config.sandbox)?;
     let (logger, _scope_guard) = logger(&config);
-    let _sentry = sentry(config.sentry.clone(), &logger, release.into())?;
+    let _sentry = sentry(
+        config.sentry.clone(),
+        &config.proxy,
+        &logger,
+        release.into(),
+    )?;
     initialise_and_run(config, logger, service, initialise).map_err(|error| {
-        // TODO: Fix error capturing after failure crate is removed.
-        let hack = anyhow::anyhow!(error.to_string());
-        sentry::integrations::anyhow::capture_anyhow(&hack);
+        sentry::capture_error(&error);
         error
     })
 }
 
+/// Run the offline store operation requested on the command line, if any.
+///
+/// Agents call this in place of `run` and exit if an operation was performed:
+///
+///   * `migrate`: apply pending store migrations.
+///   * `migrate --down-to TAG` (or `--migrate-down-to TAG`): revert store migrations
+///     applied after TAG.
+///     Older agent versions do not know how to revert migrations added after them so
+///     migrations must be reverted with the current version BEFORE rolling back the agent.
+///   * `backup-store PATH` (or `--store-backup PATH`): write a snapshot of the store to PATH.
+///   * `restore-store PATH` (or `--store-restore PATH`): replace the store with the
+///     snapshot at PATH.
+///
+/// These operations must not run while the agent is running.
+#[cfg_attr(not(feature = "store"), allow(unused_variables))]
+pub fn store_commands(args: &ArgMatches, config: &Config) -> Result<bool> {
+    #[cfg(feature = "store")]
+    {
+        match args.subcommand() {
+            Some(("migrate", args)) => {
+                match args.get_one::<String>("down-to") {
+                    Some(tag) => offline_store(config, |store| store.migrate_down(tag))?,
+                    None => offline_store(config, |store| store.migrate())?,
+                };
+                return Ok(true);
+            }
+            Some(("backup-store", args)) => {
+                let path: &String = args.get_one("path").expect("backup-store to have a path");
+                offline_store(config, |store| store.backup(path))?;
+                return Ok(true);
+            }
+            Some(("restore-store", args)) => {
+                let path: &String = args.get_one("path").expect("restore-store to have a path");
+                offline_store(config, |store| {
+                    ensure_store_idle(store, config)?;
+                    store.restore(path)
+                })?;
+                return Ok(true);
+            }
+            _ => (),
+        };
+        if let Some(tag) = args.get_one::<String>("migrate-down-to") {
+            offline_store(config, |store| store.migrate_down(tag))?;
+            return Ok(true);
+        }
+        if let Some(path) = args.get_one::<String>("store-backup") {
+            offline_store(config, |store| store.backup(path))?;
+            return Ok(true);
+        }
+        if let Some(path) = args.get_one::<String>("store-restore") {
+            offline_store(config, |store| {
+                ensure_store_idle(store, config)?;
+                store.restore(path)
+            })?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Refuse to touch a store an agent process has recently recorded a heartbeat in.
+///
+/// Agents record a heartbeat every `heartbeat.interval` seconds so a heartbeat newer
+/// than two intervals means an agent is likely still running against the store.
+#[cfg(feature = "store")]
+fn ensure_store_idle(store: &Store, config: &Config) -> Result<()> {
+    // New stores have no heartbeats table to look into.
+    if store.schema()?.applied.is_empty() {
+        return Ok(());
+    }
+    let latest =
+        store.with_transaction(|tx| tx.heartbeats().history(1, None)?.next().transpose())?;
+    let latest = match latest {
+        None => return Ok(()),
+        Some(latest) => latest,
+    };
+    let interval = config.heartbeat.interval.saturating_mul(2) as i64;
+    let age = Utc::now().signed_duration_since(latest.heartbeat_ts);
+    if age < chrono::Duration::seconds(interval) {
+        return Err(ErrorKind::PersistentInUse(latest.process_id.to_string()).into());
+    }
+    Ok(())
+}
+
+/// Run a block with the agent store outside of a running agent process.
+#[cfg(feature = "store")]
+fn offline_store<F>(config: &Config, block: F) -> Result<()>
+where
+    F: FnOnce(&mut Store) -> Result<()>,
+{
+    let (logger, _scope_guard) = logger(config);
+    let mut upkeep = Upkeep::new();
+    let tracer_opts = replicante_util_tracing::Opts::new("store", logger.clone(), &mut upkeep);
+    let tracer = tracer(config.tracing.clone(), tracer_opts)
+        .with_context(|_| ErrorKind::Initialisation("tracer configuration failed".into()))?;
+    let mut store = backend_factory(config, logger, Arc::new(tracer))?;
+    block(&mut store)
+}
+
 /// Initialise sentry integration.
 ///
 /// If sentry is configured, the panic handler is also registered.
+/// Events are sent through the configured `proxy`, if any.
 pub fn sentry(
     config: Option<SentryConfig>,
+    proxy: &ProxyConfig,
     logger: &Logger,
     release: Cow<'static, str>,
 ) -> Result<ClientInitGuard> {
@@ -195,102 +573,360 @@ pub fn sentry(
         .dsn
         .into_dsn()
         .with_context(|_| ErrorKind::Initialisation("invalid sentry configuration".into()))?;
-    let options = sentry::ClientOptions {
+    let mut options = sentry::ClientOptions {
         attach_stacktrace: true,
         dsn,
         in_app_include: vec!["replicante", "replicante_agent", "repliagent", "replisdk"],
         release: Some(release),
         ..Default::default()
     };
+    sentry_proxies(&mut options, proxy);
     let client = sentry::init(options);
     Ok(client)
 }
 
+/// Set the configured proxies on the sentry options.
+///
+/// Sentry reads the proxy environment variables by default so options are only changed
+/// for proxies set in the configuration. Sentry does not support `proxy.no_proxy`.
+fn sentry_proxies(options: &mut sentry::ClientOptions, proxy: &ProxyConfig) {
+    if let Some(url) = &proxy.http {
+        options.http_proxy = Some(url.clone().into());
+    }
+    if let Some(url) = &proxy.https {
+        options.https_proxy = Some(url.clone().into());
+    }
+}
+
 /// Check for available updates in the background.
 ///
-/// The check is performed once in a background thread that is ignored to avoid
-/// startup or shutdown delays.
+/// The check is performed in a background thread that is ignored to avoid
+/// startup or shutdown delays, once or every `updates.interval` seconds if set.
 ///
 /// The check is only performed if the `update_checker` config option is set to true.
+/// Version metadata is fetched from `updates.source`, if set, or from the agent `url`.
+/// Only releases in the configured `updates.channel` and matching the optional
+/// `updates.pin` requirement are considered.
 ///
 /// The result of the update, including any error, is reported in the logs.
+/// The outcome of the check is exposed by the `/updates` introspection endpoint
+/// and by the `repliagent_update_info` metric.
 /// If updates are available the `repliagent_upgradable` metric is also set to `1`.
 pub fn update_checker(current: Version, url: &'static str, context: &AgentContext) -> Result<()> {
+    let config = context.config.updates.clone();
+    let status = UpdateStatus::new(&current, context.config.update_checker, &config);
     if !context.config.update_checker {
+        updates::record(status);
         debug!(
             &context.logger,
             "Update checker is disabled, skipping check"
         );
         return Ok(());
     }
+    updates::record(status);
+    let client = crate::outbound::http_client(&context.config.proxy)?;
     let logger = context.logger.clone();
+    let source = config.source.clone().unwrap_or_else(|| url.to_string());
     Builder::new("r:b:update_checker")
         .full_name("replicante:base:update_checker")
         .spawn(move |scope| {
-            let _activity = scope.scoped_activity("checking for updates");
-            let response = match reqwest::blocking::get(url) {
-                Ok(response) => response,
-                Err(error) => {
-                    capture_fail!(
-                        &error,
-                        logger,
-                        "Failed to fetch latest version information";
-                        failure_info(&error)
-                    );
-                    return;
+            let mut reported = None;
+            loop {
+                {
+                    let _activity = scope.scoped_activity("checking for updates");
+                    let meta = match updates::fetch(&client, &source) {
+                        Ok(meta) => Some(meta),
+                        Err(error) => {
+                            capture_fail!(
+                                &error.to_fail(),
+                                logger,
+                                "Failed to fetch latest version information";
+                                "source" => &source,
+                                failure_info(&error.to_fail()),
+                            );
+                            None
+                        }
+                    };
+                    if let Some(meta) = meta {
+                        check_updates(&meta, &current, &config, &mut reported, &logger);
+                    }
                 }
-            };
-            let response = match response.json::<VersionMeta>() {
-                Ok(response) => response,
-                Err(error) => {
-                    capture_fail!(
-                        &error,
-                        logger,
-                        "Failed to fetch latest version information";
-                        failure_info(&error)
-                    );
-                    return;
+                match config.interval {
+                    None => return,
+                    Some(interval) => {
+                        let _activity = scope.scoped_activity("waiting for the next check");
+                        thread::sleep(Duration::from_secs(interval));
+                    }
                 }
-            };
-            let latest = match Version::parse(&response.version) {
-                Ok(version) => version,
-                Err(error) => {
-                    capture_fail!(
-                        &error,
-                        logger,
-                        "Failed to parse latest version information";
-                        failure_info(&error)
-                    );
-                    return;
-                }
-            };
-            if current < latest {
-                UPDATE_AVAILABLE.set(1.0);
-                warn!(
-                    logger,
-                    "A new version is available";
-                    "current" => %current,
-                    "latest" => %latest,
-                );
-                sentry::capture_event(sentry::protocol::Event {
-                    level: sentry::Level::Warning,
-                    message: Some("A new version is available".into()),
-                    extra: {
-                        let mut extra = BTreeMap::new();
-                        extra.insert("current".into(), current.to_string().into());
-                        extra.insert("latest".into(), latest.to_string().into());
-                        extra
-                    },
-                    ..Default::default()
-                });
             }
         })
+        .map_err(fail_compat)
         .with_context(|_| ErrorKind::ThreadSpawn("update_checker"))?;
     Ok(())
 }
 
-/// Version metadata returned by the server.
-#[derive(Debug, Deserialize)]
-struct VersionMeta {
-    version: String,
+/// Compare the current version with the latest version in the metadata and report it.
+///
+/// Available updates are reported to sentry once for each new version.
+fn check_updates(
+    meta: &VersionMeta,
+    current: &Version,
+    config: &UpdatesConfig,
+    reported: &mut Option<Version>,
+    logger: &Logger,
+) {
+    let mut status = UpdateStatus::new(current, true, config);
+    let latest = match meta.latest(config) {
+        Some(version) => version,
+        None => {
+            warn!(
+                logger,
+                "No released version matches the update channel and pin";
+                "channel" => %config.channel,
+                "pin" => ?config.pin,
+            );
+            return;
+        }
+    };
+    status.latest = Some(latest.to_string());
+    updates::record(status);
+    if *current >= latest {
+        return;
+    }
+    warn!(
+        logger,
+        "A new version is available";
+        "channel" => %config.channel,
+        "current" => %current,
+        "latest" => %latest,
+    );
+    if reported.as_ref() == Some(&latest) {
+        return;
+    }
+    sentry::capture_event(sentry::protocol::Event {
+        level: sentry::Level::Warning,
+        message: Some("A new version is available".into()),
+        extra: {
+            let mut extra = BTreeMap::new();
+            extra.insert("channel".into(), config.channel.to_string().into());
+            extra.insert("current".into(), current.to_string().into());
+            extra.insert("latest".into(), latest.to_string().into());
+            extra
+        },
+        ..Default::default()
+    });
+    *reported = Some(latest);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::commands;
+    #[cfg(feature = "store")]
+    use super::ensure_store_idle;
+    use super::probe_datastore;
+    use super::sentry_proxies;
+    use super::version_command;
+    use super::wait_for_datastore;
+    use crate::config::Agent as Config;
+    use crate::config::ProxyConfig;
+    use crate::config::StartupConfig;
+    #[cfg(feature = "store")]
+    use crate::heartbeat::Heartbeat;
+    #[cfg(feature = "store")]
+    use crate::store::Store;
+    use crate::testing::MockAgent;
+    use crate::AgentContext;
+    use crate::ErrorKind;
+    use crate::Result;
+
+    fn args(argv: &[&str]) -> clap::ArgMatches {
+        super::clap("test", "0.0.0", "test agent", "agent.yaml")
+            .try_get_matches_from(argv)
+            .unwrap()
+    }
+
+    fn context(wait_for_datastore: u64) -> AgentContext {
+        let startup = StartupConfig {
+            max_retry_delay: 1,
+            wait_for_datastore,
+        };
+        let config = Config {
+            startup,
+            ..Config::mock()
+        };
+        AgentContext::mock_with_config(config)
+    }
+
+    #[test]
+    fn check_config_validates_agent_sections() {
+        let config = Config::mock();
+        let mut validated = false;
+        let performed = commands(&args(&["agent", "check-config"]), &config, || {
+            validated = true;
+            Ok(())
+        });
+        assert!(performed.unwrap());
+        assert!(validated);
+
+        let error = commands(&args(&["agent", "check-config"]), &config, || {
+            Err(ErrorKind::ConfigInvalid("datastore.target", "test".into()).into())
+        })
+        .unwrap_err();
+        assert_eq!(error.kind().code(), "ConfigInvalid");
+    }
+
+    #[test]
+    fn check_config_validates_sdk_first() {
+        let mut config = Config::mock();
+        config.metrics_labels.insert("".into(), "value".into());
+        let mut validated = false;
+        let result = commands(&args(&["agent", "check-config"]), &config, || {
+            validated = true;
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert!(!validated);
+    }
+
+    #[test]
+    fn commands_skip_agent_validation() {
+        let config = Config::mock();
+        let mut validated = false;
+        let performed = commands(&args(&["agent", "run"]), &config, || {
+            validated = true;
+            Ok(())
+        });
+        assert!(!performed.unwrap());
+        assert!(!validated);
+        let performed = commands(&args(&["agent"]), &config, || Ok(()));
+        assert!(!performed.unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "store")]
+    fn migrate_and_backup_store() {
+        let db = std::env::temp_dir().join("repliagent-process-migrate.db");
+        let backup = std::env::temp_dir().join("repliagent-process-backup.db");
+        let _ = std::fs::remove_file(&db);
+        let _ = std::fs::remove_file(&backup);
+        let config = Config {
+            db: db.to_string_lossy().to_string(),
+            ..Config::mock()
+        };
+
+        let performed = commands(&args(&["agent", "migrate"]), &config, || Ok(()));
+        assert!(performed.unwrap());
+        assert!(db.exists());
+
+        let backup_path = backup.to_string_lossy().to_string();
+        let argv = ["agent", "backup-store", backup_path.as_str()];
+        let performed = commands(&args(&argv), &config, || Ok(()));
+        assert!(performed.unwrap());
+        assert!(backup.exists());
+    }
+
+    #[test]
+    fn probe_datastore_skipped_when_not_waiting() {
+        let context = context(0);
+        let mut agent = MockAgent::new();
+        agent.datastore_info = Err("test".into());
+        assert!(probe_datastore(&context, &agent).is_ok());
+    }
+
+    #[test]
+    fn probe_datastore_when_waiting() {
+        let context = context(10);
+        let agent = MockAgent::new();
+        assert!(probe_datastore(&context, &agent).is_ok());
+        let mut agent = MockAgent::new();
+        agent.datastore_info = Err("test".into());
+        assert!(probe_datastore(&context, &agent).is_err());
+    }
+
+    #[test]
+    fn retry_retryable_errors() {
+        let context = context(10);
+        let mut attempts = 0;
+        let result = wait_for_datastore(&context, || -> Result<u32> {
+            attempts += 1;
+            if attempts < 2 {
+                return Err(ErrorKind::StoreOpFailed("test").into());
+            }
+            Ok(attempts)
+        });
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[test]
+    fn skip_retry_of_permanent_errors() {
+        let context = context(10);
+        let mut attempts = 0;
+        let result = wait_for_datastore(&context, || -> Result<()> {
+            attempts += 1;
+            Err(ErrorKind::ConfigLoad.into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn skip_retry_when_not_waiting() {
+        let context = context(0);
+        let mut attempts = 0;
+        let result = wait_for_datastore(&context, || -> Result<()> {
+            attempts += 1;
+            Err(ErrorKind::StoreOpFailed("test").into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn version_subcommand() {
+        assert!(!version_command(&args(&["agent"])));
+        assert!(!version_command(&args(&["agent", "check-config"])));
+        assert!(version_command(&args(&["agent", "version"])));
+        assert!(version_command(&args(&["agent", "version", "--json"])));
+    }
+
+    #[test]
+    fn sentry_uses_configured_proxies() {
+        let proxy = ProxyConfig {
+            http: Some("http://proxy.example.com:3128".into()),
+            https: Some("http://secure-proxy.example.com:3128".into()),
+            no_proxy: Vec::new(),
+        };
+        let mut options = sentry::ClientOptions::default();
+        sentry_proxies(&mut options, &proxy);
+        assert_eq!(
+            options.http_proxy.as_deref(),
+            Some("http://proxy.example.com:3128")
+        );
+        assert_eq!(
+            options.https_proxy.as_deref(),
+            Some("http://secure-proxy.example.com:3128")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "store")]
+    fn store_in_use_by_running_agent() {
+        let config = Config::mock();
+        let store = Store::sqlite();
+        ensure_store_idle(&store, &config).unwrap();
+
+        let mut heartbeat = Heartbeat::current();
+        heartbeat.heartbeat_ts = heartbeat.heartbeat_ts - chrono::Duration::hours(1);
+        store
+            .with_transaction(|tx| tx.heartbeats().persist(&heartbeat, None))
+            .unwrap();
+        ensure_store_idle(&store, &config).unwrap();
+
+        let heartbeat = Heartbeat::current();
+        store
+            .with_transaction(|tx| tx.heartbeats().persist(&heartbeat, None))
+            .unwrap();
+        let error = ensure_store_idle(&store, &config).unwrap_err();
+        assert_eq!(error.kind().code(), "PersistentInUse");
+    }
 }
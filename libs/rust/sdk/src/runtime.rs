@@ -0,0 +1,174 @@
+//! Async runtime for the agent background tasks and the API server.
+//!
+//! The actions engine and the API server run as tasks on a shared tokio runtime
+//! instead of on a dedicated thread each.
+//! Blocking work (store transactions, actions, datastore calls) is handed to
+//! the runtime blocking threads pool with `blocking`.
+//!
+//! The runtime is driven by a single `r:b:runtime` thread registered with `Upkeep`:
+//!
+//!   * Tasks are notified when the process shuts down and the thread waits for them to return.
+//!   * The process exits if a task panics or returns before shutdown is requested.
+use std::future::Future;
+use std::panic::resume_unwind;
+
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use humthreads::Builder;
+use tokio::runtime::Runtime;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use replicante_util_upkeep::Upkeep;
+
+use crate::fail_compat;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// Tokio runtime shared by the agent tasks.
+pub struct AgentRuntime {
+    runtime: Runtime,
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl AgentRuntime {
+    pub fn new() -> Result<AgentRuntime> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("r:b:async")
+            .build()
+            .with_context(|_| ErrorKind::Initialisation("unable to start async runtime".into()))?;
+        let (shutdown, _) = watch::channel(false);
+        Ok(AgentRuntime {
+            runtime,
+            shutdown,
+            tasks: Vec::new(),
+        })
+    }
+
+    /// Start a task expected to run until the process shuts down.
+    ///
+    /// The task is built by `task`, called within the runtime context,
+    /// with the `Shutdown` notification it should wait on.
+    pub fn spawn<F, T>(&mut self, name: &'static str, task: F)
+    where
+        F: FnOnce(Shutdown) -> T,
+        T: Future<Output = ()> + Send + 'static,
+    {
+        let _context = self.runtime.enter();
+        let shutdown = Shutdown(self.shutdown.subscribe());
+        let handle = self.runtime.spawn(task(shutdown));
+        self.tasks.push((name, handle));
+    }
+
+    /// Watch the started tasks from a thread registered with `Upkeep`.
+    pub fn run(self, upkeep: &mut Upkeep) -> Result<()> {
+        if self.tasks.is_empty() {
+            return Ok(());
+        }
+        let AgentRuntime {
+            runtime,
+            shutdown,
+            tasks,
+        } = self;
+        let requested = Shutdown(shutdown.subscribe());
+        let thread = Builder::new("r:b:runtime")
+            .full_name("replicante:base:runtime")
+            .spawn(move |scope| {
+                scope.activity("running async tasks");
+                let mut tasks: FuturesUnordered<_> = tasks
+                    .into_iter()
+                    .map(|(name, handle)| async move { (name, handle.await) })
+                    .collect();
+                runtime.block_on(async {
+                    while let Some((name, result)) = tasks.next().await {
+                        if let Err(error) = result {
+                            if error.is_panic() {
+                                resume_unwind(error.into_panic());
+                            }
+                        }
+                        if !requested.is_set() {
+                            panic!("async task '{}' stopped before shutdown", name);
+                        }
+                    }
+                });
+            })
+            .map_err(fail_compat)
+            .with_context(|_| ErrorKind::ThreadSpawn("async runtime"))?;
+        upkeep.register_thread(thread);
+        upkeep.on_shutdown(move || {
+            // Errors mean the runtime thread already returned and nothing is left to notify.
+            let _ = shutdown.send(true);
+        });
+        Ok(())
+    }
+}
+
+/// Notification that the process is shutting down.
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// Check if shutdown was requested, without waiting.
+    pub fn is_set(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Wait until shutdown is requested.
+    pub async fn requested(&mut self) {
+        while !self.is_set() {
+            if self.0.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Run blocking code on the runtime blocking threads pool and await its result.
+///
+/// Returns `None` if the runtime shut down before the code ran.
+/// Panics in the blocking code resume in the awaiting task.
+pub async fn blocking<F, T>(block: F) -> Option<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(block).await {
+        Ok(result) => Some(result),
+        Err(error) if error.is_panic() => resume_unwind(error.into_panic()),
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use super::blocking;
+    use super::AgentRuntime;
+
+    #[test]
+    fn blocking_returns_result() {
+        let runtime = AgentRuntime::new().unwrap();
+        let result = runtime.runtime.block_on(blocking(|| 42));
+        assert_eq!(result, Some(42));
+    }
+
+    #[test]
+    fn tasks_notified_of_shutdown() {
+        let mut runtime = AgentRuntime::new().unwrap();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stopped);
+        runtime.spawn("test", move |mut shutdown| async move {
+            shutdown.requested().await;
+            flag.store(true, Ordering::SeqCst);
+        });
+        runtime.shutdown.send(true).unwrap();
+        let (_, handle) = runtime.tasks.pop().unwrap();
+        runtime.runtime.block_on(handle).unwrap();
+        assert!(stopped.load(Ordering::SeqCst));
+    }
+}
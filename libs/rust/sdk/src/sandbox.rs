@@ -0,0 +1,208 @@
+//! Harden the agent process.
+//!
+//! Agents often start as root to bind privileged ports or read protected files
+//! and hold credentials to restart the datastore, so they should give up
+//! whatever they can once running:
+//!
+//!   * Prevent the process and its children from gaining privileges (`sandbox.no_new_privs`).
+//!   * Limit the files the process can access with Landlock (`sandbox.landlock`).
+//!   * Switch to an unprivileged user and group (`sandbox.user` and `sandbox.group`).
+//!   * Deny system calls agents never need with a seccomp filter (`sandbox.seccomp`).
+//!
+//! The `no_new_privs` flag and Landlock rules only apply to the thread setting them
+//! and the threads and processes it starts afterwards, so `restrict` must be called
+//! before any thread is spawned.
+//! The remaining restrictions are applied by `apply` once the API server is listening.
+//!
+//! The sandbox applies to the whole process, including external action commands.
+use slog::info;
+use slog::Logger;
+
+use crate::config::LandlockConfig;
+use crate::config::SandboxConfig;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// System calls denied by the seccomp filter with an `EPERM` error.
+#[cfg(target_os = "linux")]
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_acct,
+    libc::SYS_bpf,
+    libc::SYS_clock_settime,
+    libc::SYS_delete_module,
+    libc::SYS_finit_module,
+    libc::SYS_init_module,
+    libc::SYS_kexec_load,
+    libc::SYS_mount,
+    libc::SYS_pivot_root,
+    libc::SYS_ptrace,
+    libc::SYS_reboot,
+    libc::SYS_settimeofday,
+    libc::SYS_swapoff,
+    libc::SYS_swapon,
+    libc::SYS_umount2,
+];
+
+/// Apply the restrictions inherited by threads, before any thread is spawned.
+///
+/// This is called before the logger is configured so errors are returned to be reported.
+pub fn restrict(config: &SandboxConfig) -> Result<()> {
+    if config.no_new_privs || config.seccomp {
+        no_new_privs()?;
+    }
+    if let Some(landlock) = &config.landlock {
+        self::landlock(landlock)?;
+    }
+    Ok(())
+}
+
+/// Apply the remaining sandbox restrictions once the agent is initialised.
+pub fn apply(config: &SandboxConfig, logger: &Logger) -> Result<()> {
+    if config.landlock.is_some() {
+        info!(logger, "Restricted filesystem access with Landlock");
+    }
+    if let Some(user) = &config.user {
+        let (uid, gid) = resolve_ids(user, config.group.as_deref())?;
+        drop_privileges(uid, gid)?;
+        info!(logger, "Dropped process privileges"; "uid" => uid, "gid" => gid);
+    }
+    if config.seccomp {
+        seccomp()?;
+        info!(logger, "Applied seccomp filter to the process");
+    }
+    Ok(())
+}
+
+/// Convert the return code of a libc call into an error naming the call.
+fn check_os(result: libc::c_int, call: &'static str) -> Result<()> {
+    if result == 0 {
+        return Ok(());
+    }
+    let error = std::io::Error::last_os_error();
+    Err(error).with_context(|_| ErrorKind::Sandbox(call))
+}
+
+/// Switch the process to the given user and group, dropping supplementary groups.
+fn drop_privileges(uid: libc::uid_t, gid: libc::gid_t) -> Result<()> {
+    // SAFETY: the list pointer is valid for the given length for the duration of the call.
+    let result = unsafe { libc::setgroups(1, &gid) };
+    check_os(result, "setgroups")?;
+    users::switch::set_current_gid(gid).with_context(|_| ErrorKind::Sandbox("setgid"))?;
+    users::switch::set_current_uid(uid).with_context(|_| ErrorKind::Sandbox("setuid"))?;
+    Ok(())
+}
+
+/// Limit filesystem access to the configured paths.
+///
+/// Landlock also sets `no_new_privs` on the calling thread.
+#[cfg(target_os = "linux")]
+fn landlock(config: &LandlockConfig) -> Result<()> {
+    use landlock::path_beneath_rules;
+    use landlock::Access;
+    use landlock::AccessFs;
+    use landlock::Ruleset;
+    use landlock::RulesetAttr;
+    use landlock::RulesetCreatedAttr;
+    use landlock::RulesetStatus;
+    use landlock::ABI;
+
+    let abi = ABI::V1;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))
+        .with_context(|_| ErrorKind::Sandbox("landlock ruleset creation"))?
+        .create()
+        .with_context(|_| ErrorKind::Sandbox("landlock ruleset creation"))?
+        .add_rules(path_beneath_rules(&config.read, AccessFs::from_read(abi)))
+        .with_context(|_| ErrorKind::Sandbox("landlock read rules"))?
+        .add_rules(path_beneath_rules(&config.write, AccessFs::from_all(abi)))
+        .with_context(|_| ErrorKind::Sandbox("landlock write rules"))?
+        .restrict_self()
+        .with_context(|_| ErrorKind::Sandbox("landlock_restrict_self"))?;
+    // Refuse to run unrestricted when the operator asked for Landlock.
+    if status.ruleset != RulesetStatus::FullyEnforced {
+        return Err(ErrorKind::Sandbox("landlock enforcement (kernel support missing)").into());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn landlock(_: &LandlockConfig) -> Result<()> {
+    Err(ErrorKind::Sandbox("landlock_restrict_self").into())
+}
+
+#[cfg(target_os = "linux")]
+fn no_new_privs() -> Result<()> {
+    // SAFETY: PR_SET_NO_NEW_PRIVS takes no pointer arguments.
+    let result = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+    check_os(result, "prctl(PR_SET_NO_NEW_PRIVS)")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn no_new_privs() -> Result<()> {
+    Err(ErrorKind::Sandbox("prctl(PR_SET_NO_NEW_PRIVS)").into())
+}
+
+/// Resolve the user and group names to switch to.
+fn resolve_ids(user: &str, group: Option<&str>) -> Result<(libc::uid_t, libc::gid_t)> {
+    let user = users::get_user_by_name(user).ok_or_else(|| {
+        let error = format!("unknown user {}", user);
+        ErrorKind::ConfigInvalid("sandbox.user", error)
+    })?;
+    let gid = match group {
+        None => user.primary_group_id(),
+        Some(group) => users::get_group_by_name(group)
+            .ok_or_else(|| {
+                let error = format!("unknown group {}", group);
+                ErrorKind::ConfigInvalid("sandbox.group", error)
+            })?
+            .gid(),
+    };
+    Ok((user.uid(), gid))
+}
+
+#[cfg(target_os = "linux")]
+fn seccomp() -> Result<()> {
+    use std::convert::TryInto;
+
+    use seccompiler::BpfProgram;
+    use seccompiler::SeccompAction;
+    use seccompiler::SeccompFilter;
+
+    let rules = DENIED_SYSCALLS
+        .iter()
+        .map(|syscall| (*syscall as i64, Vec::new()))
+        .collect();
+    let arch = std::env::consts::ARCH
+        .try_into()
+        .with_context(|_| ErrorKind::Sandbox("seccomp architecture detection"))?;
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        arch,
+    )
+    .with_context(|_| ErrorKind::Sandbox("seccomp filter creation"))?;
+    let program: BpfProgram = filter
+        .try_into()
+        .with_context(|_| ErrorKind::Sandbox("seccomp filter compilation"))?;
+    seccompiler::apply_filter_all_threads(&program)
+        .with_context(|_| ErrorKind::Sandbox("seccomp filter installation"))?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn seccomp() -> Result<()> {
+    Err(ErrorKind::Sandbox("seccomp filter installation").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve_ids;
+
+    #[test]
+    fn resolve_unknown_user() {
+        let error = resolve_ids("repliagent-no-such-user", None).unwrap_err();
+        assert_eq!(error.kind().code(), "ConfigInvalid");
+    }
+}
@@ -0,0 +1,425 @@
+//! Datastore specific details about shards.
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use humthreads::Builder;
+use opentracingrust::SpanContext;
+use regex::RegexSet;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value as Json;
+use slog::debug;
+use slog::info;
+
+use replicante_models_agent::info::ShardRole;
+use replicante_models_agent::info::Shards;
+use replicante_util_failure::failure_info;
+use replicante_util_upkeep::Upkeep;
+
+use crate::config::ShardsConfig;
+#[cfg(feature = "store")]
+use crate::events::Event;
+use crate::fail_compat;
+use crate::metrics::SHARD_ROLE_CHANGES;
+use crate::Agent;
+use crate::AgentContext;
+use crate::Error;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// Datastore specific role of a shard on the node.
+///
+/// The standard `ShardRole`s only distinguish primaries from secondaries so nodes
+/// with richer roles can report them here, alongside the standard role.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum ExtendedShardRole {
+    /// The node votes in elections but holds no data.
+    Arbiter,
+
+    /// The node holds data but is not visible to clients.
+    Hidden,
+
+    /// The node is catching up with the cluster and does not vote yet.
+    Learner,
+
+    /// The node follows the cluster but does not vote.
+    Observer,
+
+    /// Any other datastore specific role.
+    Other(String),
+}
+
+impl fmt::Display for ExtendedShardRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExtendedShardRole::Arbiter => write!(f, "arbiter"),
+            ExtendedShardRole::Hidden => write!(f, "hidden"),
+            ExtendedShardRole::Learner => write!(f, "learner"),
+            ExtendedShardRole::Observer => write!(f, "observer"),
+            ExtendedShardRole::Other(role) => write!(f, "{}", role),
+        }
+    }
+}
+
+impl From<String> for ExtendedShardRole {
+    fn from(role: String) -> ExtendedShardRole {
+        match role.as_str() {
+            "arbiter" => ExtendedShardRole::Arbiter,
+            "hidden" => ExtendedShardRole::Hidden,
+            "learner" => ExtendedShardRole::Learner,
+            "observer" => ExtendedShardRole::Observer,
+            _ => ExtendedShardRole::Other(role),
+        }
+    }
+}
+
+impl From<ExtendedShardRole> for String {
+    fn from(role: ExtendedShardRole) -> String {
+        role.to_string()
+    }
+}
+
+/// Extended roles of shards on the node, by shard ID.
+pub type ShardRoles = BTreeMap<String, ExtendedShardRole>;
+
+/// Shards collected from the node along with errors for shards that could not be.
+///
+/// Agents collecting shards independently of each other (such as Kafka topics) can
+/// report the shards they did collect instead of failing the entire collection.
+#[derive(Debug)]
+pub struct PartialShards {
+    pub errors: Vec<ShardError>,
+    pub shards: Shards,
+}
+
+impl PartialShards {
+    pub fn new(shards: Shards) -> PartialShards {
+        PartialShards {
+            errors: Vec::new(),
+            shards,
+        }
+    }
+
+    /// Record the failed collection of a shard, or group of shards.
+    ///
+    /// Groups of shards are identified with the same format as the shards in them,
+    /// using `*` for the varying part (for example `topic/*` for a Kafka topic),
+    /// so the `shards` filtering rules apply to errors the same way they apply to shards.
+    ///
+    /// Errors that invalidate the entire collection, like an exceeded request deadline,
+    /// are returned instead so the caller can stop collecting.
+    pub fn fail<S: Into<String>>(&mut self, shard: S, error: Error) -> Result<()> {
+        if let ErrorKind::DeadlineExceeded(_) = error.kind() {
+            return Err(error);
+        }
+        self.errors.push(ShardError {
+            error,
+            shard: shard.into(),
+        });
+        Ok(())
+    }
+
+    /// Return the collected shards if all were collected or the first error otherwise.
+    pub fn into_shards(mut self) -> Result<Shards> {
+        if self.errors.is_empty() {
+            return Ok(self.shards);
+        }
+        Err(self.errors.remove(0).error)
+    }
+}
+
+impl From<Shards> for PartialShards {
+    fn from(shards: Shards) -> PartialShards {
+        PartialShards::new(shards)
+    }
+}
+
+/// Select the shards reported to clients based on the `shards` configuration.
+#[derive(Clone, Debug)]
+pub struct ShardFilter {
+    exclude: RegexSet,
+    include: RegexSet,
+}
+
+impl ShardFilter {
+    pub fn new(config: &ShardsConfig) -> Result<ShardFilter> {
+        let exclude = RegexSet::new(&config.exclude)
+            .map_err(|error| ErrorKind::ConfigInvalid("shards.exclude", error.to_string()))?;
+        let include = RegexSet::new(&config.include)
+            .map_err(|error| ErrorKind::ConfigInvalid("shards.include", error.to_string()))?;
+        Ok(ShardFilter { exclude, include })
+    }
+
+    /// Check if the shard, or group of shards, with the given ID should be reported.
+    pub fn allows(&self, shard: &str) -> bool {
+        if !self.include.is_empty() && !self.include.is_match(shard) {
+            return false;
+        }
+        !self.exclude.is_match(shard)
+    }
+
+    /// Drop shards, and errors about shards, that should not be reported.
+    pub fn apply(&self, mut partial: PartialShards) -> PartialShards {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return partial;
+        }
+        partial.shards.shards.retain(|shard| self.allows(&shard.id));
+        partial.errors.retain(|error| self.allows(&error.shard));
+        partial
+    }
+}
+
+/// Change of a shard role between successive shard collections.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RoleChange {
+    pub current: String,
+    pub previous: String,
+    pub shard: String,
+}
+
+/// Track shard roles between successive collections to detect role changes.
+///
+/// Shards missing from a collection keep their last known role so shards that
+/// failed to collect do not appear to flip when they are collected again.
+#[derive(Clone, Default)]
+pub struct RoleTracker {
+    roles: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl RoleTracker {
+    /// Record the roles of collected shards and return the ones that changed.
+    ///
+    /// Shards seen for the first time are not reported as changed.
+    pub fn observe(&self, shards: &Shards) -> Vec<RoleChange> {
+        let mut roles = self.roles.lock().expect("RoleTracker lock poisoned");
+        let mut changes = Vec::new();
+        for shard in &shards.shards {
+            let current = role_name(&shard.role);
+            let previous = roles.insert(shard.id.clone(), current.clone());
+            match previous {
+                Some(previous) if previous != current => changes.push(RoleChange {
+                    current,
+                    previous,
+                    shard: shard.id.clone(),
+                }),
+                _ => (),
+            }
+        }
+        changes
+    }
+}
+
+/// Detect shard role changes since the previous collection and report them.
+///
+/// Changes are logged, counted by the `repliagent_shard_role_changes` metric
+/// and recorded as events so failovers are observable even if Core polls rarely.
+#[cfg_attr(not(feature = "store"), allow(unused_variables))]
+pub fn record_role_changes<S>(context: &AgentContext, shards: &Shards, span: S)
+where
+    S: Into<Option<SpanContext>>,
+{
+    let span = span.into();
+    for change in context.shard_roles.observe(shards) {
+        info!(
+            context.logger,
+            "Shard role changed";
+            "current" => &change.current,
+            "previous" => &change.previous,
+            "shard" => &change.shard,
+        );
+        SHARD_ROLE_CHANGES
+            .with_label_values(&[&change.previous, &change.current])
+            .inc();
+        #[cfg(feature = "store")]
+        crate::events::emit(context, Event::shard_role_changed(&change), span.clone());
+    }
+}
+
+/// Start background thread to periodically check shards for role changes.
+///
+/// Collections made to serve API requests are checked too, this thread ensures
+/// changes are detected even when the shards API is not called.
+pub fn spawn(agent: Arc<dyn Agent>, context: AgentContext, upkeep: &mut Upkeep) -> Result<()> {
+    let thread = Builder::new("r:b:shard_roles")
+        .full_name("replicante:base:shard_roles")
+        .spawn(move |scope| {
+            let interval = Duration::from_secs(context.config.shards.roles_interval);
+            scope.activity("waiting to check shard roles");
+            while !scope.should_shutdown() {
+                let _activity = scope.scoped_activity("checking shard roles");
+                check_roles(&*agent, &context);
+                thread::sleep(interval);
+            }
+        })
+        .map_err(fail_compat)
+        .with_context(|_| ErrorKind::ThreadSpawn("shard roles"))?;
+    upkeep.register_thread(thread);
+    Ok(())
+}
+
+/// Collect shards once and record any role change.
+///
+/// Shards that fail to collect keep their last known role until they are collected again.
+fn check_roles(agent: &dyn Agent, context: &AgentContext) {
+    let mut span = context.tracer.span("shards.roles").auto_finish();
+    let partial = match agent.shards_partial(&mut span) {
+        Ok(partial) => partial,
+        Err(error) => {
+            debug!(context.logger, "Failed to collect shards roles"; failure_info(&error.to_fail()));
+            return;
+        }
+    };
+    let partial = context.shard_filter.apply(partial);
+    record_role_changes(context, &partial.shards, span.context().clone());
+}
+
+/// Name of a standard shard role as reported by the API.
+fn role_name(role: &ShardRole) -> String {
+    match serde_json::to_value(role) {
+        Ok(Json::String(role)) => role,
+        _ => format!("{:?}", role).to_lowercase(),
+    }
+}
+
+/// Error collecting a shard, or group of shards, identified by `shard`.
+#[derive(Debug)]
+pub struct ShardError {
+    pub error: Error,
+    pub shard: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use replicante_models_agent::info::Shard;
+    use replicante_models_agent::info::ShardRole;
+    use replicante_models_agent::info::Shards;
+
+    use super::check_roles;
+    use super::ExtendedShardRole;
+    use super::PartialShards;
+    use super::RoleTracker;
+    use super::ShardFilter;
+    use crate::config::ShardsConfig;
+    use crate::testing::MockAgent;
+    use crate::AgentContext;
+    use crate::ErrorKind;
+
+    #[test]
+    fn filter_shards() {
+        let shard = |id| Shard::new(id, ShardRole::Primary, None, None);
+        let mut partial = PartialShards::new(Shards::new(vec![
+            shard("__consumer_offsets/0"),
+            shard("orders/0"),
+            shard("test/0"),
+        ]));
+        partial
+            .fail(
+                "__consumer_offsets/*",
+                ErrorKind::StoreOpFailed("test").into(),
+            )
+            .unwrap();
+        let config = ShardsConfig {
+            exclude: vec!["^__".into()],
+            include: vec!["^(__consumer_offsets|orders)/".into()],
+            ..ShardsConfig::default()
+        };
+        let filter = ShardFilter::new(&config).unwrap();
+        let partial = filter.apply(partial);
+        let ids: Vec<&str> = partial
+            .shards
+            .shards
+            .iter()
+            .map(|s| s.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["orders/0"]);
+        assert!(partial.errors.is_empty());
+    }
+
+    #[test]
+    fn filter_shard_group_errors() {
+        let mut partial = PartialShards::new(Shards::new(Vec::new()));
+        partial
+            .fail(
+                "__consumer_offsets/*",
+                ErrorKind::StoreOpFailed("test").into(),
+            )
+            .unwrap();
+        partial
+            .fail("orders/*", ErrorKind::StoreOpFailed("test").into())
+            .unwrap();
+        let config = ShardsConfig {
+            exclude: vec!["^__consumer_offsets/".into()],
+            ..ShardsConfig::default()
+        };
+        let filter = ShardFilter::new(&config).unwrap();
+        let partial = filter.apply(partial);
+        let errors: Vec<&str> = partial.errors.iter().map(|e| e.shard.as_str()).collect();
+        assert_eq!(errors, vec!["orders/*"]);
+    }
+
+    #[test]
+    fn role_changes() {
+        let shards = |role| Shards::new(vec![Shard::new("test", role, None, None)]);
+        let tracker = RoleTracker::default();
+        assert!(tracker.observe(&shards(ShardRole::Secondary)).is_empty());
+        assert!(tracker.observe(&shards(ShardRole::Secondary)).is_empty());
+        assert!(tracker.observe(&Shards::new(Vec::new())).is_empty());
+        let changes = tracker.observe(&shards(ShardRole::Primary));
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].shard, "test");
+        assert_ne!(changes[0].previous, changes[0].current);
+    }
+
+    #[test]
+    fn background_check_tracks_roles() {
+        let shards = |role| Shards::new(vec![Shard::new("test", role, None, None)]);
+        let context = AgentContext::mock();
+        let mut agent = MockAgent::new();
+        agent.shards = Ok(shards(ShardRole::Secondary));
+        check_roles(&agent, &context);
+        let changes = context.shard_roles.observe(&shards(ShardRole::Primary));
+        assert_eq!(changes.len(), 1);
+        // Roles are tracked by each context independently.
+        let other = AgentContext::mock();
+        assert!(other
+            .shard_roles
+            .observe(&shards(ShardRole::Secondary))
+            .is_empty());
+    }
+
+    #[test]
+    fn serialise_as_string() {
+        let roles = vec![
+            ExtendedShardRole::Arbiter,
+            ExtendedShardRole::Other("witness".into()),
+        ];
+        let encoded = serde_json::to_string(&roles).unwrap();
+        assert_eq!(encoded, r#"["arbiter","witness"]"#);
+        let decoded: Vec<ExtendedShardRole> = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(decoded, roles);
+    }
+
+    #[test]
+    fn partial_shards_deadline() {
+        let mut shards = PartialShards::new(Shards::new(Vec::new()));
+        let error = ErrorKind::DeadlineExceeded("test").into();
+        assert!(shards.fail("topic", error).is_err());
+        assert!(shards.errors.is_empty());
+    }
+
+    #[test]
+    fn partial_shards_into_shards() {
+        let mut shards = PartialShards::new(Shards::new(Vec::new()));
+        let error = ErrorKind::StoreOpFailed("test").into();
+        shards.fail("topic", error).unwrap();
+        let error = shards.into_shards().unwrap_err();
+        assert_eq!(error.kind().code(), "StoreOpFailed");
+    }
+}
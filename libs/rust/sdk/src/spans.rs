@@ -0,0 +1,98 @@
+//! Spans that follow the sampling decision of the API request they are part of.
+//!
+//! Datastore calls made for API requests that were not sampled are still given a span,
+//! marked as unsampled and never finished so it is not reported.
+//! Agents should start spans for their operations with `child_span` so that spans
+//! started while serving unsampled requests are not reported either.
+use std::ops::Deref;
+use std::ops::DerefMut;
+
+use opentracingrust::AutoFinishingSpan;
+use opentracingrust::Span;
+use opentracingrust::Tracer;
+
+/// Baggage item marking spans of requests that were not sampled.
+const UNSAMPLED: &str = "replicante.unsampled";
+
+/// Span that is finished, and reported, when dropped only if its request was sampled.
+pub enum ChildSpan {
+    Sampled(AutoFinishingSpan),
+    Unsampled(Span),
+}
+
+impl Deref for ChildSpan {
+    type Target = Span;
+
+    fn deref(&self) -> &Span {
+        match self {
+            ChildSpan::Sampled(span) => &**span,
+            ChildSpan::Unsampled(span) => span,
+        }
+    }
+}
+
+impl DerefMut for ChildSpan {
+    fn deref_mut(&mut self) -> &mut Span {
+        match self {
+            ChildSpan::Sampled(span) => &mut **span,
+            ChildSpan::Unsampled(span) => span,
+        }
+    }
+}
+
+/// Start a span for an operation, child of the given span if any.
+///
+/// Spans without a parent are always reported.
+pub fn child_span(tracer: &Tracer, name: &str, parent: Option<&Span>) -> ChildSpan {
+    let mut span = tracer.span(name);
+    let parent = match parent {
+        None => return ChildSpan::Sampled(span.auto_finish()),
+        Some(parent) => parent,
+    };
+    span.child_of(parent.context().clone());
+    if is_sampled(parent) {
+        ChildSpan::Sampled(span.auto_finish())
+    } else {
+        mark_unsampled(&mut span);
+        ChildSpan::Unsampled(span)
+    }
+}
+
+/// Check if a span is part of a sampled request.
+pub fn is_sampled(span: &Span) -> bool {
+    span.get_baggage_item(UNSAMPLED).is_none()
+}
+
+/// Mark a span as part of a request that was not sampled.
+pub fn mark_unsampled(span: &mut Span) {
+    span.set_baggage_item(UNSAMPLED, "true");
+}
+
+/// Start a span that is never reported, for operations that should not be traced.
+pub fn unsampled_span(tracer: &Tracer, name: &str) -> ChildSpan {
+    let mut span = tracer.span(name);
+    mark_unsampled(&mut span);
+    ChildSpan::Unsampled(span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::child_span;
+    use super::is_sampled;
+    use super::unsampled_span;
+    use crate::AgentContext;
+
+    #[test]
+    fn children_follow_parent_decision() {
+        let context = AgentContext::mock();
+        let root = child_span(&context.tracer, "root", None);
+        let child = child_span(&context.tracer, "child", Some(&root));
+        assert!(is_sampled(&child));
+
+        let root = unsampled_span(&context.tracer, "root");
+        let child = child_span(&context.tracer, "child", Some(&root));
+        let grandchild = child_span(&context.tracer, "grandchild", Some(&child));
+        assert!(!is_sampled(&child));
+        assert!(!is_sampled(&grandchild));
+    }
+}
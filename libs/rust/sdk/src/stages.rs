@@ -0,0 +1,78 @@
+//! Per-stage timing of datastore collection operations.
+//!
+//! Collecting information from a datastore goes through a few stages: connecting,
+//! querying the datastore and parsing the response.
+//! Agents time each stage with a `StageTimer` so slow collections can be attributed
+//! to the datastore or to the agent itself.
+//!
+//! Stage durations are reported in two ways:
+//!
+//!   * As `stage.<stage>.seconds` tags and `stage` logs on the operation span.
+//!   * With the `repliagent_collector_stage_duration{service, operation, stage}` metric.
+use std::time::Instant;
+
+use opentracingrust::Log;
+use opentracingrust::Span;
+
+use crate::metrics::COLLECTOR_STAGE_DURATION;
+
+/// Stage to establish connections to the datastore.
+pub const STAGE_CONNECT: &str = "connect";
+
+/// Stage to decode datastore responses into agent models.
+pub const STAGE_PARSE: &str = "parse";
+
+/// Stage to send requests to the datastore and wait for responses.
+pub const STAGE_QUERY: &str = "query";
+
+/// Time the stages of a datastore collection operation.
+pub struct StageTimer {
+    service: &'static str,
+    operation: &'static str,
+}
+
+impl StageTimer {
+    /// Time stages of an operation against a service (the datastore or one of its components).
+    pub fn new(service: &'static str, operation: &'static str) -> StageTimer {
+        StageTimer { service, operation }
+    }
+
+    /// Run a stage of the operation and record how long it took.
+    ///
+    /// The duration is recorded whether the stage succeeds or fails.
+    pub fn time<F, T>(&self, stage: &'static str, span: &mut Span, f: F) -> T
+    where
+        F: FnOnce(&mut Span) -> T,
+    {
+        let start = Instant::now();
+        let result = f(span);
+        let duration = start.elapsed().as_secs_f64();
+        COLLECTOR_STAGE_DURATION
+            .with_label_values(&[self.service, self.operation, stage])
+            .observe(duration);
+        span.tag(&format!("stage.{}.seconds", stage), duration);
+        span.log(Log::new().log("stage", stage));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StageTimer;
+    use super::STAGE_QUERY;
+    use crate::metrics::COLLECTOR_STAGE_DURATION;
+    use crate::AgentContext;
+
+    #[test]
+    fn time_failed_stages() {
+        let context = AgentContext::mock();
+        let mut span = context.tracer.span("TEST");
+        let timer = StageTimer::new("test", "time_failed_stages");
+        let result: Result<(), &str> = timer.time(STAGE_QUERY, &mut span, |_| Err("failed"));
+        assert!(result.is_err());
+        let count = COLLECTOR_STAGE_DURATION
+            .with_label_values(&["test", "time_failed_stages", STAGE_QUERY])
+            .get_sample_count();
+        assert_eq!(count, 1);
+    }
+}
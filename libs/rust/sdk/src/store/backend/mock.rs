@@ -1,36 +1,101 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use chrono::DateTime;
+use chrono::Utc;
 use opentracingrust::SpanContext;
 use serde_json::Value as Json;
 
 use crate::actions::ActionHistoryItem;
+use crate::actions::ActionListItem;
+use crate::actions::ActionLogLine;
 use crate::actions::ActionRecord;
+use crate::actions::ActionRecordView;
 use crate::actions::ActionState;
+use crate::events::Event;
+use crate::heartbeat::Heartbeat;
+use crate::store::interface::APITreeOverridesImpl;
+use crate::store::interface::APITreeOverridesInterface;
 use crate::store::interface::ActionImpl;
 use crate::store::interface::ActionInterface;
 use crate::store::interface::ActionsImpl;
+use crate::store::interface::ActionsInterface;
+use crate::store::interface::AgentStateImpl;
+use crate::store::interface::AgentStateInterface;
 use crate::store::interface::ConnectionImpl;
 use crate::store::interface::ConnectionInterface;
+use crate::store::interface::EventsImpl;
+use crate::store::interface::EventsInterface;
+use crate::store::interface::HeartbeatsImpl;
+use crate::store::interface::HeartbeatsInterface;
 use crate::store::interface::StoreInterface;
 use crate::store::interface::TransactionImpl;
 use crate::store::interface::TransactionInterface;
+use crate::store::APITreeOverride;
+use crate::store::ArchivedAction;
+use crate::store::ArchivedActionItem;
+use crate::store::FinishedAction;
 use crate::store::Iter;
+use crate::store::Page;
+use crate::store::StoreSchema;
+use crate::ErrorKind;
 use crate::Result;
 
 #[derive(Clone)]
 struct MockState {
     actions: HashMap<String, ActionRecord>,
+    actions_archive: Vec<ArchivedAction>,
+    actions_history: HashMap<String, Vec<ActionHistoryItem>>,
+    actions_logs: HashMap<String, Vec<ActionLogLine>>,
     actions_queue: VecDeque<String>,
+    agent_state: HashMap<String, Json>,
+    api_tree_overrides: BTreeMap<String, APITreeOverride>,
+    events: Vec<Event>,
+    heartbeats: Vec<Heartbeat>,
+    leases: HashMap<String, (String, DateTime<Utc>)>,
+}
+
+impl MockState {
+    /// Record a transition in the history of an action.
+    fn history_push(&mut self, action: &ActionRecord, timestamp: DateTime<Utc>) {
+        let item = ActionHistoryItem {
+            action_id: action.id,
+            timestamp,
+            state: <dyn ActionRecordView>::raw_state(action).clone(),
+            state_payload: action.state_payload().clone(),
+        };
+        self.actions_history
+            .entry(action.id.to_string())
+            .or_insert_with(Vec::new)
+            .push(item);
+    }
+
+    /// Check if an action is free for the given owner to lease.
+    fn leasable(&self, id: &str, owner: &str) -> bool {
+        match self.leases.get(id) {
+            None => true,
+            Some((holder, expires)) => holder == owner || *expires < Utc::now(),
+        }
+    }
 }
 
 impl Default for MockState {
     fn default() -> Self {
         MockState {
             actions: HashMap::new(),
+            actions_archive: Vec::new(),
+            actions_history: HashMap::new(),
+            actions_logs: HashMap::new(),
             actions_queue: VecDeque::new(),
+            agent_state: HashMap::new(),
+            api_tree_overrides: BTreeMap::new(),
+            events: Vec::new(),
+            heartbeats: Vec::new(),
+            leases: HashMap::new(),
         }
     }
 }
@@ -50,6 +115,10 @@ impl MockStore {
 }
 
 impl StoreInterface for MockStore {
+    fn backup(&self, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
     fn connection(&self) -> Result<ConnectionImpl> {
         let connection = ConnectionImpl::new(Connection {
             state: self.state.clone(),
@@ -60,6 +129,18 @@ impl StoreInterface for MockStore {
     fn migrate(&self) -> Result<()> {
         Ok(())
     }
+
+    fn migrate_down(&self, _: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn restore(&self, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn schema(&self) -> Result<StoreSchema> {
+        Ok(StoreSchema::default())
+    }
 }
 
 struct Connection {
@@ -94,7 +175,23 @@ impl TransactionInterface for Transaction {
 
     /// Access the actions query interface.
     fn actions(&mut self) -> ActionsImpl {
-        panic!("TODO: MockStore::Transaction::actions")
+        ActionsImpl::new(Actions {
+            state: self.state.clone(),
+        })
+    }
+
+    /// Access the agent state query interface.
+    fn agent_state(&mut self) -> AgentStateImpl {
+        AgentStateImpl::new(AgentState {
+            state: self.state.clone(),
+        })
+    }
+
+    /// Access the API tree overrides query interface.
+    fn api_tree_overrides(&mut self) -> APITreeOverridesImpl {
+        APITreeOverridesImpl::new(APITreeOverrides {
+            state: self.state.clone(),
+        })
     }
 
     /// Commit and invalidate the transaction.
@@ -104,6 +201,20 @@ impl TransactionInterface for Transaction {
         Ok(())
     }
 
+    /// Access the events query interface.
+    fn events(&mut self) -> EventsImpl {
+        EventsImpl::new(Events {
+            state: self.state.clone(),
+        })
+    }
+
+    /// Access the heartbeats query interface.
+    fn heartbeats(&mut self) -> HeartbeatsImpl {
+        HeartbeatsImpl::new(Heartbeats {
+            state: self.state.clone(),
+        })
+    }
+
     /// Rollback and invalidate the transaction.
     fn rollback(&mut self) -> Result<()> {
         // Rollbacks are no-ops.
@@ -116,54 +227,400 @@ struct Action {
 }
 
 impl ActionInterface for Action {
+    fn children(&self, id: &str, _: Option<SpanContext>) -> Result<Iter<ActionListItem>> {
+        let state = self.state.lock().unwrap();
+        let parent = Some(id.to_string());
+        let mut children: Vec<&ActionRecord> = state
+            .actions
+            .values()
+            .filter(|action| action.parent_action_id.map(|id| id.to_string()) == parent)
+            .collect();
+        children.sort_by_key(|action| action.scheduled_ts);
+        let children: Vec<_> = children.into_iter().map(Actions::list_item).collect();
+        Ok(Iter::new(children.into_iter()))
+    }
+
     fn get(&self, id: &str, _: Option<SpanContext>) -> Result<Option<ActionRecord>> {
         let state = self.state.lock().unwrap();
         let action = state.actions.get(id).cloned();
         Ok(action)
     }
 
-    fn history(&self, _id: &str, _: Option<SpanContext>) -> Result<Iter<ActionHistoryItem>> {
-        panic!("TODO: MockStore::action::history")
+    fn history(
+        &self,
+        id: &str,
+        limit: u32,
+        after: Option<&str>,
+        _: Option<SpanContext>,
+    ) -> Result<Page<ActionHistoryItem>> {
+        // Page tokens are the number of transitions already returned.
+        let skip = match after {
+            None => 0,
+            Some(token) => token
+                .parse::<usize>()
+                .map_err(|_| ErrorKind::InvalidPageToken(token.to_string()))?,
+        };
+        let state = self.state.lock().unwrap();
+        let history = state.actions_history.get(id).cloned().unwrap_or_default();
+        let end = skip.saturating_add(limit as usize);
+        let next = if history.len() > end {
+            Some(end.to_string())
+        } else {
+            None
+        };
+        let items: Vec<_> = history
+            .into_iter()
+            .rev()
+            .skip(skip)
+            .take(limit as usize)
+            .map(Ok)
+            .collect();
+        Ok(Page {
+            items: Iter::new(items.into_iter()),
+            next,
+        })
     }
 
     fn insert(&self, action: ActionRecord, _: Option<SpanContext>) -> Result<()> {
         let id = action.id;
         let mut state = self.state.lock().unwrap();
+        state.history_push(&action, action.created_ts);
         state.actions.insert(id.to_string(), action);
         state.actions_queue.push_back(id.to_string());
         Ok(())
     }
 
-    fn next(&self, _: Option<SpanContext>) -> Result<Option<ActionRecord>> {
+    fn log(
+        &self,
+        action: &ActionRecord,
+        line: &str,
+        keep: u32,
+        _: Option<SpanContext>,
+    ) -> Result<()> {
         let mut state = self.state.lock().unwrap();
+        let logs = state
+            .actions_logs
+            .entry(action.id.to_string())
+            .or_insert_with(Vec::new);
+        logs.push(ActionLogLine {
+            action_id: action.id,
+            line: line.to_string(),
+            timestamp: Utc::now(),
+        });
+        let drop = logs.len().saturating_sub(keep as usize);
+        logs.drain(..drop);
+        Ok(())
+    }
+
+    fn logs(&self, id: &str, _: Option<SpanContext>) -> Result<Iter<ActionLogLine>> {
+        let state = self.state.lock().unwrap();
+        let logs = state.actions_logs.get(id).cloned().unwrap_or_default();
+        Ok(Iter::new(logs.into_iter().map(Ok)))
+    }
+
+    fn lease(
+        &self,
+        action: &ActionRecord,
+        owner: &str,
+        expires: DateTime<Utc>,
+        _: Option<SpanContext>,
+    ) -> Result<bool> {
+        let id = action.id.to_string();
+        let mut state = self.state.lock().unwrap();
+        if !state.leasable(&id, owner) {
+            return Ok(false);
+        }
+        state.leases.insert(id, (owner.to_string(), expires));
+        Ok(true)
+    }
+
+    fn next(&self, owner: &str, _: Option<SpanContext>) -> Result<Option<ActionRecord>> {
+        // Actions are removed from the queue once they transition to a finished state.
+        let state = self.state.lock().unwrap();
         let next = state
             .actions_queue
-            .pop_front()
-            .and_then(|id| state.actions.get(&id))
+            .iter()
+            .find(|id| state.leasable(id, owner))
+            .and_then(|id| state.actions.get(id))
             .cloned();
         Ok(next)
     }
 
+    fn next_finished(
+        &self,
+        since: DateTime<Utc>,
+        after: Option<&str>,
+        _: Option<SpanContext>,
+    ) -> Result<Option<FinishedAction>> {
+        // Cursors are the position of the action in the finished order.
+        let state = self.state.lock().unwrap();
+        let mut finished: Vec<&ActionRecord> = state
+            .actions
+            .values()
+            .filter(|action| action.finished_ts.is_some())
+            .collect();
+        finished.sort_by_key(|action| (action.finished_ts, action.id));
+        let position = match after {
+            Some(after) => after
+                .parse::<usize>()
+                .map(|position| position + 1)
+                .map_err(|_| ErrorKind::InvalidPageToken(after.to_string()))?,
+            None => finished
+                .iter()
+                .position(|action| action.finished_ts > Some(since))
+                .unwrap_or_else(|| finished.len()),
+        };
+        let next = finished.get(position).map(|action| FinishedAction {
+            action: (*action).clone(),
+            cursor: position.to_string(),
+        });
+        Ok(next)
+    }
+
     fn transition(
         &self,
         action: &ActionRecord,
         transition_to: ActionState,
         payload: Option<Json>,
+        owner: &str,
         _: Option<SpanContext>,
     ) -> Result<()> {
         let id = action.id.to_string();
         let state_finished = transition_to.is_finished();
         let mut state = self.state.lock().unwrap();
+        let leased = state.leases.get(&id).map(|(holder, _)| holder != owner);
+        if leased.unwrap_or(false) {
+            return Err(ErrorKind::ActionLeaseLost(id).into());
+        }
         let record = state.actions.get_mut(&id).unwrap();
         record.set_state(transition_to);
         record.set_state_payload(payload);
-        let finished = state
+        if state_finished {
+            record.finished_ts = Some(Utc::now());
+        }
+        let record = record.clone();
+        state.history_push(&record, Utc::now());
+        if state_finished {
+            state.actions_queue.retain(|item| *item != id);
+        }
+        Ok(())
+    }
+}
+
+struct Actions {
+    state: SyncState,
+}
+
+impl Actions {
+    fn list_item(action: &ActionRecord) -> Result<ActionListItem> {
+        Ok(ActionListItem {
+            kind: action.kind.clone(),
+            id: action.id,
+            state: <dyn ActionRecordView>::raw_state(action).clone(),
+        })
+    }
+}
+
+impl ActionsInterface for Actions {
+    fn archive(&self, action: &ArchivedAction, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.actions_archive.push(action.clone());
+        Ok(())
+    }
+
+    fn archived(&self, id: &str, _: Option<SpanContext>) -> Result<Option<ArchivedAction>> {
+        let state = self.state.lock().unwrap();
+        let action = state
+            .actions_archive
+            .iter()
+            .find(|action| action.id.to_string() == id)
+            .cloned();
+        Ok(action)
+    }
+
+    fn archived_list(
+        &self,
+        limit: u32,
+        after: Option<&str>,
+        _: Option<SpanContext>,
+    ) -> Result<Page<ArchivedActionItem>> {
+        // Tokens are the position in the archive of the last action returned.
+        let state = self.state.lock().unwrap();
+        let end = match after {
+            None => state.actions_archive.len(),
+            Some(token) => token
+                .parse()
+                .map_err(|_| ErrorKind::InvalidPageToken(token.to_string()))?,
+        };
+        let end = end.min(state.actions_archive.len());
+        let start = end.saturating_sub(limit as usize);
+        let items: Vec<Result<ArchivedActionItem>> = state.actions_archive[start..end]
+            .iter()
+            .rev()
+            .map(|action| Ok(action.into()))
+            .collect();
+        let next = if start > 0 {
+            Some(start.to_string())
+        } else {
+            None
+        };
+        Ok(Page {
+            items: Iter::new(items.into_iter()),
+            next,
+        })
+    }
+
+    fn finished(&self, _: Option<SpanContext>) -> Result<Iter<ActionListItem>> {
+        let state = self.state.lock().unwrap();
+        let mut finished: Vec<&ActionRecord> = state
+            .actions
+            .values()
+            .filter(|action| action.finished_ts.is_some())
+            .collect();
+        finished.sort_by_key(|action| std::cmp::Reverse(action.scheduled_ts));
+        let finished: Vec<_> = finished.into_iter().map(Actions::list_item).collect();
+        Ok(Iter::new(finished.into_iter()))
+    }
+
+    fn queue(&self, _: Option<SpanContext>) -> Result<Iter<ActionListItem>> {
+        let state = self.state.lock().unwrap();
+        let queue: Vec<_> = state
             .actions_queue
-            .front()
-            .map(|front| *front == id)
-            .unwrap_or(false);
-        if finished && state_finished {
-            state.actions_queue.pop_front();
+            .iter()
+            .filter_map(|id| state.actions.get(id))
+            .map(Actions::list_item)
+            .collect();
+        Ok(Iter::new(queue.into_iter()))
+    }
+
+    fn prunable(
+        &self,
+        _keep: u32,
+        _limit: u32,
+        _: Option<SpanContext>,
+    ) -> Result<Iter<ActionListItem>> {
+        // Finished actions are never pruned so none are ever archived either.
+        Ok(Iter::new(Vec::new().into_iter()))
+    }
+
+    fn prune(&self, _keep: u32, _limit: u32, _: Option<SpanContext>) -> Result<()> {
+        // Finished actions are kept around for tests to inspect.
+        Ok(())
+    }
+}
+
+struct AgentState {
+    state: SyncState,
+}
+
+impl AgentStateInterface for AgentState {
+    fn get(&self, key: &str, _: Option<SpanContext>) -> Result<Option<Json>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.agent_state.get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &Json, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.agent_state.insert(key.to_string(), value.clone());
+        Ok(())
+    }
+}
+
+struct APITreeOverrides {
+    state: SyncState,
+}
+
+impl APITreeOverridesInterface for APITreeOverrides {
+    fn clear(&self, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.api_tree_overrides.clear();
+        Ok(())
+    }
+
+    fn list(&self, _: Option<SpanContext>) -> Result<Iter<APITreeOverride>> {
+        let state = self.state.lock().unwrap();
+        let overrides: Vec<Result<APITreeOverride>> =
+            state.api_tree_overrides.values().cloned().map(Ok).collect();
+        Ok(Iter::new(overrides.into_iter()))
+    }
+
+    fn set(&self, tree: &APITreeOverride, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .api_tree_overrides
+            .insert(tree.tree.clone(), tree.clone());
+        Ok(())
+    }
+}
+
+struct Events {
+    state: SyncState,
+}
+
+impl EventsInterface for Events {
+    fn history(&self, limit: u32, _: Option<SpanContext>) -> Result<Iter<Event>> {
+        let state = self.state.lock().unwrap();
+        let events: Vec<Result<Event>> = state
+            .events
+            .iter()
+            .rev()
+            .take(limit as usize)
+            .cloned()
+            .map(Ok)
+            .collect();
+        Ok(Iter::new(events.into_iter()))
+    }
+
+    fn persist(&self, event: &Event, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.events.push(event.clone());
+        Ok(())
+    }
+
+    fn prune(&self, keep: u32, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let keep = keep as usize;
+        let len = state.events.len();
+        if len > keep {
+            state.events.drain(0..len - keep);
+        }
+        Ok(())
+    }
+}
+
+struct Heartbeats {
+    state: SyncState,
+}
+
+impl HeartbeatsInterface for Heartbeats {
+    fn history(&self, limit: u32, _: Option<SpanContext>) -> Result<Iter<Heartbeat>> {
+        let state = self.state.lock().unwrap();
+        let heartbeats: Vec<Result<Heartbeat>> = state
+            .heartbeats
+            .iter()
+            .rev()
+            .take(limit as usize)
+            .cloned()
+            .map(Ok)
+            .collect();
+        Ok(Iter::new(heartbeats.into_iter()))
+    }
+
+    fn persist(&self, heartbeat: &Heartbeat, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .heartbeats
+            .retain(|item| item.process_id != heartbeat.process_id);
+        state.heartbeats.push(heartbeat.clone());
+        Ok(())
+    }
+
+    fn prune(&self, keep: u32, _: Option<SpanContext>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let keep = keep as usize;
+        let len = state.heartbeats.len();
+        if len > keep {
+            state.heartbeats.drain(0..len - keep);
         }
         Ok(())
     }
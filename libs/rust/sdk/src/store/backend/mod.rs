@@ -1,3 +1,8 @@
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use opentracingrust::Tracer;
 use slog::Logger;
 
 use replicante_util_tracing::MaybeTracer;
@@ -5,15 +10,64 @@ use replicante_util_tracing::MaybeTracer;
 use crate::config::Agent as Config;
 use crate::store::interface::StoreImpl;
 use crate::store::Store;
+use crate::store::StoreHealth;
 use crate::Result;
 
 #[cfg(any(test, feature = "with_test_support"))]
 pub mod mock;
 mod sqlite3;
 
+/// Function to instantiate a custom store backend.
+pub type BackendFactory = fn(&Config, Logger, MaybeTracer) -> Result<StoreImpl>;
+
+// Define a global to hold the custom backend factory, if any.
+lazy_static! {
+    static ref CUSTOM_BACKEND: RwLock<Option<BackendFactory>> = RwLock::new(None);
+}
+
 /// Instantiate a new storage backend based on the given configuration.
-pub fn backend_factory(config: &Config, logger: Logger, tracer: MaybeTracer) -> Result<Store> {
-    let inner = self::sqlite3::Store::new(logger.clone(), config.db.clone(), tracer)?;
-    let inner = StoreImpl::new(inner);
-    Ok(Store { inner, logger })
+///
+/// The default backend is SQLite unless a custom backend was registered.
+pub fn backend_factory(config: &Config, logger: Logger, tracer: Arc<Tracer>) -> Result<Store> {
+    let maybe_tracer = MaybeTracer::new(Arc::clone(&tracer));
+    let custom = *CUSTOM_BACKEND.read().unwrap();
+    let inner = match custom {
+        Some(factory) => factory(config, logger.clone(), maybe_tracer)?,
+        None => {
+            let inner = self::sqlite3::Store::new(logger.clone(), config.db.clone(), maybe_tracer)?;
+            StoreImpl::new(inner)
+        }
+    };
+    Ok(Store {
+        health: Arc::new(StoreHealth::default()),
+        inner,
+        logger,
+        tracer: Some(tracer),
+    })
+}
+
+/// Register a custom store backend to use in place of the default SQLite backend.
+///
+/// This should be done at the very beginning of your agent and
+/// BEFORE THE AGENT CONTEXT IS CREATED.
+///
+/// # Panics
+/// If a custom backend is registered more than once.
+pub fn register_backend(factory: BackendFactory) {
+    let mut custom = CUSTOM_BACKEND.write().unwrap();
+    if custom.is_some() {
+        panic!("cannot register more than one custom store backend");
+    }
+    *custom = Some(factory);
+}
+
+/// Instantiate a SQLite backend storing data in a new temporary file, to test SQL queries.
+#[cfg(test)]
+pub fn sqlite_temp(logger: Logger, tracer: Arc<Tracer>) -> StoreImpl {
+    let name = format!("repliagent-test-{}.db", uuid::Uuid::new_v4());
+    let path = std::env::temp_dir().join(name).display().to_string();
+    let tracer = MaybeTracer::new(tracer);
+    let inner = self::sqlite3::Store::new(logger, path, tracer)
+        .expect("failed to create temporary SQLite store");
+    StoreImpl::new(inner)
 }
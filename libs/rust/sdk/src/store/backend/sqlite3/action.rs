@@ -1,12 +1,11 @@
 use std::str::FromStr;
 
-use chrono::TimeZone;
+use chrono::DateTime;
 use chrono::Utc;
-use failure::Fail;
-use failure::ResultExt;
 use opentracingrust::SpanContext;
 use opentracingrust::StartOptions;
 use rusqlite::params;
+use rusqlite::types::Value;
 use rusqlite::Row;
 use serde_json::Value as Json;
 use uuid::Uuid;
@@ -14,6 +13,8 @@ use uuid::Uuid;
 use replicante_util_tracing::MaybeTracer;
 
 use crate::actions::ActionHistoryItem;
+use crate::actions::ActionListItem;
+use crate::actions::ActionLogLine;
 use crate::actions::ActionRecord;
 use crate::actions::ActionRecordView;
 use crate::actions::ActionState;
@@ -21,10 +22,16 @@ use crate::metrics::SQLITE_OPS_COUNT;
 use crate::metrics::SQLITE_OPS_DURATION;
 use crate::metrics::SQLITE_OP_ERRORS_COUNT;
 use crate::store::interface::ActionInterface;
+use crate::store::FinishedAction;
 use crate::store::Iter;
+use crate::store::Page;
 use crate::Error;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
+
+use super::actions::parse_actions_list;
+use super::timestamps;
 
 const ACTION_GET: &str = "action.get";
 const ACTION_GET_SQL: &str = r#"
@@ -36,6 +43,7 @@ SELECT
     headers,
     id,
     kind,
+    parent_action_id,
     requester,
     scheduled_ts,
     state,
@@ -43,16 +51,30 @@ SELECT
 FROM actions
 WHERE id = ?;
 "#;
+const ACTION_CHILDREN: &str = "action.children";
+const ACTION_CHILDREN_SQL: &str = r#"
+SELECT
+    kind, id, state
+FROM actions
+WHERE parent_action_id = ?
+ORDER BY scheduled_ts ASC, ROWID ASC
+-- Limit result as a form of blast radius containment in case of bugs.
+LIMIT 100;
+"#;
 const ACTION_GET_HISTORY: &str = "action.get.history";
 const ACTION_GET_HISTORY_SQL: &str = r#"
 SELECT
+    id,
     action_id,
     time,
     state,
     state_payload
 FROM actions_history
-WHERE action_id = ?
-ORDER BY time DESC, ROWID DESC;
+WHERE
+    action_id = ?1
+    AND (?2 IS NULL OR time < ?2 OR (time = ?2 AND id < ?3))
+ORDER BY time DESC, id DESC
+LIMIT ?4;
 "#;
 const ACTION_INSERT: &str = "action.insert";
 const ACTION_INSERT_SQL: &str = r#"
@@ -63,12 +85,13 @@ INSERT INTO actions (
     headers,
     id,
     kind,
+    parent_action_id,
     requester,
     scheduled_ts,
     state,
     state_payload
 )
-VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10);
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11);
 "#;
 const ACTION_INSERT_HISTORY: &str = "action.insert.history";
 const ACTION_INSERT_HISTORY_SQL: &str = r#"
@@ -80,6 +103,48 @@ INSERT INTO actions_history (
 )
 VALUES (?1, ?2, ?3, ?4);
 "#;
+const ACTION_LEASE: &str = "action.lease";
+const ACTION_LEASE_SQL: &str = r#"
+UPDATE actions
+SET
+    lease_owner = ?1,
+    lease_expires_ts = ?2
+WHERE
+    id = ?3
+    AND (lease_owner IS NULL OR lease_owner = ?1 OR lease_expires_ts < ?4);
+"#;
+const ACTION_LOG: &str = "action.log";
+const ACTION_LOG_SQL: &str = r#"
+INSERT INTO actions_logs (
+    action_id,
+    time,
+    line
+)
+VALUES (?1, ?2, ?3);
+"#;
+const ACTION_LOG_TRIM: &str = "action.log.trim";
+const ACTION_LOG_TRIM_SQL: &str = r#"
+DELETE FROM actions_logs
+WHERE
+    action_id = ?1
+    AND id NOT IN (
+        SELECT id
+        FROM actions_logs
+        WHERE action_id = ?1
+        ORDER BY id DESC
+        LIMIT ?2
+    );
+"#;
+const ACTION_LOGS: &str = "action.logs";
+const ACTION_LOGS_SQL: &str = r#"
+SELECT
+    action_id,
+    time,
+    line
+FROM actions_logs
+WHERE action_id = ?
+ORDER BY id ASC;
+"#;
 const ACTION_NEXT: &str = "action.next";
 const ACTION_NEXT_SQL: &str = r#"
 SELECT
@@ -90,15 +155,39 @@ SELECT
     headers,
     id,
     kind,
+    parent_action_id,
     requester,
     scheduled_ts,
     state,
     state_payload
 FROM actions
-WHERE finished_ts IS NULL
+WHERE
+    finished_ts IS NULL
+    AND (lease_owner IS NULL OR lease_owner = ?1 OR lease_expires_ts < ?2)
 ORDER BY scheduled_ts ASC, ROWID ASC
 LIMIT 1;
 "#;
+const ACTION_NEXT_FINISHED: &str = "action.next.finished";
+const ACTION_NEXT_FINISHED_SQL: &str = r#"
+SELECT
+    agent_version,
+    args,
+    created_ts,
+    finished_ts,
+    headers,
+    id,
+    kind,
+    parent_action_id,
+    requester,
+    scheduled_ts,
+    state,
+    state_payload,
+    ROWID AS position
+FROM actions
+WHERE finished_ts > ?1 OR (finished_ts = ?1 AND ROWID > ?2)
+ORDER BY finished_ts ASC, ROWID ASC
+LIMIT 1;
+"#;
 const ACTION_TRANSITION: &str = "action.transition";
 const ACTION_TRANSITION_SQL: &str = r#"
 UPDATE actions
@@ -106,7 +195,9 @@ SET
     state = ?1,
     state_payload = ?2,
     finished_ts = ?3
-WHERE id = ?4;
+WHERE
+    id = ?4
+    AND (lease_owner IS NULL OR lease_owner = ?5);
 "#;
 
 const ACTION_DUPLICATE_ERROR_MSG: &str = "UNIQUE constraint failed: actions.id";
@@ -117,9 +208,7 @@ macro_rules! decode_or_continue {
         match $decode {
             Ok(r) => r,
             Err(error) => {
-                let error = Err(error)
-                    .with_context(|_| ErrorKind::PersistentRead($op))
-                    .map_err(Error::from);
+                let error = Err(error).with_context(|_| ErrorKind::PersistentRead($op));
                 $res.push(error);
                 continue;
             }
@@ -133,15 +222,37 @@ macro_rules! decode_or_return {
         match $decode {
             Ok(r) => r,
             Err(error) => {
-                let error = Err(error)
-                    .with_context(|_| ErrorKind::PersistentRead($op))
-                    .map_err(Error::from);
+                let error = Err(error).with_context(|_| ErrorKind::PersistentRead($op));
                 return error;
             }
         }
     };
 }
 
+/// Encode the position of a history record, or finished action, into a pagination token.
+fn history_token(time: String, id: i64) -> String {
+    format!("{}-{}", time, id)
+}
+
+/// Decode a pagination token into the position of the last history record returned.
+///
+/// Tokens with epoch timestamps, issued before timestamps moved to RFC3339, are still accepted.
+fn parse_history_token(token: &str) -> Result<(String, i64)> {
+    let mut parts = token.rsplitn(2, '-');
+    let id = parts.next().and_then(|id| id.parse().ok());
+    let time = parts.next().and_then(|time| {
+        let value = match time.parse() {
+            Ok(seconds) => Value::Integer(seconds),
+            Err(_) => Value::Text(time.to_string()),
+        };
+        timestamps::decode(value, ACTION_GET_HISTORY).ok()
+    });
+    match (time, id) {
+        (Some(time), Some(id)) => Ok((timestamps::encode(&time), id)),
+        _ => Err(ErrorKind::InvalidPageToken(token.to_string()).into()),
+    }
+}
+
 /// Parse a SQLite result row into a full ActionRecord.
 fn parse_action(row: &Row, op: &'static str) -> Result<ActionRecord> {
     let id: String = decode_or_return!(row.get("id"), op);
@@ -149,17 +260,19 @@ fn parse_action(row: &Row, op: &'static str) -> Result<ActionRecord> {
     let agent_version: String = decode_or_return!(row.get("agent_version"), op);
     let args: String = decode_or_return!(row.get("args"), op);
     let args = decode_or_return!(serde_json::from_str(&args), op);
-    let created_ts: i64 = decode_or_return!(row.get("created_ts"), op);
-    let created_ts = Utc.timestamp(created_ts, 0);
-    let finished_ts: Option<i64> = decode_or_return!(row.get("finished_ts"), op);
-    let finished_ts = finished_ts.map(|ts| Utc.timestamp(ts, 0));
+    let created_ts = timestamps::column(row, "created_ts", op)?;
+    let finished_ts = timestamps::optional_column(row, "finished_ts", op)?;
     let headers: String = decode_or_return!(row.get("headers"), op);
     let headers = decode_or_return!(serde_json::from_str(&headers), op);
     let kind: String = decode_or_return!(row.get("kind"), op);
+    let parent_action_id: Option<String> = decode_or_return!(row.get("parent_action_id"), op);
+    let parent_action_id = match parent_action_id {
+        None => None,
+        Some(id) => Some(decode_or_return!(Uuid::from_str(&id), op)),
+    };
     let requester: String = decode_or_return!(row.get("requester"), op);
     let requester = decode_or_return!(serde_json::from_str(&requester), op);
-    let scheduled_ts: i64 = decode_or_return!(row.get("scheduled_ts"), op);
-    let scheduled_ts = Utc.timestamp(scheduled_ts, 0);
+    let scheduled_ts = timestamps::column(row, "scheduled_ts", op)?;
     let state: String = decode_or_return!(row.get("state"), op);
     let state = decode_or_return!(serde_json::from_str(&state), op);
     let state_payload: Option<String> = decode_or_return!(row.get("state_payload"), op);
@@ -175,6 +288,7 @@ fn parse_action(row: &Row, op: &'static str) -> Result<ActionRecord> {
         headers,
         id,
         kind,
+        parent_action_id,
         requester,
         scheduled_ts,
         state,
@@ -223,7 +337,7 @@ impl<'a, 'b: 'a> Action<'a, 'b> {
         statement
             .execute(params![
                 action_id,
-                Utc::now().timestamp(),
+                timestamps::encode(&Utc::now()),
                 state,
                 state_payload,
             ])
@@ -237,6 +351,34 @@ impl<'a, 'b: 'a> Action<'a, 'b> {
 }
 
 impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
+    fn children(&self, id: &str, span: Option<SpanContext>) -> Result<Iter<ActionListItem>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", ACTION_CHILDREN_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_CHILDREN_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_CHILDREN))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        parse_actions_list(&mut statement, params![id], ACTION_CHILDREN).map_err(|error| {
+            SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+            error
+        })
+    }
+
     fn get(&self, id: &str, span: Option<SpanContext>) -> Result<Option<ActionRecord>> {
         let _span = self.tracer.with(|tracer| {
             let mut opts = StartOptions::default();
@@ -281,14 +423,27 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
         parse_action(row, ACTION_GET).map(Some)
     }
 
-    fn history(&self, id: &str, span: Option<SpanContext>) -> Result<Iter<ActionHistoryItem>> {
+    fn history(
+        &self,
+        id: &str,
+        limit: u32,
+        after: Option<&str>,
+        span: Option<SpanContext>,
+    ) -> Result<Page<ActionHistoryItem>> {
+        let (after_time, after_id) = match after {
+            None => (None, None),
+            Some(token) => {
+                let (time, id) = parse_history_token(token)?;
+                (Some(time), Some(id))
+            }
+        };
         let _span = self.tracer.with(|tracer| {
             let mut opts = StartOptions::default();
             if let Some(context) = span {
                 opts = opts.child_of(context);
             }
             let mut span = tracer.span_with_options("store.sqlite.select", opts);
-            span.tag("sql", ACTION_GET_SQL);
+            span.tag("sql", ACTION_GET_HISTORY_SQL);
             span.auto_finish()
         });
         SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
@@ -303,20 +458,37 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
                 SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
                 error
             })?;
+
+        // Fetch one extra row to know if there is a next page without loading it.
         let mut results = Vec::new();
+        let mut last = None;
+        let mut next = None;
         let mut rows = statement
-            .query(params![id])
+            .query(params![id, after_time, after_id, i64::from(limit) + 1])
             .with_context(|_| ErrorKind::PersistentRead(ACTION_GET_HISTORY))?;
-        let mut maybe_row = rows
+        while let Some(row) = rows
             .next()
-            .with_context(|_| ErrorKind::PersistentRead(ACTION_GET_HISTORY))?;
-        while let Some(row) = maybe_row {
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_GET_HISTORY))?
+        {
+            if results.len() as u32 >= limit {
+                next = last.map(|(time, id)| history_token(time, id));
+                break;
+            }
+            let id: i64 = decode_or_continue!(row.get("id"), results, ACTION_GET_HISTORY);
+            let timestamp: Value =
+                decode_or_continue!(row.get("time"), results, ACTION_GET_HISTORY);
+            let timestamp = match timestamps::decode(timestamp, ACTION_GET_HISTORY) {
+                Ok(timestamp) => timestamp,
+                Err(error) => {
+                    results.push(Err(error));
+                    continue;
+                }
+            };
+            last = Some((timestamps::encode(&timestamp), id));
             let action_id: String =
                 decode_or_continue!(row.get("action_id"), results, ACTION_GET_HISTORY);
             let action_id =
                 decode_or_continue!(Uuid::from_str(&action_id), results, ACTION_GET_HISTORY);
-            let timestamp: i64 = decode_or_continue!(row.get("time"), results, ACTION_GET_HISTORY);
-            let timestamp = Utc.timestamp(timestamp, 0);
             let state: String = decode_or_continue!(row.get("state"), results, ACTION_GET_HISTORY);
             let state: ActionState =
                 decode_or_continue!(serde_json::from_str(&state), results, ACTION_GET_HISTORY);
@@ -334,11 +506,11 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
                 state,
                 state_payload,
             }));
-            maybe_row = rows
-                .next()
-                .with_context(|_| ErrorKind::PersistentRead(ACTION_GET_HISTORY))?;
         }
-        Ok(Iter::new(results.into_iter()))
+        Ok(Page {
+            items: Iter::new(results.into_iter()),
+            next,
+        })
     }
 
     fn insert(&self, action: ActionRecord, span: Option<SpanContext>) -> Result<()> {
@@ -366,7 +538,6 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             .map(|payload| {
                 serde_json::to_string(&payload)
                     .with_context(|_| ErrorKind::PersistentWrite(ACTION_INSERT))
-                    .map_err(Error::from)
             })
             .transpose()?;
         SQLITE_OPS_COUNT.with_label_values(&["INSERT"]).inc();
@@ -384,12 +555,13 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
         let result = statement.execute(params![
             action.agent_version,
             args,
-            action.created_ts.timestamp(),
+            timestamps::encode(&action.created_ts),
             headers,
             &action_id,
             action.kind,
+            action.parent_action_id.map(|id| id.to_string()),
             requester,
-            action.scheduled_ts.timestamp(),
+            timestamps::encode(&action.scheduled_ts),
             &state,
             &state_payload,
         ]);
@@ -404,8 +576,8 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             }
             Err(error) => {
                 SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
-                let error = error.context(ErrorKind::PersistentWrite(ACTION_INSERT));
-                return Err(error.into());
+                let error = Error::with_source(ErrorKind::PersistentWrite(ACTION_INSERT), error);
+                return Err(error);
             }
         };
         self.record_transition(
@@ -417,7 +589,161 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
         Ok(())
     }
 
-    fn next(&self, span: Option<SpanContext>) -> Result<Option<ActionRecord>> {
+    fn log(
+        &self,
+        action: &ActionRecord,
+        line: &str,
+        keep: u32,
+        span: Option<SpanContext>,
+    ) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.insert", opts);
+            span.tag("sql", ACTION_LOG_SQL);
+            span.auto_finish()
+        });
+        let action_id = action.id.to_string();
+        SQLITE_OPS_COUNT.with_label_values(&["INSERT"]).inc();
+        let timer = SQLITE_OPS_DURATION
+            .with_label_values(&["INSERT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_LOG_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_LOG))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        statement
+            .execute(params![&action_id, timestamps::encode(&Utc::now()), line])
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_LOG))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        timer.observe_duration();
+
+        // Drop the oldest lines so chatty actions don't grow the DB endlessly.
+        SQLITE_OPS_COUNT.with_label_values(&["DELETE"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["DELETE"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_LOG_TRIM_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_LOG_TRIM))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["DELETE"]).inc();
+                error
+            })?;
+        statement
+            .execute(params![&action_id, keep])
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_LOG_TRIM))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["DELETE"]).inc();
+                error
+            })?;
+        Ok(())
+    }
+
+    fn logs(&self, id: &str, span: Option<SpanContext>) -> Result<Iter<ActionLogLine>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", ACTION_LOGS_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_LOGS_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_LOGS))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        let mut rows = statement
+            .query(params![id])
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_LOGS))?;
+        let mut results = Vec::new();
+        while let Some(row) = rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_LOGS))?
+        {
+            let action_id: String = decode_or_continue!(row.get("action_id"), results, ACTION_LOGS);
+            let action_id = decode_or_continue!(Uuid::from_str(&action_id), results, ACTION_LOGS);
+            let timestamp: Value = decode_or_continue!(row.get("time"), results, ACTION_LOGS);
+            let timestamp = match timestamps::decode(timestamp, ACTION_LOGS) {
+                Ok(timestamp) => timestamp,
+                Err(error) => {
+                    results.push(Err(error));
+                    continue;
+                }
+            };
+            let line: String = decode_or_continue!(row.get("line"), results, ACTION_LOGS);
+            results.push(Ok(ActionLogLine {
+                action_id,
+                line,
+                timestamp,
+            }));
+        }
+        Ok(Iter::new(results.into_iter()))
+    }
+
+    fn lease(
+        &self,
+        action: &ActionRecord,
+        owner: &str,
+        expires: DateTime<Utc>,
+        span: Option<SpanContext>,
+    ) -> Result<bool> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.update", opts);
+            span.tag("sql", ACTION_LEASE_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["UPDATE"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["UPDATE"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_LEASE_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_LEASE))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["UPDATE"]).inc();
+                error
+            })?;
+        let changed = statement
+            .execute(params![
+                owner,
+                timestamps::encode(&expires),
+                action.id.to_string(),
+                timestamps::encode(&Utc::now()),
+            ])
+            .with_context(|_| ErrorKind::PersistentWrite(ACTION_LEASE))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["UPDATE"]).inc();
+                error
+            })?;
+        Ok(changed == 1)
+    }
+
+    fn next(&self, owner: &str, span: Option<SpanContext>) -> Result<Option<ActionRecord>> {
         let _span = self.tracer.with(|tracer| {
             let mut opts = StartOptions::default();
             if let Some(context) = span {
@@ -440,7 +766,7 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
                 error
             })?;
         let mut rows = statement
-            .query([])
+            .query(params![owner, timestamps::encode(&Utc::now())])
             .with_context(|_| ErrorKind::PersistentRead(ACTION_NEXT))
             .map_err(|error| {
                 SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
@@ -461,11 +787,72 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
         parse_action(row, ACTION_NEXT).map(Some)
     }
 
+    fn next_finished(
+        &self,
+        since: DateTime<Utc>,
+        after: Option<&str>,
+        span: Option<SpanContext>,
+    ) -> Result<Option<FinishedAction>> {
+        // Cursors pair the finish time with the ROWID to tell apart actions
+        // that finished at the same time: with only `since` no action matches on ROWID.
+        let (after_time, after_id) = match after {
+            None => (timestamps::encode(&since), i64::MAX),
+            Some(token) => parse_history_token(token)?,
+        };
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", ACTION_NEXT_FINISHED_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
+        let timer = SQLITE_OPS_DURATION
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTION_NEXT_FINISHED_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_NEXT_FINISHED))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        let mut rows = statement
+            .query(params![after_time, after_id])
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_NEXT_FINISHED))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        let row = rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(ACTION_NEXT_FINISHED))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        timer.observe_duration();
+        let row = match row {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+        let action = parse_action(row, ACTION_NEXT_FINISHED)?;
+        // Use the stored value so the cursor compares equal to the row it came from.
+        let finished_ts: String = decode_or_return!(row.get("finished_ts"), ACTION_NEXT_FINISHED);
+        let position: i64 = decode_or_return!(row.get("position"), ACTION_NEXT_FINISHED);
+        let cursor = history_token(finished_ts, position);
+        Ok(Some(FinishedAction { action, cursor }))
+    }
+
     fn transition(
         &self,
         action: &ActionRecord,
         transition_to: ActionState,
         payload: Option<Json>,
+        owner: &str,
         span: Option<SpanContext>,
     ) -> Result<()> {
         let span = self.tracer.with(|tracer| {
@@ -478,7 +865,7 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             span.auto_finish()
         });
         let finished_ts = if transition_to.is_finished() {
-            Some(Utc::now().timestamp())
+            Some(timestamps::encode(&Utc::now()))
         } else {
             None
         };
@@ -489,7 +876,6 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
             .map(|payload| {
                 serde_json::to_string(&payload)
                     .with_context(|_| ErrorKind::PersistentWrite(ACTION_TRANSITION))
-                    .map_err(Error::from)
             })
             .transpose()?;
         SQLITE_OPS_COUNT.with_label_values(&["UPDATE"]).inc();
@@ -504,13 +890,16 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
                 SQLITE_OP_ERRORS_COUNT.with_label_values(&["UPDATE"]).inc();
                 error
             })?;
-        statement
-            .execute(params![state, state_payload, finished_ts, action_id])
+        let changed = statement
+            .execute(params![state, state_payload, finished_ts, action_id, owner])
             .with_context(|_| ErrorKind::PersistentWrite(ACTION_TRANSITION))
             .map_err(|error| {
                 SQLITE_OP_ERRORS_COUNT.with_label_values(&["UPDATE"]).inc();
                 error
             })?;
+        if changed == 0 {
+            return Err(ErrorKind::ActionLeaseLost(action_id).into());
+        }
         self.record_transition(
             action_id,
             state,
@@ -520,3 +909,67 @@ impl<'a, 'b: 'a> ActionInterface for Action<'a, 'b> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::Utc;
+    use serde_json::json;
+
+    use super::history_token;
+    use super::parse_history_token;
+    use crate::actions::ActionRecord;
+    use crate::actions::ActionRequester;
+    use crate::actions::ActionState;
+    use crate::store::Store;
+
+    #[test]
+    fn next_finished_follows_cursor() {
+        let store = Store::sqlite();
+        let since = Utc::now() - Duration::seconds(1);
+        // Actions finished in the same transaction often share a finish time.
+        let mut finished = Vec::new();
+        store
+            .with_transaction(|tx| {
+                for _ in 0..3 {
+                    let action =
+                        ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+                    tx.action().insert(action.clone(), None)?;
+                    tx.action()
+                        .transition(&action, ActionState::Done, None, None)?;
+                    finished.push(action.id);
+                }
+                Ok(())
+            })
+            .unwrap();
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        while let Some(next) = store
+            .with_transaction(|tx| tx.action().next_finished(since, cursor.as_deref(), None))
+            .unwrap()
+        {
+            seen.push(next.action.id);
+            cursor = Some(next.cursor);
+        }
+        assert_eq!(seen, finished);
+    }
+
+    #[test]
+    fn history_token_round_trip() {
+        let time = "2020-09-13T12:26:40.123Z".to_string();
+        let token = history_token(time.clone(), 42);
+        assert_eq!(parse_history_token(&token).unwrap(), (time, 42));
+    }
+
+    #[test]
+    fn history_token_legacy() {
+        let time = "2020-09-13T12:26:40.000Z".to_string();
+        assert_eq!(parse_history_token("1600000000-42").unwrap(), (time, 42));
+    }
+
+    #[test]
+    fn history_token_invalid() {
+        assert!(parse_history_token("junk").is_err());
+        assert!(parse_history_token("1600000000-").is_err());
+    }
+}
@@ -1,9 +1,10 @@
 use std::str::FromStr;
 
-use failure::ResultExt;
 use opentracingrust::SpanContext;
 use opentracingrust::StartOptions;
 use rusqlite::params;
+use rusqlite::Params;
+use rusqlite::Row;
 use rusqlite::Statement;
 use uuid::Uuid;
 
@@ -15,11 +16,43 @@ use crate::metrics::SQLITE_OPS_COUNT;
 use crate::metrics::SQLITE_OPS_DURATION;
 use crate::metrics::SQLITE_OP_ERRORS_COUNT;
 use crate::store::interface::ActionsInterface;
+use crate::store::ArchivedAction;
+use crate::store::ArchivedActionItem;
 use crate::store::Iter;
-use crate::Error;
+use crate::store::Page;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
+use super::timestamps;
+
+const ACTIONS_ARCHIVE: &str = "action.archive";
+const ACTIONS_ARCHIVE_SQL: &str = r#"
+INSERT INTO actions_archive (
+    id,
+    kind,
+    archived_ts,
+    finished_ts,
+    payload
+)
+VALUES (?1, ?2, ?3, ?4, ?5);
+"#;
+const ACTIONS_ARCHIVED: &str = "action.archived";
+const ACTIONS_ARCHIVED_SQL: &str = r#"
+SELECT
+    archive_id, id, kind, archived_ts, finished_ts, payload
+FROM actions_archive
+WHERE id = ?1;
+"#;
+const ACTIONS_ARCHIVED_LIST: &str = "action.archived.list";
+const ACTIONS_ARCHIVED_LIST_SQL: &str = r#"
+SELECT
+    archive_id, id, kind, archived_ts, finished_ts
+FROM actions_archive
+WHERE ?1 IS NULL OR archive_id < ?1
+ORDER BY archive_id DESC
+LIMIT ?2;
+"#;
 const ACTIONS_FINISHED: &str = "action.finished";
 const ACTIONS_FINISHED_SQL: &str = r#"
 SELECT
@@ -42,6 +75,17 @@ ORDER BY scheduled_ts ASC, ROWID ASC
 -- There really should not be many running/pending actions on an agent.
 LIMIT 100;
 "#;
+const ACTIONS_PRUNABLE: &str = "action.prunable";
+const ACTIONS_PRUNABLE_SQL: &str = r#"
+SELECT
+    kind, id, state
+FROM actions
+WHERE finished_ts IS NOT NULL
+-- Must select the same actions as ACTIONS_PRUNE_SQL.
+ORDER BY finished_ts DESC, ROWID DESC
+LIMIT ?1
+OFFSET ?2;
+"#;
 const ACTIONS_PRUNE: &str = "action.prune";
 const ACTIONS_PRUNE_SQL: &str = r#"
 DELETE FROM actions
@@ -49,7 +93,8 @@ WHERE id IN (
     SELECT id
     FROM actions
     WHERE finished_ts IS NOT NULL
-    ORDER BY finished_ts DESC
+    -- Break ties on ROWID so ACTIONS_PRUNABLE_SQL selects the same actions.
+    ORDER BY finished_ts DESC, ROWID DESC
     -- Limit result as a form of blast radius containment in case of bugs.
     -- There really should not be many finished actions to clean up on an agent.
     LIMIT ?1
@@ -64,9 +109,7 @@ macro_rules! decode_or_continue {
         match $decode {
             Ok(r) => r,
             Err(error) => {
-                let error = Err(error)
-                    .with_context(|_| ErrorKind::PersistentRead($op))
-                    .map_err(Error::from);
+                let error = Err(error).with_context(|_| ErrorKind::PersistentRead($op));
                 $res.push(error);
                 continue;
             }
@@ -75,10 +118,17 @@ macro_rules! decode_or_continue {
 }
 
 /// Helper to convert the result of a SELECT id, state ...; into an ActionListItem iterator.
-fn parse_actions_list(statement: &mut Statement, op: &'static str) -> Result<Iter<ActionListItem>> {
+pub(super) fn parse_actions_list<P>(
+    statement: &mut Statement,
+    params: P,
+    op: &'static str,
+) -> Result<Iter<ActionListItem>>
+where
+    P: Params,
+{
     let mut results = Vec::new();
     let mut rows = statement
-        .query([])
+        .query(params)
         .with_context(|_| ErrorKind::PersistentRead(op))?;
     let mut maybe_row = rows
         .next()
@@ -97,6 +147,25 @@ fn parse_actions_list(statement: &mut Statement, op: &'static str) -> Result<Ite
     Ok(Iter::new(results.into_iter()))
 }
 
+/// Parse a SQLite result row into an archived action summary.
+fn parse_archived_item(row: &Row, op: &'static str) -> Result<ArchivedActionItem> {
+    let id: String = row
+        .get("id")
+        .with_context(|_| ErrorKind::PersistentRead(op))?;
+    let id = Uuid::from_str(&id).with_context(|_| ErrorKind::PersistentRead(op))?;
+    let kind: String = row
+        .get("kind")
+        .with_context(|_| ErrorKind::PersistentRead(op))?;
+    let archived_ts = timestamps::column(row, "archived_ts", op)?;
+    let finished_ts = timestamps::optional_column(row, "finished_ts", op)?;
+    Ok(ArchivedActionItem {
+        archived_ts,
+        finished_ts,
+        id,
+        kind,
+    })
+}
+
 pub struct Actions<'a, 'b: 'a> {
     inner: &'a rusqlite::Transaction<'b>,
     tracer: MaybeTracer,
@@ -109,6 +178,160 @@ impl<'a, 'b: 'a> Actions<'a, 'b> {
 }
 
 impl<'a, 'b: 'a> ActionsInterface for Actions<'a, 'b> {
+    fn archive(&self, action: &ArchivedAction, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.insert", opts);
+            span.tag("sql", ACTIONS_ARCHIVE_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["INSERT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["INSERT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTIONS_ARCHIVE_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(ACTIONS_ARCHIVE))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        statement
+            .execute(params![
+                action.id.to_string(),
+                action.kind,
+                timestamps::encode(&action.archived_ts),
+                action.finished_ts.as_ref().map(timestamps::encode),
+                action.payload,
+            ])
+            .with_context(|_| ErrorKind::PersistentWrite(ACTIONS_ARCHIVE))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        Ok(())
+    }
+
+    fn archived(&self, id: &str, span: Option<SpanContext>) -> Result<Option<ArchivedAction>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", ACTIONS_ARCHIVED_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTIONS_ARCHIVED_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_ARCHIVED))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        let mut rows = statement
+            .query(params![id])
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_ARCHIVED))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        let row = rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_ARCHIVED))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        let row = match row {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+        let item = parse_archived_item(row, ACTIONS_ARCHIVED)?;
+        let payload: Vec<u8> = row
+            .get("payload")
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_ARCHIVED))?;
+        Ok(Some(ArchivedAction {
+            archived_ts: item.archived_ts,
+            finished_ts: item.finished_ts,
+            id: item.id,
+            kind: item.kind,
+            payload,
+        }))
+    }
+
+    fn archived_list(
+        &self,
+        limit: u32,
+        after: Option<&str>,
+        span: Option<SpanContext>,
+    ) -> Result<Page<ArchivedActionItem>> {
+        // Tokens are the archive_id of the last action returned.
+        let after: Option<i64> = match after {
+            None => None,
+            Some(token) => Some(
+                token
+                    .parse()
+                    .map_err(|_| ErrorKind::InvalidPageToken(token.to_string()))?,
+            ),
+        };
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", ACTIONS_ARCHIVED_LIST_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTIONS_ARCHIVED_LIST_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_ARCHIVED_LIST))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+
+        // Fetch one extra row to know if there is a next page without loading it.
+        let mut results = Vec::new();
+        let mut last = None;
+        let mut next = None;
+        let mut rows = statement
+            .query(params![after, i64::from(limit) + 1])
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_ARCHIVED_LIST))?;
+        while let Some(row) = rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_ARCHIVED_LIST))?
+        {
+            if results.len() as u32 >= limit {
+                next = last.map(|id: i64| id.to_string());
+                break;
+            }
+            let archive_id: i64 =
+                decode_or_continue!(row.get("archive_id"), results, ACTIONS_ARCHIVED_LIST);
+            last = Some(archive_id);
+            results.push(parse_archived_item(row, ACTIONS_ARCHIVED_LIST));
+        }
+        Ok(Page {
+            items: Iter::new(results.into_iter()),
+            next,
+        })
+    }
+
     fn finished(&self, span: Option<SpanContext>) -> Result<Iter<ActionListItem>> {
         let _span = self.tracer.with(|tracer| {
             let mut opts = StartOptions::default();
@@ -131,7 +354,7 @@ impl<'a, 'b: 'a> ActionsInterface for Actions<'a, 'b> {
                 SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
                 error
             })?;
-        parse_actions_list(&mut statement, ACTIONS_FINISHED).map_err(|error| {
+        parse_actions_list(&mut statement, [], ACTIONS_FINISHED).map_err(|error| {
             SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
             error
         })
@@ -159,12 +382,47 @@ impl<'a, 'b: 'a> ActionsInterface for Actions<'a, 'b> {
                 SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
                 error
             })?;
-        parse_actions_list(&mut statement, ACTIONS_QUEUE).map_err(|error| {
+        parse_actions_list(&mut statement, [], ACTIONS_QUEUE).map_err(|error| {
             SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
             error
         })
     }
 
+    fn prunable(
+        &self,
+        keep: u32,
+        limit: u32,
+        span: Option<SpanContext>,
+    ) -> Result<Iter<ActionListItem>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", ACTIONS_PRUNABLE_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(ACTIONS_PRUNABLE_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(ACTIONS_PRUNABLE))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        parse_actions_list(&mut statement, params![limit, keep], ACTIONS_PRUNABLE).map_err(
+            |error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            },
+        )
+    }
+
     fn prune(&self, keep: u32, limit: u32, span: Option<SpanContext>) -> Result<()> {
         let _span = self.tracer.with(|tracer| {
             let mut opts = StartOptions::default();
@@ -197,3 +455,101 @@ impl<'a, 'b: 'a> ActionsInterface for Actions<'a, 'b> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::actions::ActionListItem;
+    use crate::actions::ActionRecord;
+    use crate::actions::ActionRequester;
+    use crate::actions::ActionState;
+    use crate::store::ArchivedAction;
+    use crate::store::ArchivedActionItem;
+    use crate::store::Store;
+
+    /// Insert an action and transition it to done.
+    fn finished(store: &Store) -> ActionRecord {
+        let action = ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+        store
+            .with_transaction(|tx| {
+                tx.action().insert(action.clone(), None)?;
+                tx.action()
+                    .transition(&action, ActionState::Done, None, None)
+            })
+            .unwrap();
+        // Ensure actions finish at distinct times.
+        thread::sleep(Duration::from_millis(2));
+        let id = action.id.to_string();
+        store
+            .with_transaction(|tx| tx.action().get(&id, None))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn archive_round_trip() {
+        let store = Store::sqlite();
+        let first = ArchivedAction::new(finished(&store), Vec::new()).unwrap();
+        let second = ArchivedAction::new(finished(&store), Vec::new()).unwrap();
+        let id = first.id.to_string();
+        let (found, page, next) = store
+            .with_transaction(|tx| {
+                tx.actions().archive(&first, None)?;
+                tx.actions().archive(&second, None)?;
+                let found = tx.actions().archived(&id, None)?;
+                let page = tx.actions().archived_list(1, None, None)?;
+                let items: Vec<ArchivedActionItem> = page.items.collect::<crate::Result<_>>()?;
+                let next = tx.actions().archived_list(1, page.next.as_deref(), None)?;
+                let next: Vec<ArchivedActionItem> = next.items.collect::<crate::Result<_>>()?;
+                Ok((found, items, next))
+            })
+            .unwrap();
+        let found = found.unwrap();
+        assert_eq!(found.payload, first.payload);
+        assert_eq!(found.kind, first.kind);
+        assert_eq!(found.finished_ts, first.finished_ts);
+        assert_eq!(page, vec![ArchivedActionItem::from(&second)]);
+        assert_eq!(next, vec![ArchivedActionItem::from(&first)]);
+    }
+
+    #[test]
+    fn archive_rejects_duplicates() {
+        let store = Store::sqlite();
+        let archived = ArchivedAction::new(finished(&store), Vec::new()).unwrap();
+        let result = store.with_transaction(|tx| {
+            tx.actions().archive(&archived, None)?;
+            tx.actions().archive(&archived, None)
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn prunable_matches_prune() {
+        let store = Store::sqlite();
+        let oldest = finished(&store);
+        let older = finished(&store);
+        let newest = finished(&store);
+        let running = ActionRecord::new("test", None, None, json!({}), ActionRequester::AgentApi);
+        let (prunable, remaining) = store
+            .with_transaction(|tx| {
+                tx.action().insert(running.clone(), None)?;
+                let prunable: Vec<ActionListItem> = tx
+                    .actions()
+                    .prunable(1, 10, None)?
+                    .collect::<crate::Result<_>>()?;
+                tx.actions().prune(1, 10, None)?;
+                let remaining: Vec<ActionListItem> =
+                    tx.actions().finished(None)?.collect::<crate::Result<_>>()?;
+                Ok((prunable, remaining))
+            })
+            .unwrap();
+        let prunable: Vec<_> = prunable.into_iter().map(|item| item.id).collect();
+        assert_eq!(prunable, vec![older.id, oldest.id]);
+        let remaining: Vec<_> = remaining.into_iter().map(|item| item.id).collect();
+        assert_eq!(remaining, vec![newest.id]);
+    }
+}
@@ -0,0 +1,119 @@
+use chrono::Utc;
+use opentracingrust::SpanContext;
+use opentracingrust::StartOptions;
+use rusqlite::params;
+use serde_json::Value as Json;
+
+use replicante_util_tracing::MaybeTracer;
+
+use super::timestamps;
+use crate::metrics::SQLITE_OPS_COUNT;
+use crate::metrics::SQLITE_OPS_DURATION;
+use crate::metrics::SQLITE_OP_ERRORS_COUNT;
+use crate::store::interface::AgentStateInterface;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+const AGENT_STATE_GET: &str = "agent_state.get";
+const AGENT_STATE_GET_SQL: &str = r#"
+SELECT value
+FROM agent_state
+WHERE key = ?1;
+"#;
+const AGENT_STATE_SET: &str = "agent_state.set";
+const AGENT_STATE_SET_SQL: &str = r#"
+INSERT OR REPLACE INTO agent_state (
+    key,
+    value,
+    updated_ts
+)
+VALUES (?1, ?2, ?3);
+"#;
+
+pub struct AgentState<'a, 'b: 'a> {
+    inner: &'a rusqlite::Transaction<'b>,
+    tracer: MaybeTracer,
+}
+
+impl<'a, 'b: 'a> AgentState<'a, 'b> {
+    pub fn new(inner: &'a rusqlite::Transaction<'b>, tracer: MaybeTracer) -> AgentState<'a, 'b> {
+        AgentState { inner, tracer }
+    }
+}
+
+impl<'a, 'b: 'a> AgentStateInterface for AgentState<'a, 'b> {
+    fn get(&self, key: &str, span: Option<SpanContext>) -> Result<Option<Json>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", AGENT_STATE_GET_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(AGENT_STATE_GET_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(AGENT_STATE_GET))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        let mut rows = statement
+            .query(params![key])
+            .with_context(|_| ErrorKind::PersistentRead(AGENT_STATE_GET))?;
+        let row = match rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(AGENT_STATE_GET))?
+        {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+        let value: String = row
+            .get("value")
+            .with_context(|_| ErrorKind::PersistentRead(AGENT_STATE_GET))?;
+        let value = serde_json::from_str(&value)
+            .with_context(|_| ErrorKind::PersistentRead(AGENT_STATE_GET))?;
+        Ok(Some(value))
+    }
+
+    fn set(&self, key: &str, value: &Json, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.insert", opts);
+            span.tag("sql", AGENT_STATE_SET_SQL);
+            span.auto_finish()
+        });
+        let value = serde_json::to_string(value)
+            .with_context(|_| ErrorKind::PersistentWrite(AGENT_STATE_SET))?;
+        SQLITE_OPS_COUNT.with_label_values(&["INSERT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["INSERT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(AGENT_STATE_SET_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(AGENT_STATE_SET))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        statement
+            .execute(params![key, value, timestamps::encode(&Utc::now())])
+            .with_context(|_| ErrorKind::PersistentWrite(AGENT_STATE_SET))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use opentracingrust::SpanContext;
+use opentracingrust::StartOptions;
+use rusqlite::params;
+
+use replicante_util_tracing::MaybeTracer;
+
+use super::timestamps;
+use crate::metrics::SQLITE_OPS_COUNT;
+use crate::metrics::SQLITE_OPS_DURATION;
+use crate::metrics::SQLITE_OP_ERRORS_COUNT;
+use crate::store::interface::APITreeOverridesInterface;
+use crate::store::APITreeOverride;
+use crate::store::Iter;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+const API_TREE_OVERRIDES_CLEAR: &str = "api_tree_overrides.clear";
+const API_TREE_OVERRIDES_CLEAR_SQL: &str = r#"
+DELETE FROM api_tree_overrides;
+"#;
+const API_TREE_OVERRIDES_LIST: &str = "api_tree_overrides.list";
+const API_TREE_OVERRIDES_LIST_SQL: &str = r#"
+SELECT
+    enabled,
+    tree,
+    updated_ts
+FROM api_tree_overrides
+ORDER BY tree ASC;
+"#;
+const API_TREE_OVERRIDES_SET: &str = "api_tree_overrides.set";
+const API_TREE_OVERRIDES_SET_SQL: &str = r#"
+INSERT OR REPLACE INTO api_tree_overrides (
+    enabled,
+    tree,
+    updated_ts
+)
+VALUES (?1, ?2, ?3);
+"#;
+
+/// Helper macro to avoid writing the same match every time.
+macro_rules! decode_or_continue {
+    ($decode:expr, $res:ident, $op:expr $(,)?) => {
+        match $decode {
+            Ok(r) => r,
+            Err(error) => {
+                let error = Err(error).with_context(|_| ErrorKind::PersistentRead($op));
+                $res.push(error);
+                continue;
+            }
+        }
+    };
+}
+
+pub struct APITreeOverrides<'a, 'b: 'a> {
+    inner: &'a rusqlite::Transaction<'b>,
+    tracer: MaybeTracer,
+}
+
+impl<'a, 'b: 'a> APITreeOverrides<'a, 'b> {
+    pub fn new(
+        inner: &'a rusqlite::Transaction<'b>,
+        tracer: MaybeTracer,
+    ) -> APITreeOverrides<'a, 'b> {
+        APITreeOverrides { inner, tracer }
+    }
+}
+
+impl<'a, 'b: 'a> APITreeOverridesInterface for APITreeOverrides<'a, 'b> {
+    fn clear(&self, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.delete", opts);
+            span.tag("sql", API_TREE_OVERRIDES_CLEAR_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["DELETE"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["DELETE"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(API_TREE_OVERRIDES_CLEAR_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(API_TREE_OVERRIDES_CLEAR))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["DELETE"]).inc();
+                error
+            })?;
+        statement
+            .execute(params![])
+            .with_context(|_| ErrorKind::PersistentWrite(API_TREE_OVERRIDES_CLEAR))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["DELETE"]).inc();
+                error
+            })?;
+        Ok(())
+    }
+
+    fn list(&self, span: Option<SpanContext>) -> Result<Iter<APITreeOverride>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", API_TREE_OVERRIDES_LIST_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(API_TREE_OVERRIDES_LIST_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(API_TREE_OVERRIDES_LIST))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        let mut results = Vec::new();
+        let mut rows = statement
+            .query(params![])
+            .with_context(|_| ErrorKind::PersistentRead(API_TREE_OVERRIDES_LIST))?;
+        while let Some(row) = rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(API_TREE_OVERRIDES_LIST))?
+        {
+            let enabled: bool =
+                decode_or_continue!(row.get("enabled"), results, API_TREE_OVERRIDES_LIST);
+            let tree: String =
+                decode_or_continue!(row.get("tree"), results, API_TREE_OVERRIDES_LIST);
+            let updated_ts = match timestamps::column(row, "updated_ts", API_TREE_OVERRIDES_LIST) {
+                Ok(updated_ts) => updated_ts,
+                Err(error) => {
+                    results.push(Err(error));
+                    continue;
+                }
+            };
+            results.push(Ok(APITreeOverride {
+                enabled,
+                tree,
+                updated_ts,
+            }));
+        }
+        Ok(Iter::new(results.into_iter()))
+    }
+
+    fn set(&self, tree: &APITreeOverride, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.insert", opts);
+            span.tag("sql", API_TREE_OVERRIDES_SET_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["INSERT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["INSERT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(API_TREE_OVERRIDES_SET_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(API_TREE_OVERRIDES_SET))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        statement
+            .execute(params![
+                tree.enabled,
+                tree.tree,
+                timestamps::encode(&tree.updated_ts),
+            ])
+            .with_context(|_| ErrorKind::PersistentWrite(API_TREE_OVERRIDES_SET))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        Ok(())
+    }
+}
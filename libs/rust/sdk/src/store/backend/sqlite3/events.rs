@@ -0,0 +1,203 @@
+use std::str::FromStr;
+
+use opentracingrust::SpanContext;
+use opentracingrust::StartOptions;
+use rusqlite::params;
+use uuid::Uuid;
+
+use replicante_util_tracing::MaybeTracer;
+
+use super::timestamps;
+use crate::events::Event;
+use crate::metrics::SQLITE_OPS_COUNT;
+use crate::metrics::SQLITE_OPS_DURATION;
+use crate::metrics::SQLITE_OP_ERRORS_COUNT;
+use crate::store::interface::EventsInterface;
+use crate::store::Iter;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+const EVENTS_HISTORY: &str = "events.history";
+const EVENTS_HISTORY_SQL: &str = r#"
+SELECT
+    event_id,
+    event_ts,
+    kind,
+    payload
+FROM events
+ORDER BY event_ts DESC, ROWID DESC
+LIMIT ?1;
+"#;
+const EVENTS_PERSIST: &str = "events.persist";
+const EVENTS_PERSIST_SQL: &str = r#"
+INSERT INTO events (
+    event_id,
+    event_ts,
+    kind,
+    payload
+)
+VALUES (?1, ?2, ?3, ?4);
+"#;
+const EVENTS_PRUNE: &str = "events.prune";
+const EVENTS_PRUNE_SQL: &str = r#"
+DELETE FROM events
+WHERE event_id NOT IN (
+    SELECT event_id
+    FROM events
+    ORDER BY event_ts DESC, ROWID DESC
+    LIMIT ?1
+);
+"#;
+
+/// Helper macro to avoid writing the same match every time.
+macro_rules! decode_or_continue {
+    ($decode:expr, $res:ident, $op:expr $(,)?) => {
+        match $decode {
+            Ok(r) => r,
+            Err(error) => {
+                let error = Err(error).with_context(|_| ErrorKind::PersistentRead($op));
+                $res.push(error);
+                continue;
+            }
+        }
+    };
+}
+
+pub struct Events<'a, 'b: 'a> {
+    inner: &'a rusqlite::Transaction<'b>,
+    tracer: MaybeTracer,
+}
+
+impl<'a, 'b: 'a> Events<'a, 'b> {
+    pub fn new(inner: &'a rusqlite::Transaction<'b>, tracer: MaybeTracer) -> Events<'a, 'b> {
+        Events { inner, tracer }
+    }
+}
+
+impl<'a, 'b: 'a> EventsInterface for Events<'a, 'b> {
+    fn history(&self, limit: u32, span: Option<SpanContext>) -> Result<Iter<Event>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", EVENTS_HISTORY_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(EVENTS_HISTORY_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(EVENTS_HISTORY))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        let mut results = Vec::new();
+        let mut rows = statement
+            .query(params![limit])
+            .with_context(|_| ErrorKind::PersistentRead(EVENTS_HISTORY))?;
+        while let Some(row) = rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(EVENTS_HISTORY))?
+        {
+            let event_id: String =
+                decode_or_continue!(row.get("event_id"), results, EVENTS_HISTORY);
+            let event_id = decode_or_continue!(Uuid::from_str(&event_id), results, EVENTS_HISTORY);
+            let event_ts = match timestamps::column(row, "event_ts", EVENTS_HISTORY) {
+                Ok(event_ts) => event_ts,
+                Err(error) => {
+                    results.push(Err(error));
+                    continue;
+                }
+            };
+            let kind: String = decode_or_continue!(row.get("kind"), results, EVENTS_HISTORY);
+            let payload: String = decode_or_continue!(row.get("payload"), results, EVENTS_HISTORY);
+            let payload =
+                decode_or_continue!(serde_json::from_str(&payload), results, EVENTS_HISTORY);
+            results.push(Ok(Event {
+                event_id,
+                event_ts,
+                kind,
+                payload,
+            }));
+        }
+        Ok(Iter::new(results.into_iter()))
+    }
+
+    fn persist(&self, event: &Event, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.insert", opts);
+            span.tag("sql", EVENTS_PERSIST_SQL);
+            span.auto_finish()
+        });
+        let payload = serde_json::to_string(&event.payload)
+            .with_context(|_| ErrorKind::PersistentWrite(EVENTS_PERSIST))?;
+        SQLITE_OPS_COUNT.with_label_values(&["INSERT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["INSERT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(EVENTS_PERSIST_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(EVENTS_PERSIST))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        statement
+            .execute(params![
+                event.event_id.to_string(),
+                timestamps::encode(&event.event_ts),
+                event.kind,
+                payload,
+            ])
+            .with_context(|_| ErrorKind::PersistentWrite(EVENTS_PERSIST))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        Ok(())
+    }
+
+    fn prune(&self, keep: u32, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.delete", opts);
+            span.tag("sql", EVENTS_PRUNE_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["DELETE"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["DELETE"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(EVENTS_PRUNE_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(EVENTS_PRUNE))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["DELETE"]).inc();
+                error
+            })?;
+        statement
+            .execute(params![keep])
+            .with_context(|_| ErrorKind::PersistentWrite(EVENTS_PRUNE))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["DELETE"]).inc();
+                error
+            })?;
+        Ok(())
+    }
+}
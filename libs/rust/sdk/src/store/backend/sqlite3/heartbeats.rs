@@ -0,0 +1,203 @@
+use std::str::FromStr;
+
+use chrono::TimeZone;
+use chrono::Utc;
+use opentracingrust::SpanContext;
+use opentracingrust::StartOptions;
+use rusqlite::params;
+use uuid::Uuid;
+
+use replicante_util_tracing::MaybeTracer;
+
+use crate::heartbeat::Heartbeat;
+use crate::metrics::SQLITE_OPS_COUNT;
+use crate::metrics::SQLITE_OPS_DURATION;
+use crate::metrics::SQLITE_OP_ERRORS_COUNT;
+use crate::store::interface::HeartbeatsInterface;
+use crate::store::Iter;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+const HEARTBEATS_HISTORY: &str = "heartbeats.history";
+const HEARTBEATS_HISTORY_SQL: &str = r#"
+SELECT
+    heartbeat_ts,
+    process_id,
+    started_ts,
+    version
+FROM heartbeats
+ORDER BY started_ts DESC, ROWID DESC
+LIMIT ?1;
+"#;
+const HEARTBEATS_PERSIST: &str = "heartbeats.persist";
+const HEARTBEATS_PERSIST_SQL: &str = r#"
+INSERT OR REPLACE INTO heartbeats (
+    heartbeat_ts,
+    process_id,
+    started_ts,
+    version
+)
+VALUES (?1, ?2, ?3, ?4);
+"#;
+const HEARTBEATS_PRUNE: &str = "heartbeats.prune";
+const HEARTBEATS_PRUNE_SQL: &str = r#"
+DELETE FROM heartbeats
+WHERE process_id NOT IN (
+    SELECT process_id
+    FROM heartbeats
+    ORDER BY started_ts DESC, ROWID DESC
+    LIMIT ?1
+);
+"#;
+
+/// Helper macro to avoid writing the same match every time.
+macro_rules! decode_or_continue {
+    ($decode:expr, $res:ident, $op:expr $(,)?) => {
+        match $decode {
+            Ok(r) => r,
+            Err(error) => {
+                let error = Err(error).with_context(|_| ErrorKind::PersistentRead($op));
+                $res.push(error);
+                continue;
+            }
+        }
+    };
+}
+
+pub struct Heartbeats<'a, 'b: 'a> {
+    inner: &'a rusqlite::Transaction<'b>,
+    tracer: MaybeTracer,
+}
+
+impl<'a, 'b: 'a> Heartbeats<'a, 'b> {
+    pub fn new(inner: &'a rusqlite::Transaction<'b>, tracer: MaybeTracer) -> Heartbeats<'a, 'b> {
+        Heartbeats { inner, tracer }
+    }
+}
+
+impl<'a, 'b: 'a> HeartbeatsInterface for Heartbeats<'a, 'b> {
+    fn history(&self, limit: u32, span: Option<SpanContext>) -> Result<Iter<Heartbeat>> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.select", opts);
+            span.tag("sql", HEARTBEATS_HISTORY_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["SELECT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["SELECT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(HEARTBEATS_HISTORY_SQL)
+            .with_context(|_| ErrorKind::PersistentRead(HEARTBEATS_HISTORY))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["SELECT"]).inc();
+                error
+            })?;
+        let mut results = Vec::new();
+        let mut rows = statement
+            .query(params![limit])
+            .with_context(|_| ErrorKind::PersistentRead(HEARTBEATS_HISTORY))?;
+        let mut maybe_row = rows
+            .next()
+            .with_context(|_| ErrorKind::PersistentRead(HEARTBEATS_HISTORY))?;
+        while let Some(row) = maybe_row {
+            let heartbeat_ts: i64 =
+                decode_or_continue!(row.get("heartbeat_ts"), results, HEARTBEATS_HISTORY);
+            let heartbeat_ts = Utc.timestamp(heartbeat_ts, 0);
+            let process_id: String =
+                decode_or_continue!(row.get("process_id"), results, HEARTBEATS_HISTORY);
+            let process_id =
+                decode_or_continue!(Uuid::from_str(&process_id), results, HEARTBEATS_HISTORY);
+            let started_ts: i64 =
+                decode_or_continue!(row.get("started_ts"), results, HEARTBEATS_HISTORY);
+            let started_ts = Utc.timestamp(started_ts, 0);
+            let version: String =
+                decode_or_continue!(row.get("version"), results, HEARTBEATS_HISTORY);
+            results.push(Ok(Heartbeat {
+                heartbeat_ts,
+                process_id,
+                started_ts,
+                version,
+            }));
+            maybe_row = rows
+                .next()
+                .with_context(|_| ErrorKind::PersistentRead(HEARTBEATS_HISTORY))?;
+        }
+        Ok(Iter::new(results.into_iter()))
+    }
+
+    fn persist(&self, heartbeat: &Heartbeat, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.insert", opts);
+            span.tag("sql", HEARTBEATS_PERSIST_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["INSERT"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["INSERT"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(HEARTBEATS_PERSIST_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(HEARTBEATS_PERSIST))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        statement
+            .execute(params![
+                heartbeat.heartbeat_ts.timestamp(),
+                heartbeat.process_id.to_string(),
+                heartbeat.started_ts.timestamp(),
+                heartbeat.version,
+            ])
+            .with_context(|_| ErrorKind::PersistentWrite(HEARTBEATS_PERSIST))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["INSERT"]).inc();
+                error
+            })?;
+        Ok(())
+    }
+
+    fn prune(&self, keep: u32, span: Option<SpanContext>) -> Result<()> {
+        let _span = self.tracer.with(|tracer| {
+            let mut opts = StartOptions::default();
+            if let Some(context) = span {
+                opts = opts.child_of(context);
+            }
+            let mut span = tracer.span_with_options("store.sqlite.delete", opts);
+            span.tag("sql", HEARTBEATS_PRUNE_SQL);
+            span.auto_finish()
+        });
+        SQLITE_OPS_COUNT.with_label_values(&["DELETE"]).inc();
+        let _timer = SQLITE_OPS_DURATION
+            .with_label_values(&["DELETE"])
+            .start_timer();
+        let mut statement = self
+            .inner
+            .prepare_cached(HEARTBEATS_PRUNE_SQL)
+            .with_context(|_| ErrorKind::PersistentWrite(HEARTBEATS_PRUNE))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["DELETE"]).inc();
+                error
+            })?;
+        statement
+            .execute(params![keep])
+            .with_context(|_| ErrorKind::PersistentWrite(HEARTBEATS_PRUNE))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["DELETE"]).inc();
+                error
+            })?;
+        Ok(())
+    }
+}
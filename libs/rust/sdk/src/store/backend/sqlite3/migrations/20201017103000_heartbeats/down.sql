@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS heartbeats_started_ts;
+DROP TABLE IF EXISTS heartbeats;
@@ -0,0 +1,8 @@
+-- Based on Heartbeat from sdk/src/heartbeat.rs
+CREATE TABLE IF NOT EXISTS heartbeats(
+  process_id TEXT PRIMARY KEY NOT NULL,
+  heartbeat_ts INTEGER NOT NULL,
+  started_ts INTEGER NOT NULL,
+  version TEXT NOT NULL
+);
+CREATE INDEX heartbeats_started_ts ON heartbeats(started_ts);
@@ -0,0 +1,32 @@
+-- Revert action timestamps to second precision epoch integers.
+-- The RFC3339 timestamps are saved aside so re-applying the migration
+-- restores their millisecond precision.
+CREATE TABLE IF NOT EXISTS actions_rfc3339_timestamps(
+  id TEXT PRIMARY KEY NOT NULL,
+  created_ts TEXT,
+  scheduled_ts TEXT,
+  finished_ts TEXT
+);
+INSERT OR REPLACE INTO actions_rfc3339_timestamps
+SELECT id, created_ts, scheduled_ts, finished_ts
+FROM actions
+WHERE typeof(created_ts) = 'text';
+
+CREATE TABLE IF NOT EXISTS actions_history_rfc3339_timestamps(
+  id INTEGER PRIMARY KEY NOT NULL,
+  time TEXT
+);
+INSERT OR REPLACE INTO actions_history_rfc3339_timestamps
+SELECT id, time
+FROM actions_history
+WHERE typeof(time) = 'text';
+
+UPDATE actions SET
+  created_ts = CAST(strftime('%s', created_ts) AS INTEGER),
+  scheduled_ts = CAST(strftime('%s', scheduled_ts) AS INTEGER),
+  finished_ts = CAST(strftime('%s', finished_ts) AS INTEGER)
+WHERE typeof(created_ts) = 'text';
+
+UPDATE actions_history SET
+  time = CAST(strftime('%s', time) AS INTEGER)
+WHERE typeof(time) = 'text';
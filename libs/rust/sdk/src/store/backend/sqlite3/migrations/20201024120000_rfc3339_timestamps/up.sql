@@ -0,0 +1,57 @@
+-- Store action timestamps as RFC3339 strings with millisecond precision.
+-- Column types are left unchanged: SQLite keeps TEXT values in INTEGER columns
+-- and fixed format UTC timestamps sort correctly as strings.
+--
+-- Timestamps saved aside when the migration was last reverted are restored
+-- as long as they were not changed by the older agent version in the meantime.
+CREATE TABLE IF NOT EXISTS actions_rfc3339_timestamps(
+  id TEXT PRIMARY KEY NOT NULL,
+  created_ts TEXT,
+  scheduled_ts TEXT,
+  finished_ts TEXT
+);
+CREATE TABLE IF NOT EXISTS actions_history_rfc3339_timestamps(
+  id INTEGER PRIMARY KEY NOT NULL,
+  time TEXT
+);
+
+UPDATE actions SET
+  created_ts = COALESCE(
+    (
+      SELECT saved.created_ts FROM actions_rfc3339_timestamps AS saved
+      WHERE saved.id = actions.id
+        AND CAST(strftime('%s', saved.created_ts) AS INTEGER) = actions.created_ts
+    ),
+    strftime('%Y-%m-%dT%H:%M:%fZ', created_ts, 'unixepoch')
+  ),
+  scheduled_ts = COALESCE(
+    (
+      SELECT saved.scheduled_ts FROM actions_rfc3339_timestamps AS saved
+      WHERE saved.id = actions.id
+        AND CAST(strftime('%s', saved.scheduled_ts) AS INTEGER) = actions.scheduled_ts
+    ),
+    strftime('%Y-%m-%dT%H:%M:%fZ', scheduled_ts, 'unixepoch')
+  ),
+  finished_ts = COALESCE(
+    (
+      SELECT saved.finished_ts FROM actions_rfc3339_timestamps AS saved
+      WHERE saved.id = actions.id
+        AND CAST(strftime('%s', saved.finished_ts) AS INTEGER) = actions.finished_ts
+    ),
+    strftime('%Y-%m-%dT%H:%M:%fZ', finished_ts, 'unixepoch')
+  )
+WHERE typeof(created_ts) = 'integer';
+
+UPDATE actions_history SET
+  time = COALESCE(
+    (
+      SELECT saved.time FROM actions_history_rfc3339_timestamps AS saved
+      WHERE saved.id = actions_history.id
+        AND CAST(strftime('%s', saved.time) AS INTEGER) = actions_history.time
+    ),
+    strftime('%Y-%m-%dT%H:%M:%fZ', time, 'unixepoch')
+  )
+WHERE typeof(time) = 'integer';
+
+DROP TABLE actions_rfc3339_timestamps;
+DROP TABLE actions_history_rfc3339_timestamps;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS actions_parent_action_id;
+ALTER TABLE actions DROP COLUMN parent_action_id;
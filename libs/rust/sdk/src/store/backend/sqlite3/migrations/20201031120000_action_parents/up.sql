@@ -0,0 +1,3 @@
+-- Link actions to the action that scheduled them, if any.
+ALTER TABLE actions ADD COLUMN parent_action_id TEXT DEFAULT NULL;
+CREATE INDEX actions_parent_action_id ON actions(parent_action_id);
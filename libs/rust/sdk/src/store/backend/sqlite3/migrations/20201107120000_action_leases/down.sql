@@ -0,0 +1,2 @@
+ALTER TABLE actions DROP COLUMN lease_expires_ts;
+ALTER TABLE actions DROP COLUMN lease_owner;
@@ -0,0 +1,3 @@
+-- Track the agent process executing each action so others don't execute it too.
+ALTER TABLE actions ADD COLUMN lease_owner TEXT DEFAULT NULL;
+ALTER TABLE actions ADD COLUMN lease_expires_ts TEXT DEFAULT NULL;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS events_event_ts;
+DROP TABLE IF EXISTS events;
@@ -0,0 +1,8 @@
+-- Based on Event from sdk/src/events.rs
+CREATE TABLE IF NOT EXISTS events(
+  event_id TEXT PRIMARY KEY NOT NULL,
+  event_ts TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  payload TEXT NOT NULL
+);
+CREATE INDEX events_event_ts ON events(event_ts);
@@ -0,0 +1,6 @@
+-- Based on APITreeOverride from sdk/src/store/mod.rs
+CREATE TABLE IF NOT EXISTS api_tree_overrides(
+  tree TEXT PRIMARY KEY NOT NULL,
+  enabled INTEGER NOT NULL,
+  updated_ts TEXT NOT NULL
+);
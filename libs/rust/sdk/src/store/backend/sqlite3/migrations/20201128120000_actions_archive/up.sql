@@ -0,0 +1,11 @@
+-- Based on ArchivedAction from sdk/src/store/mod.rs
+CREATE TABLE IF NOT EXISTS actions_archive(
+  -- INTEGER PRIMARY KEY is an alias for ROWID, used to paginate the archive.
+  archive_id INTEGER PRIMARY KEY NOT NULL,
+  id TEXT NOT NULL UNIQUE,
+  kind TEXT NOT NULL,
+  archived_ts TEXT NOT NULL,
+  finished_ts TEXT,
+  payload BLOB NOT NULL
+);
+CREATE INDEX actions_archive_finished_ts ON actions_archive(finished_ts);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS actions_logs_action_id;
+DROP TABLE IF EXISTS actions_logs;
@@ -0,0 +1,10 @@
+-- Based on ActionLogLine from sdk/src/actions/definition.rs
+CREATE TABLE IF NOT EXISTS actions_logs(
+  -- INTEGER PRIMARY KEY is an alias for ROWID, used to order lines of the same action.
+  id INTEGER PRIMARY KEY NOT NULL,
+  action_id TEXT NOT NULL,
+  time TEXT NOT NULL,
+  line TEXT NOT NULL,
+  FOREIGN KEY(action_id) REFERENCES actions(id) ON UPDATE RESTRICT ON DELETE CASCADE
+);
+CREATE INDEX actions_logs_action_id ON actions_logs(action_id);
@@ -0,0 +1,6 @@
+-- Values the agent must remember across restarts, stored as JSON documents.
+CREATE TABLE IF NOT EXISTS agent_state(
+  key TEXT PRIMARY KEY NOT NULL,
+  value TEXT NOT NULL,
+  updated_ts TEXT NOT NULL
+);
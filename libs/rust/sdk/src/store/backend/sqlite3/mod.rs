@@ -1,31 +1,73 @@
-use failure::ResultExt;
-use failure::SyncFailure;
+use std::path::Path;
+
 use migrant_lib::Config;
+use migrant_lib::Direction;
+use migrant_lib::Migratable;
 use migrant_lib::Migrator;
 use migrant_lib::Settings;
+use rusqlite::backup::Progress;
+use rusqlite::DatabaseName;
 use slog::debug;
 use slog::info;
 use slog::Logger;
 
 use replicante_util_tracing::MaybeTracer;
 
+use crate::metrics::OpenTransaction;
 use crate::metrics::SQLITE_CONNECTION_ERRORS;
+use crate::metrics::SQLITE_OPEN_TRANSACTIONS;
 use crate::metrics::SQLITE_OPS_COUNT;
 use crate::metrics::SQLITE_OPS_DURATION;
 use crate::metrics::SQLITE_OP_ERRORS_COUNT;
+use crate::metrics::SQLITE_TRANSACTION_DURATION;
+use crate::store::interface::APITreeOverridesImpl;
 use crate::store::interface::ActionImpl;
 use crate::store::interface::ActionsImpl;
+use crate::store::interface::AgentStateImpl;
 use crate::store::interface::ConnectionImpl;
 use crate::store::interface::ConnectionInterface;
+use crate::store::interface::EventsImpl;
+use crate::store::interface::HeartbeatsImpl;
 use crate::store::interface::StoreInterface;
 use crate::store::interface::TransactionImpl;
 use crate::store::interface::TransactionInterface;
-use crate::Error;
+use crate::store::StoreSchema;
+use crate::sync_compat;
 use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
 
 mod action;
 mod actions;
+mod agent_state;
+mod api_tree_overrides;
+mod events;
+mod heartbeats;
+mod timestamps;
+
+/// Schema migrations embedded in the agent, in the order they are applied.
+fn migrations() -> Vec<Box<dyn Migratable>> {
+    macro_rules! make_migration {
+        ($tag:expr) => {
+            migrant_lib::EmbeddedMigration::with_tag($tag)
+                .up(include_str!(concat!("./migrations/", $tag, "/up.sql")))
+                .down(include_str!(concat!("./migrations/", $tag, "/down.sql")))
+                .boxed()
+        };
+    }
+    vec![
+        make_migration!("20190728220141_initialise"),
+        make_migration!("20201017103000_heartbeats"),
+        make_migration!("20201024120000_rfc3339_timestamps"),
+        make_migration!("20201031120000_action_parents"),
+        make_migration!("20201107120000_action_leases"),
+        make_migration!("20201114120000_events"),
+        make_migration!("20201121120000_api_tree_overrides"),
+        make_migration!("20201128120000_actions_archive"),
+        make_migration!("20201205120000_actions_logs"),
+        make_migration!("20201212120000_agent_state"),
+    ]
+}
 
 struct Connection {
     connection: rusqlite::Connection,
@@ -60,8 +102,13 @@ impl ConnectionInterface for Connection {
             })?;
         timer.observe_duration();
         let inner = Some(inner);
+        let open = SQLITE_OPEN_TRANSACTIONS.open();
         let tracer = self.tracer.clone();
-        Ok(TransactionImpl::new(Transaction { inner, tracer }))
+        Ok(TransactionImpl::new(Transaction {
+            inner,
+            open,
+            tracer,
+        }))
     }
 }
 
@@ -80,75 +127,189 @@ impl Store {
             tracer,
         })
     }
-}
 
-impl StoreInterface for Store {
-    fn connection(&self) -> Result<ConnectionImpl> {
-        let tracer = self.tracer.clone();
-        let connection = Connection::new(&self.path, tracer).map_err(|error| {
-            SQLITE_CONNECTION_ERRORS.inc();
-            error
-        })?;
-        Ok(ConnectionImpl::new(connection))
+    /// Tags of the migrations applied to the store.
+    fn applied(&self, config: &Config) -> Result<Vec<String>> {
+        let applied = config
+            .get_applied()
+            .map_err(sync_compat)
+            .with_context(|_| ErrorKind::PersistentMigrate)?;
+        Ok(applied)
     }
 
-    fn migrate(&self) -> Result<()> {
-        debug!(self.logger, "Initialising migrations engine");
+    /// Configure the migrations engine with the embedded migrations.
+    fn migrations_config(&self) -> Result<Config> {
         let path = std::env::current_dir()
             .with_context(|_| ErrorKind::PersistentOpen(self.path.clone()))?;
         let path = path.join(&self.path);
         let settings = Settings::configure_sqlite()
             .database_path(path)
-            .map_err(SyncFailure::new)
+            .map_err(sync_compat)
             .with_context(|_| ErrorKind::PersistentOpen(self.path.clone()))?
             .build()
-            .map_err(SyncFailure::new)
+            .map_err(sync_compat)
             .with_context(|_| ErrorKind::PersistentMigrate)?;
         let mut config = Config::with_settings(&settings);
         config
             .setup()
-            .map_err(SyncFailure::new)
+            .map_err(sync_compat)
             .with_context(|_| ErrorKind::PersistentMigrate)?;
         config.use_cli_compatible_tags(true);
-
-        // Register migrations.
-        macro_rules! make_migration {
-            ($tag:expr) => {
-                migrant_lib::EmbeddedMigration::with_tag($tag)
-                    .up(include_str!(concat!("./migrations/", $tag, "/up.sql")))
-                    .down(include_str!(concat!("./migrations/", $tag, "/down.sql")))
-                    .boxed()
-            };
-        }
         config
-            .use_migrations(&[make_migration!("20190728220141_initialise")])
-            .map_err(SyncFailure::new)
+            .use_migrations(&migrations())
+            .map_err(sync_compat)
             .with_context(|_| ErrorKind::PersistentMigrate)?;
-
-        info!(self.logger, "Running DB migrations as needed");
         let config = config
             .reload()
-            .map_err(SyncFailure::new)
+            .map_err(sync_compat)
             .with_context(|_| ErrorKind::PersistentMigrate)?;
+        Ok(config)
+    }
+}
+
+impl StoreInterface for Store {
+    fn backup(&self, path: &Path) -> Result<()> {
+        let connection = Connection::new(&self.path, self.tracer.clone())?;
+        SQLITE_OPS_COUNT.with_label_values(&["BACKUP"]).inc();
+        let timer = SQLITE_OPS_DURATION
+            .with_label_values(&["BACKUP"])
+            .start_timer();
+        connection
+            .connection
+            .backup(DatabaseName::Main, path, None)
+            .with_context(|_| ErrorKind::PersistentBackup(path.display().to_string()))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["BACKUP"]).inc();
+                error
+            })?;
+        timer.observe_duration();
+        info!(self.logger, "Agent DB backed up"; "path" => %path.display());
+        Ok(())
+    }
+
+    fn connection(&self) -> Result<ConnectionImpl> {
+        let tracer = self.tracer.clone();
+        let connection = Connection::new(&self.path, tracer).map_err(|error| {
+            SQLITE_CONNECTION_ERRORS.inc();
+            error
+        })?;
+        Ok(ConnectionImpl::new(connection))
+    }
+
+    fn migrate(&self) -> Result<()> {
+        debug!(self.logger, "Initialising migrations engine");
+        let config = self.migrations_config()?;
+        let known: Vec<String> = migrations().iter().map(|m| m.tag()).collect();
+        let unknown: Vec<String> = self
+            .applied(&config)?
+            .into_iter()
+            .filter(|tag| !known.contains(tag))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(ErrorKind::PersistentSchemaUnknown(unknown.join(", ")).into());
+        }
+        info!(self.logger, "Running DB migrations as needed");
         Migrator::with_config(&config)
             .all(true)
             .show_output(true)
             .swallow_completion(true)
             .apply()
-            .map_err(SyncFailure::new)
+            .map_err(sync_compat)
             .with_context(|_| ErrorKind::PersistentMigrate)?;
         info!(self.logger, "Agent DB ready");
         Ok(())
     }
+
+    fn migrate_down(&self, tag: &str) -> Result<()> {
+        let known: Vec<String> = migrations().iter().map(|m| m.tag()).collect();
+        let config = self.migrations_config()?;
+        if !self.applied(&config)?.iter().any(|applied| applied == tag) {
+            let error = format!("migration {} is not applied", tag);
+            return Err(ErrorKind::PersistentMigrateDown(error).into());
+        }
+
+        // Revert one migration at a time so each is checked before it is reverted.
+        loop {
+            let config = self.migrations_config()?;
+            let applied = self.applied(&config)?;
+            let last = match applied.last() {
+                Some(last) if last != tag => last,
+                _ => break,
+            };
+            if !known.contains(last) {
+                let error = format!("migration {} is not known to this agent version", last);
+                return Err(ErrorKind::PersistentMigrateDown(error).into());
+            }
+            info!(self.logger, "Reverting DB migration"; "tag" => last);
+            Migrator::with_config(&config)
+                .direction(Direction::Down)
+                .all(false)
+                .show_output(true)
+                .swallow_completion(true)
+                .apply()
+                .map_err(sync_compat)
+                .with_context(|_| ErrorKind::PersistentMigrate)?;
+        }
+        info!(self.logger, "Agent DB reverted"; "tag" => tag);
+        Ok(())
+    }
+
+    fn restore(&self, path: &Path) -> Result<()> {
+        // SQLite would restore an empty DB from a missing file.
+        if !path.is_file() {
+            let error = ErrorKind::PersistentRestore(path.display().to_string());
+            return Err(error.into());
+        }
+        let mut connection = Connection::new(&self.path, self.tracer.clone())?;
+        SQLITE_OPS_COUNT.with_label_values(&["RESTORE"]).inc();
+        let timer = SQLITE_OPS_DURATION
+            .with_label_values(&["RESTORE"])
+            .start_timer();
+        connection
+            .connection
+            .restore(DatabaseName::Main, path, None::<fn(Progress)>)
+            .with_context(|_| ErrorKind::PersistentRestore(path.display().to_string()))
+            .map_err(|error| {
+                SQLITE_OP_ERRORS_COUNT.with_label_values(&["RESTORE"]).inc();
+                error
+            })?;
+        timer.observe_duration();
+        info!(self.logger, "Agent DB restored"; "path" => %path.display());
+        Ok(())
+    }
+
+    fn schema(&self) -> Result<StoreSchema> {
+        let config = self.migrations_config()?;
+        let applied = self.applied(&config)?;
+        let pending = migrations()
+            .iter()
+            .map(|migration| migration.tag())
+            .filter(|tag| !applied.contains(tag))
+            .collect();
+        let version = applied.last().cloned();
+        Ok(StoreSchema {
+            applied,
+            pending,
+            version,
+        })
+    }
 }
 
 /// Wrap all operations in a SQLite3 transaction.
 struct Transaction<'a> {
     inner: Option<rusqlite::Transaction<'a>>,
+    open: OpenTransaction,
     tracer: MaybeTracer,
 }
 
 impl<'a> Transaction<'a> {
+    /// Record how long the transaction was open for.
+    fn observe_duration(&self, outcome: &str) {
+        SQLITE_TRANSACTION_DURATION
+            .with_label_values(&[outcome])
+            .observe(self.open.elapsed().as_secs_f64());
+    }
+
     fn tx(&self) -> &rusqlite::Transaction<'a> {
         self.inner
             .as_ref()
@@ -169,11 +330,24 @@ impl<'a> TransactionInterface for Transaction<'a> {
         ActionsImpl::new(inner)
     }
 
+    fn agent_state(&mut self) -> AgentStateImpl {
+        let inner = self.tx();
+        let inner = self::agent_state::AgentState::new(inner, self.tracer.clone());
+        AgentStateImpl::new(inner)
+    }
+
+    fn api_tree_overrides(&mut self) -> APITreeOverridesImpl {
+        let inner = self.tx();
+        let inner = self::api_tree_overrides::APITreeOverrides::new(inner, self.tracer.clone());
+        APITreeOverridesImpl::new(inner)
+    }
+
     fn commit(&mut self) -> Result<()> {
         SQLITE_OPS_COUNT.with_label_values(&["COMMIT"]).inc();
         let _timer = SQLITE_OPS_DURATION
             .with_label_values(&["COMMIT"])
             .start_timer();
+        self.observe_duration("commit");
         self.inner
             .take()
             .expect("cannot use committed/rolled back transaction")
@@ -181,15 +355,28 @@ impl<'a> TransactionInterface for Transaction<'a> {
             .with_context(|_| ErrorKind::PersistentCommit)
             .map_err(|error| {
                 SQLITE_OP_ERRORS_COUNT.with_label_values(&["COMMIT"]).inc();
-                Error::from(error)
+                error
             })
     }
 
+    fn events(&mut self) -> EventsImpl {
+        let inner = self.tx();
+        let inner = self::events::Events::new(inner, self.tracer.clone());
+        EventsImpl::new(inner)
+    }
+
+    fn heartbeats(&mut self) -> HeartbeatsImpl {
+        let inner = self.tx();
+        let inner = self::heartbeats::Heartbeats::new(inner, self.tracer.clone());
+        HeartbeatsImpl::new(inner)
+    }
+
     fn rollback(&mut self) -> Result<()> {
         SQLITE_OPS_COUNT.with_label_values(&["ROLLBACK"]).inc();
         let _timer = SQLITE_OPS_DURATION
             .with_label_values(&["ROLLBACK"])
             .start_timer();
+        self.observe_duration("rollback");
         self.inner
             .take()
             .expect("cannot use committed/rolled back transaction")
@@ -199,7 +386,16 @@ impl<'a> TransactionInterface for Transaction<'a> {
                 SQLITE_OP_ERRORS_COUNT
                     .with_label_values(&["ROLLBACK"])
                     .inc();
-                Error::from(error)
+                error
             })
     }
 }
+
+impl<'a> Drop for Transaction<'a> {
+    fn drop(&mut self) {
+        // Transactions dropped while still open are rolled back by rusqlite.
+        if self.inner.is_some() {
+            self.observe_duration("dropped");
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Encoding of timestamps stored in SQLite.
+//!
+//! Timestamps are stored as RFC3339 strings in UTC with millisecond precision
+//! so they sort correctly as strings and keep the order of sub-second events.
+//! Rows written before the switch stored seconds since the epoch as integers
+//! and are still decoded for backwards compatibility.
+use chrono::DateTime;
+use chrono::SecondsFormat;
+use chrono::TimeZone;
+use chrono::Utc;
+use rusqlite::types::Value;
+use rusqlite::Row;
+
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+/// Encode a timestamp for storage.
+pub fn encode(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Decode a stored timestamp from either the RFC3339 or legacy epoch format.
+pub fn decode(value: Value, op: &'static str) -> Result<DateTime<Utc>> {
+    match value {
+        Value::Integer(seconds) => Ok(Utc.timestamp(seconds, 0)),
+        Value::Text(timestamp) => {
+            let timestamp = DateTime::parse_from_rfc3339(&timestamp)
+                .with_context(|_| ErrorKind::PersistentRead(op))?;
+            Ok(timestamp.with_timezone(&Utc))
+        }
+        _ => Err(ErrorKind::PersistentRead(op).into()),
+    }
+}
+
+/// Decode a required timestamp column.
+pub fn column(row: &Row, name: &str, op: &'static str) -> Result<DateTime<Utc>> {
+    let value: Value = row
+        .get(name)
+        .with_context(|_| ErrorKind::PersistentRead(op))?;
+    decode(value, op)
+}
+
+/// Decode an optional timestamp column.
+pub fn optional_column(row: &Row, name: &str, op: &'static str) -> Result<Option<DateTime<Utc>>> {
+    let value: Value = row
+        .get(name)
+        .with_context(|_| ErrorKind::PersistentRead(op))?;
+    match value {
+        Value::Null => Ok(None),
+        value => decode(value, op).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono::Utc;
+    use rusqlite::types::Value;
+
+    use super::decode;
+    use super::encode;
+
+    #[test]
+    fn decode_legacy_epoch() {
+        let timestamp = decode(Value::Integer(1600000000), "test").unwrap();
+        assert_eq!(timestamp, Utc.timestamp(1600000000, 0));
+    }
+
+    #[test]
+    fn round_trip_millis() {
+        let timestamp = Utc.timestamp_millis(1600000000123);
+        let encoded = encode(&timestamp);
+        assert_eq!(encoded, "2020-09-13T12:26:40.123Z");
+        assert_eq!(decode(Value::Text(encoded), "test").unwrap(), timestamp);
+    }
+
+    #[test]
+    fn sorts_as_strings() {
+        let early = encode(&Utc.timestamp_millis(1600000000999));
+        let late = encode(&Utc.timestamp_millis(1600000001000));
+        assert!(early < late);
+    }
+}
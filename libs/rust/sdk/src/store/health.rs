@@ -0,0 +1,124 @@
+use std::sync::RwLock;
+
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Serialize;
+use slog::info;
+use slog::warn;
+use slog::Logger;
+
+use crate::metrics::STORE_DEGRADED;
+use crate::Error;
+use crate::ErrorKind;
+
+/// Details about the store failing to persist writes.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct StoreDegraded {
+    /// Message of the latest write error.
+    pub error: String,
+
+    /// Time the first write error was detected.
+    pub since: DateTime<Utc>,
+}
+
+/// Track the ability of the store to persist writes.
+///
+/// The store is degraded as soon as a write or commit fails (for example because
+/// the disk is full) and recovers once a transaction with writes commits again.
+/// While degraded the agent keeps serving read-only requests but rejects new actions.
+#[derive(Debug, Default)]
+pub struct StoreHealth {
+    degraded: RwLock<Option<StoreDegraded>>,
+}
+
+impl StoreHealth {
+    /// Details about the store write failures, if the store is degraded.
+    pub fn degraded(&self) -> Option<StoreDegraded> {
+        self.degraded
+            .read()
+            .expect("StoreHealth lock poisoned")
+            .clone()
+    }
+
+    /// Check if the store is failing to persist writes.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+            .read()
+            .expect("StoreHealth lock poisoned")
+            .is_some()
+    }
+
+    /// Record the outcome of a transaction that failed.
+    ///
+    /// Only errors caused by the store failing to persist data degrade the store.
+    pub(crate) fn transaction_failed(&self, error: &Error, logger: &Logger) {
+        match error.kind() {
+            ErrorKind::PersistentCommit | ErrorKind::PersistentWrite(_) => (),
+            _ => return,
+        };
+        let message = error.to_string();
+        let mut degraded = self.degraded.write().expect("StoreHealth lock poisoned");
+        match degraded.as_mut() {
+            Some(degraded) => degraded.error = message,
+            None => {
+                warn!(
+                    logger,
+                    "Store writes are failing, agent is now in degraded mode";
+                    "error" => &message,
+                );
+                STORE_DEGRADED.set(1.0);
+                *degraded = Some(StoreDegraded {
+                    error: message,
+                    since: Utc::now(),
+                });
+            }
+        }
+    }
+
+    /// Record a transaction with writes was committed successfully.
+    pub(crate) fn writes_committed(&self, logger: &Logger) {
+        if !self.is_degraded() {
+            return;
+        }
+        let mut degraded = self.degraded.write().expect("StoreHealth lock poisoned");
+        if let Some(previous) = degraded.take() {
+            info!(
+                logger,
+                "Store writes are succeeding again, agent is leaving degraded mode";
+                "since" => %previous.since,
+            );
+            STORE_DEGRADED.set(0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::Logger;
+
+    use super::StoreHealth;
+    use crate::Error;
+    use crate::ErrorKind;
+
+    #[test]
+    fn degraded_by_write_errors_only() {
+        let logger = Logger::root(slog::Discard, slog::o!());
+        let health = StoreHealth::default();
+        health.transaction_failed(&Error::from(ErrorKind::PersistentRead("test")), &logger);
+        assert!(!health.is_degraded());
+        health.transaction_failed(&Error::from(ErrorKind::PersistentWrite("test")), &logger);
+        assert!(health.is_degraded());
+    }
+
+    #[test]
+    fn recover_after_commit() {
+        let logger = Logger::root(slog::Discard, slog::o!());
+        let health = StoreHealth::default();
+        health.transaction_failed(&Error::from(ErrorKind::PersistentCommit), &logger);
+        let degraded = health.degraded().unwrap();
+        health.transaction_failed(&Error::from(ErrorKind::PersistentWrite("test")), &logger);
+        assert_eq!(health.degraded().unwrap().since, degraded.since);
+        health.writes_committed(&logger);
+        assert!(health.degraded().is_none());
+    }
+}
@@ -1,15 +1,33 @@
+//! Interfaces to implement custom store backends.
+//!
+//! A backend implements `StoreInterface` and the interfaces returned by it
+//! (`ConnectionInterface`, `TransactionInterface`, ...) and wraps each
+//! implementation in the matching `*Impl` struct for dynamic dispatch.
+//! See the `crate::store::Store` wrapper for descriptions of the expected behaviours.
 use std::ops::Deref;
 use std::ops::DerefMut;
+use std::path::Path;
 use std::sync::Arc;
 
+use chrono::DateTime;
+use chrono::Utc;
 use opentracingrust::SpanContext;
 use serde_json::Value as Json;
 
+use super::APITreeOverride;
+use super::ArchivedAction;
+use super::ArchivedActionItem;
+use super::FinishedAction;
 use super::Iter;
+use super::Page;
+use super::StoreSchema;
 use crate::actions::ActionHistoryItem;
 use crate::actions::ActionListItem;
+use crate::actions::ActionLogLine;
 use crate::actions::ActionRecord;
 use crate::actions::ActionState;
+use crate::events::Event;
+use crate::heartbeat::Heartbeat;
 use crate::Result;
 
 // Macro definition to generate an interface trait with a wrapping wrapper
@@ -123,11 +141,23 @@ arc_interface! {
     trait StoreInterface,
 
     interface {
+        /// Write a consistent snapshot of the store to the given path.
+        fn backup(&self, path: &Path) -> Result<()>;
+
         /// Request a new connection to the store.
         fn connection(&self) -> Result<ConnectionImpl>;
 
         /// Perform database initialisation and applies migrations.
         fn migrate(&self) -> Result<()>;
+
+        /// Revert migrations applied after the given migration tag.
+        fn migrate_down(&self, tag: &str) -> Result<()>;
+
+        /// Replace the content of the store with a snapshot written by `backup`.
+        fn restore(&self, path: &Path) -> Result<()>;
+
+        /// Report the schema migrations applied to the store and those still pending.
+        fn schema(&self) -> Result<StoreSchema>;
     }
 }
 
@@ -154,28 +184,73 @@ box_interface! {
     trait ActionInterface,
 
     interface {
+        /// Iterate over actions scheduled by the given action, oldest action first.
+        fn children(&self, id: &str, span: Option<SpanContext>) -> Result<Iter<ActionListItem>>;
+
         /// Fetch an action record by ID.
         fn get(&self, id: &str, span: Option<SpanContext>) -> Result<Option<ActionRecord>>;
 
-        /// Fetch an action record's transition history.
+        /// Fetch a page of an action record's transition history, newest transition first.
+        ///
+        /// The `after` token is opaque to callers and specific to each backend.
         fn history(
             &self,
             id: &str,
+            limit: u32,
+            after: Option<&str>,
             span: Option<SpanContext>,
-        ) -> Result<Iter<ActionHistoryItem>>;
+        ) -> Result<Page<ActionHistoryItem>>;
 
         /// Persist a NEW action to the store.
         fn insert(&self, action: ActionRecord, span: Option<SpanContext>) -> Result<()>;
 
-        /// Fetch the next RUNNING or NEW action.
-        fn next(&self, span: Option<SpanContext>) -> Result<Option<ActionRecord>>;
+        /// Append a line to the action progress logs, keeping only the newest `keep` lines.
+        fn log(
+            &self,
+            action: &ActionRecord,
+            line: &str,
+            keep: u32,
+            span: Option<SpanContext>,
+        ) -> Result<()>;
+
+        /// Iterate over the action progress logs, oldest line first.
+        fn logs(&self, id: &str, span: Option<SpanContext>) -> Result<Iter<ActionLogLine>>;
+
+        /// Acquire or renew the lease on an action for the given owner until `expires`.
+        ///
+        /// Returns `false` if another owner holds an unexpired lease on the action.
+        fn lease(
+            &self,
+            action: &ActionRecord,
+            owner: &str,
+            expires: DateTime<Utc>,
+            span: Option<SpanContext>,
+        ) -> Result<bool>;
+
+        /// Fetch the next RUNNING or NEW action not leased by other owners.
+        fn next(&self, owner: &str, span: Option<SpanContext>) -> Result<Option<ActionRecord>>;
+
+        /// Fetch the action that finished first after the `after` cursor, if any.
+        ///
+        /// Without a cursor the first action that finished after `since` is returned.
+        /// The `after` cursor is opaque to callers and specific to each backend.
+        fn next_finished(
+            &self,
+            since: DateTime<Utc>,
+            after: Option<&str>,
+            span: Option<SpanContext>,
+        ) -> Result<Option<FinishedAction>>;
 
         /// Transition the action to a new state.
+        ///
+        /// The transition fails with `ErrorKind::ActionLeaseLost` if the action
+        /// is leased by a different owner.
         fn transition(
             &self,
             action: &ActionRecord,
             transition_to: ActionState,
             payload: Option<Json>,
+            owner: &str,
             span: Option<SpanContext>,
         ) -> Result<()>;
     }
@@ -191,17 +266,120 @@ box_interface! {
     trait ActionsInterface,
 
     interface {
+        /// Add a finished action to the archive.
+        fn archive(&self, action: &ArchivedAction, span: Option<SpanContext>) -> Result<()>;
+
+        /// Fetch an archived action by ID.
+        fn archived(&self, id: &str, span: Option<SpanContext>) -> Result<Option<ArchivedAction>>;
+
+        /// Fetch a page of archived actions, most recently archived first.
+        fn archived_list(
+            &self,
+            limit: u32,
+            after: Option<&str>,
+            span: Option<SpanContext>,
+        ) -> Result<Page<ArchivedActionItem>>;
+
         /// Iterate over the most recent 100 finished actions, newest action first.
         fn finished(&self, span: Option<SpanContext>) -> Result<Iter<ActionListItem>>;
 
         /// Iterate over running and pending actions, oldest action first.
         fn queue(&self, span: Option<SpanContext>) -> Result<Iter<ActionListItem>>;
 
+        /// Iterate over the finished actions the next `prune` call with the same arguments deletes.
+        fn prunable(
+            &self,
+            keep: u32,
+            limit: u32,
+            span: Option<SpanContext>,
+        ) -> Result<Iter<ActionListItem>>;
+
         /// Prune finished historic actions to prevent endless DB growth.
         fn prune(&self, keep: u32, limit: u32, span: Option<SpanContext>) -> Result<()>;
     }
 }
 
+box_interface! {
+    lifetime 'a,
+
+    /// Dynamic dispatch all operations to a backend-specific implementation.
+    struct EventsImpl,
+
+    /// Interface to record and fetch agent events.
+    trait EventsInterface,
+
+    interface {
+        /// Iterate over the most recent events, newest event first.
+        fn history(&self, limit: u32, span: Option<SpanContext>) -> Result<Iter<Event>>;
+
+        /// Persist a new event.
+        fn persist(&self, event: &Event, span: Option<SpanContext>) -> Result<()>;
+
+        /// Prune old events to prevent endless DB growth.
+        fn prune(&self, keep: u32, span: Option<SpanContext>) -> Result<()>;
+    }
+}
+
+box_interface! {
+    lifetime 'a,
+
+    /// Dynamic dispatch all operations to a backend-specific implementation.
+    struct AgentStateImpl,
+
+    /// Interface to persist values the agent must remember across restarts.
+    trait AgentStateInterface,
+
+    interface {
+        /// Fetch the value stored under a key, if any.
+        fn get(&self, key: &str, span: Option<SpanContext>) -> Result<Option<Json>>;
+
+        /// Store a value under a key, replacing any previous value.
+        fn set(&self, key: &str, value: &Json, span: Option<SpanContext>) -> Result<()>;
+    }
+}
+
+box_interface! {
+    lifetime 'a,
+
+    /// Dynamic dispatch all operations to a backend-specific implementation.
+    struct APITreeOverridesImpl,
+
+    /// Interface to persist API trees enabled or disabled at runtime.
+    trait APITreeOverridesInterface,
+
+    interface {
+        /// Remove all overrides, reverting API trees to their configured state.
+        fn clear(&self, span: Option<SpanContext>) -> Result<()>;
+
+        /// Iterate over all overrides, ordered by tree name.
+        fn list(&self, span: Option<SpanContext>) -> Result<Iter<APITreeOverride>>;
+
+        /// Persist an override, replacing any previous override of the same tree.
+        fn set(&self, tree: &APITreeOverride, span: Option<SpanContext>) -> Result<()>;
+    }
+}
+
+box_interface! {
+    lifetime 'a,
+
+    /// Dynamic dispatch all operations to a backend-specific implementation.
+    struct HeartbeatsImpl,
+
+    /// Interface to record and fetch agent heartbeats.
+    trait HeartbeatsInterface,
+
+    interface {
+        /// Iterate over heartbeats of the most recent agent processes, newest process first.
+        fn history(&self, limit: u32, span: Option<SpanContext>) -> Result<Iter<Heartbeat>>;
+
+        /// Persist the latest heartbeat for an agent process.
+        fn persist(&self, heartbeat: &Heartbeat, span: Option<SpanContext>) -> Result<()>;
+
+        /// Prune heartbeats of old agent processes to prevent endless DB growth.
+        fn prune(&self, keep: u32, span: Option<SpanContext>) -> Result<()>;
+    }
+}
+
 box_interface! {
     lifetime 'a,
 
@@ -218,9 +396,21 @@ box_interface! {
         /// Access the actions query interface.
         fn actions(&mut self) -> ActionsImpl;
 
+        /// Access the agent state query interface.
+        fn agent_state(&mut self) -> AgentStateImpl;
+
+        /// Access the API tree overrides query interface.
+        fn api_tree_overrides(&mut self) -> APITreeOverridesImpl;
+
         /// Commit and invalidate the transaction.
         fn commit(&mut self) -> Result<()>;
 
+        /// Access the events query interface.
+        fn events(&mut self) -> EventsImpl;
+
+        /// Access the heartbeats query interface.
+        fn heartbeats(&mut self) -> HeartbeatsImpl;
+
         /// Rollback and invalidate the transaction.
         fn rollback(&mut self) -> Result<()>;
     }
@@ -1,31 +1,111 @@
+//! Agent persistent storage.
+//!
+//! The SDK stores data in SQLite by default but agents can provide their own
+//! backend by implementing the traits in the `interface` module and
+//! registering a factory with `register_backend`.
+use std::cell::Cell;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::Utc;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use opentracingrust::SpanContext;
+use opentracingrust::Tracer;
+use serde::Deserialize;
+use serde::Serialize;
 use serde_json::Value as Json;
 use slog::Logger;
+use tokio::task::spawn_blocking;
+use uuid::Uuid;
 
 use replicante_util_failure::capture_fail;
 use replicante_util_failure::failure_info;
 
 mod backend;
-mod interface;
+mod health;
+pub mod interface;
 
 pub use self::backend::backend_factory;
+pub use self::backend::register_backend;
+pub use self::backend::BackendFactory;
+pub use self::health::StoreDegraded;
+pub use self::health::StoreHealth;
 
 use self::interface::StoreImpl;
 use self::interface::TransactionImpl;
 use crate::actions::ensure_transition_allowed;
 use crate::actions::ActionHistoryItem;
 use crate::actions::ActionListItem;
+use crate::actions::ActionLogLine;
 use crate::actions::ActionRecord;
 use crate::actions::ActionRecordView;
 use crate::actions::ActionState;
+use crate::actions::ACTIONS;
+use crate::events::Event;
+use crate::heartbeat::Heartbeat;
+use crate::heartbeat::PROCESS_ID;
+use crate::metrics::STORE_MIGRATIONS_APPLIED;
+use crate::metrics::STORE_MIGRATIONS_PENDING;
+use crate::Error;
+use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
+
+/// Truncate an action log line to `MAX_ACTION_LOG_LINE_LEN` bytes on a character boundary.
+fn truncate_log_line(line: &str) -> &str {
+    if line.len() <= MAX_ACTION_LOG_LINE_LEN {
+        return line;
+    }
+    let mut end = MAX_ACTION_LOG_LINE_LEN;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+/// Owner of action leases acquired by this agent process.
+fn lease_owner() -> String {
+    PROCESS_ID.to_string()
+}
+
+/// Owner of transitions requested by external orchestrators.
+///
+/// Orchestrators never hold action leases so their transitions only apply
+/// to actions not leased by any agent process.
+const EXTERNAL_LEASE_OWNER: &str = "external";
+
+/// Maximum number of ancestors of actions that schedule follow-up actions.
+pub const MAX_FOLLOW_UP_DEPTH: u32 = 10;
+
+/// Maximum number of progress log lines kept for each action.
+pub const MAX_ACTION_LOG_LINES: u32 = 1000;
+
+/// Maximum length, in bytes, of action progress log lines.
+pub const MAX_ACTION_LOG_LINE_LEN: usize = 4096;
 
 /// Single Action query interface.
 pub struct Action<'a> {
+    follow_ups: &'a Cell<bool>,
     inner: self::interface::ActionImpl<'a>,
+    tracer: Option<Arc<Tracer>>,
+    writes: &'a Cell<bool>,
 }
 
 impl<'a> Action<'a> {
+    /// Iterate over actions scheduled by the given action.
+    ///
+    /// Follow `ActionRecord::parent_action_id` and `children` to walk action workflows.
+    pub fn children<S>(&self, id: &str, span: S) -> Result<Iter<ActionListItem>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.children(id, span.into())
+    }
+
     /// Fetch an action record by ID.
     pub fn get<S>(&self, id: &str, span: S) -> Result<Option<ActionRecord>>
     where
@@ -34,28 +114,170 @@ impl<'a> Action<'a> {
         self.inner.get(id, span.into())
     }
 
-    /// Fetch an action record's transition history.
-    pub fn history<S>(&self, id: &str, span: S) -> Result<Iter<ActionHistoryItem>>
+    /// Fetch a page of an action record's transition history, newest transition first.
+    ///
+    /// At most `limit` transitions are returned, starting after the `after` token
+    /// returned with the previous page (or from the newest transition if `None`).
+    pub fn history<S>(
+        &self,
+        id: &str,
+        limit: u32,
+        after: Option<&str>,
+        span: S,
+    ) -> Result<Page<ActionHistoryItem>>
     where
         S: Into<Option<SpanContext>>,
     {
-        self.inner.history(id, span.into())
+        self.inner.history(id, limit, after, span.into())
     }
 
     /// Persist a NEW action to the store.
-    pub fn insert<S>(&self, action: ActionRecord, span: S) -> Result<()>
+    ///
+    /// If the action does not carry a tracing context already, the given span
+    /// context is stored in the action headers so invocations can be linked to it.
+    pub fn insert<S>(&self, mut action: ActionRecord, span: S) -> Result<()>
     where
         S: Into<Option<SpanContext>>,
     {
-        self.inner.insert(action, span.into())
+        let span = span.into();
+        if let (Some(context), Some(tracer)) = (span.as_ref(), self.tracer.as_ref()) {
+            if let Ok(None) = action.trace_get(tracer) {
+                action.trace_set(context, tracer)?;
+            }
+        }
+        crate::faults::store_write()?;
+        self.inner.insert(action, span)?;
+        self.writes.set(true);
+        Ok(())
     }
 
-    /// Fetch the next RUNNING or NEW action.
+    /// Append a free-form line to the progress logs of a running action.
+    ///
+    /// Long running actions use logs to explain what they are doing mid-flight.
+    /// Lines longer than `MAX_ACTION_LOG_LINE_LEN` are truncated and only the newest
+    /// `MAX_ACTION_LOG_LINES` lines are kept.
+    /// Logs are deleted along with the action when it is pruned (they are not archived).
+    pub fn log<S>(&self, action: &dyn ActionRecordView, line: &str, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        let line = truncate_log_line(line);
+        crate::faults::store_write()?;
+        self.inner
+            .log(action.inner(), line, MAX_ACTION_LOG_LINES, span.into())?;
+        self.writes.set(true);
+        Ok(())
+    }
+
+    /// Iterate over the progress logs of an action, oldest line first.
+    pub fn logs<S>(&self, id: &str, span: S) -> Result<Iter<ActionLogLine>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.logs(id, span.into())
+    }
+
+    /// Acquire or renew the lease on an action for this agent process.
+    ///
+    /// Leases prevent other agent processes using the same store from executing
+    /// the action until `duration` has passed without the lease being renewed.
+    /// Returns `false` if another process holds the lease.
+    pub fn lease<S>(&self, action: &ActionRecord, duration: Duration, span: S) -> Result<bool>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        let duration = chrono::Duration::from_std(duration)
+            .with_context(|_| ErrorKind::PersistentWrite("action.lease"))?;
+        let expires = Utc::now() + duration;
+        crate::faults::store_write()?;
+        let leased = self
+            .inner
+            .lease(action, &lease_owner(), expires, span.into())?;
+        self.writes.set(true);
+        Ok(leased)
+    }
+
+    /// Acquire the lease on an action on behalf of another agent process.
+    #[cfg(test)]
+    pub(crate) fn lease_as(
+        &self,
+        action: &ActionRecord,
+        owner: &str,
+        expires: DateTime<Utc>,
+    ) -> Result<bool> {
+        self.inner.lease(action, owner, expires, None)
+    }
+
+    /// Fetch the next RUNNING or NEW action not leased by other agent processes.
     pub fn next<S>(&self, span: S) -> Result<Option<ActionRecord>>
     where
         S: Into<Option<SpanContext>>,
     {
-        self.inner.next(span.into())
+        self.inner.next(&lease_owner(), span.into())
+    }
+
+    /// Fetch the action that finished (DONE or FAILED) first after `since`, if any.
+    ///
+    /// Pass the cursor of the previously returned action as `after` to fetch the one
+    /// that finished after it, including actions that finished at the same time.
+    pub fn next_finished<S>(
+        &self,
+        since: DateTime<Utc>,
+        after: Option<&str>,
+        span: S,
+    ) -> Result<Option<FinishedAction>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.next_finished(since, after, span.into())
+    }
+
+    /// Schedule a follow-up action on behalf of a running action.
+    ///
+    /// The new action is linked to `parent` through `ActionRecord::parent_action_id`
+    /// and inherits its requester.
+    /// Arguments are validated by the action implementation as for API requests.
+    ///
+    /// To protect against actions scheduling each other in a loop, an action can't
+    /// schedule follow-ups once it has `MAX_FOLLOW_UP_DEPTH` ancestors.
+    pub fn schedule_follow_up<S>(
+        &self,
+        parent: &dyn ActionRecordView,
+        kind: &str,
+        args: Json,
+        span: S,
+    ) -> Result<Uuid>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        let span = span.into();
+        let parent = parent.inner();
+        let action =
+            ACTIONS::get(kind).ok_or_else(|| ErrorKind::ActionNotAvailable(kind.to_string()))?;
+        action
+            .validate_args(&args)
+            .with_context(|_| ErrorKind::ActionFollowUpArgs(kind.to_string()))?;
+
+        // Walk the ancestors of the parent action to enforce the depth limit.
+        let mut depth = 0;
+        let mut ancestor = parent.parent_action_id;
+        while let Some(id) = ancestor {
+            depth += 1;
+            if depth >= MAX_FOLLOW_UP_DEPTH {
+                let error = ErrorKind::ActionFollowUpDepth(parent.id.to_string(), depth);
+                return Err(error.into());
+            }
+            ancestor = self
+                .get(&id.to_string(), span.clone())?
+                .and_then(|action| action.parent_action_id);
+        }
+
+        let mut record = ActionRecord::new(kind, None, None, args, parent.requester.clone());
+        record.parent_action_id = Some(parent.id);
+        let id = record.id;
+        self.insert(record, span)?;
+        self.follow_ups.set(true);
+        Ok(id)
     }
 
     /// Transition the action to a new state.
@@ -85,21 +307,101 @@ impl<'a> Action<'a> {
         P: Into<Option<Json>>,
         S: Into<Option<SpanContext>>,
     {
-        let (transition_to, payload) = record.map_transition(transition_to, payload.into())?;
+        self.transition_as(
+            record,
+            transition_to,
+            payload.into(),
+            &lease_owner(),
+            span.into(),
+        )
+    }
+
+    /// Transition the action to a new state on behalf of an external orchestrator.
+    ///
+    /// The transition fails with `ErrorKind::ActionLeaseLost` if the action is leased
+    /// by an agent process, so actions run by the actions engine can't be moved from under it.
+    pub fn transition_external<P, S>(
+        &self,
+        record: &dyn ActionRecordView,
+        transition_to: ActionState,
+        payload: P,
+        span: S,
+    ) -> Result<()>
+    where
+        P: Into<Option<Json>>,
+        S: Into<Option<SpanContext>>,
+    {
+        self.transition_as(
+            record,
+            transition_to,
+            payload.into(),
+            EXTERNAL_LEASE_OWNER,
+            span.into(),
+        )
+    }
+
+    fn transition_as(
+        &self,
+        record: &dyn ActionRecordView,
+        transition_to: ActionState,
+        payload: Option<Json>,
+        owner: &str,
+        span: Option<SpanContext>,
+    ) -> Result<()> {
+        let (transition_to, payload) = record.map_transition(transition_to, payload)?;
         let record = record.inner();
         let state = <dyn ActionRecordView>::raw_state(record);
-        ensure_transition_allowed(state, &transition_to);
+        ensure_transition_allowed(&record.id.to_string(), state, &transition_to)?;
+        crate::faults::store_write()?;
         self.inner
-            .transition(record, transition_to, payload, span.into())
+            .transition(record, transition_to, payload, owner, span)?;
+        self.writes.set(true);
+        Ok(())
     }
 }
 
 /// Actions query interface.
 pub struct Actions<'a> {
     inner: self::interface::ActionsImpl<'a>,
+    writes: &'a Cell<bool>,
 }
 
 impl<'a> Actions<'a> {
+    /// Add a finished action to the archive.
+    pub fn archive<S>(&self, action: &ArchivedAction, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        crate::faults::store_write()?;
+        self.inner.archive(action, span.into())?;
+        self.writes.set(true);
+        Ok(())
+    }
+
+    /// Fetch an archived action by ID.
+    pub fn archived<S>(&self, id: &str, span: S) -> Result<Option<ArchivedAction>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.archived(id, span.into())
+    }
+
+    /// Fetch a page of archived actions, most recently archived first.
+    ///
+    /// At most `limit` actions are returned, starting after the `after` token
+    /// returned with the previous page (or from the latest archived action if `None`).
+    pub fn archived_list<S>(
+        &self,
+        limit: u32,
+        after: Option<&str>,
+        span: S,
+    ) -> Result<Page<ArchivedActionItem>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.archived_list(limit, after, span.into())
+    }
+
     /// Iterate over the most recent 100 finished actions.
     pub fn finished<S>(&self, span: S) -> Result<Iter<ActionListItem>>
     where
@@ -116,12 +418,251 @@ impl<'a> Actions<'a> {
         self.inner.queue(span.into())
     }
 
+    /// Iterate over the finished actions the next `prune` call with the same arguments deletes.
+    pub fn prunable<S>(&self, keep: u32, limit: u32, span: S) -> Result<Iter<ActionListItem>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.prunable(keep, limit, span.into())
+    }
+
     /// Prune finished historic actions to prevent endless DB growth.
     pub fn prune<S>(&self, keep: u32, limit: u32, span: S) -> Result<()>
     where
         S: Into<Option<SpanContext>>,
     {
-        self.inner.prune(keep, limit, span.into())
+        crate::faults::store_write()?;
+        self.inner.prune(keep, limit, span.into())?;
+        self.writes.set(true);
+        Ok(())
+    }
+}
+
+/// Agent state query interface, for values the agent must remember across restarts.
+pub struct AgentState<'a> {
+    inner: self::interface::AgentStateImpl<'a>,
+    writes: &'a Cell<bool>,
+}
+
+impl<'a> AgentState<'a> {
+    /// Fetch the value stored under a key, if any.
+    pub fn get<S>(&self, key: &str, span: S) -> Result<Option<Json>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.get(key, span.into())
+    }
+
+    /// Store a value under a key, replacing any previous value.
+    pub fn set<S>(&self, key: &str, value: &Json, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        crate::faults::store_write()?;
+        self.inner.set(key, value, span.into())?;
+        self.writes.set(true);
+        Ok(())
+    }
+}
+
+/// API tree overrides query interface.
+pub struct APITreeOverrides<'a> {
+    inner: self::interface::APITreeOverridesImpl<'a>,
+    writes: &'a Cell<bool>,
+}
+
+impl<'a> APITreeOverrides<'a> {
+    /// Remove all overrides, reverting API trees to their configured state.
+    pub fn clear<S>(&self, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        crate::faults::store_write()?;
+        self.inner.clear(span.into())?;
+        self.writes.set(true);
+        Ok(())
+    }
+
+    /// Iterate over all overrides, ordered by tree name.
+    pub fn list<S>(&self, span: S) -> Result<Iter<APITreeOverride>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.list(span.into())
+    }
+
+    /// Persist an override, replacing any previous override of the same tree.
+    pub fn set<S>(&self, tree: &APITreeOverride, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        crate::faults::store_write()?;
+        self.inner.set(tree, span.into())?;
+        self.writes.set(true);
+        Ok(())
+    }
+}
+
+/// API tree enabled or disabled at runtime, overriding the configuration.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct APITreeOverride {
+    /// Enable or disable the API tree.
+    pub enabled: bool,
+
+    /// Name of the API tree, as in the `api.trees` configuration.
+    pub tree: String,
+
+    /// Time the override was last changed.
+    pub updated_ts: DateTime<Utc>,
+}
+
+/// Finished action moved to the archive when it was pruned.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ArchivedAction {
+    /// Time the action was archived.
+    pub archived_ts: DateTime<Utc>,
+
+    /// Time the action entered a finished state.
+    pub finished_ts: Option<DateTime<Utc>>,
+
+    /// Unique ID of the action.
+    pub id: Uuid,
+
+    /// Type ID of the action.
+    pub kind: String,
+
+    /// Gzip compressed JSON encoding of the action record and its transition history.
+    pub payload: Vec<u8>,
+}
+
+impl ArchivedAction {
+    /// Compress an action record and its transition history for the archive.
+    pub fn new(action: ActionRecord, history: Vec<ActionHistoryItem>) -> Result<ArchivedAction> {
+        let archived_ts = Utc::now();
+        let finished_ts = action.finished_ts;
+        let id = action.id;
+        let kind = action.kind.clone();
+        let details = ArchivedActionDetails { action, history };
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        serde_json::to_writer(&mut encoder, &details).with_context(|_| ErrorKind::ActionEncode)?;
+        let payload = encoder.finish().with_context(|_| ErrorKind::ActionEncode)?;
+        Ok(ArchivedAction {
+            archived_ts,
+            finished_ts,
+            id,
+            kind,
+            payload,
+        })
+    }
+
+    /// Decompress the archived action record and its transition history.
+    pub fn decode(&self) -> Result<ArchivedActionDetails> {
+        let decoder = GzDecoder::new(self.payload.as_slice());
+        let details = serde_json::from_reader(decoder).with_context(|_| ErrorKind::ActionDecode)?;
+        Ok(details)
+    }
+}
+
+impl From<&ArchivedAction> for ArchivedActionItem {
+    fn from(action: &ArchivedAction) -> ArchivedActionItem {
+        ArchivedActionItem {
+            archived_ts: action.archived_ts,
+            finished_ts: action.finished_ts,
+            id: action.id,
+            kind: action.kind.clone(),
+        }
+    }
+}
+
+/// Action record and transition history, newest transition first, of an archived action.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedActionDetails {
+    pub action: ActionRecord,
+    pub history: Vec<ActionHistoryItem>,
+}
+
+/// Summary of an archived action returned by archive listings.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ArchivedActionItem {
+    pub archived_ts: DateTime<Utc>,
+    pub finished_ts: Option<DateTime<Utc>>,
+    pub id: Uuid,
+    pub kind: String,
+}
+
+/// Agent events query interface.
+pub struct Events<'a> {
+    inner: self::interface::EventsImpl<'a>,
+    writes: &'a Cell<bool>,
+}
+
+impl<'a> Events<'a> {
+    /// Iterate over the most recent events, newest event first.
+    pub fn history<S>(&self, limit: u32, span: S) -> Result<Iter<Event>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.history(limit, span.into())
+    }
+
+    /// Persist a new event.
+    pub fn persist<S>(&self, event: &Event, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        crate::faults::store_write()?;
+        self.inner.persist(event, span.into())?;
+        self.writes.set(true);
+        Ok(())
+    }
+
+    /// Prune old events to prevent endless DB growth.
+    pub fn prune<S>(&self, keep: u32, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        crate::faults::store_write()?;
+        self.inner.prune(keep, span.into())?;
+        self.writes.set(true);
+        Ok(())
+    }
+}
+
+/// Agent heartbeats query interface.
+pub struct Heartbeats<'a> {
+    inner: self::interface::HeartbeatsImpl<'a>,
+    writes: &'a Cell<bool>,
+}
+
+impl<'a> Heartbeats<'a> {
+    /// Iterate over heartbeats of the most recent agent processes.
+    pub fn history<S>(&self, limit: u32, span: S) -> Result<Iter<Heartbeat>>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        self.inner.history(limit, span.into())
+    }
+
+    /// Persist the latest heartbeat for an agent process.
+    pub fn persist<S>(&self, heartbeat: &Heartbeat, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        crate::faults::store_write()?;
+        self.inner.persist(heartbeat, span.into())?;
+        self.writes.set(true);
+        Ok(())
+    }
+
+    /// Prune heartbeats of old agent processes to prevent endless DB growth.
+    pub fn prune<S>(&self, keep: u32, span: S) -> Result<()>
+    where
+        S: Into<Option<SpanContext>>,
+    {
+        crate::faults::store_write()?;
+        self.inner.prune(keep, span.into())?;
+        self.writes.set(true);
+        Ok(())
     }
 }
 
@@ -129,7 +670,8 @@ impl<'a> Actions<'a> {
 pub struct Iter<T>(Box<dyn Iterator<Item = Result<T>>>);
 
 impl<T> Iter<T> {
-    fn new<I>(iter: I) -> Iter<T>
+    /// Wrap an iterator over store results.
+    pub fn new<I>(iter: I) -> Iter<T>
     where
         I: Iterator<Item = Result<T>> + 'static,
     {
@@ -144,20 +686,98 @@ impl<T> Iterator for Iter<T> {
     }
 }
 
+/// Finished action and the position to look for the next one from.
+pub struct FinishedAction {
+    /// The action that finished.
+    pub action: ActionRecord,
+
+    /// Opaque token to request the next finished action with.
+    pub cursor: String,
+}
+
+/// Page of results from a paginated store query.
+pub struct Page<T> {
+    /// Results in this page.
+    pub items: Iter<T>,
+
+    /// Opaque token to request the next page with, if more results are available.
+    pub next: Option<String>,
+}
+
+/// Schema migrations status of the store.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct StoreSchema {
+    /// Tags of the migrations applied to the store, in the order they were applied.
+    pub applied: Vec<String>,
+
+    /// Tags of the migrations known to the agent but not yet applied.
+    pub pending: Vec<String>,
+
+    /// Tag of the latest applied migration, if any.
+    pub version: Option<String>,
+}
+
 /// Interface to the agent's persistent storage.
 #[derive(Clone)]
 pub struct Store {
+    health: Arc<StoreHealth>,
     logger: Logger,
     inner: StoreImpl,
+    tracer: Option<Arc<Tracer>>,
 }
 
 impl Store {
+    /// Write a consistent snapshot of the store to the given path.
+    ///
+    /// The snapshot includes the action history and can be restored with `Store::restore`,
+    /// for example to preserve the store when a node is reimaged.
+    pub fn backup<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.inner.backup(path.as_ref())
+    }
+
+    /// Access the tracker of the store ability to persist writes.
+    pub fn health(&self) -> &StoreHealth {
+        &self.health
+    }
+
     /// Perform database initialisation and applies migrations.
     ///
     /// This method requires a mutable borrow to ensure it can only
     /// be called during the process initialisation phase.
     pub fn migrate(&mut self) -> Result<()> {
-        self.inner.migrate()
+        self.inner.migrate()?;
+        self.schema()?;
+        Ok(())
+    }
+
+    /// Revert migrations applied after the given migration tag.
+    ///
+    /// Migrations are reverted by the agent version that knows about them so
+    /// this must be done BEFORE rolling back to an older agent version.
+    pub fn migrate_down(&mut self, tag: &str) -> Result<()> {
+        self.inner.migrate_down(tag)?;
+        self.schema()?;
+        Ok(())
+    }
+
+    /// Replace the content of the store with a snapshot written by `Store::backup`.
+    ///
+    /// This method requires a mutable borrow to ensure it can only
+    /// be called while the agent is not running.
+    pub fn restore<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.inner.restore(path.as_ref())?;
+        self.schema()?;
+        Ok(())
+    }
+
+    /// Report the schema migrations applied to the store and those still pending.
+    ///
+    /// The `repliagent_store_migrations_*` metrics are updated with the result.
+    pub fn schema(&self) -> Result<StoreSchema> {
+        let schema = self.inner.schema()?;
+        STORE_MIGRATIONS_APPLIED.set(schema.applied.len() as f64);
+        STORE_MIGRATIONS_PENDING.set(schema.pending.len() as f64);
+        Ok(schema)
     }
 
     #[cfg(any(test, feature = "with_test_support"))]
@@ -165,52 +785,178 @@ impl Store {
         let inner = self::backend::mock::MockStore::new();
         let inner = StoreImpl::new(inner);
         let logger = Logger::root(slog::Discard, slog::o!());
-        Store { inner, logger }
+        Store {
+            health: Arc::new(StoreHealth::default()),
+            inner,
+            logger,
+            tracer: None,
+        }
+    }
+
+    /// Create a migrated SQLite store in a new temporary file, to test SQL queries.
+    #[cfg(test)]
+    pub(crate) fn sqlite() -> Store {
+        let context = crate::AgentContext::mock();
+        let inner = self::backend::sqlite_temp(context.logger.clone(), Arc::clone(&context.tracer));
+        let mut store = Store {
+            health: Arc::new(StoreHealth::default()),
+            inner,
+            logger: context.logger,
+            tracer: None,
+        };
+        store
+            .migrate()
+            .expect("failed to migrate temporary SQLite store");
+        store
     }
 
+    /// Run a block in a store transaction, committing it if the block succeeds.
+    ///
+    /// Failures to persist writes switch the store into degraded mode
+    /// (see `StoreHealth` for details).
     pub fn with_transaction<F, T>(&self, block: F) -> Result<T>
+    where
+        F: FnOnce(&mut Transaction) -> Result<T>,
+    {
+        let result = self.transaction_inner(block);
+        match &result {
+            Err(error) => self.health.transaction_failed(error, &self.logger),
+            Ok((_, true)) => self.health.writes_committed(&self.logger),
+            Ok((_, false)) => (),
+        };
+        result.map(|(rv, _)| rv)
+    }
+
+    /// Run a block in a transaction and report if the transaction included writes.
+    fn transaction_inner<F, T>(&self, block: F) -> Result<(T, bool)>
     where
         F: FnOnce(&mut Transaction) -> Result<T>,
     {
         let mut connection = self.inner.connection()?;
         let tx = connection.transaction()?;
-        let mut tx = Transaction { inner: tx };
+        let mut tx = Transaction {
+            follow_ups: Cell::new(false),
+            inner: tx,
+            tracer: self.tracer.clone(),
+            writes: Cell::new(false),
+        };
         match block(&mut tx) {
             Err(error) => {
                 if let Err(error) = tx.rollback() {
                     capture_fail!(
-                        &error,
+                        &error.to_fail(),
                         self.logger,
                         "Failed to rollback failed transaction";
-                        failure_info(&error),
+                        failure_info(&error.to_fail()),
                     );
                 }
                 Err(error)
             }
             Ok(rv) => {
+                let writes = tx.writes.get();
                 tx.commit()?;
-                Ok(rv)
+                Ok((rv, writes))
             }
         }
     }
+
+    /// Run a transaction on the blocking threads pool and await its result.
+    ///
+    /// Use this from async contexts (like API handlers) to avoid blocking
+    /// the async runtime while the store performs I/O.
+    pub async fn with_transaction_async<F, T>(&self, block: F) -> Result<T>
+    where
+        F: FnOnce(&mut Transaction) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let store = self.clone();
+        Store::blocking(move || store.with_transaction(block)).await
+    }
+
+    /// Report the store schema migrations from async contexts, see `Store::schema`.
+    pub async fn schema_async(&self) -> Result<StoreSchema> {
+        let store = self.clone();
+        Store::blocking(move || store.schema()).await
+    }
+
+    /// Run a store operation on the blocking threads pool and await its result.
+    async fn blocking<F, T>(operation: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        match spawn_blocking(operation).await {
+            Ok(result) => result,
+            Err(error) if error.is_panic() => std::panic::resume_unwind(error.into_panic()),
+            Err(error) => Err(Error::with_source(ErrorKind::PersistentNoConnection, error)),
+        }
+    }
 }
 
 /// Interface to transactional operations on the store.
 pub struct Transaction<'a> {
+    follow_ups: Cell<bool>,
     inner: TransactionImpl<'a>,
+    tracer: Option<Arc<Tracer>>,
+    writes: Cell<bool>,
 }
 
 impl<'a> Transaction<'a> {
     /// Access single action query interface.
     pub fn action(&mut self) -> Action {
+        let follow_ups = &self.follow_ups;
         let inner = self.inner.action();
-        Action { inner }
+        let tracer = self.tracer.clone();
+        let writes = &self.writes;
+        Action {
+            follow_ups,
+            inner,
+            tracer,
+            writes,
+        }
     }
 
     /// Access the actions query interface.
     pub fn actions(&mut self) -> Actions {
         let inner = self.inner.actions();
-        Actions { inner }
+        let writes = &self.writes;
+        Actions { inner, writes }
+    }
+
+    /// Access the agent state query interface.
+    pub fn agent_state(&mut self) -> AgentState {
+        let inner = self.inner.agent_state();
+        let writes = &self.writes;
+        AgentState { inner, writes }
+    }
+
+    /// Access the API tree overrides query interface.
+    pub fn api_tree_overrides(&mut self) -> APITreeOverrides {
+        let inner = self.inner.api_tree_overrides();
+        let writes = &self.writes;
+        APITreeOverrides { inner, writes }
+    }
+
+    /// Access the agent events query interface.
+    pub fn events(&mut self) -> Events {
+        let inner = self.inner.events();
+        let writes = &self.writes;
+        Events { inner, writes }
+    }
+
+    /// Access the agent heartbeats query interface.
+    pub fn heartbeats(&mut self) -> Heartbeats {
+        let inner = self.inner.heartbeats();
+        let writes = &self.writes;
+        Heartbeats { inner, writes }
+    }
+
+    /// Check if follow-up actions were scheduled in this transaction.
+    ///
+    /// The actions engine wakes itself up once the transaction commits so
+    /// follow-ups don't wait for the next poll.
+    pub(crate) fn follow_ups_scheduled(&self) -> bool {
+        self.follow_ups.get()
     }
 
     /// Commit and consume the transaction.
@@ -226,14 +972,371 @@ impl<'a> Transaction<'a> {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use chrono::TimeZone;
     use serde_json::json;
 
+    use super::truncate_log_line;
+    use super::APITreeOverride;
+    use super::ArchivedAction;
+    use super::ArchivedActionItem;
     use super::Store;
+    use super::MAX_ACTION_LOG_LINES;
+    use super::MAX_ACTION_LOG_LINE_LEN;
+    use crate::actions::advanced::NoOp;
+    use crate::actions::ActionListItem;
+    use crate::actions::ActionLogLine;
     use crate::actions::ActionRecord;
     use crate::actions::ActionRequester;
     use crate::actions::ActionState;
+    use crate::actions::ActionsRegister;
+    use crate::actions::ACTIONS;
+    use crate::events::Event;
+    use crate::heartbeat::Heartbeat;
+    use crate::heartbeat::PROCESS_ID;
+
+    #[test]
+    fn archive_round_trip() {
+        let mut record = ActionRecord::new(
+            "test",
+            None,
+            None,
+            json!({"a": 1}),
+            ActionRequester::AgentApi,
+        );
+        record.set_state(ActionState::Done);
+        record.finished_ts = Some(chrono::Utc::now());
+        let archived = ArchivedAction::new(record.clone(), Vec::new()).unwrap();
+        let id = record.id.to_string();
+        let store = Store::mock();
+        let (found, items) = store
+            .with_transaction(|tx| {
+                tx.actions().archive(&archived, None)?;
+                let found = tx.actions().archived(&id, None)?;
+                let page = tx.actions().archived_list(10, None, None)?;
+                let items: Vec<ArchivedActionItem> = page.items.collect::<crate::Result<_>>()?;
+                Ok((found, items))
+            })
+            .unwrap();
+        assert_eq!(items, vec![ArchivedActionItem::from(&archived)]);
+        let details = found.unwrap().decode().unwrap();
+        assert_eq!(details.action, record);
+        assert!(details.history.is_empty());
+    }
+
+    #[test]
+    fn backup_restore_round_trip() {
+        let kept = ActionRecord::new("test", None, None, json!(1), ActionRequester::AgentApi);
+        let lost = ActionRecord::new("test", None, None, json!(2), ActionRequester::AgentApi);
+        let kept_id = kept.id.to_string();
+        let lost_id = lost.id.to_string();
+        let path =
+            std::env::temp_dir().join(format!("repliagent-backup-{}.db", uuid::Uuid::new_v4()));
+
+        let mut store = Store::sqlite();
+        store
+            .with_transaction(|tx| tx.action().insert(kept, None))
+            .unwrap();
+        store.backup(&path).unwrap();
+        store
+            .with_transaction(|tx| tx.action().insert(lost, None))
+            .unwrap();
+        let restored = store.restore(&path);
+        std::fs::remove_file(&path).unwrap();
+        restored.unwrap();
+
+        let (kept, lost) = store
+            .with_transaction(|tx| {
+                let kept = tx.action().get(&kept_id, None)?;
+                let lost = tx.action().get(&lost_id, None)?;
+                Ok((kept, lost))
+            })
+            .unwrap();
+        assert!(kept.is_some());
+        assert!(lost.is_none());
+    }
+
+    #[test]
+    fn migrate_down_keeps_timestamps() {
+        let mut action =
+            ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        action.created_ts = chrono::Utc.timestamp_millis(1_603_540_800_123);
+        action.scheduled_ts = chrono::Utc.timestamp_millis(1_603_540_801_456);
+        let id = action.id.to_string();
+        let mut store = Store::sqlite();
+        store
+            .with_transaction(|tx| tx.action().insert(action, None))
+            .unwrap();
+
+        store.migrate_down("20201017103000_heartbeats").unwrap();
+        let schema = store.schema().unwrap();
+        assert_eq!(schema.version.as_deref(), Some("20201017103000_heartbeats"));
+        assert!(schema
+            .pending
+            .contains(&"20201024120000_rfc3339_timestamps".to_string()));
+
+        store.migrate().unwrap();
+        let action = store
+            .with_transaction(|tx| tx.action().get(&id, None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(action.created_ts.timestamp_millis(), 1_603_540_800_123);
+        assert_eq!(action.scheduled_ts.timestamp_millis(), 1_603_540_801_456);
+    }
 
     #[test]
+    fn migrate_down_to_unapplied_migration() {
+        let mut store = Store::sqlite();
+        let error = store.migrate_down("20000101000000_unknown").unwrap_err();
+        assert_eq!(error.kind().code(), "PersistentMigrateDown");
+        assert!(store.schema().unwrap().pending.is_empty());
+    }
+
+    #[test]
+    fn action_logs_are_bounded() {
+        let action = ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        let id = action.id.to_string();
+        let store = Store::mock();
+        let logs: Vec<ActionLogLine> = store
+            .with_transaction(|tx| {
+                tx.action().insert(action.clone(), None)?;
+                for index in 0..=MAX_ACTION_LOG_LINES {
+                    tx.action().log(&action, &format!("line {}", index), None)?;
+                }
+                tx.action().logs(&id, None)?.collect()
+            })
+            .unwrap();
+        assert_eq!(logs.len(), MAX_ACTION_LOG_LINES as usize);
+        assert_eq!(logs[0].line, "line 1");
+        assert_eq!(
+            logs[logs.len() - 1].line,
+            format!("line {}", MAX_ACTION_LOG_LINES)
+        );
+    }
+
+    #[test]
+    fn action_log_lines_truncated() {
+        assert_eq!(truncate_log_line("short"), "short");
+        let long = format!("{}é", "a".repeat(MAX_ACTION_LOG_LINE_LEN - 1));
+        let truncated = truncate_log_line(&long);
+        assert_eq!(truncated.len(), MAX_ACTION_LOG_LINE_LEN - 1);
+    }
+
+    #[test]
+    fn children_of_parent() {
+        let parent = ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        let mut child =
+            ActionRecord::new("child", None, None, json!(null), ActionRequester::AgentApi);
+        child.parent_action_id = Some(parent.id);
+        let parent_id = parent.id.to_string();
+        let store = Store::mock();
+        let children: Vec<ActionListItem> = store
+            .with_transaction(|tx| {
+                tx.action().insert(parent, None)?;
+                tx.action().insert(child.clone(), None)?;
+                tx.action().children(&parent_id, None)?.collect()
+            })
+            .unwrap();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].id, child.id);
+    }
+
+    #[test]
+    fn follow_up_linked_to_parent() {
+        let parent = ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        let mut register = ActionsRegister::default();
+        register.register_reserved(NoOp::new(None));
+        let store = Store::mock();
+        let mut child = None;
+        ACTIONS::test_with(register, || {
+            child = store
+                .with_transaction(|tx| {
+                    tx.action().insert(parent.clone(), None)?;
+                    let kind = "agent.replicante.io/noop";
+                    let id = tx
+                        .action()
+                        .schedule_follow_up(&parent, kind, json!(null), None)?;
+                    tx.action().get(&id.to_string(), None)
+                })
+                .unwrap();
+        });
+        assert_eq!(child.unwrap().parent_action_id, Some(parent.id));
+    }
+
+    #[test]
+    fn follow_up_loop_protection() {
+        let mut register = ActionsRegister::default();
+        register.register_reserved(NoOp::new(None));
+        let store = Store::mock();
+        let mut result = None;
+        ACTIONS::test_with(register, || {
+            let outcome = store.with_transaction(|tx| -> crate::Result<()> {
+                let mut parent =
+                    ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+                tx.action().insert(parent.clone(), None)?;
+                loop {
+                    let kind = "agent.replicante.io/noop";
+                    let id = tx
+                        .action()
+                        .schedule_follow_up(&parent, kind, json!(null), None)?;
+                    parent = tx.action().get(&id.to_string(), None)?.unwrap();
+                }
+            });
+            result = Some(outcome);
+        });
+        let error = result.unwrap().unwrap_err();
+        assert_eq!(error.kind().code(), "ActionFollowUpDepth");
+    }
+
+    #[test]
+    fn leased_by_other_process() {
+        let record = ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        let store = Store::mock();
+        let (next, leased, transition) = store
+            .with_transaction(|tx| {
+                tx.action().insert(record.clone(), None)?;
+                let expires = chrono::Utc::now() + chrono::Duration::minutes(1);
+                tx.action().inner.lease(&record, "other", expires, None)?;
+                let next = tx.action().next(None)?;
+                let leased = tx.action().lease(&record, Duration::from_secs(60), None)?;
+                let transition = tx
+                    .action()
+                    .transition(&record, ActionState::Done, None, None);
+                Ok((next, leased, transition))
+            })
+            .unwrap();
+        assert!(next.is_none());
+        assert!(!leased);
+        let error = transition.unwrap_err();
+        assert_eq!(error.kind().code(), "ActionLeaseLost");
+    }
+
+    #[test]
+    fn heartbeats_newest_first() {
+        let mut old = Heartbeat::current();
+        old.process_id = uuid::Uuid::new_v4();
+        let current = Heartbeat::current();
+        let store = Store::mock();
+        let heartbeats: Vec<Heartbeat> = store
+            .with_transaction(|tx| {
+                tx.heartbeats().persist(&old, None)?;
+                tx.heartbeats().persist(&current, None)?;
+                tx.heartbeats().persist(&current, None)?;
+                tx.heartbeats().history(10, None)?.collect()
+            })
+            .unwrap();
+        assert_eq!(heartbeats, vec![current, old]);
+    }
+
+    #[test]
+    fn events_newest_first() {
+        let old = Event::new("TEST", json!({"n": 1}));
+        let new = Event::new("TEST", json!({"n": 2}));
+        let store = Store::mock();
+        let events: Vec<Event> = store
+            .with_transaction(|tx| {
+                tx.events().persist(&old, None)?;
+                tx.events().persist(&new, None)?;
+                tx.events().prune(1, None)?;
+                tx.events().history(10, None)?.collect()
+            })
+            .unwrap();
+        assert_eq!(events, vec![new]);
+    }
+
+    #[test]
+    fn events_sqlite_newest_first() {
+        let mut old = Event::new("TEST", json!({"n": 1}));
+        old.event_ts = chrono::Utc.timestamp_millis(1_600_000_000_000);
+        let mut middle = Event::new("TEST", json!({"n": 2}));
+        middle.event_ts = chrono::Utc.timestamp_millis(1_600_000_001_000);
+        // Events observed at the same time are ordered by insertion.
+        let mut new = Event::new("TEST", json!({"n": 3}));
+        new.event_ts = middle.event_ts;
+        let store = Store::sqlite();
+        let events: Vec<Event> = store
+            .with_transaction(|tx| {
+                tx.events().persist(&new, None)?;
+                tx.events().persist(&old, None)?;
+                tx.events().persist(&middle, None)?;
+                tx.events().history(2, None)?.collect()
+            })
+            .unwrap();
+        assert_eq!(events, vec![middle.clone(), new.clone()]);
+
+        let events: Vec<Event> = store
+            .with_transaction(|tx| {
+                tx.events().prune(2, None)?;
+                tx.events().history(10, None)?.collect()
+            })
+            .unwrap();
+        assert_eq!(events, vec![middle, new]);
+    }
+
+    #[test]
+    fn agent_state_sqlite_replace() {
+        let store = Store::sqlite();
+        let value = store
+            .with_transaction(|tx| tx.agent_state().get("datastore.version", None))
+            .unwrap();
+        assert_eq!(value, None);
+        let value = store
+            .with_transaction(|tx| {
+                tx.agent_state()
+                    .set("datastore.version", &json!("1.2.3"), None)?;
+                tx.agent_state()
+                    .set("datastore.version", &json!("2.0.0"), None)?;
+                tx.agent_state().get("datastore.version", None)
+            })
+            .unwrap();
+        assert_eq!(value, Some(json!("2.0.0")));
+    }
+
+    #[test]
+    fn api_tree_overrides_replace() {
+        let mut tree = APITreeOverride {
+            enabled: false,
+            tree: "introspect".into(),
+            updated_ts: chrono::Utc::now(),
+        };
+        let store = Store::mock();
+        let overrides: Vec<APITreeOverride> = store
+            .with_transaction(|tx| {
+                tx.api_tree_overrides().set(&tree, None)?;
+                tree.enabled = true;
+                tx.api_tree_overrides().set(&tree, None)?;
+                tx.api_tree_overrides().list(None)?.collect()
+            })
+            .unwrap();
+        assert_eq!(overrides, vec![tree]);
+        let overrides: Vec<APITreeOverride> = store
+            .with_transaction(|tx| {
+                tx.api_tree_overrides().clear(None)?;
+                tx.api_tree_overrides().list(None)?.collect()
+            })
+            .unwrap();
+        assert!(overrides.is_empty());
+    }
+
+    #[test]
+    fn reads_do_not_recover_degraded_store() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let store = Store::mock();
+        let error = crate::Error::from(crate::ErrorKind::PersistentCommit);
+        store.health().transaction_failed(&error, &logger);
+        store
+            .with_transaction(|tx| tx.heartbeats().history(10, None).map(|_| ()))
+            .unwrap();
+        assert!(store.health().is_degraded());
+        store
+            .with_transaction(|tx| tx.heartbeats().persist(&Heartbeat::current(), None))
+            .unwrap();
+        assert!(!store.health().is_degraded());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
     #[should_panic(expected = "actions are not allowed to transition from Running to New")]
     fn transition_forbidden() {
         let mut record =
@@ -249,6 +1352,26 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn transition_forbidden() {
+        let mut record =
+            ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
+        record.set_state(ActionState::Running);
+        let store = Store::mock();
+        let error = store
+            .with_transaction(|tx| {
+                tx.action().insert(record.clone(), None)?;
+                tx.action()
+                    .transition(&record, ActionState::New, None, None)
+            })
+            .unwrap_err();
+        match error.kind() {
+            crate::ErrorKind::ActionTransitionNotAllowed(_, _, _) => (),
+            kind => panic!("unexpected error kind: {:?}", kind),
+        }
+    }
+
     #[test]
     fn transition_success() {
         let record = ActionRecord::new("test", None, None, json!(null), ActionRequester::AgentApi);
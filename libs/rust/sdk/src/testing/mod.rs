@@ -1,4 +1,5 @@
 use opentracingrust::Span;
+use serde_json::Value as Json;
 
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::AgentVersion;
@@ -12,8 +13,10 @@ use super::Result;
 /// An implementation of Agent to be used for tests.
 pub struct MockAgent {
     pub agent_info: ::std::result::Result<AgentInfo, String>,
+    pub cluster_display_name: ::std::result::Result<Option<String>, String>,
     pub datastore_info: ::std::result::Result<DatastoreInfo, String>,
     pub shards: ::std::result::Result<Shards, String>,
+    pub shards_extra: ::std::result::Result<Option<Json>, String>,
 }
 
 impl MockAgent {
@@ -29,8 +32,10 @@ impl MockAgent {
         let shards = Ok(Shards::new(vec![]));
         MockAgent {
             agent_info,
+            cluster_display_name: Ok(None),
             datastore_info,
             shards,
+            shards_extra: Ok(None),
         }
     }
 }
@@ -42,6 +47,12 @@ impl Agent for MockAgent {
             .map_err(|error| ErrorKind::FreeForm(error).into())
     }
 
+    fn cluster_display_name(&self, _: &mut Span) -> Result<Option<String>> {
+        self.cluster_display_name
+            .clone()
+            .map_err(|error| ErrorKind::FreeForm(error).into())
+    }
+
     fn datastore_info(&self, _: &mut Span) -> Result<DatastoreInfo> {
         self.datastore_info
             .clone()
@@ -53,6 +64,12 @@ impl Agent for MockAgent {
             .clone()
             .map_err(|error| ErrorKind::FreeForm(error).into())
     }
+
+    fn shards_extra(&self, _: &mut Span) -> Result<Option<Json>> {
+        self.shards_extra
+            .clone()
+            .map_err(|error| ErrorKind::FreeForm(error).into())
+    }
 }
 
 impl Default for MockAgent {
@@ -1,13 +1,24 @@
+#[cfg(feature = "actions")]
 use std::sync::Arc;
+use std::time::SystemTime;
 
+#[cfg(feature = "api")]
+use actix_web::web::ServiceConfig;
 use opentracingrust::Span;
+use serde_json::Value as Json;
 
 use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::DatastoreInfo;
 use replicante_models_agent::info::Shards;
 
+#[cfg(feature = "actions")]
 use crate::actions::Action;
+#[cfg(feature = "actions")]
 use crate::actions::ActionHook;
+#[cfg(feature = "actions")]
+use crate::actions::Fencer;
+use crate::shards::PartialShards;
+use crate::shards::ShardRoles;
 use crate::Result;
 
 /// Trait to share common agent code and features.
@@ -23,6 +34,57 @@ pub trait Agent: Send + Sync {
     /// Fetches all shards and details on the managed datastore node.
     fn shards(&self, span: &mut Span) -> Result<Shards>;
 
+    /// Fetches shards on the managed datastore node, tolerating failures of individual shards.
+    ///
+    /// The shards API reports the shards that were collected along with errors for those
+    /// that were not, instead of failing entirely.
+    /// By default all shards are collected with `Agent::shards` or none are.
+    fn shards_partial(&self, span: &mut Span) -> Result<PartialShards> {
+        self.shards(span).map(PartialShards::from)
+    }
+
+    /// Fetches the current time according to the datastore node.
+    ///
+    /// The time is compared with the agent clock to detect clock skew, which
+    /// would otherwise silently corrupt time based calculations such as lag.
+    /// Agents that can't fetch the datastore time return `None`.
+    fn datastore_time(&self, _span: &mut Span) -> Result<Option<SystemTime>> {
+        Ok(None)
+    }
+
+    /// Detects a human friendly name for the cluster the datastore node belongs to.
+    ///
+    /// The name is used when the datastore info does not include one and the user
+    /// did not configure `cluster_display_name_override` so that clusters are not
+    /// only identified by IDs that may not be meaningful to users.
+    fn cluster_display_name(&self, _span: &mut Span) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Fetches datastore specific roles for the shards returned by `Agent::shards`.
+    ///
+    /// Agents can report roles that the standard `ShardRole`s can't describe (such as
+    /// arbiters or observers) without mapping them to a generic unknown role.
+    fn shards_roles(&self, _shards: &Shards, _span: &mut Span) -> Result<ShardRoles> {
+        Ok(ShardRoles::new())
+    }
+
+    /// Fetches optional datastore specific details to attach to the shards payload.
+    ///
+    /// This allows agents to report information that does not fit the shards model,
+    /// such as the view this node has of other cluster members.
+    /// Details are best-effort: errors are logged and the shards are reported without them.
+    fn shards_extra(&self, _span: &mut Span) -> Result<Option<Json>> {
+        Ok(None)
+    }
+
+    /// Mount agent specific API endpoints.
+    ///
+    /// Endpoints are mounted under `/api/unstable/agent` when the unstable API is enabled.
+    /// The endpoints are configured once for each API server worker thread.
+    #[cfg(feature = "api")]
+    fn configure_api(&self, _config: &mut ServiceConfig) {}
+
     /// Factory for store-specific well-known actions.
     ///
     /// These actions are part of the SDK reserved scope so they have well defined expectations
@@ -30,7 +92,17 @@ pub trait Agent: Send + Sync {
     ///
     /// This allows standard actions with store-specific implementation that can be used to
     /// build reusable, standard, cross-store logic.
+    #[cfg(feature = "actions")]
     fn action_hooks(&self) -> Vec<(ActionHook, Arc<dyn Action>)> {
         Vec::new()
     }
+
+    /// Store-specific implementation of node fencing.
+    ///
+    /// The returned fencer is used by the `replicante.io/node.fence` and
+    /// `replicante.io/node.unfence` actions unless users configure one with `fencing`.
+    #[cfg(feature = "actions")]
+    fn fencer(&self) -> Option<Arc<dyn Fencer>> {
+        None
+    }
 }
@@ -0,0 +1,179 @@
+use std::fs::File;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use reqwest::blocking::Client;
+use semver::Version;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::config::UpdateChannel;
+use crate::config::UpdatesConfig;
+use crate::metrics::UPDATE_AVAILABLE;
+use crate::metrics::UPDATE_INFO;
+use crate::ErrorKind;
+use crate::Result;
+use crate::ResultExt;
+
+lazy_static! {
+    static ref UPDATE_STATUS: RwLock<Option<UpdateStatus>> = RwLock::new(None);
+}
+
+/// Result of the most recent update check.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct UpdateStatus {
+    pub channel: UpdateChannel,
+    pub current: String,
+    pub enabled: bool,
+    pub latest: Option<String>,
+    pub pin: Option<String>,
+}
+
+impl UpdateStatus {
+    pub fn new(current: &Version, enabled: bool, config: &UpdatesConfig) -> UpdateStatus {
+        UpdateStatus {
+            channel: config.channel,
+            current: current.to_string(),
+            enabled,
+            latest: None,
+            pin: config.pin.clone(),
+        }
+    }
+}
+
+/// Version metadata returned by the server.
+///
+/// The `version` attribute is the latest stable release while the optional `versions`
+/// list includes all releases, pre-releases included, to select from based on channel.
+#[derive(Debug, Deserialize)]
+pub struct VersionMeta {
+    pub version: String,
+    #[serde(default)]
+    pub versions: Vec<String>,
+}
+
+impl VersionMeta {
+    /// Select the latest released version acceptable for the configured channel and pin.
+    ///
+    /// Versions that can't be parsed are ignored.
+    pub fn latest(&self, config: &UpdatesConfig) -> Option<Version> {
+        std::iter::once(&self.version)
+            .chain(self.versions.iter())
+            .filter_map(|version| Version::parse(version).ok())
+            .filter(|version| config.accepts(version))
+            .max()
+    }
+}
+
+/// Fetch version metadata from an HTTP(S) URL or a local file.
+///
+/// Sources without an `http://` or `https://` scheme are treated as file paths,
+/// with an optional `file://` prefix.
+pub fn fetch(client: &Client, source: &str) -> Result<VersionMeta> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let response = client
+            .get(source)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .with_context(|_| ErrorKind::UpdateMetadata(source.to_string()))?;
+        let meta = serde_json::from_reader(response)
+            .with_context(|_| ErrorKind::UpdateMetadata(source.to_string()))?;
+        return Ok(meta);
+    }
+    let path = source.strip_prefix("file://").unwrap_or(source);
+    let file = File::open(path).with_context(|_| ErrorKind::Io(path.to_string()))?;
+    let meta = serde_json::from_reader(file)
+        .with_context(|_| ErrorKind::UpdateMetadata(source.to_string()))?;
+    Ok(meta)
+}
+
+/// Record the result of an update check and update the related metrics.
+pub fn record(status: UpdateStatus) {
+    if let Some(latest) = &status.latest {
+        // Periodic checks may find a newer version: drop the previous labels.
+        let channel = status.channel.to_string();
+        UPDATE_INFO.reset();
+        UPDATE_INFO
+            .with_label_values(&[&channel, &status.current, latest])
+            .set(1.0);
+        let available = Version::parse(latest).ok() > Version::parse(&status.current).ok();
+        UPDATE_AVAILABLE.set(if available { 1.0 } else { 0.0 });
+    }
+    *UPDATE_STATUS.write().expect("UPDATE_STATUS lock poisoned") = Some(status);
+}
+
+/// Result of the most recent update check, if the checker was started.
+pub fn status() -> Option<UpdateStatus> {
+    UPDATE_STATUS
+        .read()
+        .expect("UPDATE_STATUS lock poisoned")
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use reqwest::blocking::Client;
+    use semver::Version;
+
+    use super::VersionMeta;
+    use crate::config::UpdateChannel;
+    use crate::config::UpdatesConfig;
+
+    fn meta() -> VersionMeta {
+        VersionMeta {
+            version: "0.8.1".into(),
+            versions: vec![
+                "0.7.5".into(),
+                "0.8.1".into(),
+                "0.9.0-beta.2".into(),
+                "junk".into(),
+            ],
+        }
+    }
+
+    #[test]
+    fn latest_for_channel() {
+        let config = UpdatesConfig::default();
+        assert_eq!(meta().latest(&config), Some(Version::new(0, 8, 1)));
+        let config = UpdatesConfig {
+            channel: UpdateChannel::Beta,
+            ..UpdatesConfig::default()
+        };
+        let beta = Version::parse("0.9.0-beta.2").unwrap();
+        assert_eq!(meta().latest(&config), Some(beta));
+    }
+
+    #[test]
+    fn latest_for_pin() {
+        let config = UpdatesConfig {
+            pin: Some("~0.7".into()),
+            ..UpdatesConfig::default()
+        };
+        assert_eq!(meta().latest(&config), Some(Version::new(0, 7, 5)));
+        let config = UpdatesConfig {
+            pin: Some("^1".into()),
+            ..UpdatesConfig::default()
+        };
+        assert_eq!(meta().latest(&config), None);
+    }
+
+    #[test]
+    fn fetch_from_file() {
+        let path = std::env::temp_dir().join("repliagent-update-meta.json");
+        fs::write(
+            &path,
+            r#"{"version": "0.8.1", "versions": ["0.9.0-beta.1"]}"#,
+        )
+        .unwrap();
+        let source = format!("file://{}", path.display());
+        let meta = super::fetch(&Client::new(), &source).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(meta.version, "0.8.1");
+        assert_eq!(meta.versions, vec!["0.9.0-beta.1".to_string()]);
+
+        let error = super::fetch(&Client::new(), "/not/a/real/path.json").unwrap_err();
+        assert_eq!(error.kind().code(), "Io");
+    }
+}
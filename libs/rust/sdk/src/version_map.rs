@@ -0,0 +1,268 @@
+use std::sync::Arc;
+
+use semver::Version;
+use semver::VersionReq;
+use slog::info;
+use slog::warn;
+use slog::Logger;
+
+use replicante_models_agent::info::DatastoreInfo;
+use replicante_util_failure::failure_info;
+
+use crate::metrics::VERSIONED_AGENT_MADE;
+use crate::ActiveAgent;
+use crate::Agent;
+use crate::ErrorKind;
+use crate::Result;
+
+/// Version ID of agents made when the datastore version is not known or not supported.
+pub const VERSION_UNKNOWN: &str = "unknown";
+
+/// Parse a datastore reported version, tolerating formats strict semver rejects.
+///
+/// Datastores report versions such as `4.4.18-ent`, `3.4.13-<commit>` or `v2.8`
+/// that either fail to parse or are treated as pre-releases no range matches.
+/// A leading `v`, pre-release or vendor suffixes and build metadata are dropped
+/// and missing minor or patch numbers default to 0.
+///
+/// Only `major.minor.patch` is kept so the raw string is what should be reported
+/// to users (the `DatastoreInfo::version` attribute).
+pub fn parse_version(raw: &str) -> Result<Version> {
+    let version = raw.trim();
+    let version = version
+        .strip_prefix(|c| c == 'v' || c == 'V')
+        .unwrap_or(version);
+    let end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or_else(|| version.len());
+    let mut numbers = [0; 3];
+    let parts = version[..end].trim_end_matches('.').split('.');
+    for (index, part) in parts.take(3).enumerate() {
+        numbers[index] = part
+            .parse::<u64>()
+            .map_err(|_| ErrorKind::VersionParse(raw.to_string()))?;
+    }
+    Ok(Version::new(numbers[0], numbers[1], numbers[2]))
+}
+
+type Maker<Source> = Box<dyn Fn(&Source) -> Arc<dyn Agent> + Send + Sync>;
+
+/// Agent implementation registered for a range of datastore versions.
+struct Entry<Source> {
+    agent_version: &'static str,
+    make: Maker<Source>,
+    range: VersionReq,
+}
+
+/// Declarative map of datastore version ranges to the agents supporting them.
+///
+/// Intended for `AgentFactory` implementations used with a `VersionedAgent`:
+///
+///   * Agents are registered for a `VersionReq` with `VersionMap::version`.
+///   * Ranges are checked in the order they are registered and the first match is used.
+///   * The default agent is used when the version is unknown or no range matches it.
+///   * Agents made by default have the `VERSION_UNKNOWN` version ID so
+///     `VersionMap::should_remake` and `VersionMap::should_remake_on_error`
+///     replace them as soon as the version can be detected.
+///
+/// Agents are made from a `Source` (usually the factory itself) so they can be
+/// created with the latest clients and configuration.
+/// Made agents are counted by the `repliagent_versioned_agent_made` metric.
+pub struct VersionMap<Source> {
+    default: Maker<Source>,
+    default_version: &'static str,
+    name: &'static str,
+    versions: Vec<Entry<Source>>,
+}
+
+impl<Source> VersionMap<Source> {
+    /// Create a map for the named datastore with the default agent to fall back to.
+    pub fn new<F>(name: &'static str, agent_version: &'static str, default: F) -> Self
+    where
+        F: Fn(&Source) -> Arc<dyn Agent> + Send + Sync + 'static,
+    {
+        VersionMap {
+            default: Box::new(default),
+            default_version: agent_version,
+            name,
+            versions: Vec::new(),
+        }
+    }
+
+    /// Register the agent to use for datastore versions in the given range.
+    pub fn version<F>(mut self, range: VersionReq, agent_version: &'static str, make: F) -> Self
+    where
+        F: Fn(&Source) -> Arc<dyn Agent> + Send + Sync + 'static,
+    {
+        self.versions.push(Entry {
+            agent_version,
+            make: Box::new(make),
+            range,
+        });
+        self
+    }
+
+    /// Make the agent best suited for the detected datastore version.
+    pub fn make(&self, source: &Source, version: Result<Version>, logger: &Logger) -> ActiveAgent {
+        let version = match version {
+            Ok(version) => version,
+            Err(error) => {
+                let agent = self.make_default(source, "unknown");
+                warn!(
+                    logger,
+                    "Could not detect datastore version, using default agent";
+                    "agent_version" => self.default_version,
+                    "datastore" => self.name,
+                    failure_info(&error.to_fail()),
+                );
+                return agent;
+            }
+        };
+        let entry = self
+            .versions
+            .iter()
+            .find(|entry| entry.range.matches(&version));
+        match entry {
+            Some(entry) => {
+                VERSIONED_AGENT_MADE
+                    .with_label_values(&[self.name, entry.agent_version, "matched"])
+                    .inc();
+                info!(
+                    logger,
+                    "Instantiated datastore agent";
+                    "agent_version" => entry.agent_version,
+                    "datastore" => self.name,
+                    "datastore_version" => %version,
+                );
+                ActiveAgent::new((entry.make)(source), version.to_string())
+            }
+            None => {
+                let agent = self.make_default(source, "unsupported");
+                warn!(
+                    logger,
+                    "Unsupported datastore version, using default agent";
+                    "agent_version" => self.default_version,
+                    "datastore" => self.name,
+                    "datastore_version" => %version,
+                );
+                agent
+            }
+        }
+    }
+
+    /// Check if the active agent was made for a different version than the one running.
+    ///
+    /// Agents made by default, or when the running version can't be parsed, are always remade.
+    pub fn should_remake(&self, active: &ActiveAgent, info: &DatastoreInfo) -> bool {
+        let version = active.version_id();
+        if version == VERSION_UNKNOWN {
+            return true;
+        }
+        match parse_version(&info.version) {
+            Ok(running) => *version != running.to_string(),
+            Err(_) => true,
+        }
+    }
+
+    /// Check if the active agent should be remade after an error.
+    ///
+    /// Only agents made by default are remade on error.
+    pub fn should_remake_on_error(&self, active: &ActiveAgent) -> bool {
+        active.version_id() == VERSION_UNKNOWN
+    }
+
+    fn make_default(&self, source: &Source, detection: &str) -> ActiveAgent {
+        VERSIONED_AGENT_MADE
+            .with_label_values(&[self.name, self.default_version, detection])
+            .inc();
+        ActiveAgent::new((self.default)(source), VERSION_UNKNOWN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use semver::Version;
+    use semver::VersionReq;
+    use slog::o;
+    use slog::Discard;
+    use slog::Logger;
+
+    use replicante_models_agent::info::DatastoreInfo;
+
+    use super::parse_version;
+    use super::VersionMap;
+    use crate::testing::MockAgent;
+    use crate::Agent;
+    use crate::ErrorKind;
+
+    fn map() -> VersionMap<()> {
+        let agent = || Arc::new(MockAgent::new()) as Arc<dyn Agent>;
+        VersionMap::new("test", "1.0.0", move |_| agent())
+            .version(VersionReq::parse(">=2.0.0").unwrap(), "2.0.0", move |_| {
+                agent()
+            })
+            .version(VersionReq::parse(">=1.0.0").unwrap(), "1.0.0", move |_| {
+                agent()
+            })
+    }
+
+    #[test]
+    fn make_matched_version() {
+        let logger = Logger::root(Discard, o!());
+        let versions = map();
+        let active = versions.make(&(), Ok(Version::parse("2.1.0").unwrap()), &logger);
+        assert_eq!(active.version_id(), "2.1.0");
+        let info = DatastoreInfo::new("test", "test", "name", "2.1.0", None);
+        assert!(!versions.should_remake(&active, &info));
+        assert!(!versions.should_remake_on_error(&active));
+        let info = DatastoreInfo::new("test", "test", "name", "2.2.0", None);
+        assert!(versions.should_remake(&active, &info));
+    }
+
+    #[test]
+    fn make_default_when_unknown_or_unsupported() {
+        let logger = Logger::root(Discard, o!());
+        let versions = map();
+        let error = ErrorKind::StoreOpFailed("test").into();
+        let active = versions.make(&(), Err(error), &logger);
+        assert_eq!(active.version_id(), "unknown");
+        assert!(versions.should_remake_on_error(&active));
+        let active = versions.make(&(), Ok(Version::parse("0.9.0").unwrap()), &logger);
+        assert_eq!(active.version_id(), "unknown");
+        let info = DatastoreInfo::new("test", "test", "name", "0.9.0", None);
+        assert!(versions.should_remake(&active, &info));
+    }
+
+    #[test]
+    fn make_matched_vendor_version() {
+        let logger = Logger::root(Discard, o!());
+        let versions = map();
+        let version = parse_version("2.1.0-ent").unwrap();
+        let active = versions.make(&(), Ok(version), &logger);
+        assert_eq!(active.version_id(), "2.1.0");
+        let info = DatastoreInfo::new("test", "test", "name", "2.1.0-ent", None);
+        assert!(!versions.should_remake(&active, &info));
+        let info = DatastoreInfo::new("test", "test", "name", "junk", None);
+        assert!(versions.should_remake(&active, &info));
+    }
+
+    #[test]
+    fn parse_lenient_versions() {
+        let version = |raw| parse_version(raw).unwrap().to_string();
+        assert_eq!(version("4.4.18"), "4.4.18");
+        assert_eq!(version("4.4.18-ent"), "4.4.18");
+        assert_eq!(
+            version("3.4.13-2d71af4dbe22557fda74f9a9b4309b15a7487f03"),
+            "3.4.13"
+        );
+        assert_eq!(version("2.8.1+build.5"), "2.8.1");
+        assert_eq!(version(" v2.8 "), "2.8.0");
+        assert_eq!(version("5"), "5.0.0");
+        assert_eq!(version("3.4.13.1"), "3.4.13");
+        assert!(parse_version("").is_err());
+        assert!(parse_version("junk").is_err());
+        assert!(parse_version("4..1").is_err());
+    }
+}
@@ -1,8 +1,17 @@
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::RwLock;
-
+use std::sync::RwLockReadGuard;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+
+#[cfg(feature = "api")]
+use actix_web::web::ServiceConfig;
+use humthreads::Builder;
 use opentracingrust::Log;
 use opentracingrust::Span;
+use serde_json::Value as Json;
 use slog::debug;
 use slog::info;
 use slog::warn;
@@ -11,13 +20,77 @@ use replicante_models_agent::info::AgentInfo;
 use replicante_models_agent::info::DatastoreInfo;
 use replicante_models_agent::info::Shards;
 use replicante_util_failure::failure_info;
+use replicante_util_upkeep::Upkeep;
 
+#[cfg(feature = "actions")]
 use crate::actions::Action;
+#[cfg(feature = "actions")]
 use crate::actions::ActionHook;
+#[cfg(feature = "actions")]
+use crate::actions::Fencer;
+#[cfg(feature = "store")]
+use crate::events::Event;
+use crate::fail_compat;
+use crate::metrics::DATASTORE_VERSION_UNSUPPORTED;
+use crate::shards::PartialShards;
+use crate::shards::ShardRoles;
 use crate::Agent;
 use crate::AgentContext;
 use crate::Error;
+use crate::ErrorKind;
 use crate::Result;
+use crate::ResultExt;
+
+/// Agent state key the last detected datastore version is persisted under.
+#[cfg(feature = "store")]
+const LAST_VERSION_KEY: &str = "datastore.version";
+
+lazy_static::lazy_static! {
+    /// Warning about the datastore version being outside the supported range.
+    static ref VERSION_WARNING: RwLock<Option<String>> = RwLock::new(None);
+}
+
+/// Warning about the datastore version being outside the supported range, if any.
+///
+/// The warning is updated every time `check_datastore_version` checks the datastore
+/// version against the range in the `datastore_version` configuration.
+pub fn datastore_version_warning() -> Option<String> {
+    VERSION_WARNING
+        .read()
+        .expect("VERSION_WARNING lock poisoned")
+        .clone()
+}
+
+/// Check a detected datastore version against the `datastore_version` supported range.
+///
+/// `VersionedAgent`s check every version they detect: other agents should call this
+/// with the version they report from `Agent::datastore_info` to keep the warning,
+/// metric and `/info` field up to date.
+pub fn check_datastore_version(context: &AgentContext, version: &str) {
+    let warning = context.config.datastore_version.check(version);
+    let mut current = VERSION_WARNING
+        .write()
+        .expect("VERSION_WARNING lock poisoned");
+    if *current == warning {
+        return;
+    }
+    match &warning {
+        None => {
+            info!(context.logger, "Datastore version is supported"; "version" => version);
+            DATASTORE_VERSION_UNSUPPORTED.set(0.0);
+        }
+        Some(warning) => {
+            warn!(
+                context.logger,
+                "Datastore version is outside the supported range";
+                "version" => version,
+                "warning" => warning,
+            );
+            DATASTORE_VERSION_UNSUPPORTED.set(1.0);
+        }
+    }
+    *current = warning;
+}
 
 /// Information about an Agent that is active.
 #[derive(Clone)]
@@ -58,6 +131,13 @@ pub trait AgentFactory: Send + Sync {
 
     /// Checks if the currently active agent should be replaced with a new one in case of error.
     fn should_remake_on_error(&self, active: &ActiveAgent, error: &Error) -> bool;
+
+    /// Mount agent specific API endpoints (see `Agent::configure_api`).
+    ///
+    /// Endpoints are mounted once and are not changed when the active agent is replaced
+    /// so they are provided by the factory instead of the version specific agents.
+    #[cfg(feature = "api")]
+    fn configure_api(&self, _config: &mut ServiceConfig) {}
 }
 
 /// Replicante agent decorator to support runtime-selected agent versions.
@@ -100,19 +180,110 @@ pub trait AgentFactory: Send + Sync {
 ///
 /// Agents applications can implement additional strategies by calling
 /// `VersionedAgent::validate_version`.
+///
+/// Agents that are rarely queried can also re-detect the version in the background
+/// with `VersionedAgent::spawn_redetection` so in place upgrades of the datastore
+/// are picked up promptly.
 pub struct VersionedAgent<Factory>
+where
+    Factory: AgentFactory + 'static,
+{
+    inner: Arc<VersionedInner<Factory>>,
+}
+
+/// State of a `VersionedAgent` shared with the background re-detection thread.
+struct VersionedInner<Factory>
 where
     Factory: AgentFactory + 'static,
 {
     active: RwLock<ActiveAgent>,
     context: AgentContext,
     factory: Factory,
+
+    /// Datastore version detected by the last successful check.
+    ///
+    /// The version is persisted in the store, when enabled, so changes made
+    /// while the agent is not running are detected once it restarts.
+    last_version: Mutex<Option<String>>,
 }
 
-impl<Factory> VersionedAgent<Factory>
+impl<Factory> VersionedInner<Factory>
 where
     Factory: AgentFactory + 'static,
 {
+    /// Record an event when the detected datastore version changes.
+    #[cfg_attr(not(feature = "store"), allow(unused_variables))]
+    fn check_version_changed(&self, info: &DatastoreInfo, span: &mut Span) {
+        let previous = self
+            .last_version
+            .lock()
+            .expect("VersionedAgent last version lock poisoned")
+            .replace(info.version.clone());
+        if previous.as_ref() == Some(&info.version) {
+            return;
+        }
+        #[cfg(feature = "store")]
+        self.persist_version(&info.version, span);
+        let previous = match previous {
+            Some(previous) => previous,
+            None => return,
+        };
+        info!(
+            self.context.logger,
+            "Datastore version changed";
+            "current" => &info.version,
+            "previous" => &previous,
+        );
+        #[cfg(feature = "store")]
+        {
+            let event = Event::datastore_version_changed(&previous, &info.version);
+            crate::events::emit(&self.context, event, span.context().clone());
+        }
+    }
+
+    /// Load the last datastore version persisted in the store, if any.
+    ///
+    /// Failures are logged and treated as if no version was persisted.
+    #[cfg(feature = "store")]
+    fn persisted_version(context: &AgentContext) -> Option<String> {
+        let version = context
+            .store
+            .with_transaction(|tx| tx.agent_state().get(LAST_VERSION_KEY, None));
+        match version {
+            Ok(Some(Json::String(version))) => Some(version),
+            Ok(_) => None,
+            Err(error) => {
+                warn!(
+                    context.logger,
+                    "Failed to load the last detected datastore version";
+                    failure_info(&error.to_fail()),
+                );
+                None
+            }
+        }
+    }
+
+    /// Persist the detected datastore version so changes are detected across restarts.
+    ///
+    /// Failures are logged and otherwise ignored, like failures to record events.
+    #[cfg(feature = "store")]
+    fn persist_version(&self, version: &str, span: &mut Span) {
+        let value = Json::String(version.to_string());
+        let span = span.context().clone();
+        let result = self
+            .context
+            .store
+            .with_transaction(|tx| tx.agent_state().set(LAST_VERSION_KEY, &value, span));
+        if let Err(error) = result {
+            warn!(
+                self.context.logger,
+                "Failed to persist the detected datastore version";
+                failure_info(&error.to_fail()),
+                "version" => version,
+            );
+        }
+    }
+
     /// Replace the active agent with a newly made one.
     fn remake_agent(&self, span: &mut Span) {
         span.log(Log::new().log("message", "VersionedAgent remakes the agent"));
@@ -129,13 +300,57 @@ where
 {
     pub fn new(context: AgentContext, factory: Factory) -> VersionedAgent<Factory> {
         let active = RwLock::new(factory.make());
-        VersionedAgent {
+        #[cfg(feature = "store")]
+        let last_version = VersionedInner::<Factory>::persisted_version(&context);
+        #[cfg(not(feature = "store"))]
+        let last_version = None;
+        let inner = VersionedInner {
             active,
             context,
             factory,
+            last_version: Mutex::new(last_version),
+        };
+        VersionedAgent {
+            inner: Arc::new(inner),
         }
     }
 
+    /// Start a background thread to periodically check if the active agent should be replaced.
+    ///
+    /// The thread is started only if `datastore_version.redetect_interval` is set.
+    pub fn spawn_redetection(&self, upkeep: &mut Upkeep) -> Result<()> {
+        let interval = match self
+            .inner
+            .context
+            .config
+            .datastore_version
+            .redetect_interval
+        {
+            None => return Ok(()),
+            Some(interval) => Duration::from_secs(interval),
+        };
+        let inner = Arc::clone(&self.inner);
+        let thread = Builder::new("r:b:redetect")
+            .full_name("replicante:base:version:redetect")
+            .spawn(move |scope| {
+                scope.activity("waiting to re-detect the datastore version");
+                while !scope.should_shutdown() {
+                    thread::sleep(interval);
+                    let _activity = scope.scoped_activity("re-detecting the datastore version");
+                    let mut span = inner
+                        .context
+                        .tracer
+                        .span("versioned.redetect")
+                        .auto_finish();
+                    inner.validate_version(&mut span);
+                }
+            })
+            .map_err(fail_compat)
+            .with_context(|_| ErrorKind::ThreadSpawn("version re-detection"))?;
+        upkeep.register_thread(thread);
+        Ok(())
+    }
+
     /// Check if the active agent should be replaced.
     ///
     /// Grabs version information from the current database and checks if a more appropriate
@@ -146,6 +361,22 @@ where
     /// datastore version the fetched `DatastoreInfo` object is returned
     /// and nothing else is changed.
     pub fn validate_version(&self, span: &mut Span) -> Option<DatastoreInfo> {
+        self.inner.validate_version(span)
+    }
+
+    fn active(&self) -> RwLockReadGuard<ActiveAgent> {
+        self.inner
+            .active
+            .read()
+            .expect("ActiveAgent lock was poisoned")
+    }
+}
+
+impl<Factory> VersionedInner<Factory>
+where
+    Factory: AgentFactory + 'static,
+{
+    fn validate_version(&self, span: &mut Span) -> Option<DatastoreInfo> {
         // Scope version check because it requires a read lock.
         let (should_remake, info) = {
             let active = self
@@ -156,10 +387,14 @@ where
             let info = active.agent.datastore_info(span);
             match info {
                 Err(error) => {
-                    warn!(self.context.logger, "Failed to detect version"; failure_info(&error));
+                    warn!(self.context.logger, "Failed to detect version"; failure_info(&error.to_fail()));
                     (self.factory.should_remake_on_error(&active, &error), None)
                 }
-                Ok(info) => (self.factory.should_remake(&active, &info), Some(info)),
+                Ok(info) => {
+                    check_datastore_version(&self.context, &info.version);
+                    self.check_version_changed(&info, span);
+                    (self.factory.should_remake(&active, &info), Some(info))
+                }
             }
         };
         // Remake the agent if needed.
@@ -178,7 +413,7 @@ where
     Factory: AgentFactory + 'static,
 {
     fn agent_info(&self, span: &mut Span) -> Result<AgentInfo> {
-        let active = self.active.read().expect("ActiveAgent lock was poisoned");
+        let active = self.active();
         active.agent.agent_info(span)
     }
 
@@ -188,36 +423,80 @@ where
             return Ok(info);
         }
         // Otherwise we attempt to get it directly.
-        let active = self
-            .active
-            .read()
-            .expect("ActiveAgent lock was poisoned")
-            .clone();
+        let active = self.active().clone();
         active.agent.datastore_info(span)
     }
 
+    fn datastore_time(&self, span: &mut Span) -> Result<Option<SystemTime>> {
+        let active = self.active();
+        active.agent.datastore_time(span)
+    }
+
     fn shards(&self, span: &mut Span) -> Result<Shards> {
-        let active = self.active.read().expect("ActiveAgent lock was poisoned");
+        let active = self.active();
         active.agent.shards(span)
     }
 
+    fn shards_partial(&self, span: &mut Span) -> Result<PartialShards> {
+        let active = self.active();
+        active.agent.shards_partial(span)
+    }
+
+    fn cluster_display_name(&self, span: &mut Span) -> Result<Option<String>> {
+        let active = self.active();
+        active.agent.cluster_display_name(span)
+    }
+
+    fn shards_roles(&self, shards: &Shards, span: &mut Span) -> Result<ShardRoles> {
+        let active = self.active();
+        active.agent.shards_roles(shards, span)
+    }
+
+    fn shards_extra(&self, span: &mut Span) -> Result<Option<Json>> {
+        let active = self.active();
+        active.agent.shards_extra(span)
+    }
+
+    #[cfg(feature = "api")]
+    fn configure_api(&self, config: &mut ServiceConfig) {
+        self.inner.factory.configure_api(config)
+    }
+
+    #[cfg(feature = "actions")]
     fn action_hooks(&self) -> Vec<(ActionHook, Arc<dyn Action>)> {
-        let active = self.active.read().expect("ActiveAgent lock was poisoned");
+        let active = self.active();
         active.agent.action_hooks()
     }
+
+    #[cfg(feature = "actions")]
+    fn fencer(&self) -> Option<Arc<dyn Fencer>> {
+        let active = self.active();
+        active.agent.fencer()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
     use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+    use std::time::Instant;
 
     use opentracingrust::Span;
+    #[cfg(feature = "store")]
+    use serde_json::json;
 
     use replicante_models_agent::info::AgentInfo;
     use replicante_models_agent::info::DatastoreInfo;
     use replicante_models_agent::info::Shards;
+    use replicante_util_upkeep::Upkeep;
 
+    use super::super::config::Agent as AgentConfig;
+    #[cfg(feature = "store")]
+    use super::super::events::Event;
+    #[cfg(feature = "store")]
+    use super::super::events::EVENT_DATASTORE_VERSION_CHANGED;
     use super::super::testing::MockAgent;
     use super::super::AgentContext;
     use super::super::Error;
@@ -362,4 +641,63 @@ mod tests {
         agent.validate_version(&mut context.tracer.span("TEST"));
         assert_eq!(2, *factory.made.lock().unwrap());
     }
+
+    #[test]
+    fn redetection_remakes_in_background() {
+        let factory = Arc::new(MockFactory {
+            agent: Arc::new(MockAgent::new()),
+            made: Mutex::new(0),
+            remake: true,
+            remake_on_error: false,
+        });
+        let mut config = AgentConfig::mock();
+        config.datastore_version.redetect_interval = Some(1);
+        let context = AgentContext::mock_with_config(config);
+        let agent = VersionedAgent::new(context, WrappedMockFactory(Arc::clone(&factory)));
+        let mut upkeep = Upkeep::new();
+        agent.spawn_redetection(&mut upkeep).unwrap();
+
+        // Nothing is re-detected by requests so new agents are made by the background thread.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while *factory.made.lock().unwrap() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(*factory.made.lock().unwrap() >= 2);
+    }
+
+    #[cfg(feature = "store")]
+    #[test]
+    fn version_change_detected_across_restarts() {
+        let context = AgentContext::mock();
+        let factory = Arc::new(MockFactory {
+            agent: Arc::new(MockAgent::new()),
+            made: Mutex::new(0),
+            remake: false,
+            remake_on_error: false,
+        });
+        let agent = VersionedAgent::new(context.clone(), WrappedMockFactory(factory));
+        agent.validate_version(&mut context.tracer.span("TEST"));
+
+        // A new agent, sharing the store, finds the datastore upgraded.
+        let mut upgraded = MockAgent::new();
+        upgraded.datastore_info = Ok(DatastoreInfo::new("id", "DB", "mock", "2.0.0", None));
+        let factory = Arc::new(MockFactory {
+            agent: Arc::new(upgraded),
+            made: Mutex::new(0),
+            remake: false,
+            remake_on_error: false,
+        });
+        let agent = VersionedAgent::new(context.clone(), WrappedMockFactory(factory));
+        agent.validate_version(&mut context.tracer.span("TEST"));
+        let events: Vec<Event> = context
+            .store
+            .with_transaction(|tx| tx.events().history(10, None)?.collect())
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, EVENT_DATASTORE_VERSION_CHANGED);
+        assert_eq!(
+            events[0].payload,
+            json!({"current": "2.0.0", "previous": "1.2.3"})
+        );
+    }
 }
@@ -0,0 +1,14 @@
+//! Zookeeper client helpers for Replicante agents.
+//!
+//! Agents for Zookeeper itself and for datastores that keep their state in it
+//! share the same needs, provided here:
+//!
+//!   * `ZookeeperClient`: a session to the ensemble re-created when it is lost.
+//!   * `zk4lw`: typed responses to the "four letter word" commands and a client to run them.
+//!   * Operations are traced and tracked with metrics (see `register_metrics`).
+mod metrics;
+mod session;
+pub mod zk4lw;
+
+pub use self::metrics::register_metrics;
+pub use self::session::ZookeeperClient;
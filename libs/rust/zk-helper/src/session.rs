@@ -0,0 +1,204 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use opentracingrust::Log;
+use opentracingrust::Span;
+use slog::debug;
+use slog::error;
+use slog::info;
+use slog::warn;
+use slog::Logger;
+use zookeeper::ZkResult;
+use zookeeper::ZkState;
+use zookeeper::ZooKeeper;
+
+use replicante_agent::fail_span;
+use replicante_agent::stages::StageTimer;
+use replicante_agent::stages::STAGE_CONNECT;
+use replicante_agent::stages::STAGE_QUERY;
+use replicante_agent::ErrorKind;
+use replicante_agent::Result;
+use replicante_agent::ResultExt;
+
+use crate::metrics::OPS_COUNT;
+use crate::metrics::OPS_DURATION;
+use crate::metrics::OP_ERRORS_COUNT;
+use crate::metrics::RECONNECT_COUNT;
+
+/// Client to a Zookeeper ensemble that re-creates its session when lost.
+///
+/// Zookeeper has no way for clients to identify themselves to the ensemble so
+/// the client identity annotates the logs and request spans of the client instead.
+pub struct ZookeeperClient {
+    identity: String,
+    logger: Logger,
+    session: Mutex<ZookeeperSession>,
+    target: String,
+    timeout: Duration,
+}
+
+impl ZookeeperClient {
+    /// Create a session with the ensemble at the given "host:port[,host:port...]" addresses.
+    pub fn connect(
+        target: String,
+        timeout: Duration,
+        identity: String,
+        logger: Logger,
+    ) -> Result<ZookeeperClient> {
+        let logger = logger.new(slog::o!("client.identity" => identity.clone()));
+        let session = ZookeeperSession::connect(&target, timeout, logger.clone())?;
+        Ok(ZookeeperClient {
+            identity,
+            logger,
+            session: Mutex::new(session),
+            target,
+            timeout,
+        })
+    }
+
+    /// Perform a Zookeeper request, tracking it with metrics and tracing.
+    ///
+    /// Zookeeper errors are returned to the caller as some are expected by operations
+    /// (for example `NodeExists` when creating a znode).
+    /// Only failures to establish a session are reported as errors.
+    pub fn request<F, T>(
+        &self,
+        method: &'static str,
+        span: &mut Span,
+        call: F,
+    ) -> Result<ZkResult<T>>
+    where
+        F: FnOnce(&ZooKeeper) -> ZkResult<T>,
+    {
+        span.tag("service", "zookeeper");
+        span.tag("client.identity", self.identity.clone());
+        let stages = StageTimer::new("zookeeper", method);
+        let keeper = self
+            .keeper(&stages, span)
+            .map_err(|error| fail_span(error, &mut *span))?;
+        span.log(Log::new().log("span.kind", "client-send"));
+        OPS_COUNT.with_label_values(&[method]).inc();
+        let timer = OPS_DURATION.with_label_values(&[method]).start_timer();
+        let result = stages.time(STAGE_QUERY, span, |_| call(&keeper));
+        timer.observe_duration();
+        if result.is_err() {
+            OP_ERRORS_COUNT.with_label_values(&[method]).inc();
+        }
+        span.log(Log::new().log("span.kind", "client-receive"));
+        Ok(result)
+    }
+
+    /// Perform a Zookeeper request that is expected to succeed.
+    ///
+    /// Failures are reported as `StoreOpFailed` errors for the given operation.
+    pub fn request_or_fail<F, T>(
+        &self,
+        operation: &'static str,
+        method: &'static str,
+        span: &mut Span,
+        call: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(&ZooKeeper) -> ZkResult<T>,
+    {
+        let result = self
+            .request(method, span, call)?
+            .map_err(|error| fail_span(error, &mut *span))
+            .with_context(|_| ErrorKind::StoreOpFailed(operation))?;
+        Ok(result)
+    }
+}
+
+impl ZookeeperClient {
+    /// Grab a zookeeper session, re-creating it if needed.
+    fn keeper(&self, stages: &StageTimer, span: &mut Span) -> Result<Arc<ZooKeeper>> {
+        let mut session = self
+            .session
+            .lock()
+            .expect("Zookeeper session lock was poisoned");
+        if !session.active() {
+            debug!(self.logger, "Creating new zookeeper session");
+            span.log(Log::new().log("action", "zookeeper.connect"));
+            RECONNECT_COUNT.inc();
+            let new_session = stages.time(STAGE_CONNECT, span, |_| {
+                ZookeeperSession::connect(&self.target, self.timeout, self.logger.clone())
+            })?;
+            *session = new_session;
+            info!(self.logger, "New zookeeper session ready");
+        }
+        Ok(session.client())
+    }
+}
+
+/// Container for a zookeeper session.
+struct ZookeeperSession {
+    active: Arc<AtomicBool>,
+    client: Arc<ZooKeeper>,
+}
+
+impl ZookeeperSession {
+    /// Create a new zookeeper session.
+    pub fn connect(
+        connection: &str,
+        timeout: Duration,
+        logger: Logger,
+    ) -> Result<ZookeeperSession> {
+        let client = ZooKeeper::connect(connection, timeout, |_| {})
+            .with_context(|_| ErrorKind::Connection("zookeeper", connection.to_string()))?;
+        let active = Arc::new(AtomicBool::new(true));
+        let notify_close = Arc::clone(&active);
+        client.add_listener(move |state| {
+            let reset = match state {
+                ZkState::AuthFailed => {
+                    error!(logger, "Zookeeper authentication error");
+                    false
+                }
+                ZkState::Closed => {
+                    warn!(logger, "Zookeeper session closed");
+                    true
+                }
+                ZkState::Connected => {
+                    info!(logger, "Zookeeper connection successfull");
+                    false
+                }
+                ZkState::ConnectedReadOnly => {
+                    warn!(logger, "Zookeeper connection is read-only");
+                    false
+                }
+                ZkState::Connecting => {
+                    debug!(logger, "Zookeeper session connecting");
+                    false
+                }
+                event => {
+                    debug!(logger, "Ignoring deprecated zookeeper event"; "event" => ?event);
+                    false
+                }
+            };
+            if reset {
+                notify_close.store(false, Ordering::Relaxed);
+                debug!(logger, "Zookeeper session marked as not active");
+            }
+        });
+        let client = Arc::new(client);
+        Ok(ZookeeperSession { active, client })
+    }
+
+    /// Checks if the session is active.
+    ///
+    /// A session is active if the connection to ZooKeper is intact.
+    ///
+    /// There may be some time while the connection is broken but the session is marked as
+    /// active while the client tries to re-establish the connection.
+    /// If this cannot be done, the session is marked as not active.
+    pub fn active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Get a reference to the ZooKeeper client for this session.
+    pub fn client(&self) -> Arc<ZooKeeper> {
+        Arc::clone(&self.client)
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use zk_4lw::Error;
+use zk_4lw::FourLetterWord;
+use zk_4lw::Result;
+
+/// The "conf" command
+pub struct Conf;
+
+impl FourLetterWord for Conf {
+    type Response = Response;
+    fn command() -> &'static str {
+        "conf"
+    }
+
+    fn parse_response(response: &str) -> Result<Self::Response> {
+        let mut zk_server_id: Option<String> = None;
+        let mut zk_servers = Vec::new();
+        let mut zk_extras = HashMap::new();
+
+        let lines = response.lines();
+        for line in lines {
+            let mut iter = line.split('=');
+            match (iter.next().map(str::trim), iter.next().map(str::trim)) {
+                (Some(key), Some(value)) => match key {
+                    "serverId" => zk_server_id = Some(value.into()),
+                    key if key.starts_with("server.") => {
+                        let id = key.trim_start_matches("server.");
+                        zk_servers.push(EnsembleServer::parse(id, value)?);
+                    }
+                    _ => {
+                        zk_extras.insert(key.into(), value.into());
+                    }
+                },
+                _ => break,
+            };
+        }
+
+        macro_rules! error_if_none {
+            ($($name:ident)*) => {
+                $(
+                    match $name {
+                        Some(v) => v,
+                        None => return Err(Error::MissingField(stringify!($name))),
+                    }
+                )*
+            }
+        }
+
+        Ok(Response {
+            zk_server_id: error_if_none!(zk_server_id),
+            zk_servers,
+            zk_extras,
+        })
+    }
+}
+
+/// Sub-set of the "conf" response the agent needs.
+pub struct Response {
+    pub zk_server_id: String,
+    pub zk_servers: Vec<EnsembleServer>,
+    pub zk_extras: HashMap<String, String>,
+}
+
+/// Ensemble member as listed by the `server.<ID>` entries (Zookeeper 3.5+).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EnsembleServer {
+    pub client_port: Option<u16>,
+    pub host: String,
+    pub id: String,
+    pub peer_type: String,
+}
+
+impl EnsembleServer {
+    /// Parse a `host:quorum_port:election_port[:type][;[client_host:]client_port]` entry.
+    fn parse(id: &str, value: &str) -> Result<EnsembleServer> {
+        let mut parts = value.splitn(2, ';');
+        let server = parts.next().unwrap_or("");
+        let client_port = match parts.next() {
+            None => None,
+            Some(client) => {
+                let port = client.rsplit(':').next().unwrap_or(client);
+                Some(port.parse::<u16>()?)
+            }
+        };
+        let mut server: Vec<&str> = server.split(':').collect();
+        let peer_type = match server.last() {
+            Some(&"participant") | Some(&"observer") => server.pop().unwrap().to_string(),
+            _ => "participant".to_string(),
+        };
+        if server.len() < 3 {
+            return Err(Error::MissingField("server"));
+        }
+        server.truncate(server.len() - 2);
+        Ok(EnsembleServer {
+            client_port,
+            host: server.join(":"),
+            id: id.to_string(),
+            peer_type,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zk_4lw::FourLetterWord;
+
+    use super::Conf;
+    use super::EnsembleServer;
+
+    #[test]
+    fn parse_valid_response() {
+        let response = Conf::parse_response(
+            r#"clientPort=2181
+dataDir=/data/version-2
+dataLogDir=/datalog/version-2
+tickTime=2000
+maxClientCnxns=60
+minSessionTimeout=4000
+maxSessionTimeout=40000
+serverId=3
+initLimit=5
+syncLimit=2
+electionAlg=3
+electionPort=3888
+quorumPort=2888
+peerType=0"#,
+        )
+        .unwrap();
+        assert_eq!(response.zk_server_id, "3");
+        assert_eq!(
+            response.zk_extras.get("dataDir").unwrap(),
+            "/data/version-2"
+        );
+        assert_eq!(response.zk_extras.get("minSessionTimeout").unwrap(), "4000");
+    }
+
+    #[test]
+    fn parse_ensemble_servers() {
+        let response = Conf::parse_response(
+            r#"clientPort=2181
+serverId=1
+server.1=zk1:2888:3888:participant;0.0.0.0:2181
+server.2=[::1]:2888:3888:observer;2182
+server.3=zk3:2888:3888
+version=100000000"#,
+        )
+        .unwrap();
+        assert_eq!(
+            response.zk_servers,
+            vec![
+                EnsembleServer {
+                    client_port: Some(2181),
+                    host: "zk1".into(),
+                    id: "1".into(),
+                    peer_type: "participant".into(),
+                },
+                EnsembleServer {
+                    client_port: Some(2182),
+                    host: "[::1]".into(),
+                    id: "2".into(),
+                    peer_type: "observer".into(),
+                },
+                EnsembleServer {
+                    client_port: None,
+                    host: "zk3".into(),
+                    id: "3".into(),
+                    peer_type: "participant".into(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use zk_4lw::Error;
+use zk_4lw::FourLetterWord;
+use zk_4lw::Result;
+
+/// The "mntr" command
+pub struct Mntr;
+
+impl FourLetterWord for Mntr {
+    type Response = Response;
+    fn command() -> &'static str {
+        "mntr"
+    }
+
+    fn parse_response(response: &str) -> Result<Self::Response> {
+        let mut zk_server_state: Option<String> = None;
+        let mut zk_extras = HashMap::new();
+
+        let lines = response.lines();
+        for line in lines {
+            let mut iter = line.splitn(2, '\t');
+            match (iter.next().map(str::trim), iter.next().map(str::trim)) {
+                (Some(key), Some(value)) => match key {
+                    "zk_server_state" => zk_server_state = Some(value.into()),
+                    _ => {
+                        zk_extras.insert(key.into(), value.into());
+                    }
+                },
+                _ => break,
+            };
+        }
+
+        macro_rules! error_if_none {
+            ($($name:ident)*) => {
+                $(
+                    match $name {
+                        Some(v) => v,
+                        None => return Err(Error::MissingField(stringify!($name))),
+                    }
+                )*
+            }
+        }
+        Ok(Response {
+            zk_server_state: error_if_none!(zk_server_state),
+            zk_extras,
+        })
+    }
+}
+
+/// Sub-set of the "mntr" response the agent needs.
+pub struct Response {
+    pub zk_server_state: String,
+    pub zk_extras: HashMap<String, String>,
+}
+
+impl Response {
+    /// Leader-only synchronisation statistics, if reported by the server.
+    pub fn sync_stats(&self) -> HashMap<String, String> {
+        const SYNC_STATS: [&str; 4] = [
+            "zk_followers",
+            "zk_pending_syncs",
+            "zk_synced_followers",
+            "zk_synced_observers",
+        ];
+        SYNC_STATS
+            .iter()
+            .filter_map(|key| {
+                self.zk_extras
+                    .get(*key)
+                    .map(|value| (key.trim_start_matches("zk_").to_string(), value.clone()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zk_4lw::FourLetterWord;
+
+    use super::Mntr;
+
+    #[test]
+    fn parse_valid_response() {
+        let response = Mntr::parse_response(
+            "zk_version\t3.4.13-2d71af4dbe22557fda74f9a9b4309b15a7487f03, built on 06/29/2018 04:05 GMT
+zk_avg_latency\t0
+zk_server_state\tleader
+zk_znode_count\t4
+zk_followers\t2
+zk_synced_followers\t1
+zk_pending_syncs\t0",
+        )
+        .unwrap();
+        assert_eq!(response.zk_server_state, "leader");
+        assert_eq!(response.zk_extras.get("zk_znode_count").unwrap(), "4");
+        let stats = response.sync_stats();
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats.get("followers").unwrap(), "2");
+        assert_eq!(stats.get("synced_followers").unwrap(), "1");
+        assert_eq!(stats.get("pending_syncs").unwrap(), "0");
+    }
+
+    #[test]
+    fn parse_missing_state() {
+        let response = Mntr::parse_response("zk_avg_latency\t0");
+        assert!(response.is_err());
+    }
+}
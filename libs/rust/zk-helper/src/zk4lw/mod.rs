@@ -0,0 +1,210 @@
+//! Typed responses to the Zookeeper "four letter word" commands agents use.
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use opentracingrust::Log;
+use opentracingrust::Span;
+use zk_4lw::Client;
+
+use replicante_agent::fail_compat;
+use replicante_agent::fail_span;
+use replicante_agent::ErrorKind;
+use replicante_agent::Result;
+use replicante_agent::ResultExt;
+
+mod conf;
+mod mntr;
+mod srvr;
+
+pub use self::conf::Conf;
+pub use self::conf::EnsembleServer;
+pub use self::mntr::Mntr;
+pub use self::srvr::Srvr;
+pub use zk_4lw::FourLetterWord;
+
+use crate::metrics::OPS_COUNT;
+use crate::metrics::OPS_DURATION;
+use crate::metrics::OP_ERRORS_COUNT;
+
+/// Client to run four letter words against a Zookeeper server.
+pub struct FourLetterClient {
+    client: Client,
+    target: String,
+    timeout: Option<Duration>,
+}
+
+impl FourLetterClient {
+    /// Create a client for the Zookeeper server at the "host:port" address.
+    pub fn new<S: Into<String>>(target: S) -> FourLetterClient {
+        let target = target.into();
+        let client = Client::new(target.clone());
+        FourLetterClient {
+            client,
+            target,
+            timeout: None,
+        }
+    }
+
+    /// Create a client that gives up on connections, reads and writes slower than `timeout`.
+    ///
+    /// Useful for servers other than the local one, which may be unreachable.
+    pub fn with_timeout<S: Into<String>>(target: S, timeout: Duration) -> FourLetterClient {
+        let mut client = FourLetterClient::new(target);
+        client.timeout = Some(timeout);
+        client
+    }
+
+    /// Execute a four letter word, tracking it with metrics and the optional span.
+    ///
+    /// Failures are reported as `StoreOpFailed` errors for the command.
+    pub fn exec<W: FourLetterWord>(&self, span: Option<&mut Span>) -> Result<W::Response> {
+        let command = W::command();
+        let mut span = span;
+        if let Some(span) = span.as_mut() {
+            span.log(Log::new().log("span.kind", "client-send"));
+        }
+        OPS_COUNT.with_label_values(&[command]).inc();
+        let timer = OPS_DURATION.with_label_values(&[command]).start_timer();
+        let response = match self.timeout {
+            None => self
+                .client
+                .exec::<W>()
+                .map_err(fail_compat)
+                .with_context(|_| ErrorKind::StoreOpFailed(command)),
+            Some(timeout) => exec_bounded::<W>(&self.target, timeout),
+        };
+        let response = response.map_err(|error| {
+            OP_ERRORS_COUNT.with_label_values(&[command]).inc();
+            match span.as_mut() {
+                Some(span) => fail_span(error, &mut **span),
+                None => error,
+            }
+        })?;
+        timer.observe_duration();
+        if let Some(span) = span {
+            span.log(Log::new().log("span.kind", "client-receive"));
+        }
+        Ok(response)
+    }
+}
+
+/// Run a four letter word with connections, reads and writes bounded by the timeout.
+fn exec_bounded<W: FourLetterWord>(target: &str, timeout: Duration) -> Result<W::Response> {
+    let command = W::command();
+    let response =
+        exchange(target, command, timeout).with_context(|_| ErrorKind::StoreOpFailed(command))?;
+    let response = W::parse_response(&response)
+        .map_err(fail_compat)
+        .with_context(|_| ErrorKind::StoreOpFailed(command))?;
+    Ok(response)
+}
+
+/// Send the command to the server and read the full response.
+fn exchange(target: &str, command: &str, timeout: Duration) -> io::Result<String> {
+    let address = target.to_socket_addrs()?.next().ok_or_else(|| {
+        let error = format!("no address found for '{}'", target);
+        io::Error::new(io::ErrorKind::NotFound, error)
+    })?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.write_all(command.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use replicante_agent::ErrorKind;
+
+    use super::FourLetterClient;
+    use super::Srvr;
+    use crate::metrics::OP_ERRORS_COUNT;
+
+    const SRVR_RESPONSE: &str = "Zookeeper version: 3.4.13-2d71af4, built on 06/29/2018 04:05 GMT
+Zxid: 0x600000004
+Mode: follower
+Node count: 4";
+
+    #[test]
+    fn exec_parses_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0; 4];
+            stream.read_exact(&mut command).unwrap();
+            stream.write_all(SRVR_RESPONSE.as_bytes()).unwrap();
+            String::from_utf8(command.to_vec()).unwrap()
+        });
+        let response = FourLetterClient::new(target).exec::<Srvr>(None).unwrap();
+        assert_eq!(server.join().unwrap(), "srvr");
+        assert_eq!(response.zk_mode, "follower");
+        assert_eq!(response.zk_zxid, 25769803780);
+    }
+
+    #[test]
+    fn exec_reports_failures() {
+        // Bind and drop a listener to find a port nothing listens on.
+        let target = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        let errors = OP_ERRORS_COUNT.with_label_values(&["srvr"]).get();
+        let error = FourLetterClient::new(target)
+            .exec::<Srvr>(None)
+            .unwrap_err();
+        match error.kind() {
+            ErrorKind::StoreOpFailed(operation) => assert_eq!(*operation, "srvr"),
+            kind => panic!("unexpected error kind: {:?}", kind),
+        }
+        let after = OP_ERRORS_COUNT.with_label_values(&["srvr"]).get();
+        assert!(after > errors);
+    }
+
+    #[test]
+    fn exec_with_timeout_gives_up() {
+        // The listener is never accepted from so the connection never gets a response.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let start = Instant::now();
+        let error = FourLetterClient::with_timeout(target, Duration::from_millis(100))
+            .exec::<Srvr>(None)
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        match error.kind() {
+            ErrorKind::StoreOpFailed(operation) => assert_eq!(*operation, "srvr"),
+            kind => panic!("unexpected error kind: {:?}", kind),
+        }
+        drop(listener);
+    }
+
+    #[test]
+    fn exec_with_timeout_parses_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0; 4];
+            stream.read_exact(&mut command).unwrap();
+            stream.write_all(SRVR_RESPONSE.as_bytes()).unwrap();
+        });
+        let response = FourLetterClient::with_timeout(target, Duration::from_secs(5))
+            .exec::<Srvr>(None)
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(response.zk_mode, "follower");
+    }
+}
@@ -0,0 +1,34 @@
+use std::process::Command;
+
+/// Expose the git commit the agent is built from, if available, and build details
+/// to the agent version.
+fn main() {
+    let hash = git(&["rev-parse", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    let taint = match git(&["status", "--porcelain"]) {
+        None => "unknown",
+        Some(status) if status.is_empty() => "not tainted",
+        Some(_) => "working directory tainted",
+    };
+    if let Some(date) = command("date", &["-u", "+%Y-%m-%dT%H:%M:%SZ"]) {
+        println!("cargo:rustc-env=BUILD_DATE={}", date);
+    }
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    if let Some(version) = command(&rustc, &["--version"]) {
+        println!("cargo:rustc-env=BUILD_RUSTC_VERSION={}", version);
+    }
+    println!("cargo:rustc-env=GIT_BUILD_HASH={}", hash);
+    println!("cargo:rustc-env=GIT_BUILD_TAINT={}", taint);
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    command("git", args)
+}
+
+fn command(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_string())
+}
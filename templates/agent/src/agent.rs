@@ -0,0 +1,96 @@
+use lazy_static::lazy_static;
+use opentracingrust::Log;
+use opentracingrust::Span;
+use opentracingrust::StartOptions;
+
+use replicante_agent::fail_span;
+use replicante_agent::Agent;
+use replicante_agent::AgentContext;
+use replicante_agent::Result;
+use replicante_models_agent::info::AgentInfo;
+use replicante_models_agent::info::AgentVersion;
+use replicante_models_agent::info::DatastoreInfo;
+use replicante_models_agent::info::Shard;
+use replicante_models_agent::info::ShardRole;
+use replicante_models_agent::info::Shards;
+
+use crate::config::Config;
+use crate::metrics::OPS_COUNT;
+use crate::metrics::OPS_DURATION;
+use crate::metrics::OP_ERRORS_COUNT;
+
+lazy_static! {
+    pub static ref AGENT_VERSION: AgentVersion = AgentVersion::new(
+        env!("GIT_BUILD_HASH"),
+        env!("CARGO_PKG_VERSION"),
+        env!("GIT_BUILD_TAINT")
+    );
+}
+
+/// {{datastore}} agent.
+///
+/// The collection methods below return placeholder data:
+/// replace them with requests to {{datastore}} made through `DatastoreAgent::op`.
+pub struct DatastoreAgent {
+    cluster: String,
+    context: AgentContext,
+    target: String,
+}
+
+impl DatastoreAgent {
+    pub fn new(config: Config, context: AgentContext) -> DatastoreAgent {
+        DatastoreAgent {
+            cluster: config.datastore.cluster,
+            context,
+            target: config.datastore.target,
+        }
+    }
+
+    /// Perform an operation against {{datastore}}, tracking it with metrics and tracing.
+    fn op<F, T>(&self, operation: &'static str, root: &Span, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let mut span = self
+            .context
+            .tracer
+            .span_with_options(
+                operation,
+                StartOptions::default().child_of(root.context().clone()),
+            )
+            .auto_finish();
+        span.log(Log::new().log("span.kind", "client-send"));
+        OPS_COUNT.with_label_values(&[operation]).inc();
+        let timer = OPS_DURATION.with_label_values(&[operation]).start_timer();
+        let response = f().map_err(|error| {
+            OP_ERRORS_COUNT.with_label_values(&[operation]).inc();
+            fail_span(error, &mut *span)
+        })?;
+        timer.observe_duration();
+        span.log(Log::new().log("span.kind", "client-receive"));
+        Ok(response)
+    }
+}
+
+impl Agent for DatastoreAgent {
+    fn agent_info(&self, _: &mut Span) -> Result<AgentInfo> {
+        let info = AgentInfo::new(AGENT_VERSION.clone());
+        Ok(info)
+    }
+
+    fn datastore_info(&self, span: &mut Span) -> Result<DatastoreInfo> {
+        // TODO: fetch the node name and version from {{datastore}}.
+        let version = self.op("version", span, || Ok(String::from("0.0.0")))?;
+        let name = self.target.clone();
+        let info = DatastoreInfo::new(self.cluster.clone(), "{{datastore}}", name, version, None);
+        Ok(info)
+    }
+
+    fn shards(&self, span: &mut Span) -> Result<Shards> {
+        // TODO: fetch the shards on the node and their role from {{datastore}}.
+        let role = self.op("role", span, || Ok(ShardRole::Unknown("TODO".into())))?;
+        let shard = Shard::new(self.cluster.clone(), role, None, None);
+        let shards = Shards::new(vec![shard]);
+        Ok(shards)
+    }
+}
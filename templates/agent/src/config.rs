@@ -0,0 +1,81 @@
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value as Json;
+
+use replicante_agent::config::Agent;
+use replicante_agent::Result;
+
+/// {{datastore}} Agent configuration
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Config {
+    /// Common agent options.
+    pub agent: Agent,
+
+    /// {{datastore}} related options.
+    pub datastore: Datastore,
+}
+
+impl Config {
+    /// Apply transformations to the configuration to derive some parameters.
+    ///
+    /// Transformations:
+    ///
+    ///   * Apply verbose debug level logic.
+    pub fn transform(mut self) -> Self {
+        self.agent = self.agent.transform();
+        self
+    }
+
+    /// Validate the {{datastore}} specific configuration options.
+    ///
+    /// Common agent options are validated by the SDK.
+    pub fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Agent specific defaults for the base agent configuration options.
+    pub fn defaults() -> Json {
+        json!({
+            "agent": {
+                "api": {
+                    "bind": "127.0.0.1:{{api_port}}",
+                },
+            },
+        })
+    }
+}
+
+/// {{datastore}} related options.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Datastore {
+    /// Name of the {{datastore}} cluster.
+    pub cluster: String,
+
+    /// Address of the {{datastore}} node to manage.
+    #[serde(default = "Datastore::default_target")]
+    pub target: String,
+}
+
+impl Datastore {
+    fn default_target() -> String {
+        "localhost:1234".into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use replicante_agent::config::ConfigFormat;
+
+    use super::Config;
+
+    #[test]
+    fn from_reader_ok() {
+        let cursor = Cursor::new("{agent: {db: 'test'}, datastore: {cluster: test}}");
+        ConfigFormat::Yaml.from_reader::<Config, _>(cursor).unwrap();
+    }
+}
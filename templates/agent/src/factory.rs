@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use slog::debug;
+
+use replicante_agent::ActiveAgent;
+use replicante_agent::AgentContext;
+use replicante_agent::AgentFactory;
+use replicante_agent::Error;
+use replicante_models_agent::info::DatastoreInfo;
+
+use crate::agent::DatastoreAgent;
+use crate::config::Config;
+
+/// Instantiate the agent best suited for the running {{datastore}} version.
+///
+/// The skeleton has a single agent for all versions: agents supporting several
+/// versions detect the version in `Factory::make` and declare the agent for each
+/// range of versions with a `replicante_agent::VersionMap`, which also decides
+/// when the agent is remade.
+pub struct Factory {
+    config: Config,
+    context: AgentContext,
+}
+
+impl Factory {
+    pub fn new(config: Config, context: AgentContext) -> Factory {
+        Factory { config, context }
+    }
+}
+
+impl AgentFactory for Factory {
+    fn make(&self) -> ActiveAgent {
+        debug!(self.context.logger, "Instantiating a new {{datastore}} agent ...");
+        let agent = DatastoreAgent::new(self.config.clone(), self.context.clone());
+        ActiveAgent::new(Arc::new(agent), "default")
+    }
+
+    fn should_remake(&self, _: &ActiveAgent, _: &DatastoreInfo) -> bool {
+        false
+    }
+
+    fn should_remake_on_error(&self, _: &ActiveAgent, _: &Error) -> bool {
+        false
+    }
+}
@@ -0,0 +1,67 @@
+use lazy_static::lazy_static;
+
+use replicante_agent::BuildInfo;
+use replicante_agent::Result;
+use replicante_agent::VersionedAgent;
+
+mod agent;
+mod config;
+mod factory;
+mod metrics;
+
+use config::Config;
+use factory::Factory;
+
+const DEFAULT_CONFIG_FILE: &str = "agent.yaml";
+const ENV_PREFIX: &str = "REPLIAGENT_";
+
+lazy_static! {
+    static ref BUILD: BuildInfo = replicante_agent::build_info!();
+    static ref RELEASE: String = format!("{{project-name}}@{}", env!("GIT_BUILD_HASH"));
+}
+
+/// Configure and start the agent.
+pub fn run() -> Result<bool> {
+    replicante_agent::build::register(BUILD.clone());
+
+    // Command line parsing.
+    let cli_args = ::replicante_agent::process::clap(
+        "{{datastore}} Replicante Agent",
+        BUILD.display(),
+        env!("CARGO_PKG_DESCRIPTION"),
+        DEFAULT_CONFIG_FILE,
+    )
+    .get_matches();
+    if replicante_agent::process::version_command(&cli_args) {
+        return Ok(true);
+    }
+
+    // Load configuration.
+    let loader =
+        replicante_agent::process::config_loader(&cli_args, ENV_PREFIX, Config::defaults())?;
+    if cli_args.get_flag("print-config") {
+        print!("{}", loader.describe::<Config>()?);
+        return Ok(true);
+    }
+    let config: Config = loader.load()?;
+    let config = config.transform();
+    if replicante_agent::process::commands(&cli_args, &config.agent, || config.validate())? {
+        return Ok(true);
+    }
+
+    // Run the agent using the provided default helper.
+    let agent_conf = config.agent.clone();
+    let release = RELEASE.as_str();
+    replicante_agent::process::run(
+        agent_conf,
+        "{{project-name}}",
+        release,
+        |context, upkeep| {
+            metrics::register_metrics(context);
+            let factory = Factory::new(config.clone(), context.clone());
+            let agent = VersionedAgent::new(context.clone(), factory);
+            agent.spawn_redetection(upkeep)?;
+            Ok(agent)
+        },
+    )
+}
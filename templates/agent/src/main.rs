@@ -0,0 +1,5 @@
+use {{crate_name}}::run;
+
+fn main() {
+    replicante_agent::process::main(run);
+}
@@ -10,24 +10,24 @@ use replicante_agent::AgentContext;
 lazy_static! {
     pub static ref OP_ERRORS_COUNT: CounterVec = CounterVec::new(
         Opts::new(
-            "repliagent_zookeeper_operation_errors",
-            "Number of Zookeeper operations failed"
+            "repliagent_datastore_operation_errors",
+            "Number of {{datastore}} operations failed"
         ),
         &["operation"]
     )
     .expect("Failed to create OP_ERRORS_COUNT counter");
     pub static ref OPS_COUNT: CounterVec = CounterVec::new(
         Opts::new(
-            "repliagent_zookeeper_operations",
-            "Number of Zookeeper operations issued"
+            "repliagent_datastore_operations",
+            "Number of {{datastore}} operations issued"
         ),
         &["operation"]
     )
     .expect("Failed to create OPS_COUNT counter");
     pub static ref OPS_DURATION: HistogramVec = HistogramVec::new(
         HistogramOpts::new(
-            "repliagent_zookeeper_operations_duration",
-            "Duration (in seconds) of Zookeeper operations"
+            "repliagent_datastore_operations_duration",
+            "Duration (in seconds) of {{datastore}} operations"
         ),
         &["operation"]
     )